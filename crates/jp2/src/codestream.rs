@@ -0,0 +1,1270 @@
+//! The JPEG 2000 codestream: main and tile-part headers, tier-2 decoding of packets into code-block data,
+//! and the reconstruction of every tile from its code-blocks. See Annexes A and B of ISO/IEC 15444-1.
+
+use image::{ImageResult, Limits};
+
+use super::dwt::inverse_2d;
+use super::error;
+use super::tier1::{decode_block, ends_segment, Segment, Subband};
+
+// Marker codes, see Table A.2
+const SOC: u16 = 0xFF4F;
+const SIZ: u16 = 0xFF51;
+const COD: u16 = 0xFF52;
+const COC: u16 = 0xFF53;
+const QCD: u16 = 0xFF5C;
+const QCC: u16 = 0xFF5D;
+const RGN: u16 = 0xFF5E;
+const POC: u16 = 0xFF5F;
+const PPM: u16 = 0xFF60;
+const PPT: u16 = 0xFF61;
+const SOT: u16 = 0xFF90;
+const SOD: u16 = 0xFF93;
+const EOC: u16 = 0xFFD9;
+const SOP: [u8; 2] = [0xFF, 0x91];
+const EPH: [u8; 2] = [0xFF, 0x92];
+
+/// Far beyond the largest images in practice, so anything larger is corrupt
+const MAX_DIMENSION: u32 = 1 << 20;
+const MAX_COMPONENTS: u16 = 16384;
+const MAX_LEVELS: u8 = 32;
+/// Samples are kept in 16 bits, which covers all images in practice
+const MAX_PRECISION: u8 = 16;
+/// Magnitudes of the quantized coefficients are kept in 32 bits
+const MAX_BITPLANES: u32 = 31;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Component {
+    /// Signed components are shifted to be unsigned on output, so the sign is not kept
+    pub precision: u8,
+    /// Subsampling relative to the reference grid
+    pub dx: u32,
+    pub dy: u32,
+}
+
+/// The image and tile size marker: geometry of the reference grid, the tiles and the components
+#[derive(Debug, Clone)]
+pub struct Header {
+    pub x0: u32,
+    pub y0: u32,
+    pub x1: u32,
+    pub y1: u32,
+    tile_x0: u32,
+    tile_y0: u32,
+    tile_width: u32,
+    tile_height: u32,
+    pub components: Vec<Component>,
+}
+
+impl Header {
+    fn parse(segment: &[u8]) -> ImageResult<Self> {
+        let mut r = Reader::new(segment);
+        r.u16()?; // capabilities
+        let (x1, y1, x0, y0) = (r.u32()?, r.u32()?, r.u32()?, r.u32()?);
+        let (tile_width, tile_height, tile_x0, tile_y0) = (r.u32()?, r.u32()?, r.u32()?, r.u32()?);
+        let count = r.u16()?;
+        if count == 0 || count > MAX_COMPONENTS {
+            return Err(error(format!("invalid number of components {count}")));
+        }
+        let mut components = Vec::with_capacity(usize::from(count));
+        for _ in 0..count {
+            let (depth, dx, dy) = (r.u8()?, r.u8()?, r.u8()?);
+            let precision = (depth & 0x7F) + 1;
+            if precision > MAX_PRECISION {
+                return Err(error(format!("unsupported bit depth {precision}")));
+            }
+            if dx == 0 || dy == 0 {
+                return Err(error("invalid component subsampling".to_owned()));
+            }
+            components.push(Component {
+                precision,
+                dx: u32::from(dx),
+                dy: u32::from(dy),
+            });
+        }
+        let header = Self {
+            x0,
+            y0,
+            x1,
+            y1,
+            tile_x0,
+            tile_y0,
+            tile_width,
+            tile_height,
+            components,
+        };
+        if x0 >= x1 || y0 >= y1 || x1 - x0 > MAX_DIMENSION || y1 - y0 > MAX_DIMENSION {
+            return Err(error(format!("invalid dimensions {}x{}", x1, y1)));
+        }
+        if tile_width == 0
+            || tile_height == 0
+            || tile_x0 > x0
+            || tile_y0 > y0
+            || u64::from(tile_x0) + u64::from(tile_width) <= u64::from(x0)
+            || u64::from(tile_y0) + u64::from(tile_height) <= u64::from(y0)
+        {
+            return Err(error("invalid tile geometry".to_owned()));
+        }
+        Ok(header)
+    }
+
+    fn tiles_wide(&self) -> u32 {
+        (self.x1 - self.tile_x0).div_ceil(self.tile_width)
+    }
+
+    fn tiles_high(&self) -> u32 {
+        (self.y1 - self.tile_y0).div_ceil(self.tile_height)
+    }
+
+    /// Area of the tile on the reference grid as (x0, y0, x1, y1)
+    fn tile_rect(&self, index: u32) -> (u32, u32, u32, u32) {
+        let (p, q) = (index % self.tiles_wide(), index / self.tiles_wide());
+        let x = u64::from(self.tile_x0) + u64::from(p) * u64::from(self.tile_width);
+        let y = u64::from(self.tile_y0) + u64::from(q) * u64::from(self.tile_height);
+        let clamp = |v: u64, min: u32, max: u32| v.clamp(u64::from(min), u64::from(max)) as u32;
+        (
+            clamp(x, self.x0, self.x1),
+            clamp(y, self.y0, self.y1),
+            clamp(x + u64::from(self.tile_width), self.x0, self.x1),
+            clamp(y + u64::from(self.tile_height), self.y0, self.y1),
+        )
+    }
+
+    /// Origin and size of the component's samples, which are subsampled relative to the reference grid
+    pub fn component_rect(&self, component: usize) -> (u32, u32, u32, u32) {
+        let Component { dx, dy, .. } = self.components[component];
+        let (x0, y0) = (self.x0.div_ceil(dx), self.y0.div_ceil(dy));
+        (x0, y0, self.x1.div_ceil(dx) - x0, self.y1.div_ceil(dy) - y0)
+    }
+}
+
+/// Coding style parameters that apply to all components, from the COD marker
+#[derive(Debug, Clone, Copy)]
+struct GlobalStyle {
+    sop: bool,
+    eph: bool,
+    progression: u8,
+    layers: u16,
+    multiple_component_transform: bool,
+}
+
+/// Coding style of a single component, from the COD or COC marker
+#[derive(Debug, Clone)]
+struct ComponentStyle {
+    levels: u8,
+    /// Exponents of the nominal code-block size
+    block_width: u8,
+    block_height: u8,
+    block_style: u8,
+    reversible: bool,
+    /// Exponents of the precinct size for every resolution level
+    precincts: Vec<(u8, u8)>,
+}
+
+impl ComponentStyle {
+    fn parse(r: &mut Reader, precincts_defined: bool) -> ImageResult<Self> {
+        let levels = r.u8()?;
+        let block_width = r.u8()? + 2;
+        let block_height = r.u8()? + 2;
+        let block_style = r.u8()?;
+        let reversible = match r.u8()? {
+            0 => false,
+            1 => true,
+            other => return Err(error(format!("unsupported wavelet transform {other}"))),
+        };
+        if levels > MAX_LEVELS {
+            return Err(error(format!("too many decomposition levels {levels}")));
+        }
+        if block_width > 10 || block_height > 10 || block_width + block_height > 12 {
+            return Err(error("invalid code-block size".to_owned()));
+        }
+        let precincts = if precincts_defined {
+            (0..=levels)
+                .map(|_| r.u8().map(|size| (size & 0xF, size >> 4)))
+                .collect::<ImageResult<_>>()?
+        } else {
+            vec![(15, 15); usize::from(levels) + 1]
+        };
+        Ok(Self {
+            levels,
+            block_width,
+            block_height,
+            block_style,
+            reversible,
+            precincts,
+        })
+    }
+}
+
+/// Quantization of a component, from the QCD or QCC marker
+#[derive(Debug, Clone)]
+struct Quantization {
+    /// 0 for none, 1 for scalar derived from the LL step, 2 for scalar with all steps given
+    style: u8,
+    guard_bits: u8,
+    /// Exponent and mantissa of the step size of every subband, in the order LL, then HL, LH, HH of every level
+    steps: Vec<(u8, u16)>,
+}
+
+impl Quantization {
+    fn parse(r: &mut Reader) -> ImageResult<Self> {
+        let sqcd = r.u8()?;
+        let style = sqcd & 0x1F;
+        let mut steps = Vec::new();
+        while !r.is_empty() {
+            steps.push(match style {
+                0 => (r.u8()? >> 3, 0),
+                1 | 2 => {
+                    let value = r.u16()?;
+                    ((value >> 11) as u8, value & 0x7FF)
+                }
+                other => return Err(error(format!("unsupported quantization style {other}"))),
+            });
+        }
+        if steps.is_empty() {
+            return Err(error("missing quantization step sizes".to_owned()));
+        }
+        Ok(Self {
+            style,
+            guard_bits: sqcd >> 5,
+            steps,
+        })
+    }
+
+    /// Exponent and mantissa of the step size of the subband at the given index,
+    /// which has gone through `decompositions` levels of the transform
+    fn step(&self, index: usize, levels: u8, decompositions: u8) -> ImageResult<(i32, u16)> {
+        if self.style == 1 {
+            let (exponent, mantissa) = self.steps[0];
+            return Ok((
+                i32::from(exponent) - i32::from(levels) + i32::from(decompositions),
+                mantissa,
+            ));
+        }
+        match self.steps.get(index) {
+            Some(&(exponent, mantissa)) => Ok((i32::from(exponent), mantissa)),
+            None => Err(error("missing quantization step size".to_owned())),
+        }
+    }
+}
+
+/// Coding parameters set by a main or tile-part header. Tile-part values take precedence,
+/// and so do the per-component COC and QCC over the COD and QCD of the same header.
+#[derive(Debug, Clone, Default)]
+struct Styles {
+    global: Option<GlobalStyle>,
+    default_style: Option<ComponentStyle>,
+    component_styles: Vec<Option<ComponentStyle>>,
+    default_quantization: Option<Quantization>,
+    component_quantizations: Vec<Option<Quantization>>,
+}
+
+impl Styles {
+    fn new(components: usize) -> Self {
+        Self {
+            component_styles: vec![None; components],
+            component_quantizations: vec![None; components],
+            ..Default::default()
+        }
+    }
+
+    /// Applies a marker segment that can appear in both main and tile-part headers
+    fn apply(&mut self, marker: u16, segment: &[u8]) -> ImageResult<()> {
+        let components = self.component_styles.len();
+        let mut r = Reader::new(segment);
+        match marker {
+            COD => {
+                let scod = r.u8()?;
+                self.global = Some(GlobalStyle {
+                    sop: scod & 2 != 0,
+                    eph: scod & 4 != 0,
+                    progression: r.u8()?,
+                    layers: r.u16()?,
+                    multiple_component_transform: r.u8()? != 0,
+                });
+                self.default_style = Some(ComponentStyle::parse(&mut r, scod & 1 != 0)?);
+            }
+            COC => {
+                let component = component_index(&mut r, components)?;
+                let scoc = r.u8()?;
+                self.component_styles[component] =
+                    Some(ComponentStyle::parse(&mut r, scoc & 1 != 0)?);
+            }
+            QCD => self.default_quantization = Some(Quantization::parse(&mut r)?),
+            QCC => {
+                let component = component_index(&mut r, components)?;
+                self.component_quantizations[component] = Some(Quantization::parse(&mut r)?);
+            }
+            RGN => {
+                return Err(error(
+                    "region of interest coding is not supported".to_owned(),
+                ))
+            }
+            POC => {
+                return Err(error(
+                    "progression order changes are not supported".to_owned(),
+                ))
+            }
+            PPM | PPT => return Err(error("packed packet headers are not supported".to_owned())),
+            // Markers that only help with random access or carry comments
+            _ => (),
+        }
+        Ok(())
+    }
+}
+
+fn component_index(r: &mut Reader, components: usize) -> ImageResult<usize> {
+    let index = if components < 257 {
+        usize::from(r.u8()?)
+    } else {
+        usize::from(r.u16()?)
+    };
+    if index >= components {
+        return Err(error(format!("invalid component index {index}")));
+    }
+    Ok(index)
+}
+
+#[derive(Debug, Default)]
+struct Tile {
+    styles: Styles,
+    /// Packet data of all tile-parts
+    data: Vec<u8>,
+}
+
+/// Samples of a single component, shifted to be unsigned
+pub struct Plane {
+    pub width: u32,
+    pub height: u32,
+    pub samples: Vec<u16>,
+}
+
+pub struct Codestream {
+    pub header: Header,
+    main: Styles,
+    tiles: Vec<Tile>,
+}
+
+impl Codestream {
+    pub fn parse(data: &[u8]) -> ImageResult<Self> {
+        let mut r = Reader::new(data);
+        if r.u16()? != SOC {
+            return Err(error("missing start of codestream marker".to_owned()));
+        }
+        if r.u16()? != SIZ {
+            return Err(error("missing image and tile size marker".to_owned()));
+        }
+        let header = Header::parse(r.segment()?)?;
+        let components = header.components.len();
+        let mut main = Styles::new(components);
+        loop {
+            match r.u16()? {
+                SOT => break,
+                marker => main.apply(marker, r.segment()?)?,
+            }
+        }
+
+        let tile_count = u64::from(header.tiles_wide()) * u64::from(header.tiles_high());
+        // Tile indices are 16-bit, so there cannot be more tiles than that
+        if tile_count > 65535 {
+            return Err(error(format!("too many tiles: {tile_count}")));
+        }
+        let mut tiles: Vec<Tile> = (0..tile_count)
+            .map(|_| Tile {
+                styles: Styles::new(components),
+                data: Vec::new(),
+            })
+            .collect();
+        loop {
+            // the SOT marker itself has already been read
+            let tile_part_start = r.position - 2;
+            let mut sot = Reader::new(r.segment()?);
+            let index = usize::from(sot.u16()?);
+            let length = sot.u32()? as usize;
+            let Some(tile) = tiles.get_mut(index) else {
+                return Err(error(format!("invalid tile index {index}")));
+            };
+            loop {
+                match r.u16()? {
+                    SOD => break,
+                    marker => tile.styles.apply(marker, r.segment()?)?,
+                }
+            }
+            // A length of zero means that the tile-part extends to the end of the codestream.
+            // Truncated files are decoded as far as the data goes.
+            let end = match length {
+                0 => {
+                    data.len()
+                        - if data.ends_with(&EOC.to_be_bytes()) {
+                            2
+                        } else {
+                            0
+                        }
+                }
+                length => (tile_part_start + length).min(data.len()),
+            };
+            if end < r.position {
+                return Err(error("invalid tile-part length".to_owned()));
+            }
+            tile.data.extend_from_slice(&data[r.position..end]);
+            r.position = end;
+            if r.is_empty() {
+                break;
+            }
+            match r.u16()? {
+                SOT => (),
+                EOC => break,
+                other => return Err(error(format!("unexpected marker {other:04X}"))),
+            }
+        }
+        Ok(Self {
+            header,
+            main,
+            tiles,
+        })
+    }
+
+    /// Decodes all tiles into a plane of samples per component.
+    /// The planes and the buffers used for decoding each tile are reserved against the limits first.
+    pub fn decode(&self, limits: &mut Limits) -> ImageResult<Vec<Plane>> {
+        let mut planes = Vec::with_capacity(self.header.components.len());
+        for c in 0..self.header.components.len() {
+            let (_, _, width, height) = self.header.component_rect(c);
+            let samples = u64::from(width) * u64::from(height);
+            limits.reserve(samples * 2)?;
+            planes.push(Plane {
+                width,
+                height,
+                samples: vec![0; samples as usize],
+            });
+        }
+        for (index, tile) in self.tiles.iter().enumerate() {
+            self.decode_tile(index as u32, tile, &mut planes, limits)?;
+        }
+        Ok(planes)
+    }
+
+    fn decode_tile(
+        &self,
+        index: u32,
+        tile: &Tile,
+        planes: &mut [Plane],
+        limits: &mut Limits,
+    ) -> ImageResult<()> {
+        let rect = self.header.tile_rect(index);
+        // Reconstruction keeps the coefficients of a resolution level and the next one at a time
+        let (x0, y0, x1, y1) = rect;
+        let working_memory = u64::from(x1 - x0) * u64::from(y1 - y0) * 8 * planes.len() as u64;
+        limits.reserve(working_memory)?;
+        let result = self.decode_tile_within_limits(rect, tile, planes);
+        limits.free(working_memory);
+        result
+    }
+
+    fn decode_tile_within_limits(
+        &self,
+        rect: (u32, u32, u32, u32),
+        tile: &Tile,
+        planes: &mut [Plane],
+    ) -> ImageResult<()> {
+        let Some(global) = tile.styles.global.or(self.main.global) else {
+            return Err(error("missing coding style".to_owned()));
+        };
+        let mut components = Vec::with_capacity(planes.len());
+        for (c, &component) in self.header.components.iter().enumerate() {
+            let style = tile.styles.component_styles[c]
+                .as_ref()
+                .or(tile.styles.default_style.as_ref())
+                .or(self.main.component_styles[c].as_ref())
+                .or(self.main.default_style.as_ref());
+            let quantization = tile.styles.component_quantizations[c]
+                .as_ref()
+                .or(tile.styles.default_quantization.as_ref())
+                .or(self.main.component_quantizations[c].as_ref())
+                .or(self.main.default_quantization.as_ref());
+            let (Some(style), Some(quantization)) = (style, quantization) else {
+                return Err(error("missing coding style or quantization".to_owned()));
+            };
+            components.push(TileComponent::new(component, style, quantization, rect)?);
+        }
+
+        let mut position = 0;
+        for packet in packet_order(&global, &components, rect)? {
+            let component = &mut components[packet.component];
+            let style = component.block_style;
+            let resolution = &mut component.resolutions[packet.resolution];
+            // A damaged or truncated tile is decoded as far as its data goes
+            match read_packet(&tile.data, position, &packet, resolution, &global, style) {
+                Ok(next) => position = next,
+                Err(_) => break,
+            }
+        }
+
+        let mut samples: Vec<Vec<f32>> = components.iter().map(|c| c.reconstruct()).collect();
+        if global.multiple_component_transform && samples.len() >= 3 {
+            if components[..3]
+                .iter()
+                .any(|c| c.size() != components[0].size())
+            {
+                return Err(error(
+                    "component transform of differently sized components".to_owned(),
+                ));
+            }
+            inverse_component_transform(&mut samples, components[0].reversible);
+        }
+
+        for (c, (component, samples)) in components.iter().zip(samples).enumerate() {
+            let precision = self.header.components[c].precision;
+            let (plane_x0, plane_y0, _, _) = self.header.component_rect(c);
+            let plane = &mut planes[c];
+            let (width, _) = component.size();
+            // Signed samples are shifted the same way, so that the output is unsigned
+            let shift = (1u32 << (precision - 1)) as f32;
+            let max = ((1u32 << precision) - 1) as f32;
+            for (row, line) in samples.chunks_exact(width.max(1) as usize).enumerate() {
+                let y = (component.y0 - plane_y0) as usize + row;
+                let start = y * plane.width as usize + (component.x0 - plane_x0) as usize;
+                for (out, &value) in plane.samples[start..start + line.len()]
+                    .iter_mut()
+                    .zip(line)
+                {
+                    *out = (value.round() + shift).clamp(0.0, max) as u16;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Inverse of the transform that decorrelates the first three components, see Annex G
+fn inverse_component_transform(samples: &mut [Vec<f32>], reversible: bool) {
+    let [y, cb, cr, ..] = samples else {
+        return;
+    };
+    for ((y, cb), cr) in y.iter_mut().zip(cb.iter_mut()).zip(cr.iter_mut()) {
+        let (red, green, blue) = if reversible {
+            let green = *y - ((*cb + *cr) / 4.0).floor();
+            (*cr + green, green, *cb + green)
+        } else {
+            (
+                *y + 1.402 * *cr,
+                *y - 0.344_136 * *cb - 0.714_136 * *cr,
+                *y + 1.772 * *cb,
+            )
+        };
+        (*y, *cb, *cr) = (red, green, blue);
+    }
+}
+
+/// A component of a single tile, with all the code-blocks that make it up
+struct TileComponent {
+    x0: u32,
+    y0: u32,
+    x1: u32,
+    y1: u32,
+    reversible: bool,
+    block_style: u8,
+    /// Subsampling and number of levels, which place the precincts on the reference grid
+    dx: u32,
+    dy: u32,
+    levels: u8,
+    resolutions: Vec<Resolution>,
+}
+
+impl TileComponent {
+    fn new(
+        component: Component,
+        style: &ComponentStyle,
+        quantization: &Quantization,
+        (tx0, ty0, tx1, ty1): (u32, u32, u32, u32),
+    ) -> ImageResult<Self> {
+        let (x0, y0) = (tx0.div_ceil(component.dx), ty0.div_ceil(component.dy));
+        let (x1, y1) = (tx1.div_ceil(component.dx), ty1.div_ceil(component.dy));
+        let levels = style.levels;
+        let mut resolutions = Vec::with_capacity(usize::from(levels) + 1);
+        for r in 0..=levels {
+            let scale = |v: u32| ceil_shift(i64::from(v), levels - r) as u32;
+            let (precinct_width, precinct_height) = style.precincts[usize::from(r)];
+            if r > 0 && (precinct_width == 0 || precinct_height == 0) {
+                return Err(error("invalid precinct size".to_owned()));
+            }
+            let mut resolution = Resolution {
+                x0: scale(x0),
+                y0: scale(y0),
+                x1: scale(x1),
+                y1: scale(y1),
+                precinct_width,
+                precinct_height,
+                precincts_wide: 0,
+                precincts_high: 0,
+                bands: Vec::new(),
+            };
+            resolution.precincts_wide =
+                precinct_count(resolution.x0, resolution.x1, precinct_width);
+            resolution.precincts_high =
+                precinct_count(resolution.y0, resolution.y1, precinct_height);
+
+            let bands: &[(Subband, u32, u32)] = if r == 0 {
+                &[(Subband::LowLow, 0, 0)]
+            } else {
+                &[
+                    (Subband::HighLow, 1, 0),
+                    (Subband::LowHigh, 0, 1),
+                    (Subband::HighHigh, 1, 1),
+                ]
+            };
+            // Number of decompositions that produced the subbands of this resolution
+            let decompositions = if r == 0 { levels } else { levels - r + 1 };
+            for (b, &(subband, x_offset, y_offset)) in bands.iter().enumerate() {
+                let band_coordinate = |v: u32, offset: u32| {
+                    let shifted = i64::from(v) - (i64::from(offset) << decompositions >> 1);
+                    ceil_shift(shifted, decompositions) as u32
+                };
+                let index = if r == 0 {
+                    0
+                } else {
+                    3 * usize::from(r - 1) + b + 1
+                };
+                let (exponent, mantissa) = quantization.step(index, levels, decompositions)?;
+                let bitplanes = i32::from(quantization.guard_bits) + exponent - 1;
+                if !(0..=MAX_BITPLANES as i32).contains(&bitplanes) {
+                    return Err(error("invalid quantization step size".to_owned()));
+                }
+                let step = if style.reversible {
+                    1.0
+                } else {
+                    let gain = match subband {
+                        Subband::LowLow => 0,
+                        Subband::HighLow | Subband::LowHigh => 1,
+                        Subband::HighHigh => 2,
+                    };
+                    let range = i32::from(component.precision) + gain;
+                    2f32.powi(range - exponent) * (1.0 + f32::from(mantissa) / 2048.0)
+                };
+                // Precincts are half as large in the subbands as in the resolution level they make up
+                let (band_precinct_width, band_precinct_height) = if r == 0 {
+                    (precinct_width, precinct_height)
+                } else {
+                    (precinct_width - 1, precinct_height - 1)
+                };
+                resolution.bands.push(Band::new(
+                    subband,
+                    (
+                        band_coordinate(x0, x_offset),
+                        band_coordinate(y0, y_offset),
+                        band_coordinate(x1, x_offset),
+                        band_coordinate(y1, y_offset),
+                    ),
+                    (
+                        style.block_width.min(band_precinct_width),
+                        style.block_height.min(band_precinct_height),
+                    ),
+                    &resolution,
+                    (band_precinct_width, band_precinct_height),
+                    bitplanes as u32,
+                    step,
+                ));
+            }
+            resolutions.push(resolution);
+        }
+        Ok(Self {
+            x0,
+            y0,
+            x1,
+            y1,
+            reversible: style.reversible,
+            block_style: style.block_style,
+            dx: component.dx,
+            dy: component.dy,
+            levels,
+            resolutions,
+        })
+    }
+
+    fn size(&self) -> (u32, u32) {
+        (self.x1 - self.x0, self.y1 - self.y0)
+    }
+
+    /// Decodes the code-blocks and applies the inverse wavelet transform
+    fn reconstruct(&self) -> Vec<f32> {
+        let mut current = self.resolutions[0].bands[0].coefficients(self.block_style);
+        for pair in self.resolutions.windows(2) {
+            let (previous, resolution) = (&pair[0], &pair[1]);
+            let width = (resolution.x1 - resolution.x0) as usize;
+            let height = (resolution.y1 - resolution.y0) as usize;
+            let mut data = vec![0.0; width * height];
+            // The low-pass samples go to even coordinates and the high-pass ones to odd coordinates
+            let mut interleave =
+                |values: &[f32],
+                 (x0, y0, x1): (u32, u32, u32),
+                 (x_offset, y_offset): (u32, u32)| {
+                    let band_width = (x1 - x0) as usize;
+                    if band_width == 0 {
+                        return;
+                    }
+                    for (row, line) in values.chunks_exact(band_width).enumerate() {
+                        let y = (2 * (y0 + row as u32) + y_offset - resolution.y0) as usize;
+                        for (column, &value) in line.iter().enumerate() {
+                            let x = (2 * (x0 + column as u32) + x_offset - resolution.x0) as usize;
+                            data[y * width + x] = value;
+                        }
+                    }
+                };
+            interleave(&current, (previous.x0, previous.y0, previous.x1), (0, 0));
+            for (band, offsets) in resolution.bands.iter().zip([(1, 0), (0, 1), (1, 1)]) {
+                let values = band.coefficients(self.block_style);
+                interleave(&values, (band.x0, band.y0, band.x1), offsets);
+            }
+            inverse_2d(
+                &mut data,
+                width,
+                height,
+                resolution.x0,
+                resolution.y0,
+                self.reversible,
+            );
+            current = data;
+        }
+        current
+    }
+
+    /// Position of the precinct on the reference grid, clamped to the tile,
+    /// which decides the order of packets in the position-driven progressions
+    fn precinct_position(&self, r: usize, precinct: u32, tile_x0: u32, tile_y0: u32) -> (u64, u64) {
+        let resolution = &self.resolutions[r];
+        let column = precinct % resolution.precincts_wide;
+        let row = precinct / resolution.precincts_wide;
+        let scale = u32::from(self.levels) - r as u32;
+        let position = |start: u32, index: u32, exponent: u8, subsampling: u32| {
+            let start = (u64::from(start >> exponent) + u64::from(index)) << exponent;
+            (start << scale) * u64::from(subsampling)
+        };
+        let x = position(resolution.x0, column, resolution.precinct_width, self.dx);
+        let y = position(resolution.y0, row, resolution.precinct_height, self.dy);
+        (x.max(u64::from(tile_x0)), y.max(u64::from(tile_y0)))
+    }
+}
+
+/// ceil(value / 2^shift), also for negative values
+fn ceil_shift(value: i64, shift: u8) -> i64 {
+    let divisor = 1i64 << shift;
+    (value + divisor - 1).div_euclid(divisor)
+}
+
+fn precinct_count(start: u32, end: u32, exponent: u8) -> u32 {
+    if end > start {
+        (((u64::from(end) + (1 << exponent) - 1) >> exponent) - (u64::from(start) >> exponent))
+            as u32
+    } else {
+        0
+    }
+}
+
+struct Resolution {
+    x0: u32,
+    y0: u32,
+    x1: u32,
+    y1: u32,
+    /// Exponents of the precinct size
+    precinct_width: u8,
+    precinct_height: u8,
+    precincts_wide: u32,
+    precincts_high: u32,
+    bands: Vec<Band>,
+}
+
+impl Resolution {
+    fn precinct_count(&self) -> u32 {
+        self.precincts_wide * self.precincts_high
+    }
+}
+
+struct Band {
+    subband: Subband,
+    x0: u32,
+    y0: u32,
+    x1: u32,
+    y1: u32,
+    /// Number of magnitude bitplanes, before subtracting the ones a code-block skips
+    bitplanes: u32,
+    step: f32,
+    /// Code-blocks in raster order
+    blocks: Vec<CodeBlock>,
+    blocks_wide: usize,
+    precincts: Vec<PrecinctBand>,
+}
+
+impl Band {
+    fn new(
+        subband: Subband,
+        (x0, y0, x1, y1): (u32, u32, u32, u32),
+        (block_width, block_height): (u8, u8),
+        resolution: &Resolution,
+        (precinct_width, precinct_height): (u8, u8),
+        bitplanes: u32,
+        step: f32,
+    ) -> Self {
+        let empty = x0 >= x1 || y0 >= y1;
+        let grid = |start: u32, end: u32, exponent: u8| {
+            if empty {
+                (0, 0)
+            } else {
+                (start >> exponent, (end - 1) >> exponent)
+            }
+        };
+        let (first_column, last_column) = grid(x0, x1, block_width);
+        let (first_row, last_row) = grid(y0, y1, block_height);
+        let (blocks_wide, blocks_high) = if empty {
+            (0, 0)
+        } else {
+            (last_column - first_column + 1, last_row - first_row + 1)
+        };
+        let mut blocks = Vec::with_capacity(blocks_wide as usize * blocks_high as usize);
+        for row in first_row..first_row + blocks_high {
+            for column in first_column..first_column + blocks_wide {
+                blocks.push(CodeBlock {
+                    x0: (column << block_width).max(x0),
+                    y0: (row << block_height).max(y0),
+                    x1: ((column + 1) << block_width).min(x1),
+                    y1: ((row + 1) << block_height).min(y1),
+                    ..Default::default()
+                });
+            }
+        }
+
+        // Code-blocks never straddle precinct boundaries, since they are at most as large
+        let column_shift = precinct_width - block_width;
+        let row_shift = precinct_height - block_height;
+        let mut precincts = Vec::with_capacity(resolution.precinct_count() as usize);
+        for precinct_row in 0..resolution.precincts_high {
+            for precinct_column in 0..resolution.precincts_wide {
+                let range = |first: u32, count: u32, base: u32, index: u32, shift: u8| {
+                    let start = (base + index) << shift;
+                    let end = (base + index + 1) << shift;
+                    let from = start.clamp(first, first + count) - first;
+                    let to = end.clamp(first, first + count) - first;
+                    (from, to)
+                };
+                let columns = range(
+                    first_column,
+                    blocks_wide,
+                    resolution.x0 >> resolution.precinct_width,
+                    precinct_column,
+                    column_shift,
+                );
+                let rows = range(
+                    first_row,
+                    blocks_high,
+                    resolution.y0 >> resolution.precinct_height,
+                    precinct_row,
+                    row_shift,
+                );
+                precincts.push(PrecinctBand::new(columns, rows));
+            }
+        }
+        Self {
+            subband,
+            x0,
+            y0,
+            x1,
+            y1,
+            bitplanes,
+            step,
+            blocks,
+            blocks_wide: blocks_wide as usize,
+            precincts,
+        }
+    }
+
+    /// Decodes the code-blocks into dequantized coefficients of the whole subband, in raster order
+    fn coefficients(&self, block_style: u8) -> Vec<f32> {
+        let width = (self.x1.saturating_sub(self.x0)) as usize;
+        let height = (self.y1.saturating_sub(self.y0)) as usize;
+        let mut coefficients = vec![0.0; width * height];
+        for block in &self.blocks {
+            if block.segments.is_empty() {
+                continue;
+            }
+            let block_width = (block.x1 - block.x0) as usize;
+            let block_height = (block.y1 - block.y0) as usize;
+            let values = decode_block(
+                block_width,
+                block_height,
+                self.subband,
+                block_style,
+                &block.segments,
+                self.bitplanes.saturating_sub(block.zero_bitplanes),
+            );
+            for (row, line) in values.chunks_exact(block_width).enumerate() {
+                let y = (block.y0 - self.y0) as usize + row;
+                let start = y * width + (block.x0 - self.x0) as usize;
+                for (out, value) in coefficients[start..start + block_width]
+                    .iter_mut()
+                    .zip(line)
+                {
+                    *out = value * self.step;
+                }
+            }
+        }
+        coefficients
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct CodeBlock {
+    x0: u32,
+    y0: u32,
+    x1: u32,
+    y1: u32,
+    included: bool,
+    zero_bitplanes: u32,
+    /// Number of bits used for signalling the length of the data
+    length_bits: u32,
+    passes: u32,
+    segments: Vec<Segment>,
+}
+
+impl CodeBlock {
+    fn add_passes(&mut self, passes: u32, data: &[u8], style: u8) {
+        match self.segments.last_mut() {
+            Some(last) if !ends_segment(style, last.first_pass + last.passes - 1) => {
+                last.passes += passes;
+                last.data.extend_from_slice(data);
+            }
+            _ => self.segments.push(Segment {
+                first_pass: self.passes,
+                passes,
+                data: data.to_vec(),
+            }),
+        }
+        self.passes += passes;
+    }
+}
+
+/// The code-blocks of a subband that belong to one precinct
+struct PrecinctBand {
+    /// Range of code-block columns and rows of the subband
+    columns: (u32, u32),
+    rows: (u32, u32),
+    inclusion: TagTree,
+    zero_bitplanes: TagTree,
+}
+
+impl PrecinctBand {
+    fn new(columns: (u32, u32), rows: (u32, u32)) -> Self {
+        let (width, height) = (columns.1 - columns.0, rows.1 - rows.0);
+        Self {
+            columns,
+            rows,
+            inclusion: TagTree::new(width, height),
+            zero_bitplanes: TagTree::new(width, height),
+        }
+    }
+}
+
+/// A quadtree of minimums that codes a value for every code-block of a precinct, see B.10.2
+struct TagTree {
+    /// Width of every level, starting from the leaves, and the offset of its first node
+    levels: Vec<(u32, usize)>,
+    values: Vec<u32>,
+    /// Lower bound of the value known so far
+    lows: Vec<u32>,
+}
+
+impl TagTree {
+    fn new(mut width: u32, mut height: u32) -> Self {
+        let mut levels = Vec::new();
+        let mut nodes = 0;
+        if width > 0 && height > 0 {
+            loop {
+                levels.push((width, nodes));
+                nodes += width as usize * height as usize;
+                if width == 1 && height == 1 {
+                    break;
+                }
+                width = width.div_ceil(2);
+                height = height.div_ceil(2);
+            }
+        }
+        Self {
+            levels,
+            values: vec![u32::MAX; nodes],
+            lows: vec![0; nodes],
+        }
+    }
+
+    /// Reads bits until it is known whether the value of the leaf is below the threshold
+    fn decode(
+        &mut self,
+        bits: &mut HeaderBits,
+        x: u32,
+        y: u32,
+        threshold: u32,
+    ) -> ImageResult<bool> {
+        let path: Vec<usize> = self
+            .levels
+            .iter()
+            .enumerate()
+            .map(|(level, &(width, offset))| {
+                offset + ((y >> level) * width + (x >> level)) as usize
+            })
+            .collect();
+        let mut low = 0;
+        for &node in path.iter().rev() {
+            low = low.max(self.lows[node]);
+            while low < threshold && low < self.values[node] {
+                if bits.bit()? == 1 {
+                    self.values[node] = low;
+                } else {
+                    low += 1;
+                }
+            }
+            self.lows[node] = low;
+        }
+        Ok(self.values[path[0]] < threshold)
+    }
+
+    fn value(&self, x: u32, y: u32) -> u32 {
+        self.values[(y * self.levels[0].0 + x) as usize]
+    }
+}
+
+/// Reads the bits of a packet header, skipping the bit stuffed after every 0xFF byte
+struct HeaderBits<'a> {
+    data: &'a [u8],
+    position: usize,
+    byte: u8,
+    bits_left: u8,
+}
+
+impl HeaderBits<'_> {
+    fn bit(&mut self) -> ImageResult<u32> {
+        if self.bits_left == 0 {
+            let Some(&byte) = self.data.get(self.position) else {
+                return Err(error("truncated packet header".to_owned()));
+            };
+            self.bits_left = if self.byte == 0xFF { 7 } else { 8 };
+            self.byte = byte;
+            self.position += 1;
+        }
+        self.bits_left -= 1;
+        Ok(u32::from(self.byte >> self.bits_left) & 1)
+    }
+
+    fn bits(&mut self, count: u32) -> ImageResult<u32> {
+        let mut value = 0;
+        for _ in 0..count {
+            value = value << 1 | self.bit()?;
+        }
+        Ok(value)
+    }
+
+    /// Position of the first byte after the header.
+    /// If the header ends with 0xFF, the byte with the stuffed bit belongs to the header too.
+    fn end(&self) -> usize {
+        if self.byte == 0xFF {
+            self.position + 1
+        } else {
+            self.position
+        }
+    }
+}
+
+/// Number of coding passes, see Table B.4
+fn pass_count(bits: &mut HeaderBits) -> ImageResult<u32> {
+    if bits.bit()? == 0 {
+        return Ok(1);
+    }
+    if bits.bit()? == 0 {
+        return Ok(2);
+    }
+    let value = bits.bits(2)?;
+    if value < 3 {
+        return Ok(3 + value);
+    }
+    let value = bits.bits(5)?;
+    if value < 31 {
+        return Ok(6 + value);
+    }
+    Ok(37 + bits.bits(7)?)
+}
+
+struct Packet {
+    layer: u32,
+    resolution: usize,
+    component: usize,
+    precinct: u32,
+}
+
+/// Lists all packets of the tile in the order they are stored in
+fn packet_order(
+    global: &GlobalStyle,
+    components: &[TileComponent],
+    (tile_x0, tile_y0, _, _): (u32, u32, u32, u32),
+) -> ImageResult<Vec<Packet>> {
+    let mut packets = Vec::new();
+    for (c, component) in components.iter().enumerate() {
+        for (r, resolution) in component.resolutions.iter().enumerate() {
+            for precinct in 0..resolution.precinct_count() {
+                let (x, y) = component.precinct_position(r, precinct, tile_x0, tile_y0);
+                for layer in 0..u32::from(global.layers) {
+                    let (l, r, c, p) = (u64::from(layer), r as u64, c as u64, u64::from(precinct));
+                    let key = match global.progression {
+                        0 => [l, r, c, p, 0],
+                        1 => [r, l, c, p, 0],
+                        2 => [r, y, x, c, l],
+                        3 => [y, x, c, r, l],
+                        4 => [c, y, x, r, l],
+                        other => return Err(error(format!("invalid progression order {other}"))),
+                    };
+                    packets.push((
+                        key,
+                        Packet {
+                            layer,
+                            resolution: r as usize,
+                            component: c as usize,
+                            precinct,
+                        },
+                    ));
+                }
+            }
+        }
+    }
+    packets.sort_by_key(|(key, _)| *key);
+    Ok(packets.into_iter().map(|(_, packet)| packet).collect())
+}
+
+/// Reads the packet at `position` into the code-blocks of the precinct and returns where the next packet starts
+fn read_packet(
+    data: &[u8],
+    mut position: usize,
+    packet: &Packet,
+    resolution: &mut Resolution,
+    global: &GlobalStyle,
+    block_style: u8,
+) -> ImageResult<usize> {
+    if global.sop && data[position..].starts_with(&SOP) {
+        position += 6;
+    }
+    let mut bits = HeaderBits {
+        data,
+        position,
+        byte: 0,
+        bits_left: 0,
+    };
+    // The lengths of the data of every included code-block: (band, block, passes, length)
+    let mut contributions = Vec::new();
+    if bits.bit()? == 1 {
+        for (b, band) in resolution.bands.iter_mut().enumerate() {
+            let precinct = &mut band.precincts[packet.precinct as usize];
+            for row in 0..precinct.rows.1 - precinct.rows.0 {
+                for column in 0..precinct.columns.1 - precinct.columns.0 {
+                    let index = (precinct.rows.0 + row) as usize * band.blocks_wide
+                        + (precinct.columns.0 + column) as usize;
+                    let block = &mut band.blocks[index];
+                    let included = if block.included {
+                        bits.bit()? == 1
+                    } else {
+                        precinct
+                            .inclusion
+                            .decode(&mut bits, column, row, packet.layer + 1)?
+                    };
+                    if !included {
+                        continue;
+                    }
+                    if !block.included {
+                        let mut threshold = 1;
+                        while !precinct
+                            .zero_bitplanes
+                            .decode(&mut bits, column, row, threshold)?
+                        {
+                            threshold += 1;
+                            if threshold > MAX_BITPLANES + 1 {
+                                return Err(error("too many missing bitplanes".to_owned()));
+                            }
+                        }
+                        block.zero_bitplanes = precinct.zero_bitplanes.value(column, row);
+                        block.included = true;
+                        block.length_bits = 3;
+                    }
+                    let passes = pass_count(&mut bits)?;
+                    while bits.bit()? == 1 {
+                        block.length_bits += 1;
+                        if block.length_bits > 32 {
+                            return Err(error("invalid code-block length".to_owned()));
+                        }
+                    }
+                    // Every terminated segment has its own length
+                    let mut pass = block.passes;
+                    let end = block.passes + passes;
+                    while pass < end {
+                        let mut count = 1;
+                        while pass + count < end && !ends_segment(block_style, pass + count - 1) {
+                            count += 1;
+                        }
+                        let length = bits.bits(block.length_bits + count.ilog2())?;
+                        contributions.push((b, index, count, length as usize));
+                        pass += count;
+                    }
+                }
+            }
+        }
+    }
+    position = bits.end();
+    if global.eph && data[position..].starts_with(&EPH) {
+        position += 2;
+    }
+    for (b, index, passes, length) in contributions {
+        let end = (position + length).min(data.len());
+        resolution.bands[b].blocks[index].add_passes(passes, &data[position..end], block_style);
+        position = end;
+    }
+    Ok(position)
+}
+
+/// Reads big-endian values from a marker segment or the codestream
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.position >= self.data.len()
+    }
+
+    fn bytes(&mut self, count: usize) -> ImageResult<&'a [u8]> {
+        let Some(bytes) = self.data.get(self.position..self.position + count) else {
+            return Err(error("truncated codestream".to_owned()));
+        };
+        self.position += count;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> ImageResult<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> ImageResult<u16> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> ImageResult<u32> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// Reads a marker segment, whose length includes the two bytes of the length itself
+    fn segment(&mut self) -> ImageResult<&'a [u8]> {
+        let length = self.u16()?;
+        if length < 2 {
+            return Err(error("invalid marker segment length".to_owned()));
+        }
+        self.bytes(usize::from(length) - 2)
+    }
+}
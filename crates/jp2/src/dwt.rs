@@ -0,0 +1,154 @@
+//! Inverse discrete wavelet transforms, see Annex F of ISO/IEC 15444-1.
+//!
+//! Both the reversible 5/3 and the irreversible 9/7 filter are implemented with lifting
+//! on a copy of the signal that is symmetrically extended at both ends.
+
+/// Lifting coefficients of the irreversible 9/7 filter
+const ALPHA: f32 = -1.586_134_3;
+const BETA: f32 = -0.052_980_12;
+const GAMMA: f32 = 0.882_911_1;
+const DELTA: f32 = 0.443_506_87;
+const K: f32 = 1.230_174_1;
+
+/// Enough samples for the longest filter to be applied in place at the edges
+const EXTENSION: usize = 4;
+
+/// Reconstructs a resolution level from the interleaved subbands, first horizontally and then vertically.
+///
+/// `x0` and `y0` are the coordinates of the top left sample, their parity decides
+/// which samples are low-pass and which are high-pass.
+pub fn inverse_2d(
+    data: &mut [f32],
+    width: usize,
+    height: usize,
+    x0: u32,
+    y0: u32,
+    reversible: bool,
+) {
+    let mut scratch = Vec::new();
+    for row in data.chunks_exact_mut(width) {
+        inverse_1d(row, x0 % 2 == 1, reversible, &mut scratch);
+    }
+    let mut column = vec![0.0; height];
+    for x in 0..width {
+        for (y, sample) in column.iter_mut().enumerate() {
+            *sample = data[y * width + x];
+        }
+        inverse_1d(&mut column, y0 % 2 == 1, reversible, &mut scratch);
+        for (y, sample) in column.iter().enumerate() {
+            data[y * width + x] = *sample;
+        }
+    }
+}
+
+/// 1D_SR from the standard. `odd_start` is set if the first sample is a high-pass one.
+fn inverse_1d(line: &mut [f32], odd_start: bool, reversible: bool, extended: &mut Vec<f32>) {
+    let n = line.len();
+    match n {
+        0 => return,
+        1 => {
+            // a lone high-pass sample was doubled by the forward transform
+            if odd_start {
+                line[0] /= 2.0;
+            }
+            return;
+        }
+        _ => (),
+    }
+
+    // Periodic symmetric extension, which handles signals shorter than the extension too
+    let period = 2 * (n - 1) as isize;
+    extended.clear();
+    extended.extend((0..n + 2 * EXTENSION).map(|k| {
+        let mut i = (k as isize - EXTENSION as isize).rem_euclid(period);
+        if i >= n as isize {
+            i = period - i;
+        }
+        line[i as usize]
+    }));
+    // EXTENSION is even, so samples at even indices have the same parity as the first one
+    let (even, odd) = if odd_start { (1, 0) } else { (0, 1) };
+    if !reversible {
+        for (k, sample) in extended.iter_mut().enumerate() {
+            *sample *= if k % 2 == even { K } else { 1.0 / K };
+        }
+    }
+    let len = extended.len();
+    let mut lift = |parity: usize, step: &dyn Fn(f32, f32, f32) -> f32| {
+        for k in (2 - parity..len - 1).step_by(2) {
+            extended[k] = step(extended[k], extended[k - 1], extended[k + 1]);
+        }
+    };
+    if reversible {
+        lift(even, &|x, left, right| {
+            x - ((left + right + 2.0) / 4.0).floor()
+        });
+        lift(odd, &|x, left, right| x + ((left + right) / 2.0).floor());
+    } else {
+        lift(even, &|x, left, right| x - DELTA * (left + right));
+        lift(odd, &|x, left, right| x - GAMMA * (left + right));
+        lift(even, &|x, left, right| x - BETA * (left + right));
+        lift(odd, &|x, left, right| x - ALPHA * (left + right));
+    }
+    line.copy_from_slice(&extended[EXTENSION..EXTENSION + n]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The forward 9/7 transform, the same way the inverse one is implemented
+    fn forward_1d(line: &mut [f32], odd_start: bool) {
+        let n = line.len();
+        let period = 2 * (n - 1) as isize;
+        let mut extended: Vec<f32> = (0..n + 2 * EXTENSION)
+            .map(|k| {
+                let mut i = (k as isize - EXTENSION as isize).rem_euclid(period);
+                if i >= n as isize {
+                    i = period - i;
+                }
+                line[i as usize]
+            })
+            .collect();
+        let (even, odd) = if odd_start { (1, 0) } else { (0, 1) };
+        let len = extended.len();
+        for (parity, coefficient) in [(odd, ALPHA), (even, BETA), (odd, GAMMA), (even, DELTA)] {
+            for k in (2 - parity..len - 1).step_by(2) {
+                extended[k] += coefficient * (extended[k - 1] + extended[k + 1]);
+            }
+        }
+        for (k, sample) in extended.iter_mut().enumerate() {
+            *sample *= if k % 2 == even { 1.0 / K } else { K };
+        }
+        line.copy_from_slice(&extended[EXTENSION..EXTENSION + n]);
+    }
+
+    #[test]
+    fn irreversible_round_trip() {
+        let mut scratch = Vec::new();
+        for n in 2..20 {
+            for odd_start in [false, true] {
+                let original: Vec<f32> = (0..n).map(|i| ((i * 37) % 11) as f32 - 5.0).collect();
+                let mut line = original.clone();
+                forward_1d(&mut line, odd_start);
+                inverse_1d(&mut line, odd_start, false, &mut scratch);
+                for (a, b) in line.iter().zip(&original) {
+                    assert!(
+                        (a - b).abs() < 1e-3,
+                        "{n} {odd_start}: {line:?} != {original:?}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn irreversible_low_pass_keeps_constant_signals() {
+        let mut line = vec![3.0; 10];
+        forward_1d(&mut line, false);
+        for (i, value) in line.iter().enumerate() {
+            let expected = if i % 2 == 0 { 3.0 } else { 0.0 };
+            assert!((value - expected).abs() < 1e-3, "{line:?}");
+        }
+    }
+}
@@ -0,0 +1,511 @@
+//! Reads JPEG 2000 images, both in the JP2 file format and as raw codestreams.
+//!
+//! Covers Part 1 of the standard (ISO/IEC 15444-1) except for region of interest coding,
+//! progression order changes and packed packet headers, which archival images do not use in practice.
+//! The whole image is decoded at full resolution and quality.
+
+#![forbid(unsafe_code)]
+
+mod codestream;
+mod dwt;
+#[cfg(test)]
+mod tests;
+mod tier1;
+
+use std::io::Read;
+
+use image::error::{DecodingError, ImageFormatHint};
+use image::{
+    ColorType, ExtendedColorType, ImageDecoder, ImageError, ImageResult, LimitSupport, Limits,
+};
+
+use codestream::{Codestream, Plane};
+
+/// The JPEG 2000 signature box that every JP2 file starts with
+pub const JP2_MAGIC: &[u8] = b"\x00\x00\x00\x0cjP  \r\n\x87\n";
+/// The start of codestream marker followed by the image and tile size marker
+pub const J2K_MAGIC: &[u8] = b"\xff\x4f\xff\x51";
+
+/// Enumerated colorspaces of the colour specification box
+const CMYK: u32 = 12;
+const SRGB: u32 = 16;
+const GRAYSCALE: u32 = 17;
+const SYCC: u32 = 18;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ColorModel {
+    Gray,
+    Rgb,
+    Cmyk,
+    Ycc,
+}
+
+impl ColorModel {
+    fn channels(&self) -> usize {
+        match self {
+            ColorModel::Gray => 1,
+            ColorModel::Rgb | ColorModel::Ycc => 3,
+            ColorModel::Cmyk => 4,
+        }
+    }
+}
+
+/// Where the samples of a channel come from
+#[derive(Debug, Clone, Copy)]
+enum Source {
+    Component(usize),
+    /// A column of the palette, indexed by the samples of the component
+    Palette {
+        component: usize,
+        column: usize,
+    },
+}
+
+/// The palette box: the bit depth of every column and the entries, one row per index
+#[derive(Debug, Default)]
+struct Palette {
+    precisions: Vec<u8>,
+    entries: Vec<Vec<u16>>,
+}
+
+/// What the JP2 header box says about the codestream
+#[derive(Debug, Default)]
+struct Jp2Header {
+    colorspace: Option<u32>,
+    icc_profile: Option<Vec<u8>>,
+    palette: Option<Palette>,
+    /// Component mapping: (component, palette column) for every channel
+    mapping: Option<Vec<(usize, Option<usize>)>>,
+    /// Channel definitions: (channel, type, association)
+    definitions: Option<Vec<(usize, u16, u16)>>,
+}
+
+pub struct Jp2Decoder {
+    codestream: Codestream,
+    palette: Option<Palette>,
+    model: ColorModel,
+    /// Colour channels in the order of the color model, then alpha if present
+    channels: Vec<Source>,
+    has_alpha: bool,
+    sixteen_bit: bool,
+    icc_profile: Option<Vec<u8>>,
+    /// What is left of the limits once the output buffer is accounted for
+    limits: Limits,
+}
+
+impl Jp2Decoder {
+    /// Parses the file up to the point where the tiles can be decoded
+    pub fn new(mut reader: impl Read) -> ImageResult<Self> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        let (codestream, header) = if data.starts_with(JP2_MAGIC) {
+            let (codestream, header) = parse_jp2(&data)?;
+            (Codestream::parse(codestream)?, header)
+        } else if data.starts_with(J2K_MAGIC) {
+            (Codestream::parse(&data)?, Jp2Header::default())
+        } else {
+            return Err(error("not a JPEG 2000 file".to_owned()));
+        };
+
+        let components = codestream.header.components.len();
+        let sources: Vec<Source> = match (&header.mapping, &header.palette) {
+            (Some(mapping), Some(palette)) => mapping
+                .iter()
+                .map(|&(component, column)| match column {
+                    Some(column) if column < palette.precisions.len() => {
+                        Ok(Source::Palette { component, column })
+                    }
+                    Some(_) => Err(error("invalid palette column".to_owned())),
+                    None => Ok(Source::Component(component)),
+                })
+                .collect::<ImageResult<_>>()?,
+            _ => (0..components).map(Source::Component).collect(),
+        };
+        if sources.iter().any(|source| match *source {
+            Source::Component(c) | Source::Palette { component: c, .. } => c >= components,
+        }) {
+            return Err(error("invalid component mapping".to_owned()));
+        }
+
+        let (mut colors, alpha): (Vec<usize>, Option<usize>) = match &header.definitions {
+            Some(definitions) => {
+                let mut colors: Vec<(u16, usize)> = definitions
+                    .iter()
+                    .filter(|&&(_, kind, _)| kind == 0)
+                    .map(|&(channel, _, association)| (association, channel))
+                    .collect();
+                colors.sort();
+                let alpha = definitions
+                    .iter()
+                    .find(|&&(_, kind, _)| kind == 1 || kind == 2)
+                    .map(|&(channel, _, _)| channel);
+                (
+                    colors.into_iter().map(|(_, channel)| channel).collect(),
+                    alpha,
+                )
+            }
+            None => {
+                let colors = match header.colorspace {
+                    Some(GRAYSCALE) => 1,
+                    Some(CMYK) if sources.len() >= 4 => 4,
+                    _ if sources.len() >= 3 => 3,
+                    _ => 1,
+                };
+                (
+                    (0..colors).collect(),
+                    (colors < sources.len()).then_some(colors),
+                )
+            }
+        };
+        let model = match (header.colorspace, colors.len()) {
+            (_, 0) => return Err(error("no color channels".to_owned())),
+            (Some(GRAYSCALE), _) | (_, 1..=2) => ColorModel::Gray,
+            (Some(CMYK), 4..) => ColorModel::Cmyk,
+            (Some(SYCC), _) => ColorModel::Ycc,
+            _ => ColorModel::Rgb,
+        };
+        colors.truncate(model.channels());
+        let mut channels = Vec::with_capacity(colors.len() + 1);
+        for channel in colors.into_iter().chain(alpha) {
+            match sources.get(channel) {
+                Some(&source) => channels.push(source),
+                None => return Err(error(format!("invalid channel {channel}"))),
+            }
+        }
+
+        let precision = |source: &Source| match *source {
+            Source::Component(c) => codestream.header.components[c].precision,
+            Source::Palette { column, .. } => header.palette.as_ref().unwrap().precisions[column],
+        };
+        let sixteen_bit = channels.iter().any(|source| precision(source) > 8);
+        Ok(Self {
+            model,
+            has_alpha: alpha.is_some(),
+            sixteen_bit,
+            channels,
+            codestream,
+            palette: header.palette,
+            icc_profile: header.icc_profile,
+            limits: Limits::default(),
+        })
+    }
+
+    /// The component that sets the size of the output; the others are scaled to match it
+    fn base_component(&self) -> usize {
+        match self.channels[0] {
+            Source::Component(c) | Source::Palette { component: c, .. } => c,
+        }
+    }
+
+    /// Resamples the channel to the grid of the base component and normalizes it to 0..=1
+    fn channel_values(&self, source: Source, planes: &[Plane]) -> Vec<f32> {
+        let header = &self.codestream.header;
+        let base = self.base_component();
+        let (base_x0, base_y0, width, height) = header.component_rect(base);
+        let (component, column) = match source {
+            Source::Component(c) => (c, None),
+            Source::Palette { component, column } => (component, Some(column)),
+        };
+        let (x0, y0, _, _) = header.component_rect(component);
+        let plane = &planes[component];
+        // nearest neighbour upsampling of subsampled components
+        let map = |start: u32,
+                   length: u32,
+                   base_scale: u32,
+                   scale: u32,
+                   own_start: u32,
+                   own_length: u32| {
+            (start..start + length)
+                .map(|v| {
+                    let v = (u64::from(v) * u64::from(base_scale) / u64::from(scale)) as u32;
+                    v.saturating_sub(own_start)
+                        .min(own_length.saturating_sub(1)) as usize
+                })
+                .collect::<Vec<usize>>()
+        };
+        let base_component = header.components[base];
+        let own = header.components[component];
+        let columns = map(base_x0, width, base_component.dx, own.dx, x0, plane.width);
+        let rows = map(base_y0, height, base_component.dy, own.dy, y0, plane.height);
+
+        let max = match column {
+            Some(column) => self.palette.as_ref().unwrap().precisions[column],
+            None => own.precision,
+        };
+        let max = ((1u32 << max) - 1) as f32;
+        let mut values = Vec::with_capacity(columns.len() * rows.len());
+        for &y in &rows {
+            let line = &plane.samples[y * plane.width as usize..];
+            for &x in &columns {
+                let sample = line[x];
+                let value = match column {
+                    Some(column) => {
+                        let entries = &self.palette.as_ref().unwrap().entries;
+                        let index = usize::from(sample).min(entries.len() - 1);
+                        entries[index][column]
+                    }
+                    None => sample,
+                };
+                values.push(f32::from(value) / max);
+            }
+        }
+        values
+    }
+}
+
+impl ImageDecoder for Jp2Decoder {
+    fn dimensions(&self) -> (u32, u32) {
+        let (_, _, width, height) = self.codestream.header.component_rect(self.base_component());
+        (width, height)
+    }
+
+    fn color_type(&self) -> ColorType {
+        let gray = self.model == ColorModel::Gray;
+        match (gray, self.has_alpha, self.sixteen_bit) {
+            (true, false, true) => ColorType::L16,
+            (true, true, true) => ColorType::La16,
+            (true, false, false) => ColorType::L8,
+            (true, true, false) => ColorType::La8,
+            (false, false, true) => ColorType::Rgb16,
+            (false, true, true) => ColorType::Rgba16,
+            (false, false, false) => ColorType::Rgb8,
+            (false, true, false) => ColorType::Rgba8,
+        }
+    }
+
+    fn original_color_type(&self) -> ExtendedColorType {
+        if self.model == ColorModel::Cmyk && !self.has_alpha && !self.sixteen_bit {
+            ExtendedColorType::Cmyk8
+        } else {
+            self.color_type().into()
+        }
+    }
+
+    fn icc_profile(&mut self) -> ImageResult<Option<Vec<u8>>> {
+        Ok(self.icc_profile.clone())
+    }
+
+    fn set_limits(&mut self, mut limits: Limits) -> ImageResult<()> {
+        limits.check_support(&LimitSupport::default())?;
+        let (width, height) = self.dimensions();
+        limits.check_dimensions(width, height)?;
+        limits.reserve(self.total_bytes())?;
+        // The channels are resampled to floating point before being written to the output
+        let samples = u64::from(width) * u64::from(height) * self.channels.len() as u64;
+        limits.reserve(samples * 4)?;
+        self.limits = limits;
+        Ok(())
+    }
+
+    fn read_image(mut self, buf: &mut [u8]) -> ImageResult<()> {
+        assert_eq!(u64::try_from(buf.len()), Ok(self.total_bytes()));
+        let planes = self.codestream.decode(&mut self.limits)?;
+        let mut channels: Vec<Vec<f32>> = self
+            .channels
+            .iter()
+            .map(|&source| self.channel_values(source, &planes))
+            .collect();
+        convert_to_rgb(self.model, &mut channels);
+
+        let output_channels = usize::from(self.color_type().channel_count());
+        let channels = &channels[channels.len() - output_channels..];
+        if self.sixteen_bit {
+            for (i, pixel) in buf.chunks_exact_mut(output_channels * 2).enumerate() {
+                for (out, channel) in pixel.chunks_exact_mut(2).zip(channels) {
+                    let value = (channel[i] * 65535.0).round().clamp(0.0, 65535.0) as u16;
+                    out.copy_from_slice(&value.to_ne_bytes());
+                }
+            }
+        } else {
+            for (i, pixel) in buf.chunks_exact_mut(output_channels).enumerate() {
+                for (out, channel) in pixel.iter_mut().zip(channels) {
+                    *out = (channel[i] * 255.0).round().clamp(0.0, 255.0) as u8;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn read_image_boxed(self: Box<Self>, buf: &mut [u8]) -> ImageResult<()> {
+        (*self).read_image(buf)
+    }
+}
+
+/// Converts the colour channels to RGB in place.
+/// The channels that are no longer needed are left at the front, so the output is a suffix.
+fn convert_to_rgb(model: ColorModel, channels: &mut [Vec<f32>]) {
+    match model {
+        ColorModel::Gray | ColorModel::Rgb => (),
+        ColorModel::Ycc => {
+            let [y, cb, cr, ..] = channels else {
+                return;
+            };
+            for ((y, cb), cr) in y.iter_mut().zip(cb.iter_mut()).zip(cr.iter_mut()) {
+                let (luma, blue, red) = (*y, *cb - 0.5, *cr - 0.5);
+                *y = luma + 1.402 * red;
+                *cb = luma - 0.344_136 * blue - 0.714_136 * red;
+                *cr = luma + 1.772 * blue;
+            }
+        }
+        ColorModel::Cmyk => {
+            // a naive conversion without a color profile, same as for PSD
+            let [c, m, y, k, ..] = channels else {
+                return;
+            };
+            for (((c, m), y), k) in c
+                .iter_mut()
+                .zip(m.iter_mut())
+                .zip(y.iter_mut())
+                .zip(k.iter_mut())
+            {
+                let white = 1.0 - *k;
+                (*m, *y, *k) = ((1.0 - *c) * white, (1.0 - *m) * white, (1.0 - *y) * white);
+            }
+        }
+    }
+}
+
+/// Finds the codestream and the JP2 header in the boxes of a JP2 file
+fn parse_jp2(data: &[u8]) -> ImageResult<(&[u8], Jp2Header)> {
+    let mut header = None;
+    for (kind, contents) in boxes(data)? {
+        match &kind {
+            b"jp2h" => header = Some(parse_header(contents)?),
+            b"jp2c" => {
+                let Some(header) = header else {
+                    return Err(error("missing JP2 header".to_owned()));
+                };
+                return Ok((contents, header));
+            }
+            _ => (),
+        }
+    }
+    Err(error("missing codestream".to_owned()))
+}
+
+fn parse_header(data: &[u8]) -> ImageResult<Jp2Header> {
+    let mut header = Jp2Header::default();
+    for (kind, contents) in boxes(data)? {
+        let mut r = Reader(contents);
+        match &kind {
+            // Only the first colour specification has to be understood by decoders
+            b"colr" if header.colorspace.is_none() && header.icc_profile.is_none() => {
+                let method = r.u8()?;
+                r.bytes(2)?; // precedence and approximation
+                match method {
+                    1 => header.colorspace = Some(r.u32()?),
+                    2 | 3 => header.icc_profile = Some(r.0.to_vec()),
+                    _ => (),
+                }
+            }
+            b"pclr" => {
+                let entries = r.u16()?;
+                let columns = r.u8()?;
+                let mut precisions = Vec::with_capacity(usize::from(columns));
+                for _ in 0..columns {
+                    let precision = (r.u8()? & 0x7F) + 1;
+                    if precision > 16 {
+                        return Err(error(format!("unsupported palette depth {precision}")));
+                    }
+                    precisions.push(precision);
+                }
+                if entries == 0 || columns == 0 {
+                    return Err(error("empty palette".to_owned()));
+                }
+                let entries = (0..entries)
+                    .map(|_| {
+                        precisions
+                            .iter()
+                            .map(|&precision| match precision {
+                                1..=8 => r.u8().map(u16::from),
+                                _ => r.u16(),
+                            })
+                            .collect::<ImageResult<Vec<u16>>>()
+                    })
+                    .collect::<ImageResult<_>>()?;
+                header.palette = Some(Palette {
+                    precisions,
+                    entries,
+                });
+            }
+            b"cmap" => {
+                let mut mapping = Vec::new();
+                while !r.0.is_empty() {
+                    let component = usize::from(r.u16()?);
+                    let kind = r.u8()?;
+                    let column = r.u8()?;
+                    mapping.push((component, (kind == 1).then_some(usize::from(column))));
+                }
+                header.mapping = Some(mapping);
+            }
+            b"cdef" => {
+                let count = r.u16()?;
+                let definitions = (0..count)
+                    .map(|_| Ok((usize::from(r.u16()?), r.u16()?, r.u16()?)))
+                    .collect::<ImageResult<_>>()?;
+                header.definitions = Some(definitions);
+            }
+            _ => (),
+        }
+    }
+    if header.colorspace.is_none() && header.icc_profile.is_none() {
+        header.colorspace = Some(SRGB);
+    }
+    Ok(header)
+}
+
+/// Splits the data into boxes, returning the type and the contents of each
+fn boxes(mut data: &[u8]) -> ImageResult<Vec<([u8; 4], &[u8])>> {
+    let mut boxes = Vec::new();
+    while !data.is_empty() {
+        let mut r = Reader(data);
+        let length = r.u32()?;
+        let kind: [u8; 4] = r.bytes(4)?.try_into().unwrap();
+        let (header, length) = match length {
+            // the box extends to the end of the file
+            0 => (8, data.len() as u64),
+            // the length does not fit into 32 bits and follows the type
+            1 => (16, u64::from(r.u32()?) << 32 | u64::from(r.u32()?)),
+            length => (8, u64::from(length)),
+        };
+        if length < header || length > data.len() as u64 {
+            return Err(error("invalid box length".to_owned()));
+        }
+        boxes.push((kind, &data[header as usize..length as usize]));
+        data = &data[length as usize..];
+    }
+    Ok(boxes)
+}
+
+/// Reads big-endian values from the contents of a box
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, count: usize) -> ImageResult<&'a [u8]> {
+        if self.0.len() < count {
+            return Err(error("truncated box".to_owned()));
+        }
+        let (bytes, rest) = self.0.split_at(count);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> ImageResult<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> ImageResult<u16> {
+        Ok(u16::from_be_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> ImageResult<u32> {
+        Ok(u32::from_be_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+}
+
+fn error(message: String) -> ImageError {
+    ImageError::Decoding(DecodingError::new(
+        ImageFormatHint::Name("JPEG 2000".to_owned()),
+        message,
+    ))
+}
@@ -0,0 +1,206 @@
+//! Round trips through a minimal encoder, which lives here since only the tests need it.
+
+mod encoder;
+
+use std::io::Cursor;
+
+use image::DynamicImage;
+
+use super::*;
+use encoder::{encode, Options};
+
+/// Deterministic content with both smooth areas and sharp edges
+fn plane(width: u32, height: u32, seed: u32, precision: u8) -> Vec<u16> {
+    let max = (1u32 << precision) - 1;
+    let mut state = seed.wrapping_mul(2_654_435_761).wrapping_add(1);
+    (0..width * height)
+        .map(|i| {
+            let (x, y) = (i % width, i / width);
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            let smooth = (x * 7 + y * 3 + seed * 50) % (max + 1);
+            let value = if (x / 5 + y / 3) % 4 == 0 {
+                state % (max + 1)
+            } else {
+                smooth
+            };
+            value as u16
+        })
+        .collect()
+}
+
+fn options(levels: u8, block_size: u8, tile_size: u32, precision: u8) -> Options {
+    Options {
+        levels,
+        block_size,
+        tile_size,
+        precision,
+    }
+}
+
+/// Wraps a codestream in a JP2 file with the given boxes in the JP2 header
+fn jp2(codestream: &[u8], header_boxes: &[(&[u8; 4], Vec<u8>)]) -> Vec<u8> {
+    let mut file = JP2_MAGIC.to_vec();
+    let boxed = |kind: &[u8; 4], contents: &[u8]| {
+        let mut b = ((contents.len() + 8) as u32).to_be_bytes().to_vec();
+        b.extend_from_slice(kind);
+        b.extend_from_slice(contents);
+        b
+    };
+    file.extend(boxed(b"ftyp", b"jp2 \x00\x00\x00\x00jp2 "));
+    let header: Vec<u8> = header_boxes
+        .iter()
+        .flat_map(|(kind, contents)| boxed(kind, contents))
+        .collect();
+    file.extend(boxed(b"jp2h", &header));
+    file.extend(boxed(b"jp2c", codestream));
+    file
+}
+
+fn decode(file: Vec<u8>) -> (DynamicImage, Option<Vec<u8>>) {
+    let mut decoder = Jp2Decoder::new(Cursor::new(file)).unwrap();
+    let icc = decoder.icc_profile().unwrap();
+    (DynamicImage::from_decoder(decoder).unwrap(), icc)
+}
+
+#[test]
+fn lossless_gray() {
+    let (width, height) = (37, 23);
+    let gray = plane(width, height, 1, 8);
+    let codestream = encode(
+        width,
+        height,
+        std::slice::from_ref(&gray),
+        &options(3, 4, 64, 8),
+    );
+    let (image, _) = decode(codestream);
+    let expected: Vec<u8> = gray.iter().map(|&v| v as u8).collect();
+    assert_eq!(image.as_luma8().unwrap().as_raw(), &expected);
+}
+
+#[test]
+fn lossless_rgb_tiles() {
+    let (width, height) = (45, 30);
+    let planes: Vec<Vec<u16>> = (0..3).map(|c| plane(width, height, c, 8)).collect();
+    let codestream = encode(width, height, &planes, &options(2, 3, 16, 8));
+    let (image, _) = decode(codestream);
+    let image = image.as_rgb8().unwrap();
+    for (i, pixel) in image.pixels().enumerate() {
+        assert_eq!(
+            pixel.0,
+            [planes[0][i], planes[1][i], planes[2][i]].map(|v| v as u8)
+        );
+    }
+}
+
+#[test]
+fn twelve_bit_is_scaled_to_sixteen() {
+    let (width, height) = (20, 9);
+    let gray = plane(width, height, 3, 12);
+    let codestream = encode(
+        width,
+        height,
+        std::slice::from_ref(&gray),
+        &options(1, 5, 32, 12),
+    );
+    let (image, _) = decode(codestream);
+    let expected: Vec<u16> = gray
+        .iter()
+        .map(|&v| (f32::from(v) / 4095.0 * 65535.0).round() as u16)
+        .collect();
+    assert_eq!(image.as_luma16().unwrap().as_raw(), &expected);
+}
+
+#[test]
+fn jp2_with_alpha_and_icc_profile() {
+    let (width, height) = (10, 8);
+    let gray = plane(width, height, 4, 8);
+    let alpha = plane(width, height, 5, 8);
+    let codestream = encode(
+        width,
+        height,
+        &[alpha.clone(), gray.clone()],
+        &options(1, 4, 16, 8),
+    );
+    let mut colr = vec![2, 0, 0];
+    colr.extend_from_slice(b"fake profile");
+    // the alpha channel comes first in the codestream
+    let mut cdef = 2u16.to_be_bytes().to_vec();
+    for value in [0u16, 1, 0, 1, 0, 1] {
+        cdef.extend_from_slice(&value.to_be_bytes());
+    }
+    let file = jp2(&codestream, &[(b"colr", colr), (b"cdef", cdef)]);
+    let (image, icc) = decode(file);
+    assert_eq!(icc.as_deref(), Some(&b"fake profile"[..]));
+    let image = image.as_luma_alpha8().unwrap();
+    for (i, pixel) in image.pixels().enumerate() {
+        assert_eq!(pixel.0, [gray[i] as u8, alpha[i] as u8]);
+    }
+}
+
+#[test]
+fn jp2_palette() {
+    let (width, height) = (6, 4);
+    let indices: Vec<u16> = (0..width * height).map(|i| (i % 3) as u16).collect();
+    let codestream = encode(width, height, &[indices], &options(1, 4, 16, 8));
+    let colors = [[255, 0, 0], [0, 255, 0], [10, 20, 30]];
+    let mut pclr = vec![0, 3, 3, 7, 7, 7];
+    pclr.extend(colors.iter().flatten());
+    let cmap: Vec<u8> = (0..3).flat_map(|column| [0, 0, 1, column]).collect();
+    let mut colr = vec![1, 0, 0];
+    colr.extend_from_slice(&SRGB.to_be_bytes());
+    let file = jp2(
+        &codestream,
+        &[(b"colr", colr), (b"pclr", pclr), (b"cmap", cmap)],
+    );
+    let (image, _) = decode(file);
+    for (i, pixel) in image.as_rgb8().unwrap().pixels().enumerate() {
+        assert_eq!(pixel.0, colors[i % 3]);
+    }
+}
+
+#[test]
+fn truncated_file_decodes_partially() {
+    let (width, height) = (32, 32);
+    let gray = plane(width, height, 6, 8);
+    let mut codestream = encode(width, height, &[gray], &options(2, 4, 32, 8));
+    codestream.truncate(codestream.len() * 2 / 3);
+    let (image, _) = decode(codestream);
+    assert_eq!(image.width(), 32);
+}
+
+#[test]
+fn not_jpeg_2000() {
+    assert!(Jp2Decoder::new(Cursor::new(b"\xff\xd8\xff\xe0".to_vec())).is_err());
+    // the codestream ends in the middle of the main header
+    assert!(Jp2Decoder::new(Cursor::new(J2K_MAGIC.to_vec())).is_err());
+}
+
+/// A codestream with a single empty tile of the given size
+fn empty_codestream(width: u32, height: u32) -> Vec<u8> {
+    let mut data = J2K_MAGIC.to_vec();
+    data.extend_from_slice(&41u16.to_be_bytes());
+    data.extend_from_slice(&[0, 0]); // capabilities
+    for value in [width, height, 0, 0, width, height, 0, 0] {
+        data.extend_from_slice(&value.to_be_bytes());
+    }
+    data.extend_from_slice(&[0, 1, 7, 1, 1]); // one 8-bit component
+    data.extend_from_slice(&[0xff, 0x90, 0, 10, 0, 0, 0, 0, 0, 0, 0, 1]);
+    data.extend_from_slice(&[0xff, 0x93, 0xff, 0xd9]);
+    data
+}
+
+#[test]
+fn oversized_header_is_rejected() {
+    let data = empty_codestream(200_000, 200_000);
+    assert!(data.len() < 100);
+    let mut decoder = Jp2Decoder::new(Cursor::new(data)).unwrap();
+    let error = decoder.set_limits(Limits::default()).unwrap_err();
+    assert!(matches!(error, ImageError::Limits(_)));
+    let mut limits = Limits::default();
+    limits.max_alloc = Some(100_000_000);
+    assert!(decoder.set_limits(limits).is_err());
+    // Dimensions no image could have are rejected right away
+    assert!(Jp2Decoder::new(Cursor::new(empty_codestream(u32::MAX, 1))).is_err());
+}
@@ -0,0 +1,707 @@
+//! A minimal lossless JPEG 2000 encoder for testing the decoder with round trips.
+//!
+//! Only writes the simplest kind of codestream: the reversible transforms, a single quality layer,
+//! one precinct per resolution level and all coding passes of a code-block in one codeword segment.
+
+use crate::tier1::{
+    initial_contexts, sign_context, stripe_columns, zero_coding_context, Subband, CONTEXT_COUNT,
+    FIRST_REFINEMENT_CONTEXT, LATER_REFINEMENT_CONTEXT, RUN_LENGTH_CONTEXT, STATES,
+    UNIFORM_CONTEXT,
+};
+
+const GUARD_BITS: u8 = 2;
+
+pub struct Options {
+    pub levels: u8,
+    /// Exponent of the code-block width and height
+    pub block_size: u8,
+    pub tile_size: u32,
+    pub precision: u8,
+}
+
+/// Encodes planes of `width` by `height` samples into a codestream.
+/// Three or more components go through the reversible component transform.
+pub fn encode(width: u32, height: u32, components: &[Vec<u16>], options: &Options) -> Vec<u8> {
+    let transform = components.len() >= 3;
+    let mut out = Vec::new();
+    marker(&mut out, 0xFF4F, None);
+
+    let mut siz = vec![0, 0];
+    for value in [
+        width,
+        height,
+        0,
+        0,
+        options.tile_size,
+        options.tile_size,
+        0,
+        0,
+    ] {
+        siz.extend_from_slice(&value.to_be_bytes());
+    }
+    siz.extend_from_slice(&(components.len() as u16).to_be_bytes());
+    for _ in components {
+        siz.extend_from_slice(&[options.precision - 1, 1, 1]);
+    }
+    marker(&mut out, 0xFF51, Some(&siz));
+
+    let block = options.block_size - 2;
+    let cod = [
+        0,
+        0,
+        0,
+        1,
+        u8::from(transform),
+        options.levels,
+        block,
+        block,
+        0,
+        1,
+    ];
+    marker(&mut out, 0xFF52, Some(&cod));
+
+    let mut qcd = vec![GUARD_BITS << 5];
+    for subband in 0..1 + 3 * usize::from(options.levels) {
+        qcd.push(exponent(options.precision, subband_kind(subband)) << 3);
+    }
+    marker(&mut out, 0xFF5C, Some(&qcd));
+
+    let tiles_wide = width.div_ceil(options.tile_size);
+    let tiles_high = height.div_ceil(options.tile_size);
+    for index in 0..tiles_wide * tiles_high {
+        let x0 = index % tiles_wide * options.tile_size;
+        let y0 = index / tiles_wide * options.tile_size;
+        let x1 = (x0 + options.tile_size).min(width);
+        let y1 = (y0 + options.tile_size).min(height);
+        let data = encode_tile(width, (x0, y0, x1, y1), components, transform, options);
+        let start = out.len();
+        let mut sot = (index as u16).to_be_bytes().to_vec();
+        sot.extend_from_slice(&0u32.to_be_bytes());
+        sot.extend_from_slice(&[0, 1]);
+        marker(&mut out, 0xFF90, Some(&sot));
+        marker(&mut out, 0xFF93, None);
+        out.extend_from_slice(&data);
+        let length = (out.len() - start) as u32;
+        out[start + 6..start + 10].copy_from_slice(&length.to_be_bytes());
+    }
+    marker(&mut out, 0xFFD9, None);
+    out
+}
+
+fn marker(out: &mut Vec<u8>, code: u16, segment: Option<&[u8]>) {
+    out.extend_from_slice(&code.to_be_bytes());
+    if let Some(segment) = segment {
+        out.extend_from_slice(&(segment.len() as u16 + 2).to_be_bytes());
+        out.extend_from_slice(segment);
+    }
+}
+
+fn subband_kind(index: usize) -> Subband {
+    match index {
+        0 => Subband::LowLow,
+        i => [Subband::HighLow, Subband::LowHigh, Subband::HighHigh][(i - 1) % 3],
+    }
+}
+
+/// Enough bits for the coefficients, with one more for the component transform
+fn exponent(precision: u8, subband: Subband) -> u8 {
+    let gain = match subband {
+        Subband::LowLow => 0,
+        Subband::HighLow | Subband::LowHigh => 1,
+        Subband::HighHigh => 2,
+    };
+    precision + gain + 1
+}
+
+fn encode_tile(
+    width: u32,
+    (x0, y0, x1, y1): (u32, u32, u32, u32),
+    components: &[Vec<u16>],
+    transform: bool,
+    options: &Options,
+) -> Vec<u8> {
+    let (tile_width, tile_height) = ((x1 - x0) as usize, (y1 - y0) as usize);
+    let shift = 1i32 << (options.precision - 1);
+    let mut samples: Vec<Vec<i32>> = components
+        .iter()
+        .map(|plane| {
+            let mut samples = Vec::with_capacity(tile_width * tile_height);
+            for y in y0..y1 {
+                for x in x0..x1 {
+                    samples.push(i32::from(plane[(y * width + x) as usize]) - shift);
+                }
+            }
+            samples
+        })
+        .collect();
+    if transform {
+        let [red, green, blue, ..] = &mut samples[..] else {
+            unreachable!()
+        };
+        for ((r, g), b) in red.iter_mut().zip(green.iter_mut()).zip(blue.iter_mut()) {
+            (*r, *g, *b) = ((*r + 2 * *g + *b) >> 2, *b - *g, *r - *g);
+        }
+    }
+    for samples in &mut samples {
+        forward_2d(samples, tile_width, tile_height, options.levels);
+    }
+
+    let mut out = Vec::new();
+    for r in 0..=options.levels {
+        for samples in &samples {
+            out.extend(encode_packet(samples, tile_width, (x0, y0), r, options));
+        }
+    }
+    out
+}
+
+/// The reversible 5/3 transform, keeping the subbands interleaved like the decoder expects them
+fn forward_2d(data: &mut [i32], width: usize, height: usize, levels: u8) {
+    for level in 0..levels {
+        let step = 1 << level;
+        let columns: Vec<usize> = (0..width).step_by(step).collect();
+        let rows: Vec<usize> = (0..height).step_by(step).collect();
+        for &x in &columns {
+            let mut line: Vec<i32> = rows.iter().map(|&y| data[y * width + x]).collect();
+            forward_1d(&mut line);
+            for (&y, value) in rows.iter().zip(line) {
+                data[y * width + x] = value;
+            }
+        }
+        for &y in &rows {
+            let mut line: Vec<i32> = columns.iter().map(|&x| data[y * width + x]).collect();
+            forward_1d(&mut line);
+            for (&x, value) in columns.iter().zip(line) {
+                data[y * width + x] = value;
+            }
+        }
+    }
+}
+
+fn forward_1d(x: &mut [i32]) {
+    let n = x.len() as isize;
+    if n < 2 {
+        return;
+    }
+    let mirror = |i: isize| {
+        (if i < 0 {
+            -i
+        } else if i >= n {
+            2 * (n - 1) - i
+        } else {
+            i
+        }) as usize
+    };
+    for i in (1..n).step_by(2) {
+        x[i as usize] -= (x[mirror(i - 1)] + x[mirror(i + 1)]) >> 1;
+    }
+    for i in (0..n).step_by(2) {
+        x[i as usize] += (x[mirror(i - 1)] + x[mirror(i + 1)] + 2) >> 2;
+    }
+}
+
+/// Encodes the only precinct of resolution level `r`
+fn encode_packet(
+    samples: &[i32],
+    tile_width: usize,
+    (tile_x0, tile_y0): (u32, u32),
+    r: u8,
+    options: &Options,
+) -> Vec<u8> {
+    let tile_height = samples.len() / tile_width;
+    let levels = options.levels;
+    let bands: &[(Subband, usize, usize)] = if r == 0 {
+        &[(Subband::LowLow, 0, 0)]
+    } else {
+        &[
+            (Subband::HighLow, 1, 0),
+            (Subband::LowHigh, 0, 1),
+            (Subband::HighHigh, 1, 1),
+        ]
+    };
+    let decompositions = if r == 0 { levels } else { levels - r + 1 };
+    let mut header = BitWriter::default();
+    let mut body = Vec::new();
+    header.put(1);
+    let mut included_any = false;
+    for &(subband, x_offset, y_offset) in bands {
+        // Coefficients of the band on the tile's lattice, in band coordinates
+        let step = 1usize << decompositions;
+        let offset = |o: usize| if r == 0 { 0 } else { o * step / 2 };
+        let band_x0 = tile_x0 as usize >> decompositions;
+        let band_y0 = tile_y0 as usize >> decompositions;
+        let band_width = (tile_width + step - 1 - offset(x_offset)) / step;
+        let band_height = (tile_height + step - 1 - offset(y_offset)) / step;
+        let coefficient = |x: usize, y: usize| {
+            samples[(y * step + offset(y_offset)) * tile_width + x * step + offset(x_offset)]
+        };
+        let bitplanes = u32::from(GUARD_BITS + exponent(options.precision, subband) - 1);
+
+        let size = 1usize << options.block_size;
+        let grid = |start: usize, length: usize| {
+            if length == 0 {
+                Vec::new()
+            } else {
+                let first = start / size;
+                let last = (start + length - 1) / size;
+                (first..=last)
+                    .map(|i| {
+                        let from = (i * size).max(start) - start;
+                        let to = ((i + 1) * size).min(start + length) - start;
+                        (from, to)
+                    })
+                    .collect()
+            }
+        };
+        let columns = grid(band_x0, band_width);
+        let rows = grid(band_y0, band_height);
+        let mut blocks = Vec::new();
+        for &(by0, by1) in &rows {
+            for &(bx0, bx1) in &columns {
+                let values: Vec<i32> = (by0..by1)
+                    .flat_map(|y| (bx0..bx1).map(move |x| (x, y)))
+                    .map(|(x, y)| coefficient(x, y))
+                    .collect();
+                blocks.push(encode_block(
+                    &values,
+                    bx1 - bx0,
+                    by1 - by0,
+                    subband,
+                    bitplanes,
+                ));
+            }
+        }
+        if blocks.is_empty() {
+            continue;
+        }
+        let inclusion: Vec<u32> = blocks.iter().map(|b| u32::from(b.is_none())).collect();
+        let zero_bitplanes: Vec<u32> = blocks
+            .iter()
+            .map(|b| b.as_ref().map_or(bitplanes, |b| b.0))
+            .collect();
+        let (width, height) = (columns.len() as u32, rows.len() as u32);
+        let mut inclusion = TagTreeEncoder::new(width, height, &inclusion);
+        let mut zero_bitplanes = TagTreeEncoder::new(width, height, &zero_bitplanes);
+        for (i, block) in blocks.iter().enumerate() {
+            let (x, y) = (i as u32 % width, i as u32 / width);
+            inclusion.encode(&mut header, x, y, 1);
+            let Some((zero, passes, data)) = block else {
+                continue;
+            };
+            included_any = true;
+            for threshold in 1..=zero + 1 {
+                zero_bitplanes.encode(&mut header, x, y, threshold);
+            }
+            match passes {
+                1 => header.put(0),
+                2 => header.bits(0b10, 2),
+                3..=5 => header.bits(0b1100 | (passes - 3), 4),
+                6..=36 => header.bits(0b1111 << 5 | (passes - 6), 9),
+                _ => header.bits(0b1_1111_1111 << 7 | (passes - 37), 16),
+            }
+            let needed = 32 - (data.len() as u32).leading_zeros();
+            let mut length_bits = 3;
+            while length_bits + passes.ilog2() < needed {
+                header.put(1);
+                length_bits += 1;
+            }
+            header.put(0);
+            header.bits(data.len() as u32, length_bits + passes.ilog2());
+            body.extend_from_slice(data);
+        }
+    }
+    if !included_any {
+        // an empty packet is a single zero bit
+        header = BitWriter::default();
+        header.put(0);
+    }
+    let mut packet = header.finish();
+    packet.extend_from_slice(&body);
+    packet
+}
+
+/// Returns the number of missing bitplanes, the number of coding passes and the data,
+/// or nothing if all coefficients are zero
+fn encode_block(
+    values: &[i32],
+    width: usize,
+    height: usize,
+    subband: Subband,
+    bitplanes: u32,
+) -> Option<(u32, u32, Vec<u8>)> {
+    let magnitude = values.iter().map(|v| v.unsigned_abs()).max()?;
+    if magnitude == 0 {
+        return None;
+    }
+    let used = 32 - magnitude.leading_zeros();
+    assert!(used <= bitplanes, "not enough guard bits");
+    let mut block = BlockEncoder {
+        width,
+        height,
+        stride: width + 2,
+        values: {
+            let mut padded = vec![0; (width + 2) * (height + 2)];
+            for y in 0..height {
+                for x in 0..width {
+                    padded[(y + 1) * (width + 2) + x + 1] = values[y * width + x];
+                }
+            }
+            padded
+        },
+        significant: vec![false; (width + 2) * (height + 2)],
+        visited: vec![false; (width + 2) * (height + 2)],
+        refined: vec![false; (width + 2) * (height + 2)],
+        subband,
+        contexts: initial_contexts(),
+        mq: MqEncoder::new(),
+    };
+    for bitplane in (0..used).rev() {
+        if bitplane != used - 1 {
+            block.significance_pass(bitplane);
+            block.refinement_pass(bitplane);
+        }
+        block.cleanup_pass(bitplane);
+    }
+    Some((bitplanes - used, 3 * used - 2, block.mq.finish()))
+}
+
+struct BlockEncoder {
+    width: usize,
+    height: usize,
+    stride: usize,
+    values: Vec<i32>,
+    significant: Vec<bool>,
+    visited: Vec<bool>,
+    refined: Vec<bool>,
+    subband: Subband,
+    contexts: [u8; CONTEXT_COUNT],
+    mq: MqEncoder,
+}
+
+impl BlockEncoder {
+    fn index(&self, x: usize, y: usize) -> usize {
+        (y + 1) * self.stride + x + 1
+    }
+
+    fn bit(&self, i: usize, bitplane: u32) -> u32 {
+        (self.values[i].unsigned_abs() >> bitplane) & 1
+    }
+
+    fn neighbours(&self, i: usize) -> (u8, u8, u8) {
+        let s = self.stride;
+        let f = |j: usize| u8::from(self.significant[j]);
+        (
+            f(i - 1) + f(i + 1),
+            f(i - s) + f(i + s),
+            f(i - s - 1) + f(i - s + 1) + f(i + s - 1) + f(i + s + 1),
+        )
+    }
+
+    fn encode(&mut self, context: usize, bit: u32) {
+        self.mq.encode(&mut self.contexts[context], bit);
+    }
+
+    fn encode_sign(&mut self, i: usize) {
+        let s = self.stride;
+        let sign = |j: usize| -> i8 {
+            match (self.significant[j], self.values[j] < 0) {
+                (false, _) => 0,
+                (true, true) => -1,
+                (true, false) => 1,
+            }
+        };
+        let (context, xor) = sign_context(sign(i - 1) + sign(i + 1), sign(i - s) + sign(i + s));
+        self.encode(context, u32::from(self.values[i] < 0) ^ xor);
+        self.significant[i] = true;
+    }
+
+    fn significance_pass(&mut self, bitplane: u32) {
+        for (x, y0) in stripe_columns(self.width, self.height) {
+            for y in y0..(y0 + 4).min(self.height) {
+                let i = self.index(x, y);
+                if self.significant[i] || self.neighbours(i) == (0, 0, 0) {
+                    continue;
+                }
+                self.visited[i] = true;
+                let bit = self.bit(i, bitplane);
+                self.encode(zero_coding_context(self.subband, self.neighbours(i)), bit);
+                if bit == 1 {
+                    self.encode_sign(i);
+                }
+            }
+        }
+    }
+
+    fn refinement_pass(&mut self, bitplane: u32) {
+        for (x, y0) in stripe_columns(self.width, self.height) {
+            for y in y0..(y0 + 4).min(self.height) {
+                let i = self.index(x, y);
+                if !self.significant[i] || self.visited[i] {
+                    continue;
+                }
+                let context = if self.refined[i] {
+                    LATER_REFINEMENT_CONTEXT
+                } else if self.neighbours(i) != (0, 0, 0) {
+                    FIRST_REFINEMENT_CONTEXT + 1
+                } else {
+                    FIRST_REFINEMENT_CONTEXT
+                };
+                self.encode(context, self.bit(i, bitplane));
+                self.refined[i] = true;
+            }
+        }
+    }
+
+    fn cleanup_pass(&mut self, bitplane: u32) {
+        for (x, y0) in stripe_columns(self.width, self.height) {
+            let y1 = (y0 + 4).min(self.height);
+            let mut y = y0;
+            let run_length = y1 - y0 == 4
+                && (y0..y1).all(|y| {
+                    let i = self.index(x, y);
+                    !self.significant[i] && !self.visited[i] && self.neighbours(i) == (0, 0, 0)
+                });
+            if run_length {
+                let first = (y0..y1).find(|&y| self.bit(self.index(x, y), bitplane) == 1);
+                let Some(first) = first else {
+                    self.encode(RUN_LENGTH_CONTEXT, 0);
+                    continue;
+                };
+                self.encode(RUN_LENGTH_CONTEXT, 1);
+                let position = (first - y0) as u32;
+                self.encode(UNIFORM_CONTEXT, position >> 1);
+                self.encode(UNIFORM_CONTEXT, position & 1);
+                self.encode_sign(self.index(x, first));
+                y = first + 1;
+            }
+            for y in y..y1 {
+                let i = self.index(x, y);
+                if self.significant[i] || self.visited[i] {
+                    continue;
+                }
+                let bit = self.bit(i, bitplane);
+                self.encode(zero_coding_context(self.subband, self.neighbours(i)), bit);
+                if bit == 1 {
+                    self.encode_sign(i);
+                }
+            }
+        }
+        self.visited.fill(false);
+    }
+}
+
+/// The MQ arithmetic encoder, see Annex C.2
+struct MqEncoder {
+    /// Starts with a placeholder byte that a carry can propagate into
+    out: Vec<u8>,
+    a: u32,
+    c: u32,
+    ct: u32,
+}
+
+impl MqEncoder {
+    fn new() -> Self {
+        Self {
+            out: vec![0],
+            a: 0x8000,
+            c: 0,
+            ct: 12,
+        }
+    }
+
+    fn encode(&mut self, context: &mut u8, bit: u32) {
+        let index = usize::from(*context >> 1);
+        let mps = u32::from(*context & 1);
+        let (qe, next_mps, next_lps, switch) = STATES[index];
+        self.a -= qe;
+        if bit == mps {
+            if self.a & 0x8000 != 0 {
+                self.c += qe;
+                return;
+            }
+            if self.a < qe {
+                self.a = qe;
+            } else {
+                self.c += qe;
+            }
+            *context = next_mps << 1 | mps as u8;
+        } else {
+            if self.a < qe {
+                self.c += qe;
+            } else {
+                self.a = qe;
+            }
+            let mps = if switch { 1 - mps } else { mps };
+            *context = next_lps << 1 | mps as u8;
+        }
+        loop {
+            self.a <<= 1;
+            self.c <<= 1;
+            self.ct -= 1;
+            if self.ct == 0 {
+                self.byte_out();
+            }
+            if self.a & 0x8000 != 0 {
+                break;
+            }
+        }
+    }
+
+    fn byte_out(&mut self) {
+        let last = self.out.len() - 1;
+        if self.out[last] == 0xFF {
+            self.out.push((self.c >> 20) as u8);
+            self.c &= 0xFFFFF;
+            self.ct = 7;
+        } else if self.c & 0x8000000 == 0 {
+            self.out.push((self.c >> 19) as u8);
+            self.c &= 0x7FFFF;
+            self.ct = 8;
+        } else {
+            self.out[last] += 1;
+            if self.out[last] == 0xFF {
+                self.c &= 0x7FFFFFF;
+                self.out.push((self.c >> 20) as u8);
+                self.c &= 0xFFFFF;
+                self.ct = 7;
+            } else {
+                self.out.push((self.c >> 19) as u8);
+                self.c &= 0x7FFFF;
+                self.ct = 8;
+            }
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        let sum = self.c + self.a;
+        self.c |= 0xFFFF;
+        if self.c >= sum {
+            self.c -= 0x8000;
+        }
+        self.c <<= self.ct;
+        self.byte_out();
+        self.c <<= self.ct;
+        self.byte_out();
+        // a trailing 0xFF would look like the start of a marker
+        if self.out.last() == Some(&0xFF) {
+            self.out.pop();
+        }
+        self.out.remove(0);
+        self.out
+    }
+}
+
+/// Writes packet header bits, stuffing a zero bit after every 0xFF byte
+#[derive(Default)]
+struct BitWriter {
+    out: Vec<u8>,
+    byte: u8,
+    count: u8,
+}
+
+impl BitWriter {
+    fn capacity(&self) -> u8 {
+        if self.out.last() == Some(&0xFF) {
+            7
+        } else {
+            8
+        }
+    }
+
+    fn put(&mut self, bit: u32) {
+        self.byte = self.byte << 1 | bit as u8;
+        self.count += 1;
+        if self.count == self.capacity() {
+            self.out.push(self.byte);
+            self.byte = 0;
+            self.count = 0;
+        }
+    }
+
+    fn bits(&mut self, value: u32, count: u32) {
+        for i in (0..count).rev() {
+            self.put((value >> i) & 1);
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            let padding = self.capacity() - self.count;
+            self.out.push(self.byte << padding);
+        }
+        if self.out.last() == Some(&0xFF) {
+            self.out.push(0);
+        }
+        self.out
+    }
+}
+
+struct TagTreeEncoder {
+    /// Width of every level, starting from the leaves, and the offset of its first node
+    levels: Vec<(u32, usize)>,
+    values: Vec<u32>,
+    lows: Vec<u32>,
+    known: Vec<bool>,
+}
+
+impl TagTreeEncoder {
+    fn new(mut width: u32, mut height: u32, leaves: &[u32]) -> Self {
+        let mut levels = Vec::new();
+        let mut values = leaves.to_vec();
+        let mut offset = 0;
+        loop {
+            levels.push((width, offset));
+            if width == 1 && height == 1 {
+                break;
+            }
+            let (parent_width, parent_height) = (width.div_ceil(2), height.div_ceil(2));
+            let parent_offset = values.len();
+            values.resize(
+                parent_offset + (parent_width * parent_height) as usize,
+                u32::MAX,
+            );
+            for y in 0..height {
+                for x in 0..width {
+                    let child = values[offset + (y * width + x) as usize];
+                    let parent =
+                        &mut values[parent_offset + (y / 2 * parent_width + x / 2) as usize];
+                    *parent = (*parent).min(child);
+                }
+            }
+            (width, height, offset) = (parent_width, parent_height, parent_offset);
+        }
+        Self {
+            levels,
+            lows: vec![0; values.len()],
+            known: vec![false; values.len()],
+            values,
+        }
+    }
+
+    fn encode(&mut self, bits: &mut BitWriter, x: u32, y: u32, threshold: u32) {
+        let path: Vec<usize> = self
+            .levels
+            .iter()
+            .enumerate()
+            .map(|(level, &(width, offset))| {
+                offset + ((y >> level) * width + (x >> level)) as usize
+            })
+            .collect();
+        let mut low = 0;
+        for &node in path.iter().rev() {
+            low = low.max(self.lows[node]);
+            while low < threshold {
+                if low >= self.values[node] {
+                    if !self.known[node] {
+                        bits.put(1);
+                        self.known[node] = true;
+                    }
+                    break;
+                }
+                bits.put(0);
+                low += 1;
+            }
+            self.lows[node] = low;
+        }
+    }
+}
@@ -0,0 +1,638 @@
+//! Tier-1 decoding: the embedded block coder that turns the bytes of a code-block into
+//! quantized wavelet coefficients, one bitplane at a time, using the MQ arithmetic decoder.
+//!
+//! See Annexes C and D of ISO/IEC 15444-1.
+
+/// Code-block style flags from the COD and COC markers
+pub const BYPASS: u8 = 0x01;
+pub const RESET: u8 = 0x02;
+pub const TERMINATE_ALL: u8 = 0x04;
+pub const VERTICALLY_CAUSAL: u8 = 0x08;
+pub const SEGMENTATION_SYMBOLS: u8 = 0x20;
+
+/// The first passes up to the end of the fourth bitplane are always arithmetic coded
+const FIRST_BYPASSED_PASS: u32 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Subband {
+    LowLow,
+    HighLow,
+    LowHigh,
+    HighHigh,
+}
+
+/// Coding passes that are decoded together, without restarting the arithmetic decoder in between
+#[derive(Debug, Clone, Default)]
+pub struct Segment {
+    pub first_pass: u32,
+    pub passes: u32,
+    pub data: Vec<u8>,
+}
+
+/// Whether the encoder terminated the codeword after this pass, so that the next pass starts a new segment
+pub fn ends_segment(style: u8, pass: u32) -> bool {
+    if style & TERMINATE_ALL != 0 {
+        true
+    } else if style & BYPASS != 0 {
+        // After the first four bitplanes the significance and refinement passes are stored raw
+        // and the cleanup pass is arithmetic coded, so every switch between the two ends a segment
+        pass + 1 == FIRST_BYPASSED_PASS
+            || (pass >= FIRST_BYPASSED_PASS && !(pass - FIRST_BYPASSED_PASS).is_multiple_of(3))
+    } else {
+        false
+    }
+}
+
+/// Decodes a code-block of the given size with `bitplanes` magnitude bitplanes.
+///
+/// Returns the coefficients in units of the quantization step, in raster order.
+/// Coefficients whose lower bitplanes were not transmitted are reconstructed at the midpoint of their interval.
+pub fn decode_block(
+    width: usize,
+    height: usize,
+    subband: Subband,
+    style: u8,
+    segments: &[Segment],
+    bitplanes: u32,
+) -> Vec<f32> {
+    let mut block = BlockDecoder::new(width, height, subband, style);
+    let mut lowest_bitplane = bitplanes;
+    for segment in segments {
+        let raw = style & BYPASS != 0
+            && segment.first_pass >= FIRST_BYPASSED_PASS
+            && pass_kind(segment.first_pass) != PassKind::Cleanup;
+        let mut coder = if raw {
+            Coder::Raw(RawDecoder::new(&segment.data))
+        } else {
+            Coder::Mq(MqDecoder::new(&segment.data))
+        };
+        for pass in segment.first_pass..segment.first_pass + segment.passes {
+            // The first pass is a cleanup pass of the most significant bitplane,
+            // after that every bitplane has all three passes
+            let Some(bitplane) = bitplanes.checked_sub(1 + pass.div_ceil(3)) else {
+                break;
+            };
+            match pass_kind(pass) {
+                PassKind::Significance => block.significance_pass(bitplane, &mut coder),
+                PassKind::Refinement => block.refinement_pass(bitplane, &mut coder),
+                PassKind::Cleanup => block.cleanup_pass(bitplane, &mut coder),
+            }
+            lowest_bitplane = bitplane;
+            if style & RESET != 0 {
+                block.reset_contexts();
+            }
+        }
+    }
+    block.coefficients(lowest_bitplane)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PassKind {
+    Significance,
+    Refinement,
+    Cleanup,
+}
+
+fn pass_kind(pass: u32) -> PassKind {
+    match pass % 3 {
+        0 => PassKind::Cleanup,
+        1 => PassKind::Significance,
+        _ => PassKind::Refinement,
+    }
+}
+
+const SIGNIFICANT: u8 = 1;
+const NEGATIVE: u8 = 2;
+/// Coded in the significance pass of the current bitplane
+const VISITED: u8 = 4;
+/// Refined at least once
+const REFINED: u8 = 8;
+
+// Context labels, see Tables D.1 to D.5
+const SIGN_CONTEXTS: usize = 9;
+pub(super) const FIRST_REFINEMENT_CONTEXT: usize = 14;
+pub(super) const LATER_REFINEMENT_CONTEXT: usize = 16;
+pub(super) const RUN_LENGTH_CONTEXT: usize = 17;
+pub(super) const UNIFORM_CONTEXT: usize = 18;
+pub(super) const CONTEXT_COUNT: usize = 19;
+
+struct BlockDecoder {
+    width: usize,
+    height: usize,
+    /// The state arrays have a border of one coefficient on every side,
+    /// so that neighbours can be looked up without bounds checks
+    stride: usize,
+    flags: Vec<u8>,
+    magnitudes: Vec<u32>,
+    subband: Subband,
+    vertically_causal: bool,
+    segmentation_symbols: bool,
+    contexts: [u8; CONTEXT_COUNT],
+}
+
+impl BlockDecoder {
+    fn new(width: usize, height: usize, subband: Subband, style: u8) -> Self {
+        let stride = width + 2;
+        let mut block = Self {
+            width,
+            height,
+            stride,
+            flags: vec![0; stride * (height + 2)],
+            magnitudes: vec![0; stride * (height + 2)],
+            subband,
+            vertically_causal: style & VERTICALLY_CAUSAL != 0,
+            segmentation_symbols: style & SEGMENTATION_SYMBOLS != 0,
+            contexts: [0; CONTEXT_COUNT],
+        };
+        block.reset_contexts();
+        block
+    }
+
+    fn reset_contexts(&mut self) {
+        self.contexts = initial_contexts();
+    }
+
+    fn index(&self, x: usize, y: usize) -> usize {
+        (y + 1) * self.stride + x + 1
+    }
+
+    /// Whether the row below can be used as context; in vertically causal mode
+    /// the coefficients of the next stripe are treated as insignificant
+    fn below_is_causal(&self, y: usize) -> bool {
+        !(self.vertically_causal && y % 4 == 3)
+    }
+
+    /// Numbers of significant horizontal, vertical and diagonal neighbours
+    fn neighbours(&self, i: usize, y: usize) -> (u8, u8, u8) {
+        let s = self.stride;
+        let f = |j: usize| self.flags[j] & SIGNIFICANT;
+        let horizontal = f(i - 1) + f(i + 1);
+        let mut vertical = f(i - s);
+        let mut diagonal = f(i - s - 1) + f(i - s + 1);
+        if self.below_is_causal(y) {
+            vertical += f(i + s);
+            diagonal += f(i + s - 1) + f(i + s + 1);
+        }
+        (horizontal, vertical, diagonal)
+    }
+
+    fn zero_coding_context(&self, neighbours: (u8, u8, u8)) -> usize {
+        zero_coding_context(self.subband, neighbours)
+    }
+
+    /// Returns the context and the bit to XOR the decoded sign with, see Table D.3
+    fn sign_context(&self, i: usize, y: usize) -> (usize, u32) {
+        let s = self.stride;
+        let contribution = |j: usize| -> i8 {
+            let f = self.flags[j];
+            if f & SIGNIFICANT == 0 {
+                0
+            } else if f & NEGATIVE != 0 {
+                -1
+            } else {
+                1
+            }
+        };
+        let horizontal = contribution(i - 1) + contribution(i + 1);
+        let mut vertical = contribution(i - s);
+        if self.below_is_causal(y) {
+            vertical += contribution(i + s);
+        }
+        sign_context(horizontal, vertical)
+    }
+
+    fn decode_sign(&mut self, i: usize, y: usize, bitplane: u32, coder: &mut Coder) {
+        let (context, xor) = self.sign_context(i, y);
+        let negative = match coder {
+            Coder::Mq(mq) => mq.decode(&mut self.contexts[context]) ^ xor,
+            Coder::Raw(raw) => raw.bit(),
+        };
+        self.flags[i] |= SIGNIFICANT | if negative == 1 { NEGATIVE } else { 0 };
+        self.magnitudes[i] |= 1 << bitplane;
+    }
+
+    fn stripe_columns(&self) -> impl Iterator<Item = (usize, usize)> {
+        stripe_columns(self.width, self.height)
+    }
+
+    fn significance_pass(&mut self, bitplane: u32, coder: &mut Coder) {
+        for (x, y0) in self.stripe_columns().collect::<Vec<_>>() {
+            for y in y0..(y0 + 4).min(self.height) {
+                let i = self.index(x, y);
+                if self.flags[i] & SIGNIFICANT != 0 {
+                    continue;
+                }
+                let neighbours = self.neighbours(i, y);
+                if neighbours == (0, 0, 0) {
+                    continue;
+                }
+                self.flags[i] |= VISITED;
+                let context = self.zero_coding_context(neighbours);
+                if coder.decode(&mut self.contexts, context) == 1 {
+                    self.decode_sign(i, y, bitplane, coder);
+                }
+            }
+        }
+    }
+
+    fn refinement_pass(&mut self, bitplane: u32, coder: &mut Coder) {
+        for (x, y0) in self.stripe_columns().collect::<Vec<_>>() {
+            for y in y0..(y0 + 4).min(self.height) {
+                let i = self.index(x, y);
+                // Coefficients that became significant in this bitplane are not refined yet
+                if self.flags[i] & (SIGNIFICANT | VISITED) != SIGNIFICANT {
+                    continue;
+                }
+                let context = if self.flags[i] & REFINED != 0 {
+                    LATER_REFINEMENT_CONTEXT
+                } else if self.neighbours(i, y) != (0, 0, 0) {
+                    FIRST_REFINEMENT_CONTEXT + 1
+                } else {
+                    FIRST_REFINEMENT_CONTEXT
+                };
+                let bit = coder.decode(&mut self.contexts, context);
+                self.magnitudes[i] |= bit << bitplane;
+                self.flags[i] |= REFINED;
+            }
+        }
+    }
+
+    fn cleanup_pass(&mut self, bitplane: u32, coder: &mut Coder) {
+        for (x, y0) in self.stripe_columns().collect::<Vec<_>>() {
+            let mut y = y0;
+            let y1 = (y0 + 4).min(self.height);
+            // Columns of four coefficients that are all insignificant with insignificant neighbours
+            // are coded with a single symbol in the common case that they stay that way
+            let run_length = y1 - y0 == 4
+                && (y0..y1).all(|y| {
+                    let i = self.index(x, y);
+                    self.flags[i] & (SIGNIFICANT | VISITED) == 0
+                        && self.neighbours(i, y) == (0, 0, 0)
+                });
+            if run_length {
+                if coder.decode(&mut self.contexts, RUN_LENGTH_CONTEXT) == 0 {
+                    continue;
+                }
+                let high = coder.decode(&mut self.contexts, UNIFORM_CONTEXT);
+                let low = coder.decode(&mut self.contexts, UNIFORM_CONTEXT);
+                y = y0 + (high << 1 | low) as usize;
+                let i = self.index(x, y);
+                self.decode_sign(i, y, bitplane, coder);
+                y += 1;
+            }
+            for y in y..y1 {
+                let i = self.index(x, y);
+                if self.flags[i] & (SIGNIFICANT | VISITED) != 0 {
+                    continue;
+                }
+                let context = self.zero_coding_context(self.neighbours(i, y));
+                if coder.decode(&mut self.contexts, context) == 1 {
+                    self.decode_sign(i, y, bitplane, coder);
+                }
+            }
+        }
+        for flags in &mut self.flags {
+            *flags &= !VISITED;
+        }
+        if self.segmentation_symbols {
+            // Always 1010, only useful for detecting errors
+            for _ in 0..4 {
+                coder.decode(&mut self.contexts, UNIFORM_CONTEXT);
+            }
+        }
+    }
+
+    fn coefficients(&self, lowest_bitplane: u32) -> Vec<f32> {
+        let midpoint = match lowest_bitplane {
+            0 => 0.0,
+            bitplane => (1u64 << (bitplane - 1)) as f32,
+        };
+        let mut coefficients = Vec::with_capacity(self.width * self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let i = self.index(x, y);
+                let value = match self.magnitudes[i] {
+                    0 => 0.0,
+                    magnitude => magnitude as f32 + midpoint,
+                };
+                coefficients.push(if self.flags[i] & NEGATIVE != 0 {
+                    -value
+                } else {
+                    value
+                });
+            }
+        }
+        coefficients
+    }
+}
+
+/// Context for coding whether a coefficient becomes significant, from the numbers of
+/// significant horizontal, vertical and diagonal neighbours, see Table D.1
+pub(super) fn zero_coding_context(subband: Subband, (h, v, d): (u8, u8, u8)) -> usize {
+    match subband {
+        Subband::LowLow | Subband::LowHigh => low_pass_context(h, v, d),
+        Subband::HighLow => low_pass_context(v, h, d),
+        Subband::HighHigh => match (d, h + v) {
+            (3.., _) => 8,
+            (2, 1..) => 7,
+            (2, 0) => 6,
+            (1, 2..) => 5,
+            (1, 1) => 4,
+            (1, 0) => 3,
+            (0, 2..) => 2,
+            (0, 1) => 1,
+            (0, 0) => 0,
+        },
+    }
+}
+
+/// Context for coding the sign and the bit to XOR it with, from the sums of the signs
+/// of the significant horizontal and vertical neighbours, see Table D.3
+pub(super) fn sign_context(horizontal: i8, vertical: i8) -> (usize, u32) {
+    let (label, xor) = match (horizontal.clamp(-1, 1), vertical.clamp(-1, 1)) {
+        (1, 1) => (4, 0),
+        (1, 0) => (3, 0),
+        (1, _) => (2, 0),
+        (0, 1) => (1, 0),
+        (0, 0) => (0, 0),
+        (0, _) => (1, 1),
+        (_, 1) => (2, 1),
+        (_, 0) => (3, 1),
+        (_, _) => (4, 1),
+    };
+    (SIGN_CONTEXTS + label, xor)
+}
+
+/// Initial states of the contexts, see Table D.7
+pub(super) fn initial_contexts() -> [u8; CONTEXT_COUNT] {
+    let mut contexts = [0; CONTEXT_COUNT];
+    contexts[0] = 4 << 1;
+    contexts[RUN_LENGTH_CONTEXT] = 3 << 1;
+    contexts[UNIFORM_CONTEXT] = 46 << 1;
+    contexts
+}
+
+/// Top of every column of four coefficients in the scan order:
+/// stripes of four rows from top to bottom, each stripe column by column
+pub(super) fn stripe_columns(width: usize, height: usize) -> impl Iterator<Item = (usize, usize)> {
+    (0..height)
+        .step_by(4)
+        .flat_map(move |y0| (0..width).map(move |x| (x, y0)))
+}
+
+/// Zero coding context for the LL and LH subbands, see Table D.1.
+/// HL uses the same table with horizontal and vertical swapped.
+fn low_pass_context(h: u8, v: u8, d: u8) -> usize {
+    match (h, v, d) {
+        (2.., _, _) => 8,
+        (1, 1.., _) => 7,
+        (1, 0, 1..) => 6,
+        (1, 0, 0) => 5,
+        (0, 2.., _) => 4,
+        (0, 1, _) => 3,
+        (0, 0, 2..) => 2,
+        (0, 0, 1) => 1,
+        (0, 0, 0) => 0,
+    }
+}
+
+enum Coder<'a> {
+    Mq(MqDecoder<'a>),
+    /// Passes coded in bypass mode store the bits as-is
+    Raw(RawDecoder<'a>),
+}
+
+impl Coder<'_> {
+    fn decode(&mut self, contexts: &mut [u8; CONTEXT_COUNT], context: usize) -> u32 {
+        match self {
+            Coder::Mq(mq) => mq.decode(&mut contexts[context]),
+            Coder::Raw(raw) => raw.bit(),
+        }
+    }
+}
+
+/// Probability estimates of the MQ coder, see Table C.2: (Qe, next index after MPS, next index after LPS, switch MPS)
+pub(super) const STATES: [(u32, u8, u8, bool); 47] = [
+    (0x5601, 1, 1, true),
+    (0x3401, 2, 6, false),
+    (0x1801, 3, 9, false),
+    (0x0AC1, 4, 12, false),
+    (0x0521, 5, 29, false),
+    (0x0221, 38, 33, false),
+    (0x5601, 7, 6, true),
+    (0x5401, 8, 14, false),
+    (0x4801, 9, 14, false),
+    (0x3801, 10, 14, false),
+    (0x3001, 11, 17, false),
+    (0x2401, 12, 18, false),
+    (0x1C01, 13, 20, false),
+    (0x1601, 29, 21, false),
+    (0x5601, 15, 14, true),
+    (0x5401, 16, 14, false),
+    (0x5101, 17, 15, false),
+    (0x4801, 18, 16, false),
+    (0x3801, 19, 17, false),
+    (0x3401, 20, 18, false),
+    (0x3001, 21, 19, false),
+    (0x2801, 22, 19, false),
+    (0x2401, 23, 20, false),
+    (0x2201, 24, 21, false),
+    (0x1C01, 25, 22, false),
+    (0x1801, 26, 23, false),
+    (0x1601, 27, 24, false),
+    (0x1401, 28, 25, false),
+    (0x1201, 29, 26, false),
+    (0x1101, 30, 27, false),
+    (0x0AC1, 31, 28, false),
+    (0x09C1, 32, 29, false),
+    (0x08A1, 33, 30, false),
+    (0x0521, 34, 31, false),
+    (0x0441, 35, 32, false),
+    (0x02A1, 36, 33, false),
+    (0x0221, 37, 34, false),
+    (0x0141, 38, 35, false),
+    (0x0111, 39, 36, false),
+    (0x0085, 40, 37, false),
+    (0x0049, 41, 38, false),
+    (0x0025, 42, 39, false),
+    (0x0015, 43, 40, false),
+    (0x0009, 44, 41, false),
+    (0x0005, 45, 42, false),
+    (0x0001, 45, 43, false),
+    (0x5601, 46, 46, false),
+];
+
+/// The MQ arithmetic decoder, see Annex C.3.
+/// Contexts are stored as the state index shifted left by one, with the most probable symbol in the lowest bit.
+pub(super) struct MqDecoder<'a> {
+    data: &'a [u8],
+    position: usize,
+    c_high: u32,
+    c_low: u32,
+    a: u32,
+    ct: i32,
+}
+
+impl<'a> MqDecoder<'a> {
+    pub(super) fn new(data: &'a [u8]) -> Self {
+        let mut decoder = Self {
+            data,
+            position: 0,
+            c_high: 0,
+            c_low: 0,
+            a: 0,
+            ct: 0,
+        };
+        decoder.c_high = decoder.byte(0);
+        decoder.byte_in();
+        decoder.c_high = ((decoder.c_high << 7) & 0xFFFF) | ((decoder.c_low >> 9) & 0x7F);
+        decoder.c_low = (decoder.c_low << 7) & 0xFFFF;
+        decoder.ct -= 7;
+        decoder.a = 0x8000;
+        decoder
+    }
+
+    /// The decoder is fed 0xFF bytes past the end of the data, like the standard requires
+    fn byte(&self, position: usize) -> u32 {
+        self.data.get(position).map_or(0xFF, |&b| u32::from(b))
+    }
+
+    fn byte_in(&mut self) {
+        if self.byte(self.position) == 0xFF {
+            if self.byte(self.position + 1) > 0x8F {
+                // a marker, which terminates the codeword
+                self.c_low += 0xFF00;
+                self.ct = 8;
+            } else {
+                self.position += 1;
+                self.c_low += self.byte(self.position) << 9;
+                self.ct = 7;
+            }
+        } else {
+            self.position += 1;
+            self.c_low += self.byte(self.position) << 8;
+            self.ct = 8;
+        }
+        if self.c_low > 0xFFFF {
+            self.c_high += self.c_low >> 16;
+            self.c_low &= 0xFFFF;
+        }
+    }
+
+    pub(super) fn decode(&mut self, context: &mut u8) -> u32 {
+        let index = usize::from(*context >> 1);
+        let mps = u32::from(*context & 1);
+        let (qe, next_mps, next_lps, switch) = STATES[index];
+        self.a -= qe;
+        let lps_state = |d: u32| next_lps << 1 | if switch { d as u8 } else { mps as u8 };
+        let decision = if self.c_high < qe {
+            // LPS exchange
+            if self.a < qe {
+                *context = next_mps << 1 | mps as u8;
+                self.a = qe;
+                mps
+            } else {
+                self.a = qe;
+                *context = lps_state(1 ^ mps);
+                1 ^ mps
+            }
+        } else {
+            self.c_high -= qe;
+            if self.a & 0x8000 != 0 {
+                return mps;
+            }
+            // MPS exchange
+            if self.a < qe {
+                *context = lps_state(1 ^ mps);
+                1 ^ mps
+            } else {
+                *context = next_mps << 1 | mps as u8;
+                mps
+            }
+        };
+        // renormalization
+        loop {
+            if self.ct == 0 {
+                self.byte_in();
+            }
+            self.a <<= 1;
+            self.c_high = ((self.c_high << 1) & 0xFFFF) | ((self.c_low >> 15) & 1);
+            self.c_low = (self.c_low << 1) & 0xFFFF;
+            self.ct -= 1;
+            if self.a & 0x8000 != 0 {
+                break;
+            }
+        }
+        decision
+    }
+}
+
+/// Reads bits as-is, skipping the bit stuffed after every 0xFF byte
+pub(super) struct RawDecoder<'a> {
+    data: &'a [u8],
+    position: usize,
+    byte: u8,
+    bits_left: u8,
+}
+
+impl<'a> RawDecoder<'a> {
+    pub(super) fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            position: 0,
+            byte: 0,
+            bits_left: 0,
+        }
+    }
+
+    pub(super) fn bit(&mut self) -> u32 {
+        if self.bits_left == 0 {
+            self.bits_left = if self.byte == 0xFF { 7 } else { 8 };
+            self.byte = self.data.get(self.position).copied().unwrap_or(0xFF);
+            self.position += 1;
+        }
+        self.bits_left -= 1;
+        u32::from(self.byte >> self.bits_left) & 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The test sequence from Annex H.2 of ITU-T T.88, which uses the same arithmetic coder
+    #[test]
+    fn mq_test_sequence() {
+        let encoded = [
+            0x84, 0xC7, 0x3B, 0xFC, 0xE1, 0xA1, 0x43, 0x04, 0x02, 0x20, 0x00, 0x00, 0x41, 0x0D,
+            0xBB, 0x86, 0xF4, 0x31, 0x7F, 0xFF, 0x88, 0xFF, 0x37, 0x47, 0x1A, 0xDB, 0x6A, 0xDF,
+            0xFF, 0xAC,
+        ];
+        let expected = [
+            0x00, 0x02, 0x00, 0x51, 0x00, 0x00, 0x00, 0xC0, 0x03, 0x52, 0x87, 0x2A, 0xAA, 0xAA,
+            0xAA, 0xAA, 0x82, 0xC0, 0x20, 0x00, 0xFC, 0xD7, 0x9E, 0xF6, 0xBF, 0x7F, 0xED, 0x90,
+            0x4F, 0x46, 0xA3, 0xBF,
+        ];
+        let mut decoder = MqDecoder::new(&encoded);
+        let mut context = 0;
+        for byte in expected {
+            let mut decoded = 0u8;
+            for _ in 0..8 {
+                decoded = decoded << 1 | decoder.decode(&mut context) as u8;
+            }
+            assert_eq!(decoded, byte);
+        }
+    }
+
+    #[test]
+    fn segment_boundaries() {
+        assert!(!ends_segment(0, 20));
+        assert!(ends_segment(TERMINATE_ALL, 0));
+        // cleanup of the fourth bitplane, then significance and refinement are raw
+        assert!(!ends_segment(BYPASS, 8));
+        assert!(ends_segment(BYPASS, 9));
+        assert!(!ends_segment(BYPASS, 10));
+        assert!(ends_segment(BYPASS, 11));
+        assert!(ends_segment(BYPASS, 12));
+    }
+}
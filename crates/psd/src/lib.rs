@@ -0,0 +1,535 @@
+//! Reads the flattened composite image that Photoshop stores alongside the layers.
+//!
+//! Layers are skipped entirely, same as imagemagick does when the composite is present.
+//! The format is documented at <https://www.adobe.com/devnet-apps/photoshop/fileformatashtml/>
+
+#![forbid(unsafe_code)]
+
+use std::io::{BufRead, Read, Seek, SeekFrom};
+
+use image::error::{DecodingError, ImageFormatHint};
+use image::{
+    ColorType, ExtendedColorType, ImageDecoder, ImageError, ImageResult, LimitSupport, Limits,
+};
+
+/// Image resource ID of the embedded ICC profile
+const ICC_PROFILE_RESOURCE: u16 = 1039;
+
+/// Photoshop refuses to create larger documents, so anything beyond this is corrupt
+const MAX_DIMENSION: u32 = 300_000;
+const MAX_CHANNELS: u16 = 56;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ColorMode {
+    Bitmap,
+    Grayscale,
+    Indexed,
+    Rgb,
+    Cmyk,
+    Duotone,
+}
+
+impl ColorMode {
+    fn from_u16(mode: u16) -> ImageResult<Self> {
+        Ok(match mode {
+            0 => ColorMode::Bitmap,
+            1 => ColorMode::Grayscale,
+            2 => ColorMode::Indexed,
+            3 => ColorMode::Rgb,
+            4 => ColorMode::Cmyk,
+            // duotone images are stored as grayscale, the inks only matter for printing
+            8 => ColorMode::Duotone,
+            other => return Err(error(format!("unsupported color mode {other}"))),
+        })
+    }
+
+    /// Number of channels that make up the color, excluding alpha
+    fn color_channels(&self) -> u16 {
+        match self {
+            ColorMode::Bitmap | ColorMode::Grayscale | ColorMode::Indexed | ColorMode::Duotone => 1,
+            ColorMode::Rgb => 3,
+            ColorMode::Cmyk => 4,
+        }
+    }
+}
+
+pub struct PsdDecoder<R> {
+    reader: R,
+    width: u32,
+    height: u32,
+    channels: u16,
+    depth: u16,
+    mode: ColorMode,
+    /// PSB, also known as the large document format, uses wider length fields
+    large: bool,
+    has_alpha: bool,
+    /// Planar: 256 red values, then 256 green, then 256 blue
+    palette: Vec<u8>,
+    icc_profile: Option<Vec<u8>>,
+}
+
+impl<R: BufRead + Seek> PsdDecoder<R> {
+    /// Parses everything up to the composite image data
+    pub fn new(mut reader: R) -> ImageResult<Self> {
+        let mut signature = [0; 4];
+        reader.read_exact(&mut signature)?;
+        if &signature != b"8BPS" {
+            return Err(error("not a PSD file".to_owned()));
+        }
+        let large = match read_u16(&mut reader)? {
+            1 => false,
+            2 => true,
+            other => return Err(error(format!("unsupported version {other}"))),
+        };
+        reader.seek(SeekFrom::Current(6))?; // reserved
+        let channels = read_u16(&mut reader)?;
+        let height = read_u32(&mut reader)?;
+        let width = read_u32(&mut reader)?;
+        let depth = read_u16(&mut reader)?;
+        let mode = ColorMode::from_u16(read_u16(&mut reader)?)?;
+
+        if width == 0 || height == 0 || width > MAX_DIMENSION || height > MAX_DIMENSION {
+            return Err(error(format!("invalid dimensions {width}x{height}")));
+        }
+        if channels < mode.color_channels() || channels > MAX_CHANNELS {
+            return Err(error(format!("invalid number of channels {channels}")));
+        }
+        match (mode, depth) {
+            (ColorMode::Bitmap, 1) => (),
+            (ColorMode::Indexed, 8) => (),
+            (ColorMode::Bitmap | ColorMode::Indexed, _) => {
+                return Err(error(format!("invalid bit depth {depth}")))
+            }
+            (_, 8 | 16) => (),
+            (_, _) => return Err(error(format!("unsupported bit depth {depth}"))),
+        }
+
+        let color_mode_data = read_section(&mut reader)?;
+        let palette = if mode == ColorMode::Indexed {
+            if color_mode_data.len() < 768 {
+                return Err(error("truncated color table".to_owned()));
+            }
+            color_mode_data[..768].to_vec()
+        } else {
+            Vec::new()
+        };
+
+        let resources = read_section(&mut reader)?;
+        let icc_profile = find_resource(&resources, ICC_PROFILE_RESOURCE).map(|r| r.to_vec());
+
+        // Layers and masks are not needed for the composite
+        let layers_length = if large {
+            read_u64(&mut reader)?
+        } else {
+            u64::from(read_u32(&mut reader)?)
+        };
+        let Ok(layers_length) = i64::try_from(layers_length) else {
+            return Err(error("invalid layer section length".to_owned()));
+        };
+        reader.seek(SeekFrom::Current(layers_length))?;
+
+        Ok(Self {
+            reader,
+            width,
+            height,
+            channels,
+            depth,
+            mode,
+            large,
+            // imagemagick treats the first channel after the color ones as alpha
+            has_alpha: channels > mode.color_channels() && mode != ColorMode::Bitmap,
+            palette,
+            icc_profile,
+        })
+    }
+
+    /// Bytes in a single row of a single channel
+    fn row_bytes(&self) -> usize {
+        (self.width as usize * usize::from(self.depth)).div_ceil(8)
+    }
+
+    /// Reads the planes of all the channels we use, as stored in the file
+    fn read_planes(&mut self) -> ImageResult<Vec<Vec<u8>>> {
+        let used_channels = self.mode.color_channels() + u16::from(self.has_alpha);
+        let plane_size = self.row_bytes() * self.height as usize;
+        let mut planes = Vec::with_capacity(usize::from(used_channels));
+        match read_u16(&mut self.reader)? {
+            0 => {
+                for _ in 0..used_channels {
+                    let mut plane = vec![0; plane_size];
+                    self.reader.read_exact(&mut plane)?;
+                    planes.push(plane);
+                }
+            }
+            1 => {
+                // The lengths of all the compressed rows of all channels come first
+                let rows = usize::from(self.channels) * self.height as usize;
+                let mut row_lengths = Vec::with_capacity(rows);
+                for _ in 0..rows {
+                    row_lengths.push(if self.large {
+                        read_u32(&mut self.reader)? as usize
+                    } else {
+                        usize::from(read_u16(&mut self.reader)?)
+                    });
+                }
+                let row_bytes = self.row_bytes();
+                let mut compressed = Vec::new();
+                for channel_rows in row_lengths
+                    .chunks_exact(self.height as usize)
+                    .take(usize::from(used_channels))
+                {
+                    let mut plane = Vec::with_capacity(plane_size);
+                    for &length in channel_rows {
+                        compressed.resize(length, 0);
+                        self.reader.read_exact(&mut compressed)?;
+                        unpack_bits(&compressed, row_bytes, &mut plane)?;
+                    }
+                    planes.push(plane);
+                }
+            }
+            other => return Err(error(format!("unsupported compression {other}"))),
+        }
+        Ok(planes)
+    }
+}
+
+impl<R: BufRead + Seek> ImageDecoder for PsdDecoder<R> {
+    fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    fn color_type(&self) -> ColorType {
+        let gray = matches!(
+            self.mode,
+            ColorMode::Bitmap | ColorMode::Grayscale | ColorMode::Duotone
+        );
+        match (gray, self.has_alpha, self.depth) {
+            (true, false, 16) => ColorType::L16,
+            (true, true, 16) => ColorType::La16,
+            (true, false, _) => ColorType::L8,
+            (true, true, _) => ColorType::La8,
+            (false, false, 16) => ColorType::Rgb16,
+            (false, true, 16) => ColorType::Rgba16,
+            (false, false, _) => ColorType::Rgb8,
+            (false, true, _) => ColorType::Rgba8,
+        }
+    }
+
+    fn original_color_type(&self) -> ExtendedColorType {
+        if self.mode == ColorMode::Bitmap {
+            ExtendedColorType::L1
+        } else {
+            self.color_type().into()
+        }
+    }
+
+    fn icc_profile(&mut self) -> ImageResult<Option<Vec<u8>>> {
+        Ok(self.icc_profile.clone())
+    }
+
+    fn set_limits(&mut self, mut limits: Limits) -> ImageResult<()> {
+        limits.check_support(&LimitSupport::default())?;
+        limits.check_dimensions(self.width, self.height)?;
+        // The output buffer, and then the planes as they are stored in the file
+        limits.reserve(self.total_bytes())?;
+        let used_channels = self.mode.color_channels() + u16::from(self.has_alpha);
+        limits.reserve(
+            (self.row_bytes() as u64)
+                .saturating_mul(u64::from(self.height))
+                .saturating_mul(u64::from(used_channels)),
+        )?;
+        Ok(())
+    }
+
+    fn read_image(mut self, buf: &mut [u8]) -> ImageResult<()> {
+        assert_eq!(u64::try_from(buf.len()), Ok(self.total_bytes()));
+        let planes = self.read_planes()?;
+        let pixel_count = self.width as usize * self.height as usize;
+        let output_channels = usize::from(self.color_type().channel_count());
+
+        if self.depth == 16 {
+            let samples: Vec<Vec<u16>> = planes
+                .iter()
+                .map(|plane| {
+                    plane
+                        .chunks_exact(2)
+                        .map(|b| u16::from_be_bytes([b[0], b[1]]))
+                        .collect()
+                })
+                .collect();
+            for (i, pixel) in buf.chunks_exact_mut(output_channels * 2).enumerate() {
+                let values = self.convert_pixel(&samples, i, u16::MAX);
+                for (out, value) in pixel.chunks_exact_mut(2).zip(values) {
+                    out.copy_from_slice(&value.to_ne_bytes());
+                }
+            }
+        } else {
+            let samples: Vec<Vec<u8>> = if self.mode == ColorMode::Bitmap {
+                vec![unpack_bitmap(&planes[0], self.width, self.row_bytes())]
+            } else {
+                planes
+            };
+            for (i, pixel) in buf
+                .chunks_exact_mut(output_channels)
+                .take(pixel_count)
+                .enumerate()
+            {
+                let values = self.convert_pixel(&samples, i, u8::MAX);
+                for (out, value) in pixel.iter_mut().zip(values) {
+                    *out = value;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn read_image_boxed(self: Box<Self>, buf: &mut [u8]) -> ImageResult<()> {
+        (*self).read_image(buf)
+    }
+}
+
+impl<R> PsdDecoder<R> {
+    /// Converts the samples of the pixel at `index` to the output color type
+    fn convert_pixel<T>(&self, planes: &[Vec<T>], index: usize, max: T) -> Vec<T>
+    where
+        T: Copy + Into<u32> + TryFrom<u32>,
+    {
+        let sample = |channel: usize| planes[channel][index];
+        let mut pixel: Vec<T> = match self.mode {
+            ColorMode::Grayscale | ColorMode::Duotone | ColorMode::Bitmap => vec![sample(0)],
+            ColorMode::Rgb => vec![sample(0), sample(1), sample(2)],
+            ColorMode::Indexed => {
+                let index: u32 = sample(0).into();
+                let index = index as usize;
+                [0, 256, 512]
+                    .iter()
+                    .map(|offset| from_u32(u32::from(self.palette[offset + index])))
+                    .collect()
+            }
+            ColorMode::Cmyk => {
+                // Photoshop stores CMYK inverted, i.e. the maximum value means no ink,
+                // so a naive conversion without a color profile is a simple multiplication
+                let max: u32 = max.into();
+                let black: u32 = sample(3).into();
+                (0..3)
+                    .map(|c| {
+                        let ink: u32 = sample(c).into();
+                        from_u32((ink * black + max / 2) / max)
+                    })
+                    .collect()
+            }
+        };
+        if self.has_alpha {
+            pixel.push(sample(usize::from(self.mode.color_channels())));
+        }
+        pixel
+    }
+}
+
+/// Infallible for values that are known to fit
+fn from_u32<T: TryFrom<u32>>(value: u32) -> T {
+    match T::try_from(value) {
+        Ok(value) => value,
+        Err(_) => unreachable!(),
+    }
+}
+
+/// Expands 1-bit samples to 8-bit ones. In PSD bitmaps a set bit is black.
+fn unpack_bitmap(plane: &[u8], width: u32, row_bytes: usize) -> Vec<u8> {
+    plane
+        .chunks_exact(row_bytes)
+        .flat_map(|row| {
+            (0..width as usize).map(move |x| {
+                if row[x / 8] & (0x80 >> (x % 8)) != 0 {
+                    0
+                } else {
+                    u8::MAX
+                }
+            })
+        })
+        .collect()
+}
+
+/// Decompresses a single row of PackBits run-length encoding, appending exactly `row_bytes` bytes to `out`
+fn unpack_bits(mut input: &[u8], row_bytes: usize, out: &mut Vec<u8>) -> ImageResult<()> {
+    let end = out.len() + row_bytes;
+    while out.len() < end {
+        let Some((&header, rest)) = input.split_first() else {
+            return Err(error("truncated compressed row".to_owned()));
+        };
+        let header = header as i8;
+        input = rest;
+        match header {
+            0..=127 => {
+                let count = header as usize + 1;
+                let Some(literal) = input.get(..count) else {
+                    return Err(error("truncated compressed row".to_owned()));
+                };
+                out.extend_from_slice(literal);
+                input = &input[count..];
+            }
+            -127..=-1 => {
+                let Some((&value, rest)) = input.split_first() else {
+                    return Err(error("truncated compressed row".to_owned()));
+                };
+                out.resize(out.len() + (1 - header as isize) as usize, value);
+                input = rest;
+            }
+            // no-op
+            -128 => (),
+        }
+    }
+    if out.len() != end {
+        return Err(error("compressed row is too long".to_owned()));
+    }
+    Ok(())
+}
+
+/// Finds an image resource block by its ID
+fn find_resource(mut resources: &[u8], id: u16) -> Option<&[u8]> {
+    while resources.len() >= 12 && &resources[..4] == b"8BIM" {
+        let resource_id = u16::from_be_bytes([resources[4], resources[5]]);
+        // The name is a Pascal string, padded to an even length including the length byte
+        let name_length = usize::from(resources[6]) + 1;
+        let name_length = name_length + name_length % 2;
+        let size_offset = 6 + name_length;
+        let size_bytes = resources.get(size_offset..size_offset + 4)?;
+        let size = u32::from_be_bytes(size_bytes.try_into().ok()?) as usize;
+        let data_offset = size_offset + 4;
+        let data = resources.get(data_offset..data_offset + size)?;
+        if resource_id == id {
+            return Some(data);
+        }
+        // data is padded to an even length too
+        resources = resources.get(data_offset + size + size % 2..)?;
+    }
+    None
+}
+
+/// Reads a section prefixed by its 32-bit length
+fn read_section(reader: &mut impl Read) -> ImageResult<Vec<u8>> {
+    let length = read_u32(reader)?;
+    let mut data = Vec::new();
+    reader.take(u64::from(length)).read_to_end(&mut data)?;
+    if data.len() != length as usize {
+        return Err(error("unexpected end of file".to_owned()));
+    }
+    Ok(data)
+}
+
+fn read_u16(reader: &mut impl Read) -> ImageResult<u16> {
+    let mut bytes = [0; 2];
+    reader.read_exact(&mut bytes)?;
+    Ok(u16::from_be_bytes(bytes))
+}
+
+fn read_u32(reader: &mut impl Read) -> ImageResult<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_be_bytes(bytes))
+}
+
+fn read_u64(reader: &mut impl Read) -> ImageResult<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_be_bytes(bytes))
+}
+
+fn error(message: String) -> ImageError {
+    ImageError::Decoding(DecodingError::new(
+        ImageFormatHint::Name("PSD".to_owned()),
+        message,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use image::DynamicImage;
+
+    use super::*;
+
+    /// Builds a minimal PSD file with the given header fields, resources and image data
+    fn psd(
+        channels: u16,
+        width: u32,
+        height: u32,
+        mode: u16,
+        resources: &[u8],
+        data: &[u8],
+    ) -> Vec<u8> {
+        let mut file = Vec::new();
+        file.extend_from_slice(b"8BPS");
+        file.extend_from_slice(&1u16.to_be_bytes());
+        file.extend_from_slice(&[0; 6]);
+        file.extend_from_slice(&channels.to_be_bytes());
+        file.extend_from_slice(&height.to_be_bytes());
+        file.extend_from_slice(&width.to_be_bytes());
+        file.extend_from_slice(&8u16.to_be_bytes());
+        file.extend_from_slice(&mode.to_be_bytes());
+        file.extend_from_slice(&0u32.to_be_bytes()); // color mode data
+        file.extend_from_slice(&(resources.len() as u32).to_be_bytes());
+        file.extend_from_slice(resources);
+        file.extend_from_slice(&0u32.to_be_bytes()); // layers
+        file.extend_from_slice(data);
+        file
+    }
+
+    fn decode(file: Vec<u8>) -> (DynamicImage, Option<Vec<u8>>) {
+        let mut decoder = PsdDecoder::new(Cursor::new(file)).unwrap();
+        let icc = decoder.icc_profile().unwrap();
+        (DynamicImage::from_decoder(decoder).unwrap(), icc)
+    }
+
+    #[test]
+    fn raw_rgb() {
+        let mut data = 0u16.to_be_bytes().to_vec();
+        // planar: red plane, then green, then blue
+        data.extend_from_slice(&[255, 0, 0, 0, 0, 255]);
+        let (image, icc) = decode(psd(3, 2, 1, 3, &[], &data));
+        assert_eq!(icc, None);
+        assert_eq!(image.to_rgb8().into_raw(), vec![255, 0, 0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn rle_gray_with_alpha_and_icc() {
+        let mut resources = b"8BIM".to_vec();
+        resources.extend_from_slice(&ICC_PROFILE_RESOURCE.to_be_bytes());
+        resources.extend_from_slice(&[0, 0]); // empty name, padded
+        resources.extend_from_slice(&3u32.to_be_bytes());
+        resources.extend_from_slice(b"icc\0");
+
+        let mut data = 1u16.to_be_bytes().to_vec();
+        // one row per channel, 2 bytes each
+        data.extend_from_slice(&2u16.to_be_bytes());
+        data.extend_from_slice(&3u16.to_be_bytes());
+        data.extend_from_slice(&[0xff, 7]); // run of 2 sevens for gray
+        data.extend_from_slice(&[0x01, 10, 20]); // 2 literal bytes for alpha
+        let (image, icc) = decode(psd(2, 2, 1, 1, &resources, &data));
+        assert_eq!(icc.as_deref(), Some(&b"icc"[..]));
+        assert_eq!(
+            image.as_luma_alpha8().unwrap().as_raw(),
+            &vec![7, 10, 7, 20]
+        );
+    }
+
+    #[test]
+    fn oversized_header_is_rejected() {
+        // A tiny file claiming to be a 300000x300000 RGB image with 16 bits per channel
+        let mut file = psd(3, 300_000, 300_000, 3, &[], &0u16.to_be_bytes());
+        file[22..24].copy_from_slice(&16u16.to_be_bytes());
+        let mut decoder = PsdDecoder::new(Cursor::new(file)).unwrap();
+        let error = decoder.set_limits(Limits::default()).unwrap_err();
+        assert!(matches!(error, ImageError::Limits(_)));
+    }
+
+    #[test]
+    fn cmyk() {
+        let mut data = 0u16.to_be_bytes().to_vec();
+        // inverted CMYK: full cyan ink, no black ink
+        data.extend_from_slice(&[0, 255, 255, 255]);
+        let (image, _) = decode(psd(4, 1, 1, 4, &[], &data));
+        assert_eq!(image.to_rgb8().into_raw(), vec![0, 255, 255]);
+    }
+}
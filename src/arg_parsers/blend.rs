@@ -0,0 +1,70 @@
+use std::{ffi::OsStr, str::FromStr};
+
+use crate::{error::MagickError, wm_err};
+
+/// The argument of `-colorize` and `-tint`: how much of the fill color to blend into each channel,
+/// as `value` for all channels or `red/green/blue`, in percent
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BlendPercent {
+    pub red: f64,
+    pub green: f64,
+    pub blue: f64,
+}
+
+impl BlendPercent {
+    pub fn to_array(self) -> [f64; 3] {
+        [self.red, self.green, self.blue]
+    }
+}
+
+impl FromStr for BlendPercent {
+    type Err = MagickError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || wm_err!("invalid argument `{}'", s);
+        let mut values = Vec::with_capacity(3);
+        for part in s.split([',', '/']) {
+            let part = part.trim();
+            let number = part.strip_suffix('%').unwrap_or(part);
+            match number.parse::<f64>() {
+                Ok(number) if number.is_finite() => values.push(number),
+                _ => return Err(invalid()),
+            }
+        }
+        // Missing channels take the value of the first one, like in imagemagick
+        let (red, green, blue) = match values[..] {
+            [value] => (value, value, value),
+            [red, green] => (red, green, red),
+            [red, green, blue] => (red, green, blue),
+            _ => return Err(invalid()),
+        };
+        Ok(Self { red, green, blue })
+    }
+}
+
+impl TryFrom<&OsStr> for BlendPercent {
+    type Error = MagickError;
+
+    fn try_from(s: &OsStr) -> Result<Self, Self::Error> {
+        match s.to_str() {
+            Some(s) => Self::from_str(s),
+            None => Err(wm_err!("invalid argument `{}'", s.to_string_lossy())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let parse = |s| BlendPercent::from_str(s).map(BlendPercent::to_array);
+        assert_eq!(parse("30%").unwrap(), [30.0; 3]);
+        assert_eq!(parse("10/20/30").unwrap(), [10.0, 20.0, 30.0]);
+        assert_eq!(parse("10,20").unwrap(), [10.0, 20.0, 10.0]);
+        assert!(parse("").is_err());
+        assert!(parse("1/2/3/4").is_err());
+        assert!(parse("red").is_err());
+    }
+}
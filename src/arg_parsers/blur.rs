@@ -0,0 +1,198 @@
+use std::{ffi::OsStr, str::FromStr};
+
+use crate::{error::MagickError, wm_err};
+
+/// The `{radius}x{sigma}` geometry of blur-like operations such as `-unsharp 0x1.5`.
+///
+/// A radius of 0 lets the operation pick one from sigma, and sigma defaults to 1 like in imagemagick.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BlurGeometry {
+    pub radius: f64,
+    pub sigma: f64,
+}
+
+impl BlurGeometry {
+    /// Parses the geometry of the given option, for use in error messages
+    fn parse(option: &str, s: &str) -> Result<Self, MagickError> {
+        let invalid = || wm_err!("invalid argument for option `-{}': {}", option, s);
+        let number = |value: &str| match value.trim().parse::<f64>() {
+            Ok(value) if value.is_finite() && value >= 0.0 => Ok(value),
+            _ => Err(invalid()),
+        };
+        let (radius, sigma) = match s.split_once(['x', 'X']) {
+            Some((radius, sigma)) => (number(radius)?, number(sigma)?),
+            None => (number(s)?, 1.0),
+        };
+        Ok(Self { radius, sigma })
+    }
+
+    /// Parses the argument of an option that takes nothing but the geometry, such as `-adaptive-blur`
+    pub fn from_arg(option: &str, value: &OsStr) -> Result<Self, MagickError> {
+        match value.to_str() {
+            Some(s) => Self::parse(option, s),
+            None => Err(wm_err!(
+                "invalid argument for option `-{}': {}",
+                option,
+                value.to_string_lossy()
+            )),
+        }
+    }
+}
+
+impl FromStr for BlurGeometry {
+    type Err = MagickError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse("blur", s)
+    }
+}
+
+/// The argument of `-motion-blur`: `{radius}x{sigma}{+angle}`, such as `0x12+45`.
+///
+/// The angle is in degrees clockwise from the positive x axis, 0 by default.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MotionBlurGeometry {
+    pub blur: BlurGeometry,
+    pub angle: f64,
+}
+
+impl MotionBlurGeometry {
+    /// Parses the geometry of the given option, for use in error messages
+    fn parse(option: &str, s: &str) -> Result<Self, MagickError> {
+        // The sign of the angle is what separates it from the rest, like in `0x12-30`
+        let (blur, angle) = match s
+            .char_indices()
+            .skip(1)
+            .find(|(_, c)| matches!(c, '+' | '-'))
+        {
+            Some((i, _)) => (&s[..i], Some(&s[i..])),
+            None => (s, None),
+        };
+        let blur = BlurGeometry::parse(option, blur)?;
+        let angle = match angle.map(|angle| angle.trim().parse::<f64>()) {
+            None => 0.0,
+            Some(Ok(angle)) if angle.is_finite() => angle,
+            Some(_) => return Err(wm_err!("invalid argument for option `-{}': {}", option, s)),
+        };
+        Ok(Self { blur, angle })
+    }
+
+    /// Parses the argument of another option with the same geometry, such as `-sketch`
+    pub fn from_arg(option: &str, value: &OsStr) -> Result<Self, MagickError> {
+        match value.to_str() {
+            Some(s) => Self::parse(option, s),
+            None => Err(wm_err!(
+                "invalid argument for option `-{}': {}",
+                option,
+                value.to_string_lossy()
+            )),
+        }
+    }
+}
+
+impl FromStr for MotionBlurGeometry {
+    type Err = MagickError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse("motion-blur", s)
+    }
+}
+
+impl TryFrom<&OsStr> for MotionBlurGeometry {
+    type Error = MagickError;
+
+    fn try_from(s: &OsStr) -> Result<Self, Self::Error> {
+        Self::from_arg("motion-blur", s)
+    }
+}
+
+/// The argument of `-unsharp`: `{radius}x{sigma}{+gain}{+threshold}`, such as `0x1.5+0.7+0.02`.
+///
+/// The gain is how much of the difference from the blurred image is added, 1 by default.
+/// The threshold is the fraction of the range that a difference must exceed to be sharpened at all,
+/// 0.05 by default.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UnsharpGeometry {
+    pub blur: BlurGeometry,
+    pub gain: f64,
+    pub threshold: f64,
+}
+
+impl FromStr for UnsharpGeometry {
+    type Err = MagickError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || wm_err!("invalid argument for option `-unsharp': {}", s);
+        let mut parts = s.split('+');
+        let blur = BlurGeometry::parse("unsharp", parts.next().unwrap_or_default())?;
+        let mut number = |default| match parts.next() {
+            Some(value) => match value.trim().parse::<f64>() {
+                Ok(value) if value.is_finite() => Ok(value),
+                _ => Err(invalid()),
+            },
+            None => Ok(default),
+        };
+        let gain = number(1.0)?;
+        let threshold = number(0.05)?;
+        if parts.next().is_some() {
+            return Err(invalid());
+        }
+        Ok(Self {
+            blur,
+            gain,
+            threshold,
+        })
+    }
+}
+
+impl TryFrom<&OsStr> for UnsharpGeometry {
+    type Error = MagickError;
+
+    fn try_from(s: &OsStr) -> Result<Self, Self::Error> {
+        match s.to_str() {
+            Some(s) => Self::from_str(s),
+            None => Err(wm_err!(
+                "invalid argument for option `-unsharp': {}",
+                s.to_string_lossy()
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_blur() {
+        let geometry = BlurGeometry::from_str("0x1.5").unwrap();
+        assert_eq!((geometry.radius, geometry.sigma), (0.0, 1.5));
+        let geometry = BlurGeometry::from_str("3").unwrap();
+        assert_eq!((geometry.radius, geometry.sigma), (3.0, 1.0));
+        assert!(BlurGeometry::from_str("-1x2").is_err());
+        assert!(BlurGeometry::from_str("x2").is_err());
+    }
+
+    #[test]
+    fn parse_motion_blur() {
+        let geometry = MotionBlurGeometry::from_str("0x12+45").unwrap();
+        assert_eq!((geometry.blur.sigma, geometry.angle), (12.0, 45.0));
+        assert_eq!(MotionBlurGeometry::from_str("5x2-30").unwrap().angle, -30.0);
+        assert_eq!(MotionBlurGeometry::from_str("0x3").unwrap().angle, 0.0);
+        assert!(MotionBlurGeometry::from_str("0x3+a").is_err());
+        assert!(MotionBlurGeometry::from_str("-1x3").is_err());
+    }
+
+    #[test]
+    fn parse_unsharp() {
+        let geometry = UnsharpGeometry::from_str("0x1.5+0.7+0.02").unwrap();
+        assert_eq!(geometry.blur.sigma, 1.5);
+        assert_eq!((geometry.gain, geometry.threshold), (0.7, 0.02));
+        let geometry = UnsharpGeometry::from_str("2x1").unwrap();
+        assert_eq!((geometry.gain, geometry.threshold), (1.0, 0.05));
+        let geometry = UnsharpGeometry::from_str("0x1+2").unwrap();
+        assert_eq!((geometry.gain, geometry.threshold), (2.0, 0.05));
+        assert!(UnsharpGeometry::from_str("0x1+1+0+0").is_err());
+        assert!(UnsharpGeometry::from_str("0x1+a").is_err());
+    }
+}
@@ -0,0 +1,69 @@
+use std::{ffi::OsStr, str::FromStr};
+
+use crate::{error::MagickError, wm_err};
+
+/// The argument of `-brightness-contrast`: `brightness[xcontrast][%]`,
+/// both in percent from -100 to 100. Contrast is 0 if missing.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BrightnessContrast {
+    pub brightness: f64,
+    pub contrast: f64,
+}
+
+impl FromStr for BrightnessContrast {
+    type Err = MagickError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || wm_err!("invalid argument for option `-brightness-contrast': {}", s);
+        let parse = |value: &str| -> Result<f64, MagickError> {
+            let value = value.trim();
+            let value = value.strip_suffix('%').unwrap_or(value);
+            match value.parse::<f64>() {
+                Ok(value) if value.is_finite() => Ok(value),
+                _ => Err(invalid()),
+            }
+        };
+        let (brightness, contrast) = match s.split_once(['x', 'X']) {
+            Some((brightness, contrast)) => (parse(brightness)?, parse(contrast)?),
+            None => (parse(s)?, 0.0),
+        };
+        Ok(Self {
+            brightness,
+            contrast,
+        })
+    }
+}
+
+impl TryFrom<&OsStr> for BrightnessContrast {
+    type Error = MagickError;
+
+    fn try_from(s: &OsStr) -> Result<Self, Self::Error> {
+        match s.to_str() {
+            Some(s) => Self::from_str(s),
+            None => Err(wm_err!(
+                "invalid argument for option `-brightness-contrast': {}",
+                s.to_string_lossy()
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let parse = |s| {
+            let parsed = BrightnessContrast::from_str(s).unwrap();
+            (parsed.brightness, parsed.contrast)
+        };
+        assert_eq!(parse("10x-20"), (10.0, -20.0));
+        assert_eq!(parse("-10"), (-10.0, 0.0));
+        assert_eq!(parse("-5.5x30%"), (-5.5, 30.0));
+        assert_eq!(parse("0x0"), (0.0, 0.0));
+        assert!(BrightnessContrast::from_str("x20").is_err());
+        assert!(BrightnessContrast::from_str("10x").is_err());
+        assert!(BrightnessContrast::from_str("bright").is_err());
+    }
+}
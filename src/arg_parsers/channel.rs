@@ -0,0 +1,122 @@
+use std::{ffi::OsStr, str::FromStr};
+
+use super::PixelChannel;
+use crate::{error::MagickError, wm_err};
+
+/// The argument of `-channel`: the channels that operations such as `-evaluate` apply to,
+/// given as names separated by commas like `red,alpha` or as letters like `RGBA`.
+///
+/// Only the color channels are selected by default, so that the alpha channel is left alone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChannelMask {
+    pub red: bool,
+    pub green: bool,
+    pub blue: bool,
+    pub alpha: bool,
+}
+
+impl Default for ChannelMask {
+    fn default() -> Self {
+        Self {
+            red: true,
+            green: true,
+            blue: true,
+            alpha: false,
+        }
+    }
+}
+
+impl ChannelMask {
+    const NONE: Self = Self {
+        red: false,
+        green: false,
+        blue: false,
+        alpha: false,
+    };
+
+    /// Whether each of the red, green, blue and alpha channels is selected
+    pub fn to_array(self) -> [bool; 4] {
+        [self.red, self.green, self.blue, self.alpha]
+    }
+
+    /// Whether some color channels are selected and others are not,
+    /// which turns gray pixels into colored ones
+    pub fn splits_colors(self) -> bool {
+        !(self.red == self.green && self.green == self.blue)
+    }
+
+    fn select(&mut self, channel: PixelChannel) {
+        match channel {
+            // the gray channel is stored where red is
+            PixelChannel::Red | PixelChannel::Gray => self.red = true,
+            PixelChannel::Green => self.green = true,
+            PixelChannel::Blue => self.blue = true,
+            PixelChannel::Alpha => self.alpha = true,
+        }
+    }
+}
+
+impl FromStr for ChannelMask {
+    type Err = MagickError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || wm_err!("unrecognized channel type `{}'", s);
+        let mut mask = Self::NONE;
+        for name in s.split(',').map(str::trim) {
+            if name.eq_ignore_ascii_case("sync") {
+                // channels are always updated together
+            } else if name.eq_ignore_ascii_case("all") {
+                mask = Self {
+                    alpha: true,
+                    ..Self::default()
+                };
+            } else if name.eq_ignore_ascii_case("default") {
+                mask = Self::default();
+            } else if let Ok(channel) = PixelChannel::from_str(name) {
+                mask.select(channel);
+            } else if !name.is_empty() {
+                for letter in name.chars() {
+                    let channel = PixelChannel::from_str(letter.encode_utf8(&mut [0; 4]))
+                        .map_err(|_| invalid())?;
+                    mask.select(channel);
+                }
+            }
+        }
+        match mask == Self::NONE {
+            true => Err(invalid()),
+            false => Ok(mask),
+        }
+    }
+}
+
+impl TryFrom<&OsStr> for ChannelMask {
+    type Error = MagickError;
+
+    fn try_from(s: &OsStr) -> Result<Self, Self::Error> {
+        match s.to_str() {
+            Some(s) => Self::from_str(s),
+            None => Err(wm_err!(
+                "unrecognized channel type `{}'",
+                s.to_string_lossy()
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let parse = |s| ChannelMask::from_str(s).map(ChannelMask::to_array);
+        assert_eq!(parse("RGB").unwrap(), [true, true, true, false]);
+        assert_eq!(parse("A").unwrap(), [false, false, false, true]);
+        assert_eq!(parse("red, Alpha").unwrap(), [true, false, false, true]);
+        assert_eq!(parse("gb").unwrap(), [false, true, true, false]);
+        assert_eq!(parse("All,sync").unwrap(), [true; 4]);
+        assert!(parse("sync").is_err());
+        assert!(parse("RGBX").is_err());
+        assert!(parse("").is_err());
+    }
+}
@@ -0,0 +1,104 @@
+use std::{ffi::OsStr, str::FromStr};
+
+use strum::EnumString;
+
+use crate::{error::MagickError, wm_err};
+
+/// A channel named in `-channel-fx` expressions
+#[derive(EnumString, Debug, Clone, Copy, PartialEq, Eq)]
+#[strum(ascii_case_insensitive)]
+pub enum PixelChannel {
+    #[strum(serialize = "red", serialize = "r")]
+    Red,
+    #[strum(serialize = "green", serialize = "g")]
+    Green,
+    #[strum(serialize = "blue", serialize = "b")]
+    Blue,
+    #[strum(serialize = "alpha", serialize = "a")]
+    Alpha,
+    /// The only color channel of grayscale images, which is the red channel of color ones
+    #[strum(serialize = "gray", serialize = "grey", serialize = "k")]
+    Gray,
+}
+
+/// A single step of a `-channel-fx` expression
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelOp {
+    /// `red<=>blue`: exchanges the two channels
+    Swap(PixelChannel, PixelChannel),
+    /// `red=>alpha`: overwrites the second channel with the first one
+    Copy(PixelChannel, PixelChannel),
+}
+
+impl ChannelOp {
+    pub fn channels(&self) -> [PixelChannel; 2] {
+        match *self {
+            ChannelOp::Swap(a, b) | ChannelOp::Copy(a, b) => [a, b],
+        }
+    }
+}
+
+/// The argument of `-channel-fx`: channel swaps and copies separated by `;` or `,`,
+/// applied in order, such as `red<=>blue; green=>alpha`.
+///
+/// Only rearranging the channels of a single image is supported,
+/// not splitting the image with `|` or assigning constants.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChannelFx {
+    pub ops: Vec<ChannelOp>,
+}
+
+impl FromStr for ChannelFx {
+    type Err = MagickError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || wm_err!("invalid argument for option `-channel-fx': {}", s);
+        let channel = |name: &str| PixelChannel::from_str(name.trim()).map_err(|_| invalid());
+        let mut ops = Vec::new();
+        for step in s.split([';', ',']) {
+            let op = if let Some((a, b)) = step.split_once("<=>") {
+                ChannelOp::Swap(channel(a)?, channel(b)?)
+            } else if let Some((a, b)) = step.split_once("=>") {
+                ChannelOp::Copy(channel(a)?, channel(b)?)
+            } else {
+                return Err(invalid());
+            };
+            ops.push(op);
+        }
+        Ok(Self { ops })
+    }
+}
+
+impl TryFrom<&OsStr> for ChannelFx {
+    type Error = MagickError;
+
+    fn try_from(s: &OsStr) -> Result<Self, Self::Error> {
+        match s.to_str() {
+            Some(s) => Self::from_str(s),
+            None => Err(wm_err!(
+                "invalid argument for option `-channel-fx': {}",
+                s.to_string_lossy()
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use PixelChannel::*;
+
+    #[test]
+    fn parse() {
+        let parse = |s| ChannelFx::from_str(s).map(|fx| fx.ops);
+        assert_eq!(parse("red<=>blue").unwrap(), [ChannelOp::Swap(Red, Blue)]);
+        assert_eq!(
+            parse(" Gray => A ; g<=>b").unwrap(),
+            [ChannelOp::Copy(Gray, Alpha), ChannelOp::Swap(Green, Blue)]
+        );
+        assert!(parse("red").is_err());
+        assert!(parse("red=>").is_err());
+        assert!(parse("cyan=>red").is_err());
+        assert!(parse("red<=>blue|green").is_err());
+    }
+}
@@ -0,0 +1,221 @@
+use std::{ffi::OsStr, str::FromStr};
+
+use crate::{error::MagickError, wm_err};
+
+/// A color as accepted by options such as `-background`, with every channel normalized to `[0, 1]`.
+///
+/// See <https://imagemagick.org/script/color.php> for the syntax.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Color {
+    pub red: f32,
+    pub green: f32,
+    pub blue: f32,
+    pub alpha: f32,
+}
+
+impl Color {
+    pub const WHITE: Color = Color::opaque(1.0, 1.0, 1.0);
+    pub const BLACK: Color = Color::opaque(0.0, 0.0, 0.0);
+
+    pub const fn opaque(red: f32, green: f32, blue: f32) -> Self {
+        Self {
+            red,
+            green,
+            blue,
+            alpha: 1.0,
+        }
+    }
+
+    pub fn is_opaque(&self) -> bool {
+        self.alpha >= 1.0
+    }
+
+    pub fn is_gray(&self) -> bool {
+        self.red == self.green && self.green == self.blue
+    }
+
+    pub fn to_array(self) -> [f32; 4] {
+        [self.red, self.green, self.blue, self.alpha]
+    }
+
+    /// The channels scaled to 8 bits, e.g. for palettes
+    pub fn to_rgba8(self) -> [u8; 4] {
+        self.to_array()
+            .map(|v| (v * 255.0).round().clamp(0.0, 255.0) as u8)
+    }
+}
+
+/// The most common of the named colors imagemagick knows, with the same values as imagemagick
+const NAMED_COLORS: &[(&str, [u8; 3])] = &[
+    ("black", [0, 0, 0]),
+    ("white", [255, 255, 255]),
+    ("red", [255, 0, 0]),
+    ("green", [0, 128, 0]),
+    ("lime", [0, 255, 0]),
+    ("blue", [0, 0, 255]),
+    ("yellow", [255, 255, 0]),
+    ("cyan", [0, 255, 255]),
+    ("aqua", [0, 255, 255]),
+    ("magenta", [255, 0, 255]),
+    ("fuchsia", [255, 0, 255]),
+    ("gray", [126, 126, 126]),
+    ("grey", [126, 126, 126]),
+    ("silver", [192, 192, 192]),
+    ("maroon", [128, 0, 0]),
+    ("navy", [0, 0, 128]),
+    ("olive", [128, 128, 0]),
+    ("purple", [128, 0, 128]),
+    ("teal", [0, 128, 128]),
+    ("orange", [255, 165, 0]),
+    ("pink", [255, 192, 203]),
+    ("brown", [165, 42, 42]),
+    ("gold", [255, 215, 0]),
+    ("skyblue", [135, 206, 235]),
+];
+
+impl FromStr for Color {
+    type Err = MagickError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.trim().to_ascii_lowercase();
+        let color = if let Some(hex) = name.strip_prefix('#') {
+            parse_hex(hex)
+        } else if let Some((function, arguments)) = name.split_once('(') {
+            parse_function(function, arguments.strip_suffix(')').unwrap_or(arguments))
+        } else if name == "none" || name == "transparent" {
+            Some(Color {
+                alpha: 0.0,
+                ..Color::opaque(0.0, 0.0, 0.0)
+            })
+        } else {
+            NAMED_COLORS
+                .iter()
+                .find(|(n, _)| *n == name)
+                .map(|(_, [r, g, b])| Color::opaque(unit(*r), unit(*g), unit(*b)))
+        };
+        color.ok_or_else(|| wm_err!("unrecognized color `{}'", s))
+    }
+}
+
+impl TryFrom<&OsStr> for Color {
+    type Error = MagickError;
+
+    fn try_from(s: &OsStr) -> Result<Self, Self::Error> {
+        match s.to_str() {
+            Some(s) => Color::from_str(s),
+            None => Err(wm_err!("unrecognized color `{}'", s.to_string_lossy())),
+        }
+    }
+}
+
+fn unit(value: u8) -> f32 {
+    f32::from(value) / 255.0
+}
+
+/// `#RGB`, `#RGBA`, `#RRGGBB`, `#RRGGBBAA` and the same with 16 bits per channel
+fn parse_hex(hex: &str) -> Option<Color> {
+    if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    let (digits, channels) = match hex.len() {
+        3 => (1, 3),
+        4 => (1, 4),
+        6 => (2, 3),
+        8 => (2, 4),
+        12 => (4, 3),
+        16 => (4, 4),
+        _ => return None,
+    };
+    let max = f32::from(u16::MAX >> (16 - 4 * digits));
+    let mut values = [1.0; 4];
+    for (i, value) in values.iter_mut().take(channels).enumerate() {
+        let digits = &hex[i * digits..(i + 1) * digits];
+        *value = f32::from(u16::from_str_radix(digits, 16).ok()?) / max;
+    }
+    let [red, green, blue, alpha] = values;
+    Some(Color {
+        red,
+        green,
+        blue,
+        alpha,
+    })
+}
+
+/// `rgb(r,g,b)`, `rgba(r,g,b,a)`, `gray(v)` and `graya(v,a)`.
+/// Color channels are 0 to 255 or percentages, alpha is 0 to 1.
+fn parse_function(function: &str, arguments: &str) -> Option<Color> {
+    let values: Vec<&str> = arguments.split(',').map(|v| v.trim()).collect();
+    let channel = |v: &str| -> Option<f32> {
+        match v.strip_suffix('%') {
+            Some(percent) => percent.parse::<f32>().ok().map(|p| p / 100.0),
+            None => v.parse::<f32>().ok().map(|v| v / 255.0),
+        }
+        .map(|v| v.clamp(0.0, 1.0))
+    };
+    let alpha = |v: &str| -> Option<f32> {
+        match v.strip_suffix('%') {
+            Some(percent) => percent.parse::<f32>().ok().map(|p| p / 100.0),
+            None => v.parse::<f32>().ok(),
+        }
+        .map(|v| v.clamp(0.0, 1.0))
+    };
+    let color = match (function, values.as_slice()) {
+        ("rgb", [r, g, b]) => Color::opaque(channel(r)?, channel(g)?, channel(b)?),
+        ("rgba", [r, g, b, a]) => Color {
+            alpha: alpha(a)?,
+            ..Color::opaque(channel(r)?, channel(g)?, channel(b)?)
+        },
+        ("gray" | "grey", [v]) => {
+            let v = channel(v)?;
+            Color::opaque(v, v, v)
+        }
+        ("graya" | "greya", [v, a]) => {
+            let v = channel(v)?;
+            Color {
+                alpha: alpha(a)?,
+                ..Color::opaque(v, v, v)
+            }
+        }
+        _ => return None,
+    };
+    Some(color)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hex() {
+        assert_eq!(Color::from_str("#fff").unwrap(), Color::WHITE);
+        assert_eq!(
+            Color::from_str("#FF000080").unwrap(),
+            Color {
+                alpha: 128.0 / 255.0,
+                ..Color::opaque(1.0, 0.0, 0.0)
+            }
+        );
+        assert_eq!(
+            Color::from_str("#00000000ffff").unwrap(),
+            Color::opaque(0.0, 0.0, 1.0)
+        );
+        assert!(Color::from_str("#12345").is_err());
+    }
+
+    #[test]
+    fn names_and_functions() {
+        assert_eq!(Color::from_str("White").unwrap(), Color::WHITE);
+        assert_eq!(Color::from_str("none").unwrap().alpha, 0.0);
+        assert_eq!(
+            Color::from_str("rgb(255, 0, 100%)").unwrap(),
+            Color::opaque(1.0, 0.0, 1.0)
+        );
+        assert_eq!(Color::from_str("rgba(0,0,0,0.5)").unwrap().alpha, 0.5);
+        assert_eq!(
+            Color::from_str("gray(50%)").unwrap(),
+            Color::opaque(0.5, 0.5, 0.5)
+        );
+        assert!(Color::from_str("no such color").is_err());
+        assert!(Color::from_str("rgb(1,2)").is_err());
+    }
+}
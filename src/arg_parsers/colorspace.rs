@@ -0,0 +1,79 @@
+use std::{ffi::OsStr, str::FromStr};
+
+use strum::{EnumString, IntoStaticStr};
+
+use crate::{error::MagickError, wm_err};
+
+/// The argument of `-colorspace`: the color model the pixel values are expressed in.
+///
+/// See <https://imagemagick.org/script/command-line-options.php#colorspace>
+#[derive(EnumString, IntoStaticStr, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[strum(ascii_case_insensitive)]
+pub enum Colorspace {
+    #[default]
+    #[strum(to_string = "sRGB", serialize = "RGB")]
+    Srgb,
+    /// Only the intensity of the sRGB colors
+    #[strum(to_string = "Gray", serialize = "Grey")]
+    Gray,
+    /// Cyan, magenta, yellow and black, for printing
+    #[strum(serialize = "CMYK")]
+    Cmyk,
+    /// CIE L*a*b* with the D65 white point, where L is the perceived lightness
+    #[strum(serialize = "Lab")]
+    Lab,
+    /// CIE XYZ with linear channels, which Lab is computed from
+    #[strum(serialize = "XYZ")]
+    Xyz,
+    /// Hue, saturation and lightness
+    #[strum(serialize = "HSL")]
+    Hsl,
+    /// Hue, saturation and brightness, also known as HSV
+    #[strum(to_string = "HSB", serialize = "HSV")]
+    Hsb,
+    /// Luma and two color differences, as used by JPEG
+    #[strum(serialize = "YCbCr")]
+    YCbCr,
+}
+
+impl Colorspace {
+    /// Whether the pixels are stored as sRGB, either in color or grayscale
+    pub fn is_srgb(self) -> bool {
+        matches!(self, Colorspace::Srgb | Colorspace::Gray)
+    }
+
+    /// The name imagemagick reports the colorspace by
+    pub fn name(self) -> &'static str {
+        self.into()
+    }
+}
+
+impl TryFrom<&OsStr> for Colorspace {
+    type Error = MagickError;
+
+    fn try_from(s: &OsStr) -> Result<Self, Self::Error> {
+        let Some(colorspace) = s.to_str().and_then(|s| Colorspace::from_str(s).ok()) else {
+            return Err(wm_err!(
+                "unrecognized colorspace type `{}'",
+                s.to_string_lossy()
+            ));
+        };
+        Ok(colorspace)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let parse = |s| Colorspace::try_from(OsStr::new(s));
+        assert_eq!(parse("LAB").unwrap(), Colorspace::Lab);
+        assert_eq!(parse("rgb").unwrap(), Colorspace::Srgb);
+        assert_eq!(parse("cmyk").unwrap().name(), "CMYK");
+        assert_eq!(parse("hsv").unwrap().name(), "HSB");
+        assert_eq!(parse("ycbcr").unwrap(), Colorspace::YCbCr);
+        assert!(parse("CMY-K").is_err());
+    }
+}
@@ -0,0 +1,144 @@
+use std::{ffi::OsStr, str::FromStr};
+
+use crate::{error::MagickError, wm_err};
+
+/// A region geometry as taken by `-crop` and similar options: `WxH{+-}X{+-}Y`,
+/// where any part may be missing and `%` makes the size relative to the image.
+///
+/// Unlike [super::Geometry], this keeps track of whether an offset was given at all,
+/// because `-crop 10x10` and `-crop 10x10+0+0` do different things.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CropGeometry {
+    pub width: Option<f64>,
+    pub height: Option<f64>,
+    /// The width and height are percentages of the image size
+    pub percent: bool,
+    pub offset: Option<(i64, i64)>,
+}
+
+impl CropGeometry {
+    /// Resolves the size against the size of the image.
+    /// Missing dimensions default to those of the image.
+    pub fn size(&self, image_width: u32, image_height: u32) -> (u32, u32) {
+        let (width, height) = self.dimensions(image_width, image_height);
+        (width.unwrap_or(image_width), height.unwrap_or(image_height))
+    }
+
+    /// Resolves percentages against the size of the image, keeping missing dimensions missing
+    pub fn dimensions(&self, image_width: u32, image_height: u32) -> (Option<u32>, Option<u32>) {
+        let resolve = |value: Option<f64>, full: u32| {
+            value.map(|value| match self.percent {
+                true => (f64::from(full) * value / 100.0).round() as u32,
+                false => value.round().min(f64::from(u32::MAX)) as u32,
+            })
+        };
+        (
+            resolve(self.width, image_width),
+            resolve(self.height, image_height),
+        )
+    }
+}
+
+impl FromStr for CropGeometry {
+    type Err = MagickError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || wm_err!("invalid geometry `{}'", s);
+        let percent = s.contains('%');
+        let trimmed: String = s.chars().filter(|&c| c != '%' && c != '!').collect();
+        let (size, offset) = match trimmed.find(['+', '-']) {
+            Some(start) => trimmed.split_at(start),
+            None => (trimmed.as_str(), ""),
+        };
+        let dimension = |value: &str| -> Result<Option<f64>, MagickError> {
+            if value.is_empty() {
+                return Ok(None);
+            }
+            match value.parse::<f64>() {
+                Ok(value) if value.is_finite() && value >= 0.0 => Ok(Some(value)),
+                _ => Err(invalid()),
+            }
+        };
+        let (width, height) = match size.split_once('x') {
+            Some((width, height)) => (dimension(width)?, dimension(height)?),
+            // a single number is used for both dimensions, like in imagemagick
+            None => (dimension(size)?, dimension(size)?),
+        };
+        let offset = if offset.is_empty() {
+            None
+        } else {
+            Some(parse_offset(offset).ok_or_else(invalid)?)
+        };
+        Ok(Self {
+            width,
+            height,
+            percent,
+            offset,
+        })
+    }
+}
+
+/// Offsets further than this from the origin place the region outside of any image,
+/// so they are clamped to it to keep the arithmetic on them from overflowing
+const MAX_OFFSET: f64 = (1u64 << 31) as f64;
+
+/// Parses `{+-}X{+-}Y`, where the Y part is optional
+fn parse_offset(s: &str) -> Option<(i64, i64)> {
+    let split = s[1..].find(['+', '-']).map(|i| i + 1).unwrap_or(s.len());
+    let (x, y) = s.split_at(split);
+    let number = |s: &str| -> Option<i64> {
+        let value: f64 = s.parse().ok()?;
+        value
+            .is_finite()
+            .then_some(value.round().clamp(-MAX_OFFSET, MAX_OFFSET) as i64)
+    };
+    let y = if y.is_empty() { 0 } else { number(y)? };
+    Some((number(x)?, y))
+}
+
+impl TryFrom<&OsStr> for CropGeometry {
+    type Error = MagickError;
+
+    fn try_from(s: &OsStr) -> Result<Self, Self::Error> {
+        match s.to_str() {
+            Some(s) => Self::from_str(s),
+            None => Err(wm_err!("invalid geometry `{}'", s.to_string_lossy())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            CropGeometry::from_str("10x20+0+0").unwrap(),
+            CropGeometry {
+                width: Some(10.0),
+                height: Some(20.0),
+                percent: false,
+                offset: Some((0, 0)),
+            }
+        );
+        assert_eq!(
+            CropGeometry::from_str("50%x25%-3+4").unwrap(),
+            CropGeometry {
+                width: Some(50.0),
+                height: Some(25.0),
+                percent: true,
+                offset: Some((-3, 4)),
+            }
+        );
+        let geometry = CropGeometry::from_str("50%").unwrap();
+        assert_eq!(geometry.size(100, 40), (50, 20));
+        let geometry = CropGeometry::from_str("30x").unwrap();
+        assert_eq!(geometry.size(100, 40), (30, 40));
+        assert_eq!(geometry.offset, None);
+        assert!(CropGeometry::from_str("10x10+a").is_err());
+        assert!(CropGeometry::from_str("-10x10").is_err());
+        let geometry = CropGeometry::from_str("10x10-1e30+1e30").unwrap();
+        assert_eq!(geometry.offset, Some((-(1 << 31), 1 << 31)));
+    }
+}
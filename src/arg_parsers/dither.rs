@@ -0,0 +1,201 @@
+use std::{ffi::OsStr, str::FromStr};
+
+use strum::{EnumString, IntoStaticStr, VariantArray};
+
+use crate::{error::MagickError, wm_err};
+
+/// Dithering methods for color reduction.
+///
+/// See <https://imagemagick.org/script/command-line-options.php#dither>
+#[derive(EnumString, IntoStaticStr, VariantArray, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[strum(ascii_case_insensitive)]
+pub enum DitherMethod {
+    /// Map every pixel to the closest color
+    None,
+    /// Error diffusion, which hides banding in gradients
+    #[default]
+    FloydSteinberg,
+    /// Error diffusion along a Hilbert curve, which avoids the directional artifacts of Floyd–Steinberg
+    Riemersma,
+}
+
+impl TryFrom<&OsStr> for DitherMethod {
+    type Error = MagickError;
+
+    fn try_from(s: &OsStr) -> Result<Self, Self::Error> {
+        let Some(method) = s.to_str().and_then(|s| DitherMethod::from_str(s).ok()) else {
+            return Err(wm_err!(
+                "unrecognized dither method `{}'",
+                s.to_string_lossy()
+            ));
+        };
+        Ok(method)
+    }
+}
+
+/// The threshold maps of `-ordered-dither`, with the names and values of imagemagick's `thresholds.xml`
+#[derive(EnumString, IntoStaticStr, VariantArray, Debug, Clone, Copy, PartialEq, Eq)]
+#[strum(ascii_case_insensitive)]
+pub enum ThresholdMap {
+    /// No dithering, like `-threshold 50%` for each level
+    #[strum(serialize = "threshold", serialize = "1x1")]
+    Threshold,
+    /// A checkerboard
+    #[strum(serialize = "checks", serialize = "2x1")]
+    Checks,
+    /// Bayer matrices, which give a regular cross-hatch pattern
+    #[strum(serialize = "o2x2", serialize = "2x2")]
+    O2x2,
+    #[strum(serialize = "o4x4", serialize = "4x4")]
+    O4x4,
+    #[strum(serialize = "o8x8", serialize = "8x8")]
+    O8x8,
+    /// Halftone: dots that grow with the darkness, at a 45° angle
+    #[strum(serialize = "h4x4a", serialize = "4x1")]
+    H4x4a,
+}
+
+impl ThresholdMap {
+    /// The width and height of the map, which is tiled over the image
+    pub fn size(self) -> usize {
+        match self {
+            ThresholdMap::Threshold => 1,
+            ThresholdMap::Checks | ThresholdMap::O2x2 => 2,
+            ThresholdMap::O4x4 | ThresholdMap::H4x4a => 4,
+            ThresholdMap::O8x8 => 8,
+        }
+    }
+
+    /// The thresholds are the levels divided by this
+    pub fn divisor(self) -> u32 {
+        match self {
+            ThresholdMap::Threshold => 2,
+            ThresholdMap::Checks => 3,
+            ThresholdMap::O2x2 => 5,
+            ThresholdMap::O4x4 => 17,
+            ThresholdMap::O8x8 => 65,
+            ThresholdMap::H4x4a => 9,
+        }
+    }
+
+    /// The threshold levels, row by row
+    pub fn levels(self) -> &'static [u32] {
+        match self {
+            ThresholdMap::Threshold => &[1],
+            ThresholdMap::Checks => &[1, 2, 2, 1],
+            ThresholdMap::O2x2 => &[1, 3, 4, 2],
+            ThresholdMap::O4x4 => &[1, 9, 3, 11, 13, 5, 15, 7, 4, 12, 2, 10, 16, 8, 14, 6],
+            #[rustfmt::skip]
+            ThresholdMap::O8x8 => &[
+                1, 49, 13, 61, 4, 52, 16, 64,
+                33, 17, 45, 29, 36, 20, 48, 32,
+                9, 57, 5, 53, 12, 60, 8, 56,
+                41, 25, 37, 21, 44, 28, 40, 24,
+                3, 51, 15, 63, 2, 50, 14, 62,
+                35, 19, 47, 31, 34, 18, 46, 30,
+                11, 59, 7, 55, 10, 58, 6, 54,
+                43, 27, 39, 23, 42, 26, 38, 22,
+            ],
+            ThresholdMap::H4x4a => &[4, 2, 7, 5, 3, 1, 8, 6, 7, 5, 4, 2, 8, 6, 3, 1],
+        }
+    }
+
+    /// The threshold level at the given pixel
+    pub fn level(self, x: u32, y: u32) -> u32 {
+        let size = self.size();
+        self.levels()[(y as usize % size) * size + x as usize % size]
+    }
+}
+
+/// The argument of `-ordered-dither`: a threshold map followed by the number of levels
+/// of the red, green, blue and alpha channels, such as `o4x4,8,8,4`.
+///
+/// Every channel has 2 levels by default, and a single number applies to all channels.
+/// Channels without a number of their own reuse the last one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OrderedDither {
+    pub map: ThresholdMap,
+    pub levels: [u32; 4],
+}
+
+impl FromStr for OrderedDither {
+    type Err = MagickError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(',').map(str::trim);
+        let name = parts.next().unwrap_or_default();
+        let map = ThresholdMap::from_str(name)
+            .map_err(|_| wm_err!("invalid dither threshold map `{}'", name))?;
+        let invalid = || wm_err!("invalid argument for option `-ordered-dither': {}", s);
+        let given = parts
+            .map(|part| part.parse().ok().filter(|&levels| levels >= 2))
+            .collect::<Option<Vec<u32>>>()
+            .ok_or_else(invalid)?;
+        if given.len() > 4 {
+            return Err(invalid());
+        }
+        let levels = std::array::from_fn(|i| given.get(i).or(given.last()).copied().unwrap_or(2));
+        Ok(Self { map, levels })
+    }
+}
+
+impl TryFrom<&OsStr> for OrderedDither {
+    type Error = MagickError;
+
+    fn try_from(s: &OsStr) -> Result<Self, Self::Error> {
+        match s.to_str() {
+            Some(s) => Self::from_str(s),
+            None => Err(wm_err!(
+                "invalid argument for option `-ordered-dither': {}",
+                s.to_string_lossy()
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            DitherMethod::try_from(OsStr::new("floydsteinberg")).unwrap(),
+            DitherMethod::FloydSteinberg
+        );
+        assert_eq!(
+            DitherMethod::try_from(OsStr::new("None")).unwrap(),
+            DitherMethod::None
+        );
+        assert_eq!(
+            DitherMethod::try_from(OsStr::new("riemersma")).unwrap(),
+            DitherMethod::Riemersma
+        );
+        assert!(DitherMethod::try_from(OsStr::new("random")).is_err());
+    }
+
+    #[test]
+    fn threshold_maps() {
+        for &map in ThresholdMap::VARIANTS {
+            assert_eq!(map.levels().len(), map.size() * map.size());
+            assert!(map.levels().iter().all(|&level| level < map.divisor()));
+        }
+    }
+
+    #[test]
+    fn parse_ordered_dither() {
+        let parse = |s| OrderedDither::from_str(s);
+        assert_eq!(
+            parse("O4x4").unwrap(),
+            OrderedDither {
+                map: ThresholdMap::O4x4,
+                levels: [2; 4]
+            }
+        );
+        assert_eq!(parse("checks,6").unwrap().levels, [6; 4]);
+        assert_eq!(parse("8x8,8,8,4").unwrap().levels, [8, 8, 4, 4]);
+        assert!(parse("o4x4,1").is_err());
+        assert!(parse("o4x4,2,2,2,2,2").is_err());
+        assert!(parse("random").is_err());
+    }
+}
@@ -0,0 +1,97 @@
+use std::{ffi::OsStr, str::FromStr};
+
+use strum::EnumString;
+
+use crate::{error::MagickError, wm_err};
+
+/// The operator of `-evaluate`, which combines every sample with a constant
+///
+/// See <https://imagemagick.org/script/command-line-options.php#evaluate>
+#[derive(EnumString, Debug, Clone, Copy, PartialEq, Eq)]
+#[strum(ascii_case_insensitive)]
+pub enum EvaluateOperator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    /// Raises the normalized sample to the power of the constant
+    Pow,
+    /// Replaces the sample with the constant
+    Set,
+    Max,
+    Min,
+    /// Logarithmic curve that keeps 0 and 1 in place, steeper for larger constants
+    Log,
+}
+
+impl EvaluateOperator {
+    /// Whether the constant is a sample value, given in quantum units or as a percentage,
+    /// rather than a plain factor or exponent
+    pub fn takes_sample_value(self) -> bool {
+        use EvaluateOperator::*;
+        matches!(self, Add | Subtract | Set | Max | Min)
+    }
+
+    /// Computes the new value of a sample normalized to `[0, 1]`.
+    /// Sample value constants must be normalized the same way.
+    pub fn apply(self, sample: f64, constant: f64) -> f64 {
+        match self {
+            EvaluateOperator::Add => sample + constant,
+            EvaluateOperator::Subtract => sample - constant,
+            EvaluateOperator::Multiply => sample * constant,
+            // imagemagick divides by a tiny number instead of zero
+            EvaluateOperator::Divide => match constant.abs() < 1e-12 {
+                true => sample / 1e-12_f64.copysign(constant),
+                false => sample / constant,
+            },
+            EvaluateOperator::Pow => sample.abs().powf(constant).copysign(sample),
+            EvaluateOperator::Set => constant,
+            EvaluateOperator::Max => sample.max(constant),
+            EvaluateOperator::Min => sample.min(constant),
+            EvaluateOperator::Log => match constant.abs() < 1e-12 {
+                true => sample,
+                false => (constant * sample + 1.0).ln() / (constant + 1.0).ln(),
+            },
+        }
+    }
+}
+
+impl TryFrom<&OsStr> for EvaluateOperator {
+    type Error = MagickError;
+
+    fn try_from(s: &OsStr) -> Result<Self, Self::Error> {
+        let Some(operator) = s.to_str().and_then(|s| Self::from_str(s).ok()) else {
+            return Err(wm_err!(
+                "unrecognized evaluate operator `{}'",
+                s.to_string_lossy()
+            ));
+        };
+        Ok(operator)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let parse = |s| EvaluateOperator::try_from(OsStr::new(s));
+        assert_eq!(parse("multiply").unwrap(), EvaluateOperator::Multiply);
+        assert_eq!(parse("POW").unwrap(), EvaluateOperator::Pow);
+        assert!(parse("modulo").is_err());
+    }
+
+    #[test]
+    fn apply() {
+        use EvaluateOperator::*;
+        assert_eq!(Multiply.apply(0.5, 0.4), 0.2);
+        assert_eq!(Subtract.apply(0.5, 0.25), 0.25);
+        assert_eq!(Pow.apply(0.25, 0.5), 0.5);
+        assert_eq!(Max.apply(0.25, 0.5), 0.5);
+        assert_eq!(Divide.apply(0.0, 0.0), 0.0);
+        assert!(Divide.apply(0.5, 0.0) > 1.0);
+        assert_eq!(Log.apply(1.0, 10.0), 1.0);
+        assert!(Log.apply(0.5, 10.0) > 0.5);
+    }
+}
@@ -6,7 +6,7 @@ use std::{
 
 use crate::{error::MagickError, wm_err};
 
-use super::{Geometry, ResizeGeometry};
+use super::{CropGeometry, ResizeGeometry};
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct InputFileArg {
@@ -15,9 +15,34 @@ pub struct InputFileArg {
     pub read_mod: Option<ReadModifier>,
 }
 
-impl InputFileArg {
-    pub fn new(path: PathBuf) -> Self {
-        todo!()
+impl TryFrom<&OsStr> for InputFileArg {
+    type Error = MagickError;
+
+    /// Splits off the read modifier in square brackets, e.g. `anim.gif[0-2]`.
+    ///
+    /// Like imagemagick, a file that exists under the full name is opened as-is,
+    /// so files with brackets in their names can still be read.
+    fn try_from(arg: &OsStr) -> Result<Self, Self::Error> {
+        let plain = Self {
+            path: PathBuf::from(arg),
+            read_mod: None,
+        };
+        if file_exists(Path::new(arg)) {
+            return Ok(plain);
+        }
+        // Read modifiers are ASCII, but non-UTF-8 filenames cannot be split without `unsafe`,
+        // so they are not recognized on such filenames
+        let Some(string) = arg.to_str() else {
+            return Ok(plain);
+        };
+        let Some((path, modifier)) = string.strip_suffix(']').and_then(|s| s.rsplit_once('['))
+        else {
+            return Ok(plain);
+        };
+        Ok(Self {
+            path: PathBuf::from(path),
+            read_mod: Some(ReadModifier::from_str(modifier)?),
+        })
     }
 }
 
@@ -26,11 +51,11 @@ impl InputFileArg {
 ///
 /// See <https://imagemagick.org/Usage/files/#read_mods> for details.
 /// I've also verified it behaves according to the documentation.
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum ReadModifier {
     Resize(ResizeGeometry),
     Crop(LoadCropGeometry),
-    // TODO: frame selection.
+    FrameSelect(FrameSelection),
 }
 
 impl FromStr for ReadModifier {
@@ -50,6 +75,13 @@ impl TryFrom<&OsStr> for ReadModifier {
         }
 
         let ascii = s.as_encoded_bytes();
+        if !ascii.is_empty()
+            && ascii
+                .iter()
+                .all(|c| c.is_ascii_digit() || b",- ".contains(c))
+        {
+            return Ok(Self::FrameSelect(FrameSelection::try_from(s)?));
+        }
         let x_count = ascii.iter().copied().filter(|c| *c == b'x').take(2).count();
         let plus_count = ascii.iter().copied().filter(|c| *c == b'+').take(3).count();
 
@@ -58,8 +90,95 @@ impl TryFrom<&OsStr> for ReadModifier {
         } else if x_count == 1 && plus_count == 2 {
             Ok(Self::Crop(LoadCropGeometry::try_from(s)?))
         } else {
-            Err(wm_err!("frame selection is not yet supported")) // TODO: support [0] at least
+            Err(wm_err!("invalid read modifier: {}", s.to_string_lossy()))
+        }
+    }
+}
+
+/// Frames of a multi-frame image to read, e.g. `[0]`, `[1-3]`, `[-1]` or `[0,2,4-5]`.
+///
+/// Negative indices count from the end, so `-1` is the last frame.
+/// Ranges can go backwards, in which case the frames are read in reverse order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameSelection {
+    /// Inclusive ranges of frame indices, in the order they were specified
+    ranges: Vec<(i64, i64)>,
+}
+
+impl FrameSelection {
+    /// Returns the indices of the selected frames for an image with the given number of frames,
+    /// in the order they were requested. Indices past the end are skipped, like imagemagick does.
+    pub fn resolve(&self, frame_count: usize) -> Vec<usize> {
+        let count = frame_count as i64;
+        let absolute = |i: i64| if i < 0 { count + i } else { i };
+        let in_range = |i: &i64| (0..count).contains(i);
+        let mut frames = Vec::new();
+        for &(start, end) in &self.ranges {
+            let (start, end) = (absolute(start), absolute(end));
+            if start <= end {
+                frames.extend((start..=end).filter(in_range).map(|i| i as usize));
+            } else {
+                frames.extend((end..=start).rev().filter(in_range).map(|i| i as usize));
+            }
+        }
+        frames
+    }
+
+    /// The highest index that can be selected, if it doesn't depend on the number of frames
+    pub fn max_index(&self) -> Option<usize> {
+        let mut max = 0;
+        for &(start, end) in &self.ranges {
+            if start < 0 || end < 0 {
+                return None;
+            }
+            max = max.max(start).max(end);
+        }
+        Some(max as usize)
+    }
+
+    /// Upper bound on the number of selected frames
+    pub fn max_count(&self) -> usize {
+        self.ranges
+            .iter()
+            .map(|&(start, end)| start.abs_diff(end) as usize + 1)
+            .sum()
+    }
+}
+
+impl FromStr for FrameSelection {
+    type Err = MagickError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut ranges = Vec::new();
+        for part in s.split(',') {
+            let part = part.trim();
+            // the first character may be the sign of a negative index, so skip it when looking for the range separator
+            let separator = part
+                .char_indices()
+                .skip(1)
+                .find(|&(_, c)| c == '-')
+                .map(|(i, _)| i);
+            let (start, end) = match separator {
+                Some(i) => (&part[..i], &part[i + 1..]),
+                None => (part, part),
+            };
+            let (Ok(start), Ok(end)) = (start.trim().parse(), end.trim().parse()) else {
+                return Err(wm_err!("invalid frame selection: {}", s));
+            };
+            ranges.push((start, end));
         }
+        Ok(Self { ranges })
+    }
+}
+
+impl TryFrom<&OsStr> for FrameSelection {
+    type Error = MagickError;
+
+    fn try_from(s: &OsStr) -> Result<Self, Self::Error> {
+        let Some(s) = s.to_str() else {
+            return Err(wm_err!("invalid frame selection: {}", s.to_string_lossy()));
+        };
+        Self::from_str(s)
     }
 }
 
@@ -86,23 +205,19 @@ impl TryFrom<&OsStr> for LoadCropGeometry {
     type Error = MagickError;
 
     fn try_from(s: &OsStr) -> Result<Self, Self::Error> {
-        let geom = Geometry::try_from(s)?;
-
-        let convert_field = |field: Option<f64>| -> Result<u32, MagickError> {
-            let f =
-                field.ok_or_else(|| wm_err!("invalid crop geometry: {}", s.to_string_lossy()))?;
-            if f.is_sign_negative() {
-                Err(wm_err!("invalid crop geometry: {}", s.to_string_lossy()))
-            } else {
-                Ok(f as u32)
-            }
+        let invalid = || wm_err!("invalid crop geometry: {}", s.to_string_lossy());
+        let geom = CropGeometry::try_from(s)?;
+        let (Some(width), Some(height), Some((xoffset, yoffset)), false) =
+            (geom.width, geom.height, geom.offset, geom.percent)
+        else {
+            return Err(invalid());
         };
-
+        let offset = |value: i64| u32::try_from(value).map_err(|_| invalid());
         Ok(Self {
-            width: convert_field(geom.width)?,
-            height: convert_field(geom.height)?,
-            xoffset: convert_field(geom.xoffset)?,
-            yoffset: convert_field(geom.yoffset)?,
+            width: width as u32,
+            height: height as u32,
+            xoffset: offset(xoffset)?,
+            yoffset: offset(yoffset)?,
         })
     }
 }
@@ -129,6 +244,8 @@ mod tests {
         };
         let parsed = LoadCropGeometry::from_str("1x2+3+4").unwrap();
         assert_eq!(expected, parsed);
+        let parsed = LoadCropGeometry::from_str("1x2+0+0").unwrap();
+        assert_eq!((parsed.xoffset, parsed.yoffset), (0, 0));
     }
 
     #[test]
@@ -139,6 +256,42 @@ mod tests {
         assert_eq!(expected, parsed);
     }
 
+    #[test]
+    fn frame_selection() {
+        let select = |s: &str, count| FrameSelection::from_str(s).unwrap().resolve(count);
+        assert_eq!(select("0", 3), vec![0]);
+        assert_eq!(select("1-3", 10), vec![1, 2, 3]);
+        assert_eq!(select("-1", 5), vec![4]);
+        assert_eq!(select("0,2,4", 5), vec![0, 2, 4]);
+        assert_eq!(select("3-1", 5), vec![3, 2, 1]);
+        assert_eq!(select("-2--1", 5), vec![3, 4]);
+        // out-of-range frames are skipped
+        assert_eq!(select("0-5", 3), vec![0, 1, 2]);
+        assert!(FrameSelection::from_str("1-").is_err());
+    }
+
+    #[test]
+    fn frame_selection_bounds() {
+        let selection = FrameSelection::from_str("0-5,7").unwrap();
+        assert_eq!(selection.max_index(), Some(7));
+        assert_eq!(selection.max_count(), 7);
+        assert_eq!(FrameSelection::from_str("-1").unwrap().max_index(), None);
+    }
+
+    #[test]
+    fn input_file_arg() {
+        let arg = InputFileArg::try_from(OsStr::new("anim.gif[0-2]")).unwrap();
+        assert_eq!(arg.path, PathBuf::from("anim.gif"));
+        assert_eq!(
+            arg.read_mod,
+            Some(ReadModifier::FrameSelect(
+                FrameSelection::from_str("0-2").unwrap()
+            ))
+        );
+        let arg = InputFileArg::try_from(OsStr::new("plain.png")).unwrap();
+        assert_eq!(arg.read_mod, None);
+    }
+
     #[test]
     fn load_resize_read_modifier() {
         // only a basic smoke test because the underlying geometry parser is well tested already
@@ -0,0 +1,57 @@
+use std::{ffi::OsStr, str::FromStr};
+
+use crate::{error::MagickError, image::QUANTUM_RANGE, wm_err};
+
+/// The argument of `-fuzz`: how far apart two colors can be and still count as the same.
+///
+/// Given either as a percentage of the maximum value, e.g. `5%`,
+/// or as an absolute distance in quantum units, e.g. `3000`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Fuzz {
+    /// The distance normalized to `[0, 1]`
+    pub distance: f64,
+}
+
+impl FromStr for Fuzz {
+    type Err = MagickError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || wm_err!("invalid argument for option `-fuzz': {}", s);
+        let s = s.trim();
+        let distance = match s.strip_suffix('%') {
+            Some(percent) => percent.parse::<f64>().map_err(|_| invalid())? / 100.0,
+            None => s.parse::<f64>().map_err(|_| invalid())? / QUANTUM_RANGE,
+        };
+        if !distance.is_finite() || distance < 0.0 {
+            return Err(invalid());
+        }
+        Ok(Self { distance })
+    }
+}
+
+impl TryFrom<&OsStr> for Fuzz {
+    type Error = MagickError;
+
+    fn try_from(s: &OsStr) -> Result<Self, Self::Error> {
+        match s.to_str() {
+            Some(s) => Self::from_str(s),
+            None => Err(wm_err!(
+                "invalid argument for option `-fuzz': {}",
+                s.to_string_lossy()
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!(Fuzz::from_str("5%").unwrap().distance, 0.05);
+        assert_eq!(Fuzz::from_str("65535").unwrap().distance, 1.0);
+        assert!(Fuzz::from_str("-1").is_err());
+        assert!(Fuzz::from_str("five%").is_err());
+    }
+}
@@ -85,7 +85,7 @@ impl TryFrom<&OsStr> for Geometry {
         let mut ascii = s.as_encoded_bytes();
         let mut result = Geometry::default();
 
-        if ascii.len() == 0 {
+        if ascii.is_empty() {
             // emptry string yields empty geometry
             return Ok(result);
         }
@@ -119,6 +119,9 @@ impl TryFrom<&OsStr> for Geometry {
                 }
             }
         }
+        if !ascii.is_empty() {
+            return Err(invalid_geometry_err());
+        }
 
         Ok(result)
     }
@@ -148,7 +151,8 @@ fn read_float(input: &mut &[u8], allow_sign: bool) -> Option<f64> {
     }
 
     let (number, remainder) = input.split_at(count);
-    let float = str::from_utf8(number).unwrap().parse::<f64>().unwrap();
+    // There may be no digits at all, e.g. in `x` or `+`
+    let float = str::from_utf8(number).unwrap().parse::<f64>().ok()?;
     *input = remainder;
     Some(float)
 }
@@ -205,6 +209,13 @@ mod tests {
         assert_eq!(orig, parsed)
     }
 
+    #[test]
+    fn test_invalid() {
+        for invalid in ["big", "x", "10x", "10x20junk", "10+"] {
+            assert!(Geometry::from_str(invalid).is_err(), "{invalid}");
+        }
+    }
+
     #[quickcheck]
     fn roundtrip_is_lossless(orig: Geometry) {
         let stringified = orig.to_string();
@@ -0,0 +1,109 @@
+use std::{ffi::OsStr, str::FromStr};
+
+use strum::{EnumString, IntoStaticStr, VariantArray};
+
+use crate::{error::MagickError, wm_err};
+
+/// The argument of `-gravity`: which part of the image geometry offsets are relative to.
+///
+/// See <https://imagemagick.org/script/command-line-options.php#gravity>
+#[derive(EnumString, IntoStaticStr, VariantArray, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[strum(ascii_case_insensitive)]
+pub enum Gravity {
+    /// Same as `NorthWest`, the default
+    #[default]
+    None,
+    /// Same as `NorthWest`
+    Forget,
+    NorthWest,
+    North,
+    NorthEast,
+    West,
+    Center,
+    East,
+    SouthWest,
+    South,
+    SouthEast,
+}
+
+impl Gravity {
+    /// Places an object of the given size inside a container, like imagemagick's `GravityAdjustGeometry`.
+    ///
+    /// The offset moves the object away from the edge the gravity points to,
+    /// e.g. with `SouthEast` a positive offset moves it up and to the left.
+    /// Returns the position of the top left corner of the object within the container.
+    pub fn position(
+        self,
+        (container_width, container_height): (u32, u32),
+        (width, height): (u32, u32),
+        (x, y): (i64, i64),
+    ) -> (i64, i64) {
+        let free_x = i64::from(container_width) - i64::from(width);
+        let free_y = i64::from(container_height) - i64::from(height);
+        let x = match self {
+            Gravity::North | Gravity::Center | Gravity::South => x.saturating_add(free_x / 2),
+            Gravity::NorthEast | Gravity::East | Gravity::SouthEast => free_x.saturating_sub(x),
+            _ => x,
+        };
+        let y = match self {
+            Gravity::West | Gravity::Center | Gravity::East => y.saturating_add(free_y / 2),
+            Gravity::SouthWest | Gravity::South | Gravity::SouthEast => free_y.saturating_sub(y),
+            _ => y,
+        };
+        (x, y)
+    }
+}
+
+impl TryFrom<&OsStr> for Gravity {
+    type Error = MagickError;
+
+    fn try_from(s: &OsStr) -> Result<Self, Self::Error> {
+        let Some(gravity) = s.to_str().and_then(|s| Gravity::from_str(s).ok()) else {
+            return Err(wm_err!(
+                "unrecognized gravity type `{}'",
+                s.to_string_lossy()
+            ));
+        };
+        Ok(gravity)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            Gravity::try_from(OsStr::new("southeast")).unwrap(),
+            Gravity::SouthEast
+        );
+        assert_eq!(
+            Gravity::try_from(OsStr::new("Center")).unwrap(),
+            Gravity::Center
+        );
+        assert!(Gravity::try_from(OsStr::new("up")).is_err());
+    }
+
+    #[test]
+    fn offsets_point_away_from_the_edge() {
+        let container = (100, 50);
+        let object = (10, 20);
+        assert_eq!(Gravity::None.position(container, object, (5, 5)), (5, 5));
+        assert_eq!(
+            Gravity::Center.position(container, object, (5, 5)),
+            (50, 20)
+        );
+        assert_eq!(
+            Gravity::SouthEast.position(container, object, (5, 5)),
+            (85, 25)
+        );
+        assert_eq!(Gravity::North.position(container, object, (0, 0)), (45, 0));
+        assert_eq!(Gravity::West.position(container, object, (0, 0)), (0, 15));
+        // untrusted offsets saturate instead of overflowing
+        assert_eq!(
+            Gravity::SouthEast.position(container, object, (i64::MIN, i64::MIN)),
+            (i64::MAX, i64::MAX)
+        );
+    }
+}
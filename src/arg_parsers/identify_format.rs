@@ -0,0 +1,147 @@
+use std::{ffi::OsStr, str::FromStr};
+
+use crate::{error::MagickError, wm_err};
+
+/// Parsed `-format` template, e.g. `%f %wx%h\n`.
+///
+/// See <https://imagemagick.org/script/escape.php> for the full list of escapes.
+/// Like in imagemagick, `%%` is a literal percent sign, and a backslash turns `n`, `r` and `t`
+/// into a newline, a carriage return and a tab and makes any other character literal, e.g. `\\`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct IdentifyFormat {
+    pub template: Vec<Token>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Token {
+    /// Text that is printed as-is
+    Literal(String),
+    /// Single-letter escape such as `%w`
+    Escape(char),
+    /// Long-form escape such as `%[mean]`, stored without the brackets
+    Property(String),
+}
+
+impl FromStr for IdentifyFormat {
+    type Err = MagickError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut template = Vec::new();
+        let mut literal = String::new();
+        let mut chars = s.chars().peekable();
+        while let Some(c) = chars.next() {
+            if c == '\\' {
+                match chars.next() {
+                    Some('n') => literal.push('\n'),
+                    Some('r') => literal.push('\r'),
+                    Some('t') => literal.push('\t'),
+                    Some(other) => literal.push(other),
+                    // a trailing backslash is printed as-is
+                    None => literal.push('\\'),
+                }
+                continue;
+            }
+            if c != '%' {
+                literal.push(c);
+                continue;
+            }
+            match chars.next() {
+                // a trailing % is printed as-is
+                None => literal.push('%'),
+                Some('%') => literal.push('%'),
+                Some('[') => {
+                    // Properties may contain brackets themselves, e.g. in fx expressions, so track nesting
+                    let mut depth = 1;
+                    let mut property = String::new();
+                    for c in chars.by_ref() {
+                        match c {
+                            '[' => depth += 1,
+                            ']' => depth -= 1,
+                            _ => (),
+                        }
+                        if depth == 0 {
+                            break;
+                        }
+                        property.push(c);
+                    }
+                    if depth != 0 {
+                        return Err(wm_err!("unterminated escape in format: {}", s));
+                    }
+                    flush_literal(&mut literal, &mut template);
+                    template.push(Token::Property(property));
+                }
+                Some(escape) => {
+                    flush_literal(&mut literal, &mut template);
+                    template.push(Token::Escape(escape));
+                }
+            }
+        }
+        flush_literal(&mut literal, &mut template);
+        Ok(Self { template })
+    }
+}
+
+impl TryFrom<&OsStr> for IdentifyFormat {
+    type Error = MagickError;
+
+    fn try_from(s: &OsStr) -> Result<Self, Self::Error> {
+        // Lossy conversion is fine because the non-UTF-8 parts can only be literals
+        Self::from_str(&s.to_string_lossy())
+    }
+}
+
+fn flush_literal(literal: &mut String, template: &mut Vec<Token>) {
+    if !literal.is_empty() {
+        template.push(Token::Literal(std::mem::take(literal)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_and_literals() {
+        let parsed = IdentifyFormat::from_str("%f: %wx%h").unwrap();
+        let expected = vec![
+            Token::Escape('f'),
+            Token::Literal(": ".to_owned()),
+            Token::Escape('w'),
+            Token::Literal("x".to_owned()),
+            Token::Escape('h'),
+        ];
+        assert_eq!(parsed.template, expected);
+    }
+
+    #[test]
+    fn properties() {
+        let parsed = IdentifyFormat::from_str("%[mean] %[fx:w/h]").unwrap();
+        let expected = vec![
+            Token::Property("mean".to_owned()),
+            Token::Literal(" ".to_owned()),
+            Token::Property("fx:w/h".to_owned()),
+        ];
+        assert_eq!(parsed.template, expected);
+    }
+
+    #[test]
+    fn nested_brackets() {
+        let parsed = IdentifyFormat::from_str("%[fx:u[0].r]").unwrap();
+        assert_eq!(
+            parsed.template,
+            vec![Token::Property("fx:u[0].r".to_owned())]
+        );
+    }
+
+    #[test]
+    fn backslash_and_percent_escapes() {
+        let parsed = IdentifyFormat::from_str("%w%%\\t\\n\\\\\\%h\\").unwrap();
+        let expected = vec![Token::Escape('w'), Token::Literal("%\t\n\\%h\\".to_owned())];
+        assert_eq!(parsed.template, expected);
+    }
+
+    #[test]
+    fn unterminated_property() {
+        assert!(IdentifyFormat::from_str("%[mean").is_err());
+    }
+}
@@ -0,0 +1,66 @@
+use std::{ffi::OsStr, str::FromStr};
+
+use strum::{EnumString, IntoStaticStr, VariantArray};
+
+use crate::{error::MagickError, wm_err};
+
+/// The argument of `-interpolate`: how operations that move pixels by fractional amounts,
+/// such as `-rotate` and `-shear`, compute the color between pixel centers.
+/// `-resize` uses `-filter` instead.
+///
+/// See <https://imagemagick.org/script/command-line-options.php#interpolate>
+#[derive(EnumString, IntoStaticStr, VariantArray, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[strum(ascii_case_insensitive)]
+pub enum InterpolateMethod {
+    /// The mean of the 2x2 pixels around the point
+    #[strum(serialize = "Average", serialize = "Average4")]
+    Average,
+    /// The mean of the 3x3 pixels around the nearest one
+    Average9,
+    /// The mean of the 4x4 pixels around the point
+    Average16,
+    #[default]
+    Bilinear,
+    /// Catmull-Rom cubic, which keeps the image sharper than bilinear but can overshoot at edges
+    #[strum(serialize = "Catrom", serialize = "Bicubic")]
+    Catrom,
+    /// The pixel up and to the left of the point
+    Integer,
+    /// The pixel the point is in
+    #[strum(serialize = "Nearest", serialize = "NearestNeighbor")]
+    Nearest,
+    /// Cubic B-spline, which is smooth but blurry
+    Spline,
+}
+
+impl TryFrom<&OsStr> for InterpolateMethod {
+    type Error = MagickError;
+
+    fn try_from(s: &OsStr) -> Result<Self, Self::Error> {
+        let Some(method) = s.to_str().and_then(|s| InterpolateMethod::from_str(s).ok()) else {
+            return Err(wm_err!(
+                "unrecognized interpolate method `{}'",
+                s.to_string_lossy()
+            ));
+        };
+        Ok(method)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let parse = |s: &str| InterpolateMethod::try_from(OsStr::new(s));
+        assert_eq!(parse("bilinear").unwrap(), InterpolateMethod::Bilinear);
+        assert_eq!(parse("Average4").unwrap(), InterpolateMethod::Average);
+        assert_eq!(parse("bicubic").unwrap(), InterpolateMethod::Catrom);
+        assert_eq!(
+            parse("NearestNeighbor").unwrap(),
+            InterpolateMethod::Nearest
+        );
+        assert!(parse("sharpest").is_err());
+    }
+}
@@ -0,0 +1,119 @@
+use std::{ffi::OsStr, str::FromStr};
+
+use strum::{EnumString, IntoStaticStr};
+
+use crate::{error::MagickError, wm_err};
+
+/// Resource types accepted by `-limit` and the corresponding `MAGICK_*_LIMIT` environment variables.
+///
+/// See <https://imagemagick.org/script/command-line-options.php#limit>
+#[derive(EnumString, IntoStaticStr, Debug, Clone, Copy, PartialEq, Eq)]
+#[strum(serialize_all = "lowercase", ascii_case_insensitive)]
+pub enum ResourceType {
+    Area,
+    Disk,
+    Height,
+    Memory,
+    Thread,
+    Time,
+    Width,
+}
+
+impl ResourceType {
+    /// Name of the environment variable that sets this limit, e.g. `MAGICK_MEMORY_LIMIT`
+    pub fn env_var(&self) -> String {
+        let name: &'static str = self.into();
+        format!("MAGICK_{}_LIMIT", name.to_ascii_uppercase())
+    }
+}
+
+impl TryFrom<&OsStr> for ResourceType {
+    type Error = MagickError;
+
+    fn try_from(s: &OsStr) -> Result<Self, Self::Error> {
+        s.to_str()
+            .and_then(|s| ResourceType::from_str(s).ok())
+            .ok_or_else(|| wm_err!("unrecognized resource type `{}'", s.to_string_lossy()))
+    }
+}
+
+/// The value of a resource limit. `None` means `unlimited`.
+///
+/// Accepts plain numbers as well as numbers with SI prefixes such as `256MB` or `16KP`.
+/// A prefix followed by `i` is binary, so `2GiB` is 2*1024^3 while `2GB` is 2*1000^3.
+/// The trailing unit (`B` for bytes or `P` for pixels) is optional and ignored.
+pub fn parse_resource_value(s: &OsStr) -> Result<Option<u64>, MagickError> {
+    let invalid_value_err = || wm_err!("invalid resource limit: {}", s.to_string_lossy());
+    let string = s.to_str().ok_or_else(invalid_value_err)?;
+    if string.eq_ignore_ascii_case("unlimited") {
+        return Ok(None);
+    }
+
+    let digits_end = string
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(string.len());
+    let (number, mut suffix) = string.split_at(digits_end);
+    let number: f64 = number.parse().map_err(|_| invalid_value_err())?;
+
+    if let Some(stripped) = suffix.strip_suffix(['B', 'b', 'P', 'p']) {
+        suffix = stripped;
+    }
+    let (prefix, binary) = match suffix.strip_suffix('i') {
+        Some(prefix) => (prefix, true),
+        None => (suffix, false),
+    };
+    let exponent = match prefix {
+        "" => 0,
+        "K" | "k" => 1,
+        "M" => 2,
+        "G" => 3,
+        "T" => 4,
+        "P" => 5,
+        "E" => 6,
+        _ => return Err(invalid_value_err()),
+    };
+    let base: f64 = if binary { 1024.0 } else { 1000.0 };
+    Ok(Some((number * base.powi(exponent)) as u64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> Option<u64> {
+        parse_resource_value(OsStr::new(s)).unwrap()
+    }
+
+    #[test]
+    fn plain_number() {
+        assert_eq!(parse("4"), Some(4));
+    }
+
+    #[test]
+    fn si_prefixes() {
+        assert_eq!(parse("256MB"), Some(256_000_000));
+        assert_eq!(parse("2GiB"), Some(2 * 1024 * 1024 * 1024));
+        assert_eq!(parse("16KP"), Some(16_000));
+        assert_eq!(parse("1.5Ki"), Some(1536));
+    }
+
+    #[test]
+    fn unlimited() {
+        assert_eq!(parse("unlimited"), None);
+    }
+
+    #[test]
+    fn garbage() {
+        assert!(parse_resource_value(OsStr::new("12XB")).is_err());
+        assert!(parse_resource_value(OsStr::new("MB")).is_err());
+    }
+
+    #[test]
+    fn resource_names() {
+        assert_eq!(
+            ResourceType::try_from(OsStr::new("Memory")).unwrap(),
+            ResourceType::Memory
+        );
+        assert_eq!(ResourceType::Thread.env_var(), "MAGICK_THREAD_LIMIT");
+    }
+}
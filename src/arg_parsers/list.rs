@@ -0,0 +1,40 @@
+use std::{ffi::OsStr, str::FromStr};
+
+use strum::{EnumString, IntoStaticStr, VariantArray};
+
+use crate::{error::MagickError, wm_err};
+
+/// The argument of `-list`: what to print instead of processing images.
+/// Imagemagick supports many more lists than we do.
+///
+/// See <https://imagemagick.org/script/command-line-options.php#list>
+#[derive(EnumString, IntoStaticStr, VariantArray, Debug, Clone, Copy, PartialEq, Eq)]
+#[strum(ascii_case_insensitive)]
+pub enum ListType {
+    /// The fonts installed on the system, which `-font` accepts by name
+    Font,
+}
+
+impl TryFrom<&OsStr> for ListType {
+    type Error = MagickError;
+
+    fn try_from(s: &OsStr) -> Result<Self, Self::Error> {
+        let Some(list) = s.to_str().and_then(|s| ListType::from_str(s).ok()) else {
+            return Err(wm_err!("unrecognized list type `{}'", s.to_string_lossy()));
+        };
+        Ok(list)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let parse = |s: &str| ListType::try_from(OsStr::new(s));
+        assert_eq!(parse("font").unwrap(), ListType::Font);
+        assert_eq!(parse("Font").unwrap(), ListType::Font);
+        assert!(parse("fonts").is_err());
+    }
+}
@@ -0,0 +1,66 @@
+use std::{ffi::OsStr, str::FromStr};
+
+use strum::{EnumString, IntoStaticStr, VariantArray};
+
+use crate::{error::MagickError, wm_err};
+
+/// Metrics accepted by `compare -metric`.
+///
+/// See <https://imagemagick.org/script/command-line-options.php#metric>
+#[derive(EnumString, IntoStaticStr, VariantArray, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[strum(serialize_all = "UPPERCASE", ascii_case_insensitive)]
+pub enum Metric {
+    /// Absolute error count, the number of different pixels
+    Ae,
+    /// Mean absolute error
+    Mae,
+    /// Mean squared error
+    Mse,
+    /// Normalized cross correlation
+    Ncc,
+    /// Perceptual hash
+    Phash,
+    /// Peak signal to noise ratio
+    Psnr,
+    /// Root mean squared error, the default in imagemagick
+    #[default]
+    Rmse,
+    /// Structural similarity index
+    Ssim,
+}
+
+impl Metric {
+    /// Metrics that measure similarity rather than distortion, i.e. are highest for identical images
+    pub fn is_similarity(&self) -> bool {
+        matches!(self, Metric::Ncc | Metric::Psnr | Metric::Ssim)
+    }
+}
+
+impl TryFrom<&OsStr> for Metric {
+    type Error = MagickError;
+
+    fn try_from(s: &OsStr) -> Result<Self, Self::Error> {
+        let Some(metric) = s.to_str().and_then(|s| Metric::from_str(s).ok()) else {
+            return Err(wm_err!(
+                "unrecognized metric type `{}'",
+                s.to_string_lossy()
+            ));
+        };
+        Ok(metric)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn case_insensitive() {
+        assert_eq!(Metric::try_from(OsStr::new("ssim")).unwrap(), Metric::Ssim);
+        assert_eq!(
+            Metric::try_from(OsStr::new("PHash")).unwrap(),
+            Metric::Phash
+        );
+        assert!(Metric::try_from(OsStr::new("foo")).is_err());
+    }
+}
@@ -3,7 +3,66 @@
 
 mod resize;
 pub use resize::*;
+// The plain geometry that options such as `CropGeometry` are parsed into and then validated
 mod geometry;
 pub use geometry::*;
 mod filename;
 pub use filename::*;
+mod limit;
+pub use limit::*;
+mod identify_format;
+pub use identify_format::*;
+mod metric;
+pub use metric::*;
+mod number;
+pub use number::*;
+mod color;
+pub use color::*;
+mod rotate;
+pub use rotate::*;
+mod fuzz;
+pub use fuzz::*;
+mod gravity;
+pub use gravity::*;
+mod dither;
+pub use dither::*;
+mod crop;
+pub use crop::*;
+mod shear;
+pub use shear::*;
+mod interpolate;
+pub use interpolate::*;
+mod list;
+pub use list::*;
+mod modulate;
+pub use modulate::*;
+mod brightness_contrast;
+pub use brightness_contrast::*;
+mod stretch;
+pub use stretch::*;
+mod quantum;
+pub use quantum::*;
+mod sepia_threshold;
+pub use sepia_threshold::*;
+mod blend;
+pub use blend::*;
+mod channel_fx;
+pub use channel_fx::*;
+mod colorspace;
+pub use colorspace::*;
+mod channel;
+pub use channel::*;
+mod evaluate;
+pub use evaluate::*;
+mod blur;
+pub use blur::*;
+mod statistic;
+pub use statistic::*;
+mod noise;
+pub use noise::*;
+mod wave;
+pub use wave::*;
+mod shadow;
+pub use shadow::*;
+mod raise;
+pub use raise::*;
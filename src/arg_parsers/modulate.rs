@@ -0,0 +1,106 @@
+use std::{ffi::OsStr, str::FromStr};
+
+use strum::EnumString;
+
+use crate::{error::MagickError, wm_err};
+
+/// The argument of `-modulate`: `brightness[,saturation[,hue]]` in percent.
+/// Missing values are 100, which leaves the component unchanged.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Modulate {
+    pub brightness: f64,
+    pub saturation: f64,
+    /// 0 and 200 both turn the hue by 180 degrees
+    pub hue: f64,
+}
+
+impl FromStr for Modulate {
+    type Err = MagickError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || wm_err!("invalid argument for option `-modulate': {}", s);
+        let mut values = [100.0; 3];
+        let mut parts = s.split([',', 'x', 'X', '/']);
+        for value in &mut values {
+            let Some(part) = parts.next() else {
+                break;
+            };
+            let part = part.trim();
+            let number = part.strip_suffix('%').unwrap_or(part);
+            match number.parse::<f64>() {
+                Ok(number) if number.is_finite() => *value = number,
+                _ => return Err(invalid()),
+            }
+        }
+        if parts.next().is_some() {
+            return Err(invalid());
+        }
+        let [brightness, saturation, hue] = values;
+        Ok(Self {
+            brightness,
+            saturation,
+            hue,
+        })
+    }
+}
+
+impl TryFrom<&OsStr> for Modulate {
+    type Error = MagickError;
+
+    fn try_from(s: &OsStr) -> Result<Self, Self::Error> {
+        match s.to_str() {
+            Some(s) => Self::from_str(s),
+            None => Err(wm_err!(
+                "invalid argument for option `-modulate': {}",
+                s.to_string_lossy()
+            )),
+        }
+    }
+}
+
+/// The colorspace `-modulate` adjusts the image in, set by `-define modulate:colorspace`
+#[derive(EnumString, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[strum(ascii_case_insensitive)]
+pub enum ModulateColorspace {
+    /// Hue, chroma and luma. Changing the hue does not change the perceived brightness as much.
+    #[strum(serialize = "HCL")]
+    Hcl,
+    /// Hue, saturation and lightness
+    #[default]
+    #[strum(serialize = "HSL")]
+    Hsl,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let parse = |s| {
+            let modulate = Modulate::from_str(s).unwrap();
+            (modulate.brightness, modulate.saturation, modulate.hue)
+        };
+        assert_eq!(parse("120"), (120.0, 100.0, 100.0));
+        assert_eq!(parse("100,150"), (100.0, 150.0, 100.0));
+        assert_eq!(parse("80, 0, 50.5%"), (80.0, 0.0, 50.5));
+        assert_eq!(parse("100x100x0"), (100.0, 100.0, 0.0));
+        assert!(Modulate::from_str("").is_err());
+        assert!(Modulate::from_str("100,,50").is_err());
+        assert!(Modulate::from_str("1,2,3,4").is_err());
+        assert!(Modulate::from_str("bright").is_err());
+    }
+
+    #[test]
+    fn colorspace() {
+        assert_eq!(
+            ModulateColorspace::from_str("hcl").unwrap(),
+            ModulateColorspace::Hcl
+        );
+        assert_eq!(
+            ModulateColorspace::from_str("HSL").unwrap(),
+            ModulateColorspace::Hsl
+        );
+        assert!(ModulateColorspace::from_str("HSV").is_err());
+    }
+}
@@ -0,0 +1,53 @@
+use std::{ffi::OsStr, str::FromStr};
+
+use strum::EnumString;
+
+use crate::{error::MagickError, wm_err};
+
+/// The argument of `+noise`: the distribution of the noise added to the image.
+/// Its strength is scaled by `-attenuate`.
+///
+/// See <https://imagemagick.org/script/command-line-options.php#noise>
+#[derive(EnumString, Debug, Clone, Copy, PartialEq, Eq)]
+#[strum(ascii_case_insensitive)]
+pub enum NoiseType {
+    Gaussian,
+    /// Salt and pepper: some samples become black or white
+    Impulse,
+    Laplacian,
+    /// Gaussian noise proportional to the sample
+    #[strum(serialize = "Multiplicative", serialize = "MultiplicativeGaussian")]
+    Multiplicative,
+    /// Noise that grows with the brightness, like photon noise in a camera sensor
+    Poisson,
+    /// Replaces the samples with random values
+    Random,
+    Uniform,
+}
+
+impl TryFrom<&OsStr> for NoiseType {
+    type Error = MagickError;
+
+    fn try_from(s: &OsStr) -> Result<Self, Self::Error> {
+        let Some(noise) = s.to_str().and_then(|s| Self::from_str(s).ok()) else {
+            return Err(wm_err!("unrecognized noise type `{}'", s.to_string_lossy()));
+        };
+        Ok(noise)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let parse = |s| NoiseType::try_from(OsStr::new(s));
+        assert_eq!(parse("gaussian").unwrap(), NoiseType::Gaussian);
+        assert_eq!(
+            parse("MultiplicativeGaussian").unwrap(),
+            NoiseType::Multiplicative
+        );
+        assert!(parse("pink").is_err());
+    }
+}
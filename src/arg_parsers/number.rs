@@ -0,0 +1,53 @@
+use std::{ffi::OsStr, str::FromStr};
+
+use crate::{error::MagickError, wm_err};
+
+/// Parses the value of an option that takes a plain number, such as `-precision 4`
+pub fn parse_numeric_arg<T: FromStr>(option: &str, value: &OsStr) -> Result<T, MagickError> {
+    let Some(number) = value.to_str().and_then(|v| v.trim().parse().ok()) else {
+        return Err(wm_err!(
+            "invalid argument for option `-{}': {}",
+            option,
+            value.to_string_lossy()
+        ));
+    };
+    Ok(number)
+}
+
+/// Parses a number that may be negative or fractional, but not infinite or NaN, such as `-kerning -0.5`
+pub fn parse_finite_arg(option: &str, value: &OsStr) -> Result<f64, MagickError> {
+    let number: f64 = parse_numeric_arg(option, value)?;
+    if !number.is_finite() {
+        return Err(wm_err!(
+            "invalid argument for option `-{}': {}",
+            option,
+            value.to_string_lossy()
+        ));
+    }
+    Ok(number)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numbers() {
+        assert_eq!(
+            parse_numeric_arg::<usize>("precision", OsStr::new("4")).unwrap(),
+            4
+        );
+        assert_eq!(
+            parse_numeric_arg::<f64>("similarity-threshold", OsStr::new("0.5")).unwrap(),
+            0.5
+        );
+        assert!(parse_numeric_arg::<usize>("precision", OsStr::new("-1")).is_err());
+        assert!(parse_numeric_arg::<usize>("precision", OsStr::new("four")).is_err());
+        assert_eq!(
+            parse_finite_arg("kerning", OsStr::new("-1.5")).unwrap(),
+            -1.5
+        );
+        assert!(parse_finite_arg("kerning", OsStr::new("inf")).is_err());
+        assert!(parse_finite_arg("kerning", OsStr::new("NaN")).is_err());
+    }
+}
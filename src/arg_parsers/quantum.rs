@@ -0,0 +1,41 @@
+use std::ffi::OsStr;
+
+use crate::{error::MagickError, image::QUANTUM_RANGE, wm_err};
+
+/// Parses a sample value such as the one of `-threshold`: either an absolute value in quantum units,
+/// e.g. `32768`, or a percentage of the maximum, e.g. `50%`.
+///
+/// Returns the value normalized so that the maximum is 1. Values outside of the range are allowed.
+pub fn parse_quantum_arg(option: &str, value: &OsStr) -> Result<f64, MagickError> {
+    let invalid = || {
+        wm_err!(
+            "invalid argument for option `-{}': {}",
+            option,
+            value.to_string_lossy()
+        )
+    };
+    let s = value.to_str().ok_or_else(invalid)?.trim();
+    let normalized = match s.strip_suffix('%') {
+        Some(percent) => percent.parse::<f64>().map_err(|_| invalid())? / 100.0,
+        None => s.parse::<f64>().map_err(|_| invalid())? / QUANTUM_RANGE,
+    };
+    match normalized.is_finite() {
+        true => Ok(normalized),
+        false => Err(invalid()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let parse = |value: &str| parse_quantum_arg("threshold", OsStr::new(value));
+        assert_eq!(parse("50%").unwrap(), 0.5);
+        assert_eq!(parse("65535").unwrap(), 1.0);
+        assert_eq!(parse("-10%").unwrap(), -0.1);
+        assert!(parse("half").is_err());
+        assert!(parse("inf%").is_err());
+    }
+}
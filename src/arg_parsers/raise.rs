@@ -0,0 +1,60 @@
+use std::ffi::OsStr;
+
+use crate::{arg_parsers::Geometry, error::MagickError, wm_err};
+
+/// The argument of `-raise`: the width of the vertical and the height of the horizontal edges, `WxH`.
+/// A single number is used for both.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RaiseGeometry {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl TryFrom<&OsStr> for RaiseGeometry {
+    type Error = MagickError;
+
+    fn try_from(s: &OsStr) -> Result<Self, Self::Error> {
+        let invalid = || {
+            wm_err!(
+                "invalid argument for option `-raise': {}",
+                s.to_string_lossy()
+            )
+        };
+        let geometry = Geometry::try_from(s).map_err(|_| invalid())?;
+        let (Some(width), Some(height)) = (
+            geometry.width.or(geometry.height),
+            geometry.height.or(geometry.width),
+        ) else {
+            return Err(invalid());
+        };
+        Ok(Self {
+            width: width as u32,
+            height: height as u32,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let parse = |s: &str| RaiseGeometry::try_from(OsStr::new(s)).unwrap();
+        assert_eq!(
+            parse("5x3"),
+            RaiseGeometry {
+                width: 5,
+                height: 3
+            }
+        );
+        assert_eq!(
+            parse("4"),
+            RaiseGeometry {
+                width: 4,
+                height: 4
+            }
+        );
+        assert!(RaiseGeometry::try_from(OsStr::new("bevel")).is_err());
+    }
+}
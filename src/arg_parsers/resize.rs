@@ -254,11 +254,12 @@ fn find_and_parse_float(input: &[u8]) -> Result<Option<f64>, ParseFloatError> {
         Ok(None)
     } else {
         let number_str = String::from_utf8(number).unwrap();
-        number_str.parse::<f64>().map(|f| Some(f))
+        number_str.parse::<f64>().map(Some)
     }
 }
 
 #[cfg(test)]
+#[allow(clippy::field_reassign_with_default)]
 mod tests {
     use std::str::FromStr;
 
@@ -0,0 +1,94 @@
+use std::{ffi::OsStr, str::FromStr};
+
+use crate::{error::MagickError, wm_err};
+
+/// Which images `-rotate` applies to, selected by a `<` or `>` after the angle
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RotateConstraint {
+    #[default]
+    Always,
+    /// `>`: only rotate images that are wider than they are tall
+    OnlyWide,
+    /// `<`: only rotate images that are taller than they are wide
+    OnlyTall,
+}
+
+/// The argument of `-rotate`: an angle in degrees, clockwise, optionally followed by `<` or `>`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RotateGeometry {
+    pub degrees: f64,
+    pub constraint: RotateConstraint,
+}
+
+impl RotateGeometry {
+    /// Whether an image of the given size is rotated at all
+    pub fn applies_to(&self, width: u32, height: u32) -> bool {
+        match self.constraint {
+            RotateConstraint::Always => true,
+            RotateConstraint::OnlyWide => width > height,
+            RotateConstraint::OnlyTall => width < height,
+        }
+    }
+}
+
+impl FromStr for RotateGeometry {
+    type Err = MagickError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || wm_err!("invalid argument for option `-rotate': {}", s);
+        let only_wide = s.contains('>');
+        let only_tall = s.contains('<');
+        let constraint = match (only_wide, only_tall) {
+            (false, false) => RotateConstraint::Always,
+            (true, false) => RotateConstraint::OnlyWide,
+            (false, true) => RotateConstraint::OnlyTall,
+            (true, true) => return Err(invalid()),
+        };
+        let degrees: f64 = s
+            .trim_matches(|c: char| c == '<' || c == '>' || c.is_whitespace())
+            .parse()
+            .map_err(|_| invalid())?;
+        if !degrees.is_finite() {
+            return Err(invalid());
+        }
+        Ok(Self {
+            degrees,
+            constraint,
+        })
+    }
+}
+
+impl TryFrom<&OsStr> for RotateGeometry {
+    type Error = MagickError;
+
+    fn try_from(s: &OsStr) -> Result<Self, Self::Error> {
+        match s.to_str() {
+            Some(s) => Self::from_str(s),
+            None => Err(wm_err!(
+                "invalid argument for option `-rotate': {}",
+                s.to_string_lossy()
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let geometry = RotateGeometry::from_str("-22.5").unwrap();
+        assert_eq!(geometry.degrees, -22.5);
+        assert_eq!(geometry.constraint, RotateConstraint::Always);
+        let geometry = RotateGeometry::from_str("90>").unwrap();
+        assert_eq!(geometry.constraint, RotateConstraint::OnlyWide);
+        assert!(geometry.applies_to(3, 2));
+        assert!(!geometry.applies_to(2, 2));
+        let geometry = RotateGeometry::from_str("90<").unwrap();
+        assert!(geometry.applies_to(2, 3));
+        assert!(RotateGeometry::from_str("90<>").is_err());
+        assert!(RotateGeometry::from_str("ninety").is_err());
+        assert!(RotateGeometry::from_str("inf").is_err());
+    }
+}
@@ -0,0 +1,34 @@
+use std::ffi::OsStr;
+
+use super::parse_quantum_arg;
+use crate::error::MagickError;
+
+/// The argument of `-sepia-tone`: the intensity above which the red and green channels saturate,
+/// given in quantum units or as a percentage such as `80%`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SepiaThreshold {
+    /// The threshold normalized to `[0, 1]`
+    pub threshold: f64,
+}
+
+impl TryFrom<&OsStr> for SepiaThreshold {
+    type Error = MagickError;
+
+    fn try_from(s: &OsStr) -> Result<Self, Self::Error> {
+        Ok(Self {
+            threshold: parse_quantum_arg("sepia-tone", s)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let parse = |s: &str| SepiaThreshold::try_from(OsStr::new(s));
+        assert_eq!(parse("80%").unwrap().threshold, 0.8);
+        assert!(parse("sepia").is_err());
+    }
+}
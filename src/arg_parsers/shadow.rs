@@ -0,0 +1,66 @@
+use std::{ffi::OsStr, str::FromStr};
+
+use crate::{arg_parsers::Geometry, error::MagickError, wm_err};
+
+/// The argument of `-shadow`: `opacity[%][xsigma][{+-}x{+-}y]`.
+/// Like in imagemagick, sigma defaults to 4 and the shadow is offset 4 pixels right and down.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ShadowGeometry {
+    /// How opaque the shadow is, in percent
+    pub opacity: f64,
+    pub sigma: f64,
+    pub x: i64,
+    pub y: i64,
+}
+
+impl FromStr for ShadowGeometry {
+    type Err = MagickError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || wm_err!("invalid argument for option `-shadow': {}", s);
+        // The opacity is always a percentage, with or without the sign
+        let geometry = Geometry::from_str(&s.replace('%', "")).map_err(|_| invalid())?;
+        let Some(opacity) = geometry.width else {
+            return Err(invalid());
+        };
+        let offset = |offset: Option<f64>| offset.unwrap_or(4.0).round() as i64;
+        Ok(Self {
+            opacity,
+            sigma: geometry.height.unwrap_or(4.0),
+            x: offset(geometry.xoffset),
+            y: offset(geometry.yoffset),
+        })
+    }
+}
+
+impl TryFrom<&OsStr> for ShadowGeometry {
+    type Error = MagickError;
+
+    fn try_from(s: &OsStr) -> Result<Self, Self::Error> {
+        match s.to_str() {
+            Some(s) => Self::from_str(s),
+            None => Err(wm_err!(
+                "invalid argument for option `-shadow': {}",
+                s.to_string_lossy()
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let parse = |s| {
+            let geometry = ShadowGeometry::from_str(s).unwrap();
+            (geometry.opacity, geometry.sigma, geometry.x, geometry.y)
+        };
+        assert_eq!(parse("80x3+5+5"), (80.0, 3.0, 5, 5));
+        assert_eq!(parse("60%x2-3+4"), (60.0, 2.0, -3, 4));
+        assert_eq!(parse("100"), (100.0, 4.0, 4, 4));
+        assert!(ShadowGeometry::from_str("x3").is_err());
+        assert!(ShadowGeometry::from_str("shade").is_err());
+    }
+}
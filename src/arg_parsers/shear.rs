@@ -0,0 +1,66 @@
+use std::{ffi::OsStr, str::FromStr};
+
+use crate::{error::MagickError, wm_err};
+
+/// The argument of `-shear`: `Xdegrees[xYdegrees]`.
+/// A single angle shears the image along both axes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ShearGeometry {
+    pub x_degrees: f64,
+    pub y_degrees: f64,
+}
+
+impl FromStr for ShearGeometry {
+    type Err = MagickError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || wm_err!("invalid argument for option `-shear': {}", s);
+        let parse = |angle: &str| -> Result<f64, MagickError> {
+            match angle.trim().parse::<f64>() {
+                Ok(angle) if angle.is_finite() => Ok(angle),
+                _ => Err(invalid()),
+            }
+        };
+        // `x` separates the angles, but a leading one would leave the first angle empty
+        let (x_degrees, y_degrees) = match s.split_once(['x', 'X']) {
+            Some((x, y)) => (parse(x)?, parse(y)?),
+            None => (parse(s)?, parse(s)?),
+        };
+        Ok(Self {
+            x_degrees,
+            y_degrees,
+        })
+    }
+}
+
+impl TryFrom<&OsStr> for ShearGeometry {
+    type Error = MagickError;
+
+    fn try_from(s: &OsStr) -> Result<Self, Self::Error> {
+        match s.to_str() {
+            Some(s) => Self::from_str(s),
+            None => Err(wm_err!(
+                "invalid argument for option `-shear': {}",
+                s.to_string_lossy()
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let geometry = ShearGeometry::from_str("20").unwrap();
+        assert_eq!((geometry.x_degrees, geometry.y_degrees), (20.0, 20.0));
+        let geometry = ShearGeometry::from_str("-10x0").unwrap();
+        assert_eq!((geometry.x_degrees, geometry.y_degrees), (-10.0, 0.0));
+        let geometry = ShearGeometry::from_str("0x-7.5").unwrap();
+        assert_eq!((geometry.x_degrees, geometry.y_degrees), (0.0, -7.5));
+        assert!(ShearGeometry::from_str("x5").is_err());
+        assert!(ShearGeometry::from_str("5x").is_err());
+        assert!(ShearGeometry::from_str("nan").is_err());
+    }
+}
@@ -0,0 +1,104 @@
+use std::{ffi::OsStr, str::FromStr};
+
+use strum::EnumString;
+
+use crate::{arg_parsers::Geometry, error::MagickError, wm_err};
+
+/// The first argument of `-statistic`: what every pixel is replaced with,
+/// computed from the pixels in the window around it
+///
+/// See <https://imagemagick.org/script/command-line-options.php#statistic>
+#[derive(EnumString, Debug, Clone, Copy, PartialEq, Eq)]
+#[strum(ascii_case_insensitive)]
+pub enum StatisticType {
+    /// The difference between the maximum and the minimum
+    Gradient,
+    Maximum,
+    Mean,
+    Median,
+    Minimum,
+    /// The most common value
+    Mode,
+    /// The median, unless it is the lowest or highest value in the window,
+    /// in which case the value next to it
+    Nonpeak,
+    RootMeanSquare,
+    StandardDeviation,
+}
+
+impl TryFrom<&OsStr> for StatisticType {
+    type Error = MagickError;
+
+    fn try_from(s: &OsStr) -> Result<Self, Self::Error> {
+        let Some(statistic) = s.to_str().and_then(|s| Self::from_str(s).ok()) else {
+            return Err(wm_err!(
+                "unrecognized statistic type `{}'",
+                s.to_string_lossy()
+            ));
+        };
+        Ok(statistic)
+    }
+}
+
+/// The `{width}x{height}` of the window that `-statistic` and `-median` look at around every pixel.
+/// A single number is a square, like `-median 3` for 3x3.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StatisticWindow {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl TryFrom<&OsStr> for StatisticWindow {
+    type Error = MagickError;
+
+    fn try_from(s: &OsStr) -> Result<Self, Self::Error> {
+        let geometry = Geometry::try_from(s)?;
+        let (Some(width), Some(height)) = (
+            geometry.width.or(geometry.height),
+            geometry.height.or(geometry.width),
+        ) else {
+            return Err(wm_err!("invalid geometry: {}", s.to_string_lossy()));
+        };
+        // Like imagemagick, a window smaller than a pixel is just the pixel itself
+        Ok(Self {
+            width: (width as u32).max(1),
+            height: (height as u32).max(1),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_type() {
+        let parse = |s| StatisticType::try_from(OsStr::new(s));
+        assert_eq!(parse("median").unwrap(), StatisticType::Median);
+        assert_eq!(
+            parse("StandardDeviation").unwrap(),
+            StatisticType::StandardDeviation
+        );
+        assert!(parse("average").is_err());
+    }
+
+    #[test]
+    fn parse_window() {
+        let parse = |s| StatisticWindow::try_from(OsStr::new(s));
+        assert_eq!(
+            parse("3").unwrap(),
+            StatisticWindow {
+                width: 3,
+                height: 3
+            }
+        );
+        assert_eq!(
+            parse("5x1").unwrap(),
+            StatisticWindow {
+                width: 5,
+                height: 1
+            }
+        );
+        assert!(parse("").is_err());
+    }
+}
@@ -0,0 +1,88 @@
+use std::{ffi::OsStr, str::FromStr};
+
+use crate::{error::MagickError, wm_err};
+
+/// The argument of `-contrast-stretch` and `-linear-stretch`: `black[xwhite][%]`.
+///
+/// The values are the number of darkest and brightest pixels that become black and white,
+/// or a percentage of all pixels with `%`. White is the same as black if missing.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StretchGeometry {
+    pub black: f64,
+    pub white: f64,
+    pub percent: bool,
+}
+
+impl StretchGeometry {
+    /// The number of pixels at the dark and the bright end of an image with this many pixels
+    pub fn pixel_counts(&self, pixels: u64) -> (f64, f64) {
+        let scale = match self.percent {
+            true => pixels as f64 / 100.0,
+            false => 1.0,
+        };
+        (self.black * scale, self.white * scale)
+    }
+}
+
+impl FromStr for StretchGeometry {
+    type Err = MagickError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || wm_err!("invalid geometry: {}", s);
+        let percent = s.contains('%');
+        let parse = |value: &str| -> Result<f64, MagickError> {
+            match value.trim().trim_end_matches('%').parse::<f64>() {
+                Ok(value) if value.is_finite() && value >= 0.0 => Ok(value),
+                _ => Err(invalid()),
+            }
+        };
+        let (black, white) = match s.split_once(['x', 'X', ',']) {
+            Some((black, white)) => (parse(black)?, parse(white)?),
+            None => (parse(s)?, parse(s)?),
+        };
+        Ok(Self {
+            black,
+            white,
+            percent,
+        })
+    }
+}
+
+impl TryFrom<&OsStr> for StretchGeometry {
+    type Error = MagickError;
+
+    fn try_from(s: &OsStr) -> Result<Self, Self::Error> {
+        match s.to_str() {
+            Some(s) => Self::from_str(s),
+            None => Err(wm_err!("invalid geometry: {}", s.to_string_lossy())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let parse = |s| {
+            let geometry = StretchGeometry::from_str(s).unwrap();
+            (geometry.black, geometry.white, geometry.percent)
+        };
+        assert_eq!(parse("2%x1%"), (2.0, 1.0, true));
+        assert_eq!(parse("2x1%"), (2.0, 1.0, true));
+        assert_eq!(parse("5%"), (5.0, 5.0, true));
+        assert_eq!(parse("10,20"), (10.0, 20.0, false));
+        assert!(StretchGeometry::from_str("-1%").is_err());
+        assert!(StretchGeometry::from_str("x1").is_err());
+        assert!(StretchGeometry::from_str("").is_err());
+    }
+
+    #[test]
+    fn pixel_counts() {
+        let geometry = StretchGeometry::from_str("2x1%").unwrap();
+        assert_eq!(geometry.pixel_counts(1000), (20.0, 10.0));
+        let geometry = StretchGeometry::from_str("3x4").unwrap();
+        assert_eq!(geometry.pixel_counts(1000), (3.0, 4.0));
+    }
+}
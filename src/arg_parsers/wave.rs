@@ -0,0 +1,67 @@
+use std::{ffi::OsStr, str::FromStr};
+
+use crate::{error::MagickError, wm_err};
+
+/// The argument of `-wave`: `amplitude[xwavelength]`, in pixels.
+/// The wavelength defaults to 1 like in imagemagick.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WaveGeometry {
+    pub amplitude: f64,
+    pub wavelength: f64,
+}
+
+impl FromStr for WaveGeometry {
+    type Err = MagickError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || wm_err!("invalid argument for option `-wave': {}", s);
+        let parse = |value: &str| -> Result<f64, MagickError> {
+            match value.trim().parse::<f64>() {
+                Ok(value) if value.is_finite() => Ok(value),
+                _ => Err(invalid()),
+            }
+        };
+        let (amplitude, wavelength) = match s.split_once(['x', 'X']) {
+            Some((amplitude, wavelength)) => (parse(amplitude)?, parse(wavelength)?),
+            None => (parse(s)?, 1.0),
+        };
+        // The wavelength divides the position along the wave
+        if wavelength == 0.0 {
+            return Err(invalid());
+        }
+        Ok(Self {
+            amplitude,
+            wavelength,
+        })
+    }
+}
+
+impl TryFrom<&OsStr> for WaveGeometry {
+    type Error = MagickError;
+
+    fn try_from(s: &OsStr) -> Result<Self, Self::Error> {
+        match s.to_str() {
+            Some(s) => Self::from_str(s),
+            None => Err(wm_err!(
+                "invalid argument for option `-wave': {}",
+                s.to_string_lossy()
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let geometry = WaveGeometry::from_str("10x50").unwrap();
+        assert_eq!((geometry.amplitude, geometry.wavelength), (10.0, 50.0));
+        let geometry = WaveGeometry::from_str("-2.5").unwrap();
+        assert_eq!((geometry.amplitude, geometry.wavelength), (-2.5, 1.0));
+        assert!(WaveGeometry::from_str("5x0").is_err());
+        assert!(WaveGeometry::from_str("5x").is_err());
+        assert!(WaveGeometry::from_str("inf").is_err());
+    }
+}
@@ -6,46 +6,278 @@
 use std::ffi::{OsStr, OsString};
 
 use crate::{
-    arg_parsers::ResizeGeometry,
     error::MagickError,
-    operations::Operation,
-    plan::{ExecutionPlan, FilePlan},
+    plan::{ExecutionPlan, FilePlan, Modifiers, NULL_OUTPUT},
     wm_err,
 };
 
 use strum::{EnumString, IntoStaticStr, VariantArray};
 
 #[derive(EnumString, IntoStaticStr, VariantArray, Debug, Clone, Copy, PartialEq, Eq)]
-#[strum(serialize_all = "snake_case")]
+#[strum(serialize_all = "kebab-case")]
 pub enum Arg {
     Resize,
     Thumbnail,
     Scale,
     Sample,
+    AutoOrient,
+    Flip,
+    Flop,
+    Rotate,
+    Shear,
+    Swirl,
+    Implode,
+    Wave,
+    Crop,
+    Shave,
+    Extent,
+    Trim,
+    Strip,
+    AutoLevel,
+    AutoGamma,
+    Gamma,
+    BrightnessContrast,
+    Normalize,
+    ContrastStretch,
+    LinearStretch,
+    Modulate,
+    Unsharp,
+    AdaptiveBlur,
+    AdaptiveSharpen,
+    MotionBlur,
+    /// `-radial-blur` is the older name of `-rotational-blur`
+    #[strum(to_string = "rotational-blur", serialize = "radial-blur")]
+    RotationalBlur,
+    Statistic,
+    Median,
+    Despeckle,
+    Raise,
+    Shadow,
+    Edge,
+    Emboss,
+    Charcoal,
+    Sketch,
+    Noise,
+    Posterize,
+    OrderedDither,
+    Colors,
+    Monochrome,
+    /// `-map` is the older name of `-remap`
+    #[strum(to_string = "remap", serialize = "map")]
+    Remap,
+    SepiaTone,
+    Negate,
+    Evaluate,
+    Fx,
+    ChannelFx,
+    Colorspace,
+    Separate,
+    Opaque,
+    Transparent,
+    Colorize,
+    Tint,
+    Threshold,
+    BlackThreshold,
+    WhiteThreshold,
+    Comment,
+    Label,
+    Set,
+    Identify,
+    Format,
+    Precision,
+    Verbose,
+    Bench,
+    RegardWarnings,
+    Update,
+    Adjoin,
+    Preview,
+    /// `--wm-journal`, our own extension
+    #[strum(serialize = "-wm-journal")]
+    WmJournal,
+    /// `--wm-no-srgb-fallback`, our own extension
+    #[strum(serialize = "-wm-no-srgb-fallback")]
+    WmNoSrgbFallback,
+    /// `--wm-web-thumbnail`, our own extension
+    #[strum(serialize = "-wm-web-thumbnail")]
+    WmWebThumbnail,
+    /// `--wm-untrusted`, our own extension
+    #[strum(serialize = "-wm-untrusted")]
+    WmUntrusted,
+    Define,
+    Limit,
+    Background,
+    Fuzz,
+    Channel,
+    Gravity,
+    Size,
+    Pointsize,
+    Font,
+    Fill,
+    Stroke,
+    Strokewidth,
+    Undercolor,
+    Kerning,
+    InterlineSpacing,
+    InterwordSpacing,
+    Interpolate,
+    Quantize,
+    Treedepth,
+    Dither,
+    Attenuate,
+    Seed,
+    Loop,
+    TransparentColor,
 }
 
-impl Arg {
-    pub fn needs_value(&self) -> bool {
-        match self {
-            Arg::Resize => true,
-            Arg::Thumbnail => true,
-            Arg::Scale => true,
-            Arg::Sample => true,
+/// Whether an option was given as `-option` or `+option`. Some options mean different things with `+`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArgSign {
+    Minus,
+    Plus,
+}
+
+/// An option along with the sign it was given with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SignedArg {
+    pub sign: ArgSign,
+    pub arg: Arg,
+}
+
+impl SignedArg {
+    pub fn is_plus(&self) -> bool {
+        self.sign == ArgSign::Plus
+    }
+
+    /// The number of values that follow the argument on the command line,
+    /// which is fewer for some `+option`s
+    pub fn value_count(&self) -> usize {
+        match (self.sign, self.arg) {
+            // `+set key` removes the property, so there is no value
+            (ArgSign::Plus, Arg::Set) => 1,
+            // `+channel` resets the channels to the default
+            (ArgSign::Plus, Arg::Channel) => 0,
+            // `+dither` turns dithering off
+            (ArgSign::Plus, Arg::Dither) => 0,
+            // `+seed` goes back to a different sequence of random numbers on every run
+            (ArgSign::Plus, Arg::Seed) => 0,
+            (_, arg) => arg.value_count(),
         }
     }
+}
 
-    pub fn to_operation(&self, value: Option<&OsStr>) -> Result<Operation, MagickError> {
-        if self.needs_value() != value.is_some() {
-            return Err(wm_err!("argument requires a value"));
-        };
+impl From<Arg> for SignedArg {
+    /// The usual `-option` form
+    fn from(arg: Arg) -> Self {
+        Self {
+            sign: ArgSign::Minus,
+            arg,
+        }
+    }
+}
 
+impl Arg {
+    /// The number of values that follow the argument on the command line
+    pub fn value_count(&self) -> usize {
         match self {
-            Arg::Resize => Ok(Operation::Resize(ResizeGeometry::try_from(value.unwrap())?)),
-            Arg::Thumbnail => Ok(Operation::Thumbnail(ResizeGeometry::try_from(
-                value.unwrap(),
-            )?)),
-            Arg::Scale => Ok(Operation::Scale(ResizeGeometry::try_from(value.unwrap())?)),
-            Arg::Sample => Ok(Operation::Sample(ResizeGeometry::try_from(value.unwrap())?)),
+            Arg::Resize => 1,
+            Arg::Thumbnail => 1,
+            Arg::Scale => 1,
+            Arg::Sample => 1,
+            Arg::AutoOrient => 0,
+            Arg::Flip => 0,
+            Arg::Flop => 0,
+            Arg::Rotate => 1,
+            Arg::Shear => 1,
+            Arg::Swirl => 1,
+            Arg::Implode => 1,
+            Arg::Wave => 1,
+            Arg::Crop => 1,
+            Arg::Shave => 1,
+            Arg::Extent => 1,
+            Arg::Trim => 0,
+            Arg::Strip => 0,
+            Arg::AutoLevel => 0,
+            Arg::AutoGamma => 0,
+            Arg::Gamma => 1,
+            Arg::BrightnessContrast => 1,
+            Arg::Normalize => 0,
+            Arg::ContrastStretch => 1,
+            Arg::LinearStretch => 1,
+            Arg::Modulate => 1,
+            Arg::Unsharp => 1,
+            Arg::AdaptiveBlur => 1,
+            Arg::AdaptiveSharpen => 1,
+            Arg::MotionBlur => 1,
+            Arg::RotationalBlur => 1,
+            Arg::Statistic => 2,
+            Arg::Median => 1,
+            Arg::Despeckle => 0,
+            Arg::Raise => 1,
+            Arg::Shadow => 1,
+            Arg::Edge => 1,
+            Arg::Emboss => 1,
+            Arg::Charcoal => 1,
+            Arg::Sketch => 1,
+            Arg::Noise => 1,
+            Arg::Posterize => 1,
+            Arg::OrderedDither => 1,
+            Arg::Colors => 1,
+            Arg::Monochrome => 0,
+            Arg::Remap => 1,
+            Arg::SepiaTone => 1,
+            Arg::Negate => 0,
+            Arg::Evaluate => 2,
+            Arg::Fx => 1,
+            Arg::ChannelFx => 1,
+            Arg::Colorspace => 1,
+            Arg::Separate => 0,
+            Arg::Opaque => 1,
+            Arg::Transparent => 1,
+            Arg::Colorize => 1,
+            Arg::Tint => 1,
+            Arg::Threshold => 1,
+            Arg::BlackThreshold => 1,
+            Arg::WhiteThreshold => 1,
+            Arg::Comment => 1,
+            Arg::Label => 1,
+            Arg::Set => 2,
+            Arg::Identify => 0,
+            Arg::Format => 1,
+            Arg::Precision => 1,
+            Arg::Verbose => 0,
+            Arg::Bench => 1,
+            Arg::RegardWarnings => 0,
+            Arg::Update => 0,
+            Arg::Adjoin => 0,
+            Arg::Preview => 0,
+            Arg::WmJournal => 1,
+            Arg::WmNoSrgbFallback => 0,
+            Arg::WmWebThumbnail => 1,
+            Arg::WmUntrusted => 0,
+            Arg::Define => 1,
+            Arg::Limit => 2,
+            Arg::Background => 1,
+            Arg::Fuzz => 1,
+            Arg::Channel => 1,
+            Arg::Gravity => 1,
+            Arg::Size => 1,
+            Arg::Pointsize => 1,
+            Arg::Font => 1,
+            Arg::Fill => 1,
+            Arg::Stroke => 1,
+            Arg::Strokewidth => 1,
+            Arg::Undercolor => 1,
+            Arg::Kerning => 1,
+            Arg::InterlineSpacing => 1,
+            Arg::InterwordSpacing => 1,
+            Arg::Interpolate => 1,
+            Arg::Quantize => 1,
+            Arg::Treedepth => 1,
+            Arg::Dither => 1,
+            Arg::Attenuate => 1,
+            Arg::Seed => 1,
+            Arg::Loop => 1,
+            Arg::TransparentColor => 1,
         }
     }
 
@@ -55,6 +287,109 @@ impl Arg {
             Arg::Thumbnail => "create a thumbnail of the image",
             Arg::Scale => "scale the image",
             Arg::Sample => "scale image with pixel sampling",
+            Arg::AutoOrient => "automagically orient (rotate) image",
+            Arg::Flip => "flip image in the vertical direction",
+            Arg::Flop => "flop image in the horizontal direction",
+            Arg::Rotate => "apply Paeth rotation to the image",
+            Arg::Shear => "slide one edge of the image along the X or Y axis",
+            Arg::Swirl => "swirl image pixels about the center",
+            Arg::Implode => "implode image pixels about the center",
+            Arg::Wave => "alter an image along a sine wave",
+            Arg::Crop => "cut out a rectangular region of the image",
+            Arg::Shave => "shave pixels from the image edges",
+            Arg::Extent => "set the image size",
+            Arg::Trim => "trim image edges",
+            Arg::Strip => "strip image of all profiles and comments",
+            Arg::AutoLevel => "automagically adjust color levels of image",
+            Arg::AutoGamma => "automagically adjust gamma level of image",
+            Arg::Gamma => "level of gamma correction",
+            Arg::BrightnessContrast => "improve brightness / contrast of the image",
+            Arg::Normalize => "transform image to span the full range of colors",
+            Arg::ContrastStretch => "improve contrast by 'stretching' the intensity range",
+            Arg::LinearStretch => {
+                "improve contrast by 'stretching with saturation' the intensity range"
+            }
+            Arg::Modulate => "vary the brightness, saturation, and hue",
+            Arg::Unsharp => "sharpen the image",
+            Arg::AdaptiveBlur => "adaptively blur pixels; decrease effect near edges",
+            Arg::AdaptiveSharpen => "adaptively sharpen pixels; increase effect near edges",
+            Arg::MotionBlur => "simulate motion blur",
+            Arg::RotationalBlur => "rotational blur the image",
+            Arg::Statistic => {
+                "replace each pixel with corresponding statistic from the neighborhood"
+            }
+            Arg::Median => "apply a median filter to the image",
+            Arg::Despeckle => "reduce the speckles within an image",
+            Arg::Raise => "lighten/darken image edges to create a 3-D effect",
+            Arg::Shadow => "simulate an image shadow",
+            Arg::Edge => "apply a filter to detect edges in the image",
+            Arg::Emboss => "emboss an image",
+            Arg::Charcoal => "simulate a charcoal drawing",
+            Arg::Sketch => "simulate a pencil sketch",
+            Arg::Noise => "add or reduce noise in an image",
+            Arg::Posterize => "reduce the image to a limited number of color levels",
+            Arg::OrderedDither => "add a noise pattern to the image with specific amplitudes",
+            Arg::Colors => "preferred number of colors in the image",
+            Arg::Monochrome => "transform image to black and white",
+            Arg::Remap => "transform image colors to match this set of colors",
+            Arg::SepiaTone => "simulate a sepia-toned photo",
+            Arg::Negate => "replace every pixel with its complementary color",
+            Arg::Evaluate => "evaluate an arithmetic expression",
+            Arg::Fx => "apply mathematical expression to an image channel(s)",
+            Arg::Opaque => "change this color to the fill color",
+            Arg::Transparent => "make this color transparent within the image",
+            Arg::Colorspace => "alternate image colorspace",
+            Arg::Separate => "separate an image channel into a grayscale image",
+            Arg::ChannelFx => "exchange, extract, or transfer one or more image channels",
+            Arg::Colorize => "colorize the image with the fill color",
+            Arg::Tint => "tint the image with the fill color",
+            Arg::Threshold => "threshold the image",
+            Arg::BlackThreshold => "force all pixels below the threshold into black",
+            Arg::WhiteThreshold => "force all pixels above the threshold into white",
+            Arg::Comment => "annotate image with comment",
+            Arg::Label => "assign a label to an image",
+            Arg::Set => "set an image attribute",
+            Arg::Identify => "identify the format and characteristics of the image",
+            Arg::Format => "output formatted image characteristics",
+            Arg::Precision => "set the maximum number of significant digits to be printed",
+            Arg::Verbose => "print detailed information about the image",
+            Arg::Bench => "measure performance",
+            Arg::RegardWarnings => "pay attention to warning messages",
+            Arg::Update => "skip images whose output file is newer than the input",
+            Arg::Adjoin => "join images into a single multi-image file",
+            Arg::Preview => "list the images that would be processed, without processing them",
+            Arg::WmJournal => "record completed files in this file and skip them when rerun",
+            Arg::WmNoSrgbFallback => {
+                "keep the colors of images whose ICC profile the output format cannot hold"
+            }
+            Arg::WmWebThumbnail => {
+                "shrink, orient, convert to sRGB and strip the image for use on the web"
+            }
+            Arg::WmUntrusted => "only allow web formats and limit resources, for untrusted images",
+            Arg::Define => "define one or more image format options",
+            Arg::Limit => "pixel cache resource limit",
+            Arg::Background => "background color",
+            Arg::Fuzz => "colors within this distance are considered equal",
+            Arg::Channel => "apply option to select image channels",
+            Arg::Gravity => "horizontal and vertical placement preference",
+            Arg::Size => "width and height of image",
+            Arg::Pointsize => "font point size",
+            Arg::Font => "render text with this font",
+            Arg::Fill => "color to use when filling a graphic primitive",
+            Arg::Stroke => "graphic primitive stroke color",
+            Arg::Strokewidth => "graphic primitive stroke width",
+            Arg::Undercolor => "annotation bounding box color",
+            Arg::Kerning => "set the space between two letters",
+            Arg::InterlineSpacing => "set the space between two text lines",
+            Arg::InterwordSpacing => "set the space between two words",
+            Arg::Interpolate => "pixel color interpolation method",
+            Arg::Quantize => "reduce colors in this colorspace",
+            Arg::Treedepth => "color tree depth",
+            Arg::Dither => "apply error diffusion to image",
+            Arg::Attenuate => "lessen (or intensify) when adding noise to an image",
+            Arg::Seed => "seed a new sequence of pseudo-random numbers",
+            Arg::Loop => "add Netscape loop extension to your GIF animation",
+            Arg::TransparentColor => "transparent color",
         }
     }
 }
@@ -71,51 +406,76 @@ pub fn parse_args(mut args: Vec<OsString>) -> Result<ExecutionPlan, MagickError>
     // Contrary to the documentation about -flags being treated as filenames by default,
     // the observed behavior on my system is that they're only ever parsed as flags.
     let output_filename = args.pop().unwrap();
-    // imagemagick rejects output filenames that look like arguments
-    if starts_with_sign(&output_filename) {
+    // imagemagick rejects output filenames that look like arguments, except for `-` meaning stdout
+    if starts_with_sign(&output_filename) && output_filename != "-" {
         return Err(wm_err!(
             "missing an image filename `{}'",
             output_filename.to_string_lossy()
         ));
     }
 
-    let mut plan = ExecutionPlan::default();
-    plan.output_file = output_filename;
+    let mut plan = new_plan(output_filename);
+    apply_args(&mut plan, args)?;
+    Ok(plan)
+}
 
-    // TODO: parse the filename specification, there's a lot of operations that can be attached to it
+/// Parses the arguments of `identify`, which takes no output file:
+/// every image is identified after all of the options are applied, and nothing is written.
+pub fn parse_identify_args(args: Vec<OsString>) -> Result<ExecutionPlan, MagickError> {
+    if args.len() <= 1 {
+        return Err(wm_err!("No command-line arguments provided"));
+    }
+    let mut plan = new_plan(NULL_OUTPUT.into());
+    apply_args(&mut plan, args)?;
+    plan.apply_arg(Arg::Identify.into(), &[])?;
+    Ok(plan)
+}
 
+fn new_plan(output_file: OsString) -> ExecutionPlan {
+    ExecutionPlan {
+        output_file,
+        // Environment variables are read first so that command-line flags can override them
+        modifiers: Modifiers::from_env(),
+        ..Default::default()
+    }
+}
+
+/// Adds the input files and options to the plan in order. The first argument is the path to our binary.
+fn apply_args(plan: &mut ExecutionPlan, args: Vec<OsString>) -> Result<(), MagickError> {
     let mut iter = args.into_iter().skip(1); // skip argv[0], path to our binary
     while let Some(raw_arg) = iter.next() {
         if raw_arg.as_encoded_bytes() == [b'-'] {
-            todo!(); // this is stdin or stdout
+            // this is stdin; decoding takes care of it
+            plan.input_files.push(FilePlan::new(raw_arg));
         } else if starts_with_sign(&raw_arg) {
             // A file named "-foobar.jpg" will be parsed as an option.
             // Sadly imagemagick does not support the -- convention to separate options and filenames,
             // and there is nothing we can do about it without introducing incompatibility in argument parsing.
-            let (_sign, string_arg) = sign_and_arg_name(raw_arg)?;
+            let (sign, string_arg) = sign_and_arg_name(raw_arg)?;
             let arg = Arg::try_from(string_arg.as_str())
                 .map_err(|_| wm_err!("unrecognized option `{}'", string_arg))?;
-            let operation = if arg.needs_value() {
+            let arg = SignedArg { sign, arg };
+            let mut values = Vec::with_capacity(arg.value_count());
+            for _ in 0..arg.value_count() {
                 let value = iter
                     .next()
                     .ok_or(wm_err!("argument requires a value: {}", &string_arg))?;
-                arg.to_operation(Some(value.as_os_str()))?
-            } else {
-                arg.to_operation(None)?
-            };
-            plan.add_operation(operation);
+                values.push(value);
+            }
+            let values: Vec<&OsStr> = values.iter().map(|v| v.as_os_str()).collect();
+            plan.apply_arg(arg, &values)?;
         } else {
-            plan.input_files.push(FilePlan::new(raw_arg));
+            plan.add_input(&raw_arg)?;
         }
     }
     if plan.input_files.is_empty() {
         return Err(wm_err!("no images defined")); // mimics imagemagick
     }
-    Ok(plan)
+    Ok(())
 }
 
 /// Checks if the string starts with a `-` or a `+`
-fn starts_with_sign(arg: &OsStr) -> bool {
+pub(crate) fn starts_with_sign(arg: &OsStr) -> bool {
     let first_byte = arg.as_encoded_bytes().first();
     first_byte == Some(&b'-')
         || first_byte == Some(&b'+')
@@ -124,11 +484,14 @@ fn starts_with_sign(arg: &OsStr) -> bool {
 }
 
 /// Splits the string into a sign (- or +) and argument name
-fn sign_and_arg_name(raw_arg: OsString) -> Result<(u8, String), MagickError> {
+fn sign_and_arg_name(raw_arg: OsString) -> Result<(ArgSign, String), MagickError> {
     let mut string = raw_arg
         .into_string()
         .map_err(|s| wm_err!("unrecognized option `{}'", s.to_string_lossy()))?;
-    let sign = string.remove(0);
-    assert!(sign == '-' || sign == '+');
-    Ok((sign as u8, string))
+    let sign = match string.remove(0) {
+        '-' => ArgSign::Minus,
+        '+' => ArgSign::Plus,
+        _ => unreachable!("options start with a sign"),
+    };
+    Ok((sign, string))
 }
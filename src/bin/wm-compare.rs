@@ -0,0 +1,29 @@
+use std::error::Error;
+use std::process::ExitCode;
+use wondermagick::compare;
+use wondermagick::error::{MagickError, BROKEN_PIPE_EXIT_CODE};
+
+fn main() -> ExitCode {
+    match real_main() {
+        Ok(code) => code,
+        Err(e) if is_broken_pipe(e.as_ref()) => ExitCode::from(BROKEN_PIPE_EXIT_CODE),
+        Err(e) => {
+            eprintln!("{}", e);
+            // imagemagick's `compare` uses 1 to signal that the images differ, and 2 for errors
+            ExitCode::from(2)
+        }
+    }
+}
+
+/// Nobody reads the output anymore, which imagemagick would have been killed by SIGPIPE for
+fn is_broken_pipe(error: &(dyn Error + 'static)) -> bool {
+    error
+        .downcast_ref::<MagickError>()
+        .is_some_and(MagickError::is_broken_pipe)
+}
+
+fn real_main() -> Result<ExitCode, Box<dyn Error>> {
+    let arguments: Vec<_> = std::env::args_os().collect();
+    let plan = compare::parse_args(arguments)?;
+    Ok(plan.execute()?)
+}
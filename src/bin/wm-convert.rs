@@ -1,25 +1,30 @@
 use std::error::Error;
-use wondermagick::{args, decode::decode, help};
+use std::process::ExitCode;
+use wondermagick::error::{MagickError, BROKEN_PIPE_EXIT_CODE};
+use wondermagick::{args, help};
 
-fn main() {
-    if let Err(e) = real_main() {
-        eprintln!("{}", e);
+fn main() -> ExitCode {
+    match real_main() {
+        Ok(code) => code,
+        Err(e) if is_broken_pipe(e.as_ref()) => ExitCode::from(BROKEN_PIPE_EXIT_CODE),
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
     }
 }
 
-fn real_main() -> Result<(), Box<dyn Error>> {
+/// Nobody reads the output anymore, which imagemagick would have been killed by SIGPIPE for
+fn is_broken_pipe(error: &(dyn Error + 'static)) -> bool {
+    error
+        .downcast_ref::<MagickError>()
+        .is_some_and(MagickError::is_broken_pipe)
+}
+
+fn real_main() -> Result<ExitCode, Box<dyn Error>> {
     help::maybe_print_help_and_exit(env!("CARGO_BIN_NAME"));
+    help::maybe_print_list_and_exit()?;
     let arguments: Vec<_> = std::env::args_os().collect();
     let plan = args::parse_args(arguments)?;
-
-    // TODO: handle multiple images
-    let file_plan = plan.input_files.first().unwrap();
-    let mut image = decode(&file_plan.filename, None)?;
-
-    for operation in &file_plan.ops {
-        operation.execute(&mut image)?;
-    }
-
-    image.save(plan.output_file)?;
-    Ok(())
+    Ok(plan.execute()?)
 }
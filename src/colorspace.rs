@@ -0,0 +1,401 @@
+//! Converting images between the colorspaces of `-colorspace`.
+//!
+//! Images outside of sRGB keep their channels in the pixel buffer in imagemagick's order and scaling,
+//! e.g. L, a and b for Lab, so that `-separate` and the other channel operations see them.
+//! They are stored as floating point, since some channels such as Z of XYZ exceed `[0, 1]`,
+//! and converted back to sRGB when encoding, since none of the formats we write can store them.
+
+use std::borrow::Cow;
+
+use image::{ColorType, DynamicImage};
+
+use crate::{
+    arg_parsers::Colorspace, encoders::common::convert, image::Image,
+    utils::channel_map::map_colors,
+};
+
+/// The weights imagemagick computes the gray colorspace with
+const REC709_LUMA: [f64; 3] = [0.212656, 0.715158, 0.072186];
+
+/// The D65 white point in CIE XYZ
+const D65: [f64; 3] = [0.95047, 1.0, 1.08883];
+
+/// Converts the pixels of the image to the colorspace and records it in the image.
+///
+/// Converting to gray leaves the image in sRGB with grayscale pixels, like imagemagick's `Gray` does.
+pub fn transform(image: &mut Image, target: Colorspace) {
+    // Gray is stored as sRGB, so converting to it always has to look at the pixels
+    if image.colorspace == target && target != Colorspace::Gray {
+        return;
+    }
+    to_srgb_in_place(image);
+    match target {
+        Colorspace::Srgb => (),
+        Colorspace::Gray => {
+            let (to_gray, _) = conversions(Colorspace::Gray).expect("three channels");
+            let mut pixels = convert(&image.pixels, float_color_type(&image.pixels));
+            map_colors(&mut pixels, to_gray);
+            image.pixels = convert(&pixels, gray_color_type(image));
+        }
+        Colorspace::Cmyk => {
+            // The black channel takes the place of alpha, so transparency is lost
+            let mut pixels = image.pixels.to_rgba32f();
+            for pixel in pixels.pixels_mut() {
+                let rgb = [0, 1, 2].map(|c| f64::from(pixel[c]));
+                pixel.0 = rgb_to_cmyk(rgb).map(|v| v as f32);
+            }
+            image.pixels = DynamicImage::ImageRgba32F(pixels);
+        }
+        _ => {
+            let (from_srgb, _) = conversions(target).expect("three channels");
+            image.pixels = convert(&image.pixels, float_color_type(&image.pixels));
+            map_colors(&mut image.pixels, from_srgb);
+        }
+    }
+    if !target.is_srgb() {
+        image.colorspace = target;
+    }
+}
+
+/// Returns the image converted to sRGB, or the image itself if it is in sRGB already
+pub fn to_srgb(image: &Image) -> Cow<'_, Image> {
+    if image.colorspace.is_srgb() {
+        return Cow::Borrowed(image);
+    }
+    let mut image = image.clone();
+    to_srgb_in_place(&mut image);
+    Cow::Owned(image)
+}
+
+fn to_srgb_in_place(image: &mut Image) {
+    match image.colorspace {
+        Colorspace::Srgb | Colorspace::Gray => return,
+        Colorspace::Cmyk => {
+            let cmyk = image.pixels.to_rgba32f();
+            let rgb = image::ImageBuffer::from_fn(cmyk.width(), cmyk.height(), |x, y| {
+                let cmyk = cmyk.get_pixel(x, y).0.map(f64::from);
+                image::Rgb(cmyk_to_rgb(cmyk).map(|v| v as f32))
+            });
+            image.pixels = DynamicImage::ImageRgb32F(rgb);
+        }
+        colorspace => {
+            let (_, to_srgb) = conversions(colorspace).expect("three channels");
+            map_colors(&mut image.pixels, to_srgb);
+        }
+    }
+    image.colorspace = Colorspace::Srgb;
+    // Go back to the depth the image was read with
+    let original = image.original_color_type;
+    let deep = original.bits_per_pixel() / u16::from(original.channel_count()) > 8;
+    let color = match (deep, image.pixels.color().has_alpha()) {
+        (false, false) => ColorType::Rgb8,
+        (false, true) => ColorType::Rgba8,
+        (true, false) => ColorType::Rgb16,
+        (true, true) => ColorType::Rgba16,
+    };
+    image.pixels = convert(&image.pixels, color);
+}
+
+/// Converts the three channels of a color, normalized to `[0, 1]`, to another colorspace
+pub type Conversion = fn([f64; 3]) -> [f64; 3];
+
+/// The functions converting a color from sRGB to the colorspace and back,
+/// or `None` for CMYK, which has four channels.
+///
+/// Gray colors are three equal channels, which converting back leaves as they are.
+pub fn conversions(colorspace: Colorspace) -> Option<(Conversion, Conversion)> {
+    let conversions: (Conversion, Conversion) = match colorspace {
+        Colorspace::Srgb => (|rgb| rgb, |rgb| rgb),
+        Colorspace::Gray => (|rgb| [weighted_sum(rgb, REC709_LUMA); 3], |gray| gray),
+        Colorspace::Lab => (srgb_to_lab, lab_to_srgb),
+        Colorspace::Xyz => (srgb_to_xyz, xyz_to_srgb),
+        Colorspace::Hsl => (
+            |rgb| {
+                let (h, s, l) = rgb_to_hsl(rgb);
+                [h, s, l]
+            },
+            |[h, s, l]| hsl_to_rgb(h, s, l),
+        ),
+        Colorspace::Hsb => (rgb_to_hsb, hsb_to_rgb),
+        Colorspace::YCbCr => (rgb_to_ycbcr, ycbcr_to_rgb),
+        Colorspace::Cmyk => return None,
+    };
+    Some(conversions)
+}
+
+/// The floating-point color type with the same channels, so that conversions do not lose precision
+fn float_color_type(pixels: &DynamicImage) -> ColorType {
+    match pixels.color().has_alpha() {
+        true => ColorType::Rgba32F,
+        false => ColorType::Rgb32F,
+    }
+}
+
+/// The grayscale color type with the same depth and alpha as the image
+fn gray_color_type(image: &Image) -> ColorType {
+    let deep = image.pixels.color().bytes_per_pixel() / image.pixels.color().channel_count() > 1;
+    match (deep, image.pixels.color().has_alpha()) {
+        (false, false) => ColorType::L8,
+        (false, true) => ColorType::La8,
+        (true, false) => ColorType::L16,
+        (true, true) => ColorType::La16,
+    }
+}
+
+fn weighted_sum(values: [f64; 3], weights: [f64; 3]) -> f64 {
+    values.iter().zip(weights).map(|(v, w)| v * w).sum()
+}
+
+/// Hue in turns, and the chroma, i.e. the difference between the largest and the smallest channel
+pub fn hue_and_chroma([r, g, b]: [f64; 3]) -> (f64, f64) {
+    let max = r.max(g).max(b);
+    let chroma = max - r.min(g).min(b);
+    let sector = if chroma <= 0.0 {
+        0.0
+    } else if max == r {
+        ((g - b) / chroma).rem_euclid(6.0)
+    } else if max == g {
+        (b - r) / chroma + 2.0
+    } else {
+        (r - g) / chroma + 4.0
+    };
+    (sector / 6.0, chroma)
+}
+
+/// The fully saturated color of the hue with the given chroma, with its smallest channel at 0
+pub fn hue_to_rgb(hue: f64, chroma: f64) -> [f64; 3] {
+    let sector = hue.rem_euclid(1.0) * 6.0;
+    let x = chroma * (1.0 - (sector % 2.0 - 1.0).abs());
+    match sector as u32 {
+        0 => [chroma, x, 0.0],
+        1 => [x, chroma, 0.0],
+        2 => [0.0, chroma, x],
+        3 => [0.0, x, chroma],
+        4 => [x, 0.0, chroma],
+        _ => [chroma, 0.0, x],
+    }
+}
+
+pub fn rgb_to_hsl(rgb: [f64; 3]) -> (f64, f64, f64) {
+    let (hue, chroma) = hue_and_chroma(rgb);
+    let max = rgb[0].max(rgb[1]).max(rgb[2]);
+    let lightness = max - chroma / 2.0;
+    let saturation = match lightness {
+        l if chroma <= 0.0 || l <= 0.0 || l >= 1.0 => 0.0,
+        l if l <= 0.5 => chroma / (2.0 * l),
+        l => chroma / (2.0 - 2.0 * l),
+    };
+    (hue, saturation, lightness)
+}
+
+pub fn hsl_to_rgb(hue: f64, saturation: f64, lightness: f64) -> [f64; 3] {
+    let chroma = match lightness <= 0.5 {
+        true => 2.0 * lightness * saturation,
+        false => (2.0 - 2.0 * lightness) * saturation,
+    };
+    let min = lightness - chroma / 2.0;
+    hue_to_rgb(hue, chroma).map(|channel| channel + min)
+}
+
+/// Same as imagemagick's ConvertRGBToCMYK(): black takes as much of the color as possible
+fn rgb_to_cmyk(rgb: [f64; 3]) -> [f64; 4] {
+    let [c, m, y] = rgb.map(|v| 1.0 - v);
+    let k = c.min(m).min(y);
+    if (k - 1.0).abs() < f64::EPSILON {
+        return [0.0, 0.0, 0.0, 1.0];
+    }
+    let [c, m, y] = [c, m, y].map(|v| (v - k) / (1.0 - k));
+    [c, m, y, k]
+}
+
+fn cmyk_to_rgb([c, m, y, k]: [f64; 4]) -> [f64; 3] {
+    [c, m, y].map(|v| (1.0 - v) * (1.0 - k))
+}
+
+fn decode_srgb_gamma(v: f64) -> f64 {
+    if v <= 0.0404482362771076 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn encode_srgb_gamma(v: f64) -> f64 {
+    if v <= 0.0031306684425005883 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+fn srgb_to_xyz(rgb: [f64; 3]) -> [f64; 3] {
+    let [r, g, b] = rgb.map(decode_srgb_gamma);
+    [
+        0.4124564 * r + 0.3575761 * g + 0.1804375 * b,
+        0.2126729 * r + 0.7151522 * g + 0.0721750 * b,
+        0.0193339 * r + 0.1191920 * g + 0.9503041 * b,
+    ]
+}
+
+fn xyz_to_srgb([x, y, z]: [f64; 3]) -> [f64; 3] {
+    [
+        3.2404542 * x - 1.5371385 * y - 0.4985314 * z,
+        -0.9692660 * x + 1.8760108 * y + 0.0415560 * z,
+        0.0556434 * x - 0.2040259 * y + 1.0572252 * z,
+    ]
+    .map(encode_srgb_gamma)
+}
+
+const CIE_EPSILON: f64 = 216.0 / 24389.0;
+const CIE_K: f64 = 24389.0 / 27.0;
+
+/// L is scaled from `[0, 100]` to `[0, 1]`, and a and b from `[-127.5, 127.5]` to `[0, 1]`,
+/// like in imagemagick
+fn srgb_to_lab(rgb: [f64; 3]) -> [f64; 3] {
+    let xyz = srgb_to_xyz(rgb);
+    let [fx, fy, fz] = [0, 1, 2].map(|c| {
+        let t = xyz[c] / D65[c];
+        match t > CIE_EPSILON {
+            true => t.cbrt(),
+            false => (CIE_K * t + 16.0) / 116.0,
+        }
+    });
+    [
+        (116.0 * fy - 16.0) / 100.0,
+        500.0 * (fx - fy) / 255.0 + 0.5,
+        200.0 * (fy - fz) / 255.0 + 0.5,
+    ]
+}
+
+fn lab_to_srgb([l, a, b]: [f64; 3]) -> [f64; 3] {
+    let fy = (100.0 * l + 16.0) / 116.0;
+    let fx = fy + 255.0 * (a - 0.5) / 500.0;
+    let fz = fy - 255.0 * (b - 0.5) / 200.0;
+    let inverse = |f: f64| match f * f * f > CIE_EPSILON {
+        true => f * f * f,
+        false => (116.0 * f - 16.0) / CIE_K,
+    };
+    let xyz = [inverse(fx), inverse(fy), inverse(fz)];
+    xyz_to_srgb([0, 1, 2].map(|c| xyz[c] * D65[c]))
+}
+
+/// Hue, saturation and brightness, where brightness is the largest channel
+fn rgb_to_hsb(rgb: [f64; 3]) -> [f64; 3] {
+    let (hue, chroma) = hue_and_chroma(rgb);
+    let brightness = rgb[0].max(rgb[1]).max(rgb[2]);
+    let saturation = match brightness > 0.0 {
+        true => chroma / brightness,
+        false => 0.0,
+    };
+    [hue, saturation, brightness]
+}
+
+fn hsb_to_rgb([hue, saturation, brightness]: [f64; 3]) -> [f64; 3] {
+    let chroma = saturation * brightness;
+    let min = brightness - chroma;
+    hue_to_rgb(hue, chroma).map(|channel| channel + min)
+}
+
+/// The Rec. 601 luma weights imagemagick uses for YCbCr
+const YCBCR_LUMA: [f64; 3] = [0.298839, 0.586811, 0.114350];
+
+/// Cb and Cr are the differences of blue and red from luma, scaled to `[-0.5, 0.5]`
+/// and offset by a half, so that gray is in the middle of the range
+fn rgb_to_ycbcr(rgb: [f64; 3]) -> [f64; 3] {
+    let [red_weight, _, blue_weight] = YCBCR_LUMA;
+    let y = weighted_sum(rgb, YCBCR_LUMA);
+    let cb = (rgb[2] - y) / (2.0 * (1.0 - blue_weight));
+    let cr = (rgb[0] - y) / (2.0 * (1.0 - red_weight));
+    [y, cb + 0.5, cr + 0.5]
+}
+
+fn ycbcr_to_rgb([y, cb, cr]: [f64; 3]) -> [f64; 3] {
+    let [red_weight, green_weight, blue_weight] = YCBCR_LUMA;
+    let red = y + 2.0 * (1.0 - red_weight) * (cr - 0.5);
+    let blue = y + 2.0 * (1.0 - blue_weight) * (cb - 0.5);
+    let green = (y - red_weight * red - blue_weight * blue) / green_weight;
+    [red, green, blue]
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgb, RgbImage, Rgba, RgbaImage};
+
+    use super::*;
+
+    fn assert_close(a: [f64; 3], b: [f64; 3]) {
+        for c in 0..3 {
+            assert!((a[c] - b[c]).abs() < 1e-4, "{a:?} {b:?}");
+        }
+    }
+
+    #[test]
+    fn lab_values() {
+        assert_close(srgb_to_lab([1.0; 3]), [1.0, 0.5, 0.5]);
+        assert_close(srgb_to_lab([0.0; 3]), [0.0, 0.5, 0.5]);
+        // L*a*b* of pure red is about (53.24, 80.09, 67.20)
+        let [l, a, b] = srgb_to_lab([1.0, 0.0, 0.0]);
+        assert_close(
+            [l * 100.0, (a - 0.5) * 255.0, (b - 0.5) * 255.0],
+            [53.2408, 80.0925, 67.2032],
+        );
+        for rgb in [[0.2, 0.4, 0.6], [0.01, 0.9, 0.5]] {
+            assert_close(lab_to_srgb(srgb_to_lab(rgb)), rgb);
+        }
+    }
+
+    #[test]
+    fn three_channel_values() {
+        assert_close(srgb_to_xyz([1.0; 3]), D65);
+        assert_close(rgb_to_hsb([0.5, 0.25, 0.5]), [5.0 / 6.0, 0.5, 0.5]);
+        assert_close(rgb_to_ycbcr([1.0, 1.0, 1.0]), [1.0, 0.5, 0.5]);
+        assert_close(rgb_to_ycbcr([0.0, 0.0, 1.0]), [0.11435, 1.0, 0.418457]);
+        for rgb in [[0.2, 0.4, 0.6], [0.9, 0.8, 0.1], [0.3, 0.3, 0.3]] {
+            assert_close(xyz_to_srgb(srgb_to_xyz(rgb)), rgb);
+            assert_close(hsb_to_rgb(rgb_to_hsb(rgb)), rgb);
+            assert_close(ycbcr_to_rgb(rgb_to_ycbcr(rgb)), rgb);
+        }
+    }
+
+    #[test]
+    fn cmyk_values() {
+        assert_eq!(rgb_to_cmyk([1.0, 0.0, 0.0]), [0.0, 1.0, 1.0, 0.0]);
+        assert_eq!(rgb_to_cmyk([0.0; 3]), [0.0, 0.0, 0.0, 1.0]);
+        let [c, m, y, k] = rgb_to_cmyk([0.5, 0.25, 0.5]);
+        assert_close([c, m, y], [0.0, 0.5, 0.0]);
+        assert!((k - 0.5).abs() < 1e-9);
+        assert_close(cmyk_to_rgb([c, m, y, k]), [0.5, 0.25, 0.5]);
+    }
+
+    #[test]
+    fn round_trips() {
+        let pixels = RgbImage::from_fn(8, 8, |x, y| Rgb([x as u8 * 30, y as u8 * 30, 100]));
+        for colorspace in [
+            Colorspace::Lab,
+            Colorspace::Cmyk,
+            Colorspace::Xyz,
+            Colorspace::Hsl,
+            Colorspace::Hsb,
+            Colorspace::YCbCr,
+        ] {
+            let mut image = Image::new(DynamicImage::ImageRgb8(pixels.clone()));
+            transform(&mut image, colorspace);
+            assert_eq!(image.colorspace, colorspace);
+            let srgb = to_srgb(&image);
+            assert_eq!(srgb.colorspace, Colorspace::Srgb);
+            assert_eq!(srgb.pixels.as_rgb8().unwrap(), &pixels);
+            transform(&mut image, Colorspace::Srgb);
+            assert_eq!(image.pixels.as_rgb8().unwrap(), &pixels);
+        }
+    }
+
+    #[test]
+    fn gray_keeps_alpha() {
+        let pixels = RgbaImage::from_pixel(1, 1, Rgba([255, 0, 0, 128]));
+        let mut image = Image::new(DynamicImage::ImageRgba8(pixels));
+        transform(&mut image, Colorspace::Gray);
+        assert_eq!(image.colorspace, Colorspace::Srgb);
+        let gray = image.pixels.as_luma_alpha8().unwrap().get_pixel(0, 0);
+        assert_eq!(gray.0, [54, 128]);
+    }
+}
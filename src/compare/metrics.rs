@@ -0,0 +1,302 @@
+//! Implementations of the metrics for `compare -metric`.
+//!
+//! All of them operate on samples normalized to the `[0, 1]` range,
+//! so images of different bit depths can be compared against each other.
+
+use image::{DynamicImage, GenericImageView};
+
+use crate::arg_parsers::Metric;
+use crate::operations::blur::gaussian_weights;
+
+/// Two images of the same size, split into planes of normalized samples
+struct Planes {
+    width: usize,
+    height: usize,
+    /// `(reference, compared)` pairs, one for each channel
+    channels: Vec<(Vec<f64>, Vec<f64>)>,
+}
+
+impl Planes {
+    /// The color channels are always compared. Alpha is only compared if either image has it.
+    fn new(a: &DynamicImage, b: &DynamicImage) -> Self {
+        let channel_count = if a.color().has_alpha() || b.color().has_alpha() {
+            4
+        } else {
+            3
+        };
+        let (a, b) = (a.to_rgba32f(), b.to_rgba32f());
+        let plane = |samples: &[f32], channel: usize| -> Vec<f64> {
+            samples
+                .chunks_exact(4)
+                .map(|pixel| f64::from(pixel[channel]))
+                .collect()
+        };
+        let channels = (0..channel_count)
+            .map(|c| (plane(a.as_raw(), c), plane(b.as_raw(), c)))
+            .collect();
+        Self {
+            width: a.width() as usize,
+            height: a.height() as usize,
+            channels,
+        }
+    }
+
+    fn sample_count(&self) -> f64 {
+        (self.width * self.height * self.channels.len()) as f64
+    }
+
+    fn differences(&self) -> impl Iterator<Item = f64> + '_ {
+        self.channels
+            .iter()
+            .flat_map(|(a, b)| a.iter().zip(b).map(|(a, b)| a - b))
+    }
+}
+
+/// Computes the metric for two images of the same dimensions.
+///
+/// Distortion metrics are 0 for identical images, similarity metrics (see [Metric::is_similarity])
+/// are at their maximum: 1 for NCC and SSIM and infinity for PSNR.
+pub fn compute(metric: Metric, a: &DynamicImage, b: &DynamicImage) -> f64 {
+    assert_eq!(a.dimensions(), b.dimensions());
+    let planes = Planes::new(a, b);
+    match metric {
+        Metric::Ae => absolute_error(&planes),
+        Metric::Mae => planes.differences().map(f64::abs).sum::<f64>() / planes.sample_count(),
+        Metric::Mse => mean_squared_error(&planes),
+        Metric::Rmse => mean_squared_error(&planes).sqrt(),
+        Metric::Psnr => {
+            let mse = mean_squared_error(&planes);
+            if mse == 0.0 {
+                f64::INFINITY
+            } else {
+                10.0 * (1.0 / mse).log10()
+            }
+        }
+        Metric::Ncc => average_over_channels(&planes, cross_correlation),
+        Metric::Ssim => average_over_channels(&planes, |a, b| {
+            structural_similarity(a, b, planes.width, planes.height)
+        }),
+        Metric::Phash => perceptual_hash_distance(&planes),
+    }
+}
+
+/// Number of pixels that differ in any channel
+fn absolute_error(planes: &Planes) -> f64 {
+    let pixel_count = planes.width * planes.height;
+    (0..pixel_count)
+        .filter(|&i| planes.channels.iter().any(|(a, b)| a[i] != b[i]))
+        .count() as f64
+}
+
+fn mean_squared_error(planes: &Planes) -> f64 {
+    planes.differences().map(|d| d * d).sum::<f64>() / planes.sample_count()
+}
+
+fn average_over_channels(planes: &Planes, f: impl Fn(&[f64], &[f64]) -> f64) -> f64 {
+    let sum: f64 = planes.channels.iter().map(|(a, b)| f(a, b)).sum();
+    sum / planes.channels.len() as f64
+}
+
+fn mean(samples: &[f64]) -> f64 {
+    samples.iter().sum::<f64>() / samples.len() as f64
+}
+
+/// Pearson correlation coefficient of the two channels
+fn cross_correlation(a: &[f64], b: &[f64]) -> f64 {
+    let (mean_a, mean_b) = (mean(a), mean(b));
+    let (mut covariance, mut variance_a, mut variance_b) = (0.0, 0.0, 0.0);
+    for (a, b) in a.iter().zip(b) {
+        let (da, db) = (a - mean_a, b - mean_b);
+        covariance += da * db;
+        variance_a += da * da;
+        variance_b += db * db;
+    }
+    let denominator = (variance_a * variance_b).sqrt();
+    if denominator == 0.0 {
+        // Correlation is undefined for flat channels; treat them as matching only if they are equal
+        if a == b {
+            1.0
+        } else {
+            0.0
+        }
+    } else {
+        covariance / denominator
+    }
+}
+
+/// Standard deviation of the Gaussian window used by SSIM, same as in the original paper and imagemagick
+const SSIM_SIGMA: f64 = 1.5;
+const SSIM_RADIUS: usize = 5;
+const SSIM_K1: f64 = 0.01;
+const SSIM_K2: f64 = 0.03;
+
+/// Mean structural similarity index of the two channels, computed over a Gaussian window
+fn structural_similarity(a: &[f64], b: &[f64], width: usize, height: usize) -> f64 {
+    let kernel = gaussian_weights(SSIM_SIGMA, SSIM_RADIUS);
+    let blur = |samples: Vec<f64>| gaussian_blur(&samples, width, height, &kernel);
+    let mean_a = blur(a.to_vec());
+    let mean_b = blur(b.to_vec());
+    let square_a = blur(a.iter().map(|v| v * v).collect());
+    let square_b = blur(b.iter().map(|v| v * v).collect());
+    let product = blur(a.iter().zip(b).map(|(a, b)| a * b).collect());
+
+    let c1 = SSIM_K1 * SSIM_K1;
+    let c2 = SSIM_K2 * SSIM_K2;
+    let sum: f64 = (0..a.len())
+        .map(|i| {
+            let (ma, mb) = (mean_a[i], mean_b[i]);
+            let variance_a = square_a[i] - ma * ma;
+            let variance_b = square_b[i] - mb * mb;
+            let covariance = product[i] - ma * mb;
+            ((2.0 * ma * mb + c1) * (2.0 * covariance + c2))
+                / ((ma * ma + mb * mb + c1) * (variance_a + variance_b + c2))
+        })
+        .sum();
+    sum / a.len() as f64
+}
+
+/// Separable convolution with the given kernel. Edge pixels are repeated past the border.
+fn gaussian_blur(samples: &[f64], width: usize, height: usize, kernel: &[f64]) -> Vec<f64> {
+    let radius = kernel.len() / 2;
+    let clamp = |v: isize, max: usize| v.clamp(0, max as isize - 1) as usize;
+    let mut horizontal = vec![0.0; samples.len()];
+    for y in 0..height {
+        for x in 0..width {
+            horizontal[y * width + x] = kernel
+                .iter()
+                .enumerate()
+                .map(|(k, weight)| {
+                    let sx = clamp(x as isize + k as isize - radius as isize, width);
+                    weight * samples[y * width + sx]
+                })
+                .sum();
+        }
+    }
+    let mut output = vec![0.0; samples.len()];
+    for y in 0..height {
+        for x in 0..width {
+            output[y * width + x] = kernel
+                .iter()
+                .enumerate()
+                .map(|(k, weight)| {
+                    let sy = clamp(y as isize + k as isize - radius as isize, height);
+                    weight * horizontal[sy * width + x]
+                })
+                .sum();
+        }
+    }
+    output
+}
+
+/// Sum of squared differences between the perceptual hashes of the color channels.
+///
+/// Like imagemagick, the hash of a channel consists of its 7 Hu image moments on a log scale.
+/// imagemagick additionally hashes the image in the HCLp colorspace; we only use sRGB,
+/// so our values are roughly half of what imagemagick reports.
+fn perceptual_hash_distance(planes: &Planes) -> f64 {
+    planes
+        .channels
+        .iter()
+        .take(3)
+        .map(|(a, b)| {
+            let hash_a = perceptual_hash(a, planes.width);
+            let hash_b = perceptual_hash(b, planes.width);
+            hash_a
+                .iter()
+                .zip(&hash_b)
+                .map(|(a, b)| (a - b) * (a - b))
+                .sum::<f64>()
+        })
+        .sum()
+}
+
+fn perceptual_hash(samples: &[f64], width: usize) -> [f64; 7] {
+    hu_moments(samples, width).map(|moment| {
+        if moment.abs() < f64::EPSILON {
+            0.0
+        } else {
+            -moment.signum() * moment.abs().log10()
+        }
+    })
+}
+
+/// The 7 Hu moments, which are invariant to translation, scale and rotation.
+/// See <https://en.wikipedia.org/wiki/Image_moment#Rotation_invariants>
+fn hu_moments(samples: &[f64], width: usize) -> [f64; 7] {
+    let pixels = || {
+        samples
+            .iter()
+            .enumerate()
+            .map(move |(i, v)| ((i % width) as f64, (i / width) as f64, *v))
+    };
+    let m00: f64 = samples.iter().sum();
+    if m00 == 0.0 {
+        return [0.0; 7];
+    }
+    let x_mean = pixels().map(|(x, _, v)| x * v).sum::<f64>() / m00;
+    let y_mean = pixels().map(|(_, y, v)| y * v).sum::<f64>() / m00;
+    // normalized central moment of order p+q
+    let eta = |p: i32, q: i32| {
+        let mu: f64 = pixels()
+            .map(|(x, y, v)| (x - x_mean).powi(p) * (y - y_mean).powi(q) * v)
+            .sum();
+        mu / m00.powf(1.0 + f64::from(p + q) / 2.0)
+    };
+    let (n20, n02, n11) = (eta(2, 0), eta(0, 2), eta(1, 1));
+    let (n30, n03, n21, n12) = (eta(3, 0), eta(0, 3), eta(2, 1), eta(1, 2));
+
+    let (s1, s2) = (n30 + n12, n21 + n03);
+    let (d1, d2) = (n30 - 3.0 * n12, 3.0 * n21 - n03);
+    [
+        n20 + n02,
+        (n20 - n02).powi(2) + 4.0 * n11 * n11,
+        d1 * d1 + d2 * d2,
+        s1 * s1 + s2 * s2,
+        d1 * s1 * (s1 * s1 - 3.0 * s2 * s2) + d2 * s2 * (3.0 * s1 * s1 - s2 * s2),
+        (n20 - n02) * (s1 * s1 - s2 * s2) + 4.0 * n11 * s1 * s2,
+        d2 * s1 * (s1 * s1 - 3.0 * s2 * s2) - d1 * s2 * (3.0 * s1 * s1 - s2 * s2),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{GrayImage, Luma};
+
+    use super::*;
+
+    fn gradient(offset: u8) -> DynamicImage {
+        DynamicImage::ImageLuma8(GrayImage::from_fn(16, 16, |x, y| {
+            Luma([(x * 8 + y * 4) as u8 + offset])
+        }))
+    }
+
+    #[test]
+    fn identical_images() {
+        let image = gradient(0);
+        for metric in [
+            Metric::Ae,
+            Metric::Mae,
+            Metric::Mse,
+            Metric::Rmse,
+            Metric::Phash,
+        ] {
+            assert_eq!(compute(metric, &image, &image), 0.0, "{metric:?}");
+        }
+        assert_eq!(compute(Metric::Psnr, &image, &image), f64::INFINITY);
+        assert!((compute(Metric::Ncc, &image, &image) - 1.0).abs() < 1e-9);
+        assert!((compute(Metric::Ssim, &image, &image) - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn brightness_shift() {
+        // samples go through f32, so the results are only accurate to about 7 digits
+        let (a, b) = (gradient(0), gradient(51));
+        assert_eq!(compute(Metric::Ae, &a, &b), 256.0);
+        assert!((compute(Metric::Mae, &a, &b) - 0.2).abs() < 1e-6);
+        // 10 * log10(1 / 0.2^2)
+        assert!((compute(Metric::Psnr, &a, &b) - 13.979400086720377).abs() < 1e-6);
+        // correlation ignores the brightness offset entirely
+        assert!((compute(Metric::Ncc, &a, &b) - 1.0).abs() < 1e-6);
+        assert!(compute(Metric::Ssim, &a, &b) < 1.0);
+    }
+}
@@ -0,0 +1,242 @@
+//! `compare`: mathematically and visually annotates the difference between two images.
+//!
+//! Like imagemagick, the metric is printed to stderr and the exit code is 0 if the images are identical,
+//! 1 if they are not and 2 on errors.
+
+mod metrics;
+mod search;
+
+use std::ffi::OsString;
+use std::process::ExitCode;
+
+use image::{DynamicImage, GenericImageView, Rgba, RgbaImage};
+
+use crate::arg_parsers::{parse_numeric_arg, Metric};
+use crate::args::starts_with_sign;
+use crate::decode::decode;
+use crate::encode::{encode, split_format_prefix};
+use crate::image::{Image, QUANTUM_RANGE};
+use crate::plan::Modifiers;
+use crate::pseudo::PseudoOutput;
+use crate::utils::number_format::format_g;
+use crate::{error::MagickError, wm_err};
+
+/// Color of the pixels that differ in the difference image, imagemagick's default `-highlight-color`
+const HIGHLIGHT_COLOR: Rgba<u8> = Rgba([241, 0, 30, 204]);
+/// Color of the pixels that are the same in the difference image, imagemagick's default `-lowlight-color`
+const LOWLIGHT_COLOR: Rgba<u8> = Rgba([255, 255, 255, 204]);
+
+#[derive(Debug)]
+pub struct ComparePlan {
+    pub metric: Metric,
+    pub reference_file: OsString,
+    pub compared_file: OsString,
+    /// Where to write the image highlighting the differences, if anywhere
+    pub difference_file: Option<OsString>,
+    /// `-subimage-search`: look for the compared image within the larger reference image
+    pub subimage_search: bool,
+    /// `-similarity-threshold`: stop the subimage search at the first match with this distortion or less
+    pub similarity_threshold: Option<f64>,
+    pub modifiers: Modifiers,
+}
+
+pub fn parse_args(args: Vec<OsString>) -> Result<ComparePlan, MagickError> {
+    let mut metric = Metric::default();
+    let mut subimage_search = false;
+    let mut similarity_threshold = None;
+    let mut precision = None;
+    let mut files = Vec::new();
+    let mut iter = args.into_iter().skip(1); // skip argv[0], path to our binary
+    while let Some(arg) = iter.next() {
+        if arg == "-metric" {
+            let Some(value) = iter.next() else {
+                return Err(wm_err!("argument requires a value: metric"));
+            };
+            metric = Metric::try_from(value.as_os_str())?;
+        } else if arg == "-subimage-search" {
+            subimage_search = true;
+        } else if arg == "-similarity-threshold" {
+            let Some(value) = iter.next() else {
+                return Err(wm_err!("argument requires a value: similarity-threshold"));
+            };
+            similarity_threshold = Some(parse_numeric_arg("similarity-threshold", &value)?);
+        } else if arg == "-precision" {
+            let Some(value) = iter.next() else {
+                return Err(wm_err!("argument requires a value: precision"));
+            };
+            precision = Some(parse_numeric_arg("precision", &value)?);
+        } else if starts_with_sign(&arg) && arg != "-" {
+            return Err(wm_err!("unrecognized option `{}'", arg.to_string_lossy()));
+        } else {
+            files.push(arg);
+        }
+    }
+
+    let mut files = files.into_iter();
+    let (Some(reference_file), Some(compared_file)) = (files.next(), files.next()) else {
+        return Err(wm_err!("missing an image filename"));
+    };
+    let difference_file = files.next();
+    if let Some(extra) = files.next() {
+        return Err(wm_err!("unexpected argument `{}'", extra.to_string_lossy()));
+    }
+    Ok(ComparePlan {
+        metric,
+        reference_file,
+        compared_file,
+        difference_file,
+        subimage_search,
+        similarity_threshold,
+        modifiers: Modifiers {
+            precision,
+            ..Modifiers::from_env()
+        },
+    })
+}
+
+impl ComparePlan {
+    pub fn execute(&self) -> Result<ExitCode, MagickError> {
+        let mut reference = decode(&self.reference_file, None, &self.modifiers)?.pixels;
+        let mut compared = decode(&self.compared_file, None, &self.modifiers)?.pixels;
+        let mut location = None;
+        if reference.dimensions() != compared.dimensions() {
+            if !self.subimage_search {
+                return Err(wm_err!(
+                    "image widths or heights differ `{}'",
+                    self.compared_file.to_string_lossy()
+                ));
+            }
+            // The usual order is the large image first, but accept the other one as well
+            if fits_within(&reference, &compared) {
+                std::mem::swap(&mut reference, &mut compared);
+            }
+            if !fits_within(&compared, &reference) {
+                return Err(wm_err!(
+                    "image widths or heights differ `{}'",
+                    self.compared_file.to_string_lossy()
+                ));
+            }
+            let found = search::subimage_search(&reference, &compared, self.similarity_threshold);
+            // Everything else only looks at the area where the match was found
+            reference = reference.crop_imm(found.x, found.y, compared.width(), compared.height());
+            location = Some(found);
+        }
+
+        let value = metrics::compute(self.metric, &reference, &compared);
+        match location {
+            Some(found) => eprintln!(
+                "{} @ {},{}",
+                format_metric(self.metric, value, self.modifiers.precision()),
+                found.x,
+                found.y
+            ),
+            None => eprintln!(
+                "{}",
+                format_metric(self.metric, value, self.modifiers.precision())
+            ),
+        }
+
+        if let Some(file) = &self.difference_file {
+            // `null:` is the conventional way to discard the difference image
+            if PseudoOutput::parse(file).is_none() {
+                let difference = difference_image(&reference, &compared);
+                let (format, file) = split_format_prefix(file)?;
+                encode(&Image::new(difference), file, format, &self.modifiers)?;
+            }
+        }
+
+        let identical = if self.metric == Metric::Ae {
+            value == 0.0
+        } else {
+            metrics::compute(Metric::Ae, &reference, &compared) == 0.0
+        };
+        Ok(if identical {
+            ExitCode::SUCCESS
+        } else {
+            ExitCode::from(1)
+        })
+    }
+}
+
+fn fits_within(inner: &DynamicImage, outer: &DynamicImage) -> bool {
+    inner.width() <= outer.width() && inner.height() <= outer.height()
+}
+
+/// Formats the value the way imagemagick prints it
+fn format_metric(metric: Metric, value: f64, precision: usize) -> String {
+    match metric {
+        Metric::Ae => format!("{value}"),
+        // errors are printed both in the quantum range and normalized
+        Metric::Mae | Metric::Mse | Metric::Rmse => format!(
+            "{} ({})",
+            format_g(value * QUANTUM_RANGE, precision),
+            format_g(value, precision)
+        ),
+        Metric::Ncc | Metric::Phash | Metric::Psnr | Metric::Ssim => format_g(value, precision),
+    }
+}
+
+/// Shows the reference image faded out, with the pixels that differ highlighted in red
+fn difference_image(reference: &DynamicImage, compared: &DynamicImage) -> DynamicImage {
+    let (reference, compared) = (reference.to_rgba8(), compared.to_rgba8());
+    let difference = RgbaImage::from_fn(reference.width(), reference.height(), |x, y| {
+        let pixel = reference.get_pixel(x, y);
+        let overlay = if pixel == compared.get_pixel(x, y) {
+            LOWLIGHT_COLOR
+        } else {
+            HIGHLIGHT_COLOR
+        };
+        let alpha = u16::from(overlay[3]);
+        let blend = |over: u8, under: u8| {
+            ((u16::from(over) * alpha + u16::from(under) * (255 - alpha) + 127) / 255) as u8
+        };
+        Rgba([
+            blend(overlay[0], pixel[0]),
+            blend(overlay[1], pixel[1]),
+            blend(overlay[2], pixel[2]),
+            255,
+        ])
+    });
+    DynamicImage::ImageRgba8(difference)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<OsString> {
+        std::iter::once("compare")
+            .chain(args.iter().copied())
+            .map(OsString::from)
+            .collect()
+    }
+
+    #[test]
+    fn parse_metric() {
+        let plan = parse_args(args(&["-metric", "ssim", "a.png", "b.png", "null:"])).unwrap();
+        assert_eq!(plan.metric, Metric::Ssim);
+        assert_eq!(plan.reference_file, "a.png");
+        assert_eq!(plan.compared_file, "b.png");
+        assert_eq!(plan.difference_file.as_deref(), Some("null:".as_ref()));
+    }
+
+    #[test]
+    fn parse_subimage_search() {
+        let plan = parse_args(args(&[
+            "-subimage-search",
+            "-similarity-threshold",
+            "0.01",
+            "big.png",
+            "small.png",
+        ]))
+        .unwrap();
+        assert!(plan.subimage_search);
+        assert_eq!(plan.similarity_threshold, Some(0.01));
+        assert_eq!(plan.difference_file, None);
+    }
+
+    #[test]
+    fn missing_file() {
+        assert!(parse_args(args(&["-metric", "AE", "a.png"])).is_err());
+    }
+}
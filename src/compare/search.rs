@@ -0,0 +1,210 @@
+//! `compare -subimage-search`: finds the location of a smaller image within a larger one.
+//!
+//! Candidates are ranked by normalized cross-correlation, which is insensitive to brightness and contrast changes.
+//! The window sums and variances that normalize the correlation are looked up from integral images in constant time,
+//! so only the correlation itself has to be computed for every position.
+
+use image::DynamicImage;
+
+/// Location of the best match of the template within the image
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Match {
+    pub x: u32,
+    pub y: u32,
+    /// Normalized cross-correlation of the template and the matched area, averaged over color channels
+    pub similarity: f64,
+}
+
+/// Finds the position where the template matches the image best.
+///
+/// If `similarity_threshold` is set, the search stops at the first position
+/// where the distortion (`1 - similarity`) is at or below the threshold.
+/// The template must not be larger than the image in either dimension.
+pub fn subimage_search(
+    image: &DynamicImage,
+    template: &DynamicImage,
+    similarity_threshold: Option<f64>,
+) -> Match {
+    assert!(template.width() <= image.width() && template.height() <= image.height());
+    let (image, template) = (image.to_rgb32f(), template.to_rgb32f());
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    let (t_width, t_height) = (template.width() as usize, template.height() as usize);
+
+    let channels: Vec<ChannelSearch> = (0..3)
+        .map(|c| {
+            ChannelSearch::new(
+                &plane(image.as_raw(), c),
+                width,
+                height,
+                &plane(template.as_raw(), c),
+                t_width,
+                t_height,
+            )
+        })
+        .collect();
+
+    let mut best = Match {
+        x: 0,
+        y: 0,
+        similarity: f64::NEG_INFINITY,
+    };
+    for y in 0..=height - t_height {
+        for x in 0..=width - t_width {
+            let similarity = channels
+                .iter()
+                .map(|channel| channel.correlation(x, y))
+                .sum::<f64>()
+                / channels.len() as f64;
+            if similarity > best.similarity {
+                best = Match {
+                    x: x as u32,
+                    y: y as u32,
+                    similarity,
+                };
+                if similarity_threshold.is_some_and(|threshold| 1.0 - similarity <= threshold) {
+                    return best;
+                }
+            }
+        }
+    }
+    best
+}
+
+fn plane(samples: &[f32], channel: usize) -> Vec<f64> {
+    samples
+        .chunks_exact(3)
+        .map(|pixel| f64::from(pixel[channel]))
+        .collect()
+}
+
+/// Precomputed data for searching a single channel
+struct ChannelSearch {
+    image: Vec<f64>,
+    width: usize,
+    /// Template with its mean subtracted, so that the correlation does not depend on the window mean
+    template: Vec<f64>,
+    t_width: usize,
+    t_height: usize,
+    template_mean: f64,
+    template_norm: f64,
+    sums: IntegralImage,
+    squared_sums: IntegralImage,
+}
+
+impl ChannelSearch {
+    fn new(
+        image: &[f64],
+        width: usize,
+        height: usize,
+        template: &[f64],
+        t_width: usize,
+        t_height: usize,
+    ) -> Self {
+        let template_mean = template.iter().sum::<f64>() / template.len() as f64;
+        let template: Vec<f64> = template.iter().map(|v| v - template_mean).collect();
+        let template_norm = template.iter().map(|v| v * v).sum::<f64>().sqrt();
+        let squared: Vec<f64> = image.iter().map(|v| v * v).collect();
+        Self {
+            image: image.to_vec(),
+            width,
+            template,
+            t_width,
+            t_height,
+            template_mean,
+            template_norm,
+            sums: IntegralImage::new(image, width, height),
+            squared_sums: IntegralImage::new(&squared, width, height),
+        }
+    }
+
+    /// Normalized cross-correlation of the template placed at `(x, y)`
+    fn correlation(&self, x: usize, y: usize) -> f64 {
+        let n = (self.t_width * self.t_height) as f64;
+        let sum = self.sums.sum(x, y, self.t_width, self.t_height);
+        let squared_sum = self.squared_sums.sum(x, y, self.t_width, self.t_height);
+        let window_norm = (squared_sum - sum * sum / n).max(0.0).sqrt();
+
+        // Both norms being tiny means both areas are flat, so only their levels can be compared
+        const EPSILON: f64 = 1e-12;
+        if window_norm < EPSILON || self.template_norm < EPSILON {
+            let same_level = (sum / n - self.template_mean).abs() < 1e-6;
+            let both_flat = window_norm < EPSILON && self.template_norm < EPSILON;
+            return if both_flat && same_level { 1.0 } else { 0.0 };
+        }
+
+        let mut numerator = 0.0;
+        for v in 0..self.t_height {
+            let image_row = &self.image[(y + v) * self.width + x..][..self.t_width];
+            let template_row = &self.template[v * self.t_width..][..self.t_width];
+            numerator += image_row
+                .iter()
+                .zip(template_row)
+                .map(|(i, t)| i * t)
+                .sum::<f64>();
+        }
+        numerator / (window_norm * self.template_norm)
+    }
+}
+
+/// Summed-area table: the sum of any rectangle can be looked up in constant time
+struct IntegralImage {
+    /// Has an extra row and column of zeroes at the top and left to avoid special-casing the edges
+    table: Vec<f64>,
+    stride: usize,
+}
+
+impl IntegralImage {
+    fn new(samples: &[f64], width: usize, height: usize) -> Self {
+        let stride = width + 1;
+        let mut table = vec![0.0; stride * (height + 1)];
+        for y in 0..height {
+            let mut row_sum = 0.0;
+            for x in 0..width {
+                row_sum += samples[y * width + x];
+                table[(y + 1) * stride + x + 1] = table[y * stride + x + 1] + row_sum;
+            }
+        }
+        Self { table, stride }
+    }
+
+    fn sum(&self, x: usize, y: usize, width: usize, height: usize) -> f64 {
+        let at = |x: usize, y: usize| self.table[y * self.stride + x];
+        at(x + width, y + height) - at(x, y + height) - at(x + width, y) + at(x, y)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{GrayImage, Luma};
+
+    use super::*;
+
+    /// Pseudo-random but deterministic texture, so that the template matches in exactly one place
+    fn texture(width: u32, height: u32) -> GrayImage {
+        GrayImage::from_fn(width, height, |x, y| {
+            Luma([((x * 7919 + y * 104729) ^ (x * y * 31)) as u8])
+        })
+    }
+
+    #[test]
+    fn finds_template() {
+        let image = texture(40, 30);
+        let template = image::imageops::crop_imm(&image, 13, 9, 8, 6).to_image();
+        let found = subimage_search(
+            &DynamicImage::ImageLuma8(image),
+            &DynamicImage::ImageLuma8(template),
+            None,
+        );
+        assert_eq!((found.x, found.y), (13, 9));
+        assert!((found.similarity - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn integral_image_sums() {
+        let samples: Vec<f64> = (0..12).map(f64::from).collect();
+        let table = IntegralImage::new(&samples, 4, 3);
+        // the 2x2 block at (1, 1) covers 5, 6, 9 and 10
+        assert_eq!(table.sum(1, 1, 2, 2), 30.0);
+        assert_eq!(table.sum(0, 0, 4, 3), 66.0);
+    }
+}
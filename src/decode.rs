@@ -1,20 +1,474 @@
 use std::ffi::OsStr;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Seek};
+use std::path::Path;
 
-use image::{DynamicImage, ImageDecoder, ImageFormat, ImageReader};
+use image::codecs::{gif::GifDecoder, png::PngDecoder, webp::WebPDecoder};
+use image::{
+    AnimationDecoder, DynamicImage, ExtendedColorType, ImageDecoder, ImageFormat, ImageReader,
+};
 
-use crate::{error::MagickError, wm_try};
+#[cfg(feature = "jp2")]
+use wondermagick_jp2::{Jp2Decoder, J2K_MAGIC, JP2_MAGIC};
+#[cfg(feature = "psd")]
+use wondermagick_psd::PsdDecoder;
+
+use crate::decoders::tiff::TiffPages;
+use crate::{
+    arg_parsers::FrameSelection,
+    error::MagickError,
+    file_format::{sniff_unsupported, FileFormat},
+    image::Image,
+    limits::{Limits, Timed},
+    plan::Modifiers,
+    utils::{spool::spool_stdin, timer::Stopwatch},
+    wm_err, wm_try,
+};
 
 /// If the format has not been explicitly specified, guesses the format based on file contents.
-pub fn decode(file: &OsStr, format: Option<ImageFormat>) -> Result<DynamicImage, MagickError> {
-    let mut reader = wm_try!(ImageReader::open(file));
-    match format {
-        Some(format) => reader.set_format(format),
-        None => reader = wm_try!(reader.with_guessed_format()),
+///
+/// The filename `-` stands for stdin. Only the first frame of multi-frame images is read.
+pub fn decode(
+    file: &OsStr,
+    format: Option<FileFormat>,
+    modifiers: &Modifiers,
+) -> Result<Image, MagickError> {
+    let timer = Stopwatch::start();
+    let reader = open(file, modifiers)?;
+    let file_size = reader_size(&reader);
+    let mut image = decode_impl(reader, file, format, modifiers)?;
+    image.file_size = file_size;
+    image.timer = timer;
+    Ok(image)
+}
+
+/// Decodes the frames of a multi-frame image such as an animated GIF.
+///
+/// Without a selection all frames are read, otherwise the selected ones in the order they were selected.
+/// Formats that cannot hold multiple frames are treated as having exactly one.
+pub fn decode_frames(
+    file: &OsStr,
+    format: Option<FileFormat>,
+    selection: Option<&FrameSelection>,
+    modifiers: &Modifiers,
+) -> Result<Vec<Image>, MagickError> {
+    let timer = Stopwatch::start();
+    let mut reader = open(file, modifiers)?;
+    let file_size = reader_size(&reader);
+    let format = match format {
+        Some(format) => format,
+        None => guess_format(&mut reader, file)?,
+    };
+    modifiers.check_format_allowed(format)?;
+    let limits = &modifiers.limits;
+    let mut frames = match format {
+        FileFormat::Image(ImageFormat::Gif) => {
+            let mut decoder = wm_try!(GifDecoder::new(reader));
+            let metadata = animation_metadata(&mut decoder, limits)?;
+            read_animation(decoder, metadata, selection, limits)?
+        }
+        FileFormat::Image(ImageFormat::Png) => {
+            let mut decoder = wm_try!(PngDecoder::new(reader));
+            if wm_try!(decoder.is_apng()) {
+                let metadata = animation_metadata(&mut decoder, limits)?;
+                read_animation(wm_try!(decoder.apng()), metadata, selection, limits)?
+            } else {
+                select_still(load(decoder, limits)?, selection)
+            }
+        }
+        FileFormat::Image(ImageFormat::WebP) => {
+            let mut decoder = wm_try!(WebPDecoder::new(reader));
+            if decoder.has_animation() {
+                let metadata = animation_metadata(&mut decoder, limits)?;
+                read_animation(decoder, metadata, selection, limits)?
+            } else {
+                select_still(load(decoder, limits)?, selection)
+            }
+        }
+        FileFormat::Image(ImageFormat::Tiff) => read_pages(reader, limits, selection)?,
+        _ => select_still(
+            decode_impl(reader, file, Some(format), modifiers)?,
+            selection,
+        ),
+    };
+    if frames.is_empty() {
+        return Err(wm_err!("no images defined `{}'", file.to_string_lossy()));
+    }
+    for frame in &mut frames {
+        frame.filename = file.to_owned();
+        frame.format = Some(format);
+        frame.file_size = file_size;
+        frame.timer = timer;
+    }
+    Ok(frames)
+}
+
+fn open(file: &OsStr, modifiers: &Modifiers) -> Result<BufReader<Timed<File>>, MagickError> {
+    let limits = &modifiers.limits;
+    if file == "-" {
+        // The decoders need to seek, so we cannot read from stdin directly
+        let spooled = wm_try!(spool_stdin(modifiers.temporary_path.as_deref(), limits));
+        return Ok(BufReader::new(limits.timed(spooled)));
+    }
+    match File::open(file) {
+        Ok(file) => Ok(BufReader::new(limits.timed(file))),
+        Err(e) => Err(wm_err!(
+            "unable to open image `{}': {}",
+            file.to_string_lossy(),
+            e
+        )),
+    }
+}
+
+/// Returns `None` if the size cannot be determined, since it's only used for reporting
+fn reader_size(reader: &BufReader<Timed<File>>) -> Option<u64> {
+    reader
+        .get_ref()
+        .get_ref()
+        .metadata()
+        .ok()
+        .map(|metadata| metadata.len())
+}
+
+/// A still image is the only frame there is, so it is selected by `[0]` or `[-1]`
+fn select_still(image: Image, selection: Option<&FrameSelection>) -> Vec<Image> {
+    let Some(selection) = selection else {
+        return vec![image];
+    };
+    selection
+        .resolve(1)
+        .into_iter()
+        .map(|_| image.clone())
+        .collect()
+}
+
+/// The color type and ICC profile shared by all frames of an animation
+type AnimationMetadata = (ExtendedColorType, Option<Vec<u8>>);
+
+fn animation_metadata(
+    decoder: &mut impl ImageDecoder,
+    limits: &Limits,
+) -> Result<AnimationMetadata, MagickError> {
+    let (width, height) = decoder.dimensions();
+    limits.check_area(width, height)?;
+    wm_try!(decoder.set_limits(limits.to_image_limits()));
+    Ok((
+        decoder.original_color_type(),
+        wm_try!(decoder.icc_profile()),
+    ))
+}
+
+/// Decodes the frames of an animation.
+///
+/// Every frame is a full RGBA canvas, so the frames read so far are checked against the memory limit
+/// together: a small file with many frames would otherwise take up far more memory than any one of them.
+fn read_animation<'a>(
+    decoder: impl AnimationDecoder<'a>,
+    (original_color_type, icc_profile): AnimationMetadata,
+    selection: Option<&FrameSelection>,
+    limits: &Limits,
+) -> Result<Vec<Image>, MagickError> {
+    // Negative indices count from the end, so then all frames have to be decoded to know where the end is
+    let max_index = selection.and_then(|selection| selection.max_index());
+    let mut frames = Vec::new();
+    let mut total_bytes = 0u64;
+    for frame in decoder.into_frames() {
+        let frame = wm_try!(frame);
+        total_bytes = total_bytes.saturating_add(frame.buffer().as_raw().len() as u64);
+        limits.check_memory(total_bytes)?;
+        frames.push((frame.delay(), frame.into_buffer()));
+        if max_index.is_some_and(|max| frames.len() > max) {
+            break;
+        }
     }
-    let mut decoder = wm_try!(reader.into_decoder());
+    // Decoding stops early for selections like `[0]`, but imagemagick still numbers the frame
+    let multi_frame = frames.len() > 1 || max_index.is_some();
+    let selected = match selection {
+        Some(selection) => selection
+            .resolve(frames.len())
+            .into_iter()
+            .map(|index| (index, frames[index].clone()))
+            .collect(),
+        None => frames.into_iter().enumerate().collect::<Vec<_>>(),
+    };
+    Ok(selected
+        .into_iter()
+        .map(|(index, (delay, pixels))| {
+            let mut image = Image::new(DynamicImage::ImageRgba8(pixels));
+            image.original_color_type = original_color_type;
+            image.icc_profile = icc_profile.clone();
+            image.delay = Some(delay);
+            image.scene = multi_frame.then_some(index);
+            image
+        })
+        .collect())
+}
+
+/// Reads the selected pages of a TIFF file, numbering them like the frames of an animation
+fn read_pages<R: Read + Seek>(
+    reader: R,
+    limits: &Limits,
+    selection: Option<&FrameSelection>,
+) -> Result<Vec<Image>, MagickError> {
+    let mut pages = wm_try!(TiffPages::new(reader, &limits.to_image_limits()));
+    // Negative indices count from the end, so then all pages have to be counted
+    let max_index = selection.and_then(|selection| selection.max_index());
+    let count = wm_try!(pages.count(max_index));
+    let multi_page = count > 1 || pages.more_pages();
+    let indices = match selection {
+        Some(selection) => selection.resolve(count),
+        None => (0..count).collect(),
+    };
+    // Like the frames of an animation, the pages are checked against the memory limit together
+    let mut total_bytes = 0u64;
+    indices
+        .into_iter()
+        .map(|index| {
+            let mut page = wm_try!(pages.page(index));
+            wm_try!(page.set_limits(limits.to_image_limits()));
+            total_bytes = total_bytes.saturating_add(page.total_bytes());
+            limits.check_memory(total_bytes)?;
+            let mut image = load(page, limits)?;
+            image.scene = multi_page.then_some(index);
+            Ok(image)
+        })
+        .collect()
+}
+
+fn decode_impl<R: BufRead + Seek>(
+    mut reader: R,
+    file: &OsStr,
+    format: Option<FileFormat>,
+    modifiers: &Modifiers,
+) -> Result<Image, MagickError> {
+    let format = match format {
+        Some(format) => format,
+        None => guess_format(&mut reader, file)?,
+    };
+    modifiers.check_format_allowed(format)?;
+    let limits = &modifiers.limits;
+    let mut image = match format {
+        FileFormat::Image(image_format) => {
+            if !image_format.reading_enabled() {
+                return Err(no_decode_delegate(format.name()));
+            }
+            let mut reader = ImageReader::with_format(reader, image_format);
+            reader.limits(limits.to_image_limits());
+            load(wm_try!(reader.into_decoder()), limits)?
+        }
+        #[cfg(feature = "psd")]
+        FileFormat::Psd => {
+            let mut decoder = wm_try!(PsdDecoder::new(reader));
+            wm_try!(decoder.set_limits(limits.to_image_limits()));
+            load(decoder, limits)?
+        }
+        #[cfg(feature = "jp2")]
+        FileFormat::Jp2 | FileFormat::J2k => {
+            let mut decoder = wm_try!(Jp2Decoder::new(reader));
+            wm_try!(decoder.set_limits(limits.to_image_limits()));
+            load(decoder, limits)?
+        }
+        // pseudo-files are generated rather than decoded, see the `pseudo` module
+        FileFormat::Pseudo(_) => return Err(no_decode_delegate(format.name())),
+    };
+    image.filename = file.to_owned();
+    image.format = Some(format);
+    Ok(image)
+}
+
+/// Reads the pixels and the metadata we keep track of from a decoder that's ready to go
+fn load(mut decoder: impl ImageDecoder, limits: &Limits) -> Result<Image, MagickError> {
+    let (width, height) = decoder.dimensions();
+    limits.check_area(width, height)?;
+    // Not every decoder enforces the memory limit, and the buffer is allocated from the header alone
+    limits.check_memory(decoder.total_bytes())?;
     let orientation = wm_try!(decoder.orientation());
-    let mut image = wm_try!(DynamicImage::from_decoder(decoder));
-    // TODO: apply orientation only if -auto-orient is passed
-    image.apply_orientation(orientation);
+    let original_color_type = decoder.original_color_type();
+    let icc_profile = wm_try!(decoder.icc_profile());
+    let exif = wm_try!(decoder.exif_metadata());
+    let mut image = Image::new(wm_try!(DynamicImage::from_decoder(decoder)));
+    image.icc_profile = icc_profile;
+    image.exif = exif;
+    image.original_color_type = original_color_type;
+    // The orientation is only applied to the pixels by `-auto-orient`
+    image.orientation = orientation;
     Ok(image)
 }
+
+/// Determines the format from the contents of the file like imagemagick does,
+/// falling back to the file extension if the contents are not recognized
+fn guess_format<R: BufRead>(reader: &mut R, file: &OsStr) -> Result<FileFormat, MagickError> {
+    // The magic bytes are all near the start of the file, so the buffered data is enough to find them.
+    // This does not consume the data, so the decoder will see it again.
+    let header = wm_try!(reader.fill_buf());
+    if let Ok(format) = image::guess_format(header) {
+        return Ok(FileFormat::Image(format));
+    }
+    #[cfg(feature = "psd")]
+    if header.starts_with(b"8BPS") {
+        return Ok(FileFormat::Psd);
+    }
+    #[cfg(feature = "jp2")]
+    if header.starts_with(JP2_MAGIC) {
+        return Ok(FileFormat::Jp2);
+    }
+    #[cfg(feature = "jp2")]
+    if header.starts_with(J2K_MAGIC) {
+        return Ok(FileFormat::J2k);
+    }
+    if let Some(name) = sniff_unsupported(header) {
+        return Err(no_decode_delegate(name));
+    }
+    match FileFormat::from_path(Path::new(file)) {
+        Some(format) => Ok(format),
+        None => {
+            let extension = Path::new(file).extension().unwrap_or_default();
+            Err(no_decode_delegate(
+                &extension.to_string_lossy().to_ascii_uppercase(),
+            ))
+        }
+    }
+}
+
+fn no_decode_delegate(format_name: &str) -> MagickError {
+    wm_err!("no decode delegate for this image format `{}'", format_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    use image::codecs::gif::GifEncoder;
+    use image::{Delay, Frame, Rgba, RgbaImage};
+
+    #[test]
+    fn select_gif_frames() {
+        let mut file = tempfile::Builder::new().suffix(".gif").tempfile().unwrap();
+        {
+            let mut encoder = GifEncoder::new(file.as_file_mut());
+            for index in 0..5 {
+                let pixels = RgbaImage::from_pixel(2, 2, Rgba([index * 50, 0, 0, 255]));
+                let frame = Frame::from_parts(pixels, 0, 0, Delay::from_numer_denom_ms(100, 1));
+                encoder.encode_frame(frame).unwrap();
+            }
+        }
+        let path = file.path().as_os_str();
+        // Frames are composited onto the same canvas, so they are told apart by color
+        let frames = |selection: &str| -> Vec<u8> {
+            let selection = FrameSelection::from_str(selection).unwrap();
+            decode_frames(path, None, Some(&selection), &Modifiers::default())
+                .unwrap()
+                .iter()
+                .map(|image| image.pixels.to_rgba8().get_pixel(0, 0)[0] / 50)
+                .collect()
+        };
+        let all = decode_frames(path, None, None, &Modifiers::default()).unwrap();
+        assert_eq!(all.len(), 5);
+        assert_eq!(all[4].scene, Some(4));
+        assert_eq!(frames("0"), vec![0]);
+        assert_eq!(frames("-1"), vec![4]);
+        assert_eq!(frames("3-1,0"), vec![3, 2, 1, 0]);
+        assert_eq!(frames("4-9"), vec![4]);
+        let past_end = FrameSelection::from_str("7").unwrap();
+        assert!(decode_frames(path, None, Some(&past_end), &Modifiers::default()).is_err());
+    }
+
+    #[test]
+    fn animation_is_checked_against_memory_limit() {
+        let mut file = tempfile::Builder::new().suffix(".gif").tempfile().unwrap();
+        {
+            // 50 frames of 40 kilobytes each once decoded
+            let mut encoder = GifEncoder::new(file.as_file_mut());
+            for index in 0..50 {
+                let pixels = RgbaImage::from_pixel(100, 100, Rgba([index * 5, 0, 0, 255]));
+                let frame = Frame::from_parts(pixels, 0, 0, Delay::from_numer_denom_ms(100, 1));
+                encoder.encode_frame(frame).unwrap();
+            }
+        }
+        let path = file.path().as_os_str();
+        let mut modifiers = Modifiers::default();
+        modifiers
+            .limits
+            .set(crate::arg_parsers::ResourceType::Memory, OsStr::new("1MB"))
+            .unwrap();
+        assert!(decode_frames(path, None, None, &modifiers).is_err());
+        let first = FrameSelection::from_str("0").unwrap();
+        assert_eq!(
+            decode_frames(path, None, Some(&first), &modifiers)
+                .unwrap()
+                .len(),
+            1
+        );
+        let all = decode_frames(path, None, None, &Modifiers::default()).unwrap();
+        assert_eq!(all.len(), 50);
+    }
+
+    #[test]
+    fn read_every_tiff_page() {
+        let mut file = tempfile::Builder::new().suffix(".tif").tempfile().unwrap();
+        {
+            let mut encoder = tiff::encoder::TiffEncoder::new(file.as_file_mut()).unwrap();
+            encoder
+                .write_image::<tiff::encoder::colortype::RGB8>(3, 2, &[200; 18])
+                .unwrap();
+            encoder
+                .write_image::<tiff::encoder::colortype::Gray16>(5, 4, &[1000; 20])
+                .unwrap();
+        }
+        let path = file.path().as_os_str();
+        let pages = decode_frames(path, None, None, &Modifiers::default()).unwrap();
+        assert_eq!(pages.len(), 2);
+        assert_eq!(
+            pages[0].pixels.as_rgb8().unwrap().get_pixel(2, 1).0,
+            [200; 3]
+        );
+        assert_eq!(
+            pages[1].pixels.as_luma16().unwrap().get_pixel(4, 3).0,
+            [1000]
+        );
+        assert_eq!(pages[1].scene, Some(1));
+
+        let last = FrameSelection::from_str("-1").unwrap();
+        let pages = decode_frames(path, None, Some(&last), &Modifiers::default()).unwrap();
+        assert_eq!(pages.len(), 1);
+        assert_eq!((pages[0].width(), pages[0].height()), (5, 4));
+        let first = FrameSelection::from_str("0").unwrap();
+        let pages = decode_frames(path, None, Some(&first), &Modifiers::default()).unwrap();
+        assert_eq!((pages[0].width(), pages[0].height()), (3, 2));
+        assert_eq!(pages[0].scene, Some(0));
+    }
+
+    #[cfg(feature = "psd")]
+    #[test]
+    fn oversized_psd_header() {
+        // A 300000x300000 RGB image with 16 bits per channel, in well under a kilobyte
+        let mut data = b"8BPS".to_vec();
+        data.extend_from_slice(&1u16.to_be_bytes());
+        data.extend_from_slice(&[0; 6]);
+        data.extend_from_slice(&3u16.to_be_bytes());
+        data.extend_from_slice(&300_000u32.to_be_bytes());
+        data.extend_from_slice(&300_000u32.to_be_bytes());
+        data.extend_from_slice(&16u16.to_be_bytes());
+        data.extend_from_slice(&3u16.to_be_bytes());
+        data.extend_from_slice(&[0; 12]); // color mode data, resources and layers
+        data.extend_from_slice(&[0; 2]); // raw data follows
+        data.resize(144, 0);
+        let mut file = tempfile::Builder::new().suffix(".psd").tempfile().unwrap();
+        std::io::Write::write_all(&mut file, &data).unwrap();
+        let path = file.path().as_os_str();
+        assert!(decode(path, None, &Modifiers::default()).is_err());
+        let mut modifiers = Modifiers::default();
+        modifiers
+            .limits
+            .set(
+                crate::arg_parsers::ResourceType::Memory,
+                OsStr::new("100MB"),
+            )
+            .unwrap();
+        assert!(decode(path, None, &modifiers).is_err());
+        // The limit is also enforced for decoders that do not check it themselves
+        let decoder = PsdDecoder::new(std::io::Cursor::new(data)).unwrap();
+        assert!(load(decoder, &modifiers.limits).is_err());
+    }
+}
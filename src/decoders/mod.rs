@@ -0,0 +1,3 @@
+//! Decoders for formats that the `image` crate does not support.
+
+pub mod tiff;
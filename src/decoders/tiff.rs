@@ -0,0 +1,199 @@
+//! Reads every page of a TIFF file, since the `image` crate only reads the first one.
+//!
+//! Pages are decoded the same way `image` decodes the first page, so the supported color types match.
+
+use std::io::{Read, Seek};
+
+use image::error::{
+    DecodingError, ImageFormatHint, LimitError, LimitErrorKind, UnsupportedError,
+    UnsupportedErrorKind,
+};
+use image::metadata::Orientation;
+use image::{
+    ColorType, ExtendedColorType, ImageDecoder, ImageError, ImageFormat, ImageResult, LimitSupport,
+    Limits,
+};
+use tiff::decoder::{Decoder, DecodingResult};
+use tiff::tags::{SampleFormat, Tag};
+use tiff::TiffError;
+
+/// The tag that holds the ICC profile, which the `tiff` crate has no name for
+const ICC_PROFILE_TAG: u16 = 34675;
+
+pub struct TiffPages<R: Read + Seek> {
+    decoder: Decoder<R>,
+}
+
+impl<R: Read + Seek> TiffPages<R> {
+    pub fn new(reader: R, limits: &Limits) -> ImageResult<Self> {
+        let max_alloc = usize::try_from(limits.max_alloc.unwrap_or(u64::MAX)).unwrap_or(usize::MAX);
+        let mut tiff_limits = tiff::decoder::Limits::default();
+        tiff_limits.decoding_buffer_size = max_alloc;
+        tiff_limits.intermediate_buffer_size = max_alloc;
+        tiff_limits.ifd_value_size = max_alloc;
+        let decoder = Decoder::new(reader).map_err(tiff_error)?;
+        Ok(Self {
+            decoder: decoder.with_limits(tiff_limits),
+        })
+    }
+
+    /// Counts the pages, stopping once there are more than `max_index` of them if that is set
+    pub fn count(&mut self, max_index: Option<usize>) -> ImageResult<usize> {
+        self.decoder.seek_to_image(0).map_err(tiff_error)?;
+        let mut count = 1;
+        while self.decoder.more_images() && max_index.is_none_or(|max| count <= max) {
+            self.decoder.next_image().map_err(tiff_error)?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Whether there are pages after the last one counted
+    pub fn more_pages(&self) -> bool {
+        self.decoder.more_images()
+    }
+
+    pub fn page(&mut self, index: usize) -> ImageResult<TiffPage<'_, R>> {
+        self.decoder.seek_to_image(index).map_err(tiff_error)?;
+        TiffPage::new(&mut self.decoder)
+    }
+}
+
+/// The page that the decoder is currently on
+pub struct TiffPage<'a, R: Read + Seek> {
+    decoder: &'a mut Decoder<R>,
+    dimensions: (u32, u32),
+    color_type: ColorType,
+    original_color_type: ExtendedColorType,
+}
+
+impl<'a, R: Read + Seek> TiffPage<'a, R> {
+    fn new(decoder: &'a mut Decoder<R>) -> ImageResult<Self> {
+        let dimensions = decoder.dimensions().map_err(tiff_error)?;
+        let sample_formats = decoder
+            .find_tag_unsigned_vec::<u16>(Tag::SampleFormat)
+            .map_err(tiff_error)?;
+        for format in sample_formats.unwrap_or_default() {
+            match SampleFormat::from_u16(format) {
+                Some(SampleFormat::Uint) => (),
+                other => return Err(unsupported(format!("sample format {other:?}"))),
+            }
+        }
+        let tiff_color_type = decoder.colortype().map_err(tiff_error)?;
+        let color_type = match tiff_color_type {
+            tiff::ColorType::Gray(8) => ColorType::L8,
+            tiff::ColorType::Gray(16) => ColorType::L16,
+            tiff::ColorType::GrayA(8) => ColorType::La8,
+            tiff::ColorType::GrayA(16) => ColorType::La16,
+            tiff::ColorType::RGB(8) => ColorType::Rgb8,
+            tiff::ColorType::RGB(16) => ColorType::Rgb16,
+            tiff::ColorType::RGBA(8) => ColorType::Rgba8,
+            tiff::ColorType::RGBA(16) => ColorType::Rgba16,
+            tiff::ColorType::CMYK(8) => ColorType::Rgb8,
+            other => return Err(unsupported(format!("color type {other:?}"))),
+        };
+        let original_color_type = match tiff_color_type {
+            tiff::ColorType::CMYK(8) => ExtendedColorType::Cmyk8,
+            _ => color_type.into(),
+        };
+        Ok(Self {
+            decoder,
+            dimensions,
+            color_type,
+            original_color_type,
+        })
+    }
+
+    fn is_cmyk(&self) -> bool {
+        self.original_color_type == ExtendedColorType::Cmyk8
+    }
+}
+
+impl<R: Read + Seek> ImageDecoder for TiffPage<'_, R> {
+    fn dimensions(&self) -> (u32, u32) {
+        self.dimensions
+    }
+
+    fn color_type(&self) -> ColorType {
+        self.color_type
+    }
+
+    fn original_color_type(&self) -> ExtendedColorType {
+        self.original_color_type
+    }
+
+    fn icc_profile(&mut self) -> ImageResult<Option<Vec<u8>>> {
+        Ok(self
+            .decoder
+            .get_tag_u8_vec(Tag::Unknown(ICC_PROFILE_TAG))
+            .ok())
+    }
+
+    fn orientation(&mut self) -> ImageResult<Orientation> {
+        let orientation = self
+            .decoder
+            .find_tag(Tag::Orientation)
+            .map_err(tiff_error)?
+            .and_then(|value| Orientation::from_exif(value.into_u16().ok()?.min(255) as u8));
+        Ok(orientation.unwrap_or(Orientation::NoTransforms))
+    }
+
+    fn set_limits(&mut self, mut limits: Limits) -> ImageResult<()> {
+        limits.check_support(&LimitSupport::default())?;
+        limits.check_dimensions(self.dimensions.0, self.dimensions.1)?;
+        limits.reserve(self.total_bytes())?;
+        // CMYK is decoded in full before it is converted
+        if self.is_cmyk() {
+            let (width, height) = self.dimensions;
+            limits.reserve(u64::from(width) * u64::from(height) * 4)?;
+        }
+        Ok(())
+    }
+
+    fn read_image(self, buf: &mut [u8]) -> ImageResult<()> {
+        assert_eq!(u64::try_from(buf.len()), Ok(self.total_bytes()));
+        let cmyk = self.is_cmyk();
+        match self.decoder.read_image().map_err(tiff_error)? {
+            DecodingResult::U8(samples) if cmyk => {
+                for (out, cmyk) in buf.chunks_exact_mut(3).zip(samples.chunks_exact(4)) {
+                    out.copy_from_slice(&cmyk_to_rgb(cmyk));
+                }
+            }
+            DecodingResult::U8(samples) => buf.copy_from_slice(&samples),
+            DecodingResult::U16(samples) => {
+                for (out, sample) in buf.chunks_exact_mut(2).zip(samples) {
+                    out.copy_from_slice(&sample.to_ne_bytes());
+                }
+            }
+            _ => return Err(unsupported("sample size".to_owned())),
+        }
+        Ok(())
+    }
+
+    fn read_image_boxed(self: Box<Self>, buf: &mut [u8]) -> ImageResult<()> {
+        (*self).read_image(buf)
+    }
+}
+
+/// The same naive conversion that `image` uses for the first page
+fn cmyk_to_rgb(cmyk: &[u8]) -> [u8; 3] {
+    let black = 1.0 - f32::from(cmyk[3]) / 255.0;
+    std::array::from_fn(|c| ((255.0 - f32::from(cmyk[c])) * black) as u8)
+}
+
+fn tiff_error(error: TiffError) -> ImageError {
+    match error {
+        TiffError::IoError(error) => ImageError::IoError(error),
+        TiffError::LimitsExceeded => {
+            ImageError::Limits(LimitError::from_kind(LimitErrorKind::InsufficientMemory))
+        }
+        error => ImageError::Decoding(DecodingError::new(ImageFormat::Tiff.into(), error)),
+    }
+}
+
+fn unsupported(feature: String) -> ImageError {
+    ImageError::Unsupported(UnsupportedError::from_format_and_kind(
+        ImageFormatHint::Exact(ImageFormat::Tiff),
+        UnsupportedErrorKind::GenericFeature(feature),
+    ))
+}
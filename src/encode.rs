@@ -0,0 +1,278 @@
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use image::{DynamicImage, ImageFormat};
+
+use crate::{
+    colorspace,
+    encoders::{
+        self,
+        common::{optimize_pixel_format, EncoderCapabilities},
+    },
+    error::MagickError,
+    file_format::{FileFormat, PseudoFormat},
+    icc,
+    image::Image,
+    limits::{DiskBounded, Timed},
+    plan::Modifiers,
+    utils::spool::temp_file,
+    wm_err, wm_try,
+};
+
+/// Writes the image to the specified file. The filename `-` stands for stdout.
+///
+/// If the format has not been explicitly specified, it is determined by the file extension.
+pub fn encode(
+    image: &Image,
+    file: &OsStr,
+    format: Option<ImageFormat>,
+    modifiers: &Modifiers,
+) -> Result<(), MagickError> {
+    let image = colorspace::to_srgb(image);
+    write_output(file, format, modifiers, |writer, format| {
+        write_image(&image, writer, format, modifiers)
+    })
+}
+
+/// Writes all the frames to a single file, e.g. as an animated GIF.
+///
+/// More than one frame can only be written in formats for which [writes_multiple_frames] is true.
+pub fn encode_frames(
+    frames: &[Image],
+    file: &OsStr,
+    format: Option<ImageFormat>,
+    modifiers: &Modifiers,
+) -> Result<(), MagickError> {
+    if let [frame] = frames {
+        return encode(frame, file, format, modifiers);
+    }
+    if frames.iter().any(|frame| !frame.colorspace.is_srgb()) {
+        let frames: Vec<Image> = frames
+            .iter()
+            .map(|frame| colorspace::to_srgb(frame).into_owned())
+            .collect();
+        return encode_frames(&frames, file, format, modifiers);
+    }
+    write_output(file, format, modifiers, |writer, format| {
+        write_frames(frames, writer, format, modifiers)
+    })
+}
+
+/// Whether all the frames of an image can be written to this file,
+/// as opposed to writing every frame to a separate file
+pub fn writes_multiple_frames(file: &OsStr, format: Option<ImageFormat>) -> bool {
+    let format = format.or_else(|| match FileFormat::from_path(Path::new(file)) {
+        Some(FileFormat::Image(format)) => Some(format),
+        _ => None,
+    });
+    matches!(format, Some(ImageFormat::Gif | ImageFormat::Tiff))
+}
+
+/// Resolves the format and opens the file or stdout for `write`
+fn write_output(
+    file: &OsStr,
+    format: Option<ImageFormat>,
+    modifiers: &Modifiers,
+    write: impl FnOnce(
+        &mut BufWriter<Timed<DiskBounded<&mut File>>>,
+        ImageFormat,
+    ) -> Result<(), MagickError>,
+) -> Result<(), MagickError> {
+    if file == "-" {
+        let Some(format) = format else {
+            return Err(wm_err!(
+                "no output format specified for stdout, use e.g. `png:-' to set one"
+            ));
+        };
+        modifiers.check_format_allowed(FileFormat::Image(format))?;
+        // The encoders need to seek, so we cannot write to stdout directly
+        let mut spooled = wm_try!(temp_file(modifiers.temporary_path.as_deref()));
+        let limits = &modifiers.limits;
+        let mut writer = BufWriter::new(limits.timed(limits.disk_bounded(&mut spooled)));
+        write(&mut writer, format)?;
+        wm_try!(writer.flush());
+        drop(writer);
+        wm_try!(spooled.seek(SeekFrom::Start(0)));
+        wm_try!(std::io::copy(&mut spooled, &mut std::io::stdout().lock()));
+        return Ok(());
+    }
+
+    let format = match format {
+        Some(format) => format,
+        None => {
+            let extension = Path::new(file).extension().unwrap_or_default();
+            encodable_format(&extension.to_string_lossy())?
+        }
+    };
+    modifiers.check_format_allowed(FileFormat::Image(format))?;
+    let mut output = wm_try!(File::create(file));
+    let mut writer = BufWriter::new(modifiers.limits.timed(DiskBounded::unbounded(&mut output)));
+    write(&mut writer, format)?;
+    wm_try!(writer.flush());
+    Ok(())
+}
+
+fn write_frames<W: Write + Seek>(
+    frames: &[Image],
+    writer: &mut W,
+    format: ImageFormat,
+    modifiers: &Modifiers,
+) -> Result<(), MagickError> {
+    if frames
+        .iter()
+        .any(|frame| needs_srgb_fallback(frame, format, modifiers))
+    {
+        let frames: Vec<Image> = frames.iter().map(icc::to_srgb).collect();
+        return write_frames(&frames, writer, format, modifiers);
+    }
+    match format {
+        ImageFormat::Gif => {
+            let options = encoders::gif::GifOptions::from_modifiers(modifiers)?;
+            encoders::gif::encode_frames(frames, writer, &options)
+        }
+        ImageFormat::Tiff => encoders::tiff::encode_frames(frames, writer),
+        _ => Err(wm_err!(
+            "unable to write multiple frames as {}",
+            FileFormat::Image(format).name()
+        )),
+    }
+}
+
+/// Dispatches to our own encoders for formats where we deviate from the defaults of the `image` crate
+fn write_image<W: Write + Seek>(
+    image: &Image,
+    writer: &mut W,
+    format: ImageFormat,
+    modifiers: &Modifiers,
+) -> Result<(), MagickError> {
+    if needs_srgb_fallback(image, format, modifiers) {
+        return write_image(&icc::to_srgb(image), writer, format, modifiers);
+    }
+    let pixels = &image.pixels;
+    match format {
+        ImageFormat::Jpeg => {
+            let comment = image.comment.as_deref();
+            encoders::jpeg::encode(pixels, comment, modifiers.quality, writer)
+        }
+        ImageFormat::Png => {
+            let options = encoders::png::PngOptions::from_modifiers(modifiers)?;
+            encoders::png::encode(image, writer, &options)
+        }
+        ImageFormat::Gif => write_frames(std::slice::from_ref(image), writer, format, modifiers),
+        ImageFormat::WebP => {
+            let options = encoders::webp::WebpOptions::from_modifiers(modifiers)?;
+            encoders::webp::encode(image, writer, &options)
+        }
+        // Many encoders only accept some pixel formats, and `write_to` does not convert for us
+        _ => match EncoderCapabilities::of(format) {
+            Some(capabilities) => write_converted(
+                &optimize_pixel_format(pixels, &capabilities),
+                writer,
+                format,
+            ),
+            None => write_converted(pixels, writer, format),
+        },
+    }
+}
+
+/// Images with an ICC profile look wrong without it, so if the format cannot hold the profile,
+/// the colors are converted to sRGB, which is what viewers assume in its absence.
+/// `--wm-no-srgb-fallback` writes the colors as they are instead.
+fn needs_srgb_fallback(image: &Image, format: ImageFormat, modifiers: &Modifiers) -> bool {
+    let embeds_profile = EncoderCapabilities::of(format).is_some_and(|c| c.icc_profile);
+    image.icc_profile.is_some() && !embeds_profile && !modifiers.no_srgb_fallback
+}
+
+fn write_converted<W: Write + Seek>(
+    image: &DynamicImage,
+    writer: &mut W,
+    format: ImageFormat,
+) -> Result<(), MagickError> {
+    wm_try!(image.write_to(writer, format));
+    Ok(())
+}
+
+/// Splits off an explicit format prefix such as `png:` in `png:-` or `png:out.dat`.
+///
+/// Like in imagemagick, the prefix only counts if it names a format or a pseudo-file,
+/// and anything else before a colon is part of the path, e.g. `C:\out.png` or `12:30.png`.
+pub fn split_format_prefix(file: &OsStr) -> Result<(Option<ImageFormat>, &OsStr), MagickError> {
+    // Format prefixes are always ASCII, so filenames that aren't valid UTF-8 can't have one
+    let Some(string) = file.to_str() else {
+        return Ok((None, file));
+    };
+    let Some((prefix, rest)) = string.split_once(':') else {
+        return Ok((None, file));
+    };
+    match FileFormat::from_extension(prefix) {
+        Some(FileFormat::Image(format)) if format.writing_enabled() => {
+            Ok((Some(format), OsStr::new(rest)))
+        }
+        Some(_) => Err(no_encode_delegate(prefix)),
+        None if PseudoFormat::from_prefix(prefix).is_some() => Err(no_encode_delegate(prefix)),
+        None => Ok((None, file)),
+    }
+}
+
+/// Looks up a format we can write by its extension or prefix, e.g. `farbfeld`
+fn encodable_format(extension: &str) -> Result<ImageFormat, MagickError> {
+    match FileFormat::from_extension(extension) {
+        Some(FileFormat::Image(format)) if format.writing_enabled() => Ok(format),
+        _ => Err(no_encode_delegate(extension)),
+    }
+}
+
+fn no_encode_delegate(format: &str) -> MagickError {
+    wm_err!("no encode delegate for this image format `{}'", format)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_prefix() {
+        let (format, file) = split_format_prefix(OsStr::new("png:-")).unwrap();
+        assert_eq!(format, Some(ImageFormat::Png));
+        assert_eq!(file, "-");
+    }
+
+    #[test]
+    fn no_format_prefix() {
+        let (format, file) = split_format_prefix(OsStr::new("out.png")).unwrap();
+        assert_eq!(format, None);
+        assert_eq!(file, "out.png");
+    }
+
+    #[test]
+    fn imagemagick_format_names() {
+        let (format, _) = split_format_prefix(OsStr::new("farbfeld:out.dat")).unwrap();
+        assert_eq!(format, Some(ImageFormat::Farbfeld));
+        let (format, _) = split_format_prefix(OsStr::new("QOI:-")).unwrap();
+        assert_eq!(format, Some(ImageFormat::Qoi));
+    }
+
+    #[test]
+    fn drive_letter() {
+        let (format, file) = split_format_prefix(OsStr::new("C:\\out.png")).unwrap();
+        assert_eq!(format, None);
+        assert_eq!(file, "C:\\out.png");
+    }
+
+    #[test]
+    fn colon_in_path() {
+        let (format, file) = split_format_prefix(OsStr::new("12:30.png")).unwrap();
+        assert_eq!(format, None);
+        assert_eq!(file, "12:30.png");
+        let (format, file) = split_format_prefix(OsStr::new("photos:2024/out.png")).unwrap();
+        assert_eq!(format, None);
+        assert_eq!(file, "photos:2024/out.png");
+    }
+
+    #[test]
+    fn unwritable_format_prefix() {
+        assert!(split_format_prefix(OsStr::new("label:out.png")).is_err());
+    }
+}
@@ -0,0 +1,204 @@
+//! Helpers shared between encoders
+
+use std::borrow::Cow;
+
+use image::{ColorType, DynamicImage, ImageFormat};
+
+/// The pixel formats an encoder can write, which decide what [optimize_pixel_format] converts to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EncoderCapabilities {
+    /// The bits per sample the format can store, in increasing order
+    pub bit_depths: &'static [u8],
+    /// Whether grayscale images can be written with a single color channel
+    pub grayscale: bool,
+    /// Whether the alpha channel can be written
+    pub alpha: bool,
+    /// Whether the alpha channel can be left out of images that are fully opaque
+    pub opaque: bool,
+    /// Whether the ICC profile of the image is written along with it
+    pub icc_profile: bool,
+}
+
+impl EncoderCapabilities {
+    pub const PNG: Self = Self {
+        bit_depths: &[8, 16],
+        grayscale: true,
+        alpha: true,
+        opaque: true,
+        icc_profile: false,
+    };
+    pub const JPEG: Self = Self {
+        bit_depths: &[8],
+        grayscale: true,
+        alpha: false,
+        opaque: true,
+        icc_profile: false,
+    };
+    pub const WEBP: Self = Self {
+        bit_depths: &[8],
+        grayscale: true,
+        alpha: true,
+        opaque: true,
+        icc_profile: true,
+    };
+    /// Farbfeld is always 16-bit RGBA
+    pub const FARBFELD: Self = Self {
+        bit_depths: &[16],
+        grayscale: false,
+        alpha: true,
+        opaque: false,
+        icc_profile: false,
+    };
+    pub const QOI: Self = Self {
+        bit_depths: &[8],
+        grayscale: false,
+        alpha: true,
+        opaque: true,
+        icc_profile: false,
+    };
+
+    /// The capabilities of the format, if we pick the pixel format for its encoder.
+    /// Other encoders are given the pixels as they are.
+    pub fn of(format: ImageFormat) -> Option<Self> {
+        match format {
+            ImageFormat::Png => Some(Self::PNG),
+            ImageFormat::Jpeg => Some(Self::JPEG),
+            ImageFormat::WebP => Some(Self::WEBP),
+            ImageFormat::Farbfeld => Some(Self::FARBFELD),
+            ImageFormat::Qoi => Some(Self::QOI),
+            _ => None,
+        }
+    }
+}
+
+/// Finds the smallest in-memory pixel format the encoder can write that represents the image
+/// as closely as the format allows: drops the alpha channel if every pixel is fully opaque,
+/// and the color channels if every pixel is gray. The bit depth is only reduced if the format
+/// cannot store it.
+///
+/// Encoders use this to write e.g. single-channel files for grayscale images
+/// that have been decoded into an RGB buffer.
+pub fn optimize_pixel_format<'a>(
+    image: &'a DynamicImage,
+    capabilities: &EncoderCapabilities,
+) -> Cow<'a, DynamicImage> {
+    let color = image.color();
+    let has_alpha = match capabilities.alpha {
+        true => !capabilities.opaque || (color.has_alpha() && !is_opaque(image)),
+        false => false,
+    };
+    let has_color = !capabilities.grayscale || (color.has_color() && !is_gray(image));
+    let bits_per_sample = color.bytes_per_pixel() / color.channel_count() * 8;
+    let depths = capabilities.bit_depths;
+    let bits_per_sample = depths
+        .iter()
+        .copied()
+        .find(|&depth| depth >= bits_per_sample)
+        .or(depths.last().copied())
+        .unwrap_or(bits_per_sample);
+    let target = match (bits_per_sample, has_color, has_alpha) {
+        (8, false, false) => ColorType::L8,
+        (8, false, true) => ColorType::La8,
+        (8, true, false) => ColorType::Rgb8,
+        (8, true, true) => ColorType::Rgba8,
+        (16, false, false) => ColorType::L16,
+        (16, false, true) => ColorType::La16,
+        (16, true, false) => ColorType::Rgb16,
+        (16, true, true) => ColorType::Rgba16,
+        // there are no floating-point grayscale formats, so only alpha can be dropped
+        (_, _, false) => ColorType::Rgb32F,
+        (_, _, true) => ColorType::Rgba32F,
+    };
+    if target == color {
+        Cow::Borrowed(image)
+    } else {
+        Cow::Owned(convert(image, target))
+    }
+}
+
+/// Converts the image to one of the pixel formats that [DynamicImage] has a variant for
+pub fn convert(image: &DynamicImage, color: ColorType) -> DynamicImage {
+    match color {
+        ColorType::L8 => DynamicImage::ImageLuma8(image.to_luma8()),
+        ColorType::La8 => DynamicImage::ImageLumaA8(image.to_luma_alpha8()),
+        ColorType::L16 => DynamicImage::ImageLuma16(image.to_luma16()),
+        ColorType::La16 => DynamicImage::ImageLumaA16(image.to_luma_alpha16()),
+        ColorType::Rgb8 => DynamicImage::ImageRgb8(image.to_rgb8()),
+        ColorType::Rgba8 => DynamicImage::ImageRgba8(image.to_rgba8()),
+        ColorType::Rgb16 => DynamicImage::ImageRgb16(image.to_rgb16()),
+        ColorType::Rgba16 => DynamicImage::ImageRgba16(image.to_rgba16()),
+        ColorType::Rgb32F => DynamicImage::ImageRgb32F(image.to_rgb32f()),
+        ColorType::Rgba32F => DynamicImage::ImageRgba32F(image.to_rgba32f()),
+        _ => unreachable!(),
+    }
+}
+
+/// Returns true if the image has no alpha channel or every pixel is fully opaque
+pub fn is_opaque(image: &DynamicImage) -> bool {
+    match image {
+        DynamicImage::ImageLumaA8(buf) => buf.pixels().all(|p| p[1] == u8::MAX),
+        DynamicImage::ImageRgba8(buf) => buf.pixels().all(|p| p[3] == u8::MAX),
+        DynamicImage::ImageLumaA16(buf) => buf.pixels().all(|p| p[1] == u16::MAX),
+        DynamicImage::ImageRgba16(buf) => buf.pixels().all(|p| p[3] == u16::MAX),
+        DynamicImage::ImageRgba32F(buf) => buf.pixels().all(|p| p[3] >= 1.0),
+        _ => true,
+    }
+}
+
+/// Returns true if the image has no color channels or all of them are equal in every pixel
+pub fn is_gray(image: &DynamicImage) -> bool {
+    fn all_gray<T: PartialEq + Copy>(samples: &[T], channels: usize) -> bool {
+        samples
+            .chunks_exact(channels)
+            .all(|p| p[0] == p[1] && p[1] == p[2])
+    }
+    match image {
+        DynamicImage::ImageRgb8(buf) => all_gray(buf.as_raw(), 3),
+        DynamicImage::ImageRgba8(buf) => all_gray(buf.as_raw(), 4),
+        DynamicImage::ImageRgb16(buf) => all_gray(buf.as_raw(), 3),
+        DynamicImage::ImageRgba16(buf) => all_gray(buf.as_raw(), 4),
+        DynamicImage::ImageRgb32F(buf) => all_gray(buf.as_raw(), 3),
+        DynamicImage::ImageRgba32F(buf) => all_gray(buf.as_raw(), 4),
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{ColorType, Rgb32FImage, Rgba, RgbaImage};
+
+    use super::*;
+
+    const PNG: &EncoderCapabilities = &EncoderCapabilities::PNG;
+
+    #[test]
+    fn opaque_gray_becomes_luma() {
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_pixel(2, 2, Rgba([7, 7, 7, 255])));
+        assert_eq!(optimize_pixel_format(&image, PNG).color(), ColorType::L8);
+    }
+
+    #[test]
+    fn transparent_color_is_kept() {
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_pixel(2, 2, Rgba([7, 8, 9, 128])));
+        assert!(matches!(
+            optimize_pixel_format(&image, PNG),
+            Cow::Borrowed(_)
+        ));
+    }
+
+    #[test]
+    fn capabilities_limit_the_format() {
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_pixel(2, 2, Rgba([7, 7, 7, 128])));
+        let jpeg = optimize_pixel_format(&image, &EncoderCapabilities::JPEG);
+        assert_eq!(jpeg.color(), ColorType::L8);
+        let farbfeld = optimize_pixel_format(&image, &EncoderCapabilities::FARBFELD);
+        assert_eq!(farbfeld.color(), ColorType::Rgba16);
+        let qoi = optimize_pixel_format(&image, &EncoderCapabilities::QOI);
+        assert_eq!(qoi.color(), ColorType::Rgba8);
+
+        let float = DynamicImage::ImageRgb32F(Rgb32FImage::new(2, 2));
+        assert_eq!(optimize_pixel_format(&float, PNG).color(), ColorType::L16);
+        let webp = optimize_pixel_format(&float, &EncoderCapabilities::WEBP);
+        assert_eq!(webp.color(), ColorType::L8);
+    }
+}
@@ -0,0 +1,540 @@
+//! Animated GIF encoding with the `gif` crate, which unlike `image` lets us choose the palettes
+//! and where each frame goes on the canvas
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use gif::{DisposalMethod, Repeat};
+use image::{Delay, Rgba, RgbaImage};
+
+use crate::{
+    arg_parsers::{Color, DitherMethod},
+    error::MagickError,
+    image::Image,
+    plan::Modifiers,
+    quantize::Quantizer,
+    wm_err, wm_try,
+};
+
+/// Settings read from `-loop`, `-background`, `-transparent-color` and `-define gif:*`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct GifOptions {
+    /// `gif:colors`, `-dither` or `gif:dither`, `-quantize` and `-treedepth`:
+    /// how frames with too many colors are reduced to a palette
+    pub quantizer: Quantizer,
+    /// `-loop`: how many times the animation plays, 0 for forever
+    pub iterations: u16,
+    /// `gif:palette`: whether the frames share a palette
+    pub palette: PaletteMode,
+    /// `gif:optimize`: only store the part of each frame that differs from the previous one
+    pub optimize: bool,
+    /// `-background`: the color the header points legacy viewers to for the area around the frames.
+    /// It can only be chosen from a global palette.
+    pub background: Option<[u8; 4]>,
+    /// `-transparent-color`: the color stored for the transparent palette entry,
+    /// which viewers that ignore transparency show instead. Black by default like in imagemagick.
+    pub transparent_color: [u8; 3],
+}
+
+/// Which palettes the frames use, set by `-define gif:palette=local` or `global`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum PaletteMode {
+    /// Every frame has a palette of its own, which keeps the most colors
+    #[default]
+    Local,
+    /// All frames share one palette, which saves space when their colors are similar
+    Global,
+}
+
+impl GifOptions {
+    pub fn from_modifiers(modifiers: &Modifiers) -> Result<Self, MagickError> {
+        let mut options = Self {
+            quantizer: modifiers.quantizer(Quantizer::default().colors),
+            iterations: modifiers.loop_iterations.unwrap_or(0),
+            background: modifiers.background.map(Color::to_rgba8),
+            transparent_color: modifiers.transparent_color.map_or([0; 3], |color| {
+                let [red, green, blue, _] = color.to_rgba8();
+                [red, green, blue]
+            }),
+            ..Default::default()
+        };
+        if let Some(colors) = modifiers.parse_define::<usize>("gif:colors")? {
+            if !(2..=256).contains(&colors) {
+                return Err(wm_err!(
+                    "invalid value for `-define gif:colors': {}, must be between 2 and 256",
+                    colors
+                ));
+            }
+            options.quantizer.colors = colors;
+        }
+        if let Some(dither) = modifiers.define("gif:dither") {
+            options.quantizer.dither = DitherMethod::try_from(std::ffi::OsStr::new(dither))?;
+        }
+        if let Some(palette) = modifiers.define("gif:palette") {
+            options.palette = match palette.to_ascii_lowercase().as_str() {
+                "local" => PaletteMode::Local,
+                "global" => PaletteMode::Global,
+                _ => {
+                    return Err(wm_err!(
+                        "invalid value for `-define gif:palette': {}",
+                        palette
+                    ))
+                }
+            };
+        }
+        if let Some(optimize) = modifiers.define("gif:optimize") {
+            options.optimize = match optimize.to_ascii_lowercase().as_str() {
+                "true" | "1" | "" => true,
+                "false" | "0" => false,
+                _ => {
+                    return Err(wm_err!(
+                        "invalid value for `-define gif:optimize': {}",
+                        optimize
+                    ))
+                }
+            };
+        }
+        Ok(options)
+    }
+}
+
+/// The part of a frame that is written to the file
+struct Region {
+    left: u32,
+    top: u32,
+    pixels: RgbaImage,
+    dispose: DisposalMethod,
+}
+
+/// Writes the frames as an animation that loops forever unless `-loop` says otherwise,
+/// which is what imagemagick does by default. A single frame is written as a still image.
+///
+/// All frames are placed at the top left corner of the canvas, which is the size of the first frame.
+/// Frames with more colors than GIF allows are quantized according to the options.
+pub fn encode_frames<W: Write>(
+    frames: &[Image],
+    writer: W,
+    options: &GifOptions,
+) -> Result<(), MagickError> {
+    let Some(first) = frames.first() else {
+        return Ok(());
+    };
+    let (width, height) = dimensions(first.width(), first.height())?;
+    let mut pixels: Vec<RgbaImage> = frames.iter().map(|image| image.pixels.to_rgba8()).collect();
+    // Unchanged pixels become transparent in frame differences, so that needs a palette entry
+    let quantizer = &options.quantizer;
+    match options.palette {
+        PaletteMode::Global => quantizer.quantize_shared(&mut pixels, options.optimize),
+        PaletteMode::Local if options.optimize => {
+            for frame in &mut pixels {
+                quantizer.quantize_shared(std::slice::from_mut(frame), true);
+            }
+        }
+        // The colors are kept as they are if there are few enough of them
+        PaletteMode::Local => pixels
+            .iter_mut()
+            .for_each(|frame| quantizer.quantize(frame)),
+    }
+
+    let regions: Vec<Region> = (0..pixels.len())
+        .map(|i| {
+            let previous = i.checked_sub(1).map(|previous| &pixels[previous]);
+            region(&pixels[i], previous.filter(|_| options.optimize))
+        })
+        .collect();
+    drop(pixels);
+    let mut global = match options.palette {
+        PaletteMode::Global => Some(ColorTable::new(
+            regions.iter().map(|r| &r.pixels),
+            options.transparent_color,
+        )?),
+        PaletteMode::Local => None,
+    };
+    let background = match (&mut global, options.background) {
+        (Some(table), Some(color)) => table.background_index(color),
+        _ => 0,
+    };
+    let writer = BackgroundIndex {
+        writer,
+        written: 0,
+        index: background,
+    };
+
+    let global_palette = global.as_ref().map_or(&[][..], |table| &table.palette);
+    let mut encoder = wm_try!(gif::Encoder::new(writer, width, height, global_palette));
+    match (frames.len(), options.iterations) {
+        (1, _) | (_, 1) => (),
+        (_, 0) => wm_try!(encoder.set_repeat(Repeat::Infinite)),
+        // The count is how many times the animation repeats after playing once
+        (_, iterations) => wm_try!(encoder.set_repeat(Repeat::Finite(iterations - 1))),
+    }
+    for (image, region) in frames.iter().zip(&regions) {
+        let local;
+        let table = match &global {
+            Some(table) => table,
+            None => {
+                local = ColorTable::new([&region.pixels], options.transparent_color)?;
+                &local
+            }
+        };
+        let (region_width, region_height) =
+            dimensions(region.pixels.width(), region.pixels.height())?;
+        let mut frame = gif::Frame::from_indexed_pixels(
+            region_width,
+            region_height,
+            table.index(&region.pixels),
+            table.transparent,
+        );
+        if global.is_none() {
+            frame.palette = Some(table.palette.clone());
+        }
+        frame.left = region.left as u16;
+        frame.top = region.top as u16;
+        frame.dispose = region.dispose;
+        // Stills converted into an animation have no delay, same as in imagemagick
+        frame.delay = image.delay.map_or(0, centiseconds);
+        wm_try!(encoder.write_frame(&frame));
+    }
+    Ok(())
+}
+
+/// The dimensions of the canvas or a frame, which GIF stores in 16 bits
+fn dimensions(width: u32, height: u32) -> Result<(u16, u16), MagickError> {
+    match (u16::try_from(width), u16::try_from(height)) {
+        (Ok(width), Ok(height)) => Ok((width, height)),
+        _ => Err(wm_err!(
+            "width or height exceeds limit `{}x{}'",
+            width,
+            height
+        )),
+    }
+}
+
+fn centiseconds(delay: Delay) -> u16 {
+    let (numer, denom) = delay.numer_denom_ms();
+    (f64::from(numer) / f64::from(denom) / 10.0)
+        .round()
+        .min(f64::from(u16::MAX)) as u16
+}
+
+/// The part of the frame to write.
+///
+/// Between opaque frames of the same size, which completely cover what came before them,
+/// that is the rectangle around the pixels that changed, with the unchanged pixels in it made transparent
+/// so that the previous frame shows through. Other frames are written whole,
+/// and cleared afterwards if they have transparent pixels so that the next frame is not drawn over them.
+fn region(frame: &RgbaImage, previous: Option<&RgbaImage>) -> Region {
+    let opaque = |pixels: &RgbaImage| pixels.pixels().all(|pixel| pixel[3] == u8::MAX);
+    let whole = || Region {
+        left: 0,
+        top: 0,
+        pixels: frame.clone(),
+        dispose: match opaque(frame) {
+            true => DisposalMethod::Keep,
+            false => DisposalMethod::Background,
+        },
+    };
+    let Some(previous) = previous else {
+        return whole();
+    };
+    if previous.dimensions() != frame.dimensions() || !opaque(previous) || !opaque(frame) {
+        return whole();
+    }
+    let changed = frame
+        .enumerate_pixels()
+        .filter(|&(x, y, pixel)| previous.get_pixel(x, y) != pixel);
+    let (mut left, mut top, mut right, mut bottom) = (u32::MAX, u32::MAX, 0, 0);
+    for (x, y, _) in changed {
+        (left, top) = (left.min(x), top.min(y));
+        (right, bottom) = (right.max(x), bottom.max(y));
+    }
+    // An unchanged frame still takes up its delay, as a single transparent pixel
+    if left == u32::MAX {
+        (left, top) = (0, 0);
+    }
+    let pixels = RgbaImage::from_fn(right - left + 1, bottom - top + 1, |x, y| {
+        let (x, y) = (x + left, y + top);
+        match frame.get_pixel(x, y) == previous.get_pixel(x, y) {
+            true => Rgba([0, 0, 0, 0]),
+            false => *frame.get_pixel(x, y),
+        }
+    });
+    Region {
+        left,
+        top,
+        pixels,
+        dispose: DisposalMethod::Keep,
+    }
+}
+
+/// Sets the background color index in the header, which `gif` always writes as 0,
+/// as it passes through to the writer
+struct BackgroundIndex<W> {
+    writer: W,
+    written: usize,
+    index: u8,
+}
+
+impl<W: Write> Write for BackgroundIndex<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // After the signature, the size of the canvas and the flags
+        const OFFSET: usize = 11;
+        let written = match OFFSET.checked_sub(self.written) {
+            Some(offset) if offset < buf.len() => {
+                let mut patched = buf.to_vec();
+                patched[offset] = self.index;
+                self.writer.write(&patched)?
+            }
+            _ => self.writer.write(buf)?,
+        };
+        self.written += written;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// A palette and the index of every color in it. All transparent pixels share one entry.
+struct ColorTable {
+    indices: HashMap<Rgba<u8>, u8>,
+    /// The RGB triplets of the colors
+    palette: Vec<u8>,
+    transparent: Option<u8>,
+}
+
+impl ColorTable {
+    /// The colors of already quantized images, whose alpha is fully opaque or fully transparent
+    fn new<'a>(
+        images: impl IntoIterator<Item = &'a RgbaImage>,
+        transparent_color: [u8; 3],
+    ) -> Result<Self, MagickError> {
+        let mut table = Self {
+            indices: HashMap::new(),
+            palette: Vec::new(),
+            transparent: None,
+        };
+        for pixel in images.into_iter().flat_map(|pixels| pixels.pixels()) {
+            if table.indices.contains_key(pixel) {
+                continue;
+            }
+            let Ok(index) = u8::try_from(table.indices.len()) else {
+                return Err(wm_err!("too many colors for a GIF palette"));
+            };
+            table.indices.insert(*pixel, index);
+            match pixel[3] {
+                0 => {
+                    table.palette.extend_from_slice(&transparent_color);
+                    table.transparent = Some(index);
+                }
+                _ => table.palette.extend_from_slice(&pixel.0[..3]),
+            }
+        }
+        Ok(table)
+    }
+
+    /// The index of the background color, which is added to the palette if it is missing and there is room.
+    /// A transparent background is the transparent entry.
+    fn background_index(&mut self, [red, green, blue, alpha]: [u8; 4]) -> u8 {
+        let color = match alpha {
+            0 => Rgba([0, 0, 0, 0]),
+            _ => Rgba([red, green, blue, u8::MAX]),
+        };
+        if let Some(&index) = self.indices.get(&color) {
+            return index;
+        }
+        let Ok(index) = u8::try_from(self.indices.len()) else {
+            return 0;
+        };
+        self.indices.insert(color, index);
+        match alpha {
+            0 => {
+                self.palette.extend_from_slice(&[0; 3]);
+                self.transparent = Some(index);
+            }
+            _ => self.palette.extend_from_slice(&[red, green, blue]),
+        }
+        index
+    }
+
+    fn index(&self, pixels: &RgbaImage) -> Vec<u8> {
+        pixels.pixels().map(|pixel| self.indices[pixel]).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use image::{codecs::gif::GifDecoder, AnimationDecoder, DynamicImage};
+
+    use super::*;
+
+    #[test]
+    fn frames_and_delays_are_kept() {
+        let frames: Vec<Image> = (0..3)
+            .map(|index| {
+                let mut image = Image::new(DynamicImage::ImageRgba8(RgbaImage::new(4, 4)));
+                image.delay = Some(Delay::from_numer_denom_ms(index * 100, 1));
+                image
+            })
+            .collect();
+        let mut output = Vec::new();
+        encode_frames(&frames, &mut output, &GifOptions::default()).unwrap();
+
+        let decoder = GifDecoder::new(Cursor::new(output)).unwrap();
+        let decoded = decoder.into_frames().collect_frames().unwrap();
+        assert_eq!(decoded.len(), 3);
+        assert_eq!(decoded[2].delay(), Delay::from_numer_denom_ms(200, 1));
+    }
+
+    #[test]
+    fn color_count_is_honored() {
+        let pixels =
+            RgbaImage::from_fn(32, 32, |x, y| Rgba([(x * 8) as u8, (y * 8) as u8, 0, 255]));
+        let image = Image::new(DynamicImage::ImageRgba8(pixels));
+        let mut modifiers = Modifiers::default();
+        modifiers.defines.insert("gif:colors".into(), "4".into());
+        modifiers.defines.insert("gif:dither".into(), "None".into());
+        let options = GifOptions::from_modifiers(&modifiers).unwrap();
+        let mut output = Vec::new();
+        encode_frames(std::slice::from_ref(&image), &mut output, &options).unwrap();
+
+        let decoded = image::load_from_memory(&output).unwrap().to_rgba8();
+        let colors: std::collections::HashSet<_> = decoded.pixels().collect();
+        assert!(colors.len() <= 4);
+
+        modifiers.defines.insert("gif:colors".into(), "1000".into());
+        assert!(GifOptions::from_modifiers(&modifiers).is_err());
+    }
+
+    /// Two frames that only differ in one pixel
+    fn two_frames() -> Vec<Image> {
+        let first = RgbaImage::from_fn(8, 8, |x, y| Rgba([(x * 30) as u8, (y * 30) as u8, 0, 255]));
+        let mut second = first.clone();
+        second.put_pixel(5, 3, Rgba([0, 0, 255, 255]));
+        [first, second]
+            .map(|pixels| Image::new(DynamicImage::ImageRgba8(pixels)))
+            .to_vec()
+    }
+
+    /// The frames as stored in the file, without compositing them onto the canvas
+    fn raw_frames(output: &[u8]) -> (gif::Decoder<Cursor<&[u8]>>, Vec<gif::Frame<'static>>) {
+        let mut decoder = gif::Decoder::new(Cursor::new(output)).unwrap();
+        let mut frames = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            frames.push(frame.clone());
+        }
+        (decoder, frames)
+    }
+
+    #[test]
+    fn loop_count() {
+        let encode = |iterations| {
+            let options = GifOptions {
+                iterations,
+                ..Default::default()
+            };
+            let mut output = Vec::new();
+            encode_frames(&two_frames(), &mut output, &options).unwrap();
+            let (decoder, _) = raw_frames(&output);
+            decoder.repeat()
+        };
+        assert_eq!(encode(0), gif::Repeat::Infinite);
+        assert_eq!(encode(3), gif::Repeat::Finite(2));
+        // Without the extension, the animation plays once
+        assert_eq!(encode(1), gif::Repeat::Finite(0));
+    }
+
+    #[test]
+    fn global_palette() {
+        for (mode, global) in [(PaletteMode::Local, false), (PaletteMode::Global, true)] {
+            let options = GifOptions {
+                palette: mode,
+                ..Default::default()
+            };
+            let mut output = Vec::new();
+            encode_frames(&two_frames(), &mut output, &options).unwrap();
+            // `gif` always writes a global palette, if only a dummy one, so look at the frames
+            let (_, frames) = raw_frames(&output);
+            assert!(frames.iter().all(|frame| frame.palette.is_some() != global));
+        }
+    }
+
+    #[test]
+    fn frame_differences() {
+        let frames = two_frames();
+        for palette in [PaletteMode::Local, PaletteMode::Global] {
+            let options = GifOptions {
+                palette,
+                optimize: true,
+                ..Default::default()
+            };
+            let mut output = Vec::new();
+            encode_frames(&frames, &mut output, &options).unwrap();
+            let (_, raw) = raw_frames(&output);
+            assert_eq!(
+                (raw[1].left, raw[1].top, raw[1].width, raw[1].height),
+                (5, 3, 1, 1)
+            );
+
+            // Drawn over the first frame, the second one looks the same as before
+            let decoder = GifDecoder::new(Cursor::new(output)).unwrap();
+            let decoded = decoder.into_frames().collect_frames().unwrap();
+            assert_eq!(decoded[1].buffer(), &frames[1].pixels.to_rgba8());
+        }
+    }
+
+    #[test]
+    fn parse_defines() {
+        let mut modifiers = Modifiers {
+            loop_iterations: Some(2),
+            ..Default::default()
+        };
+        modifiers
+            .defines
+            .insert("gif:palette".into(), "Global".into());
+        modifiers
+            .defines
+            .insert("gif:optimize".into(), "true".into());
+        let options = GifOptions::from_modifiers(&modifiers).unwrap();
+        assert_eq!(options.iterations, 2);
+        assert_eq!(options.palette, PaletteMode::Global);
+        assert!(options.optimize);
+
+        modifiers
+            .defines
+            .insert("gif:palette".into(), "shared".into());
+        assert!(GifOptions::from_modifiers(&modifiers).is_err());
+    }
+
+    #[test]
+    fn background_and_transparent_colors() {
+        let mut pixels = RgbaImage::from_pixel(4, 4, Rgba([0, 255, 0, 255]));
+        pixels.put_pixel(0, 0, Rgba([0, 0, 0, 0]));
+        let image = Image::new(DynamicImage::ImageRgba8(pixels));
+        let options = GifOptions {
+            palette: PaletteMode::Global,
+            background: Some([255, 0, 0, 255]),
+            transparent_color: [0, 0, 255],
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        encode_frames(std::slice::from_ref(&image), &mut output, &options).unwrap();
+
+        let (decoder, frames) = raw_frames(&output);
+        let palette = decoder.global_palette().unwrap();
+        let entry = |index: u8| &palette[usize::from(index) * 3..][..3];
+        assert_eq!(entry(output[11]), [255, 0, 0]);
+        assert_eq!(entry(frames[0].transparent.unwrap()), [0, 0, 255]);
+        // Still transparent when decoded
+        assert_eq!(
+            image::load_from_memory(&output)
+                .unwrap()
+                .to_rgba8()
+                .get_pixel(0, 0)[3],
+            0
+        );
+    }
+}
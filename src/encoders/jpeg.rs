@@ -0,0 +1,97 @@
+//! JPEG encoding with imagemagick's defaults
+
+use std::io::Write;
+
+use image::{codecs::jpeg::JpegEncoder, DynamicImage, ExtendedColorType};
+
+use crate::{
+    encoders::common::{optimize_pixel_format, EncoderCapabilities},
+    error::MagickError,
+    wm_try,
+};
+
+/// imagemagick uses this quality unless the input was a JPEG with an estimable quality
+const DEFAULT_QUALITY: u8 = 92;
+
+/// Encodes the image as a baseline JPEG, with imagemagick's default quality unless another one is given.
+///
+/// Grayscale images are written with a single luma component, which makes the file
+/// smaller and faster to encode and decode, same as imagemagick does.
+/// JPEG has no alpha channel, so it is discarded. The comment, if any, is stored in a COM marker.
+pub fn encode<W: Write>(
+    image: &DynamicImage,
+    comment: Option<&str>,
+    quality: Option<u8>,
+    mut writer: W,
+) -> Result<(), MagickError> {
+    let quality = quality.unwrap_or(DEFAULT_QUALITY);
+    let optimized = optimize_pixel_format(image, &EncoderCapabilities::JPEG);
+    let samples = optimized.as_bytes();
+    let color_type = ExtendedColorType::from(optimized.color());
+    // `JpegEncoder::encode_image` would always go through RGBA, so pass the samples directly
+    let Some(comment) = comment else {
+        let mut encoder = JpegEncoder::new_with_quality(writer, quality);
+        wm_try!(encoder.encode(samples, image.width(), image.height(), color_type));
+        return Ok(());
+    };
+    let mut encoded = Vec::new();
+    let mut encoder = JpegEncoder::new_with_quality(&mut encoded, quality);
+    wm_try!(encoder.encode(samples, image.width(), image.height(), color_type));
+    // The comment goes right after the start of image marker
+    let (start, rest) = encoded.split_at(2);
+    wm_try!(writer.write_all(start));
+    wm_try!(writer.write_all(&comment_segments(comment)));
+    wm_try!(writer.write_all(rest));
+    Ok(())
+}
+
+/// COM markers holding the comment, split into several if it is longer than a marker can hold
+fn comment_segments(comment: &str) -> Vec<u8> {
+    // The length field counts itself
+    const MAX_LENGTH: usize = u16::MAX as usize - 2;
+    let mut segments = Vec::new();
+    for chunk in comment.as_bytes().chunks(MAX_LENGTH) {
+        segments.extend_from_slice(&[0xFF, 0xFE]);
+        segments.extend_from_slice(&(chunk.len() as u16 + 2).to_be_bytes());
+        segments.extend_from_slice(chunk);
+    }
+    segments
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{ImageDecoder, Rgb, RgbImage};
+
+    use super::*;
+
+    #[test]
+    fn gray_rgb_is_written_as_luma() {
+        let image = DynamicImage::ImageRgb8(RgbImage::from_fn(8, 8, |x, y| {
+            let v = (x * 16 + y) as u8;
+            Rgb([v, v, v])
+        }));
+        let mut out = Vec::new();
+        encode(&image, None, None, &mut out).unwrap();
+        let decoder = image::codecs::jpeg::JpegDecoder::new(std::io::Cursor::new(out)).unwrap();
+        assert_eq!(decoder.color_type(), image::ColorType::L8);
+    }
+
+    #[test]
+    fn color_is_written_as_rgb() {
+        let image = DynamicImage::ImageRgb8(RgbImage::from_pixel(8, 8, Rgb([255, 0, 0])));
+        let mut out = Vec::new();
+        encode(&image, None, None, &mut out).unwrap();
+        let decoder = image::codecs::jpeg::JpegDecoder::new(std::io::Cursor::new(out)).unwrap();
+        assert_eq!(decoder.color_type(), image::ColorType::Rgb8);
+    }
+
+    #[test]
+    fn comment_is_embedded() {
+        let image = DynamicImage::ImageRgb8(RgbImage::new(8, 8));
+        let mut out = Vec::new();
+        encode(&image, Some("hello"), None, &mut out).unwrap();
+        assert_eq!(&out[..11], b"\xFF\xD8\xFF\xFE\x00\x07hello");
+        assert!(image::load_from_memory(&out).is_ok());
+        assert_eq!(comment_segments(&"a".repeat(70000)).len(), 70000 + 2 * 4);
+    }
+}
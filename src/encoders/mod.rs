@@ -0,0 +1,8 @@
+//! Format-specific encoding logic that goes beyond what `DynamicImage::write_to` does.
+
+pub mod common;
+pub mod gif;
+pub mod jpeg;
+pub mod png;
+pub mod tiff;
+pub mod webp;
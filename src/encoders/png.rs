@@ -0,0 +1,152 @@
+//! PNG encoding with the text metadata set by `-comment` and `-label`,
+//! controlled by imagemagick's `-define png:bit-depth`
+
+use std::io::{Cursor, Write};
+
+use image::ImageFormat;
+
+use super::common::{optimize_pixel_format, EncoderCapabilities};
+use crate::{error::MagickError, image::Image, plan::Modifiers, wm_err, wm_try};
+
+/// Length of the PNG signature plus the IHDR chunk, which must come first
+const HEADER_LENGTH: usize = 8 + 4 + 4 + 13 + 4;
+
+/// Settings read from `-define png:*`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PngOptions {
+    /// `png:bit-depth`: writes 8 or 16 bits per sample regardless of the bit depth of the image,
+    /// e.g. to keep scientific data in 16 bits even when its values would fit in 8
+    pub bit_depth: Option<u8>,
+}
+
+impl PngOptions {
+    pub fn from_modifiers(modifiers: &Modifiers) -> Result<Self, MagickError> {
+        let bit_depth = modifiers.parse_define::<u8>("png:bit-depth")?;
+        if let Some(depth) = bit_depth {
+            if depth != 8 && depth != 16 {
+                return Err(wm_err!(
+                    "invalid value for `-define png:bit-depth': {}, must be 8 or 16",
+                    depth
+                ));
+            }
+        }
+        Ok(Self { bit_depth })
+    }
+
+    fn capabilities(&self) -> EncoderCapabilities {
+        let bit_depths: &'static [u8] = match self.bit_depth {
+            Some(8) => &[8],
+            Some(16) => &[16],
+            _ => EncoderCapabilities::PNG.bit_depths,
+        };
+        EncoderCapabilities {
+            bit_depths,
+            ..EncoderCapabilities::PNG
+        }
+    }
+}
+
+/// Encodes the image with the `image` crate, adding the comment and the label as text chunks
+/// with the same keywords imagemagick uses
+pub fn encode<W: Write>(
+    image: &Image,
+    mut writer: W,
+    options: &PngOptions,
+) -> Result<(), MagickError> {
+    let mut encoded = Vec::new();
+    let pixels = optimize_pixel_format(&image.pixels, &options.capabilities());
+    wm_try!(pixels.write_to(&mut Cursor::new(&mut encoded), ImageFormat::Png));
+    let (header, rest) = encoded.split_at(HEADER_LENGTH);
+    wm_try!(writer.write_all(header));
+    let texts = [("comment", &image.comment), ("label", &image.label)];
+    for (keyword, text) in texts {
+        if let Some(text) = text {
+            wm_try!(writer.write_all(&text_chunk(keyword, text)));
+        }
+    }
+    wm_try!(writer.write_all(rest));
+    Ok(())
+}
+
+/// A `tEXt` chunk, which can only hold Latin-1, or an uncompressed `iTXt` chunk for other text
+fn text_chunk(keyword: &str, text: &str) -> Vec<u8> {
+    let mut data = keyword.as_bytes().to_vec();
+    data.push(0);
+    let chunk_type = match text.chars().map(u32::from).all(|c| c <= 0xff) {
+        true => {
+            data.extend(text.chars().map(|c| c as u8));
+            b"tEXt"
+        }
+        false => {
+            // no compression, then empty language tag and translated keyword
+            data.extend_from_slice(&[0, 0, 0, 0]);
+            data.extend_from_slice(text.as_bytes());
+            b"iTXt"
+        }
+    };
+    let mut chunk = Vec::with_capacity(data.len() + 12);
+    chunk.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    chunk.extend_from_slice(chunk_type);
+    chunk.extend_from_slice(&data);
+    let mut crc = crc32fast::Hasher::new();
+    crc.update(chunk_type);
+    crc.update(&data);
+    chunk.extend_from_slice(&crc.finalize().to_be_bytes());
+    chunk
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{DynamicImage, GrayImage};
+
+    use super::*;
+
+    #[test]
+    fn text_is_embedded() {
+        let mut image = Image::new(DynamicImage::ImageLuma8(GrayImage::new(2, 2)));
+        image.comment = Some("café".to_owned());
+        image.label = Some("日本".to_owned());
+        let mut out = Vec::new();
+        encode(&image, &mut out, &PngOptions::default()).unwrap();
+
+        let decoder = png::Decoder::new(Cursor::new(&out));
+        let reader = decoder.read_info().unwrap();
+        let info = reader.info();
+        assert_eq!(info.uncompressed_latin1_text[0].keyword, "comment");
+        assert_eq!(info.uncompressed_latin1_text[0].text, "café");
+        assert_eq!(info.utf8_text[0].keyword, "label");
+        assert_eq!(info.utf8_text[0].get_text().unwrap(), "日本");
+        // the pixels are still readable
+        assert_eq!(image::load_from_memory(&out).unwrap().width(), 2);
+    }
+
+    fn bit_depth(pixels: DynamicImage, define: Option<&str>) -> png::BitDepth {
+        let mut modifiers = Modifiers::default();
+        if let Some(depth) = define {
+            modifiers
+                .defines
+                .insert("png:bit-depth".to_owned(), depth.to_owned());
+        }
+        let options = PngOptions::from_modifiers(&modifiers).unwrap();
+        let mut out = Vec::new();
+        encode(&Image::new(pixels), &mut out, &options).unwrap();
+        let reader = png::Decoder::new(Cursor::new(&out)).read_info().unwrap();
+        reader.info().bit_depth
+    }
+
+    #[test]
+    fn bit_depth_define() {
+        let eight = || DynamicImage::ImageLuma8(GrayImage::new(2, 2));
+        let sixteen = || DynamicImage::ImageLuma16(image::ImageBuffer::new(2, 2));
+        assert_eq!(bit_depth(eight(), None), png::BitDepth::Eight);
+        assert_eq!(bit_depth(sixteen(), None), png::BitDepth::Sixteen);
+        assert_eq!(bit_depth(eight(), Some("16")), png::BitDepth::Sixteen);
+        assert_eq!(bit_depth(sixteen(), Some("8")), png::BitDepth::Eight);
+
+        let mut modifiers = Modifiers::default();
+        modifiers
+            .defines
+            .insert("png:bit-depth".to_owned(), "4".to_owned());
+        assert!(PngOptions::from_modifiers(&modifiers).is_err());
+    }
+}
@@ -0,0 +1,80 @@
+//! Multi-page TIFF encoding, which the `image` crate does not support
+
+use std::io::{Seek, Write};
+
+use image::{ColorType, DynamicImage};
+use tiff::encoder::{colortype, TiffEncoder};
+
+use super::common::convert;
+use crate::{error::MagickError, image::Image, wm_try};
+
+/// Writes every frame as a separate page of a single TIFF file, keeping its bit depth
+pub fn encode_frames<W: Write + Seek>(frames: &[Image], writer: &mut W) -> Result<(), MagickError> {
+    let mut encoder = wm_try!(TiffEncoder::new(writer));
+    for frame in frames {
+        let (width, height) = (frame.width(), frame.height());
+        let result = match &frame.pixels {
+            DynamicImage::ImageLuma8(buf) => {
+                encoder.write_image::<colortype::Gray8>(width, height, buf)
+            }
+            DynamicImage::ImageLuma16(buf) => {
+                encoder.write_image::<colortype::Gray16>(width, height, buf)
+            }
+            DynamicImage::ImageRgb8(buf) => {
+                encoder.write_image::<colortype::RGB8>(width, height, buf)
+            }
+            DynamicImage::ImageRgba8(buf) => {
+                encoder.write_image::<colortype::RGBA8>(width, height, buf)
+            }
+            DynamicImage::ImageRgb16(buf) => {
+                encoder.write_image::<colortype::RGB16>(width, height, buf)
+            }
+            DynamicImage::ImageRgba16(buf) => {
+                encoder.write_image::<colortype::RGBA16>(width, height, buf)
+            }
+            DynamicImage::ImageRgb32F(buf) => {
+                encoder.write_image::<colortype::RGB32Float>(width, height, buf)
+            }
+            DynamicImage::ImageRgba32F(buf) => {
+                encoder.write_image::<colortype::RGBA32Float>(width, height, buf)
+            }
+            // The encoder has no grayscale with alpha, so it is written as color
+            DynamicImage::ImageLumaA8(_) => {
+                let pixels = convert(&frame.pixels, ColorType::Rgba8).into_rgba8();
+                encoder.write_image::<colortype::RGBA8>(width, height, &pixels)
+            }
+            DynamicImage::ImageLumaA16(_) => {
+                let pixels = convert(&frame.pixels, ColorType::Rgba16).into_rgba16();
+                encoder.write_image::<colortype::RGBA16>(width, height, &pixels)
+            }
+            _ => unreachable!(),
+        };
+        wm_try!(result);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use image::{GrayAlphaImage, RgbImage};
+
+    use super::*;
+
+    #[test]
+    fn writes_every_page() {
+        let frames = [
+            Image::new(DynamicImage::ImageRgb8(RgbImage::new(3, 2))),
+            Image::new(DynamicImage::ImageLumaA8(GrayAlphaImage::new(5, 4))),
+        ];
+        let mut file = Cursor::new(Vec::new());
+        encode_frames(&frames, &mut file).unwrap();
+        file.set_position(0);
+        let mut decoder = tiff::decoder::Decoder::new(file).unwrap();
+        assert_eq!(decoder.dimensions().unwrap(), (3, 2));
+        decoder.next_image().unwrap();
+        assert_eq!(decoder.dimensions().unwrap(), (5, 4));
+        assert!(!decoder.more_images());
+    }
+}
@@ -0,0 +1,374 @@
+//! WebP encoding controlled by imagemagick's `-define webp:*` options
+
+use std::io::Write;
+
+use image_webp::{ColorType, EncoderParams, WebPEncoder};
+
+use crate::{
+    encoders::common::{optimize_pixel_format, EncoderCapabilities},
+    error::MagickError,
+    image::Image,
+    plan::Modifiers,
+    utils::exif,
+    wm_err, wm_try,
+};
+
+/// Settings read from `-define webp:*`, with the same defaults as imagemagick.
+///
+/// Only lossless encoding is available, so instead of lowering the quality
+/// the file size is reduced by near-lossless preprocessing and by quantizing the alpha channel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WebpOptions {
+    /// `webp:method`: 0 is the fastest and 6 the slowest but most compact
+    pub method: u8,
+    /// `webp:alpha-quality`: 100 keeps the alpha channel intact, lower values quantize it
+    pub alpha_quality: u8,
+    /// `webp:near-lossless`: 100 is off, lower values allow larger changes to pixels on edges
+    pub near_lossless: u8,
+    /// `webp:target-size`: the desired file size in bytes, reached by lowering `near_lossless`
+    pub target_size: Option<u64>,
+}
+
+impl Default for WebpOptions {
+    fn default() -> Self {
+        Self {
+            method: 4,
+            alpha_quality: 100,
+            near_lossless: 100,
+            target_size: None,
+        }
+    }
+}
+
+impl WebpOptions {
+    pub fn from_modifiers(modifiers: &Modifiers) -> Result<Self, MagickError> {
+        let mut options = Self::default();
+        if let Some(method) = modifiers.parse_define::<u8>("webp:method")? {
+            options.method = method.min(6);
+        }
+        // Only lossless encoding is available, so asking for lossy is an error rather than ignored
+        if let Some(lossless) = modifiers.define("webp:lossless") {
+            match lossless.to_ascii_lowercase().as_str() {
+                "true" | "1" | "" => (),
+                "false" | "0" => {
+                    return Err(wm_err!(
+                        "lossy WebP encoding is not supported, use `-define webp:near-lossless' or `-define webp:target-size' to reduce the file size"
+                    ))
+                }
+                _ => {
+                    return Err(wm_err!(
+                        "invalid value for `-define webp:lossless': {}",
+                        lossless
+                    ))
+                }
+            }
+        }
+        if let Some(quality) = modifiers.parse_define::<u8>("webp:alpha-quality")? {
+            options.alpha_quality = quality.min(100);
+        }
+        if let Some(level) = modifiers.parse_define::<u8>("webp:near-lossless")? {
+            options.near_lossless = level.min(100);
+        }
+        options.target_size = modifiers
+            .parse_define("webp:target-size")?
+            .filter(|&size| size > 0);
+        Ok(options)
+    }
+}
+
+/// Encodes the image as a lossless WebP, in the smallest pixel format that represents it exactly.
+///
+/// The ICC profile and EXIF metadata are embedded, unless they have been removed with `-strip`.
+pub fn encode<W: Write>(
+    image: &Image,
+    mut writer: W,
+    options: &WebpOptions,
+) -> Result<(), MagickError> {
+    let metadata = Metadata::of(image);
+    let image = &image.pixels;
+    let optimized = optimize_pixel_format(image, &EncoderCapabilities::WEBP);
+    let has_alpha = optimized.color().has_alpha();
+    let mut samples = optimized.as_bytes().to_vec();
+    let color = match (optimized.color().has_color(), has_alpha) {
+        (false, false) => ColorType::L8,
+        (false, true) => ColorType::La8,
+        (true, false) => ColorType::Rgb8,
+        (true, true) => ColorType::Rgba8,
+    };
+    let (width, height) = (image.width(), image.height());
+    if has_alpha {
+        let channels = usize::from(optimized.color().channel_count());
+        quantize_alpha(&mut samples, channels, options.alpha_quality);
+    }
+
+    let mut level = options.near_lossless;
+    let mut output = encode_at_level(&samples, width, height, color, &metadata, options, level)?;
+    // Like libwebp, try increasingly lossy settings until the file is small enough
+    if let Some(target) = options.target_size {
+        while output.len() as u64 > target && level > 0 {
+            level = level.saturating_sub(20);
+            output = encode_at_level(&samples, width, height, color, &metadata, options, level)?;
+        }
+    }
+    wm_try!(writer.write_all(&output));
+    Ok(())
+}
+
+/// The metadata chunks written alongside the pixels
+struct Metadata {
+    icc_profile: Option<Vec<u8>>,
+    exif: Option<Vec<u8>>,
+}
+
+impl Metadata {
+    fn of(image: &Image) -> Self {
+        let exif = image.exif.clone().map(|mut exif| {
+            // The pixels may have been rotated by `-auto-orient` since the file was read
+            exif::set_orientation(&mut exif, image.orientation);
+            exif
+        });
+        Self {
+            icc_profile: image.icc_profile.clone(),
+            exif,
+        }
+    }
+}
+
+fn encode_at_level(
+    samples: &[u8],
+    width: u32,
+    height: u32,
+    color: ColorType,
+    metadata: &Metadata,
+    options: &WebpOptions,
+    near_lossless: u8,
+) -> Result<Vec<u8>, MagickError> {
+    let mut output = Vec::new();
+    let mut encoder = WebPEncoder::new(&mut output);
+    let mut params = EncoderParams::default();
+    // The predictor transform is what makes encoding slow, and method 0 asks for the fastest
+    params.use_predictor_transform = options.method > 0;
+    encoder.set_params(params);
+    if let Some(icc_profile) = &metadata.icc_profile {
+        encoder.set_icc_profile(icc_profile.clone());
+    }
+    if let Some(exif) = &metadata.exif {
+        encoder.set_exif_metadata(exif.clone());
+    }
+    let channels = samples.len() / (width as usize * height as usize).max(1);
+    if near_lossless < 100 {
+        let mut samples = samples.to_vec();
+        apply_near_lossless(&mut samples, width as usize, channels, near_lossless);
+        wm_try!(encoder.encode(&samples, width, height, color));
+    } else {
+        wm_try!(encoder.encode(samples, width, height, color));
+    }
+    Ok(output)
+}
+
+/// Reduces the alpha channel to fewer distinct values,
+/// with the same number of levels per quality setting as libwebp
+fn quantize_alpha(samples: &mut [u8], channels: usize, quality: u8) {
+    let quality = u32::from(quality);
+    let levels = if quality <= 70 {
+        2 + quality / 5
+    } else {
+        16 + (quality - 70) * 8
+    };
+    if levels >= 256 {
+        return;
+    }
+    let steps = levels - 1;
+    for pixel in samples.chunks_exact_mut(channels) {
+        let alpha = &mut pixel[channels - 1];
+        let level = (u32::from(*alpha) * steps + 127) / 255;
+        *alpha = ((level * 255 + steps / 2) / steps) as u8;
+    }
+}
+
+/// Port of libwebp's near-lossless preprocessing: pixels that differ from their neighbours
+/// by more than a threshold get their low bits rounded off, so that they compress better.
+/// Smooth areas, where such rounding would be visible, are left intact.
+fn apply_near_lossless(samples: &mut [u8], width: usize, channels: usize, level: u8) {
+    let stride = width * channels;
+    let height = samples.len().checked_div(stride).unwrap_or(0);
+    // Small images don't benefit much and the artifacts would be noticeable
+    if width < 64 && height < 64 || height < 3 || width < 3 {
+        return;
+    }
+    let max_bits = 5 - u32::from(level) / 20;
+    for bits in (1..=max_bits).rev() {
+        let limit = 1i16 << bits;
+        let source = samples.to_vec();
+        let pixel = |x: usize, y: usize| &source[y * stride + x * channels..][..channels];
+        for y in 1..height - 1 {
+            for x in 1..width - 1 {
+                let center = pixel(x, y);
+                let smooth = [
+                    pixel(x - 1, y),
+                    pixel(x + 1, y),
+                    pixel(x, y - 1),
+                    pixel(x, y + 1),
+                ]
+                .iter()
+                .all(|neighbour| {
+                    neighbour
+                        .iter()
+                        .zip(center)
+                        .all(|(&n, &c)| (i16::from(n) - i16::from(c)).abs() < limit)
+                });
+                if !smooth {
+                    let start = y * stride + x * channels;
+                    for sample in &mut samples[start..start + channels] {
+                        *sample = discretize(*sample, bits);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Rounds to the closest multiple of `1 << bits`, the way libwebp does it
+fn discretize(value: u8, bits: u32) -> u8 {
+    let mask = (1u32 << bits) - 1;
+    let value = u32::from(value);
+    let biased = value + (mask >> 1) + ((value >> bits) & 1);
+    if biased > 0xFF {
+        0xFF
+    } else {
+        (biased & !mask) as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{DynamicImage, GrayAlphaImage, ImageDecoder, LumaA, Rgb, RgbImage};
+
+    use super::*;
+
+    fn modifiers(defines: &[(&str, &str)]) -> Modifiers {
+        let mut modifiers = Modifiers::default();
+        for (key, value) in defines {
+            modifiers.defines.insert(key.to_string(), value.to_string());
+        }
+        modifiers
+    }
+
+    fn noisy_image() -> Image {
+        let mut state = 1u32;
+        Image::new(DynamicImage::ImageRgb8(RgbImage::from_fn(
+            96,
+            96,
+            |x, _| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                let noise = (state >> 24) as u8;
+                Rgb([noise, noise / 2, x as u8])
+            },
+        )))
+    }
+
+    fn encoded_len(image: &Image, options: &WebpOptions) -> usize {
+        let mut out = Vec::new();
+        encode(image, &mut out, options).unwrap();
+        out.len()
+    }
+
+    #[test]
+    fn parse_defines() {
+        let options = WebpOptions::from_modifiers(&modifiers(&[
+            ("webp:method", "0"),
+            ("webp:lossless", "true"),
+            ("webp:alpha-quality", "50"),
+            ("webp:near-lossless", "60"),
+            ("webp:target-size", "1000"),
+        ]))
+        .unwrap();
+        assert_eq!(
+            options,
+            WebpOptions {
+                method: 0,
+                alpha_quality: 50,
+                near_lossless: 60,
+                target_size: Some(1000),
+            }
+        );
+        assert_eq!(
+            WebpOptions::from_modifiers(&Modifiers::default()).unwrap(),
+            WebpOptions::default()
+        );
+        assert!(WebpOptions::from_modifiers(&modifiers(&[("webp:method", "fast")])).is_err());
+        assert!(WebpOptions::from_modifiers(&modifiers(&[("webp:lossless", "false")])).is_err());
+    }
+
+    #[test]
+    fn lossless_by_default() {
+        let image = noisy_image();
+        let mut out = Vec::new();
+        encode(&image, &mut out, &WebpOptions::default()).unwrap();
+        let decoded = image::load_from_memory(&out).unwrap();
+        assert_eq!(decoded.to_rgb8(), image.pixels.to_rgb8());
+    }
+
+    #[test]
+    fn near_lossless_shrinks_the_file() {
+        let image = noisy_image();
+        let lossless = encoded_len(&image, &WebpOptions::default());
+        let near_lossless = WebpOptions {
+            near_lossless: 0,
+            ..Default::default()
+        };
+        assert!(encoded_len(&image, &near_lossless) < lossless);
+    }
+
+    #[test]
+    fn target_size_lowers_the_quality() {
+        let image = noisy_image();
+        let lossless = encoded_len(&image, &WebpOptions::default());
+        let targeted = WebpOptions {
+            target_size: Some(lossless as u64 / 2),
+            ..Default::default()
+        };
+        assert!(encoded_len(&image, &targeted) < lossless);
+    }
+
+    #[test]
+    fn alpha_quality_quantizes_alpha() {
+        let image = Image::new(DynamicImage::ImageLumaA8(GrayAlphaImage::from_fn(
+            16,
+            1,
+            |x, _| LumaA([0, (x * 16) as u8]),
+        )));
+        let options = WebpOptions {
+            alpha_quality: 0,
+            ..Default::default()
+        };
+        let mut out = Vec::new();
+        encode(&image, &mut out, &options).unwrap();
+        let decoded = image::load_from_memory(&out).unwrap().to_luma_alpha8();
+        assert!(decoded.pixels().all(|p| p[1] == 0 || p[1] == 255));
+    }
+
+    #[test]
+    fn metadata_is_embedded() {
+        let mut image = Image::new(DynamicImage::ImageRgb8(RgbImage::new(4, 4)));
+        image.icc_profile = Some(b"not really an ICC profile".to_vec());
+        // orientation 6, which `-auto-orient` has already applied to the pixels
+        let mut exif = vec![0x4d, 0x4d, 0, 42, 0, 0, 0, 8, 0, 1];
+        exif.extend_from_slice(&[0x01, 0x12, 0, 3, 0, 0, 0, 1, 0, 6, 0, 0, 0, 0, 0, 0]);
+        image.exif = Some(exif);
+        let mut out = Vec::new();
+        encode(&image, &mut out, &WebpOptions::default()).unwrap();
+
+        let mut decoder =
+            image::codecs::webp::WebPDecoder::new(std::io::Cursor::new(&out)).unwrap();
+        assert_eq!(decoder.icc_profile().unwrap(), image.icc_profile);
+        assert_eq!(decoder.exif_metadata().unwrap().unwrap()[19], 1);
+    }
+
+    #[test]
+    fn discretize_rounds_like_libwebp() {
+        assert_eq!(discretize(5, 2), 4);
+        assert_eq!(discretize(6, 2), 8);
+        assert_eq!(discretize(254, 3), 255);
+    }
+}
@@ -15,6 +15,25 @@ impl Debug for MagickError {
 
 impl std::error::Error for MagickError {}
 
+/// imagemagick is killed by SIGPIPE when the reader of its output goes away, e.g. `head` that has read enough,
+/// which the shell reports as 128 + 13. Rust ignores SIGPIPE and reports EPIPE instead,
+/// so the binaries exit with the same status by hand when they get a [MagickError::is_broken_pipe] error.
+pub const BROKEN_PIPE_EXIT_CODE: u8 = 128 + 13;
+
+const BROKEN_PIPE_MESSAGE: &str = "wondermagick: broken pipe";
+
+impl MagickError {
+    /// The error for writing to a pipe that nobody reads anymore, which `wm_try!` turns EPIPE into
+    pub fn broken_pipe() -> Self {
+        MagickError(BROKEN_PIPE_MESSAGE.to_owned())
+    }
+
+    /// Whether nobody reads the output anymore, which ends the whole run quietly
+    pub fn is_broken_pipe(&self) -> bool {
+        self.0 == BROKEN_PIPE_MESSAGE
+    }
+}
+
 /// Like `format!`, but returns a `MagickError` instead of a `String`,
 /// and records the source code location where it was called.
 /// We use it to imitate the structure of imagemagick's error messages.
@@ -68,12 +87,16 @@ macro_rules! wm_try {
                 // This only happens a handful of times per execution and only when we're bailing out anyway,
                 // so we can easily afford the slight runtime overhead of copying a string.
                 let magick_type_id = TypeId::of::<$crate::error::MagickError>();
+                let io_error = (&err as &dyn std::any::Any).downcast_ref::<std::io::Error>();
                 let magick_error = if get_type_id(&err) == magick_type_id {
                     // Even though we know *at runtime* that we are dealing with a MagickError,
                     // we still need this to compile for any type.
                     // We achieve this by using `to_string()` from the `Display` trait
                     // because anything that implements `Error` also implements `Display`.
                     $crate::error::MagickError(err.to_string())
+                } else if io_error.is_some_and(|e| e.kind() == std::io::ErrorKind::BrokenPipe) {
+                    // Kept recognizable so that the binaries can exit like imagemagick does
+                    $crate::error::MagickError::broken_pipe()
                 } else {
                     // Convert the foreign error into our format
                     wm_err!("{}", err)
@@ -0,0 +1,207 @@
+//! Identification of image formats: their names as printed by imagemagick,
+//! and recognizing formats that we cannot decode by their magic bytes.
+
+use std::{path::Path, str::FromStr};
+
+use image::ImageFormat;
+use strum::EnumString;
+
+/// A format we can decode, either through the `image` crate or with our own decoders,
+/// or a pseudo-file that is not read from a file at all
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileFormat {
+    Image(ImageFormat),
+    /// Photoshop document; only the flattened composite image is read
+    #[cfg(feature = "psd")]
+    Psd,
+    /// JPEG 2000 in the JP2 file format
+    #[cfg(feature = "jp2")]
+    Jp2,
+    /// A raw JPEG 2000 codestream
+    #[cfg(feature = "jp2")]
+    J2k,
+    /// An image generated from the settings in effect, such as `label:Hello`
+    Pseudo(PseudoFormat),
+}
+
+/// The prefixes of pseudo-files, which are generated or consumed instead of being a file on disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumString)]
+#[strum(serialize_all = "lowercase", ascii_case_insensitive)]
+pub enum PseudoFormat {
+    Label,
+    Caption,
+    Pango,
+    Null,
+    Info,
+}
+
+impl PseudoFormat {
+    /// Looks up a pseudo-file by its prefix, such as `label` in `label:Hello`
+    pub fn from_prefix(prefix: &str) -> Option<Self> {
+        Self::from_str(prefix).ok()
+    }
+}
+
+impl FileFormat {
+    /// Looks up the format by a file extension or an explicit prefix such as `qoi:`.
+    ///
+    /// On top of the extensions known to the `image` crate this accepts the names imagemagick uses,
+    /// e.g. `farbfeld` along with `ff`.
+    pub fn from_extension(extension: &str) -> Option<FileFormat> {
+        match extension.to_ascii_lowercase().as_str() {
+            "farbfeld" | "ff" => Some(FileFormat::Image(ImageFormat::Farbfeld)),
+            "qoi" => Some(FileFormat::Image(ImageFormat::Qoi)),
+            #[cfg(feature = "psd")]
+            "psd" | "psb" => Some(FileFormat::Psd),
+            #[cfg(feature = "jp2")]
+            "jp2" => Some(FileFormat::Jp2),
+            #[cfg(feature = "jp2")]
+            "j2k" | "j2c" | "jpc" => Some(FileFormat::J2k),
+            other => ImageFormat::from_extension(other).map(FileFormat::Image),
+        }
+    }
+
+    /// Looks up the format by the extension of the file
+    pub fn from_path(path: &Path) -> Option<FileFormat> {
+        Self::from_extension(path.extension()?.to_str()?)
+    }
+
+    /// The formats common on the web, whose decoders see the most use and fuzzing.
+    /// They are the only ones read and written with `--wm-untrusted`.
+    pub fn is_web_safe(&self) -> bool {
+        matches!(
+            self,
+            FileFormat::Image(
+                ImageFormat::Png | ImageFormat::Jpeg | ImageFormat::Gif | ImageFormat::WebP
+            )
+        )
+    }
+
+    /// Name of the format as imagemagick prints it
+    pub fn name(&self) -> &'static str {
+        match self {
+            FileFormat::Image(ImageFormat::Png) => "PNG",
+            FileFormat::Image(ImageFormat::Jpeg) => "JPEG",
+            FileFormat::Image(ImageFormat::Gif) => "GIF",
+            FileFormat::Image(ImageFormat::WebP) => "WEBP",
+            FileFormat::Image(ImageFormat::Pnm) => "PNM",
+            FileFormat::Image(ImageFormat::Tiff) => "TIFF",
+            FileFormat::Image(ImageFormat::Tga) => "TGA",
+            FileFormat::Image(ImageFormat::Dds) => "DDS",
+            FileFormat::Image(ImageFormat::Bmp) => "BMP",
+            FileFormat::Image(ImageFormat::Ico) => "ICO",
+            FileFormat::Image(ImageFormat::Hdr) => "HDR",
+            FileFormat::Image(ImageFormat::OpenExr) => "EXR",
+            FileFormat::Image(ImageFormat::Farbfeld) => "FARBFELD",
+            FileFormat::Image(ImageFormat::Avif) => "AVIF",
+            FileFormat::Image(ImageFormat::Qoi) => "QOI",
+            FileFormat::Image(_) => "UNKNOWN",
+            #[cfg(feature = "psd")]
+            FileFormat::Psd => "PSD",
+            #[cfg(feature = "jp2")]
+            FileFormat::Jp2 => "JP2",
+            #[cfg(feature = "jp2")]
+            FileFormat::J2k => "J2K",
+            FileFormat::Pseudo(PseudoFormat::Label) => "LABEL",
+            FileFormat::Pseudo(PseudoFormat::Caption) => "CAPTION",
+            FileFormat::Pseudo(PseudoFormat::Pango) => "PANGO",
+            FileFormat::Pseudo(PseudoFormat::Null) => "NULL",
+            FileFormat::Pseudo(PseudoFormat::Info) => "INFO",
+        }
+    }
+}
+
+/// Name of the format as imagemagick prints it, or `UNKNOWN` if the image was not read from a file
+pub fn format_name(format: Option<FileFormat>) -> &'static str {
+    format.map_or("UNKNOWN", |f| f.name())
+}
+
+/// A format we can recognize but not decode
+struct Signature {
+    /// Name of the format as imagemagick prints it
+    name: &'static str,
+    offset: usize,
+    magic: &'static [u8],
+}
+
+const fn signature(name: &'static str, offset: usize, magic: &'static [u8]) -> Signature {
+    Signature {
+        name,
+        offset,
+        magic,
+    }
+}
+
+/// Magic bytes of formats that imagemagick supports and the `image` crate doesn't.
+///
+/// Formats that the `image` crate can recognize are not listed here, see [image::guess_format].
+const SIGNATURES: &[Signature] = &[
+    signature("PSD", 0, b"8BPS"),
+    signature("DJVU", 0, b"AT&TFORM"),
+    signature("JP2", 0, b"\x00\x00\x00\x0cjP  \r\n\x87\n"),
+    signature("J2K", 0, b"\xff\x4f\xff\x51"),
+    signature("JXL", 0, b"\x00\x00\x00\x0cJXL \r\n\x87\n"),
+    signature("JXL", 0, b"\xff\x0a"),
+    signature("HEIC", 4, b"ftypheic"),
+    signature("HEIC", 4, b"ftypheix"),
+    signature("HEIC", 4, b"ftypmif1"),
+    signature("XCF", 0, b"gimp xcf"),
+    signature("PDF", 0, b"%PDF"),
+    signature("PS", 0, b"%!"),
+    signature("SVG", 0, b"<svg"),
+    signature("MIFF", 0, b"id=ImageMagick"),
+    signature("MNG", 0, b"\x8aMNG\r\n\x1a\n"),
+    signature("JNG", 0, b"\x8bJNG\r\n\x1a\n"),
+    signature("WMF", 0, b"\xd7\xcd\xc6\x9a"),
+    signature("FITS", 0, b"SIMPLE  ="),
+    signature("DPX", 0, b"SDPX"),
+    signature("DPX", 0, b"XPDS"),
+    signature("CIN", 0, b"\x80\x2a\x5f\xd7"),
+    signature("SGI", 0, b"\x01\xda"),
+    signature("XPM", 0, b"/* XPM */"),
+    signature("DCM", 128, b"DICM"),
+    signature("BPG", 0, b"BPG\xfb"),
+    signature("FLIF", 0, b"FLIF"),
+];
+
+/// Identifies formats that we recognize but cannot decode, for error reporting
+pub fn sniff_unsupported(header: &[u8]) -> Option<&'static str> {
+    SIGNATURES
+        .iter()
+        .find(|s| {
+            header
+                .get(s.offset..)
+                .is_some_and(|h| h.starts_with(s.magic))
+        })
+        .map(|s| s.name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sniff() {
+        assert_eq!(sniff_unsupported(b"8BPS\x00\x01"), Some("PSD"));
+        assert_eq!(
+            sniff_unsupported(b"AT&TFORM\x00\x00\x00\x00DJVU"),
+            Some("DJVU")
+        );
+        assert_eq!(sniff_unsupported(b"\x00\x00\x00\x18ftypheic"), Some("HEIC"));
+        assert_eq!(sniff_unsupported(b"\x89PNG\r\n\x1a\n"), None);
+        // too short for the DICOM signature
+        assert_eq!(sniff_unsupported(b"DICM"), None);
+    }
+
+    #[test]
+    fn pseudo_formats() {
+        assert_eq!(
+            PseudoFormat::from_prefix("Label"),
+            Some(PseudoFormat::Label)
+        );
+        assert_eq!(PseudoFormat::from_prefix("png"), None);
+        // pseudo-files are only recognized by their prefix, never by the extension
+        assert_eq!(FileFormat::from_extension("null"), None);
+        assert_eq!(FileFormat::Pseudo(PseudoFormat::Null).name(), "NULL");
+    }
+}
@@ -0,0 +1,289 @@
+//! The fx expression language, e.g. `w > h ? w : h`.
+//!
+//! Arithmetic, comparisons, logical and conditional operators, common functions and constants
+//! are supported. Symbols such as `w`, `mean.r` or the pixel value `u` are looked up
+//! in a [Context] provided by the caller, so the same expressions work in `%[fx:...]` and `-fx`.
+//! Pixels at other coordinates, such as `p[-1,0]`, cannot be referred to.
+//!
+//! See <https://imagemagick.org/script/fx.php>
+
+mod parse;
+
+use crate::{error::MagickError, image::QUANTUM_RANGE, wm_err};
+
+/// Parsed fx expression
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Number(f64),
+    /// A variable such as `w`, optionally restricted to a channel like `mean.r`
+    Symbol {
+        name: String,
+        channel: Option<char>,
+    },
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    /// `condition ? then : otherwise`
+    Conditional(Box<Expr>, Box<Expr>, Box<Expr>),
+    Call(String, Vec<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Negate,
+    Not,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Or,
+    And,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
+    Power,
+}
+
+/// Provides the values of the symbols in an expression
+pub trait Context {
+    /// Returns the value of the symbol, or `None` if it is not defined
+    fn symbol(&self, name: &str, channel: Option<char>) -> Option<f64>;
+}
+
+impl Expr {
+    pub fn parse(source: &str) -> Result<Self, MagickError> {
+        parse::parse(source)
+    }
+
+    /// Checks if the expression refers to the symbol, with or without a channel
+    pub fn uses_symbol(&self, symbol: &str) -> bool {
+        match self {
+            Expr::Number(_) => false,
+            Expr::Symbol { name, .. } => name == symbol,
+            Expr::Unary(_, operand) => operand.uses_symbol(symbol),
+            Expr::Binary(_, left, right) => left.uses_symbol(symbol) || right.uses_symbol(symbol),
+            Expr::Conditional(condition, then, otherwise) => [condition, then, otherwise]
+                .iter()
+                .any(|expr| expr.uses_symbol(symbol)),
+            Expr::Call(_, args) => args.iter().any(|expr| expr.uses_symbol(symbol)),
+        }
+    }
+
+    pub fn evaluate(&self, context: &dyn Context) -> Result<f64, MagickError> {
+        let truth = |value: bool| if value { 1.0 } else { 0.0 };
+        Ok(match self {
+            Expr::Number(value) => *value,
+            Expr::Symbol { name, channel } => match context.symbol(name, *channel) {
+                Some(value) => value,
+                None if channel.is_none() => {
+                    constant(name).ok_or_else(|| wm_err!("undefined variable `{}'", name))?
+                }
+                None => return Err(wm_err!("undefined variable `{}'", name)),
+            },
+            Expr::Unary(op, operand) => {
+                let value = operand.evaluate(context)?;
+                match op {
+                    UnaryOp::Negate => -value,
+                    UnaryOp::Not => truth(value == 0.0),
+                }
+            }
+            Expr::Binary(op, left, right) => {
+                let left = left.evaluate(context)?;
+                // Like in C, the right side of `&&` and `||` is only evaluated if it matters
+                match op {
+                    BinaryOp::And if left == 0.0 => return Ok(0.0),
+                    BinaryOp::Or if left != 0.0 => return Ok(1.0),
+                    _ => (),
+                }
+                let right = right.evaluate(context)?;
+                match op {
+                    BinaryOp::Or | BinaryOp::And => truth(right != 0.0),
+                    BinaryOp::Equal => truth(left == right),
+                    BinaryOp::NotEqual => truth(left != right),
+                    BinaryOp::Less => truth(left < right),
+                    BinaryOp::LessEqual => truth(left <= right),
+                    BinaryOp::Greater => truth(left > right),
+                    BinaryOp::GreaterEqual => truth(left >= right),
+                    BinaryOp::Add => left + right,
+                    BinaryOp::Subtract => left - right,
+                    BinaryOp::Multiply => left * right,
+                    BinaryOp::Divide => left / right,
+                    BinaryOp::Modulo => left % right,
+                    BinaryOp::Power => left.powf(right),
+                }
+            }
+            Expr::Conditional(condition, then, otherwise) => {
+                match condition.evaluate(context)? != 0.0 {
+                    true => then.evaluate(context)?,
+                    false => otherwise.evaluate(context)?,
+                }
+            }
+            Expr::Call(name, arguments) => {
+                let values = arguments
+                    .iter()
+                    .map(|argument| argument.evaluate(context))
+                    .collect::<Result<Vec<f64>, _>>()?;
+                call(name, &values)?
+            }
+        })
+    }
+}
+
+/// Named constants, which symbols from the context take precedence over
+fn constant(name: &str) -> Option<f64> {
+    match name {
+        "pi" | "Pi" | "PI" => Some(std::f64::consts::PI),
+        "e" | "E" => Some(std::f64::consts::E),
+        "QuantumRange" | "MaxRGB" => Some(QUANTUM_RANGE),
+        "QuantumScale" => Some(1.0 / QUANTUM_RANGE),
+        "Opaque" => Some(1.0),
+        "Transparent" => Some(0.0),
+        _ => None,
+    }
+}
+
+fn call(name: &str, arguments: &[f64]) -> Result<f64, MagickError> {
+    let value = match (name, arguments) {
+        ("abs", &[x]) => x.abs(),
+        ("acos", &[x]) => x.acos(),
+        ("asin", &[x]) => x.asin(),
+        ("atan", &[x]) => x.atan(),
+        ("atan2", &[y, x]) => y.atan2(x),
+        ("ceil", &[x]) => x.ceil(),
+        ("cos", &[x]) => x.cos(),
+        ("exp", &[x]) => x.exp(),
+        ("floor", &[x]) => x.floor(),
+        ("hypot", &[x, y]) => x.hypot(y),
+        ("int" | "trunc", &[x]) => x.trunc(),
+        ("ln", &[x]) => x.ln(),
+        ("log", &[x]) => x.log10(),
+        ("logtwo", &[x]) => x.log2(),
+        ("max", &[x, y]) => x.max(y),
+        ("min", &[x, y]) => x.min(y),
+        ("mod", &[x, y]) => x % y,
+        ("pow", &[x, y]) => x.powf(y),
+        ("round", &[x]) => x.round(),
+        ("sign", &[x]) => {
+            if x < 0.0 {
+                -1.0
+            } else if x > 0.0 {
+                1.0
+            } else {
+                0.0
+            }
+        }
+        ("sin", &[x]) => x.sin(),
+        ("sqrt", &[x]) => x.sqrt(),
+        ("tan", &[x]) => x.tan(),
+        _ => {
+            return Err(wm_err!(
+                "unknown function or wrong number of arguments `{}'",
+                name
+            ))
+        }
+    };
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Landscape;
+
+    impl Context for Landscape {
+        fn symbol(&self, name: &str, channel: Option<char>) -> Option<f64> {
+            match (name, channel) {
+                ("w", None) => Some(640.0),
+                ("h", None) => Some(480.0),
+                ("mean", Some('r')) => Some(0.25),
+                _ => None,
+            }
+        }
+    }
+
+    fn eval(source: &str) -> f64 {
+        Expr::parse(source).unwrap().evaluate(&Landscape).unwrap()
+    }
+
+    #[test]
+    fn arithmetic_and_precedence() {
+        assert_eq!(eval("1 + 2 * 3"), 7.0);
+        assert_eq!(eval("(1 + 2) * 3"), 9.0);
+        assert_eq!(eval("2 ^ 3 ^ 2"), 512.0);
+        assert_eq!(eval("-2 ^ 2"), 4.0);
+        assert_eq!(eval("10 - 4 - 3"), 3.0);
+        assert_eq!(eval("7 % 4"), 3.0);
+        assert_eq!(eval("50%"), 0.5);
+        assert_eq!(eval("50% * w"), 320.0);
+        assert_eq!(eval("1.5e2"), 150.0);
+        assert_eq!(eval("w/h"), 640.0 / 480.0);
+        assert_eq!(eval("mean.r * 2"), 0.5);
+    }
+
+    #[test]
+    fn comparisons_and_conditionals() {
+        assert_eq!(eval("w > h"), 1.0);
+        assert_eq!(eval("w <= h"), 0.0);
+        assert_eq!(eval("w == 640 && h != 480"), 0.0);
+        assert_eq!(eval("w < h || !0"), 1.0);
+        assert_eq!(eval("w > h ? 1 : 0"), 1.0);
+        assert_eq!(eval("w < h ? 1 : h > 500 ? 2 : 3"), 3.0);
+        assert_eq!(eval("(w > h ? w : h) / 2"), 320.0);
+    }
+
+    #[test]
+    fn functions_and_constants() {
+        assert_eq!(eval("max(w, h)"), 640.0);
+        assert_eq!(eval("round(2.5) + floor(-0.5)"), 2.0);
+        assert_eq!(eval("cos(pi)"), -1.0);
+        assert_eq!(eval("QuantumRange"), 65535.0);
+    }
+
+    #[test]
+    fn symbol_usage() {
+        let expr = Expr::parse("u > 0.5 ? max(v.r, 1) : 0").unwrap();
+        assert!(expr.uses_symbol("u"));
+        assert!(expr.uses_symbol("v"));
+        assert!(!expr.uses_symbol("w"));
+    }
+
+    #[test]
+    fn errors() {
+        for source in ["", "1 +", "(1", "1 ? 2", "w h", "2 $ 3"] {
+            assert!(Expr::parse(source).is_err(), "{source}");
+        }
+        let undefined = Expr::parse("foo + 1").unwrap();
+        assert!(undefined.evaluate(&Landscape).is_err());
+        let call = Expr::parse("max(1)").unwrap();
+        assert!(call.evaluate(&Landscape).is_err());
+        // the right side of a short-circuiting operator is not evaluated
+        assert_eq!(eval("0 && foo"), 0.0);
+    }
+
+    #[test]
+    fn deep_nesting_is_rejected() {
+        let nested = |depth: usize| format!("{}1{}", "(".repeat(depth), ")".repeat(depth));
+        assert_eq!(eval(&nested(200)), 1.0);
+        assert_eq!(eval(&format!("{}1", "-".repeat(200))), 1.0);
+        assert_eq!(eval(&vec!["1"; 200].join("+")), 200.0);
+        for source in [
+            nested(50_000),
+            format!("{}1", "-".repeat(60_000)),
+            format!("{}1", "!".repeat(60_000)),
+            vec!["2"; 60_000].join("^"),
+            vec!["1"; 60_000].join("+"),
+            format!("{}0", "max(".repeat(50_000)),
+            format!("{}1", "1?".repeat(50_000)),
+        ] {
+            assert!(Expr::parse(&source).is_err());
+        }
+    }
+}
@@ -0,0 +1,272 @@
+//! Parsing fx expressions into a syntax tree, by recursive descent over the operator precedence levels
+
+use super::{BinaryOp, Expr, UnaryOp};
+use crate::{error::MagickError, wm_err};
+
+/// Parses the whole expression, rejecting anything left over after it
+pub fn parse(source: &str) -> Result<Expr, MagickError> {
+    let mut parser = Parser {
+        source,
+        chars: source.char_indices().collect(),
+        position: 0,
+        depth: 0,
+    };
+    let expr = parser.ternary()?;
+    parser.skip_whitespace();
+    if parser.position < parser.chars.len() {
+        return Err(parser.error());
+    }
+    Ok(expr)
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    chars: Vec<(usize, char)>,
+    position: usize,
+    /// How deeply the expression is nested so far, see [MAX_DEPTH]
+    depth: usize,
+}
+
+/// Parsing and evaluation recurse on the nesting of the expression,
+/// so deeper expressions are rejected rather than overflowing the stack
+const MAX_DEPTH: usize = 256;
+
+/// Binary operators from the loosest to the tightest binding, all left-associative.
+/// Longer operators come before their prefixes so that `<=` is not read as `<`.
+const LEVELS: &[&[(&str, BinaryOp)]] = &[
+    &[("||", BinaryOp::Or)],
+    &[("&&", BinaryOp::And)],
+    &[("==", BinaryOp::Equal), ("!=", BinaryOp::NotEqual)],
+    &[
+        ("<=", BinaryOp::LessEqual),
+        (">=", BinaryOp::GreaterEqual),
+        ("<", BinaryOp::Less),
+        (">", BinaryOp::Greater),
+    ],
+    &[("+", BinaryOp::Add), ("-", BinaryOp::Subtract)],
+    &[
+        ("*", BinaryOp::Multiply),
+        ("/", BinaryOp::Divide),
+        ("%", BinaryOp::Modulo),
+    ],
+];
+
+impl Parser<'_> {
+    fn error(&self) -> MagickError {
+        wm_err!("unable to parse expression `{}'", self.source)
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).map(|&(_, c)| c)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.position += 1;
+        }
+    }
+
+    /// Consumes the token if it comes next, skipping whitespace before it
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_whitespace();
+        let matches = token
+            .chars()
+            .enumerate()
+            .all(|(i, c)| self.chars.get(self.position + i).map(|&(_, x)| x) == Some(c));
+        if matches {
+            self.position += token.chars().count();
+        }
+        matches
+    }
+
+    /// Goes one level deeper into the expression, failing past [MAX_DEPTH]
+    fn descend(&mut self) -> Result<(), MagickError> {
+        self.depth += 1;
+        match self.depth > MAX_DEPTH {
+            true => Err(wm_err!("expression is nested too deeply `{}'", self.source)),
+            false => Ok(()),
+        }
+    }
+
+    /// Parses a nested part of the expression one level deeper
+    fn nested(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<Expr, MagickError>,
+    ) -> Result<Expr, MagickError> {
+        self.descend()?;
+        let expr = parse(self)?;
+        self.depth -= 1;
+        Ok(expr)
+    }
+
+    fn expect(&mut self, token: &str) -> Result<(), MagickError> {
+        match self.eat(token) {
+            true => Ok(()),
+            false => Err(self.error()),
+        }
+    }
+
+    /// `condition ? then : else`, which is right-associative and binds the loosest
+    fn ternary(&mut self) -> Result<Expr, MagickError> {
+        let condition = self.binary(0)?;
+        if !self.eat("?") {
+            return Ok(condition);
+        }
+        let then = self.nested(Self::ternary)?;
+        self.expect(":")?;
+        let otherwise = self.nested(Self::ternary)?;
+        Ok(Expr::Conditional(
+            Box::new(condition),
+            Box::new(then),
+            Box::new(otherwise),
+        ))
+    }
+
+    /// Binary operators of the given level in [LEVELS] and tighter ones, by precedence climbing,
+    /// which keeps the recursion shallow compared to a function per level
+    fn binary(&mut self, level: usize) -> Result<Expr, MagickError> {
+        let mut left = self.power()?;
+        // Every operator in a chain such as `1+2+3` nests the ones before it one level deeper
+        let depth = self.depth;
+        while let Some((operator_level, op)) = self.binary_operator(level) {
+            self.descend()?;
+            let right = self.binary(operator_level + 1)?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+        self.depth = depth;
+        Ok(left)
+    }
+
+    /// Consumes a binary operator of the given level or a tighter one, returning its level
+    fn binary_operator(&mut self, level: usize) -> Option<(usize, BinaryOp)> {
+        for (operator_level, operators) in LEVELS.iter().enumerate().skip(level) {
+            for &(token, op) in operators.iter() {
+                if self.eat(token) {
+                    return Some((operator_level, op));
+                }
+            }
+        }
+        None
+    }
+
+    /// `^` binds tighter than the other binary operators and is right-associative
+    fn power(&mut self) -> Result<Expr, MagickError> {
+        let base = self.unary()?;
+        if self.eat("^") {
+            let exponent = self.nested(Self::power)?;
+            return Ok(Expr::Binary(
+                BinaryOp::Power,
+                Box::new(base),
+                Box::new(exponent),
+            ));
+        }
+        Ok(base)
+    }
+
+    fn unary(&mut self) -> Result<Expr, MagickError> {
+        if self.eat("-") {
+            let operand = self.nested(Self::unary)?;
+            return Ok(Expr::Unary(UnaryOp::Negate, Box::new(operand)));
+        }
+        if self.eat("+") {
+            return self.nested(Self::unary);
+        }
+        // `!=` is a binary operator, but it cannot appear where an operand is expected
+        if self.eat("!") {
+            let operand = self.nested(Self::unary)?;
+            return Ok(Expr::Unary(UnaryOp::Not, Box::new(operand)));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Expr, MagickError> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some('(') => {
+                self.position += 1;
+                let expr = self.nested(Self::ternary)?;
+                self.expect(")")?;
+                Ok(expr)
+            }
+            Some(c) if c.is_ascii_digit() || c == '.' => self.number(),
+            Some(c) if c.is_ascii_alphabetic() || c == '_' => self.name(),
+            _ => Err(self.error()),
+        }
+    }
+
+    /// A number such as `2`, `0.5`, `1e-3` or `50%`
+    fn number(&mut self) -> Result<Expr, MagickError> {
+        let start = self.position;
+        while self.peek().is_some_and(|c| c.is_ascii_digit() || c == '.') {
+            self.position += 1;
+        }
+        // An exponent, but only if digits follow, so that `2e` is not swallowed
+        if matches!(self.peek(), Some('e' | 'E')) {
+            let mut end = self.position + 1;
+            if matches!(self.chars.get(end), Some((_, '+' | '-'))) {
+                end += 1;
+            }
+            if self.chars.get(end).is_some_and(|(_, c)| c.is_ascii_digit()) {
+                self.position = end;
+                while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+                    self.position += 1;
+                }
+            }
+        }
+        let text = self.slice(start);
+        let mut value: f64 = text.parse().map_err(|_| self.error())?;
+        // A percent sign right after a number makes it a fraction, unlike the modulo operator
+        if self.peek() == Some('%') && !self.operand_follows_percent() {
+            self.position += 1;
+            value /= 100.0;
+        }
+        Ok(Expr::Number(value))
+    }
+
+    /// Checks if the `%` after a number is the modulo operator, i.e. another operand follows it
+    fn operand_follows_percent(&self) -> bool {
+        self.chars[self.position + 1..]
+            .iter()
+            .map(|&(_, c)| c)
+            .find(|c| !c.is_whitespace())
+            .is_some_and(|c| c.is_ascii_alphanumeric() || matches!(c, '(' | '.' | '_'))
+    }
+
+    /// A symbol such as `w` or `mean.r`, or a function call such as `max(w, h)`
+    fn name(&mut self) -> Result<Expr, MagickError> {
+        let start = self.position;
+        while self
+            .peek()
+            .is_some_and(|c| c.is_ascii_alphanumeric() || c == '_')
+        {
+            self.position += 1;
+        }
+        let name = self.slice(start).to_owned();
+        if self.eat("(") {
+            let mut arguments = vec![self.nested(Self::ternary)?];
+            while self.eat(",") {
+                arguments.push(self.nested(Self::ternary)?);
+            }
+            self.expect(")")?;
+            return Ok(Expr::Call(name, arguments));
+        }
+        // A channel selector such as `.r` directly after the name
+        let channel = match (self.peek(), self.chars.get(self.position + 1)) {
+            (Some('.'), Some(&(_, c))) if c.is_ascii_alphabetic() => {
+                self.position += 2;
+                Some(c)
+            }
+            _ => None,
+        };
+        Ok(Expr::Symbol { name, channel })
+    }
+
+    fn slice(&self, start: usize) -> &str {
+        let byte = |position: usize| {
+            self.chars
+                .get(position)
+                .map_or(self.source.len(), |&(byte, _)| byte)
+        };
+        &self.source[byte(start)..byte(self.position)]
+    }
+}
@@ -1,9 +1,15 @@
 use std::ffi::OsStr;
+use std::io::{ErrorKind, Write};
 
 use current_platform::CURRENT_PLATFORM;
 use strum::VariantArray;
 
-use crate::args::Arg;
+use crate::{
+    arg_parsers::ListType,
+    args::Arg,
+    error::{MagickError, BROKEN_PIPE_EXIT_CODE},
+    text, wm_err, wm_try,
+};
 
 pub fn maybe_print_help_and_exit(bin_name: &str) {
     match std::env::args_os().nth(1) {
@@ -16,24 +22,59 @@ pub fn maybe_print_help_and_exit(bin_name: &str) {
     }
 }
 
-fn print_help_and_exit(bin_name: &str) -> ! {
-    print_help(bin_name);
+/// Implements `-list`: prints the list and exits without processing any images, like imagemagick
+pub fn maybe_print_list_and_exit() -> Result<(), MagickError> {
+    let mut args = std::env::args_os().skip(1);
+    if !args.any(|arg| arg == "-list") {
+        return Ok(());
+    }
+    let value = args
+        .next()
+        .ok_or(wm_err!("argument requires a value: list"))?;
+    let output = match ListType::try_from(value.as_os_str())? {
+        ListType::Font => text::font_list(),
+    };
+    wm_try!(std::io::stdout().lock().write_all(output.as_bytes()));
     std::process::exit(0);
 }
 
-fn print_help(bin_name: &str) {
-    println!("Version: {}", version_string());
-    println!("Copyright: (C) 2024 WonderMagick contributors");
-    println!("License: {}", env!("CARGO_PKG_LICENSE"));
+fn print_help_and_exit(bin_name: &str) -> ! {
+    match print_help(bin_name) {
+        Ok(()) => std::process::exit(0),
+        Err(e) if e.kind() == ErrorKind::BrokenPipe => {
+            std::process::exit(BROKEN_PIPE_EXIT_CODE.into())
+        }
+        Err(e) => {
+            eprintln!("{}", wm_err!("{e}"));
+            std::process::exit(1);
+        }
+    }
+}
+
+fn print_help(bin_name: &str) -> std::io::Result<()> {
+    let mut out = std::io::stdout().lock();
+    writeln!(out, "Version: {}", version_string())?;
+    writeln!(out, "Copyright: (C) 2024 WonderMagick contributors")?;
+    writeln!(out, "License: {}", env!("CARGO_PKG_LICENSE"))?;
     // TODO: "Features:"
     // TODO: "Delegates (built-in):"
-    println!("Usage: {bin_name} [options ...] file [options ...] file");
-    println!("");
-    println!("Image Operators:");
+    writeln!(
+        out,
+        "Usage: {bin_name} [options ...] file [options ...] file"
+    )?;
+    writeln!(out)?;
+    writeln!(out, "Image Operators:")?;
     for arg in Arg::VARIANTS {
         let name: &'static str = arg.into();
-        println!("  -{name:19} {}", arg.help_text());
+        writeln!(out, "  -{name:19} {}", arg.help_text())?;
     }
+    writeln!(out)?;
+    writeln!(out, "Miscellaneous Options:")?;
+    writeln!(
+        out,
+        "  -{:19} print a list of supported option arguments",
+        "list type"
+    )
 }
 
 fn version_string() -> String {
@@ -0,0 +1,106 @@
+//! Color management with the ICC profiles embedded in images
+
+use image::{ColorType, DynamicImage, ImageBuffer};
+use moxcms::{ColorProfile, DataColorSpace, Layout, TransformOptions};
+
+use crate::{encoders::common::convert, image::Image};
+
+/// Converts the pixels from the colors described by the embedded ICC profile to sRGB,
+/// which is what viewers assume for images without one, and drops the profile.
+///
+/// Profiles that cannot be applied to the pixels, such as CMYK or broken ones, are dropped
+/// without touching the pixels, same as when the output format cannot hold the profile.
+pub fn to_srgb(image: &Image) -> Image {
+    let mut converted = Image {
+        icc_profile: None,
+        ..image.clone()
+    };
+    let Some(profile) = image
+        .icc_profile
+        .as_deref()
+        .and_then(|icc| ColorProfile::new_from_slice(icc).ok())
+    else {
+        return converted;
+    };
+    let color = image.pixels.color();
+    let (pixels, layout, color) = match profile.color_space {
+        DataColorSpace::Rgb => {
+            // Gray pixels in an RGB profile are not necessarily gray in sRGB
+            let color = match color {
+                ColorType::L8 => ColorType::Rgb8,
+                ColorType::La8 => ColorType::Rgba8,
+                ColorType::L16 => ColorType::Rgb16,
+                ColorType::La16 => ColorType::Rgba16,
+                color => color,
+            };
+            (image.pixels.to_rgba16().into_raw(), Layout::Rgba, color)
+        }
+        DataColorSpace::Gray => (
+            image.pixels.to_luma_alpha16().into_raw(),
+            Layout::GrayAlpha,
+            color,
+        ),
+        _ => return converted,
+    };
+    let srgb = ColorProfile::new_srgb();
+    let Ok(transform) =
+        profile.create_transform_16bit(layout, &srgb, Layout::Rgba, TransformOptions::default())
+    else {
+        return converted;
+    };
+    let mut result = vec![0u16; pixels.len() / layout.channels() * 4];
+    if transform.transform(&pixels, &mut result).is_err() {
+        return converted;
+    }
+    let (width, height) = (image.pixels.width(), image.pixels.height());
+    if let Some(result) = ImageBuffer::from_raw(width, height, result) {
+        converted.pixels = convert(&DynamicImage::ImageRgba16(result), color);
+    }
+    converted
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgba, RgbaImage};
+    use moxcms::ColorProfile;
+
+    use super::*;
+
+    fn image_with_profile(profile: &ColorProfile, color: Rgba<u8>) -> Image {
+        let mut image = Image::new(DynamicImage::ImageRgba8(RgbaImage::from_pixel(2, 2, color)));
+        image.icc_profile = Some(profile.encode().unwrap());
+        image
+    }
+
+    #[test]
+    fn srgb_stays_the_same() {
+        let image = image_with_profile(&ColorProfile::new_srgb(), Rgba([200, 100, 50, 128]));
+        let converted = to_srgb(&image);
+        assert_eq!(converted.icc_profile, None);
+        let pixel = converted.pixels.as_rgba8().unwrap().get_pixel(0, 0).0;
+        for (converted, original) in pixel.iter().zip([200, 100, 50, 128]) {
+            assert!(converted.abs_diff(original) <= 1, "{pixel:?}");
+        }
+    }
+
+    #[test]
+    fn wide_gamut_is_desaturated() {
+        // Display P3 red is outside of sRGB, so it is clipped to the most saturated red there is
+        let image = image_with_profile(&ColorProfile::new_display_p3(), Rgba([255, 0, 0, 255]));
+        let pixel = to_srgb(&image).pixels.as_rgba8().unwrap().get_pixel(0, 0).0;
+        assert_eq!(pixel[0], 255);
+        // a less saturated P3 color is more saturated in sRGB
+        let image = image_with_profile(&ColorProfile::new_display_p3(), Rgba([200, 100, 100, 255]));
+        let pixel = to_srgb(&image).pixels.as_rgba8().unwrap().get_pixel(0, 0).0;
+        assert!(pixel[0] > 200 && pixel[1] < 100, "{pixel:?}");
+    }
+
+    #[test]
+    fn broken_profile_is_dropped() {
+        let mut image = Image::new(DynamicImage::new_rgb8(1, 1));
+        image.icc_profile = Some(b"not really an ICC profile".to_vec());
+        let converted = to_srgb(&image);
+        assert_eq!(converted.icc_profile, None);
+        assert_eq!(converted.pixels, image.pixels);
+    }
+}
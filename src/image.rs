@@ -0,0 +1,162 @@
+//! The image that operations work on: pixel data plus the metadata that we keep track of.
+
+use std::{collections::BTreeMap, ffi::OsString};
+
+use image::{metadata::Orientation, Delay, DynamicImage, ExtendedColorType};
+
+use crate::{arg_parsers::Colorspace, file_format::FileFormat, utils::timer::Stopwatch};
+
+/// imagemagick reports quantum-scaled values such as `%[mean]` in the range of its build's quantum depth.
+/// We report ourselves as Q16 in `-version`, so we have to match that.
+pub const QUANTUM_RANGE: f64 = 65535.0;
+
+/// The gamma imagemagick records for sRGB images
+pub const SRGB_GAMMA: f64 = 1.0 / 2.2;
+
+#[derive(Debug, Clone)]
+pub struct Image {
+    pub pixels: DynamicImage,
+    /// The file the image was read from
+    pub filename: OsString,
+    /// The format the image was decoded from, if it was read from a file
+    pub format: Option<FileFormat>,
+    /// The color type the image was stored with, which may differ from the in-memory one
+    pub original_color_type: ExtendedColorType,
+    /// EXIF orientation that has not been applied to the pixels yet.
+    /// Cleared by `-auto-orient`.
+    pub orientation: Orientation,
+    /// Virtual canvas the image is placed on, if any
+    pub page: Option<PageGeometry>,
+    /// Embedded ICC color profile, if any
+    pub icc_profile: Option<Vec<u8>>,
+    /// Raw EXIF chunk, if any. The orientation tag in it may be out of date, see `orientation`.
+    pub exif: Option<Vec<u8>>,
+    /// How long the frame is shown for, if it is a frame of an animation
+    pub delay: Option<Delay>,
+    /// Index of the frame in the file it was read from, if the file holds more than one
+    pub scene: Option<usize>,
+    /// The colorspace the pixel values are in, set by `-colorspace`. Grayscale images are sRGB.
+    pub colorspace: Colorspace,
+    /// The gamma the pixel values are encoded with. Changed by `-gamma`, and set by `+gamma`.
+    pub gamma: f64,
+    /// Text set by `-comment`, written to the output by formats that support it
+    pub comment: Option<String>,
+    /// Text set by `-label`, written to the output by formats that support it
+    pub label: Option<String>,
+    /// Other properties set by `-set`, such as `filename:area`, keyed by their lowercase names
+    pub properties: BTreeMap<String, String>,
+    /// Size of the file the image was read from, in bytes
+    pub file_size: Option<u64>,
+    /// Started when reading the image began, for the time reported by `-identify`
+    pub timer: Stopwatch,
+}
+
+impl Image {
+    pub fn new(pixels: DynamicImage) -> Self {
+        Self {
+            original_color_type: pixels.color().into(),
+            pixels,
+            filename: OsString::new(),
+            format: None,
+            orientation: Orientation::NoTransforms,
+            page: None,
+            icc_profile: None,
+            exif: None,
+            delay: None,
+            scene: None,
+            colorspace: Colorspace::Srgb,
+            gamma: SRGB_GAMMA,
+            comment: None,
+            label: None,
+            properties: BTreeMap::new(),
+            file_size: None,
+            timer: Stopwatch::start(),
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.pixels.width()
+    }
+
+    pub fn height(&self) -> u32 {
+        self.pixels.height()
+    }
+}
+
+/// Placement of the image on a virtual canvas, also known as page geometry.
+/// Printed by imagemagick as `WxH+X+Y`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PageGeometry {
+    /// Width of the canvas
+    pub width: u32,
+    /// Height of the canvas
+    pub height: u32,
+    /// Offset of the image from the left edge of the canvas
+    pub x: i64,
+    /// Offset of the image from the top edge of the canvas
+    pub y: i64,
+}
+
+impl PageGeometry {
+    /// Mirrors the placement of an image of the given width left-to-right
+    pub fn flip_horizontal(&mut self, image_width: u32) {
+        self.x = i64::from(self.width) - self.x - i64::from(image_width);
+    }
+
+    /// Mirrors the placement of an image of the given height top-to-bottom
+    pub fn flip_vertical(&mut self, image_height: u32) {
+        self.y = i64::from(self.height) - self.y - i64::from(image_height);
+    }
+
+    /// Rotates the canvas by 90 degrees clockwise along with an image of the given height
+    pub fn rotate90(&mut self, image_height: u32) {
+        let new_x = i64::from(self.height) - self.y - i64::from(image_height);
+        *self = Self {
+            width: self.height,
+            height: self.width,
+            x: new_x,
+            y: self.x,
+        };
+    }
+
+    /// Rotates the canvas by 270 degrees clockwise along with an image of the given width
+    pub fn rotate270(&mut self, image_width: u32) {
+        let new_y = i64::from(self.width) - self.x - i64::from(image_width);
+        *self = Self {
+            width: self.height,
+            height: self.width,
+            x: self.y,
+            y: new_y,
+        };
+    }
+
+    /// Applies the same transformation as [DynamicImage::apply_orientation] does to the pixels.
+    /// The dimensions are those of the image *before* the transformation.
+    pub fn apply_orientation(
+        &mut self,
+        orientation: Orientation,
+        image_width: u32,
+        image_height: u32,
+    ) {
+        match orientation {
+            Orientation::NoTransforms => (),
+            Orientation::Rotate90 => self.rotate90(image_height),
+            Orientation::Rotate180 => {
+                self.flip_horizontal(image_width);
+                self.flip_vertical(image_height);
+            }
+            Orientation::Rotate270 => self.rotate270(image_width),
+            Orientation::FlipHorizontal => self.flip_horizontal(image_width),
+            Orientation::FlipVertical => self.flip_vertical(image_height),
+            Orientation::Rotate90FlipH => {
+                self.rotate90(image_height);
+                // after rotation the width of the image is its former height
+                self.flip_horizontal(image_height);
+            }
+            Orientation::Rotate270FlipH => {
+                self.rotate270(image_width);
+                self.flip_horizontal(image_height);
+            }
+        }
+    }
+}
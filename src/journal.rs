@@ -0,0 +1,97 @@
+//! Checkpointing of long batch runs for `--wm-journal`, a wondermagick extension.
+//!
+//! Every input file that was processed successfully is appended to the journal as soon as it is done,
+//! along with the number of output files written for it. When the same command is run again,
+//! the files in the journal are skipped, so an interrupted run picks up where it left off.
+//! The number of outputs keeps the numbering of `out-N.png` files the same as in an uninterrupted run.
+
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+
+use crate::{error::MagickError, wm_err, wm_try};
+
+/// The journal file, one line per completed input file: the number of outputs, a tab, and the filename
+pub struct Journal {
+    file: File,
+    completed: HashMap<String, usize>,
+}
+
+impl Journal {
+    /// Opens the journal, creating it if it does not exist yet
+    pub fn open(path: &Path) -> Result<Self, MagickError> {
+        let mut file = File::options()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)
+            .map_err(|e| wm_err!("unable to open journal `{}': {}", path.display(), e))?;
+        let mut contents = String::new();
+        wm_try!(file.read_to_string(&mut contents));
+        // The last line is cut short if the previous run was killed while writing it
+        if !contents.is_empty() && !contents.ends_with('\n') {
+            wm_try!(file.write_all(b"\n"));
+        }
+        let completed = contents
+            .lines()
+            .filter_map(|line| {
+                let (outputs, filename) = line.split_once('\t')?;
+                Some((filename.to_owned(), outputs.parse().ok()?))
+            })
+            .collect();
+        Ok(Self { file, completed })
+    }
+
+    /// The number of outputs written for the input file, if it was completed by an earlier run
+    pub fn completed(&self, input: &OsStr) -> Option<usize> {
+        self.completed.get(input.to_str()?).copied()
+    }
+
+    /// Records the input file as completed.
+    ///
+    /// Filenames that are not valid UTF-8 or contain line breaks cannot be recorded,
+    /// so such files are processed again on every run.
+    pub fn record(&mut self, input: &OsStr, outputs: usize) -> Result<(), MagickError> {
+        let Some(filename) = input.to_str().filter(|name| !name.contains(['\n', '\r'])) else {
+            return Ok(());
+        };
+        // A single write, so that the line is not interleaved with anything else
+        wm_try!(self
+            .file
+            .write_all(format!("{outputs}\t{filename}\n").as_bytes()));
+        self.completed.insert(filename.to_owned(), outputs);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resumes_from_journal() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("journal");
+        let mut journal = Journal::open(&path).unwrap();
+        assert_eq!(journal.completed(OsStr::new("a.png")), None);
+        journal.record(OsStr::new("a.png"), 1).unwrap();
+        journal.record(OsStr::new("anim.gif"), 3).unwrap();
+        journal.record(OsStr::new("new\nline.png"), 1).unwrap();
+        drop(journal);
+
+        // simulate a run killed halfway through a line
+        let mut file = File::options().append(true).open(&path).unwrap();
+        file.write_all(b"1\tb.pn").unwrap();
+        drop(file);
+
+        let mut journal = Journal::open(&path).unwrap();
+        assert_eq!(journal.completed(OsStr::new("a.png")), Some(1));
+        assert_eq!(journal.completed(OsStr::new("anim.gif")), Some(3));
+        assert_eq!(journal.completed(OsStr::new("new\nline.png")), None);
+        journal.record(OsStr::new("c.png"), 1).unwrap();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(contents, "1\ta.png\n3\tanim.gif\n1\tb.pn\n1\tc.png\n");
+    }
+}
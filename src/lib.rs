@@ -6,9 +6,25 @@
 
 mod arg_parsers;
 pub mod args;
+mod colorspace;
+pub mod compare;
 pub mod decode;
-mod error;
+mod decoders;
+mod encode;
+mod encoders;
+pub mod error;
+mod file_format;
+mod fx;
 pub mod help;
+mod icc;
+pub mod image;
+mod journal;
+mod limits;
 mod operations;
-mod plan;
+pub mod plan;
+#[cfg(feature = "plugins")]
+pub mod plugin;
+mod pseudo;
+mod quantize;
+mod text;
 mod utils;
@@ -0,0 +1,407 @@
+//! Resource limits set via `-limit` or the `MAGICK_*_LIMIT` environment variables.
+//!
+//! Command-line flags take precedence over the environment.
+
+use std::ffi::OsStr;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use crate::arg_parsers::{parse_resource_value, ResourceType};
+use crate::error::MagickError;
+use crate::utils::pool;
+use crate::wm_err;
+
+/// The memory limit for images created by operations if `-limit memory` is not set, see [Limits::check_new_image]
+const DEFAULT_OPERATION_MEMORY: u64 = 16 * 1024 * 1024 * 1024;
+
+/// When the run started, which `-limit time` counts from.
+/// Set when the limits are first read from the environment, which is right at startup.
+static START: OnceLock<Instant> = OnceLock::new();
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Limits {
+    /// Maximum width*height of an image, in pixels
+    pub area: Option<u64>,
+    /// Maximum disk space used for temporary files, in bytes
+    pub disk: Option<u64>,
+    /// Maximum image height, in pixels
+    pub height: Option<u64>,
+    /// Maximum memory used by the decoder, in bytes.
+    /// If unset, the default limit of the `image` crate applies.
+    pub memory: Option<u64>,
+    /// Maximum number of threads. We are currently single-threaded,
+    /// so this is only accepted for compatibility.
+    pub thread: Option<u64>,
+    /// Maximum wall-clock run time, in seconds
+    pub time: Option<u64>,
+    /// Maximum image width, in pixels
+    pub width: Option<u64>,
+}
+
+impl Limits {
+    /// Reads the limits from `MAGICK_*_LIMIT` environment variables.
+    ///
+    /// Invalid values are ignored, same as in imagemagick.
+    pub fn from_env() -> Self {
+        START.get_or_init(Instant::now);
+        let mut limits = Self::default();
+        for resource in [
+            ResourceType::Area,
+            ResourceType::Disk,
+            ResourceType::Height,
+            ResourceType::Memory,
+            ResourceType::Thread,
+            ResourceType::Time,
+            ResourceType::Width,
+        ] {
+            if let Some(value) = std::env::var_os(resource.env_var()) {
+                let _ = limits.set(resource, &value);
+            }
+        }
+        limits
+    }
+
+    /// Fills in the limits that are not set yet with ones suitable for images from untrusted sources,
+    /// such as user uploads to a thumbnailing service, for `--wm-untrusted`.
+    ///
+    /// Like all limits they apply to decoding, and through [Limits::check_new_image]
+    /// to every image that is created rather than read, such as `null:` or the result of `-extent`.
+    pub fn set_untrusted_defaults(&mut self) {
+        const MIB: u64 = 1024 * 1024;
+        self.area.get_or_insert(64_000_000);
+        self.height.get_or_insert(16_000);
+        self.memory.get_or_insert(512 * MIB);
+        self.time.get_or_insert(60);
+        self.width.get_or_insert(16_000);
+    }
+
+    pub fn set(&mut self, resource: ResourceType, value: &OsStr) -> Result<(), MagickError> {
+        let value = parse_resource_value(value)?;
+        let field = match resource {
+            ResourceType::Area => &mut self.area,
+            ResourceType::Disk => &mut self.disk,
+            ResourceType::Height => &mut self.height,
+            ResourceType::Memory => &mut self.memory,
+            ResourceType::Thread => &mut self.thread,
+            ResourceType::Time => &mut self.time,
+            ResourceType::Width => &mut self.width,
+        };
+        *field = value;
+        Ok(())
+    }
+
+    /// Converts the limits into the form understood by the decoders in the `image` crate
+    pub fn to_image_limits(&self) -> image::Limits {
+        let mut limits = image::Limits::default();
+        limits.max_image_width = self.width.map(|w| w.try_into().unwrap_or(u32::MAX));
+        limits.max_image_height = self.height.map(|h| h.try_into().unwrap_or(u32::MAX));
+        if self.memory.is_some() {
+            limits.max_alloc = self.memory;
+        }
+        limits
+    }
+
+    /// Fails once the run has taken longer than `-limit time` allows.
+    ///
+    /// It is checked between operations and frames, and by the readers and writers from [Limits::timed]
+    /// while files are being decoded and encoded.
+    pub fn check_time(&self) -> Result<(), MagickError> {
+        match time_exceeded(self.time) {
+            true => Err(wm_err!(
+                "time limit exceeded `{}'",
+                self.time.unwrap_or_default()
+            )),
+            false => Ok(()),
+        }
+    }
+
+    /// Wraps a reader or writer so that it fails once the time limit runs out
+    pub fn timed<T>(&self, inner: T) -> Timed<T> {
+        Timed {
+            inner,
+            time: self.time,
+        }
+    }
+
+    /// Wraps a temporary file so that writing to it fails once it would grow past `-limit disk`
+    pub fn disk_bounded<T>(&self, inner: T) -> DiskBounded<T> {
+        DiskBounded {
+            inner,
+            position: 0,
+            max_size: self.disk,
+        }
+    }
+
+    /// Checks that a buffer of the given size fits into the memory limit, or the default limit
+    /// of the `image` crate if none is set, before it is allocated
+    pub fn check_memory(&self, bytes: u64) -> Result<(), MagickError> {
+        if let Some(max_alloc) = self.to_image_limits().max_alloc {
+            if bytes > max_alloc {
+                return Err(crate::wm_err!(
+                    "memory allocation exceeds limit ({} > {})",
+                    bytes,
+                    max_alloc
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks the size of an image that is about to be created, such as the result of `-resize`,
+    /// against all the limits, and converts it to whole pixels.
+    ///
+    /// The default memory limit of the `image` crate is meant for decoding, while operations work
+    /// in floating point, so without `-limit memory` a far more generous default applies instead.
+    /// It only keeps nonsensical sizes from aborting the process when they cannot be allocated.
+    pub fn check_new_image(
+        &self,
+        width: f64,
+        height: f64,
+        bytes_per_pixel: u64,
+    ) -> Result<(u32, u32), MagickError> {
+        let fits = |value: f64, limit: Option<u64>| {
+            value.is_finite()
+                && value <= f64::from(u32::MAX)
+                && limit.is_none_or(|limit| value <= limit as f64)
+        };
+        if !fits(width, self.width) || !fits(height, self.height) {
+            return Err(crate::wm_err!(
+                "width or height exceeds limit ({}x{})",
+                width,
+                height
+            ));
+        }
+        let (width, height) = (width.max(0.0) as u32, height.max(0.0) as u32);
+        self.check_area(width, height)?;
+        let bytes = (u64::from(width) * u64::from(height)).saturating_mul(bytes_per_pixel);
+        let max_bytes = self.memory.unwrap_or(DEFAULT_OPERATION_MEMORY);
+        // Buffers kept for reuse count against the limit too, and are let go to make room
+        if bytes.saturating_add(pool::pooled_bytes()) > max_bytes {
+            pool::clear();
+        }
+        if bytes > max_bytes {
+            return Err(crate::wm_err!(
+                "memory allocation exceeds limit ({} > {})",
+                bytes,
+                max_bytes
+            ));
+        }
+        Ok((width, height))
+    }
+
+    /// Checks the area limit, which the `image` crate has no notion of
+    pub fn check_area(&self, width: u32, height: u32) -> Result<(), MagickError> {
+        if let Some(max_area) = self.area {
+            if u64::from(width) * u64::from(height) > max_area {
+                return Err(crate::wm_err!(
+                    "image area exceeds limit ({}x{} > {})",
+                    width,
+                    height,
+                    max_area
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn time_exceeded(time: Option<u64>) -> bool {
+    time.is_some_and(|time| START.get_or_init(Instant::now).elapsed() >= Duration::from_secs(time))
+}
+
+/// A reader or writer that fails once `-limit time` runs out, so that decoding or encoding
+/// a large file is cut short rather than only being noticed once it is done
+pub struct Timed<T> {
+    inner: T,
+    time: Option<u64>,
+}
+
+impl<T> Timed<T> {
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    fn check(&self) -> std::io::Result<()> {
+        match time_exceeded(self.time) {
+            true => Err(std::io::Error::other("time limit exceeded")),
+            false => Ok(()),
+        }
+    }
+}
+
+impl<T: Read> Read for Timed<T> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.check()?;
+        self.inner.read(buf)
+    }
+}
+
+impl<T: Write> Write for Timed<T> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.check()?;
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<T: Seek> Seek for Timed<T> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+/// A writer that fails once the file would grow past `-limit disk`,
+/// so that a huge image on stdin or stdout cannot fill up the temporary directory
+pub struct DiskBounded<T> {
+    inner: T,
+    /// Where the next write goes, since encoders seek back to fill in headers
+    position: u64,
+    max_size: Option<u64>,
+}
+
+impl<T> DiskBounded<T> {
+    /// Output files are not temporary, so they do not count against `-limit disk`
+    pub fn unbounded(inner: T) -> Self {
+        Self {
+            inner,
+            position: 0,
+            max_size: None,
+        }
+    }
+}
+
+impl<T: Write> Write for DiskBounded<T> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let end = self.position.saturating_add(buf.len() as u64);
+        if self.max_size.is_some_and(|max_size| end > max_size) {
+            return Err(std::io::Error::other("disk limit exceeded"));
+        }
+        let written = self.inner.write(buf)?;
+        self.position += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<T: Seek> Seek for DiskBounded<T> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.position = self.inner.seek(pos)?;
+        Ok(self.position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn image_limits() {
+        let mut limits = Limits::default();
+        limits.set(ResourceType::Width, OsStr::new("16KP")).unwrap();
+        limits
+            .set(ResourceType::Memory, OsStr::new("1GiB"))
+            .unwrap();
+        let image_limits = limits.to_image_limits();
+        assert_eq!(image_limits.max_image_width, Some(16_000));
+        assert_eq!(image_limits.max_image_height, None);
+        assert_eq!(image_limits.max_alloc, Some(1024 * 1024 * 1024));
+    }
+
+    #[test]
+    fn time_limit() {
+        let mut limits = Limits::default();
+        assert!(limits.check_time().is_ok());
+        limits.set(ResourceType::Time, OsStr::new("0")).unwrap();
+        assert!(limits.check_time().is_err());
+        let mut reader = limits.timed(std::io::Cursor::new([1, 2, 3]));
+        assert!(reader.read(&mut [0; 3]).is_err());
+        limits.set(ResourceType::Time, OsStr::new("3600")).unwrap();
+        let mut reader = limits.timed(std::io::Cursor::new([1, 2, 3]));
+        assert_eq!(reader.read(&mut [0; 3]).unwrap(), 3);
+    }
+
+    #[test]
+    fn disk_limit() {
+        let mut limits = Limits::default();
+        limits.set(ResourceType::Disk, OsStr::new("4")).unwrap();
+        let mut writer = limits.disk_bounded(std::io::Cursor::new(Vec::new()));
+        writer.write_all(&[1, 2, 3]).unwrap();
+        // overwriting what is already there does not grow the file
+        writer.seek(SeekFrom::Start(0)).unwrap();
+        writer.write_all(&[4, 5, 6, 7]).unwrap();
+        assert!(writer.write_all(&[8]).is_err());
+        let mut writer = DiskBounded::unbounded(std::io::Cursor::new(Vec::new()));
+        writer.write_all(&[0; 16]).unwrap();
+    }
+
+    #[test]
+    fn untrusted_defaults_keep_explicit_limits() {
+        let mut limits = Limits::default();
+        limits.set(ResourceType::Time, OsStr::new("5")).unwrap();
+        limits.set_untrusted_defaults();
+        assert_eq!(limits.time, Some(5));
+        assert_eq!(limits.area, Some(64_000_000));
+        assert!(limits.memory.is_some());
+    }
+
+    #[test]
+    fn memory_limit() {
+        let mut limits = Limits::default();
+        assert!(limits.check_memory(1024).is_ok());
+        assert!(limits.check_memory(u64::MAX).is_err());
+        limits
+            .set(ResourceType::Memory, OsStr::new("1KiB"))
+            .unwrap();
+        assert!(limits.check_memory(1024).is_ok());
+        assert!(limits.check_memory(1025).is_err());
+    }
+
+    #[test]
+    fn new_image_limits() {
+        let mut limits = Limits::default();
+        assert_eq!(
+            limits.check_new_image(640.0, 480.0, 16).unwrap(),
+            (640, 480)
+        );
+        assert!(limits.check_new_image(f64::INFINITY, 1.0, 16).is_err());
+        assert!(limits.check_new_image(1e10, 1.0, 16).is_err());
+        assert!(limits.check_new_image(1e9, 1e9, 16).is_err());
+        assert!(limits.check_new_image(4e9, 4e9, 16).is_err());
+        limits.set(ResourceType::Width, OsStr::new("100")).unwrap();
+        assert!(limits.check_new_image(101.0, 1.0, 16).is_err());
+        limits
+            .set(ResourceType::Memory, OsStr::new("1KiB"))
+            .unwrap();
+        assert!(limits.check_new_image(8.0, 8.0, 16).is_ok());
+        assert!(limits.check_new_image(9.0, 8.0, 16).is_err());
+    }
+
+    #[test]
+    fn pooled_buffers_count_against_the_memory_limit() {
+        let mut limits = Limits::default();
+        limits
+            .set(ResourceType::Memory, OsStr::new("1KiB"))
+            .unwrap();
+        pool::recycle(Vec::with_capacity(64));
+        assert!(limits.check_new_image(4.0, 4.0, 16).is_ok());
+        assert_eq!(pool::pooled_bytes(), 256);
+        assert!(limits.check_new_image(8.0, 8.0, 16).is_ok());
+        assert_eq!(pool::pooled_bytes(), 0);
+    }
+
+    #[test]
+    fn default_memory_limit() {
+        let limits = Limits::default();
+        assert_eq!(
+            limits.to_image_limits().max_alloc,
+            image::Limits::default().max_alloc
+        );
+    }
+}
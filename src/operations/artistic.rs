@@ -0,0 +1,184 @@
+//! Effects that imitate drawing techniques: `-edge`, `-emboss`, `-charcoal` and `-sketch`.
+//!
+//! Like imagemagick, they are built from convolution kernels, sized the same way as those of the blurs.
+
+use std::f64::consts::PI;
+
+use image::{DynamicImage, GenericImageView, Rgba, Rgba32FImage};
+
+use super::{
+    blur::{convolve_2d, gaussian_blur, kernel_width, motion_blur},
+    histogram::{equalize, normalize},
+    negate::negate,
+};
+use crate::{
+    arg_parsers::{BlurGeometry, ChannelMask, Colorspace, MotionBlurGeometry},
+    encoders::common::convert,
+    error::MagickError,
+    image::Image,
+    utils::random,
+};
+
+/// Implements `-edge` like imagemagick's EdgeImage(): flat areas become black
+/// and only the changes in color remain.
+pub fn edge(image: &mut Image, radius: f64) -> Result<(), MagickError> {
+    let edges = edges(&image.pixels.to_rgba32f(), radius);
+    image.pixels = convert(&DynamicImage::ImageRgba32F(edges), image.pixels.color());
+    Ok(())
+}
+
+fn edges(pixels: &Rgba32FImage, radius: f64) -> Rgba32FImage {
+    // Every weight is -1 except the one in the middle, which balances them out
+    let width = kernel_width(radius, 0.5);
+    let mut kernel = vec![-1.0; width * width];
+    kernel[width * width / 2] = (width * width - 1) as f32;
+    let mut edges = convolve_2d(pixels, &kernel);
+    for pixel in edges.pixels_mut() {
+        for c in 0..3 {
+            pixel[c] = pixel[c].clamp(0.0, 1.0);
+        }
+    }
+    edges
+}
+
+/// Implements `-emboss` like imagemagick's EmbossImage(): the image is convolved with a gaussian
+/// along the diagonal that is negative on one side of the center and positive on the other,
+/// so that edges look raised and lit from the top left, and then equalized.
+pub fn emboss(image: &mut Image, geometry: &BlurGeometry) -> Result<(), MagickError> {
+    let sigma = geometry.sigma.max(f64::EPSILON);
+    let width = kernel_width(geometry.radius, sigma);
+    let half = (width / 2) as i64;
+    let mut kernel = Vec::with_capacity(width * width);
+    for v in -half..=half {
+        for u in -half..=half {
+            let weight = if u == -v {
+                let sign = if u < 0 || v < 0 { -8.0 } else { 8.0 };
+                sign * (-((u * u + v * v) as f64) / (2.0 * sigma * sigma)).exp()
+                    / (2.0 * PI * sigma * sigma)
+            } else {
+                0.0
+            };
+            kernel.push(weight as f32);
+        }
+    }
+    let mut embossed = convolve_2d(&image.pixels.to_rgba32f(), &kernel);
+    for pixel in embossed.pixels_mut() {
+        for c in 0..3 {
+            pixel[c] = pixel[c].clamp(0.0, 1.0);
+        }
+    }
+    equalize(&mut embossed);
+    image.pixels = convert(&DynamicImage::ImageRgba32F(embossed), image.pixels.color());
+    Ok(())
+}
+
+/// Implements `-charcoal` like imagemagick's CharcoalImage(): the edges of the image are blurred,
+/// normalized and negated into dark strokes on white paper.
+pub fn charcoal(image: &mut Image, geometry: &BlurGeometry) -> Result<(), MagickError> {
+    let mut edges = edges(&image.pixels.to_rgba32f(), geometry.radius);
+    // Paper is not transparent
+    for pixel in edges.pixels_mut() {
+        pixel[3] = 1.0;
+    }
+    let blurred = gaussian_blur(&edges, geometry);
+    image.pixels = convert(&DynamicImage::ImageRgba32F(blurred), image.pixels.color());
+    normalize(image)?;
+    negate(image, false, ChannelMask::default())?;
+    crate::colorspace::transform(image, Colorspace::Gray);
+    Ok(())
+}
+
+/// Implements `-sketch` like imagemagick's SketchImage(): random noise is smeared by a motion blur
+/// into pencil strokes, which then lighten the image wherever they are faint.
+pub fn sketch(image: &mut Image, geometry: &MotionBlurGeometry) -> Result<(), MagickError> {
+    let (width, height) = image.pixels.dimensions();
+    // The strokes are drawn at twice the size, so that they are finer once scaled down
+    let noise = Rgba32FImage::from_fn(2 * width, 2 * height, |_, _| {
+        let v = random::next_f64() as f32;
+        Rgba([v, v, v, 1.0])
+    });
+    let mut strokes = Image::new(DynamicImage::ImageRgba32F(noise));
+    motion_blur(&mut strokes, geometry)?;
+    strokes.pixels =
+        DynamicImage::ImageRgba32F(edges(&strokes.pixels.to_rgba32f(), geometry.blur.radius));
+    normalize(&mut strokes)?;
+    negate(&mut strokes, false, ChannelMask::default())?;
+    let strokes = strokes.pixels.to_rgba32f();
+
+    let mut pixels = image.pixels.to_rgba32f();
+    for (x, y, pixel) in pixels.enumerate_pixels_mut() {
+        let dodge = [(0, 0), (1, 0), (0, 1), (1, 1)]
+            .iter()
+            .map(|(dx, dy)| strokes.get_pixel(2 * x + dx, 2 * y + dy)[0])
+            .sum::<f32>()
+            / 4.0;
+        for c in 0..3 {
+            // The color dodge blend, mixed 20% into the original
+            let dodged = match dodge >= 1.0 {
+                true => 1.0,
+                false => (pixel[c] / (1.0 - dodge)).min(1.0),
+            };
+            pixel[c] = 0.2 * pixel[c] + 0.8 * dodged;
+        }
+    }
+    image.pixels = convert(&DynamicImage::ImageRgba32F(pixels), image.pixels.color());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use image::{GrayImage, Luma, RgbImage};
+
+    use super::*;
+
+    fn step() -> Image {
+        let pixels = GrayImage::from_fn(6, 6, |x, _| Luma([if x < 3 { 0 } else { 255 }]));
+        Image::new(DynamicImage::ImageLuma8(pixels))
+    }
+
+    #[test]
+    fn edge_keeps_only_changes() {
+        let mut image = step();
+        edge(&mut image, 0.0).unwrap();
+        let pixels = image.pixels.to_luma8();
+        assert_eq!(pixels.get_pixel(0, 3)[0], 0);
+        assert_eq!(pixels.get_pixel(5, 3)[0], 0);
+        assert_eq!(pixels.get_pixel(3, 3)[0], 255);
+    }
+
+    #[test]
+    fn emboss_spreads_values() {
+        let mut image = step();
+        emboss(&mut image, &BlurGeometry::from_str("0x1").unwrap()).unwrap();
+        let pixels = image.pixels.to_luma8();
+        let min = pixels.pixels().map(|p| p[0]).min().unwrap();
+        let max = pixels.pixels().map(|p| p[0]).max().unwrap();
+        assert!(max > min);
+    }
+
+    #[test]
+    fn charcoal_draws_dark_edges_on_white() {
+        let pixels = RgbImage::from_fn(8, 8, |x, _| match x < 4 {
+            true => image::Rgb([255, 0, 0]),
+            false => image::Rgb([0, 0, 255]),
+        });
+        let mut image = Image::new(DynamicImage::ImageRgb8(pixels));
+        charcoal(&mut image, &BlurGeometry::from_str("0x1").unwrap()).unwrap();
+        let pixels = image.pixels.to_rgb8();
+        let corner = pixels.get_pixel(0, 0);
+        let edge = pixels.get_pixel(4, 4);
+        assert!(corner[0] == corner[1] && corner[1] == corner[2]);
+        assert!(edge[0] < corner[0]);
+    }
+
+    #[test]
+    fn sketch_only_lightens() {
+        random::set_seed(1);
+        let pixels = GrayImage::from_pixel(4, 4, Luma([100]));
+        let mut image = Image::new(DynamicImage::ImageLuma8(pixels));
+        sketch(&mut image, &MotionBlurGeometry::from_str("0x2+45").unwrap()).unwrap();
+        assert!(image.pixels.to_luma8().pixels().all(|p| p[0] >= 100));
+    }
+}
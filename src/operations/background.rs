@@ -0,0 +1,67 @@
+//! Helpers for operations that add new areas to the image and fill them with `-background`
+
+use image::{ColorType, DynamicImage, Rgba32FImage};
+
+use crate::{arg_parsers::Color, encoders::common::convert, image::Image, utils::pool};
+
+/// Replaces the pixels with the given ones, keeping the pixel format of the image
+/// unless the background color needs a channel the image does not have.
+///
+/// The memory of the given pixels and of the replaced ones is recycled.
+pub(super) fn set_pixels(image: &mut Image, pixels: Rgba32FImage, background: &Color) {
+    let color = image.pixels.color();
+    let has_alpha = color.has_alpha() || !background.is_opaque();
+    let has_color = color.has_color() || !background.is_gray();
+    let target = match (color, has_color, has_alpha) {
+        (ColorType::L8 | ColorType::La8, false, true) => ColorType::La8,
+        (ColorType::L8 | ColorType::La8 | ColorType::Rgb8 | ColorType::Rgba8, true, true) => {
+            ColorType::Rgba8
+        }
+        (ColorType::L8 | ColorType::Rgb8, true, false) => ColorType::Rgb8,
+        (ColorType::L16 | ColorType::La16, false, true) => ColorType::La16,
+        (ColorType::L16 | ColorType::La16 | ColorType::Rgb16 | ColorType::Rgba16, true, true) => {
+            ColorType::Rgba16
+        }
+        (ColorType::L16 | ColorType::Rgb16, true, false) => ColorType::Rgb16,
+        (ColorType::Rgb32F | ColorType::Rgba32F, _, true) => ColorType::Rgba32F,
+        (color, _, _) => color,
+    };
+    let pixels = DynamicImage::ImageRgba32F(pixels);
+    let old = match target {
+        ColorType::Rgba32F => std::mem::replace(&mut image.pixels, pixels),
+        _ => {
+            let converted = convert(&pixels, target);
+            pool::recycle_image(pixels);
+            std::mem::replace(&mut image.pixels, converted)
+        }
+    };
+    pool::recycle_image(old);
+}
+
+/// Blends a pixel over another one, with both having unassociated alpha
+pub(crate) fn over(top: [f32; 4], bottom: [f32; 4]) -> [f32; 4] {
+    let alpha = top[3] + bottom[3] * (1.0 - top[3]);
+    if alpha <= 0.0 {
+        return [0.0; 4];
+    }
+    let mut blended = [0.0, 0.0, 0.0, alpha];
+    for c in 0..3 {
+        blended[c] = (top[c] * top[3] + bottom[c] * bottom[3] * (1.0 - top[3])) / alpha;
+    }
+    blended
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blending() {
+        let red = [1.0, 0.0, 0.0, 1.0];
+        let blue = [0.0, 0.0, 1.0, 1.0];
+        assert_eq!(over(red, blue), red);
+        assert_eq!(over([1.0, 0.0, 0.0, 0.0], blue), blue);
+        assert_eq!(over([1.0, 0.0, 0.0, 0.5], blue), [0.5, 0.0, 0.5, 1.0]);
+        assert_eq!(over([0.0; 4], [0.0; 4]), [0.0; 4]);
+    }
+}
@@ -0,0 +1,462 @@
+use image::{DynamicImage, Rgba32FImage};
+
+use super::{histogram::INTENSITY, interpolate::interpolate};
+use crate::{
+    arg_parsers::{BlurGeometry, InterpolateMethod, MotionBlurGeometry, UnsharpGeometry},
+    encoders::common::convert,
+    error::MagickError,
+    image::{Image, QUANTUM_RANGE},
+    utils::channel_map::Sample,
+};
+
+/// Implements `-unsharp` like imagemagick's UnsharpMaskImage(): adds the difference from a blurred copy,
+/// scaled by the gain, wherever twice that difference reaches the threshold. Smaller differences,
+/// such as noise in flat areas, are left alone.
+pub fn unsharp(image: &mut Image, geometry: &UnsharpGeometry) -> Result<(), MagickError> {
+    let mut pixels = image.pixels.to_rgba32f();
+    let blurred = gaussian_blur(&pixels, &geometry.blur);
+    let threshold = geometry.threshold as f32;
+    let gain = geometry.gain as f32;
+    for (pixel, blurred) in pixels.pixels_mut().zip(blurred.pixels()) {
+        for c in 0..4 {
+            let difference = pixel[c] - blurred[c];
+            if 2.0 * difference.abs() >= threshold {
+                pixel[c] = (pixel[c] + difference * gain).clamp(0.0, 1.0);
+            }
+        }
+    }
+    image.pixels = convert(&DynamicImage::ImageRgba32F(pixels), image.pixels.color());
+    Ok(())
+}
+
+/// A gaussian blur of every channel, applied horizontally and then vertically.
+/// Pixels beyond the edges repeat the nearest edge pixel.
+///
+/// The colors are weighted by alpha, so that the colors of transparent pixels do not bleed
+/// into the visible ones.
+pub(super) fn gaussian_blur(pixels: &Rgba32FImage, geometry: &BlurGeometry) -> Rgba32FImage {
+    let kernel = gaussian_kernel(geometry);
+    let horizontal = convolve(&premultiply(pixels), &kernel, true);
+    let mut blurred = convolve(&horizontal, &kernel, false);
+    for pixel in blurred.pixels_mut() {
+        unpremultiply(pixel);
+    }
+    blurred
+}
+
+/// Implements `-adaptive-blur` and `-adaptive-sharpen` like imagemagick's AdaptiveBlurImage()
+/// and AdaptiveSharpenImage(): every pixel gets a gaussian kernel of its own size,
+/// chosen by how strong the edges around it are.
+///
+/// Blurring uses smaller kernels near edges so that they stay crisp, while sharpening uses larger ones there
+/// and leaves flat areas, where it would mostly amplify noise, nearly untouched.
+pub fn adaptive(
+    image: &mut Image,
+    geometry: &BlurGeometry,
+    sharpen: bool,
+) -> Result<(), MagickError> {
+    if geometry.sigma <= f64::EPSILON {
+        return Ok(());
+    }
+    let pixels = image.pixels.to_rgba32f();
+    let edges = edge_strength(&pixels, geometry);
+    // kernels[i] is 2 * i + 1 pixels wide, so kernels[0] leaves the pixel as it is
+    let width = kernel_width(geometry.radius, geometry.sigma);
+    let kernels: Vec<Vec<f32>> = (0..=width / 2)
+        .map(|i| gaussian_kernel_2d(2 * i + 1, geometry.sigma))
+        .collect();
+    let premultiplied = premultiply(&pixels);
+    let result = Rgba32FImage::from_fn(pixels.width(), pixels.height(), |x, y| {
+        let edge = edges.get_pixel(x, y)[0];
+        let strength = if sharpen { edge } else { 1.0 - edge };
+        let kernel = &kernels[(strength * (kernels.len() - 1) as f32).round() as usize];
+        let mut blurred = convolve_pixel(&premultiplied, kernel, x, y);
+        unpremultiply(&mut blurred);
+        if sharpen {
+            // the same as a kernel of negated weights with twice their sum in the middle
+            let pixel = pixels.get_pixel(x, y);
+            for c in 0..4 {
+                blurred[c] = (2.0 * pixel[c] - blurred[c]).clamp(0.0, 1.0);
+            }
+        }
+        blurred
+    });
+    image.pixels = convert(&DynamicImage::ImageRgba32F(result), image.pixels.color());
+    Ok(())
+}
+
+/// Implements `-motion-blur` like imagemagick's MotionBlurImage(): every pixel becomes an average
+/// of the pixels on a line that starts at it and goes in the direction of the angle,
+/// weighted by one side of a gaussian so that the nearest ones count the most.
+pub fn motion_blur(image: &mut Image, geometry: &MotionBlurGeometry) -> Result<(), MagickError> {
+    let sigma = geometry.blur.sigma;
+    if sigma <= f64::EPSILON {
+        return Ok(());
+    }
+    let length = kernel_width(geometry.blur.radius, sigma);
+    let weights: Vec<f64> = (0..length)
+        .map(|i| (-((i * i) as f64) / (2.0 * sigma * sigma)).exp())
+        .collect();
+    let sum: f64 = weights.iter().sum();
+    let (sin, cos) = geometry.angle.to_radians().sin_cos();
+    let taps: Vec<((i64, i64), f32)> = weights
+        .iter()
+        .enumerate()
+        .map(|(i, weight)| {
+            let offset = (
+                (i as f64 * cos).round() as i64,
+                (i as f64 * sin).round() as i64,
+            );
+            (offset, (weight / sum) as f32)
+        })
+        .collect();
+    let premultiplied = premultiply(&image.pixels.to_rgba32f());
+    let (width, height) = premultiplied.dimensions();
+    let blurred = Rgba32FImage::from_fn(width, height, |x, y| {
+        let mut sum = [0f32; 4];
+        for ((dx, dy), weight) in &taps {
+            let sx = (x as i64 + dx).clamp(0, width as i64 - 1) as u32;
+            let sy = (y as i64 + dy).clamp(0, height as i64 - 1) as u32;
+            let source = premultiplied.get_pixel(sx, sy);
+            for c in 0..4 {
+                sum[c] += source[c] * weight;
+            }
+        }
+        let mut pixel = image::Rgba(sum);
+        unpremultiply(&mut pixel);
+        pixel
+    });
+    image.pixels = convert(&DynamicImage::ImageRgba32F(blurred), image.pixels.color());
+    Ok(())
+}
+
+/// Implements `-rotational-blur` like imagemagick's RotationalBlurImage(): every pixel becomes the average
+/// of the pixels on an arc around the center of the image that spans the angle, in degrees.
+///
+/// Pixels far from the center are averaged over more samples than those close to it,
+/// where the arc is short.
+pub fn rotational_blur(
+    image: &mut Image,
+    angle: f64,
+    method: InterpolateMethod,
+) -> Result<(), MagickError> {
+    let premultiplied = premultiply(&image.pixels.to_rgba32f());
+    let (width, height) = premultiplied.dimensions();
+    let center = (f64::from(width) / 2.0, f64::from(height) / 2.0);
+    let blur_radius = center.0.hypot(center.1);
+    let angle = angle.to_radians();
+    let samples = (4.0 * angle.abs() * blur_radius.sqrt()) as usize + 2;
+    let step = angle / (samples - 1) as f64;
+    let rotations: Vec<(f64, f64)> = (0..samples)
+        .map(|i| (step * (i as f64 - (samples - 1) as f64 / 2.0)).sin_cos())
+        .collect();
+    let blurred = Rgba32FImage::from_fn(width, height, |x, y| {
+        let (dx, dy) = (f64::from(x) + 0.5 - center.0, f64::from(y) + 0.5 - center.1);
+        let radius = dx.hypot(dy);
+        let stride = match radius > f64::EPSILON {
+            true => ((blur_radius / radius) as usize).clamp(1, samples - 1),
+            false => 1,
+        };
+        let mut sum = [0f32; 4];
+        let mut count = 0;
+        for &(sin, cos) in rotations.iter().step_by(stride) {
+            // Points beyond the edges take the nearest edge pixel
+            let sx = (center.0 + dx * cos - dy * sin).clamp(0.5, f64::from(width) - 0.5);
+            let sy = (center.1 + dx * sin + dy * cos).clamp(0.5, f64::from(height) - 0.5);
+            let sample = interpolate(&premultiplied, method, sx, sy, [0.0; 4]);
+            for c in 0..4 {
+                sum[c] += sample[c];
+            }
+            count += 1;
+        }
+        let mut pixel = image::Rgba(sum.map(|value| value / count as f32));
+        unpremultiply(&mut pixel);
+        pixel
+    });
+    image.pixels = convert(&DynamicImage::ImageRgba32F(blurred), image.pixels.color());
+    Ok(())
+}
+
+/// How strong the edges around every pixel are, from 0 to 1 in the first channel.
+///
+/// This is the magnitude of the intensity gradient, smoothed by the blur geometry
+/// and stretched to the full range like `-auto-level`.
+fn edge_strength(pixels: &Rgba32FImage, geometry: &BlurGeometry) -> Rgba32FImage {
+    let (width, height) = pixels.dimensions();
+    let intensity = |x: i64, y: i64| {
+        let pixel = pixels.get_pixel(
+            x.clamp(0, width as i64 - 1) as u32,
+            y.clamp(0, height as i64 - 1) as u32,
+        );
+        let gray: f64 = (0..3).map(|c| pixel[c] as f64 * INTENSITY[c]).sum();
+        gray as f32 * pixel[3]
+    };
+    // Sobel operator
+    let gradient = Rgba32FImage::from_fn(width, height, |x, y| {
+        let (x, y) = (x as i64, y as i64);
+        let dx = intensity(x + 1, y - 1) + 2.0 * intensity(x + 1, y) + intensity(x + 1, y + 1)
+            - intensity(x - 1, y - 1)
+            - 2.0 * intensity(x - 1, y)
+            - intensity(x - 1, y + 1);
+        let dy = intensity(x - 1, y + 1) + 2.0 * intensity(x, y + 1) + intensity(x + 1, y + 1)
+            - intensity(x - 1, y - 1)
+            - 2.0 * intensity(x, y - 1)
+            - intensity(x + 1, y - 1);
+        image::Rgba([dx.hypot(dy), 0.0, 0.0, 1.0])
+    });
+    let kernel = gaussian_kernel(geometry);
+    let mut edges = convolve(&convolve(&gradient, &kernel, true), &kernel, false);
+    let (min, max) = edges
+        .pixels()
+        .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), p| {
+            (min.min(p[0]), max.max(p[0]))
+        });
+    for pixel in edges.pixels_mut() {
+        pixel[0] = match max - min > f32::EPSILON {
+            true => (pixel[0] - min) / (max - min),
+            false => 0.0,
+        };
+    }
+    edges
+}
+
+/// Weights the colors by alpha, so that the colors of transparent pixels do not bleed into the visible ones
+fn premultiply(pixels: &Rgba32FImage) -> Rgba32FImage {
+    let mut premultiplied = pixels.clone();
+    for pixel in premultiplied.pixels_mut() {
+        for c in 0..3 {
+            pixel[c] *= pixel[3];
+        }
+    }
+    premultiplied
+}
+
+fn unpremultiply(pixel: &mut image::Rgba<f32>) {
+    if pixel[3] > 0.0 {
+        for c in 0..3 {
+            pixel[c] /= pixel[3];
+        }
+    }
+}
+
+/// The one-dimensional gaussian kernel for the `-blur` geometry
+fn gaussian_kernel(geometry: &BlurGeometry) -> Vec<f32> {
+    let sigma = geometry.sigma;
+    if sigma <= f64::EPSILON {
+        return vec![1.0];
+    }
+    gaussian_weights(sigma, kernel_width(geometry.radius, sigma) / 2)
+}
+
+/// Normalized one-dimensional gaussian weights from `-radius` to `radius`,
+/// in the precision of the samples they are applied to
+pub(crate) fn gaussian_weights<T: Sample>(sigma: f64, radius: usize) -> Vec<T> {
+    let radius = radius as i64;
+    let weights: Vec<f64> = (-radius..=radius)
+        .map(|x| (-(x * x) as f64 / (2.0 * sigma * sigma)).exp())
+        .collect();
+    let sum: f64 = weights.iter().sum();
+    weights.iter().map(|w| T::from_unit(w / sum)).collect()
+}
+
+/// Normalized two-dimensional gaussian weights of a `size` by `size` square, row by row
+fn gaussian_kernel_2d(size: usize, sigma: f64) -> Vec<f32> {
+    let radius = (size / 2) as i64;
+    let weights: Vec<f64> = (-radius..=radius)
+        .flat_map(|y| (-radius..=radius).map(move |x| (x, y)))
+        .map(|(x, y)| (-((x * x + y * y) as f64) / (2.0 * sigma * sigma)).exp())
+        .collect();
+    let sum: f64 = weights.iter().sum();
+    weights.iter().map(|w| (w / sum) as f32).collect()
+}
+
+/// The width of the kernel like imagemagick's GetOptimalKernelWidth1D(): twice the radius plus one,
+/// or if the radius is 0, just wide enough that the outermost weights are still visible at 16 bits
+pub(super) fn kernel_width(radius: f64, sigma: f64) -> usize {
+    if radius > f64::EPSILON {
+        return 2 * radius.ceil() as usize + 1;
+    }
+    let alpha = 1.0 / (2.0 * sigma * sigma);
+    let beta = 1.0 / ((2.0 * std::f64::consts::PI).sqrt() * sigma);
+    let mut width = 5;
+    loop {
+        let j = (width - 1) as f64 / 2.0;
+        let weight = |i: f64| (-i * i * alpha).exp() * beta;
+        let normalize: f64 = (0..width).map(|i| weight(i as f64 - j)).sum();
+        let outermost = weight(j) / normalize;
+        if outermost < 1.0 / QUANTUM_RANGE || outermost < f64::EPSILON {
+            return width - 2;
+        }
+        width += 2;
+    }
+}
+
+/// Convolves every row, or every column, with the kernel
+fn convolve(pixels: &Rgba32FImage, kernel: &[f32], horizontal: bool) -> Rgba32FImage {
+    let (width, height) = pixels.dimensions();
+    let radius = (kernel.len() / 2) as i64;
+    Rgba32FImage::from_fn(width, height, |x, y| {
+        let mut sum = [0f32; 4];
+        for (i, weight) in kernel.iter().enumerate() {
+            let offset = i as i64 - radius;
+            let (sx, sy) = match horizontal {
+                true => ((x as i64 + offset).clamp(0, width as i64 - 1) as u32, y),
+                false => (x, (y as i64 + offset).clamp(0, height as i64 - 1) as u32),
+            };
+            let source = pixels.get_pixel(sx, sy);
+            for c in 0..4 {
+                sum[c] += source[c] * weight;
+            }
+        }
+        image::Rgba(sum)
+    })
+}
+
+/// Convolves the color channels of every pixel with a square kernel, leaving alpha alone.
+/// Pixels beyond the edges repeat the nearest edge pixel.
+pub(super) fn convolve_2d(pixels: &Rgba32FImage, kernel: &[f32]) -> Rgba32FImage {
+    Rgba32FImage::from_fn(pixels.width(), pixels.height(), |x, y| {
+        let mut pixel = convolve_pixel(pixels, kernel, x, y);
+        pixel[3] = pixels.get_pixel(x, y)[3];
+        pixel
+    })
+}
+
+/// Convolves the square around a single pixel with a square kernel, such as one from [gaussian_kernel_2d]
+fn convolve_pixel(pixels: &Rgba32FImage, kernel: &[f32], x: u32, y: u32) -> image::Rgba<f32> {
+    let (width, height) = pixels.dimensions();
+    let size = kernel.len().isqrt();
+    let radius = (size / 2) as i64;
+    let mut sum = [0f32; 4];
+    for (i, weight) in kernel.iter().enumerate() {
+        let sx = (x as i64 + (i % size) as i64 - radius).clamp(0, width as i64 - 1) as u32;
+        let sy = (y as i64 + (i / size) as i64 - radius).clamp(0, height as i64 - 1) as u32;
+        let source = pixels.get_pixel(sx, sy);
+        for c in 0..4 {
+            sum[c] += source[c] * weight;
+        }
+    }
+    image::Rgba(sum)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use image::{GrayImage, Luma, Rgba};
+
+    use super::*;
+
+    #[test]
+    fn kernel_is_normalized() {
+        // the same widths as imagemagick
+        let kernel = gaussian_kernel(&BlurGeometry::from_str("0x1.5").unwrap());
+        assert_eq!(kernel.len(), 13);
+        assert_eq!(
+            gaussian_kernel(&BlurGeometry::from_str("0x1").unwrap()).len(),
+            9
+        );
+        assert!((kernel.iter().sum::<f32>() - 1.0).abs() < 1e-6);
+        let kernel = gaussian_kernel(&BlurGeometry::from_str("2x1").unwrap());
+        assert_eq!(kernel.len(), 5);
+    }
+
+    #[test]
+    fn unsharp_increases_edge_contrast() {
+        let pixels = GrayImage::from_fn(8, 1, |x, _| Luma([if x < 4 { 100 } else { 150 }]));
+        let mut image = Image::new(DynamicImage::ImageLuma8(pixels));
+        let geometry = UnsharpGeometry::from_str("0x1+1+0").unwrap();
+        unsharp(&mut image, &geometry).unwrap();
+        let row = image.pixels.as_luma8().unwrap().as_raw().clone();
+        assert!(row[3] < 100 && row[4] > 150, "{row:?}");
+        // flat areas are left alone
+        assert_eq!((row[0], row[7]), (100, 150));
+    }
+
+    #[test]
+    fn transparent_colors_do_not_bleed() {
+        let pixels = Rgba32FImage::from_fn(8, 1, |x, _| match x < 4 {
+            true => Rgba([1.0, 0.0, 0.0, 1.0]),
+            false => Rgba([0.0, 0.0, 1.0, 0.0]),
+        });
+        let blurred = gaussian_blur(&pixels, &BlurGeometry::from_str("0x2").unwrap());
+        let edge = blurred.get_pixel(4, 0);
+        assert!(edge[3] > 0.0 && edge[3] < 1.0);
+        assert!((edge[0] - 1.0).abs() < 1e-5 && edge[2].abs() < 1e-5);
+    }
+
+    /// A vertical edge from black to white, with a faint speck in the black half
+    fn edge_and_speck() -> GrayImage {
+        let mut pixels = GrayImage::from_fn(16, 16, |x, _| Luma([if x < 8 { 0 } else { 255 }]));
+        pixels.put_pixel(2, 8, Luma([40]));
+        pixels
+    }
+
+    #[test]
+    fn adaptive_blur_keeps_edges() {
+        let mut image = Image::new(DynamicImage::ImageLuma8(edge_and_speck()));
+        adaptive(&mut image, &BlurGeometry::from_str("0x1").unwrap(), false).unwrap();
+        let pixels = image.pixels.as_luma8().unwrap();
+        // the speck is smoothed out, the edge is not
+        assert!(pixels.get_pixel(2, 8)[0] < 20, "{pixels:?}");
+        assert_eq!(
+            (pixels.get_pixel(7, 4)[0], pixels.get_pixel(8, 4)[0]),
+            (0, 255)
+        );
+    }
+
+    #[test]
+    fn adaptive_sharpen_leaves_flat_areas() {
+        let pixels = GrayImage::from_fn(16, 16, |x, y| {
+            Luma([if x < 8 { 100 } else { 150 } + (x + y) as u8 % 2])
+        });
+        let mut image = Image::new(DynamicImage::ImageLuma8(pixels.clone()));
+        adaptive(&mut image, &BlurGeometry::from_str("0x1").unwrap(), true).unwrap();
+        let sharpened = image.pixels.as_luma8().unwrap();
+        assert!(sharpened.get_pixel(7, 4)[0] < 95 && sharpened.get_pixel(8, 4)[0] > 155);
+        // the checkerboard noise far from the edge barely changes
+        for x in [0, 1, 14, 15] {
+            let difference = sharpened.get_pixel(x, 4)[0].abs_diff(pixels.get_pixel(x, 4)[0]);
+            assert!(difference <= 1, "{sharpened:?}");
+        }
+    }
+
+    #[test]
+    fn motion_blur_trails_in_one_direction() {
+        let mut pixels = GrayImage::new(9, 9);
+        pixels.put_pixel(4, 4, Luma([255]));
+        let mut image = Image::new(DynamicImage::ImageLuma8(pixels));
+        let geometry = MotionBlurGeometry::from_str("0x1+90").unwrap();
+        motion_blur(&mut image, &geometry).unwrap();
+        let pixels = image.pixels.as_luma8().unwrap();
+        // pixels above the dot pick it up along a downward line, the ones below and beside it do not
+        assert!(pixels.get_pixel(4, 3)[0] > 0 && pixels.get_pixel(4, 3)[0] < 255);
+        assert!(pixels.get_pixel(4, 4)[0] > pixels.get_pixel(4, 3)[0]);
+        assert_eq!(pixels.get_pixel(4, 5)[0], 0);
+        assert_eq!(pixels.get_pixel(3, 4)[0], 0);
+    }
+
+    #[test]
+    fn rotational_blur_spares_the_center() {
+        let pixels = GrayImage::from_fn(9, 9, |x, y| Luma([if x < 4 || y == 4 { 0 } else { 255 }]));
+        let mut image = Image::new(DynamicImage::ImageLuma8(pixels.clone()));
+        rotational_blur(&mut image, 0.0, InterpolateMethod::Bilinear).unwrap();
+        assert_eq!(image.pixels.as_luma8().unwrap(), &pixels);
+        rotational_blur(&mut image, 30.0, InterpolateMethod::Bilinear).unwrap();
+        let blurred = image.pixels.as_luma8().unwrap();
+        assert_eq!(blurred.get_pixel(4, 4)[0], 0);
+        // the line through the middle is smeared into a wedge far from the center
+        assert!(blurred.get_pixel(8, 4)[0] > 0 && blurred.get_pixel(8, 4)[0] < 255);
+        assert!(blurred.get_pixel(8, 5)[0] < 255);
+    }
+
+    #[test]
+    fn threshold_skips_small_differences() {
+        let pixels = GrayImage::from_fn(8, 1, |x, _| Luma([if x < 4 { 100 } else { 104 }]));
+        let mut image = Image::new(DynamicImage::ImageLuma8(pixels.clone()));
+        // twice the difference from the blurred edge is still below the threshold
+        let geometry = UnsharpGeometry::from_str("0x1+1+0.05").unwrap();
+        unsharp(&mut image, &geometry).unwrap();
+        assert_eq!(image.pixels.as_luma8().unwrap(), &pixels);
+    }
+}
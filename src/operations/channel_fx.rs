@@ -0,0 +1,116 @@
+use image::DynamicImage;
+
+use super::colorize::{add_alpha, promote_to_color};
+use crate::{
+    arg_parsers::{ChannelFx, ChannelOp, PixelChannel},
+    error::MagickError,
+    image::Image,
+};
+
+/// Implements `-channel-fx`: swaps and copies channels, such as `red<=>blue`.
+///
+/// Grayscale images are converted to color if a color channel is mentioned,
+/// and an opaque alpha channel is added if the alpha channel is.
+pub fn channel_fx(image: &mut Image, fx: &ChannelFx) -> Result<(), MagickError> {
+    let mentions = |wanted: &[PixelChannel]| {
+        fx.ops
+            .iter()
+            .flat_map(|op| op.channels())
+            .any(|channel| wanted.contains(&channel))
+    };
+    use PixelChannel::*;
+    if mentions(&[Red, Green, Blue]) {
+        promote_to_color(&mut image.pixels);
+    }
+    if mentions(&[Alpha]) {
+        add_alpha(&mut image.pixels);
+    }
+    let channels = usize::from(image.pixels.color().channel_count());
+    let index = |channel: PixelChannel| match channel {
+        Red | Gray => 0,
+        Green => 1,
+        Blue => 2,
+        // alpha is always the last channel
+        Alpha => channels - 1,
+    };
+    let ops: Vec<(bool, usize, usize)> = fx
+        .ops
+        .iter()
+        .map(|op| match *op {
+            ChannelOp::Swap(a, b) => (true, index(a), index(b)),
+            ChannelOp::Copy(from, to) => (false, index(from), index(to)),
+        })
+        .collect();
+    match &mut image.pixels {
+        DynamicImage::ImageLuma8(buf) => rearrange(buf, channels, &ops),
+        DynamicImage::ImageLumaA8(buf) => rearrange(buf, channels, &ops),
+        DynamicImage::ImageRgb8(buf) => rearrange(buf, channels, &ops),
+        DynamicImage::ImageRgba8(buf) => rearrange(buf, channels, &ops),
+        DynamicImage::ImageLuma16(buf) => rearrange(buf, channels, &ops),
+        DynamicImage::ImageLumaA16(buf) => rearrange(buf, channels, &ops),
+        DynamicImage::ImageRgb16(buf) => rearrange(buf, channels, &ops),
+        DynamicImage::ImageRgba16(buf) => rearrange(buf, channels, &ops),
+        DynamicImage::ImageRgb32F(buf) => rearrange(buf, channels, &ops),
+        DynamicImage::ImageRgba32F(buf) => rearrange(buf, channels, &ops),
+        _ => unreachable!(),
+    }
+    Ok(())
+}
+
+/// Applies the swaps and copies, given as `(is_swap, first, second)` channel indices, to every pixel
+fn rearrange<T: Copy>(samples: &mut [T], channels: usize, ops: &[(bool, usize, usize)]) {
+    for pixel in samples.chunks_exact_mut(channels) {
+        for &(is_swap, first, second) in ops {
+            match is_swap {
+                true => pixel.swap(first, second),
+                false => pixel[second] = pixel[first],
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use image::{GrayImage, Luma, LumaA, Rgb, RgbImage, Rgba};
+
+    use super::*;
+
+    fn apply(image: DynamicImage, fx: &str) -> DynamicImage {
+        let mut image = Image::new(image);
+        channel_fx(&mut image, &ChannelFx::from_str(fx).unwrap()).unwrap();
+        image.pixels
+    }
+
+    #[test]
+    fn swaps_and_copies() {
+        let pixels = DynamicImage::ImageRgb8(RgbImage::from_pixel(1, 1, Rgb([10, 20, 30])));
+        let swapped = apply(pixels.clone(), "red<=>blue");
+        assert_eq!(
+            swapped.as_rgb8().unwrap().get_pixel(0, 0),
+            &Rgb([30, 20, 10])
+        );
+        // steps apply in order
+        let copied = apply(pixels, "red<=>blue; blue=>alpha");
+        assert_eq!(
+            copied.as_rgba8().unwrap().get_pixel(0, 0),
+            &Rgba([30, 20, 10, 10])
+        );
+    }
+
+    #[test]
+    fn grayscale_images() {
+        let pixels = DynamicImage::ImageLuma8(GrayImage::from_pixel(1, 1, Luma([50])));
+        let alpha = apply(pixels.clone(), "gray=>alpha");
+        assert_eq!(
+            alpha.as_luma_alpha8().unwrap().get_pixel(0, 0),
+            &LumaA([50, 50])
+        );
+        let color = apply(pixels, "alpha=>red");
+        assert_eq!(
+            color.as_rgba8().unwrap().get_pixel(0, 0),
+            &Rgba([255, 50, 50, 255])
+        );
+    }
+}
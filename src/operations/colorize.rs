@@ -0,0 +1,124 @@
+use image::{ColorType, DynamicImage};
+
+use super::histogram::INTENSITY;
+use crate::{
+    arg_parsers::{BlendPercent, Color},
+    encoders::common::convert,
+    error::MagickError,
+    image::Image,
+    utils::channel_map::map_colors,
+};
+
+/// Implements `-colorize`: blends the fill color into every pixel by the given percentage per channel
+pub fn colorize(image: &mut Image, blend: &BlendPercent, fill: &Color) -> Result<(), MagickError> {
+    let blend = blend.to_array().map(|percent| percent / 100.0);
+    let fill = fill_array(fill);
+    if !is_gray_result(&blend, &fill) {
+        promote_to_color(&mut image.pixels);
+    }
+    map_colors(&mut image.pixels, |rgb| {
+        std::array::from_fn(|i| rgb[i] * (1.0 - blend[i]) + fill[i] * blend[i])
+    });
+    Ok(())
+}
+
+/// Implements `-tint`: shifts the midtones towards the fill color, leaving black and white alone.
+///
+/// Follows imagemagick's TintImage(): the shift is the fill color weighted by the percentage
+/// minus the intensity of the fill color, applied fully at 50% and fading out along a parabola.
+pub fn tint(image: &mut Image, weight: &BlendPercent, fill: &Color) -> Result<(), MagickError> {
+    let weight = weight.to_array().map(|percent| percent / 100.0);
+    let fill = fill_array(fill);
+    let intensity: f64 = fill.iter().zip(INTENSITY).map(|(c, w)| c * w).sum();
+    let shift: [f64; 3] = std::array::from_fn(|i| weight[i] * fill[i] - intensity);
+    if !(shift[0] == shift[1] && shift[1] == shift[2]) {
+        promote_to_color(&mut image.pixels);
+    }
+    map_colors(&mut image.pixels, |rgb| {
+        std::array::from_fn(|i| {
+            let distance = rgb[i] - 0.5;
+            rgb[i] + shift[i] * (1.0 - 4.0 * distance * distance)
+        })
+    });
+    Ok(())
+}
+
+fn fill_array(fill: &Color) -> [f64; 3] {
+    [fill.red, fill.green, fill.blue].map(f64::from)
+}
+
+/// Whether blending keeps gray pixels gray
+fn is_gray_result(blend: &[f64; 3], fill: &[f64; 3]) -> bool {
+    let products: [f64; 3] = std::array::from_fn(|i| blend[i] * fill[i]);
+    blend.iter().all(|&b| b == blend[0]) && products.iter().all(|&p| p == products[0])
+}
+
+/// Converts grayscale images to RGB so that a color can be applied to them
+pub(super) fn promote_to_color(pixels: &mut DynamicImage) {
+    let color = match pixels.color() {
+        ColorType::L8 => ColorType::Rgb8,
+        ColorType::La8 => ColorType::Rgba8,
+        ColorType::L16 => ColorType::Rgb16,
+        ColorType::La16 => ColorType::Rgba16,
+        _ => return,
+    };
+    *pixels = convert(pixels, color);
+}
+
+/// Adds an opaque alpha channel to images without one
+pub(super) fn add_alpha(pixels: &mut DynamicImage) {
+    let color = match pixels.color() {
+        ColorType::L8 => ColorType::La8,
+        ColorType::Rgb8 => ColorType::Rgba8,
+        ColorType::L16 => ColorType::La16,
+        ColorType::Rgb16 => ColorType::Rgba16,
+        ColorType::Rgb32F => ColorType::Rgba32F,
+        _ => return,
+    };
+    *pixels = convert(pixels, color);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use image::{GrayImage, Luma, Rgb, RgbImage};
+
+    use super::*;
+
+    #[test]
+    fn colorize_blends_fill() {
+        let pixels = GrayImage::from_pixel(2, 2, Luma([0]));
+        let mut image = Image::new(DynamicImage::ImageLuma8(pixels));
+        let blend = BlendPercent::from_str("50/100/0").unwrap();
+        colorize(&mut image, &blend, &Color::WHITE).unwrap();
+        assert_eq!(
+            image.pixels.as_rgb8().unwrap().get_pixel(0, 0),
+            &Rgb([128, 255, 0])
+        );
+
+        // gray stays gray
+        let pixels = GrayImage::from_pixel(2, 2, Luma([0]));
+        let mut image = Image::new(DynamicImage::ImageLuma8(pixels));
+        let blend = BlendPercent::from_str("25%").unwrap();
+        colorize(&mut image, &blend, &Color::WHITE).unwrap();
+        assert_eq!(
+            image.pixels.as_luma8().unwrap().get_pixel(0, 0),
+            &Luma([64])
+        );
+    }
+
+    #[test]
+    fn tint_shifts_midtones() {
+        let pixels = RgbImage::from_fn(3, 1, |x, _| Rgb([[0, 128, 255][x as usize]; 3]));
+        let mut image = Image::new(DynamicImage::ImageRgb8(pixels));
+        let red = Color::from_str("red").unwrap();
+        tint(&mut image, &BlendPercent::from_str("100").unwrap(), &red).unwrap();
+        let pixels = image.pixels.as_rgb8().unwrap();
+        // black and white are left alone
+        assert_eq!(pixels.get_pixel(0, 0), &Rgb([0, 0, 0]));
+        assert_eq!(pixels.get_pixel(2, 0), &Rgb([255, 255, 255]));
+        let [r, g, b] = pixels.get_pixel(1, 0).0;
+        assert!(r > 128 && g < 128 && b < 128, "{r} {g} {b}");
+    }
+}
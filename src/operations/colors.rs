@@ -0,0 +1,156 @@
+use std::collections::HashSet;
+
+use image::{ColorType, DynamicImage, Rgba, RgbaImage};
+
+use crate::{
+    arg_parsers::Colorspace,
+    encoders::common::{convert, is_opaque},
+    error::MagickError,
+    image::Image,
+    quantize::Quantizer,
+    wm_err,
+};
+
+/// Implements `-colors`: reduces the image to at most `quantizer.colors` colors.
+///
+/// Unlike in GIF, translucent pixels keep their alpha. Only fully transparent pixels
+/// are left out of the palette, since their color cannot be seen.
+pub fn colors(image: &mut Image, quantizer: &Quantizer) -> Result<(), MagickError> {
+    keeping_alpha(image, |pixels| quantizer.quantize(pixels));
+    Ok(())
+}
+
+/// Implements `-remap`: replaces every color with the closest one from the palette,
+/// dithering if `-dither` is in effect. Alpha is kept like in [colors].
+pub fn remap(
+    image: &mut Image,
+    palette: &[Rgba<u8>],
+    quantizer: &Quantizer,
+) -> Result<(), MagickError> {
+    keeping_alpha(image, |pixels| quantizer.remap(pixels, palette));
+    Ok(())
+}
+
+/// Implements `-monochrome`: turns the image into black and white pixels, matched by their
+/// brightness and dithered if `-dither` is in effect. Alpha is kept like in [colors].
+pub fn monochrome(image: &mut Image, quantizer: &Quantizer) -> Result<(), MagickError> {
+    let quantizer = Quantizer {
+        colorspace: Colorspace::Gray,
+        ..*quantizer
+    };
+    let palette = [Rgba([0, 0, 0, u8::MAX]), Rgba([u8::MAX; 4])];
+    keeping_alpha(image, |pixels| quantizer.remap(pixels, &palette));
+    let gray = match !is_opaque(&image.pixels) {
+        true => ColorType::La8,
+        false => ColorType::L8,
+    };
+    image.pixels = convert(&image.pixels, gray);
+    Ok(())
+}
+
+/// The distinct colors of the visible pixels of `-remap` image, as opaque colors
+pub fn palette_of(image: &Image) -> Result<Vec<Rgba<u8>>, MagickError> {
+    let colors: HashSet<[u8; 3]> = image
+        .pixels
+        .to_rgba8()
+        .pixels()
+        .filter(|pixel| pixel[3] != 0)
+        .map(|pixel| [pixel[0], pixel[1], pixel[2]])
+        .collect();
+    if colors.is_empty() {
+        return Err(wm_err!(
+            "unable to remap image, the palette image `{}' is fully transparent",
+            image.filename.to_string_lossy()
+        ));
+    }
+    let mut palette: Vec<Rgba<u8>> = colors
+        .into_iter()
+        .map(|[r, g, b]| Rgba([r, g, b, u8::MAX]))
+        .collect();
+    // the closest match is the first one found, so ties must not depend on hashing order
+    palette.sort_by_key(|color| color.0);
+    Ok(palette)
+}
+
+/// Runs `reduce` on the image made opaque, then restores the original alpha
+fn keeping_alpha(image: &mut Image, reduce: impl FnOnce(&mut RgbaImage)) {
+    let mut pixels = image.pixels.to_rgba8();
+    let alpha: Vec<u8> = pixels.pixels().map(|pixel| pixel[3]).collect();
+    for pixel in pixels.pixels_mut().filter(|pixel| pixel[3] != 0) {
+        pixel[3] = u8::MAX;
+    }
+    reduce(&mut pixels);
+    for (pixel, alpha) in pixels.pixels_mut().zip(alpha) {
+        pixel[3] = alpha;
+    }
+    image.pixels = convert(&DynamicImage::ImageRgba8(pixels), image.pixels.color());
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use crate::arg_parsers::DitherMethod;
+
+    use super::*;
+
+    #[test]
+    fn reduces_colors_and_keeps_alpha() {
+        let pixels = RgbaImage::from_fn(32, 32, |x, y| {
+            Rgba([(x * 8) as u8, (y * 8) as u8, 100, (x * 8) as u8])
+        });
+        let mut image = Image::new(DynamicImage::ImageRgba8(pixels));
+        let quantizer = Quantizer {
+            colors: 8,
+            ..Default::default()
+        };
+        colors(&mut image, &quantizer).unwrap();
+        let pixels = image.pixels.as_rgba8().unwrap();
+        let colors: HashSet<[u8; 3]> = pixels
+            .pixels()
+            .filter(|pixel| pixel[3] != 0)
+            .map(|pixel| [pixel[0], pixel[1], pixel[2]])
+            .collect();
+        assert!(colors.len() <= 8, "{}", colors.len());
+        assert_eq!(pixels.get_pixel(5, 3)[3], 40);
+    }
+
+    #[test]
+    fn monochrome_is_black_and_white() {
+        let pixels = RgbaImage::from_fn(16, 16, |x, _| Rgba([(x * 16) as u8, 0, 200, 255]));
+        let mut image = Image::new(DynamicImage::ImageRgba8(pixels));
+        monochrome(&mut image, &Quantizer::default()).unwrap();
+        let pixels = image.pixels.as_luma8().unwrap();
+        assert!(pixels
+            .pixels()
+            .all(|pixel| pixel[0] == 0 || pixel[0] == 255));
+        assert!(pixels.pixels().any(|pixel| pixel[0] == 0));
+        assert!(pixels.pixels().any(|pixel| pixel[0] == 255));
+    }
+
+    #[test]
+    fn remaps_to_the_colors_of_another_image() {
+        let palette_image = RgbaImage::from_fn(4, 1, |x, _| match x {
+            0 | 1 => Rgba([0, 0, 0, 255]),
+            2 => Rgba([255, 255, 255, 255]),
+            _ => Rgba([255, 0, 0, 0]),
+        });
+        let palette = palette_of(&Image::new(DynamicImage::ImageRgba8(palette_image))).unwrap();
+        assert_eq!(palette, [Rgba([0, 0, 0, 255]), Rgba([255, 255, 255, 255])]);
+
+        let pixels = RgbaImage::from_fn(2, 1, |x, _| match x {
+            0 => Rgba([20, 40, 10, 255]),
+            _ => Rgba([200, 220, 250, 100]),
+        });
+        let mut image = Image::new(DynamicImage::ImageRgba8(pixels));
+        let quantizer = Quantizer {
+            dither: DitherMethod::None,
+            ..Default::default()
+        };
+        remap(&mut image, &palette, &quantizer).unwrap();
+        assert_eq!(
+            image.pixels.as_rgba8().unwrap().as_raw(),
+            &[0, 0, 0, 255, 255, 255, 255, 100]
+        );
+    }
+}
@@ -0,0 +1,44 @@
+use super::identify::expand_template;
+use crate::{arg_parsers::IdentifyFormat, error::MagickError, image::Image};
+
+/// Implements `-comment`: sets the comment of the image to the expanded template,
+/// or removes it for `+comment`. `sequence_length` is the number of images in the sequence, for `%n`.
+pub fn comment(
+    image: &mut Image,
+    sequence_length: usize,
+    template: Option<&IdentifyFormat>,
+) -> Result<(), MagickError> {
+    image.comment = template.map(|template| expand_template(image, sequence_length, template));
+    Ok(())
+}
+
+/// Implements `-label`: sets the label of the image to the expanded template,
+/// or removes it for `+label`. `sequence_length` is the number of images in the sequence, for `%n`.
+pub fn label(
+    image: &mut Image,
+    sequence_length: usize,
+    template: Option<&IdentifyFormat>,
+) -> Result<(), MagickError> {
+    image.label = template.map(|template| expand_template(image, sequence_length, template));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use image::{DynamicImage, RgbImage};
+
+    use super::*;
+
+    #[test]
+    fn escapes_are_expanded() {
+        let mut image = Image::new(DynamicImage::ImageRgb8(RgbImage::new(3, 2)));
+        image.filename = "dir/rose.jpg".into();
+        let template = IdentifyFormat::from_str("%f is %wx%h").unwrap();
+        comment(&mut image, 1, Some(&template)).unwrap();
+        assert_eq!(image.comment.as_deref(), Some("rose.jpg is 3x2"));
+        comment(&mut image, 1, None).unwrap();
+        assert_eq!(image.comment, None);
+    }
+}
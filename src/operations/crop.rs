@@ -1,14 +1,284 @@
-use crate::arg_parsers::LoadCropGeometry;
-
-pub fn crop_on_load(
-    image: &mut image::DynamicImage,
-    geom: &LoadCropGeometry,
-) -> Result<(), crate::error::MagickError> {
-    // Sadly this doesn't check bounds right now, so we can get panics later on because of wrong crop parameters:
-    // https://github.com/image-rs/image/issues/2296
-    // TODO: change this in `image` because I don't want to emulate this on the client side
-    // and pretend the problem doesn't exist for anyone else
-    let cropped = image.crop_imm(geom.xoffset, geom.yoffset, geom.width, geom.height);
-    *image = cropped;
+use image::{DynamicImage, RgbaImage};
+
+use crate::{
+    arg_parsers::{CropGeometry, Gravity, LoadCropGeometry},
+    error::MagickError,
+    image::{Image, PageGeometry},
+    wm_err,
+};
+
+/// Implements the `[WxH+X+Y]` read modifier, which crops the image right after loading
+pub fn crop_on_load(image: &mut Image, geom: &LoadCropGeometry) -> Result<(), MagickError> {
+    crop_region(
+        image,
+        i64::from(geom.xoffset),
+        i64::from(geom.yoffset),
+        geom.width,
+        geom.height,
+    );
     Ok(())
 }
+
+/// Implements `-crop`: cuts out a region of the image, positioned according to `-gravity`.
+///
+/// The region may extend past the image or have negative offsets, in which case
+/// only the part that overlaps the image is kept, same as in imagemagick.
+/// The result keeps its position on the virtual canvas until `+repage`.
+pub fn crop(
+    image: &mut Image,
+    geometry: &CropGeometry,
+    gravity: Gravity,
+) -> Result<(), MagickError> {
+    let (width, height) = geometry.size(image.width(), image.height());
+    let offset = match geometry.offset {
+        Some(offset) => offset,
+        // With gravity a single region is cut out, otherwise the image is split into tiles
+        None if !matches!(gravity, Gravity::None | Gravity::Forget) => (0, 0),
+        None if width >= image.width() && height >= image.height() => (0, 0),
+        None => {
+            return Err(wm_err!(
+                "cropping into tiles is not supported, specify an offset such as `{}x{}+0+0'",
+                width,
+                height
+            ))
+        }
+    };
+    // Offsets are relative to the virtual canvas, not to the image
+    let (x, y) = gravity.position((image.width(), image.height()), (width, height), offset);
+    crop_region(image, x, y, width, height);
+    Ok(())
+}
+
+/// The virtual canvas of the image, which is just the image itself if it has no page geometry
+pub(super) fn canvas(image: &Image) -> PageGeometry {
+    match image.page {
+        Some(page) if page.width != 0 && page.height != 0 => page,
+        Some(page) => PageGeometry {
+            width: image.width(),
+            height: image.height(),
+            ..page
+        },
+        None => PageGeometry {
+            width: image.width(),
+            height: image.height(),
+            x: 0,
+            y: 0,
+        },
+    }
+}
+
+/// Crops to the region at the given position on the virtual canvas,
+/// clamped to the image the same way imagemagick's `CropImage` does.
+/// A zero width or height stands for the width or height of the canvas.
+pub(super) fn crop_region(image: &mut Image, x: i64, y: i64, width: u32, height: u32) {
+    let canvas = canvas(image);
+    let (columns, rows) = (i64::from(image.width()), i64::from(image.height()));
+    let mut width = match width {
+        0 => i64::from(canvas.width),
+        width => i64::from(width),
+    };
+    let mut height = match height {
+        0 => i64::from(canvas.height),
+        height => i64::from(height),
+    };
+    let (requested_width, requested_height) = (width, height);
+    // Convert to coordinates within the image. The offsets are untrusted, so nothing may overflow.
+    let (mut x, mut y) = (x.saturating_sub(canvas.x), y.saturating_sub(canvas.y));
+    if x.saturating_neg() >= width || y.saturating_neg() >= height || x > columns || y > rows {
+        transparent_pixel(image, canvas);
+        return;
+    }
+    // Drop the parts to the left and above the image
+    if x < 0 {
+        width += x;
+        x = 0;
+    }
+    if y < 0 {
+        height += y;
+        y = 0;
+    }
+    // ...and to the right and below it
+    let width = width.min(columns - x).min(requested_width);
+    let height = height.min(rows - y).min(requested_height);
+    if width <= 0 || height <= 0 {
+        transparent_pixel(image, canvas);
+        return;
+    }
+    image.pixels = image
+        .pixels
+        .crop_imm(x as u32, y as u32, width as u32, height as u32);
+    image.page = Some(PageGeometry {
+        x: canvas.x.saturating_add(x),
+        y: canvas.y.saturating_add(y),
+        ..canvas
+    });
+}
+
+/// What imagemagick returns when there is nothing left of the image:
+/// a single transparent pixel placed just outside the canvas
+pub(super) fn transparent_pixel(image: &mut Image, canvas: PageGeometry) {
+    image.pixels = DynamicImage::ImageRgba8(RgbaImage::new(1, 1));
+    image.page = Some(PageGeometry {
+        x: -1,
+        y: -1,
+        ..canvas
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use image::{GenericImageView, Rgb, RgbImage};
+    use quickcheck_macros::quickcheck;
+    use strum::VariantArray;
+
+    use super::*;
+
+    /// Every pixel stores its own coordinates
+    fn test_image() -> Image {
+        Image::new(DynamicImage::ImageRgb8(RgbImage::from_fn(10, 8, |x, y| {
+            Rgb([x as u8, y as u8, 0])
+        })))
+    }
+
+    fn cropped(geometry: &str, gravity: Gravity) -> Image {
+        let mut image = test_image();
+        crop(
+            &mut image,
+            &CropGeometry::from_str(geometry).unwrap(),
+            gravity,
+        )
+        .unwrap();
+        image
+    }
+
+    fn summary(image: &Image) -> (u32, u32, i64, i64, [u8; 2]) {
+        let page = image.page.unwrap();
+        let corner = image.pixels.get_pixel(0, 0);
+        (
+            image.width(),
+            image.height(),
+            page.x,
+            page.y,
+            [corner[0], corner[1]],
+        )
+    }
+
+    #[test]
+    fn inside_the_image() {
+        assert_eq!(
+            summary(&cropped("4x3+2+1", Gravity::None)),
+            (4, 3, 2, 1, [2, 1])
+        );
+        assert_eq!(
+            summary(&cropped("50%+0+0", Gravity::None)),
+            (5, 4, 0, 0, [0, 0])
+        );
+    }
+
+    #[test]
+    fn overflow_is_clamped() {
+        assert_eq!(
+            summary(&cropped("100x100+6+5", Gravity::None)),
+            (4, 3, 6, 5, [6, 5])
+        );
+        assert_eq!(
+            summary(&cropped("5x5-2-3", Gravity::None)),
+            (3, 2, 0, 0, [0, 0])
+        );
+    }
+
+    #[test]
+    fn regions_outside_leave_a_transparent_pixel() {
+        for geometry in ["5x5+10+0", "5x5-5+0", "5x5+0+100", "5x5-10-10"] {
+            let image = cropped(geometry, Gravity::None);
+            assert_eq!((image.width(), image.height()), (1, 1), "{geometry}");
+            assert_eq!(image.page.unwrap().x, -1);
+            assert_eq!(image.pixels.to_rgba8().get_pixel(0, 0)[3], 0);
+        }
+    }
+
+    #[test]
+    fn gravity() {
+        assert_eq!(
+            summary(&cropped("4x2", Gravity::Center)),
+            (4, 2, 3, 3, [3, 3])
+        );
+        assert_eq!(
+            summary(&cropped("4x2+1+1", Gravity::SouthEast)),
+            (4, 2, 5, 5, [5, 5])
+        );
+    }
+
+    #[test]
+    fn offsets_are_relative_to_the_canvas() {
+        let mut image = test_image();
+        image.page = Some(PageGeometry {
+            width: 20,
+            height: 20,
+            x: 5,
+            y: 5,
+        });
+        crop(
+            &mut image,
+            &CropGeometry::from_str("10x10+0+0").unwrap(),
+            Gravity::None,
+        )
+        .unwrap();
+        assert_eq!(summary(&image), (5, 5, 5, 5, [0, 0]));
+        assert_eq!(image.page.unwrap().width, 20);
+    }
+
+    /// Untrusted rectangles must never panic, and the result must always be a part of the image
+    #[quickcheck]
+    fn any_rectangle_is_safe(x: i64, y: i64, width: u16, height: u16) {
+        let mut image = test_image();
+        crop_region(&mut image, x, y, width.into(), height.into());
+        let page = image.page.unwrap();
+        if (page.x, page.y) == (-1, -1) {
+            assert_eq!((image.width(), image.height()), (1, 1));
+            return;
+        }
+        assert!(page.x >= 0 && page.x + i64::from(image.width()) <= 10);
+        assert!(page.y >= 0 && page.y + i64::from(image.height()) <= 8);
+        let corner = image.pixels.get_pixel(0, 0);
+        assert_eq!(
+            (i64::from(corner[0]), i64::from(corner[1])),
+            (page.x, page.y)
+        );
+    }
+
+    #[test]
+    fn huge_offsets_with_any_gravity() {
+        for gravity in Gravity::VARIANTS {
+            for offset in [
+                "10x10-1e30+0",
+                "10x10+1e30-1e30",
+                "10x10+9223372036854775807+0",
+            ] {
+                let mut image = test_image();
+                let geometry = CropGeometry::from_str(offset).unwrap();
+                crop(&mut image, &geometry, *gravity).unwrap();
+                assert_eq!((image.width(), image.height()), (1, 1));
+            }
+        }
+    }
+
+    #[test]
+    fn tiles_are_rejected() {
+        let mut image = test_image();
+        let geometry = CropGeometry::from_str("5x5").unwrap();
+        assert!(crop(&mut image, &geometry, Gravity::None).is_err());
+        let geometry = CropGeometry::from_str("100%").unwrap();
+        assert!(crop(&mut image, &geometry, Gravity::None).is_ok());
+    }
+
+    #[test]
+    fn read_modifier_is_clamped_too() {
+        let mut image = test_image();
+        let geometry = LoadCropGeometry::from_str("100x100+8+0").unwrap();
+        crop_on_load(&mut image, &geometry).unwrap();
+        assert_eq!((image.width(), image.height()), (2, 8));
+    }
+}
@@ -0,0 +1,81 @@
+use super::colorize::promote_to_color;
+use crate::{
+    arg_parsers::{ChannelMask, EvaluateOperator},
+    error::MagickError,
+    image::Image,
+    utils::channel_map::map_rgba,
+};
+
+/// Implements `-evaluate`: combines every sample of the selected channels with a constant,
+/// such as `-channel A -evaluate multiply 0.4` to make the image more transparent.
+///
+/// `constant` is normalized to `[0, 1]` for operators that take a sample value.
+/// Images without an alpha channel are left without one.
+pub fn evaluate(
+    image: &mut Image,
+    operator: EvaluateOperator,
+    constant: f64,
+    channels: ChannelMask,
+) -> Result<(), MagickError> {
+    if channels.splits_colors() {
+        promote_to_color(&mut image.pixels);
+    }
+    let selected = channels.to_array();
+    map_rgba(&mut image.pixels, |rgba| {
+        std::array::from_fn(|i| match selected[i] {
+            true => operator.apply(rgba[i], constant),
+            false => rgba[i],
+        })
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use image::{DynamicImage, GrayImage, Luma, Rgb, Rgba, RgbaImage};
+
+    use super::*;
+
+    #[test]
+    fn selected_channels_only() {
+        let pixels = RgbaImage::from_pixel(1, 1, Rgba([100, 100, 100, 200]));
+        let mut image = Image::new(DynamicImage::ImageRgba8(pixels));
+        let alpha = ChannelMask::from_str("A").unwrap();
+        evaluate(&mut image, EvaluateOperator::Multiply, 0.5, alpha).unwrap();
+        assert_eq!(
+            image.pixels.as_rgba8().unwrap().get_pixel(0, 0),
+            &Rgba([100, 100, 100, 100])
+        );
+
+        let mut image = image.clone();
+        let colors = ChannelMask::default();
+        evaluate(&mut image, EvaluateOperator::Add, 0.2, colors).unwrap();
+        assert_eq!(
+            image.pixels.as_rgba8().unwrap().get_pixel(0, 0),
+            &Rgba([151, 151, 151, 100])
+        );
+    }
+
+    #[test]
+    fn gray_images() {
+        let pixels = GrayImage::from_pixel(1, 1, Luma([100]));
+        let mut image = Image::new(DynamicImage::ImageLuma8(pixels));
+        evaluate(
+            &mut image,
+            EvaluateOperator::Set,
+            0.0,
+            ChannelMask::default(),
+        )
+        .unwrap();
+        assert_eq!(image.pixels.as_luma8().unwrap().get_pixel(0, 0), &Luma([0]));
+
+        let red = ChannelMask::from_str("R").unwrap();
+        evaluate(&mut image, EvaluateOperator::Set, 1.0, red).unwrap();
+        assert_eq!(
+            image.pixels.as_rgb8().unwrap().get_pixel(0, 0),
+            &Rgb([255, 0, 0])
+        );
+    }
+}
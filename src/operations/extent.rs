@@ -0,0 +1,156 @@
+use super::background::{over, set_pixels};
+use crate::{
+    arg_parsers::{Color, CropGeometry, Gravity},
+    error::MagickError,
+    image::Image,
+    limits::Limits,
+    utils::pool,
+    wm_err,
+};
+
+/// Implements `-extent`: sets the size of the image, cutting it or padding it with the background color.
+///
+/// The image is placed according to `-gravity` and the offset, e.g. `-gravity center -extent 120%`
+/// adds a tenth of the size on every side. The image is blended over the background,
+/// so its transparent parts show the background color.
+/// The new size is checked against the limits before the canvas is allocated.
+pub fn extent(
+    image: &mut Image,
+    geometry: &CropGeometry,
+    gravity: Gravity,
+    background: &Color,
+    limits: &Limits,
+) -> Result<(), MagickError> {
+    let (width, height) = geometry.size(image.width(), image.height());
+    if width == 0 || height == 0 {
+        return Err(wm_err!(
+            "negative or zero image size `{}'",
+            image.filename.to_string_lossy()
+        ));
+    }
+    // The result is built in floating point RGBA
+    limits.check_new_image(f64::from(width), f64::from(height), 16)?;
+    let offset = geometry.offset.unwrap_or((0, 0));
+    // The position of the new canvas relative to the image
+    let (x, y) = gravity.position((image.width(), image.height()), (width, height), offset);
+    if (width, height, x, y) == (image.width(), image.height(), 0, 0) {
+        return Ok(());
+    }
+    let source = pool::to_rgba32f(&image.pixels);
+    let fill = background.to_array();
+    let extended = pool::rgba32f_from_fn(width, height, |column, row| {
+        let source_x = i64::from(column) + x;
+        let source_y = i64::from(row) + y;
+        let inside = (0..i64::from(source.width())).contains(&source_x)
+            && (0..i64::from(source.height())).contains(&source_y);
+        let pixel = match inside {
+            true => over(source.get_pixel(source_x as u32, source_y as u32).0, fill),
+            false => fill,
+        };
+        image::Rgba(pixel)
+    });
+    pool::recycle(source.into_raw());
+    set_pixels(image, extended, background);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use image::{ColorType, DynamicImage, GenericImageView, Rgb, RgbImage, Rgba, RgbaImage};
+
+    use super::*;
+
+    fn extended(geometry: &str, gravity: Gravity, background: &str) -> Image {
+        let pixels = RgbImage::from_pixel(10, 10, Rgb([0, 0, 255]));
+        let mut image = Image::new(DynamicImage::ImageRgb8(pixels));
+        let geometry = CropGeometry::from_str(geometry).unwrap();
+        let background = Color::from_str(background).unwrap();
+        extent(
+            &mut image,
+            &geometry,
+            gravity,
+            &background,
+            &Limits::default(),
+        )
+        .unwrap();
+        image
+    }
+
+    #[test]
+    fn zero_size_is_rejected() {
+        let mut image = Image::new(DynamicImage::ImageRgb8(RgbImage::new(10, 10)));
+        let background = Color::from_str("white").unwrap();
+        for geometry in ["0x0", "0%", "10x0"] {
+            let geometry = CropGeometry::from_str(geometry).unwrap();
+            assert!(extent(
+                &mut image,
+                &geometry,
+                Gravity::None,
+                &background,
+                &Limits::default()
+            )
+            .is_err());
+        }
+    }
+
+    #[test]
+    fn huge_size_is_rejected() {
+        let mut image = Image::new(DynamicImage::ImageRgb8(RgbImage::new(10, 10)));
+        let geometry = CropGeometry::from_str("300000x300000").unwrap();
+        let result = extent(
+            &mut image,
+            &geometry,
+            Gravity::None,
+            &Color::WHITE,
+            &Limits::default(),
+        );
+        assert!(result.is_err());
+        assert_eq!((image.width(), image.height()), (10, 10));
+    }
+
+    #[test]
+    fn grows_around_the_gravity_point() {
+        let image = extended("120%x120%", Gravity::Center, "red");
+        assert_eq!((image.width(), image.height()), (12, 12));
+        assert_eq!(image.pixels.get_pixel(0, 0), Rgba([255, 0, 0, 255]));
+        assert_eq!(image.pixels.get_pixel(1, 1), Rgba([0, 0, 255, 255]));
+        assert_eq!(image.pixels.get_pixel(11, 11), Rgba([255, 0, 0, 255]));
+        assert_eq!(image.pixels.color(), ColorType::Rgb8);
+    }
+
+    #[test]
+    fn offsets_and_cropping() {
+        // the canvas starts 2 pixels to the right of the image, so 2 columns are cut off
+        let image = extended("10x12+2+0", Gravity::None, "white");
+        assert_eq!(image.pixels.get_pixel(7, 9), Rgba([0, 0, 255, 255]));
+        assert_eq!(image.pixels.get_pixel(8, 0), Rgba([255, 255, 255, 255]));
+        assert_eq!(image.pixels.get_pixel(0, 10), Rgba([255, 255, 255, 255]));
+        // with eastern gravity the offset counts from the right edge, pushing the image right
+        let image = extended("12x10+1+0", Gravity::East, "white");
+        assert_eq!(image.pixels.get_pixel(2, 0), Rgba([255, 255, 255, 255]));
+        assert_eq!(image.pixels.get_pixel(3, 0), Rgba([0, 0, 255, 255]));
+        assert_eq!(image.pixels.get_pixel(11, 0), Rgba([0, 0, 255, 255]));
+    }
+
+    #[test]
+    fn transparent_pixels_show_the_background() {
+        let pixels = RgbaImage::from_pixel(2, 2, Rgba([0, 0, 0, 0]));
+        let mut image = Image::new(DynamicImage::ImageRgba8(pixels));
+        let geometry = CropGeometry::from_str("3x3").unwrap();
+        extent(
+            &mut image,
+            &geometry,
+            Gravity::None,
+            &Color::WHITE,
+            &Limits::default(),
+        )
+        .unwrap();
+        assert!(image
+            .pixels
+            .to_rgba8()
+            .pixels()
+            .all(|p| *p == Rgba([255, 255, 255, 255])));
+    }
+}
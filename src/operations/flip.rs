@@ -0,0 +1,62 @@
+use image::metadata::Orientation;
+
+use crate::{error::MagickError, image::Image};
+
+/// Implements `-flip`: mirrors the image top-to-bottom.
+///
+/// The placement on the virtual canvas is mirrored too, so that frames of an animation
+/// still line up with each other afterwards.
+pub fn flip(image: &mut Image) -> Result<(), MagickError> {
+    if let Some(page) = &mut image.page {
+        page.flip_vertical(image.pixels.height());
+    }
+    image.pixels.apply_orientation(Orientation::FlipVertical);
+    Ok(())
+}
+
+/// Implements `-flop`: mirrors the image left-to-right.
+///
+/// The placement on the virtual canvas is mirrored too, so that frames of an animation
+/// still line up with each other afterwards.
+pub fn flop(image: &mut Image) -> Result<(), MagickError> {
+    if let Some(page) = &mut image.page {
+        page.flip_horizontal(image.pixels.width());
+    }
+    image.pixels.apply_orientation(Orientation::FlipHorizontal);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use image::DynamicImage;
+
+    use super::*;
+    use crate::image::PageGeometry;
+
+    fn image_on_canvas() -> Image {
+        let mut image = Image::new(DynamicImage::new_rgb8(4, 3));
+        image.page = Some(PageGeometry {
+            width: 20,
+            height: 10,
+            x: 2,
+            y: 1,
+        });
+        image
+    }
+
+    #[test]
+    fn flip_page() {
+        let mut image = image_on_canvas();
+        flip(&mut image).unwrap();
+        let page = image.page.unwrap();
+        assert_eq!((page.x, page.y), (2, 6));
+    }
+
+    #[test]
+    fn flop_page() {
+        let mut image = image_on_canvas();
+        flop(&mut image).unwrap();
+        let page = image.page.unwrap();
+        assert_eq!((page.x, page.y), (14, 1));
+    }
+}
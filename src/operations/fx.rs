@@ -0,0 +1,182 @@
+use image::{DynamicImage, Rgba, Rgba32FImage};
+
+use super::{colorize::promote_to_color, histogram::INTENSITY};
+use crate::{
+    arg_parsers::ChannelMask,
+    encoders::common::convert,
+    error::MagickError,
+    fx::{Context, Expr},
+    image::Image,
+    wm_err,
+};
+
+/// Implements `-fx`: computes every sample of the selected channels with the expression,
+/// such as `-fx '(u + v) / 2'` to average two images.
+///
+/// `u` is the image being changed and `v` the next one in the sequence, such as the channels
+/// split off by `-separate`. Samples are normalized to `[0, 1]`.
+///
+/// Unlike in imagemagick, every input file goes through the operations on its own,
+/// so `v` cannot refer to the next input file, and `convert a.png b.png -fx '(u+v)/2'` is an error.
+pub fn fx(
+    image: &mut Image,
+    others: &[Image],
+    expr: &Expr,
+    channels: ChannelMask,
+) -> Result<(), MagickError> {
+    if others.is_empty() && expr.uses_symbol("v") {
+        return Err(wm_err!(
+            "`v' in -fx needs a second image in the same sequence, such as from -separate; \
+             -fx across multiple input files is not supported"
+        ));
+    }
+    let sources: Vec<Rgba32FImage> = std::iter::once(&*image)
+        .chain(others)
+        .map(|image| image.pixels.to_rgba32f())
+        .collect();
+    let has_color = std::iter::once(&*image)
+        .chain(others)
+        .any(|image| image.pixels.color().has_color());
+    if has_color || channels.splits_colors() {
+        promote_to_color(&mut image.pixels);
+    }
+    let selected = channels.to_array();
+    let (width, height) = sources[0].dimensions();
+    let mut result = Rgba32FImage::new(width, height);
+    for (x, y, pixel) in result.enumerate_pixels_mut() {
+        let mut context = PixelContext {
+            sources: &sources,
+            x,
+            y,
+            channel: 0,
+        };
+        let mut values = sources[0].get_pixel(x, y).0;
+        for (channel, value) in values.iter_mut().enumerate() {
+            if selected[channel] {
+                context.channel = channel;
+                *value = expr.evaluate(&context)? as f32;
+            }
+        }
+        *pixel = Rgba(values);
+    }
+    image.pixels = convert(&DynamicImage::ImageRgba32F(result), image.pixels.color());
+    Ok(())
+}
+
+/// The symbols of `-fx` expressions for a single sample
+struct PixelContext<'a> {
+    /// `u` and `v`, as floating-point RGBA
+    sources: &'a [Rgba32FImage],
+    x: u32,
+    y: u32,
+    /// The channel being computed, which `u` and `v` stand for when no channel is given
+    channel: usize,
+}
+
+impl PixelContext<'_> {
+    fn sample(&self, image: usize, channel: usize) -> Option<f64> {
+        let source = self.sources.get(image)?;
+        // images of different sizes are read at the nearest edge
+        let x = self.x.min(source.width() - 1);
+        let y = self.y.min(source.height() - 1);
+        Some(f64::from(source.get_pixel(x, y)[channel]))
+    }
+}
+
+impl Context for PixelContext<'_> {
+    fn symbol(&self, name: &str, channel: Option<char>) -> Option<f64> {
+        let channel_index = |channel| match channel {
+            'r' | 'c' => Some(0),
+            'g' | 'm' => Some(1),
+            'b' | 'y' => Some(2),
+            'a' | 'k' => Some(3),
+            _ => None,
+        };
+        let (width, height) = self.sources[0].dimensions();
+        match (name, channel) {
+            ("u", None) => self.sample(0, self.channel),
+            ("v", None) => self.sample(1, self.channel),
+            ("u", Some(channel)) => self.sample(0, channel_index(channel)?),
+            ("v", Some(channel)) => self.sample(1, channel_index(channel)?),
+            ("r" | "g" | "b" | "a", None) => self.sample(0, channel_index(name.chars().next()?)?),
+            ("intensity", None) => (0..3)
+                .map(|channel| Some(self.sample(0, channel)? * INTENSITY[channel]))
+                .sum(),
+            ("i", None) => Some(f64::from(self.x)),
+            ("j", None) => Some(f64::from(self.y)),
+            ("w", None) => Some(f64::from(width)),
+            ("h", None) => Some(f64::from(height)),
+            ("n", None) => Some(self.sources.len() as f64),
+            ("t", None) => Some(0.0),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use image::{GrayImage, Luma, Rgb, RgbImage};
+
+    use super::*;
+
+    fn apply(pixels: DynamicImage, others: &[Image], expr: &str) -> DynamicImage {
+        let mut image = Image::new(pixels);
+        let expr = Expr::parse(expr).unwrap();
+        fx(&mut image, others, &expr, ChannelMask::default()).unwrap();
+        image.pixels
+    }
+
+    #[test]
+    fn pixel_values_and_coordinates() {
+        let pixels = RgbImage::from_pixel(4, 1, Rgb([0, 102, 255]));
+        let inverted = apply(DynamicImage::ImageRgb8(pixels.clone()), &[], "1 - u");
+        assert_eq!(
+            inverted.as_rgb8().unwrap().get_pixel(0, 0),
+            &Rgb([255, 153, 0])
+        );
+        let swapped = apply(DynamicImage::ImageRgb8(pixels), &[], "u.b");
+        assert_eq!(
+            swapped.as_rgb8().unwrap().get_pixel(0, 0),
+            &Rgb([255, 255, 255])
+        );
+
+        let pixels = GrayImage::new(4, 1);
+        let gradient = apply(DynamicImage::ImageLuma8(pixels), &[], "i / (w - 1)");
+        assert_eq!(gradient.as_luma8().unwrap().as_raw(), &[0, 85, 170, 255]);
+    }
+
+    #[test]
+    fn second_image() {
+        let pixels = GrayImage::from_pixel(1, 1, Luma([100]));
+        let other = Image::new(DynamicImage::ImageRgb8(RgbImage::from_pixel(
+            1,
+            1,
+            Rgb([200, 0, 100]),
+        )));
+        let average = apply(DynamicImage::ImageLuma8(pixels), &[other], "(u + v) / 2");
+        assert_eq!(
+            average.as_rgb8().unwrap().get_pixel(0, 0),
+            &Rgb([150, 50, 100])
+        );
+        let mut image = Image::new(DynamicImage::new_luma8(1, 1));
+        let missing = Expr::parse("v").unwrap();
+        let error = fx(&mut image, &[], &missing, ChannelMask::default()).unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("multiple input files is not supported"));
+    }
+
+    #[test]
+    fn selected_channels_only() {
+        let pixels = RgbImage::from_pixel(1, 1, Rgb([10, 20, 30]));
+        let mut image = Image::new(DynamicImage::ImageRgb8(pixels));
+        let red = ChannelMask::from_str("R").unwrap();
+        fx(&mut image, &[], &Expr::parse("0").unwrap(), red).unwrap();
+        assert_eq!(
+            image.pixels.as_rgb8().unwrap().get_pixel(0, 0),
+            &Rgb([0, 20, 30])
+        );
+    }
+}
@@ -0,0 +1,221 @@
+//! Operations that stretch the range of intensities based on a histogram of the image:
+//! `-normalize`, `-contrast-stretch` and `-linear-stretch`.
+//!
+//! Like imagemagick, the histogram is of the intensity of the pixels,
+//! and all color channels are stretched by the same amount so that colors do not shift.
+
+use image::{DynamicImage, ImageBuffer, Pixel, Primitive, Rgba32FImage};
+
+use crate::{
+    arg_parsers::StretchGeometry, error::MagickError, image::Image,
+    utils::channel_map::map_color_channels,
+};
+
+/// One bin per 16-bit value, like imagemagick's Q16 build
+const BINS: usize = 1 << 16;
+
+/// Luma coefficients of imagemagick's default pixel intensity
+pub(super) const INTENSITY: [f64; 3] = [0.298839, 0.586811, 0.114350];
+
+/// Implements `-normalize`: stretches the intensities so that 2% of the pixels become black
+/// and 1% become white
+pub fn normalize(image: &mut Image) -> Result<(), MagickError> {
+    let stretch = StretchGeometry {
+        black: 2.0,
+        white: 1.0,
+        percent: true,
+    };
+    contrast_stretch(image, &stretch)
+}
+
+/// Implements `-contrast-stretch`: more than the given number of darkest pixels become black,
+/// and more than the given number of brightest pixels become white
+pub fn contrast_stretch(image: &mut Image, stretch: &StretchGeometry) -> Result<(), MagickError> {
+    let histogram = intensity_histogram(&image.pixels);
+    let (black, white) = stretch.pixel_counts(pixel_count(&image.pixels));
+    let black_level = find_level(histogram.iter().enumerate(), |count| count > black);
+    let white_level = find_level(histogram.iter().enumerate().rev(), |count| count > white);
+    stretch_levels(image, black_level, white_level);
+    Ok(())
+}
+
+/// Implements `-linear-stretch`: at least the given number of darkest pixels become black,
+/// and at least the given number of brightest pixels become white
+pub fn linear_stretch(image: &mut Image, stretch: &StretchGeometry) -> Result<(), MagickError> {
+    let histogram = intensity_histogram(&image.pixels);
+    let (black, white) = stretch.pixel_counts(pixel_count(&image.pixels));
+    let black_level = find_level(histogram.iter().enumerate(), |count| count >= black);
+    let white_level = find_level(histogram.iter().enumerate().rev(), |count| count >= white);
+    stretch_levels(image, black_level, white_level);
+    Ok(())
+}
+
+/// Spreads the values of every color channel evenly over the whole range, like imagemagick's EqualizeImage()
+pub(super) fn equalize(pixels: &mut Rgba32FImage) {
+    let max = (BINS - 1) as f32;
+    let bin = |v: f32| (v * max).round().clamp(0.0, max) as usize;
+    for c in 0..3 {
+        let mut cumulative = vec![0u64; BINS];
+        for pixel in pixels.pixels() {
+            cumulative[bin(pixel[c])] += 1;
+        }
+        for i in 1..BINS {
+            cumulative[i] += cumulative[i - 1];
+        }
+        let black = cumulative[0] as f32;
+        let white = cumulative[BINS - 1] as f32;
+        if white <= black {
+            continue;
+        }
+        for pixel in pixels.pixels_mut() {
+            pixel[c] = (cumulative[bin(pixel[c])] as f32 - black) / (white - black);
+        }
+    }
+}
+
+/// Finds the first bin where the number of pixels up to and including it satisfies the condition
+fn find_level<'a>(
+    mut bins: impl Iterator<Item = (usize, &'a u64)>,
+    reached: impl Fn(f64) -> bool,
+) -> usize {
+    let mut total = 0;
+    let mut last = 0;
+    for (bin, &count) in &mut bins {
+        total += count;
+        last = bin;
+        if reached(total as f64) {
+            break;
+        }
+    }
+    last
+}
+
+/// Maps the black level to 0 and the white level to 1, clipping the values outside of them
+fn stretch_levels(image: &mut Image, black_level: usize, white_level: usize) {
+    // Nothing to stretch in images of a single color
+    if white_level <= black_level {
+        return;
+    }
+    let max = (BINS - 1) as f64;
+    let black = black_level as f64 / max;
+    let white = white_level as f64 / max;
+    map_color_channels(&mut image.pixels, |v| {
+        ((v - black) / (white - black)).clamp(0.0, 1.0)
+    });
+}
+
+fn pixel_count(image: &DynamicImage) -> u64 {
+    u64::from(image.width()) * u64::from(image.height())
+}
+
+/// Counts the pixels of every intensity, scaled to 16 bits regardless of the bit depth of the image
+fn intensity_histogram(image: &DynamicImage) -> Vec<u64> {
+    match image {
+        DynamicImage::ImageLuma8(buf) => histogram(buf),
+        DynamicImage::ImageLumaA8(buf) => histogram(buf),
+        DynamicImage::ImageRgb8(buf) => histogram(buf),
+        DynamicImage::ImageRgba8(buf) => histogram(buf),
+        DynamicImage::ImageLuma16(buf) => histogram(buf),
+        DynamicImage::ImageLumaA16(buf) => histogram(buf),
+        DynamicImage::ImageRgb16(buf) => histogram(buf),
+        DynamicImage::ImageRgba16(buf) => histogram(buf),
+        DynamicImage::ImageRgb32F(buf) => histogram(buf),
+        DynamicImage::ImageRgba32F(buf) => histogram(buf),
+        _ => unreachable!(),
+    }
+}
+
+fn histogram<P: Pixel>(buf: &ImageBuffer<P, Vec<P::Subpixel>>) -> Vec<u64>
+where
+    P::Subpixel: Into<f64>,
+{
+    let scale = (BINS - 1) as f64 / <P::Subpixel as Primitive>::DEFAULT_MAX_VALUE.into();
+    let color_channels = match P::CHANNEL_COUNT {
+        1 | 2 => 1,
+        _ => 3,
+    };
+    let mut histogram = vec![0; BINS];
+    for pixel in buf.pixels() {
+        let channels = &pixel.channels()[..color_channels];
+        let intensity: f64 = match channels {
+            [gray] => (*gray).into(),
+            _ => channels
+                .iter()
+                .zip(INTENSITY)
+                .map(|(&channel, weight)| channel.into() * weight)
+                .sum(),
+        };
+        let bin = (intensity * scale).round().clamp(0.0, (BINS - 1) as f64);
+        histogram[bin as usize] += 1;
+    }
+    histogram
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{GrayImage, ImageBuffer, Luma, Rgb, Rgb32FImage};
+
+    use super::*;
+    use std::str::FromStr;
+
+    /// A gray ramp from 50 to 149
+    fn ramp() -> Image {
+        let pixels = GrayImage::from_fn(100, 1, |x, _| Luma([50 + x as u8]));
+        Image::new(DynamicImage::ImageLuma8(pixels))
+    }
+
+    #[test]
+    fn histograms_of_every_depth() {
+        let gray = intensity_histogram(&ramp().pixels);
+        assert_eq!(gray.iter().sum::<u64>(), 100);
+        assert_eq!(gray[50 * 257], 1);
+
+        let pixels: ImageBuffer<Luma<u16>, Vec<u16>> = ImageBuffer::from_pixel(2, 2, Luma([1000]));
+        let deep = intensity_histogram(&DynamicImage::ImageLuma16(pixels));
+        assert_eq!(deep[1000], 4);
+
+        let pixels = Rgb32FImage::from_pixel(1, 1, Rgb([1.0, 1.0, 1.0]));
+        let float = intensity_histogram(&DynamicImage::ImageRgb32F(pixels));
+        assert_eq!(float[BINS - 1], 1);
+    }
+
+    #[test]
+    fn stretches() {
+        let mut image = ramp();
+        let stretch = StretchGeometry::from_str("0").unwrap();
+        contrast_stretch(&mut image, &stretch).unwrap();
+        let stretched = image.pixels.as_luma8().unwrap();
+        assert_eq!(stretched.get_pixel(0, 0), &Luma([0]));
+        assert_eq!(stretched.get_pixel(99, 0), &Luma([255]));
+
+        // with 2% black and 1% white, the 3 darkest and 2 brightest pixels are clipped
+        let mut image = ramp();
+        normalize(&mut image).unwrap();
+        let stretched = image.pixels.as_luma8().unwrap();
+        assert_eq!(stretched.get_pixel(2, 0), &Luma([0]));
+        assert!(stretched.get_pixel(3, 0).0[0] > 0);
+        assert_eq!(stretched.get_pixel(98, 0), &Luma([255]));
+        assert!(stretched.get_pixel(97, 0).0[0] < 255);
+
+        // while linear stretch clips exactly that many
+        let mut image = ramp();
+        let stretch = StretchGeometry::from_str("2x1%").unwrap();
+        linear_stretch(&mut image, &stretch).unwrap();
+        let stretched = image.pixels.as_luma8().unwrap();
+        assert_eq!(stretched.get_pixel(1, 0), &Luma([0]));
+        assert!(stretched.get_pixel(2, 0).0[0] > 0);
+        assert_eq!(stretched.get_pixel(99, 0), &Luma([255]));
+        assert!(stretched.get_pixel(98, 0).0[0] < 255);
+    }
+
+    #[test]
+    fn solid_color_is_unchanged() {
+        let pixels = GrayImage::from_pixel(3, 3, Luma([77]));
+        let mut image = Image::new(DynamicImage::ImageLuma8(pixels));
+        normalize(&mut image).unwrap();
+        assert_eq!(
+            image.pixels.as_luma8().unwrap().get_pixel(1, 1),
+            &Luma([77])
+        );
+    }
+}
@@ -0,0 +1,598 @@
+use std::cell::OnceCell;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::Write as _;
+use std::io::Write;
+use std::path::Path;
+
+use image::ExtendedColorType;
+
+use crate::{
+    arg_parsers::{Colorspace, Fuzz, IdentifyFormat, Token},
+    error::MagickError,
+    file_format::format_name,
+    fx::{Context, Expr},
+    image::{Image, QUANTUM_RANGE},
+    utils::{
+        depth::minimal_depth,
+        number_format::{format_g, format_size, DEFAULT_PRECISION},
+        statistics::{Channel, ChannelStatistics, ImageStatistics},
+    },
+    wm_try,
+};
+
+use super::trim::bounding_box;
+
+/// Implements `-identify`: writes information about the image to `output`, normally stdout.
+/// The output is determined by `-format` if specified, or by `-verbose`.
+/// Floats are printed with `precision` significant digits.
+/// `sequence_length` is the number of images in the sequence, reported by `%n`.
+pub fn identify(
+    image: &Image,
+    sequence_length: usize,
+    format: Option<&IdentifyFormat>,
+    verbose: bool,
+    precision: usize,
+    fuzz: &Fuzz,
+    output: &mut dyn Write,
+) -> Result<(), MagickError> {
+    let text = match format {
+        Some(format) => format_template(image, sequence_length, format, precision, fuzz),
+        None if verbose => verbose_info(image, precision),
+        None => default_line(image, precision),
+    };
+    wm_try!(output.write_all(text.as_bytes()));
+    Ok(())
+}
+
+/// The one-line summary printed by `identify` without any arguments, e.g.
+/// `rose.jpg JPEG 70x46 70x46+0+0 8-bit sRGB 6975B 0.000u 0:00.002`
+fn default_line(image: &Image, precision: usize) -> String {
+    let file_size = match file_size(image) {
+        0 => String::new(),
+        size => format!("{} ", format_size(size, true, precision)),
+    };
+    format!(
+        "{}{} {} {}x{} {} {}-bit {} {}{}\n",
+        image.filename.to_string_lossy(),
+        image
+            .scene
+            .map(|scene| format!("[{scene}]"))
+            .unwrap_or_default(),
+        format_name(image.format),
+        image.width(),
+        image.height(),
+        page_geometry(image),
+        depth(image.original_color_type),
+        colorspace(image),
+        file_size,
+        image.timer.format(),
+    )
+}
+
+fn verbose_info(image: &Image, precision: usize) -> String {
+    let stats = ImageStatistics::compute(&image.pixels);
+    let scale = depth_scale(image);
+    let mut out = String::new();
+    // Writing to a String cannot fail, so the results are ignored
+    let _ = writeln!(out, "Image:");
+    let _ = writeln!(out, "  Filename: {}", image.filename.to_string_lossy());
+    let _ = writeln!(out, "  Format: {}", format_name(image.format));
+    let _ = writeln!(out, "  Geometry: {}x{}+0+0", image.width(), image.height());
+    let _ = writeln!(out, "  Colorspace: {}", colorspace(image));
+    let _ = writeln!(out, "  Type: {}", image_type(image));
+    let _ = writeln!(out, "  Depth: {}-bit", depth(image.original_color_type));
+    let _ = writeln!(out, "  Page geometry: {}", page_geometry(image));
+    let _ = writeln!(out, "  Channel statistics:");
+    let _ = writeln!(
+        out,
+        "    Pixels: {}",
+        u64::from(image.width()) * u64::from(image.height())
+    );
+    for (channel, channel_stats) in &stats.channels {
+        let _ = writeln!(out, "    {}:", channel.name());
+        write_channel_statistics(&mut out, channel_stats, scale, precision);
+    }
+    let _ = writeln!(out, "  Image statistics:");
+    let _ = writeln!(out, "    Overall:");
+    write_channel_statistics(&mut out, &stats.overall, scale, precision);
+    let _ = writeln!(out, "  Gamma: {}", format_g(image.gamma, precision));
+    // imagemagick lists the properties sorted by name
+    let mut properties: BTreeMap<&str, &str> = image
+        .properties
+        .iter()
+        .map(|(key, value)| (key.as_str(), value.as_str()))
+        .collect();
+    if let Some(comment) = &image.comment {
+        properties.insert("comment", comment);
+    }
+    if let Some(label) = &image.label {
+        properties.insert("label", label);
+    }
+    if !properties.is_empty() {
+        let _ = writeln!(out, "  Properties:");
+        for (key, value) in properties {
+            let _ = writeln!(out, "    {key}: {value}");
+        }
+    }
+    out
+}
+
+fn write_channel_statistics(
+    out: &mut String,
+    stats: &ChannelStatistics,
+    scale: f64,
+    precision: usize,
+) {
+    let scaled = |value: f64| {
+        format!(
+            "{} ({})",
+            format_g(value * scale, precision),
+            format_g(value, precision)
+        )
+    };
+    let _ = writeln!(out, "      min: {}", scaled(stats.min));
+    let _ = writeln!(out, "      max: {}", scaled(stats.max));
+    let _ = writeln!(out, "      mean: {}", scaled(stats.mean));
+    let _ = writeln!(
+        out,
+        "      standard deviation: {}",
+        scaled(stats.standard_deviation)
+    );
+    let _ = writeln!(
+        out,
+        "      kurtosis: {}",
+        format_g(stats.kurtosis, precision)
+    );
+    let _ = writeln!(
+        out,
+        "      skewness: {}",
+        format_g(stats.skewness, precision)
+    );
+    let _ = writeln!(out, "      entropy: {}", format_g(stats.entropy, precision));
+}
+
+/// Expands the escapes in the template for `-comment` and `-label`, printing floats with the default precision
+pub(super) fn expand_template(
+    image: &Image,
+    sequence_length: usize,
+    template: &IdentifyFormat,
+) -> String {
+    format_template(
+        image,
+        sequence_length,
+        template,
+        DEFAULT_PRECISION,
+        &Fuzz::default(),
+    )
+}
+
+fn format_template(
+    image: &Image,
+    sequence_length: usize,
+    format: &IdentifyFormat,
+    precision: usize,
+    fuzz: &Fuzz,
+) -> String {
+    // Statistics are expensive, so only compute them if the template asks for them
+    let stats: OnceCell<ImageStatistics> = OnceCell::new();
+    let mut out = String::new();
+    for token in &format.template {
+        match token {
+            Token::Literal(text) => out.push_str(text),
+            Token::Escape(c) => {
+                out.push_str(&expand_escape(image, sequence_length, *c, precision, fuzz))
+            }
+            Token::Property(name) => out.push_str(&expand_property(image, &stats, name, precision)),
+        }
+    }
+    out
+}
+
+fn expand_escape(
+    image: &Image,
+    sequence_length: usize,
+    escape: char,
+    precision: usize,
+    fuzz: &Fuzz,
+) -> String {
+    let path = Path::new(&image.filename);
+    let lossy = |s: Option<&std::ffi::OsStr>| s.unwrap_or_default().to_string_lossy().into_owned();
+    match escape {
+        'd' => lossy(path.parent().map(|p| p.as_os_str())),
+        'e' => lossy(path.extension()),
+        'f' => lossy(path.file_name()),
+        'i' => image.filename.to_string_lossy().into_owned(),
+        't' => lossy(path.file_stem()),
+        'c' => image.comment.clone().unwrap_or_default(),
+        'l' => image.label.clone().unwrap_or_default(),
+        'm' => format_name(image.format).to_owned(),
+        'b' => format_size(file_size(image), false, precision),
+        'B' => file_size(image).to_string(),
+        'w' => image.width().to_string(),
+        'h' => image.height().to_string(),
+        'W' => page(image).0.to_string(),
+        'H' => page(image).1.to_string(),
+        'X' => format!("{:+}", page(image).2),
+        'Y' => format!("{:+}", page(image).3),
+        'g' => page_geometry(image),
+        // the depth the image was stored with, not the minimal one; see `%[bit-depth]`
+        'z' => depth(image.original_color_type).to_string(),
+        'n' => sequence_length.to_string(),
+        'M' => image.filename.to_string_lossy().into_owned(),
+        'P' => format!("{}x{}", page(image).0, page(image).1),
+        'O' => format!("{:+}{:+}", page(image).2, page(image).3),
+        'p' | 's' => image.scene.unwrap_or(0).to_string(),
+        // pixels are handled at 16 bits, like in the Q16 build of imagemagick
+        'q' => "16".to_owned(),
+        'k' => unique_colors(image).to_string(),
+        'r' => format!(
+            "DirectClass {}{}",
+            colorspace(image),
+            if image.pixels.color().has_alpha() {
+                " Alpha"
+            } else {
+                ""
+            }
+        ),
+        'A' => match image.pixels.color().has_alpha() {
+            true => "Blend".to_owned(),
+            false => "Undefined".to_owned(),
+        },
+        // in hundredths of a second, like in GIF
+        'T' => image
+            .delay
+            .map(|delay| {
+                let (numerator, denominator) = delay.numer_denom_ms();
+                (numerator / denominator.max(1) / 10).to_string()
+            })
+            .unwrap_or_else(|| "0".to_owned()),
+        '@' => trim_geometry(image, fuzz),
+        // imagemagick prints unknown escapes as-is
+        other => format!("%{other}"),
+    }
+}
+
+/// The number of distinct pixel values, counting the alpha channel
+fn unique_colors(image: &Image) -> usize {
+    let pixels = image.pixels.to_rgba16();
+    let colors: HashSet<[u16; 4]> = pixels.pixels().map(|pixel| pixel.0).collect();
+    colors.len()
+}
+
+/// Size of the file the image was read from in bytes, or 0 if it was not read from a file
+fn file_size(image: &Image) -> u64 {
+    image
+        .file_size
+        .or_else(|| std::fs::metadata(&image.filename).ok().map(|m| m.len()))
+        .unwrap_or(0)
+}
+
+fn expand_property(
+    image: &Image,
+    stats: &OnceCell<ImageStatistics>,
+    name: &str,
+    precision: usize,
+) -> String {
+    if let Some(value) = image.properties.get(&name.to_ascii_lowercase()) {
+        return value.clone();
+    }
+    match name {
+        "comment" => return image.comment.clone().unwrap_or_default(),
+        "label" => return image.label.clone().unwrap_or_default(),
+        _ => (),
+    }
+    if name == "bit-depth" {
+        // unlike `%z`, this is the depth actually needed to represent the pixels
+        return minimal_depth(&image.pixels).to_string();
+    }
+    if let Some(expression) = name.strip_prefix("fx:") {
+        return expand_fx(image, stats, expression.trim(), precision);
+    }
+    let stats = stats.get_or_init(|| ImageStatistics::compute(&image.pixels));
+    let overall = &stats.overall;
+    let value = match name {
+        // These are reported in the quantum range
+        "min" => overall.min * QUANTUM_RANGE,
+        "max" => overall.max * QUANTUM_RANGE,
+        "mean" => overall.mean * QUANTUM_RANGE,
+        "standard-deviation" => overall.standard_deviation * QUANTUM_RANGE,
+        // and these are unitless
+        "kurtosis" => overall.kurtosis,
+        "skewness" => overall.skewness,
+        "entropy" => overall.entropy,
+        // imagemagick prints nothing for unknown properties
+        _ => return String::new(),
+    };
+    format_g(value, precision)
+}
+
+/// Evaluates a `%[fx:...]` escape. Expressions that cannot be evaluated print nothing.
+fn expand_fx(
+    image: &Image,
+    stats: &OnceCell<ImageStatistics>,
+    expression: &str,
+    precision: usize,
+) -> String {
+    let context = FxContext { image, stats };
+    match Expr::parse(expression).and_then(|expr| expr.evaluate(&context)) {
+        Ok(value) => format_g(value, precision),
+        Err(_) => String::new(),
+    }
+}
+
+/// The image properties and statistics available to `%[fx:...]` escapes.
+/// Statistics are only computed if the expression refers to them.
+struct FxContext<'a> {
+    image: &'a Image,
+    stats: &'a OnceCell<ImageStatistics>,
+}
+
+impl Context for FxContext<'_> {
+    fn symbol(&self, name: &str, channel: Option<char>) -> Option<f64> {
+        let image = self.image;
+        match (name, channel) {
+            ("w", None) => return Some(f64::from(image.width())),
+            ("h", None) => return Some(f64::from(image.height())),
+            ("n", None) => return Some(1.0),
+            ("t", None) => return Some(image.scene.unwrap_or(0) as f64),
+            ("z", None) => return Some(f64::from(depth(image.original_color_type))),
+            _ => (),
+        }
+        // Statistics can be restricted to a single channel, e.g. `mean.r`
+        const STATISTICS: &[&str] = &[
+            "minima",
+            "maxima",
+            "mean",
+            "standard_deviation",
+            "kurtosis",
+            "skewness",
+            "entropy",
+        ];
+        if !STATISTICS.contains(&name) {
+            return None;
+        }
+        let all = self
+            .stats
+            .get_or_init(|| ImageStatistics::compute(&image.pixels));
+        let is_gray = !image.pixels.color().has_color();
+        let stats = match channel {
+            None => &all.overall,
+            // the color channels of a grayscale image are all the same gray channel
+            Some('r' | 'g' | 'b') if is_gray => all.channel(Channel::Gray)?,
+            Some('r') => all.channel(Channel::Red)?,
+            Some('g') => all.channel(Channel::Green)?,
+            Some('b') => all.channel(Channel::Blue)?,
+            Some('a') => all.channel(Channel::Alpha)?,
+            Some(_) => return None,
+        };
+        match name {
+            "minima" => Some(stats.min),
+            "maxima" => Some(stats.max),
+            "mean" => Some(stats.mean),
+            "standard_deviation" => Some(stats.standard_deviation),
+            "kurtosis" => Some(stats.kurtosis),
+            "skewness" => Some(stats.skewness),
+            "entropy" => Some(stats.entropy),
+            _ => None,
+        }
+    }
+}
+
+/// Returns canvas width, height, x offset and y offset
+fn page(image: &Image) -> (u32, u32, i64, i64) {
+    match image.page {
+        Some(page) => (page.width, page.height, page.x, page.y),
+        None => (image.width(), image.height(), 0, 0),
+    }
+}
+
+/// The area `-trim` would keep, as `WxH+X+Y`
+fn trim_geometry(image: &Image, fuzz: &Fuzz) -> String {
+    match bounding_box(&image.pixels, fuzz) {
+        Some((x, y, width, height)) => format!("{width}x{height}+{x}+{y}"),
+        None => "0x0+0+0".to_owned(),
+    }
+}
+
+fn page_geometry(image: &Image) -> String {
+    let (width, height, x, y) = page(image);
+    format!("{width}x{height}{x:+}{y:+}")
+}
+
+/// Bits per channel
+fn depth(color_type: ExtendedColorType) -> u16 {
+    color_type.bits_per_pixel() / u16::from(color_type.channel_count())
+}
+
+/// Scale of the image's depth, e.g. 255 for 8-bit images
+fn depth_scale(image: &Image) -> f64 {
+    match depth(image.original_color_type) {
+        // floating-point formats are scaled like 16-bit ones by imagemagick
+        0 | 32.. => QUANTUM_RANGE,
+        bits => ((1u64 << bits) - 1) as f64,
+    }
+}
+
+fn colorspace(image: &Image) -> &'static str {
+    if !image.colorspace.is_srgb() {
+        image.colorspace.name()
+    } else if image.pixels.color().has_color() {
+        "sRGB"
+    } else {
+        "Gray"
+    }
+}
+
+fn image_type(image: &Image) -> &'static str {
+    if image.colorspace == Colorspace::Cmyk {
+        return "ColorSeparation";
+    }
+    let color = image.pixels.color();
+    match (color.has_color(), color.has_alpha()) {
+        (false, false) => "Grayscale",
+        (false, true) => "GrayscaleAlpha",
+        (true, false) => "TrueColor",
+        (true, true) => "TrueColorAlpha",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use image::ImageFormat;
+
+    use crate::file_format::FileFormat;
+
+    use crate::utils::number_format::DEFAULT_PRECISION;
+
+    use image::{DynamicImage, GrayImage, Luma};
+
+    use super::*;
+
+    fn test_image() -> Image {
+        let pixels = GrayImage::from_fn(4, 2, |x, _| Luma([if x < 2 { 0 } else { 255 }]));
+        let mut image = Image::new(DynamicImage::ImageLuma8(pixels));
+        image.filename = "dir/test.png".into();
+        image.format = Some(FileFormat::Image(ImageFormat::Png));
+        image
+    }
+
+    fn format(image: &Image, template: &str) -> String {
+        format_template(
+            image,
+            1,
+            &IdentifyFormat::from_str(template).unwrap(),
+            DEFAULT_PRECISION,
+            &Fuzz::default(),
+        )
+    }
+
+    #[test]
+    fn writes_to_the_given_output() {
+        let image = test_image();
+        let template = IdentifyFormat::from_str("%wx%h\\n").unwrap();
+        let mut output = Vec::new();
+        for _ in 0..2 {
+            identify(
+                &image,
+                1,
+                Some(&template),
+                false,
+                DEFAULT_PRECISION,
+                &Fuzz::default(),
+                &mut output,
+            )
+            .unwrap();
+        }
+        assert_eq!(output, b"4x2\n4x2\n");
+    }
+
+    #[test]
+    fn default() {
+        let mut image = test_image();
+        image.file_size = Some(6975);
+        let line = default_line(&image, DEFAULT_PRECISION);
+        // the times vary from run to run
+        let (line, times) = line.split_at(line.rfind("B ").unwrap() + 2);
+        assert_eq!(line, "dir/test.png PNG 4x2 4x2+0+0 8-bit Gray 6975B ");
+        let (cpu_time, elapsed) = times.trim_end().split_once("u ").unwrap();
+        assert!(cpu_time.parse::<f64>().is_ok());
+        assert!(elapsed.starts_with("0:0"));
+        assert!(times.ends_with('\n'));
+    }
+
+    #[test]
+    fn filename_escapes() {
+        assert_eq!(
+            format(&test_image(), "%d|%f|%t|%e"),
+            "dir|test.png|test|png"
+        );
+    }
+
+    #[test]
+    fn statistics_escapes() {
+        let image = test_image();
+        assert_eq!(format(&image, "%[mean]"), "32767.5");
+        assert_eq!(format(&image, "%[fx:mean]"), "0.5");
+        assert_eq!(format(&image, "%[fx:maxima]"), "1");
+        assert_eq!(format(&image, "%[fx:mean.r]"), "0.5");
+        assert_eq!(format(&image, "%[fx:w > h ? 7 : 3]"), "7");
+        assert_eq!(format(&image, "%[fx:mean * 2 == maxima]"), "1");
+        assert_eq!(format(&image, "%[fx:(w+1)/2]"), "2.5");
+        assert_eq!(format(&image, "%[fx:nonsense +]"), "");
+    }
+
+    #[test]
+    fn image_escapes() {
+        let mut image = test_image();
+        image.scene = Some(2);
+        image.delay = Some(image::Delay::from_numer_denom_ms(250, 1));
+        assert_eq!(
+            format(&image, "%k %r %A %s %T %q"),
+            "2 DirectClass Gray Undefined 2 25 16"
+        );
+        assert_eq!(format(&image, "%P%O|%M"), "4x2+0+0|dir/test.png");
+    }
+
+    #[test]
+    fn sequence_length() {
+        let template = IdentifyFormat::from_str("%n").unwrap();
+        let image = test_image();
+        assert_eq!(format(&image, "%n"), "1");
+        assert_eq!(
+            format_template(&image, 3, &template, DEFAULT_PRECISION, &Fuzz::default()),
+            "3"
+        );
+    }
+
+    #[test]
+    fn depth_escapes() {
+        let mut image = test_image();
+        image.pixels = DynamicImage::ImageLuma16(image.pixels.to_luma16());
+        image.original_color_type = ExtendedColorType::L16;
+        // the pixels are only black and white, so one bit is enough to store them
+        assert_eq!(format(&image, "%z %[bit-depth]"), "16 1");
+    }
+
+    #[test]
+    fn precision() {
+        let image = test_image();
+        let template = IdentifyFormat::from_str("%[fx:standard_deviation]").unwrap();
+        assert_eq!(
+            format_template(&image, 1, &template, 3, &Fuzz::default()),
+            "0.535"
+        );
+        assert_eq!(
+            format_template(&image, 1, &template, 8, &Fuzz::default()),
+            "0.53452248"
+        );
+    }
+
+    #[test]
+    fn trim_bounding_box() {
+        let mut image = test_image();
+        image.pixels = DynamicImage::ImageLuma8(GrayImage::from_fn(6, 5, |x, y| {
+            Luma([if (1..3).contains(&x) && y == 2 {
+                0
+            } else {
+                255
+            }])
+        }));
+        assert_eq!(format(&image, "%@"), "2x1+1+2");
+        // the image itself is left alone
+        assert_eq!(format(&image, "%wx%h"), "6x5");
+    }
+
+    #[test]
+    fn file_size_escapes() {
+        let mut image = test_image();
+        image.file_size = Some(1_234_567);
+        assert_eq!(format(&image, "%b|%B"), "1.23457MB|1234567");
+        image.file_size = Some(270336);
+        assert_eq!(format(&image, "%b|%B"), "270336B|270336");
+        // not read from a file
+        image.file_size = None;
+        assert_eq!(format(&image, "%b|%B"), "0B|0");
+    }
+}
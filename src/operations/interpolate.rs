@@ -0,0 +1,132 @@
+//! Sampling images between pixel centers for operations that move pixels by fractional amounts
+
+use image::Rgba32FImage;
+
+use crate::arg_parsers::InterpolateMethod;
+
+/// Samples the image at the given point. Pixel centers are at half-integer coordinates.
+///
+/// Samples outside of the image are the background, so that the edges blend into it smoothly.
+pub(super) fn interpolate(
+    source: &Rgba32FImage,
+    method: InterpolateMethod,
+    x: f64,
+    y: f64,
+    fill: [f32; 4],
+) -> [f32; 4] {
+    let (xs, ys) = (taps(method, x), taps(method, y));
+    let mut pixel = [0.0; 4];
+    for &(y, y_weight) in &ys {
+        for &(x, x_weight) in &xs {
+            let inside =
+                x >= 0 && y >= 0 && x < i64::from(source.width()) && y < i64::from(source.height());
+            let sample = match inside {
+                true => source.get_pixel(x as u32, y as u32).0,
+                false => fill,
+            };
+            for (value, sample) in pixel.iter_mut().zip(sample) {
+                *value += sample * x_weight * y_weight;
+            }
+        }
+    }
+    pixel
+}
+
+/// The pixels along one axis that contribute to the sample, and their weights.
+/// All methods are separable, so the 2D weights are products of these.
+fn taps(method: InterpolateMethod, position: f64) -> Vec<(i64, f32)> {
+    // The pixel whose center is the closest one at or before the position
+    let before = (position - 0.5).floor();
+    let fraction = (position - 0.5 - before) as f32;
+    let before = before as i64;
+    let nearest = position.floor() as i64;
+    match method {
+        InterpolateMethod::Nearest => vec![(nearest, 1.0)],
+        InterpolateMethod::Integer => vec![(before, 1.0)],
+        InterpolateMethod::Bilinear => vec![(before, 1.0 - fraction), (before + 1, fraction)],
+        InterpolateMethod::Average => vec![(before, 0.5), (before + 1, 0.5)],
+        InterpolateMethod::Average9 => (nearest - 1..=nearest + 1)
+            .map(|x| (x, 1.0 / 3.0))
+            .collect(),
+        InterpolateMethod::Average16 => (before - 1..=before + 2).map(|x| (x, 0.25)).collect(),
+        InterpolateMethod::Catrom => cubic(before, fraction, catmull_rom),
+        InterpolateMethod::Spline => cubic(before, fraction, b_spline),
+    }
+}
+
+fn cubic(before: i64, fraction: f32, kernel: fn(f32) -> f32) -> Vec<(i64, f32)> {
+    (-1..=2)
+        .map(|offset| (before + offset, kernel(offset as f32 - fraction)))
+        .collect()
+}
+
+fn catmull_rom(distance: f32) -> f32 {
+    let x = distance.abs();
+    if x < 1.0 {
+        1.5 * x * x * x - 2.5 * x * x + 1.0
+    } else if x < 2.0 {
+        -0.5 * x * x * x + 2.5 * x * x - 4.0 * x + 2.0
+    } else {
+        0.0
+    }
+}
+
+fn b_spline(distance: f32) -> f32 {
+    let x = distance.abs();
+    if x < 1.0 {
+        (3.0 * x * x * x - 6.0 * x * x + 4.0) / 6.0
+    } else if x < 2.0 {
+        (2.0 - x).powi(3) / 6.0
+    } else {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use strum::VariantArray;
+
+    #[test]
+    fn weights_add_up_to_one() {
+        for &method in InterpolateMethod::VARIANTS {
+            for position in [0.0, 0.3, 0.5, 1.25, 7.9] {
+                let sum: f32 = taps(method, position).iter().map(|(_, w)| w).sum();
+                assert!((sum - 1.0).abs() < 1e-6, "{method:?} at {position}");
+            }
+        }
+    }
+
+    #[test]
+    fn methods() {
+        // a horizontal ramp from 0 to 0.3
+        let image =
+            Rgba32FImage::from_fn(4, 4, |x, _| image::Rgba([x as f32 / 10.0, 0.0, 0.0, 1.0]));
+        let fill = [1.0; 4];
+        let red = |method, x| interpolate(&image, method, x, 2.0, fill)[0];
+        // pixel centers are reproduced exactly by the interpolating methods
+        for method in [
+            InterpolateMethod::Bilinear,
+            InterpolateMethod::Catrom,
+            InterpolateMethod::Nearest,
+            InterpolateMethod::Integer,
+        ] {
+            assert!((red(method, 1.5) - 0.1).abs() < 1e-6, "{method:?}");
+        }
+        assert!((red(InterpolateMethod::Bilinear, 1.75) - 0.125).abs() < 1e-6);
+        assert!((red(InterpolateMethod::Catrom, 1.75) - 0.125).abs() < 1e-6);
+        assert_eq!(red(InterpolateMethod::Nearest, 1.75), 0.1);
+        assert_eq!(red(InterpolateMethod::Nearest, 2.25), 0.2);
+        assert_eq!(red(InterpolateMethod::Integer, 2.25), 0.1);
+        assert!((red(InterpolateMethod::Average, 2.25) - 0.15).abs() < 1e-6);
+        // the spline does not go through the pixels, but straight lines stay straight
+        assert!((red(InterpolateMethod::Spline, 1.5) - 0.1).abs() < 1e-6);
+        // and the averages are not affected by where exactly the point is
+        assert!((red(InterpolateMethod::Average16, 1.6) - 0.15).abs() < 1e-6);
+        // outside of the image is the background
+        assert_eq!(
+            interpolate(&image, InterpolateMethod::Nearest, 5.0, 0.5, fill),
+            fill
+        );
+    }
+}
@@ -0,0 +1,161 @@
+use crate::{
+    arg_parsers::BrightnessContrast,
+    error::MagickError,
+    image::Image,
+    utils::{channel_map::map_color_channels, statistics::ImageStatistics},
+};
+
+/// Implements `-auto-level`: stretches the color channels so that the darkest value becomes black
+/// and the brightest becomes white. All color channels are adjusted by the same amount.
+pub fn auto_level(image: &mut Image) -> Result<(), MagickError> {
+    let stats = ImageStatistics::compute(&image.pixels).overall;
+    let (min, max) = (stats.min, stats.max);
+    if max <= min {
+        return Ok(()); // a solid color, nothing to stretch
+    }
+    map_color_channels(&mut image.pixels, |v| (v - min) / (max - min));
+    Ok(())
+}
+
+/// Implements `-auto-gamma`: applies the gamma correction that brings the mean value to 50% gray.
+pub fn auto_gamma(image: &mut Image) -> Result<(), MagickError> {
+    let mean = ImageStatistics::compute(&image.pixels).overall.mean;
+    if mean <= 0.0 || mean >= 1.0 {
+        return Ok(()); // no gamma can move pure black or pure white
+    }
+    let gamma = mean.ln() / 0.5f64.ln();
+    map_color_channels(&mut image.pixels, |v| v.powf(1.0 / gamma));
+    Ok(())
+}
+
+/// Implements `-gamma`: raises every color sample to the power of 1/gamma,
+/// so gamma above 1 brightens the image. Gamma 0 makes it black, like in imagemagick.
+pub fn gamma(image: &mut Image, gamma: f64) -> Result<(), MagickError> {
+    if gamma == 1.0 {
+        return Ok(());
+    }
+    map_color_channels(&mut image.pixels, |v| match gamma {
+        0.0 => 0.0,
+        _ => v.max(0.0).powf(1.0 / gamma),
+    });
+    image.gamma *= gamma;
+    Ok(())
+}
+
+/// Implements `+gamma`: records the gamma of the image without changing the pixels
+pub fn set_gamma(image: &mut Image, gamma: f64) -> Result<(), MagickError> {
+    image.gamma = gamma;
+    Ok(())
+}
+
+/// Implements `-brightness-contrast` with the same linear mapping as imagemagick:
+/// contrast sets the slope, and brightness shifts the result up or down.
+pub fn brightness_contrast(
+    image: &mut Image,
+    settings: &BrightnessContrast,
+) -> Result<(), MagickError> {
+    let brightness = settings.brightness;
+    let slope = (std::f64::consts::PI * (settings.contrast / 100.0 + 1.0) / 4.0)
+        .tan()
+        .max(0.0);
+    let intercept = brightness / 100.0 + (100.0 - brightness) / 200.0 * (1.0 - slope);
+    map_color_channels(&mut image.pixels, |v| slope * v + intercept);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{DynamicImage, GrayImage, ImageBuffer, Luma, Rgb, Rgb32FImage, RgbImage};
+
+    use super::*;
+
+    #[test]
+    fn auto_level_stretches() {
+        let pixels = GrayImage::from_fn(2, 1, |x, _| Luma([if x == 0 { 50 } else { 150 }]));
+        let mut image = Image::new(DynamicImage::ImageLuma8(pixels));
+        auto_level(&mut image).unwrap();
+        assert_eq!(image.pixels.as_luma8().unwrap().as_raw(), &vec![0, 255]);
+    }
+
+    #[test]
+    fn auto_gamma_centers_mean() {
+        let pixels = GrayImage::from_pixel(2, 2, Luma([64]));
+        let mut image = Image::new(DynamicImage::ImageLuma8(pixels));
+        auto_gamma(&mut image).unwrap();
+        assert_eq!(
+            image.pixels.as_luma8().unwrap().get_pixel(0, 0),
+            &Luma([128])
+        );
+    }
+
+    #[test]
+    fn gamma_in_every_format() {
+        let pixels = RgbImage::from_pixel(1, 1, Rgb([64, 0, 255]));
+        let mut image = Image::new(DynamicImage::ImageRgb8(pixels));
+        gamma(&mut image, 2.0).unwrap();
+        // sqrt(64 / 255) * 255 = 127.75
+        assert_eq!(
+            image.pixels.as_rgb8().unwrap().get_pixel(0, 0),
+            &Rgb([128, 0, 255])
+        );
+        assert!((image.gamma - 2.0 / 2.2).abs() < 1e-9);
+
+        let pixels: ImageBuffer<Luma<u16>, Vec<u16>> = ImageBuffer::from_pixel(1, 1, Luma([16384]));
+        let mut image = Image::new(DynamicImage::ImageLuma16(pixels));
+        gamma(&mut image, 0.5).unwrap();
+        // (16384 / 65535)^2 * 65535 = 4096.06
+        assert_eq!(
+            image.pixels.as_luma16().unwrap().get_pixel(0, 0),
+            &Luma([4096])
+        );
+
+        let pixels = Rgb32FImage::from_pixel(1, 1, Rgb([0.25, 1.0, 0.0]));
+        let mut image = Image::new(DynamicImage::ImageRgb32F(pixels));
+        gamma(&mut image, 2.0).unwrap();
+        assert_eq!(
+            image.pixels.as_rgb32f().unwrap().get_pixel(0, 0),
+            &Rgb([0.5, 1.0, 0.0])
+        );
+
+        gamma(&mut image, 0.0).unwrap();
+        assert_eq!(
+            image.pixels.as_rgb32f().unwrap().get_pixel(0, 0),
+            &Rgb([0.0; 3])
+        );
+    }
+
+    #[test]
+    fn set_gamma_keeps_pixels() {
+        let pixels = GrayImage::from_pixel(1, 1, Luma([64]));
+        let mut image = Image::new(DynamicImage::ImageLuma8(pixels));
+        set_gamma(&mut image, 1.0).unwrap();
+        assert_eq!(image.gamma, 1.0);
+        assert_eq!(
+            image.pixels.as_luma8().unwrap().get_pixel(0, 0),
+            &Luma([64])
+        );
+    }
+
+    #[test]
+    fn brightness_and_contrast() {
+        let adjusted = |brightness, contrast| {
+            let pixels = GrayImage::from_fn(3, 1, |x, _| Luma([[0, 64, 255][x as usize]]));
+            let mut image = Image::new(DynamicImage::ImageLuma8(pixels));
+            let settings = BrightnessContrast {
+                brightness,
+                contrast,
+            };
+            brightness_contrast(&mut image, &settings).unwrap();
+            image.pixels.as_luma8().unwrap().as_raw().clone()
+        };
+        assert_eq!(adjusted(0.0, 0.0), vec![0, 64, 255]);
+        // brightness shifts everything by the percentage
+        assert_eq!(adjusted(20.0, 0.0), vec![51, 115, 255]);
+        assert_eq!(adjusted(100.0, 0.0), vec![255, 255, 255]);
+        assert_eq!(adjusted(-100.0, 0.0), vec![0, 0, 0]);
+        // contrast pivots around the middle
+        assert_eq!(adjusted(0.0, 50.0), vec![0, 0, 255]);
+        assert_eq!(adjusted(0.0, 20.0), vec![0, 40, 255]);
+        assert_eq!(adjusted(0.0, -100.0), vec![128, 128, 128]);
+    }
+}
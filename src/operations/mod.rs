@@ -1,30 +1,441 @@
+mod artistic;
+pub(crate) mod background;
+pub(crate) mod blur;
+mod channel_fx;
+mod colorize;
+mod colors;
+mod comment;
 mod crop;
+mod evaluate;
+mod extent;
+mod flip;
+mod fx;
+mod histogram;
+mod identify;
+mod interpolate;
+mod levels;
+mod modulate;
+mod negate;
+mod noise;
+mod opaque;
+mod ordered_dither;
+mod orient;
+mod posterize;
+mod raise;
 mod resize;
+mod rotate;
+mod separate;
+mod sepia;
+mod set;
+mod shadow;
+mod shave;
+mod shear;
+mod statistic;
+mod strip;
+mod threshold;
+mod trim;
+mod warp;
 
-use image::DynamicImage;
+use std::io::Write;
+
+use image::Rgba;
 
 use crate::{
-    arg_parsers::{LoadCropGeometry, ResizeGeometry},
+    arg_parsers::{
+        BlendPercent, BlurGeometry, BrightnessContrast, ChannelFx, ChannelMask, Color, Colorspace,
+        CropGeometry, DitherMethod, EvaluateOperator, Fuzz, Gravity, IdentifyFormat,
+        InterpolateMethod, LoadCropGeometry, Modulate, ModulateColorspace, MotionBlurGeometry,
+        NoiseType, OrderedDither, RaiseGeometry, ResizeGeometry, RotateGeometry, SepiaThreshold,
+        ShadowGeometry, ShearGeometry, StatisticType, StatisticWindow, StretchGeometry,
+        UnsharpGeometry, WaveGeometry,
+    },
     error::MagickError,
+    image::Image,
+    limits::Limits,
+    quantize::Quantizer,
 };
 
-#[derive(Debug, Copy, Clone, PartialEq)]
+pub use colors::palette_of;
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Operation {
     Resize(ResizeGeometry),
     Thumbnail(ResizeGeometry),
     Scale(ResizeGeometry),
     Sample(ResizeGeometry),
     CropOnLoad(LoadCropGeometry),
+    AutoOrient,
+    Flip,
+    Flop,
+    Rotate {
+        geometry: RotateGeometry,
+        background: Color,
+        interpolate: InterpolateMethod,
+    },
+    Shear {
+        geometry: ShearGeometry,
+        background: Color,
+        interpolate: InterpolateMethod,
+    },
+    Swirl {
+        degrees: f64,
+        background: Color,
+        interpolate: InterpolateMethod,
+    },
+    Implode {
+        amount: f64,
+        background: Color,
+        interpolate: InterpolateMethod,
+    },
+    Wave {
+        geometry: WaveGeometry,
+        background: Color,
+        interpolate: InterpolateMethod,
+    },
+    Crop {
+        geometry: CropGeometry,
+        gravity: Gravity,
+    },
+    Shave(CropGeometry),
+    Extent {
+        geometry: CropGeometry,
+        gravity: Gravity,
+        background: Color,
+    },
+    Trim(Fuzz),
+    Strip,
+    AutoLevel,
+    AutoGamma,
+    Gamma(f64),
+    /// `+gamma`: sets the gamma metadata of the image without changing the pixels
+    SetGamma(f64),
+    BrightnessContrast(BrightnessContrast),
+    Normalize,
+    ContrastStretch(StretchGeometry),
+    LinearStretch(StretchGeometry),
+    Modulate {
+        modulate: Modulate,
+        colorspace: ModulateColorspace,
+    },
+    Posterize {
+        levels: u32,
+        dither: DitherMethod,
+    },
+    OrderedDither {
+        dither: OrderedDither,
+        channels: ChannelMask,
+    },
+    /// `-colors`, along with the `-dither`, `-quantize` and `-treedepth` settings
+    Colors(Quantizer),
+    Unsharp(UnsharpGeometry),
+    AdaptiveBlur(BlurGeometry),
+    AdaptiveSharpen(BlurGeometry),
+    MotionBlur(MotionBlurGeometry),
+    RotationalBlur {
+        angle: f64,
+        interpolate: InterpolateMethod,
+    },
+    Statistic {
+        kind: StatisticType,
+        window: StatisticWindow,
+    },
+    Despeckle,
+    /// `-raise`, or `+raise` for a sunken edge
+    Raise {
+        geometry: RaiseGeometry,
+        raised: bool,
+    },
+    Shadow {
+        geometry: ShadowGeometry,
+        background: Color,
+    },
+    Edge(f64),
+    Emboss(BlurGeometry),
+    Charcoal(BlurGeometry),
+    Sketch(MotionBlurGeometry),
+    AddNoise {
+        kind: NoiseType,
+        attenuate: f64,
+        channels: ChannelMask,
+    },
+    /// `-monochrome`, along with the `-dither` setting
+    Monochrome(Quantizer),
+    /// `-remap`: the colors of the palette image, along with the `-dither` and `-quantize` settings
+    Remap {
+        palette: Vec<Rgba<u8>>,
+        quantizer: Quantizer,
+    },
+    SepiaTone(SepiaThreshold),
+    /// `-negate`, or `+negate` to only negate gray pixels
+    Negate {
+        gray_only: bool,
+        channels: ChannelMask,
+    },
+    Fx {
+        expr: crate::fx::Expr,
+        channels: ChannelMask,
+    },
+    Evaluate {
+        operator: EvaluateOperator,
+        /// Normalized to `[0, 1]` if the operator takes a sample value
+        constant: f64,
+        channels: ChannelMask,
+    },
+    ChannelFx(ChannelFx),
+    Colorspace(Colorspace),
+    /// Converts from other colorspaces and from the colors of the ICC profile to sRGB, dropping the profile
+    ConvertToSrgb,
+    /// `-opaque`, or `+opaque` to replace the colors that do not match
+    Opaque {
+        target: Color,
+        fill: Color,
+        fuzz: Fuzz,
+        invert: bool,
+    },
+    /// `-transparent`, or `+transparent` to make the colors that do not match transparent
+    Transparent {
+        target: Color,
+        fuzz: Fuzz,
+        invert: bool,
+    },
+    Colorize {
+        blend: BlendPercent,
+        fill: Color,
+    },
+    Tint {
+        weight: BlendPercent,
+        fill: Color,
+    },
+    Threshold(f64),
+    BlackThreshold(f64),
+    WhiteThreshold(f64),
+    /// `-comment`, or `+comment` to remove it
+    Comment(Option<IdentifyFormat>),
+    /// `-label`, or `+label` to remove it
+    Label(Option<IdentifyFormat>),
+    /// `-set`, or `+set` to remove the property
+    Set {
+        key: String,
+        value: Option<IdentifyFormat>,
+    },
+    Identify {
+        format: Option<IdentifyFormat>,
+        verbose: bool,
+        precision: usize,
+        /// For the trim bounding box reported by `%@`
+        fuzz: Fuzz,
+    },
+    /// `-separate`: turns every image into one image per channel
+    Separate,
+    #[cfg(feature = "plugins")]
+    Plugin(crate::plugin::Plugin),
 }
 
 impl Operation {
-    pub fn execute(&self, image: &mut DynamicImage) -> Result<(), MagickError> {
+    /// Applies the operation to a sequence of images.
+    /// Most operations transform every image on its own, but `-separate` changes how many there are,
+    /// and `-fx` combines all of them into the first one.
+    ///
+    /// Operations that print something, such as `-identify`, write it to `output`.
+    /// Templates for `-identify`, `-comment`, `-label` and `-set` see the length of the whole sequence as `%n`.
+    pub fn execute_sequence(
+        &self,
+        images: &mut Vec<Image>,
+        output: &mut dyn Write,
+        limits: &Limits,
+    ) -> Result<(), MagickError> {
+        match self {
+            Operation::Identify {
+                format,
+                verbose,
+                precision,
+                fuzz,
+            } => images.iter().try_for_each(|image| {
+                let length = images.len();
+                identify::identify(
+                    image,
+                    length,
+                    format.as_ref(),
+                    *verbose,
+                    *precision,
+                    fuzz,
+                    output,
+                )
+            }),
+            Operation::Comment(template) => {
+                let length = images.len();
+                images
+                    .iter_mut()
+                    .try_for_each(|image| comment::comment(image, length, template.as_ref()))
+            }
+            Operation::Label(template) => {
+                let length = images.len();
+                images
+                    .iter_mut()
+                    .try_for_each(|image| comment::label(image, length, template.as_ref()))
+            }
+            Operation::Set { key, value } => {
+                let length = images.len();
+                images
+                    .iter_mut()
+                    .try_for_each(|image| set::set(image, length, key, value.as_ref()))
+            }
+            Operation::Separate => {
+                *images = images.drain(..).flat_map(separate::separate).collect();
+                Ok(())
+            }
+            Operation::Fx { expr, channels } => {
+                let (first, others) = images.split_first_mut().expect("sequences are not empty");
+                fx::fx(first, others, expr, *channels)?;
+                images.truncate(1);
+                Ok(())
+            }
+            operation => images
+                .iter_mut()
+                .try_for_each(|image| operation.execute(image, limits)),
+        }
+    }
+
+    /// Applies an operation that transforms every image on its own.
+    /// Only called by [Operation::execute_sequence], which handles the operations that do not.
+    fn execute(&self, image: &mut Image, limits: &Limits) -> Result<(), MagickError> {
         match self {
-            Operation::Resize(geom) => resize::resize(image, geom),
-            Operation::Thumbnail(geom) => resize::thumbnail(image, geom),
-            Operation::Scale(geom) => resize::scale(image, geom),
-            Operation::Sample(geom) => resize::sample(image, geom),
+            Operation::Resize(geometry) => resize::resize(&mut image.pixels, geometry, limits),
+            Operation::Thumbnail(geometry) => {
+                resize::thumbnail(&mut image.pixels, geometry, limits)
+            }
+            Operation::Scale(geometry) => resize::scale(&mut image.pixels, geometry, limits),
+            Operation::Sample(geometry) => resize::sample(&mut image.pixels, geometry, limits),
             Operation::CropOnLoad(geom) => crop::crop_on_load(image, geom),
+            Operation::AutoOrient => orient::auto_orient(image),
+            Operation::Flip => flip::flip(image),
+            Operation::Flop => flip::flop(image),
+            Operation::Rotate {
+                geometry,
+                background,
+                interpolate,
+            } => rotate::rotate(image, geometry, background, *interpolate, limits),
+            Operation::Shear {
+                geometry,
+                background,
+                interpolate,
+            } => shear::shear(image, geometry, background, *interpolate, limits),
+            Operation::Swirl {
+                degrees,
+                background,
+                interpolate,
+            } => warp::swirl(image, *degrees, background, *interpolate),
+            Operation::Implode {
+                amount,
+                background,
+                interpolate,
+            } => warp::implode(image, *amount, background, *interpolate),
+            Operation::Wave {
+                geometry,
+                background,
+                interpolate,
+            } => warp::wave(image, geometry, background, *interpolate, limits),
+            Operation::Crop { geometry, gravity } => crop::crop(image, geometry, *gravity),
+            Operation::Shave(geometry) => shave::shave(image, geometry),
+            Operation::Extent {
+                geometry,
+                gravity,
+                background,
+            } => extent::extent(image, geometry, *gravity, background, limits),
+            Operation::Trim(fuzz) => trim::trim(image, fuzz),
+            Operation::Strip => strip::strip(image),
+            Operation::AutoLevel => levels::auto_level(image),
+            Operation::AutoGamma => levels::auto_gamma(image),
+            Operation::Gamma(gamma) => levels::gamma(image, *gamma),
+            Operation::SetGamma(gamma) => levels::set_gamma(image, *gamma),
+            Operation::Normalize => histogram::normalize(image),
+            Operation::ContrastStretch(stretch) => histogram::contrast_stretch(image, stretch),
+            Operation::LinearStretch(stretch) => histogram::linear_stretch(image, stretch),
+            Operation::BrightnessContrast(settings) => levels::brightness_contrast(image, settings),
+            Operation::Modulate {
+                modulate,
+                colorspace,
+            } => modulate::modulate(image, modulate, *colorspace),
+            Operation::Colors(quantizer) => colors::colors(image, quantizer),
+            Operation::Unsharp(geometry) => blur::unsharp(image, geometry),
+            Operation::AdaptiveBlur(geometry) => blur::adaptive(image, geometry, false),
+            Operation::AdaptiveSharpen(geometry) => blur::adaptive(image, geometry, true),
+            Operation::MotionBlur(geometry) => blur::motion_blur(image, geometry),
+            Operation::RotationalBlur { angle, interpolate } => {
+                blur::rotational_blur(image, *angle, *interpolate)
+            }
+            Operation::Statistic { kind, window } => statistic::statistic(image, *kind, *window),
+            Operation::Despeckle => statistic::despeckle(image),
+            Operation::Raise { geometry, raised } => raise::raise(image, geometry, *raised),
+            Operation::Shadow {
+                geometry,
+                background,
+            } => shadow::shadow(image, geometry, background, limits),
+            Operation::Edge(radius) => artistic::edge(image, *radius),
+            Operation::Emboss(geometry) => artistic::emboss(image, geometry),
+            Operation::Charcoal(geometry) => artistic::charcoal(image, geometry),
+            Operation::Sketch(geometry) => artistic::sketch(image, geometry),
+            Operation::AddNoise {
+                kind,
+                attenuate,
+                channels,
+            } => noise::add_noise(image, *kind, *attenuate, *channels),
+            Operation::Monochrome(quantizer) => colors::monochrome(image, quantizer),
+            Operation::Remap { palette, quantizer } => colors::remap(image, palette, quantizer),
+            Operation::OrderedDither { dither, channels } => {
+                ordered_dither::ordered_dither(image, dither, *channels)
+            }
+            Operation::Posterize { levels, dither } => {
+                posterize::posterize(image, *levels, *dither)
+            }
+            Operation::SepiaTone(threshold) => sepia::sepia_tone(image, threshold),
+            Operation::Negate {
+                gray_only,
+                channels,
+            } => negate::negate(image, *gray_only, *channels),
+            Operation::Fx { expr, channels } => fx::fx(image, &[], expr, *channels),
+            Operation::Evaluate {
+                operator,
+                constant,
+                channels,
+            } => evaluate::evaluate(image, *operator, *constant, *channels),
+            Operation::ChannelFx(fx) => channel_fx::channel_fx(image, fx),
+            Operation::ConvertToSrgb => {
+                crate::colorspace::transform(image, Colorspace::Srgb);
+                if image.icc_profile.is_some() {
+                    *image = crate::icc::to_srgb(image);
+                }
+                Ok(())
+            }
+            Operation::Colorspace(colorspace) => {
+                crate::colorspace::transform(image, *colorspace);
+                Ok(())
+            }
+            Operation::Opaque {
+                target,
+                fill,
+                fuzz,
+                invert,
+            } => opaque::opaque(image, target, fill, fuzz, *invert),
+            Operation::Transparent {
+                target,
+                fuzz,
+                invert,
+            } => opaque::transparent(image, target, fuzz, *invert),
+            Operation::Colorize { blend, fill } => colorize::colorize(image, blend, fill),
+            Operation::Tint { weight, fill } => colorize::tint(image, weight, fill),
+            Operation::Threshold(value) => threshold::threshold(image, *value),
+            Operation::BlackThreshold(value) => threshold::black_threshold(image, *value),
+            Operation::WhiteThreshold(value) => threshold::white_threshold(image, *value),
+            Operation::Identify { .. }
+            | Operation::Comment(_)
+            | Operation::Label(_)
+            | Operation::Set { .. } => {
+                unreachable!("templates are expanded by execute_sequence")
+            }
+            Operation::Separate => unreachable!("-separate is applied by execute_sequence"),
+            #[cfg(feature = "plugins")]
+            Operation::Plugin(plugin) => plugin.execute(image),
         }
     }
 }
@@ -0,0 +1,153 @@
+use crate::{
+    arg_parsers::{Modulate, ModulateColorspace},
+    colorspace::{hsl_to_rgb, hue_and_chroma, hue_to_rgb, rgb_to_hsl},
+    error::MagickError,
+    image::Image,
+    utils::channel_map::map_colors,
+};
+
+/// Luma coefficients imagemagick uses for the HCL colorspace
+const LUMA: [f64; 3] = [0.298839, 0.586811, 0.114350];
+
+/// Implements `-modulate`: scales the brightness and the saturation of the image,
+/// and turns the hue, in the HSL or HCL colorspace.
+pub fn modulate(
+    image: &mut Image,
+    modulate: &Modulate,
+    colorspace: ModulateColorspace,
+) -> Result<(), MagickError> {
+    let brightness = modulate.brightness / 100.0;
+    let saturation = modulate.saturation / 100.0;
+    // Hue is in turns, and 200% is half a turn
+    let hue_shift = (modulate.hue - 100.0) % 200.0 / 200.0;
+    match colorspace {
+        ModulateColorspace::Hsl => map_colors(&mut image.pixels, |rgb| {
+            let (hue, s, l) = rgb_to_hsl(rgb);
+            hsl_to_rgb(hue + hue_shift, s * saturation, l * brightness)
+        }),
+        ModulateColorspace::Hcl => map_colors(&mut image.pixels, |rgb| {
+            let (hue, chroma, luma) = rgb_to_hcl(rgb);
+            hcl_to_rgb(hue + hue_shift, chroma * saturation, luma * brightness)
+        }),
+    }
+    Ok(())
+}
+
+fn rgb_to_hcl(rgb: [f64; 3]) -> (f64, f64, f64) {
+    let (hue, chroma) = hue_and_chroma(rgb);
+    (hue, chroma, luma(rgb))
+}
+
+fn hcl_to_rgb(hue: f64, chroma: f64, luma_value: f64) -> [f64; 3] {
+    let rgb = hue_to_rgb(hue, chroma);
+    let offset = luma_value - luma(rgb);
+    rgb.map(|channel| channel + offset)
+}
+
+fn luma(rgb: [f64; 3]) -> f64 {
+    rgb.iter()
+        .zip(LUMA)
+        .map(|(channel, weight)| channel * weight)
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{DynamicImage, GrayImage, Luma, Rgb, RgbImage};
+
+    use super::*;
+
+    fn modulated(pixel: [u8; 3], brightness: f64, saturation: f64, hue: f64) -> [u8; 3] {
+        let pixels = RgbImage::from_pixel(1, 1, Rgb(pixel));
+        let mut image = Image::new(DynamicImage::ImageRgb8(pixels));
+        let settings = Modulate {
+            brightness,
+            saturation,
+            hue,
+        };
+        modulate(&mut image, &settings, ModulateColorspace::Hsl).unwrap();
+        image.pixels.as_rgb8().unwrap().get_pixel(0, 0).0
+    }
+
+    #[test]
+    fn round_trips() {
+        for rgb in [
+            [0.2, 0.4, 0.6],
+            [1.0, 0.0, 0.0],
+            [0.9, 0.8, 0.1],
+            [0.3, 0.3, 0.3],
+            [0.0, 0.5, 1.0],
+        ] {
+            let (h, s, l) = rgb_to_hsl(rgb);
+            let (h2, c, y) = rgb_to_hcl(rgb);
+            for (a, b) in rgb.iter().zip(hsl_to_rgb(h, s, l)) {
+                assert!((a - b).abs() < 1e-9, "{rgb:?}");
+            }
+            for (a, b) in rgb.iter().zip(hcl_to_rgb(h2, c, y)) {
+                assert!((a - b).abs() < 1e-9, "{rgb:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn hsl() {
+        // unchanged at 100%
+        assert_eq!(
+            modulated([10, 120, 240], 100.0, 100.0, 100.0),
+            [10, 120, 240]
+        );
+        // half a turn takes red to cyan, in either direction
+        assert_eq!(modulated([255, 0, 0], 100.0, 100.0, 200.0), [0, 255, 255]);
+        assert_eq!(modulated([255, 0, 0], 100.0, 100.0, 0.0), [0, 255, 255]);
+        // a third of a turn takes red to green
+        assert_eq!(
+            modulated([255, 0, 0], 100.0, 100.0, 100.0 + 200.0 / 3.0),
+            [0, 255, 0]
+        );
+        // no saturation is gray of the same lightness
+        assert_eq!(modulated([255, 0, 0], 100.0, 0.0, 100.0), [128, 128, 128]);
+        // lightness is scaled
+        assert_eq!(modulated([255, 0, 0], 50.0, 100.0, 100.0), [128, 0, 0]);
+        assert_eq!(modulated([255, 0, 0], 200.0, 100.0, 100.0), [255, 255, 255]);
+    }
+
+    #[test]
+    fn hcl_keeps_luma() {
+        let pixels = RgbImage::from_pixel(1, 1, Rgb([150, 100, 100]));
+        let mut image = Image::new(DynamicImage::ImageRgb8(pixels));
+        let before = luma(
+            image
+                .pixels
+                .as_rgb8()
+                .unwrap()
+                .get_pixel(0, 0)
+                .0
+                .map(f64::from),
+        );
+        let settings = Modulate {
+            brightness: 100.0,
+            saturation: 100.0,
+            hue: 150.0,
+        };
+        modulate(&mut image, &settings, ModulateColorspace::Hcl).unwrap();
+        let pixel = image.pixels.as_rgb8().unwrap().get_pixel(0, 0).0;
+        assert_ne!(pixel, [150, 100, 100]);
+        assert!((luma(pixel.map(f64::from)) - before).abs() < 1.0);
+    }
+
+    #[test]
+    fn gray_stays_gray() {
+        let pixels = GrayImage::from_pixel(1, 1, Luma([100]));
+        let mut image = Image::new(DynamicImage::ImageLuma8(pixels));
+        let settings = Modulate {
+            brightness: 150.0,
+            saturation: 200.0,
+            hue: 30.0,
+        };
+        modulate(&mut image, &settings, ModulateColorspace::Hsl).unwrap();
+        assert_eq!(
+            image.pixels.as_luma8().unwrap().get_pixel(0, 0),
+            &Luma([150])
+        );
+    }
+}
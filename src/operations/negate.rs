@@ -0,0 +1,63 @@
+use super::colorize::promote_to_color;
+use crate::{
+    arg_parsers::ChannelMask, error::MagickError, image::Image, utils::channel_map::map_rgba,
+};
+
+/// Implements `-negate` and `+negate`: replaces the samples of the selected channels with their
+/// inverse, such as white with black.
+///
+/// `+negate` only changes gray pixels, whose color channels are all equal, and leaves colored ones alone.
+pub fn negate(
+    image: &mut Image,
+    gray_only: bool,
+    channels: ChannelMask,
+) -> Result<(), MagickError> {
+    if channels.splits_colors() {
+        promote_to_color(&mut image.pixels);
+    }
+    let selected = channels.to_array();
+    map_rgba(&mut image.pixels, |rgba| {
+        if gray_only && !(rgba[0] == rgba[1] && rgba[1] == rgba[2]) {
+            return rgba;
+        }
+        std::array::from_fn(|i| match selected[i] {
+            true => 1.0 - rgba[i],
+            false => rgba[i],
+        })
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use image::{DynamicImage, Rgba, RgbaImage};
+
+    use super::*;
+
+    fn negated(gray_only: bool, channels: &str) -> Vec<u8> {
+        let pixels = RgbaImage::from_fn(2, 1, |x, _| match x {
+            0 => Rgba([10, 200, 30, 255]),
+            _ => Rgba([40, 40, 40, 100]),
+        });
+        let mut image = Image::new(DynamicImage::ImageRgba8(pixels));
+        let channels = ChannelMask::from_str(channels).unwrap();
+        negate(&mut image, gray_only, channels).unwrap();
+        image.pixels.into_rgba8().into_raw()
+    }
+
+    #[test]
+    fn negates_colors() {
+        assert_eq!(
+            negated(false, "RGB"),
+            [245, 55, 225, 255, 215, 215, 215, 100]
+        );
+        assert_eq!(negated(false, "A"), [10, 200, 30, 0, 40, 40, 40, 155]);
+    }
+
+    #[test]
+    fn plus_negate_leaves_colored_pixels_alone() {
+        assert_eq!(negated(true, "RGB"), [10, 200, 30, 255, 215, 215, 215, 100]);
+    }
+}
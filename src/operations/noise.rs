@@ -0,0 +1,166 @@
+//! `+noise`: adds random noise to the image, following imagemagick's GenerateDifferentialNoise().
+//! The opposite, `-noise`, is a `-statistic Nonpeak` filter.
+
+use std::f64::consts::PI;
+
+use image::{DynamicImage, Rgba32FImage};
+
+use crate::{
+    arg_parsers::{ChannelMask, NoiseType},
+    encoders::common::convert,
+    error::MagickError,
+    image::{Image, QUANTUM_RANGE},
+    utils::random::next_f64,
+};
+
+/// Implements `+noise`: adds noise of the given type to every selected channel,
+/// scaled by `-attenuate`.
+///
+/// Grayscale images get the same noise in every color channel, so that they stay gray.
+pub fn add_noise(
+    image: &mut Image,
+    kind: NoiseType,
+    attenuate: f64,
+    channels: ChannelMask,
+) -> Result<(), MagickError> {
+    let gray = !image.pixels.color().has_color() && !channels.splits_colors();
+    let selected = channels.to_array();
+    let mut pixels: Rgba32FImage = image.pixels.to_rgba32f();
+    for pixel in pixels.pixels_mut() {
+        let mut gray_value = None;
+        for (channel, value) in pixel.0.iter_mut().enumerate() {
+            if !selected[channel] {
+                continue;
+            }
+            let noisy = match (gray && channel < 3, gray_value) {
+                (true, Some(noisy)) => noisy,
+                _ => noise(kind, attenuate, f64::from(*value)) as f32,
+            };
+            if gray && channel < 3 {
+                gray_value = Some(noisy);
+            }
+            *value = noisy.clamp(0.0, 1.0);
+        }
+    }
+    image.pixels = convert(&DynamicImage::ImageRgba32F(pixels), image.pixels.color());
+    Ok(())
+}
+
+/// The sample with noise added, both normalized to `[0, 1]`.
+/// The constants are imagemagick's, in which the strength of the noise is defined.
+fn noise(kind: NoiseType, attenuate: f64, sample: f64) -> f64 {
+    let alpha = next_f64();
+    match kind {
+        NoiseType::Uniform => sample + attenuate * 0.015625 * (alpha - 0.5),
+        NoiseType::Gaussian => {
+            // Box-Muller transform, which needs a nonzero number
+            let alpha = if alpha < f64::EPSILON { 1.0 } else { alpha };
+            let gamma = (-2.0 * alpha.ln()).sqrt();
+            let (sin, cos) = (2.0 * PI * next_f64()).sin_cos();
+            let quantum = sample * QUANTUM_RANGE;
+            sample
+                + (quantum.sqrt() * attenuate * 0.015625 * gamma * cos) / QUANTUM_RANGE
+                + attenuate * 0.078125 * gamma * sin
+        }
+        NoiseType::Impulse => {
+            let sigma = attenuate * 0.1;
+            if alpha < sigma / 2.0 {
+                0.0
+            } else if alpha >= 1.0 - sigma / 2.0 {
+                1.0
+            } else {
+                sample
+            }
+        }
+        NoiseType::Laplacian => {
+            let sigma = attenuate * 0.0390625;
+            if alpha <= 0.5 {
+                match alpha <= f64::EPSILON {
+                    true => sample - 1.0,
+                    false => sample + sigma * (2.0 * alpha).ln(),
+                }
+            } else {
+                let beta = 1.0 - alpha;
+                match beta <= 0.5 * f64::EPSILON {
+                    true => sample + 1.0,
+                    false => sample - sigma * (2.0 * beta).ln(),
+                }
+            }
+        }
+        NoiseType::Multiplicative => {
+            let sigma = match alpha > f64::EPSILON {
+                true => (-2.0 * alpha.ln()).sqrt(),
+                false => 1.0,
+            };
+            let cos = (2.0 * PI * next_f64()).cos();
+            sample + sample * attenuate * 0.5 * sigma * cos / 2.0
+        }
+        NoiseType::Poisson => {
+            // Counts events until their combined probability drops below that of the sample's brightness
+            let sigma = attenuate * 12.5;
+            let poisson = (-sigma * sample).exp();
+            let mut alpha = alpha;
+            let mut events = 0u32;
+            while alpha > poisson {
+                alpha *= next_f64();
+                events += 1;
+            }
+            match sigma > f64::EPSILON {
+                true => f64::from(events) / sigma,
+                false => 0.0,
+            }
+        }
+        NoiseType::Random => attenuate * alpha,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{GrayImage, Luma, RgbImage};
+
+    use super::*;
+    use crate::utils::random::set_seed;
+
+    fn noisy(kind: NoiseType, pixels: DynamicImage) -> DynamicImage {
+        let mut image = Image::new(pixels);
+        add_noise(&mut image, kind, 1.0, ChannelMask::default()).unwrap();
+        image.pixels
+    }
+
+    #[test]
+    fn seed_makes_noise_reproducible() {
+        let pixels = DynamicImage::ImageRgb8(RgbImage::from_pixel(8, 8, image::Rgb([128; 3])));
+        set_seed(7);
+        let first = noisy(NoiseType::Gaussian, pixels.clone());
+        set_seed(7);
+        assert_eq!(noisy(NoiseType::Gaussian, pixels.clone()), first);
+        assert_ne!(first, pixels);
+    }
+
+    #[test]
+    fn gray_stays_gray() {
+        set_seed(1);
+        let pixels = DynamicImage::ImageLuma8(GrayImage::from_pixel(8, 8, Luma([128])));
+        let result = noisy(NoiseType::Laplacian, pixels);
+        assert!(result.as_luma8().is_some());
+    }
+
+    #[test]
+    fn impulse_is_black_or_white() {
+        set_seed(3);
+        let pixels = DynamicImage::ImageLuma8(GrayImage::from_pixel(64, 64, Luma([128])));
+        let result = noisy(NoiseType::Impulse, pixels);
+        let values = result.as_luma8().unwrap().as_raw();
+        assert!(values.iter().all(|&v| [0, 128, 255].contains(&v)));
+        // about 10% of the pixels are affected
+        let changed = values.iter().filter(|&&v| v != 128).count();
+        assert!((200..600).contains(&changed), "{changed}");
+    }
+
+    #[test]
+    fn poisson_keeps_black() {
+        for _ in 0..100 {
+            assert_eq!(noise(NoiseType::Poisson, 1.0, 0.0), 0.0);
+        }
+    }
+}
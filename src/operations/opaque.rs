@@ -0,0 +1,112 @@
+use super::colorize::{add_alpha, promote_to_color};
+use crate::{
+    arg_parsers::{Color, Fuzz},
+    error::MagickError,
+    image::Image,
+    utils::{channel_map::map_rgba, color_distance::is_similar},
+};
+
+/// Implements `-opaque`: replaces the pixels of the target color, within the `-fuzz` distance,
+/// with the fill color. `+opaque` replaces all the other pixels instead.
+pub fn opaque(
+    image: &mut Image,
+    target: &Color,
+    fill: &Color,
+    fuzz: &Fuzz,
+    invert: bool,
+) -> Result<(), MagickError> {
+    if !fill.is_gray() {
+        promote_to_color(&mut image.pixels);
+    }
+    if !fill.is_opaque() {
+        add_alpha(&mut image.pixels);
+    }
+    let target = to_array(target);
+    let fill = to_array(fill);
+    map_rgba(&mut image.pixels, |pixel| {
+        match is_similar(pixel, target, fuzz) != invert {
+            true => fill,
+            false => pixel,
+        }
+    });
+    Ok(())
+}
+
+/// Implements `-transparent`: makes the pixels of the target color, within the `-fuzz` distance,
+/// fully transparent. `+transparent` makes all the other pixels transparent instead.
+pub fn transparent(
+    image: &mut Image,
+    target: &Color,
+    fuzz: &Fuzz,
+    invert: bool,
+) -> Result<(), MagickError> {
+    add_alpha(&mut image.pixels);
+    let target = to_array(target);
+    map_rgba(&mut image.pixels, |[r, g, b, a]| {
+        match is_similar([r, g, b, a], target, fuzz) != invert {
+            true => [r, g, b, 0.0],
+            false => [r, g, b, a],
+        }
+    });
+    Ok(())
+}
+
+fn to_array(color: &Color) -> [f64; 4] {
+    color.to_array().map(f64::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use image::{DynamicImage, GrayImage, Luma, LumaA, Rgb, RgbImage, Rgba};
+
+    use super::*;
+
+    fn image() -> Image {
+        Image::new(DynamicImage::ImageRgb8(RgbImage::from_fn(3, 1, |x, _| {
+            Rgb([[255, 250, 0][x as usize], 0, 0])
+        })))
+    }
+
+    #[test]
+    fn opaque_replaces_matching_colors() {
+        let red = Color::from_str("red").unwrap();
+        let blue = Color::from_str("blue").unwrap();
+        let mut exact = image();
+        opaque(&mut exact, &red, &blue, &Fuzz::default(), false).unwrap();
+        let pixels = exact.pixels.as_rgb8().unwrap();
+        assert_eq!(pixels.get_pixel(0, 0), &Rgb([0, 0, 255]));
+        assert_eq!(pixels.get_pixel(1, 0), &Rgb([250, 0, 0]));
+
+        let mut fuzzy = image();
+        let fuzz = Fuzz::from_str("5%").unwrap();
+        opaque(&mut fuzzy, &red, &blue, &fuzz, false).unwrap();
+        let pixels = fuzzy.pixels.as_rgb8().unwrap();
+        assert_eq!(pixels.get_pixel(1, 0), &Rgb([0, 0, 255]));
+        assert_eq!(pixels.get_pixel(2, 0), &Rgb([0, 0, 0]));
+
+        let mut inverted = image();
+        opaque(&mut inverted, &red, &blue, &Fuzz::default(), true).unwrap();
+        let pixels = inverted.pixels.as_rgb8().unwrap();
+        assert_eq!(pixels.get_pixel(0, 0), &Rgb([255, 0, 0]));
+        assert_eq!(pixels.get_pixel(2, 0), &Rgb([0, 0, 255]));
+    }
+
+    #[test]
+    fn transparent_adds_alpha() {
+        let mut image = image();
+        let black = Color::BLACK;
+        transparent(&mut image, &black, &Fuzz::default(), false).unwrap();
+        let pixels = image.pixels.as_rgba8().unwrap();
+        assert_eq!(pixels.get_pixel(0, 0), &Rgba([255, 0, 0, 255]));
+        assert_eq!(pixels.get_pixel(2, 0), &Rgba([0, 0, 0, 0]));
+
+        let pixels = GrayImage::from_fn(2, 1, |x, _| Luma([x as u8 * 255]));
+        let mut image = Image::new(DynamicImage::ImageLuma8(pixels));
+        transparent(&mut image, &black, &Fuzz::default(), true).unwrap();
+        let pixels = image.pixels.as_luma_alpha8().unwrap();
+        assert_eq!(pixels.get_pixel(0, 0), &LumaA([0, 255]));
+        assert_eq!(pixels.get_pixel(1, 0), &LumaA([255, 0]));
+    }
+}
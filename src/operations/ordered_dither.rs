@@ -0,0 +1,81 @@
+use image::{DynamicImage, Rgba32FImage};
+
+use super::colorize::promote_to_color;
+use crate::{
+    arg_parsers::{ChannelMask, OrderedDither},
+    encoders::common::convert,
+    error::MagickError,
+    image::Image,
+};
+
+/// Implements `-ordered-dither`: reduces every selected channel to the given number of evenly
+/// spaced levels, choosing between the two closest ones by the threshold map tiled over the image.
+///
+/// Follows imagemagick's OrderedDitherImage(), which splits every step between two levels
+/// into as many parts as the map has thresholds.
+pub fn ordered_dither(
+    image: &mut Image,
+    dither: &OrderedDither,
+    channels: ChannelMask,
+) -> Result<(), MagickError> {
+    if channels.splits_colors() || dither.levels[..3].iter().any(|&l| l != dither.levels[0]) {
+        promote_to_color(&mut image.pixels);
+    }
+    let selected = channels.to_array();
+    let map = dither.map;
+    let parts = f64::from(map.divisor() - 1);
+    let mut pixels: Rgba32FImage = image.pixels.to_rgba32f();
+    for (x, y, pixel) in pixels.enumerate_pixels_mut() {
+        let threshold = map.level(x, y);
+        for (channel, value) in pixel.0.iter_mut().enumerate() {
+            if !selected[channel] {
+                continue;
+            }
+            let steps = f64::from(dither.levels[channel] - 1);
+            let position = (f64::from(*value).clamp(0.0, 1.0) * (steps * parts + 1.0)).floor();
+            let level = (position / parts).floor();
+            let part = position - level * parts;
+            let level = level + f64::from(u8::from(part >= f64::from(threshold)));
+            *value = (level / steps).min(1.0) as f32;
+        }
+    }
+    image.pixels = convert(&DynamicImage::ImageRgba32F(pixels), image.pixels.color());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use image::{GrayImage, Luma};
+
+    use super::*;
+
+    fn dithered(value: u8, map: &str) -> Vec<u8> {
+        let pixels = GrayImage::from_pixel(4, 4, Luma([value]));
+        let mut image = Image::new(DynamicImage::ImageLuma8(pixels));
+        let dither = OrderedDither::from_str(map).unwrap();
+        ordered_dither(&mut image, &dither, ChannelMask::default()).unwrap();
+        image.pixels.into_luma8().into_raw()
+    }
+
+    #[test]
+    fn patterns() {
+        let checks = dithered(128, "checks");
+        assert_eq!(&checks[..8], [255, 0, 255, 0, 0, 255, 0, 255]);
+        let white = dithered(128, "o4x4").iter().filter(|&&v| v == 255).count();
+        assert_eq!(white, 8);
+        // the extremes are never dithered
+        assert!(dithered(0, "o8x8").iter().all(|&v| v == 0));
+        assert!(dithered(255, "o8x8").iter().all(|&v| v == 255));
+    }
+
+    #[test]
+    fn levels() {
+        // halfway between 0 and 0.5 with 3 levels
+        let values = dithered(64, "o2x2,3");
+        assert!(values.iter().all(|&v| v == 0 || v == 128));
+        assert!(values.contains(&0) && values.contains(&128));
+        assert_eq!(dithered(128, "threshold,3"), vec![128; 16]);
+    }
+}
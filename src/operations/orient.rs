@@ -0,0 +1,110 @@
+use image::metadata::Orientation;
+
+use crate::{error::MagickError, image::Image};
+
+/// Implements `-auto-orient`: rotates and/or mirrors the pixels according to the EXIF orientation,
+/// so that the image is displayed correctly by viewers that ignore the orientation tag.
+pub fn auto_orient(image: &mut Image) -> Result<(), MagickError> {
+    let orientation = image.orientation;
+    if let Some(page) = &mut image.page {
+        page.apply_orientation(orientation, image.pixels.width(), image.pixels.height());
+    }
+    image.pixels.apply_orientation(orientation);
+    image.orientation = Orientation::NoTransforms;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{DynamicImage, GenericImageView, Rgb, RgbImage};
+
+    use super::*;
+    use crate::image::PageGeometry;
+
+    /// A 3x2 image where every pixel is different, so any wrong mirroring or rotation is detected
+    fn upright() -> DynamicImage {
+        let mut img = RgbImage::new(3, 2);
+        for (x, y, pixel) in img.enumerate_pixels_mut() {
+            *pixel = Rgb([x as u8, y as u8, (x + y * 3) as u8]);
+        }
+        DynamicImage::ImageRgb8(img)
+    }
+
+    /// Constructs the image as it would be stored on disk by a camera with the given EXIF orientation,
+    /// following the definitions from the EXIF specification directly
+    /// rather than reusing any of the transformations under test.
+    fn stored_with_orientation(exif_orientation: u8) -> DynamicImage {
+        let upright = upright();
+        let (w, h) = upright.dimensions();
+        let (stored_w, stored_h) = match exif_orientation {
+            1..=4 => (w, h),
+            5..=8 => (h, w),
+            _ => unreachable!(),
+        };
+        let mut stored = RgbImage::new(stored_w, stored_h);
+        for (a, b, pixel) in stored.enumerate_pixels_mut() {
+            let (x, y) = match exif_orientation {
+                1 => (a, b),
+                2 => (w - 1 - a, b),
+                3 => (w - 1 - a, h - 1 - b),
+                4 => (a, h - 1 - b),
+                5 => (b, a),
+                6 => (w - 1 - b, a),
+                7 => (w - 1 - b, h - 1 - a),
+                8 => (b, h - 1 - a),
+                _ => unreachable!(),
+            };
+            *pixel = upright.as_rgb8().unwrap().get_pixel(x, y).to_owned();
+        }
+        DynamicImage::ImageRgb8(stored)
+    }
+
+    #[test]
+    fn all_exif_orientations() {
+        for exif_orientation in 1..=8 {
+            let mut image = Image::new(stored_with_orientation(exif_orientation));
+            image.orientation = Orientation::from_exif(exif_orientation).unwrap();
+            auto_orient(&mut image).unwrap();
+            assert_eq!(
+                image.pixels,
+                upright(),
+                "EXIF orientation {exif_orientation}"
+            );
+            assert_eq!(image.orientation, Orientation::NoTransforms);
+        }
+    }
+
+    #[test]
+    fn page_geometry_follows_pixels() {
+        // Place the stored image on a larger canvas and check that the region it occupies
+        // ends up in the same place as the marker pixel we paint at its position
+        for exif_orientation in 1..=8 {
+            let stored = stored_with_orientation(exif_orientation);
+            let (x, y) = (1, 4);
+            let mut canvas = RgbImage::new(10, 7);
+            image::imageops::replace(&mut canvas, stored.as_rgb8().unwrap(), x, y);
+            let mut canvas = DynamicImage::ImageRgb8(canvas);
+
+            let mut image = Image::new(stored);
+            image.orientation = Orientation::from_exif(exif_orientation).unwrap();
+            image.page = Some(PageGeometry {
+                width: 10,
+                height: 7,
+                x,
+                y,
+            });
+            auto_orient(&mut image).unwrap();
+            canvas.apply_orientation(Orientation::from_exif(exif_orientation).unwrap());
+
+            let page = image.page.unwrap();
+            assert_eq!((page.width, page.height), canvas.dimensions());
+            let placed = canvas.crop_imm(
+                page.x as u32,
+                page.y as u32,
+                image.pixels.width(),
+                image.pixels.height(),
+            );
+            assert_eq!(placed, image.pixels, "EXIF orientation {exif_orientation}");
+        }
+    }
+}
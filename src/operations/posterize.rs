@@ -0,0 +1,118 @@
+use image::DynamicImage;
+
+use crate::{
+    arg_parsers::DitherMethod,
+    encoders::common::convert,
+    error::MagickError,
+    image::Image,
+    utils::{
+        channel_map::map_color_channels,
+        dither::{floyd_steinberg, riemersma},
+        pool,
+    },
+};
+
+/// Implements `-posterize`: reduces every color channel to the given number of evenly spaced levels,
+/// e.g. 2 levels leave only 0 and the maximum. Fewer than 2 levels make the image black.
+///
+/// With dithering the rounding error is spread to the neighbouring pixels to hide banding.
+pub fn posterize(image: &mut Image, levels: u32, dither: DitherMethod) -> Result<(), MagickError> {
+    if levels < 2 {
+        map_color_channels(&mut image.pixels, |_| 0.0);
+        return Ok(());
+    }
+    let steps = f64::from(levels - 1);
+    let round = move |v: f64| (v * steps).round() / steps;
+    match dither {
+        DitherMethod::None => map_color_channels(&mut image.pixels, round),
+        DitherMethod::FloydSteinberg => floyd_steinberg_posterize(image, round),
+        DitherMethod::Riemersma => riemersma_posterize(image, round),
+    }
+    Ok(())
+}
+
+/// Floyd–Steinberg error diffusion of every color channel to the closest level
+fn floyd_steinberg_posterize(image: &mut Image, round: impl Fn(f64) -> f64) {
+    let mut pixels = pool::to_rgba32f(&image.pixels);
+    let (width, height) = pixels.dimensions();
+    floyd_steinberg(width, height, |x, y, correction| {
+        let pixel = pixels.get_pixel_mut(x, y);
+        Some(std::array::from_fn(|c| {
+            let value = (pixel[c] + correction[c]).clamp(0.0, 1.0);
+            pixel[c] = round(f64::from(value)) as f32;
+            value - pixel[c]
+        }))
+    });
+    let pixels = DynamicImage::ImageRgba32F(pixels);
+    let posterized = convert(&pixels, image.pixels.color());
+    pool::recycle_image(pixels);
+    pool::recycle_image(std::mem::replace(&mut image.pixels, posterized));
+}
+
+/// Riemersma error diffusion of every color channel to the closest level
+fn riemersma_posterize(image: &mut Image, round: impl Fn(f64) -> f64) {
+    let mut pixels = pool::to_rgba32f(&image.pixels);
+    let (width, height) = pixels.dimensions();
+    riemersma(width, height, |x, y, correction| {
+        let pixel = pixels.get_pixel_mut(x, y);
+        Some(std::array::from_fn(|c| {
+            let original = pixel[c];
+            let value = (original + correction[c]).clamp(0.0, 1.0);
+            pixel[c] = round(f64::from(value)) as f32;
+            original - pixel[c]
+        }))
+    });
+    let pixels = DynamicImage::ImageRgba32F(pixels);
+    let posterized = convert(&pixels, image.pixels.color());
+    pool::recycle_image(pixels);
+    pool::recycle_image(std::mem::replace(&mut image.pixels, posterized));
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{GrayImage, Luma};
+
+    use super::*;
+
+    fn gradient() -> Image {
+        Image::new(DynamicImage::ImageLuma8(GrayImage::from_fn(
+            256,
+            4,
+            |x, _| Luma([x as u8]),
+        )))
+    }
+
+    #[test]
+    fn levels() {
+        let mut image = gradient();
+        posterize(&mut image, 3, DitherMethod::None).unwrap();
+        let row: Vec<u8> = (0..256)
+            .map(|x| image.pixels.as_luma8().unwrap()[(x, 0)][0])
+            .collect();
+        assert_eq!(row[0], 0);
+        assert_eq!(row[63], 0);
+        assert_eq!(row[64], 128);
+        assert_eq!(row[191], 128);
+        assert_eq!(row[192], 255);
+
+        let mut image = gradient();
+        posterize(&mut image, 1, DitherMethod::None).unwrap();
+        assert!(image.pixels.as_luma8().unwrap().pixels().all(|p| p[0] == 0));
+    }
+
+    #[test]
+    fn dithering_keeps_the_average() {
+        for dither in [DitherMethod::FloydSteinberg, DitherMethod::Riemersma] {
+            let mut image = Image::new(DynamicImage::ImageLuma8(GrayImage::from_pixel(
+                16,
+                16,
+                Luma([64]),
+            )));
+            posterize(&mut image, 2, dither).unwrap();
+            let pixels = image.pixels.as_luma8().unwrap();
+            assert!(pixels.pixels().all(|p| p[0] == 0 || p[0] == 255));
+            let mean = pixels.pixels().map(|p| f64::from(p[0])).sum::<f64>() / 256.0;
+            assert!((mean - 64.0).abs() < 8.0, "{dither:?}: {mean}");
+        }
+    }
+}
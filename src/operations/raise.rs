@@ -0,0 +1,114 @@
+use image::DynamicImage;
+
+use crate::{
+    arg_parsers::RaiseGeometry, encoders::common::convert, error::MagickError, image::Image, wm_err,
+};
+
+/// How much of the original color is kept in each part of the edges, as in imagemagick's RaiseImage()
+const HIGHLIGHT: f32 = 190.0 / 255.0;
+const ACCENTUATE: f32 = 135.0 / 255.0;
+const SHADOW: f32 = 190.0 / 255.0;
+const TROUGH: f32 = 135.0 / 255.0;
+
+/// Implements `-raise` and `+raise` like imagemagick's RaiseImage(): lightens the top and left edges
+/// and darkens the bottom and right ones so that the image looks like a raised button,
+/// or the other way around for a sunken one with `+raise`.
+///
+/// The corners where the edges meet are split diagonally.
+pub fn raise(image: &mut Image, geometry: &RaiseGeometry, raised: bool) -> Result<(), MagickError> {
+    let (width, height) = (image.width(), image.height());
+    let RaiseGeometry {
+        width: edge_width,
+        height: edge_height,
+    } = *geometry;
+    if width <= edge_width.saturating_mul(2) || height <= edge_height.saturating_mul(2) {
+        return Err(wm_err!(
+            "image size must exceed bevel width `{}x{}'",
+            edge_width,
+            edge_height
+        ));
+    }
+    let (light, dark) = match raised {
+        true => (1.0, 0.0),
+        false => (0.0, 1.0),
+    };
+    let mut pixels = image.pixels.to_rgba32f();
+    for (x, y, pixel) in pixels.enumerate_pixels_mut() {
+        let (factor, target) = if y < edge_height {
+            // The top edge, cut diagonally at both ends
+            if x < y {
+                (HIGHLIGHT, light)
+            } else if x < width - y {
+                (ACCENTUATE, light)
+            } else {
+                (SHADOW, dark)
+            }
+        } else if y < height - edge_height {
+            if x < edge_width {
+                (HIGHLIGHT, light)
+            } else if x < width - edge_width {
+                continue;
+            } else {
+                (SHADOW, dark)
+            }
+        } else {
+            // The bottom edge, cut diagonally at both ends
+            let from_bottom = height - y;
+            if x < from_bottom {
+                (HIGHLIGHT, light)
+            } else if x < width - from_bottom {
+                (TROUGH, dark)
+            } else {
+                (SHADOW, dark)
+            }
+        };
+        for c in 0..3 {
+            pixel[c] = pixel[c] * factor + target * (1.0 - factor);
+        }
+    }
+    image.pixels = convert(&DynamicImage::ImageRgba32F(pixels), image.pixels.color());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{GrayImage, Luma};
+
+    use super::*;
+
+    fn raised(raised: bool) -> GrayImage {
+        let pixels = GrayImage::from_pixel(10, 10, Luma([128]));
+        let mut image = Image::new(DynamicImage::ImageLuma8(pixels));
+        let geometry = RaiseGeometry {
+            width: 2,
+            height: 2,
+        };
+        raise(&mut image, &geometry, raised).unwrap();
+        image.pixels.to_luma8()
+    }
+
+    #[test]
+    fn edges_are_lit_from_the_top_left() {
+        let pixels = raised(true);
+        let value = |x, y| pixels.get_pixel(x, y)[0];
+        assert_eq!(value(5, 5), 128);
+        assert!(value(5, 0) > 128);
+        assert!(value(0, 5) > 128);
+        assert!(value(9, 5) < 128);
+        assert!(value(5, 9) < 128);
+
+        let sunken = raised(false);
+        assert!(sunken.get_pixel(5, 0)[0] < 128);
+        assert!(sunken.get_pixel(9, 5)[0] > 128);
+    }
+
+    #[test]
+    fn edges_must_fit() {
+        let mut image = Image::new(DynamicImage::ImageLuma8(GrayImage::new(4, 4)));
+        let geometry = RaiseGeometry {
+            width: 2,
+            height: 1,
+        };
+        assert!(raise(&mut image, &geometry, true).is_err());
+    }
+}
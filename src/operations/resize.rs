@@ -6,44 +6,107 @@ use pic_scale_safe::ResamplingFunction;
 use crate::{
     arg_parsers::{ResizeConstraint, ResizeGeometry},
     error::MagickError,
-    utils::fraction::Fraction,
+    limits::Limits,
+    utils::{channel_map::Sample, fraction::Fraction},
     wm_try,
 };
 
 use crate::arg_parsers::ResizeTarget;
 
 /// Implements `-resize` command
-pub fn resize(image: &mut DynamicImage, geometry: &ResizeGeometry) -> Result<(), MagickError> {
-    let (dst_width, dst_height) = compute_dimensions(image, geometry);
+pub fn resize(
+    image: &mut DynamicImage,
+    geometry: &ResizeGeometry,
+    limits: &Limits,
+) -> Result<(), MagickError> {
+    let (dst_width, dst_height) = checked_dimensions(image, geometry, limits)?;
     // The default algorithm is Sinc/Lancsoz3, a very high-quality one
     resize_impl(image, dst_width, dst_height, Default::default())
 }
 
-/// Implements `-scale` command
-pub fn scale(image: &mut DynamicImage, geometry: &ResizeGeometry) -> Result<(), MagickError> {
-    let (dst_width, dst_height) = compute_dimensions(image, geometry);
-    resize_impl(image, dst_width, dst_height, ResamplingFunction::Box)
+/// Implements `-scale` command.
+///
+/// Like imagemagick's ScaleImage(), every destination pixel is the average of the source area it covers.
+/// A box filter does not match that: when enlarging it picks the nearest source pixel
+/// instead of blending the two that a destination pixel straddles.
+pub fn scale(
+    image: &mut DynamicImage,
+    geometry: &ResizeGeometry,
+    limits: &Limits,
+) -> Result<(), MagickError> {
+    let (dst_width, dst_height) = checked_dimensions(image, geometry, limits)?;
+    if image.width() == dst_width && image.height() == dst_height {
+        return Ok(());
+    }
+    let has_alpha = image.color().has_alpha();
+    match image {
+        DynamicImage::ImageLuma8(buf) => *buf = average_area(buf, dst_width, dst_height, has_alpha),
+        DynamicImage::ImageLumaA8(buf) => {
+            *buf = average_area(buf, dst_width, dst_height, has_alpha)
+        }
+        DynamicImage::ImageRgb8(buf) => *buf = average_area(buf, dst_width, dst_height, has_alpha),
+        DynamicImage::ImageRgba8(buf) => *buf = average_area(buf, dst_width, dst_height, has_alpha),
+        DynamicImage::ImageLuma16(buf) => {
+            *buf = average_area(buf, dst_width, dst_height, has_alpha)
+        }
+        DynamicImage::ImageLumaA16(buf) => {
+            *buf = average_area(buf, dst_width, dst_height, has_alpha)
+        }
+        DynamicImage::ImageRgb16(buf) => *buf = average_area(buf, dst_width, dst_height, has_alpha),
+        DynamicImage::ImageRgba16(buf) => {
+            *buf = average_area(buf, dst_width, dst_height, has_alpha)
+        }
+        DynamicImage::ImageRgb32F(buf) => {
+            *buf = average_area(buf, dst_width, dst_height, has_alpha)
+        }
+        DynamicImage::ImageRgba32F(buf) => {
+            *buf = average_area(buf, dst_width, dst_height, has_alpha)
+        }
+        _ => unreachable!(),
+    }
+    Ok(())
 }
 
-/// Implements `-sample` command
-pub fn sample(image: &mut DynamicImage, geometry: &ResizeGeometry) -> Result<(), MagickError> {
-    let (dst_width, dst_height) = compute_dimensions(image, geometry);
-    resize_impl(image, dst_width, dst_height, ResamplingFunction::Nearest)
+/// Implements `-sample` command: picks the source pixel under the center of every destination pixel
+pub fn sample(
+    image: &mut DynamicImage,
+    geometry: &ResizeGeometry,
+    limits: &Limits,
+) -> Result<(), MagickError> {
+    let (dst_width, dst_height) = checked_dimensions(image, geometry, limits)?;
+    if dst_width <= image.width() && dst_height <= image.height() {
+        sample_in_place(image, dst_width, dst_height);
+        return Ok(());
+    }
+    match image {
+        DynamicImage::ImageLuma8(buf) => *buf = sample_buffer(buf, dst_width, dst_height),
+        DynamicImage::ImageLumaA8(buf) => *buf = sample_buffer(buf, dst_width, dst_height),
+        DynamicImage::ImageRgb8(buf) => *buf = sample_buffer(buf, dst_width, dst_height),
+        DynamicImage::ImageRgba8(buf) => *buf = sample_buffer(buf, dst_width, dst_height),
+        DynamicImage::ImageLuma16(buf) => *buf = sample_buffer(buf, dst_width, dst_height),
+        DynamicImage::ImageLumaA16(buf) => *buf = sample_buffer(buf, dst_width, dst_height),
+        DynamicImage::ImageRgb16(buf) => *buf = sample_buffer(buf, dst_width, dst_height),
+        DynamicImage::ImageRgba16(buf) => *buf = sample_buffer(buf, dst_width, dst_height),
+        DynamicImage::ImageRgb32F(buf) => *buf = sample_buffer(buf, dst_width, dst_height),
+        DynamicImage::ImageRgba32F(buf) => *buf = sample_buffer(buf, dst_width, dst_height),
+        _ => unreachable!(),
+    }
+    Ok(())
 }
 
 /// Implements `-thumbnail` command
-pub fn thumbnail(image: &mut DynamicImage, geometry: &ResizeGeometry) -> Result<(), MagickError> {
-    let (dst_width, dst_height) = compute_dimensions(image, geometry);
-
-    // imagemagick first downscales to 5x the target size with the cheap nearest-neighbor algorithm
-    let width = image.width().min(dst_width * 5);
-    let height = image.height().min(dst_height * 5);
-    wm_try!(resize_impl(
-        image,
-        width,
-        height,
-        ResamplingFunction::Nearest
-    ));
+pub fn thumbnail(
+    image: &mut DynamicImage,
+    geometry: &ResizeGeometry,
+    limits: &Limits,
+) -> Result<(), MagickError> {
+    let (dst_width, dst_height) = checked_dimensions(image, geometry, limits)?;
+
+    // imagemagick first downscales to 5x the target size with the cheap nearest-neighbor algorithm.
+    // We do that in place, without allocating an intermediate image.
+    let width = image.width().min(dst_width.saturating_mul(5));
+    let height = image.height().min(dst_height.saturating_mul(5));
+    sample_in_place(image, width, height);
 
     // now do the actual resize to the target dimensions
     resize_impl(image, dst_width, dst_height, Default::default())
@@ -131,6 +194,182 @@ fn resize_impl(
     Ok(())
 }
 
+/// Nearest-neighbor downscaling that reuses the memory of the image.
+///
+/// Every destination pixel takes the source pixel [`sample_index`] maps its center to,
+/// like imagemagick, and the same one [`sample_buffer`] picks when it allocates a new image.
+fn sample_in_place(image: &mut DynamicImage, dst_width: u32, dst_height: u32) {
+    if image.width() == dst_width && image.height() == dst_height {
+        return;
+    }
+    match image {
+        DynamicImage::ImageLuma8(buf) => shrink_buffer(buf, dst_width, dst_height),
+        DynamicImage::ImageLumaA8(buf) => shrink_buffer(buf, dst_width, dst_height),
+        DynamicImage::ImageRgb8(buf) => shrink_buffer(buf, dst_width, dst_height),
+        DynamicImage::ImageRgba8(buf) => shrink_buffer(buf, dst_width, dst_height),
+        DynamicImage::ImageLuma16(buf) => shrink_buffer(buf, dst_width, dst_height),
+        DynamicImage::ImageLumaA16(buf) => shrink_buffer(buf, dst_width, dst_height),
+        DynamicImage::ImageRgb16(buf) => shrink_buffer(buf, dst_width, dst_height),
+        DynamicImage::ImageRgba16(buf) => shrink_buffer(buf, dst_width, dst_height),
+        DynamicImage::ImageRgb32F(buf) => shrink_buffer(buf, dst_width, dst_height),
+        DynamicImage::ImageRgba32F(buf) => shrink_buffer(buf, dst_width, dst_height),
+        _ => unreachable!(),
+    }
+}
+
+/// Resizes so that every destination pixel is the average of the source pixels it covers,
+/// weighted by how much of each it covers. Colors are weighted by alpha, so that the color
+/// of transparent pixels does not bleed into visible ones.
+fn average_area<P: Pixel>(
+    buf: &ImageBuffer<P, Vec<P::Subpixel>>,
+    dst_width: u32,
+    dst_height: u32,
+    has_alpha: bool,
+) -> ImageBuffer<P, Vec<P::Subpixel>>
+where
+    P::Subpixel: Sample,
+{
+    let (src_width, src_height) = buf.dimensions();
+    let channels = usize::from(P::CHANNEL_COUNT);
+    let color_channels = if has_alpha { channels - 1 } else { channels };
+    let columns = area_weights(src_width, dst_width);
+    let rows = area_weights(src_height, dst_height);
+
+    // Scale the rows first, into premultiplied values
+    let src_row_length = src_width as usize * channels;
+    let dst_row_length = dst_width as usize * channels;
+    let mut scaled_rows = vec![0.0; dst_row_length * src_height as usize];
+    for (src_row, dst_row) in buf
+        .as_raw()
+        .chunks_exact(src_row_length)
+        .zip(scaled_rows.chunks_exact_mut(dst_row_length))
+    {
+        for (dst, weights) in dst_row.chunks_exact_mut(channels).zip(&columns) {
+            for &(x, weight) in weights {
+                let src = &src_row[x * channels..][..channels];
+                let alpha = if has_alpha {
+                    src[color_channels].to_unit()
+                } else {
+                    1.0
+                };
+                for c in 0..channels {
+                    let value = src[c].to_unit();
+                    let premultiplied = if c < color_channels {
+                        value * alpha
+                    } else {
+                        value
+                    };
+                    dst[c] += premultiplied * weight;
+                }
+            }
+        }
+    }
+
+    // Then the columns, going back to straight alpha
+    let mut samples = Vec::with_capacity(dst_row_length * dst_height as usize);
+    let mut pixel = vec![0.0; channels];
+    for weights in &rows {
+        for x in 0..dst_width as usize {
+            pixel.fill(0.0);
+            for &(y, weight) in weights {
+                let src = &scaled_rows[y * dst_row_length + x * channels..][..channels];
+                for (sum, value) in pixel.iter_mut().zip(src) {
+                    *sum += value * weight;
+                }
+            }
+            let alpha = if has_alpha {
+                pixel[color_channels]
+            } else {
+                1.0
+            };
+            for (c, value) in pixel.iter().enumerate() {
+                let straight = match c < color_channels {
+                    true if alpha > 0.0 => value / alpha,
+                    true => 0.0,
+                    false => *value,
+                };
+                samples.push(P::Subpixel::from_unit(straight));
+            }
+        }
+    }
+    ImageBuffer::from_raw(dst_width, dst_height, samples).expect("buffer has the right size")
+}
+
+/// For every destination pixel, the source pixels it covers along one dimension
+/// and how much of it each of them makes up
+fn area_weights(src_length: u32, dst_length: u32) -> Vec<Vec<(usize, f64)>> {
+    let scale = f64::from(src_length) / f64::from(dst_length);
+    (0..dst_length)
+        .map(|dst| {
+            let start = f64::from(dst) * scale;
+            let end = f64::from(dst + 1) * scale;
+            (start.floor() as u32..(end.ceil() as u32).min(src_length))
+                .filter_map(|src| {
+                    let covered = end.min(f64::from(src + 1)) - start.max(f64::from(src));
+                    (covered > 0.0).then_some((src as usize, covered / scale))
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn shrink_buffer<P: Pixel>(
+    buf: &mut ImageBuffer<P, Vec<P::Subpixel>>,
+    dst_width: u32,
+    dst_height: u32,
+) {
+    let (src_width, src_height) = buf.dimensions();
+    let channels = usize::from(P::CHANNEL_COUNT);
+    let mut samples = std::mem::take(buf).into_raw();
+    let pick = |dst: u32, src_length: u32, dst_length: u32| {
+        // Downscaling always reads at or after the pixel being written, so nothing is overwritten
+        // before it is read. This holds mathematically, and `max` makes sure of it despite rounding.
+        sample_index(dst, src_length, dst_length).max(dst as usize)
+    };
+    let columns: Vec<usize> = (0..dst_width)
+        .map(|x| pick(x, src_width, dst_width))
+        .collect();
+    let mut write = 0;
+    for y in 0..dst_height {
+        let row = pick(y, src_height, dst_height) * src_width as usize;
+        for &column in &columns {
+            let read = (row + column) * channels;
+            samples.copy_within(read..read + channels, write);
+            write += channels;
+        }
+    }
+    samples.truncate(write);
+    *buf = ImageBuffer::from_raw(dst_width, dst_height, samples).unwrap();
+}
+
+/// Like [`shrink_buffer`], but into a new buffer so that the image can also be enlarged
+fn sample_buffer<P: Pixel>(
+    buf: &ImageBuffer<P, Vec<P::Subpixel>>,
+    dst_width: u32,
+    dst_height: u32,
+) -> ImageBuffer<P, Vec<P::Subpixel>> {
+    let (src_width, src_height) = buf.dimensions();
+    let columns: Vec<u32> = (0..dst_width)
+        .map(|x| sample_index(x, src_width, dst_width) as u32)
+        .collect();
+    let rows: Vec<u32> = (0..dst_height)
+        .map(|y| sample_index(y, src_height, dst_height) as u32)
+        .collect();
+    ImageBuffer::from_fn(dst_width, dst_height, |x, y| {
+        *buf.get_pixel(columns[x as usize], rows[y as usize])
+    })
+}
+
+/// The source pixel that `-sample` picks for a destination pixel, along one dimension.
+///
+/// Matches imagemagick's SampleImage(): the center of the destination pixel is mapped to the source
+/// and the coordinate is truncated. Generic nearest-neighbor resizers subtract half a pixel
+/// before rounding instead, which picks a different pixel when shrinking by an even factor.
+fn sample_index(dst: u32, src_length: u32, dst_length: u32) -> usize {
+    let src = (f64::from(dst) + 0.5) * f64::from(src_length) / f64::from(dst_length);
+    (src as usize).min(src_length as usize - 1)
+}
+
 /// Return value indicates whether the image was in premultiplied by alpha
 #[must_use]
 fn premultiply_alpha_if_needed(image: &mut DynamicImage) -> bool {
@@ -247,6 +486,18 @@ fn has_constant_alpha_f32(img: &ImageBuffer<image::Rgba<f32>, Vec<f32>>) -> bool
         .all(|alpha| alpha == first_pixel_alpha)
 }
 
+/// Computes the size of the resized image and checks it against the limits before it is allocated,
+/// since a geometry like `1000000x1000000` is easy to type but impossible to fit into memory
+fn checked_dimensions(
+    image: &DynamicImage,
+    geometry: &ResizeGeometry,
+    limits: &Limits,
+) -> Result<(u32, u32), MagickError> {
+    let (width, height) = compute_dimensions(image, geometry);
+    let bytes_per_pixel = u64::from(image.color().bytes_per_pixel());
+    limits.check_new_image(f64::from(width), f64::from(height), bytes_per_pixel)
+}
+
 #[must_use]
 fn compute_dimensions(image: &DynamicImage, geometry: &ResizeGeometry) -> (u32, u32) {
     let constraint = geometry.constraint;
@@ -261,7 +512,8 @@ fn compute_dimensions(image: &DynamicImage, geometry: &ResizeGeometry) -> (u32,
                 let height = compute_dimension(image.height(), height, &constraint);
                 (width, height)
             } else {
-                preserve_aspect_ratio(image, width, height)
+                let size = preserve_aspect_ratio(image, width, height);
+                apply_constraint(image, size, constraint)
             }
         }
         ResizeTarget::Percentage { width, height } => {
@@ -278,21 +530,27 @@ fn compute_dimensions(image: &DynamicImage, geometry: &ResizeGeometry) -> (u32,
             }
         }
         ResizeTarget::Area(area) => {
-            let (width, height) = size_with_max_area(image.width(), image.height(), area);
-            match constraint {
-                ResizeConstraint::Unconstrained => (width, height),
-                ResizeConstraint::OnlyEnlarge => {
-                    (width.max(image.width()), height.max(image.height()))
-                }
-                ResizeConstraint::OnlyShrink => {
-                    (width.min(image.width()), height.min(image.height()))
-                }
-            }
+            let size = size_with_max_area(image.width(), image.height(), area);
+            apply_constraint(image, size, constraint)
         }
         ResizeTarget::FullyCover { width, height } => cover_area(image, width, height),
     }
 }
 
+#[must_use]
+/// Keeps the dimensions of the image where `>` or `<` forbid changing them, like imagemagick does
+fn apply_constraint(
+    image: &DynamicImage,
+    (width, height): (u32, u32),
+    constraint: ResizeConstraint,
+) -> (u32, u32) {
+    match constraint {
+        ResizeConstraint::Unconstrained => (width, height),
+        ResizeConstraint::OnlyEnlarge => (width.max(image.width()), height.max(image.height())),
+        ResizeConstraint::OnlyShrink => (width.min(image.width()), height.min(image.height())),
+    }
+}
+
 #[must_use]
 /// Scale the image dimension by the given percentage
 fn apply_percentage(size: u32, percentage: f64) -> u32 {
@@ -402,6 +660,7 @@ fn size_with_max_area(width: u32, height: u32, max_area: u64) -> (u32, u32) {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use quickcheck_macros::quickcheck;
     use std::str::FromStr;
 
     #[test]
@@ -463,6 +722,15 @@ mod tests {
         assert_eq!((128, 200), compute_dimensions(&image, &geometry));
     }
 
+    #[test]
+    fn only_shrink_keeps_small_images() {
+        let geometry = ResizeGeometry::from_str("400x400>").unwrap();
+        let small = DynamicImage::new_rgb8(64, 48);
+        assert_eq!((64, 48), compute_dimensions(&small, &geometry));
+        let large = DynamicImage::new_rgb8(800, 600);
+        assert_eq!((400, 300), compute_dimensions(&large, &geometry));
+    }
+
     #[test]
     fn percentage() {
         let image = DynamicImage::new_rgb8(800, 600);
@@ -545,4 +813,143 @@ mod tests {
         let geometry = ResizeGeometry::from_str("100^").unwrap();
         assert_eq!((100, 200), compute_dimensions(&image, &geometry));
     }
+
+    #[test]
+    fn scale_averages_covered_area() {
+        let scale_to = |pixels: &image::GrayImage, geometry: &str| {
+            let mut image = DynamicImage::ImageLuma8(pixels.clone());
+            scale(
+                &mut image,
+                &ResizeGeometry::from_str(geometry).unwrap(),
+                &Limits::default(),
+            )
+            .unwrap();
+            image.into_luma8().into_raw()
+        };
+        let pixels = image::GrayImage::from_raw(2, 1, vec![0, 240]).unwrap();
+        // enlarging by a whole factor replicates pixels
+        assert_eq!(scale_to(&pixels, "4x1!"), [0, 0, 240, 240]);
+        // the middle pixel straddles both source pixels
+        assert_eq!(scale_to(&pixels, "3x1!"), [0, 120, 240]);
+
+        let pixels = image::GrayImage::from_raw(3, 1, vec![0, 90, 240]).unwrap();
+        assert_eq!(scale_to(&pixels, "1x1!"), [110]);
+        // the middle source pixel is split between both destination pixels
+        assert_eq!(scale_to(&pixels, "2x1!"), [30, 190]);
+    }
+
+    #[test]
+    fn scale_weights_colors_by_alpha() {
+        let pixels = image::RgbaImage::from_raw(2, 1, vec![255, 0, 0, 255, 0, 255, 0, 0]).unwrap();
+        let mut image = DynamicImage::ImageRgba8(pixels);
+        scale(
+            &mut image,
+            &ResizeGeometry::from_str("1x1!").unwrap(),
+            &Limits::default(),
+        )
+        .unwrap();
+        assert_eq!(image.into_rgba8().into_raw(), [255, 0, 0, 128]);
+    }
+
+    #[test]
+    fn sample_grid_matches_imagemagick() {
+        // shrinking by an even factor picks the second pixel of every pair, not the first
+        assert_eq!(sample_index(0, 2, 1), 1);
+        assert_eq!(
+            (0..2).map(|x| sample_index(x, 4, 2)).collect::<Vec<_>>(),
+            [1, 3]
+        );
+        assert_eq!(
+            (0..2).map(|x| sample_index(x, 3, 2)).collect::<Vec<_>>(),
+            [0, 2]
+        );
+        assert_eq!(
+            (0..3).map(|x| sample_index(x, 2, 3)).collect::<Vec<_>>(),
+            [0, 1, 1]
+        );
+        // imagemagick's formula, evaluated the same way for every combination of small sizes
+        for src in 1..=64u32 {
+            for dst in 1..=64u32 {
+                for x in 0..dst {
+                    let expected = ((x as f64 + 0.5) * src as f64 / dst as f64) as usize;
+                    assert_eq!(sample_index(x, src, dst), expected, "{x} of {src} to {dst}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn sample_in_place_matches_allocated() {
+        for (src, dst) in [
+            ((37, 23), (5, 4)),
+            ((100, 10), (33, 10)),
+            ((7, 50), (7, 3)),
+            ((1001, 999), (1000, 998)),
+        ] {
+            let pixels = image::GrayImage::from_fn(src.0, src.1, |x, y| {
+                image::Luma([(x * 7 + y * 13) as u8])
+            });
+            let mut in_place = DynamicImage::ImageLuma8(pixels.clone());
+            sample_in_place(&mut in_place, dst.0, dst.1);
+            let allocated = sample_buffer(&pixels, dst.0, dst.1);
+            assert_eq!(
+                in_place.as_luma8().unwrap(),
+                &allocated,
+                "{src:?} to {dst:?}"
+            );
+        }
+        // with several channels
+        let pixels =
+            image::Rgba32FImage::from_fn(9, 9, |x, y| image::Rgba([x as f32, y as f32, 0.0, 1.0]));
+        let mut in_place = DynamicImage::ImageRgba32F(pixels.clone());
+        sample_in_place(&mut in_place, 4, 2);
+        assert_eq!(
+            in_place.as_rgba32f().unwrap(),
+            &sample_buffer(&pixels, 4, 2)
+        );
+    }
+
+    /// Every destination pixel is exactly the one imagemagick's SampleImage() picks,
+    /// whether the image is shrunk in place or enlarged into a new buffer
+    #[quickcheck]
+    fn sample_is_pixel_identical_to_imagemagick(src: (u16, u16), dst: (u16, u16)) -> bool {
+        let size = |size: u16| u32::from(size % 300) + 1;
+        let (src_width, src_height) = (size(src.0), size(src.1));
+        let (dst_width, dst_height) = (size(dst.0), size(dst.1));
+        // every pixel holds its own coordinates, so it can be told which one was picked
+        let pixels = image::ImageBuffer::from_fn(src_width, src_height, |x, y| {
+            image::Rgb([x as u16, y as u16, 0])
+        });
+        let mut image = DynamicImage::ImageRgb16(pixels);
+        let geometry = ResizeGeometry::from_str(&format!("{dst_width}x{dst_height}!")).unwrap();
+        sample(&mut image, &geometry, &Limits::default()).unwrap();
+        // the center of the destination pixel mapped to the source and truncated,
+        // computed in integers so that no rounding can hide an off-by-one
+        let expected = |dst: u32, src_length: u32, dst_length: u32| {
+            ((2 * u64::from(dst) + 1) * u64::from(src_length) / (2 * u64::from(dst_length))) as u16
+        };
+        let image = image.into_rgb16();
+        image.dimensions() == (dst_width, dst_height)
+            && image.enumerate_pixels().all(|(x, y, pixel)| {
+                pixel.0
+                    == [
+                        expected(x, src_width, dst_width),
+                        expected(y, src_height, dst_height),
+                        0,
+                    ]
+            })
+    }
+
+    #[test]
+    fn sample_enlarges() {
+        let pixels = image::GrayImage::from_raw(2, 1, vec![0, 240]).unwrap();
+        let mut image = DynamicImage::ImageLuma8(pixels);
+        sample(
+            &mut image,
+            &ResizeGeometry::from_str("3x2!").unwrap(),
+            &Limits::default(),
+        )
+        .unwrap();
+        assert_eq!(image.into_luma8().into_raw(), [0, 240, 240, 0, 240, 240]);
+    }
 }
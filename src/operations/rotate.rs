@@ -0,0 +1,166 @@
+use image::metadata::Orientation;
+
+use super::{background::set_pixels, interpolate::interpolate};
+use crate::{
+    arg_parsers::{Color, InterpolateMethod, RotateGeometry},
+    error::MagickError,
+    image::Image,
+    limits::Limits,
+    utils::pool,
+};
+
+/// Implements `-rotate`: rotates the image clockwise by the given angle.
+///
+/// Multiples of 90 degrees are lossless. Any other angle enlarges the image to fit the rotated one,
+/// samples it with the `-interpolate` method and fills the corners with the background color.
+/// The enlarged size is checked against the limits first.
+pub fn rotate(
+    image: &mut Image,
+    geometry: &RotateGeometry,
+    background: &Color,
+    method: InterpolateMethod,
+    limits: &Limits,
+) -> Result<(), MagickError> {
+    if !geometry.applies_to(image.width(), image.height()) {
+        return Ok(());
+    }
+    let degrees = geometry.degrees.rem_euclid(360.0);
+    let orientation = match degrees {
+        0.0 => return Ok(()),
+        90.0 => Orientation::Rotate90,
+        180.0 => Orientation::Rotate180,
+        270.0 => Orientation::Rotate270,
+        _ => return rotate_arbitrary(image, degrees, background, method, limits),
+    };
+    if let Some(page) = &mut image.page {
+        page.apply_orientation(orientation, image.pixels.width(), image.pixels.height());
+    }
+    image.pixels.apply_orientation(orientation);
+    Ok(())
+}
+
+fn rotate_arbitrary(
+    image: &mut Image,
+    degrees: f64,
+    background: &Color,
+    method: InterpolateMethod,
+    limits: &Limits,
+) -> Result<(), MagickError> {
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    let (width, height) = (f64::from(image.width()), f64::from(image.height()));
+    // The bounding box of the rotated image. Rounding errors must not add a column of background.
+    let fit = |length: f64| (length - 1e-6).ceil().max(1.0);
+    // The result is built in floating point RGBA
+    let (new_width, new_height) = limits.check_new_image(
+        fit(width * cos.abs() + height * sin.abs()),
+        fit(width * sin.abs() + height * cos.abs()),
+        16,
+    )?;
+
+    let source = pool::to_rgba32f(&image.pixels);
+    let fill = background.to_array();
+    let (center_x, center_y) = (width / 2.0, height / 2.0);
+    let (new_center_x, new_center_y) = (f64::from(new_width) / 2.0, f64::from(new_height) / 2.0);
+    let rotated = pool::rgba32f_from_fn(new_width, new_height, |x, y| {
+        // Map the center of the destination pixel back onto the source image
+        let dx = f64::from(x) + 0.5 - new_center_x;
+        let dy = f64::from(y) + 0.5 - new_center_y;
+        let u = center_x + dx * cos + dy * sin;
+        let v = center_y - dx * sin + dy * cos;
+        image::Rgba(interpolate(&source, method, u, v, fill))
+    });
+    pool::recycle(source.into_raw());
+    set_pixels(image, rotated, background);
+
+    // The rotated image stays centered on the same spot of the canvas
+    if let Some(page) = &mut image.page {
+        page.x -= (i64::from(new_width) - width as i64) / 2;
+        page.y -= (i64::from(new_height) - height as i64) / 2;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{ColorType, DynamicImage, GrayImage, Luma, Rgb, RgbImage};
+
+    use super::*;
+    use crate::arg_parsers::RotateConstraint;
+    use std::str::FromStr;
+
+    fn geometry(degrees: f64) -> RotateGeometry {
+        RotateGeometry {
+            degrees,
+            constraint: RotateConstraint::Always,
+        }
+    }
+
+    #[test]
+    fn right_angles_are_lossless() {
+        let pixels = RgbImage::from_fn(3, 2, |x, y| Rgb([x as u8, y as u8, 0]));
+        let mut image = Image::new(DynamicImage::ImageRgb8(pixels));
+        rotate(
+            &mut image,
+            &geometry(-270.0),
+            &Color::WHITE,
+            InterpolateMethod::Bilinear,
+            &Limits::default(),
+        )
+        .unwrap();
+        let rotated = image.pixels.as_rgb8().unwrap();
+        assert_eq!(rotated.dimensions(), (2, 3));
+        // the bottom left corner ends up in the top left
+        assert_eq!(rotated.get_pixel(0, 0), &Rgb([0, 1, 0]));
+    }
+
+    #[test]
+    fn constraint_skips_images() {
+        let mut image = Image::new(DynamicImage::ImageRgb8(RgbImage::new(3, 2)));
+        let geometry = RotateGeometry::from_str("90<").unwrap();
+        rotate(
+            &mut image,
+            &geometry,
+            &Color::WHITE,
+            InterpolateMethod::Bilinear,
+            &Limits::default(),
+        )
+        .unwrap();
+        assert_eq!(image.pixels.width(), 3);
+    }
+
+    #[test]
+    fn corners_are_filled_with_background() {
+        let pixels = GrayImage::from_pixel(10, 10, Luma([0]));
+        let mut image = Image::new(DynamicImage::ImageLuma8(pixels));
+        let red = Color::from_str("red").unwrap();
+        rotate(
+            &mut image,
+            &geometry(45.0),
+            &red,
+            InterpolateMethod::Bilinear,
+            &Limits::default(),
+        )
+        .unwrap();
+        // 10 * sqrt(2) = 14.14
+        assert_eq!((image.pixels.width(), image.pixels.height()), (15, 15));
+        let rotated = image.pixels.as_rgb8().unwrap();
+        assert_eq!(rotated.get_pixel(0, 0), &Rgb([255, 0, 0]));
+        assert_eq!(rotated.get_pixel(7, 7), &Rgb([0, 0, 0]));
+    }
+
+    #[test]
+    fn transparent_background_adds_alpha() {
+        let pixels = GrayImage::from_pixel(4, 4, Luma([100]));
+        let mut image = Image::new(DynamicImage::ImageLuma8(pixels));
+        rotate(
+            &mut image,
+            &geometry(30.0),
+            &Color::from_str("none").unwrap(),
+            InterpolateMethod::Bilinear,
+            &Limits::default(),
+        )
+        .unwrap();
+        assert_eq!(image.pixels.color(), ColorType::La8);
+        assert_eq!(image.pixels.as_luma_alpha8().unwrap().get_pixel(0, 0)[1], 0);
+    }
+}
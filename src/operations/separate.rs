@@ -0,0 +1,77 @@
+use image::{DynamicImage, ExtendedColorType, ImageBuffer, Luma};
+
+use crate::{arg_parsers::Colorspace, image::Image};
+
+/// Implements `-separate`: splits the image into one grayscale image per color channel,
+/// such as red, green and blue, or cyan, magenta, yellow and black for CMYK.
+///
+/// The alpha channel is left out, like imagemagick does unless `-channel` asks for it.
+pub fn separate(mut image: Image) -> Vec<Image> {
+    let channels = match image.colorspace {
+        Colorspace::Cmyk => 4,
+        _ if !image.pixels.color().has_color() => 1,
+        _ => 3,
+    };
+    let original = image.original_color_type;
+    let deep = original.bits_per_pixel() / u16::from(original.channel_count()) > 8;
+    // CMYK keeps K in the alpha channel, so all four are needed
+    let samples = image.pixels.to_rgba32f();
+    image.pixels = DynamicImage::new_luma8(0, 0);
+    (0..channels)
+        .map(|channel| {
+            let value = |x, y| samples.get_pixel(x, y)[channel].clamp(0.0, 1.0);
+            let (width, height) = samples.dimensions();
+            let mut separated = image.clone();
+            separated.pixels = match deep {
+                false => DynamicImage::ImageLuma8(ImageBuffer::from_fn(width, height, |x, y| {
+                    Luma([(value(x, y) * 255.0).round() as u8])
+                })),
+                true => DynamicImage::ImageLuma16(ImageBuffer::from_fn(width, height, |x, y| {
+                    Luma([(value(x, y) * 65535.0).round() as u16])
+                })),
+            };
+            separated.original_color_type = match deep {
+                false => ExtendedColorType::L8,
+                true => ExtendedColorType::L16,
+            };
+            separated.colorspace = Colorspace::Srgb;
+            separated
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{GrayImage, Rgb, RgbImage, Rgba, RgbaImage};
+
+    use super::*;
+
+    #[test]
+    fn one_image_per_channel() {
+        let pixels = RgbaImage::from_pixel(2, 1, Rgba([10, 20, 30, 40]));
+        let images = separate(Image::new(DynamicImage::ImageRgba8(pixels)));
+        let values: Vec<u8> = images
+            .iter()
+            .map(|image| image.pixels.as_luma8().unwrap().get_pixel(1, 0)[0])
+            .collect();
+        assert_eq!(values, [10, 20, 30]);
+
+        let pixels = GrayImage::from_pixel(2, 1, Luma([50]));
+        let images = separate(Image::new(DynamicImage::ImageLuma8(pixels)));
+        assert_eq!(images.len(), 1);
+    }
+
+    #[test]
+    fn cmyk_has_four_channels() {
+        let pixels = RgbImage::from_pixel(1, 1, Rgb([255, 0, 0]));
+        let mut image = Image::new(DynamicImage::ImageRgb8(pixels));
+        crate::colorspace::transform(&mut image, Colorspace::Cmyk);
+        let images = separate(image);
+        let values: Vec<u8> = images
+            .iter()
+            .map(|image| image.pixels.as_luma8().unwrap().get_pixel(0, 0)[0])
+            .collect();
+        assert_eq!(values, [0, 255, 255, 0]);
+        assert!(images.iter().all(|image| image.colorspace.is_srgb()));
+    }
+}
@@ -0,0 +1,84 @@
+use image::DynamicImage;
+
+use super::{colorize::promote_to_color, histogram::normalize, histogram::INTENSITY};
+use crate::{
+    arg_parsers::SepiaThreshold, error::MagickError, image::Image, utils::channel_map::map_colors,
+};
+
+/// Implements `-sepia-tone`: turns the image into a brown-tinted monochrome, like an old photograph.
+///
+/// Follows imagemagick's SepiaToneImage(): the intensity of every pixel is mapped to a tone
+/// where red saturates first and blue last, then the result is normalized and its contrast increased.
+///
+/// The output has not been compared against imagemagick's yet, neither with DSSIM nor pixel by pixel,
+/// so the match is unverified and may be off, e.g. in how `-normalize` clips the histogram.
+pub fn sepia_tone(image: &mut Image, threshold: &SepiaThreshold) -> Result<(), MagickError> {
+    let threshold = threshold.threshold;
+    // The tint needs color channels
+    promote_to_color(&mut image.pixels);
+    map_colors(&mut image.pixels, |rgb| {
+        let intensity: f64 = rgb.iter().zip(INTENSITY).map(|(c, w)| c * w).sum();
+        let tone = |threshold: f64| match intensity > threshold {
+            true => 1.0,
+            false => intensity + 1.0 - threshold,
+        };
+        let red = tone(threshold);
+        let green = tone(7.0 * threshold / 6.0);
+        let blue = (intensity - threshold / 6.0).max(0.0);
+        let floor = threshold / 7.0;
+        [red, green.max(floor), blue.max(floor)]
+    });
+    normalize(image)?;
+    sharpen_contrast(&mut image.pixels);
+    Ok(())
+}
+
+/// imagemagick's `-contrast`: increases the brightness of bright pixels and decreases it for dark ones
+/// along a sine curve, keeping the hue and the saturation
+fn sharpen_contrast(pixels: &mut DynamicImage) {
+    map_colors(pixels, |rgb| {
+        // Brightness in the HSB colorspace is the largest channel, so with the same hue and saturation
+        // changing it scales all channels together
+        let brightness = rgb[0].max(rgb[1]).max(rgb[2]);
+        if brightness <= 0.0 {
+            return rgb;
+        }
+        let curve = 0.5 * ((std::f64::consts::PI * (brightness - 0.5)).sin() + 1.0);
+        let adjusted = (brightness + 0.5 * (curve - brightness)).clamp(0.0, 1.0);
+        rgb.map(|c| c * adjusted / brightness)
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{GrayImage, Luma};
+
+    use super::*;
+
+    #[test]
+    fn gray_becomes_brown() {
+        let pixels = GrayImage::from_fn(16, 1, |x, _| Luma([x as u8 * 16]));
+        let mut image = Image::new(DynamicImage::ImageLuma8(pixels));
+        let threshold = SepiaThreshold { threshold: 0.8 };
+        sepia_tone(&mut image, &threshold).unwrap();
+        let pixels = image.pixels.as_rgb8().unwrap();
+        let [r, g, b] = pixels.get_pixel(8, 0).0;
+        assert!(r > g && g > b, "{r} {g} {b}");
+        // normalization stretches the intensities to almost black and cream highlights
+        assert!(pixels.get_pixel(0, 0).0.iter().all(|&c| c < 16));
+        let [r, g, b] = pixels.get_pixel(15, 0).0;
+        assert!(r == 255 && g == 255 && b > 192, "{r} {g} {b}");
+    }
+
+    #[test]
+    fn contrast_curve() {
+        let mut pixels = DynamicImage::ImageRgb32F(image::Rgb32FImage::from_fn(3, 1, |x, _| {
+            image::Rgb([[0.25, 0.5, 0.75][x as usize], 0.0, 0.0])
+        }));
+        sharpen_contrast(&mut pixels);
+        let red: Vec<f32> = pixels.as_rgb32f().unwrap().pixels().map(|p| p[0]).collect();
+        assert!(red[0] < 0.25);
+        assert!((red[1] - 0.5).abs() < 1e-6);
+        assert!(red[2] > 0.75);
+    }
+}
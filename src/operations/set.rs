@@ -0,0 +1,55 @@
+use super::{
+    comment::{comment, label},
+    identify::expand_template,
+};
+use crate::{arg_parsers::IdentifyFormat, error::MagickError, image::Image};
+
+/// Implements `-set`: sets the image property to the expanded template, or removes it for `+set`.
+///
+/// `comment` and `label` are the same as `-comment` and `-label`. Properties named `filename:*`
+/// can be used in the output filename, e.g. `-set filename:area '%wx%h' 'out_%[filename:area].png'`.
+pub fn set(
+    image: &mut Image,
+    sequence_length: usize,
+    key: &str,
+    value: Option<&IdentifyFormat>,
+) -> Result<(), MagickError> {
+    match key {
+        "comment" => return comment(image, sequence_length, value),
+        "label" => return label(image, sequence_length, value),
+        _ => (),
+    }
+    match value {
+        Some(template) => {
+            let value = expand_template(image, sequence_length, template);
+            image.properties.insert(key.to_owned(), value);
+        }
+        None => {
+            image.properties.remove(key);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use image::{DynamicImage, RgbImage};
+
+    use super::*;
+
+    #[test]
+    fn properties_are_expanded() {
+        let mut image = Image::new(DynamicImage::ImageRgb8(RgbImage::new(3, 2)));
+        let template = IdentifyFormat::from_str("%wx%h").unwrap();
+        set(&mut image, 1, "filename:area", Some(&template)).unwrap();
+        assert_eq!(image.properties["filename:area"], "3x2");
+        // later templates can refer to it
+        let template = IdentifyFormat::from_str("area %[filename:area]").unwrap();
+        set(&mut image, 1, "comment", Some(&template)).unwrap();
+        assert_eq!(image.comment.as_deref(), Some("area 3x2"));
+        set(&mut image, 1, "filename:area", None).unwrap();
+        assert!(image.properties.is_empty());
+    }
+}
@@ -0,0 +1,109 @@
+use image::{Rgba, Rgba32FImage};
+
+use super::{background::set_pixels, blur::gaussian_blur};
+use crate::{
+    arg_parsers::{BlurGeometry, Color, ShadowGeometry},
+    error::MagickError,
+    image::{Image, PageGeometry},
+    limits::Limits,
+};
+
+/// Larger sigmas only spread the shadow into a faint haze, while the canvas and the blur kernel
+/// keep growing with it, so that an argument like `80x1000000` would never finish
+const MAX_SIGMA: f64 = 500.0;
+
+/// Implements `-shadow` like imagemagick's ShadowImage(): replaces the image with its silhouette
+/// in the background color, as opaque as the given percentage and blurred by sigma.
+///
+/// The shadow is larger than the image by twice the sigma on every side to fit the blur,
+/// and its page offset places it under the image, moved by the offset of the geometry.
+/// Composited under the original, it makes a drop shadow.
+/// The sigma is capped at [MAX_SIGMA], and the size of the shadow is checked against the limits.
+pub fn shadow(
+    image: &mut Image,
+    geometry: &ShadowGeometry,
+    color: &Color,
+    limits: &Limits,
+) -> Result<(), MagickError> {
+    let sigma = geometry.sigma.clamp(0.0, MAX_SIGMA);
+    let border = (2.0 * sigma + 0.5).floor();
+    // The shadow is built in floating point RGBA
+    let (width, height) = limits.check_new_image(
+        f64::from(image.width()) + 2.0 * border,
+        f64::from(image.height()) + 2.0 * border,
+        16,
+    )?;
+    let border = border as u32;
+    let source = image.pixels.to_rgba32f();
+    let opacity = (geometry.opacity / 100.0).clamp(0.0, 1.0) as f32;
+    let [red, green, blue, _] = color.to_array();
+    let silhouette = Rgba32FImage::from_fn(width, height, |x, y| {
+        let alpha = match (x.checked_sub(border), y.checked_sub(border)) {
+            (Some(x), Some(y)) if x < source.width() && y < source.height() => {
+                source.get_pixel(x, y)[3]
+            }
+            _ => 0.0,
+        };
+        Rgba([red, green, blue, alpha * opacity])
+    });
+    let blur = BlurGeometry { radius: 0.0, sigma };
+    let shadow = gaussian_blur(&silhouette, &blur);
+
+    let page = image.page.get_or_insert(PageGeometry {
+        width: source.width(),
+        height: source.height(),
+        x: 0,
+        y: 0,
+    });
+    let x_shift = geometry.x - i64::from(border);
+    let y_shift = geometry.y - i64::from(border);
+    page.width = (i64::from(page.width) + x_shift).max(0) as u32;
+    page.height = (i64::from(page.height) + y_shift).max(0) as u32;
+    page.x += x_shift;
+    page.y += y_shift;
+    // The shadow fades out into transparency, so the image needs an alpha channel
+    let transparent = Color {
+        alpha: 0.0,
+        ..*color
+    };
+    set_pixels(image, shadow, &transparent);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ffi::OsStr, str::FromStr};
+
+    use image::{DynamicImage, RgbImage};
+
+    use super::*;
+    use crate::arg_parsers::ResourceType;
+
+    #[test]
+    fn blurred_silhouette() {
+        let pixels = RgbImage::from_pixel(30, 30, image::Rgb([255, 0, 0]));
+        let mut image = Image::new(DynamicImage::ImageRgb8(pixels));
+        let geometry = ShadowGeometry::from_str("50x2+3+1").unwrap();
+        shadow(&mut image, &geometry, &Color::BLACK, &Limits::default()).unwrap();
+
+        assert_eq!((image.width(), image.height()), (38, 38));
+        let pixels = image.pixels.to_rgba8();
+        // Black, half transparent in the middle and fading out towards the edges
+        assert_eq!(*pixels.get_pixel(19, 19), Rgba([0, 0, 0, 128]));
+        assert!(pixels.get_pixel(0, 0)[3] < 8);
+        let page = image.page.unwrap();
+        assert_eq!((page.x, page.y), (-1, -3));
+        assert_eq!((page.width, page.height), (29, 27));
+    }
+
+    #[test]
+    fn size_is_checked_against_the_limits() {
+        let pixels = RgbImage::new(2, 2);
+        let mut image = Image::new(DynamicImage::ImageRgb8(pixels));
+        let geometry = ShadowGeometry::from_str("80x1000000").unwrap();
+        let mut limits = Limits::default();
+        limits.set(ResourceType::Width, OsStr::new("1000")).unwrap();
+        assert!(shadow(&mut image, &geometry, &Color::BLACK, &limits).is_err());
+        assert_eq!((image.width(), image.height()), (2, 2));
+    }
+}
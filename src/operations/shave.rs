@@ -0,0 +1,87 @@
+use crate::{
+    arg_parsers::CropGeometry,
+    error::MagickError,
+    image::{Image, PageGeometry},
+    wm_err,
+};
+
+use super::crop::{canvas, crop_region};
+
+/// Implements `-shave`: removes the given number of pixels from the left and right edges
+/// and from the top and bottom ones. The virtual canvas shrinks by the same amount.
+pub fn shave(image: &mut Image, geometry: &CropGeometry) -> Result<(), MagickError> {
+    let (width, height) = geometry.dimensions(image.width(), image.height());
+    let (width, height) = (width.unwrap_or(0), height.unwrap_or(0));
+    if u64::from(width) * 2 >= u64::from(image.width())
+        || u64::from(height) * 2 >= u64::from(image.height())
+    {
+        return Err(wm_err!(
+            "geometry does not contain image `{}'",
+            image.filename.to_string_lossy()
+        ));
+    }
+    let page = canvas(image);
+    crop_region(
+        image,
+        page.x + i64::from(width),
+        page.y + i64::from(height),
+        image.width() - 2 * width,
+        image.height() - 2 * height,
+    );
+    image.page = image.page.map(|cropped| PageGeometry {
+        width: page.width.saturating_sub(2 * width),
+        height: page.height.saturating_sub(2 * height),
+        x: cropped.x - i64::from(width),
+        y: cropped.y - i64::from(height),
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use image::{DynamicImage, GenericImageView, Rgb, RgbImage};
+
+    use super::*;
+
+    fn test_image() -> Image {
+        Image::new(DynamicImage::ImageRgb8(RgbImage::from_fn(10, 8, |x, y| {
+            Rgb([x as u8, y as u8, 0])
+        })))
+    }
+
+    #[test]
+    fn edges_are_removed() {
+        let mut image = test_image();
+        shave(&mut image, &CropGeometry::from_str("2x1").unwrap()).unwrap();
+        assert_eq!((image.width(), image.height()), (6, 6));
+        assert_eq!(image.pixels.get_pixel(0, 0), image::Rgba([2, 1, 0, 255]));
+        assert_eq!(
+            image.page,
+            Some(PageGeometry {
+                width: 6,
+                height: 6,
+                x: 0,
+                y: 0,
+            })
+        );
+    }
+
+    #[test]
+    fn single_number_and_percent() {
+        let mut image = test_image();
+        shave(&mut image, &CropGeometry::from_str("3").unwrap()).unwrap();
+        assert_eq!((image.width(), image.height()), (4, 2));
+
+        let mut image = test_image();
+        shave(&mut image, &CropGeometry::from_str("10%x25%").unwrap()).unwrap();
+        assert_eq!((image.width(), image.height()), (8, 4));
+    }
+
+    #[test]
+    fn cannot_shave_everything() {
+        let mut image = test_image();
+        assert!(shave(&mut image, &CropGeometry::from_str("5x0").unwrap()).is_err());
+    }
+}
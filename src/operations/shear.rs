@@ -0,0 +1,155 @@
+use super::{background::set_pixels, interpolate::interpolate};
+use crate::{
+    arg_parsers::{Color, InterpolateMethod, ShearGeometry},
+    error::MagickError,
+    image::Image,
+    limits::Limits,
+    utils::pool,
+    wm_err,
+};
+
+/// Implements `-shear`: slides the rows of the image horizontally by the X angle,
+/// then the columns vertically by the Y angle.
+///
+/// Positive X angles move the top of the image to the right, positive Y angles move the right side down.
+/// The image is enlarged to fit the sheared one, and the new areas are filled with the background color.
+/// Angles close to 90 degrees make it enormous, so its size is checked against the limits first.
+pub fn shear(
+    image: &mut Image,
+    geometry: &ShearGeometry,
+    background: &Color,
+    method: InterpolateMethod,
+    limits: &Limits,
+) -> Result<(), MagickError> {
+    // Same conventions as imagemagick's ShearImage()
+    let x_shear = -shear_factor(geometry.x_degrees)?;
+    let y_shear = shear_factor(geometry.y_degrees)?;
+    if x_shear == 0.0 && y_shear == 0.0 {
+        return Ok(());
+    }
+    let (width, height) = (f64::from(image.width()), f64::from(image.height()));
+    let (center_x, center_y) = (width / 2.0, height / 2.0);
+    let forward = |x: f64, y: f64| {
+        let x = x + x_shear * y;
+        (x, y + y_shear * x)
+    };
+
+    // Fit the sheared corners of the image, relative to its center
+    let corners = [
+        (-center_x, -center_y),
+        (center_x, -center_y),
+        (-center_x, center_y),
+        (center_x, center_y),
+    ]
+    .map(|(x, y)| forward(x, y));
+    let extent = |axis: fn(&(f64, f64)) -> f64| {
+        let min = corners.iter().map(axis).fold(f64::INFINITY, f64::min);
+        let max = corners.iter().map(axis).fold(f64::NEG_INFINITY, f64::max);
+        (max - min + 0.5).floor().max(1.0)
+    };
+    // The result is built in floating point RGBA
+    let (new_width, new_height) =
+        limits.check_new_image(extent(|corner| corner.0), extent(|corner| corner.1), 16)?;
+
+    let source = pool::to_rgba32f(&image.pixels);
+    let fill = background.to_array();
+    let (new_center_x, new_center_y) = (f64::from(new_width) / 2.0, f64::from(new_height) / 2.0);
+    let sheared = pool::rgba32f_from_fn(new_width, new_height, |x, y| {
+        // Undo the vertical shear, then the horizontal one
+        let sheared_x = f64::from(x) + 0.5 - new_center_x;
+        let sheared_y = f64::from(y) + 0.5 - new_center_y;
+        let v = sheared_y - y_shear * sheared_x;
+        let u = sheared_x - x_shear * v;
+        image::Rgba(interpolate(
+            &source,
+            method,
+            center_x + u,
+            center_y + v,
+            fill,
+        ))
+    });
+    pool::recycle(source.into_raw());
+    set_pixels(image, sheared, background);
+
+    // The sheared image stays centered on the same spot of the canvas
+    if let Some(page) = &mut image.page {
+        page.x -= (i64::from(new_width) - width as i64) / 2;
+        page.y -= (i64::from(new_height) - height as i64) / 2;
+    }
+    Ok(())
+}
+
+/// Converts the shear angle into the horizontal displacement per row
+fn shear_factor(degrees: f64) -> Result<f64, MagickError> {
+    let degrees = degrees % 360.0;
+    // tan() goes to infinity at 90 degrees
+    if ((degrees.abs() + 90.0) % 180.0).abs() < f64::EPSILON {
+        return Err(wm_err!("angle is discontinuous `{}'", degrees));
+    }
+    Ok(degrees.to_radians().tan())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use image::{DynamicImage, GenericImageView, Rgb, RgbImage, Rgba};
+
+    use super::*;
+
+    fn sheared(geometry: &str) -> Result<Image, MagickError> {
+        let pixels = RgbImage::from_pixel(10, 10, Rgb([0, 0, 255]));
+        let mut image = Image::new(DynamicImage::ImageRgb8(pixels));
+        let geometry = ShearGeometry::from_str(geometry).unwrap();
+        shear(
+            &mut image,
+            &geometry,
+            &Color::from_str("red").unwrap(),
+            InterpolateMethod::Bilinear,
+            &Limits::default(),
+        )?;
+        Ok(image)
+    }
+
+    #[test]
+    fn horizontal_shear() {
+        let image = sheared("45x0").unwrap();
+        assert_eq!((image.width(), image.height()), (20, 10));
+        // the top row moves right, the bottom row moves left
+        assert_eq!(image.pixels.get_pixel(0, 0), Rgba([255, 0, 0, 255]));
+        assert_eq!(image.pixels.get_pixel(15, 0), Rgba([0, 0, 255, 255]));
+        assert_eq!(image.pixels.get_pixel(4, 9), Rgba([0, 0, 255, 255]));
+        assert_eq!(image.pixels.get_pixel(19, 9), Rgba([255, 0, 0, 255]));
+    }
+
+    #[test]
+    fn vertical_shear() {
+        let image = sheared("0x45").unwrap();
+        assert_eq!((image.width(), image.height()), (10, 20));
+        // the right side moves down
+        assert_eq!(image.pixels.get_pixel(9, 0), Rgba([255, 0, 0, 255]));
+        assert_eq!(image.pixels.get_pixel(9, 15), Rgba([0, 0, 255, 255]));
+        assert_eq!(image.pixels.get_pixel(0, 4), Rgba([0, 0, 255, 255]));
+    }
+
+    #[test]
+    fn both_axes() {
+        let image = sheared("30").unwrap();
+        assert!(image.width() > 10 && image.height() > 10);
+        assert_eq!(image.pixels.get_pixel(0, 0), Rgba([255, 0, 0, 255]));
+    }
+
+    #[test]
+    fn no_op_and_discontinuous_angles() {
+        let image = sheared("0x180").unwrap();
+        assert_eq!((image.width(), image.height()), (10, 10));
+        assert!(sheared("90x0").is_err());
+        assert!(sheared("0x-270").is_err());
+    }
+
+    #[test]
+    fn near_vertical_angles_are_limited() {
+        assert!(sheared("89.9999").is_err());
+        assert!(sheared("89.99").is_err());
+    }
+}
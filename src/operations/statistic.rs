@@ -0,0 +1,225 @@
+//! Filters that replace every pixel with a statistic of the pixels around it:
+//! `-statistic`, `-median`, and `-despeckle`, which removes isolated specks of noise.
+
+use image::{DynamicImage, Rgba, Rgba32FImage};
+
+use crate::{
+    arg_parsers::{StatisticType, StatisticWindow},
+    encoders::common::convert,
+    error::MagickError,
+    image::Image,
+};
+
+/// Implements `-statistic` and `-median` like imagemagick's StatisticImage():
+/// every channel of every pixel is replaced with the statistic of the same channel
+/// in the window centered on it. Pixels beyond the edges repeat the nearest edge pixel.
+pub fn statistic(
+    image: &mut Image,
+    kind: StatisticType,
+    window: StatisticWindow,
+) -> Result<(), MagickError> {
+    let pixels = image.pixels.to_rgba32f();
+    let (width, height) = pixels.dimensions();
+    let (left, top) = (i64::from(window.width / 2), i64::from(window.height / 2));
+    let mut values = Vec::with_capacity(window.width as usize * window.height as usize);
+    let result = Rgba32FImage::from_fn(width, height, |x, y| {
+        let mut pixel = [0.0; 4];
+        for (c, value) in pixel.iter_mut().enumerate() {
+            values.clear();
+            for wy in 0..i64::from(window.height) {
+                let sy = (i64::from(y) - top + wy).clamp(0, i64::from(height) - 1) as u32;
+                for wx in 0..i64::from(window.width) {
+                    let sx = (i64::from(x) - left + wx).clamp(0, i64::from(width) - 1) as u32;
+                    values.push(pixels.get_pixel(sx, sy)[c]);
+                }
+            }
+            *value = compute(kind, &mut values);
+        }
+        Rgba(pixel)
+    });
+    image.pixels = convert(&DynamicImage::ImageRgba32F(result), image.pixels.color());
+    Ok(())
+}
+
+/// Computes the statistic of the samples, which may be reordered in the process
+fn compute(kind: StatisticType, values: &mut [f32]) -> f32 {
+    let count = values.len() as f32;
+    let min = || values.iter().copied().fold(f32::INFINITY, f32::min);
+    let max = || values.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let mean = |values: &[f32]| values.iter().sum::<f32>() / count;
+    let mean_square = |values: &[f32]| values.iter().map(|v| v * v).sum::<f32>() / count;
+    match kind {
+        StatisticType::Gradient => max() - min(),
+        StatisticType::Maximum => max(),
+        StatisticType::Minimum => min(),
+        StatisticType::Mean => mean(values),
+        StatisticType::RootMeanSquare => mean_square(values).sqrt(),
+        StatisticType::StandardDeviation => {
+            (mean_square(values) - mean(values).powi(2)).max(0.0).sqrt()
+        }
+        StatisticType::Median => {
+            values.sort_unstable_by(f32::total_cmp);
+            values[values.len() / 2]
+        }
+        StatisticType::Nonpeak => {
+            values.sort_unstable_by(f32::total_cmp);
+            let median = values[values.len() / 2];
+            let (lowest, highest) = (values[0], values[values.len() - 1]);
+            if median == lowest && median != highest {
+                values
+                    .iter()
+                    .copied()
+                    .find(|&v| v > median)
+                    .unwrap_or(median)
+            } else if median == highest && median != lowest {
+                values
+                    .iter()
+                    .copied()
+                    .rfind(|&v| v < median)
+                    .unwrap_or(median)
+            } else {
+                median
+            }
+        }
+        StatisticType::Mode => {
+            // Like imagemagick, values are counted at 16 bits so that nearly equal ones count together
+            let quantize = |v: &f32| (v.clamp(0.0, 1.0) * 65535.0).round() as u16;
+            values.sort_unstable_by(f32::total_cmp);
+            let mut mode: &[f32] = &[];
+            for run in values.chunk_by(|a, b| quantize(a) == quantize(b)) {
+                if run.len() > mode.len() {
+                    mode = run;
+                }
+            }
+            mode[0]
+        }
+    }
+}
+
+/// Implements `-despeckle` like imagemagick's DespeckleImage(), with the Crimmins speckle removal filter.
+///
+/// Every channel is repeatedly nudged by one 8-bit step towards its neighbors in all four directions
+/// where it stands out from them, which wears down isolated specks while leaving edges, where
+/// only one side differs, alone. Pixels beyond the edges count as black, same as in imagemagick.
+pub fn despeckle(image: &mut Image) -> Result<(), MagickError> {
+    let mut pixels = image.pixels.to_rgba32f();
+    let (width, height) = (pixels.width() as usize, pixels.height() as usize);
+    let stride = width + 2;
+    let index = |x: u32, y: u32| (y as usize + 1) * stride + x as usize + 1;
+    for c in 0..4 {
+        // In units of 8-bit steps, with a border of one pixel all around
+        let mut f = vec![0f32; stride * (height + 2)];
+        let mut g = f.clone();
+        for (x, y, pixel) in pixels.enumerate_pixels() {
+            f[index(x, y)] = pixel[c] * 255.0;
+        }
+        for (dx, dy) in [(0, 1), (1, 0), (1, 1), (-1, 1)] {
+            let offset = dy * stride as isize + dx;
+            hull(&mut f, &mut g, offset, (width, height), true);
+            hull(&mut f, &mut g, -offset, (width, height), true);
+            hull(&mut f, &mut g, -offset, (width, height), false);
+            hull(&mut f, &mut g, offset, (width, height), false);
+        }
+        for (x, y, pixel) in pixels.enumerate_pixels_mut() {
+            pixel[c] = (f[index(x, y)] / 255.0).clamp(0.0, 1.0);
+        }
+    }
+    image.pixels = convert(&DynamicImage::ImageRgba32F(pixels), image.pixels.color());
+    Ok(())
+}
+
+/// A single pass of the Crimmins filter in one direction, raising pixels darker than their neighbors
+/// if `raise` is set and lowering brighter ones otherwise.
+/// `f` holds the samples and receives the result; `g` is scratch space of the same size.
+fn hull(f: &mut [f32], g: &mut [f32], offset: isize, (width, height): (usize, usize), raise: bool) {
+    let stride = width + 2;
+    let sign = if raise { 1.0 } else { -1.0 };
+    let interior = (0..height).flat_map(|y| (0..width).map(move |x| (y + 1) * stride + x + 1));
+    for i in interior.clone() {
+        let (value, neighbor) = (f[i], f[i.wrapping_add_signed(offset)]);
+        g[i] = match sign * (neighbor - value) >= 2.0 {
+            true => value + sign,
+            false => value,
+        };
+    }
+    for i in interior {
+        let value = g[i];
+        let (ahead, behind) = (
+            g[i.wrapping_add_signed(offset)],
+            g[i.wrapping_add_signed(-offset)],
+        );
+        f[i] = match sign * (behind - value) >= 2.0 && sign * (ahead - value) > 0.0 {
+            true => value + sign,
+            false => value,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{GrayImage, Luma};
+
+    use super::*;
+
+    fn window(width: u32, height: u32) -> StatisticWindow {
+        StatisticWindow { width, height }
+    }
+
+    #[test]
+    fn median_removes_salt_and_pepper() {
+        let mut pixels = GrayImage::from_pixel(5, 5, Luma([100]));
+        pixels.put_pixel(1, 1, Luma([255]));
+        pixels.put_pixel(3, 3, Luma([0]));
+        let mut image = Image::new(DynamicImage::ImageLuma8(pixels));
+        statistic(&mut image, StatisticType::Median, window(3, 3)).unwrap();
+        assert_eq!(
+            image.pixels.as_luma8().unwrap(),
+            &GrayImage::from_pixel(5, 5, Luma([100]))
+        );
+    }
+
+    #[test]
+    fn statistics() {
+        let values = |kind| compute(kind, &mut [0.5, 0.0, 1.0, 0.5, 0.25]);
+        assert_eq!(values(StatisticType::Minimum), 0.0);
+        assert_eq!(values(StatisticType::Maximum), 1.0);
+        assert_eq!(values(StatisticType::Gradient), 1.0);
+        assert_eq!(values(StatisticType::Mean), 0.45);
+        assert_eq!(values(StatisticType::Median), 0.5);
+        assert_eq!(values(StatisticType::Mode), 0.5);
+        assert!((values(StatisticType::StandardDeviation) - 0.3317).abs() < 1e-4);
+        assert!((values(StatisticType::RootMeanSquare) - 0.5590).abs() < 1e-4);
+        // the median is the lowest value, so the next one up is taken instead
+        assert_eq!(
+            compute(StatisticType::Nonpeak, &mut [0.0, 0.0, 0.0, 0.5, 1.0]),
+            0.5
+        );
+        assert_eq!(compute(StatisticType::Nonpeak, &mut [0.0, 0.5, 1.0]), 0.5);
+    }
+
+    #[test]
+    fn window_is_not_square() {
+        let pixels =
+            GrayImage::from_fn(5, 5, |x, y| Luma([if x == 2 || y == 2 { 200 } else { 0 }]));
+        let mut image = Image::new(DynamicImage::ImageLuma8(pixels));
+        statistic(&mut image, StatisticType::Maximum, window(1, 3)).unwrap();
+        let pixels = image.pixels.as_luma8().unwrap();
+        // the horizontal line grows vertically, the vertical one does not grow sideways
+        assert_eq!(pixels.get_pixel(0, 1)[0], 200);
+        assert_eq!(pixels.get_pixel(1, 0)[0], 0);
+    }
+
+    #[test]
+    fn despeckle_wears_down_specks() {
+        let mut pixels = GrayImage::from_pixel(9, 9, Luma([100]));
+        pixels.put_pixel(4, 4, Luma([200]));
+        pixels.put_pixel(6, 2, Luma([20]));
+        let mut image = Image::new(DynamicImage::ImageLuma8(pixels));
+        despeckle(&mut image).unwrap();
+        let pixels = image.pixels.as_luma8().unwrap();
+        assert!(pixels.get_pixel(4, 4)[0] < 200, "{pixels:?}");
+        assert!(pixels.get_pixel(6, 2)[0] > 20, "{pixels:?}");
+        // the flat area around them is left alone
+        assert_eq!(pixels.get_pixel(2, 6)[0], 100);
+    }
+}
@@ -0,0 +1,10 @@
+use crate::{error::MagickError, image::Image};
+
+/// Implements `-strip`: removes color profiles, comments and metadata such as EXIF,
+/// so that encoders that could write them don't.
+pub fn strip(image: &mut Image) -> Result<(), MagickError> {
+    image.icc_profile = None;
+    image.exif = None;
+    image.comment = None;
+    Ok(())
+}
@@ -0,0 +1,108 @@
+//! Thresholding by pixel intensity: `-threshold`, `-black-threshold` and `-white-threshold`.
+//!
+//! Like imagemagick, the intensity of the whole pixel is compared against the threshold
+//! and all of its color channels are set together, so colors do not shift. Alpha is left alone.
+
+use super::histogram::INTENSITY;
+use crate::{error::MagickError, image::Image, utils::channel_map::map_colors};
+
+/// Implements `-threshold`: pixels brighter than the threshold become white, the rest become black
+pub fn threshold(image: &mut Image, threshold: f64) -> Result<(), MagickError> {
+    map_colors(&mut image.pixels, |color| {
+        match intensity(color) <= threshold {
+            true => [0.0; 3],
+            false => [1.0; 3],
+        }
+    });
+    Ok(())
+}
+
+/// Implements `-black-threshold`: pixels darker than the threshold become black
+pub fn black_threshold(image: &mut Image, threshold: f64) -> Result<(), MagickError> {
+    map_colors(&mut image.pixels, |color| {
+        match intensity(color) < threshold {
+            true => [0.0; 3],
+            false => color,
+        }
+    });
+    Ok(())
+}
+
+/// Implements `-white-threshold`: pixels brighter than the threshold become white
+pub fn white_threshold(image: &mut Image, threshold: f64) -> Result<(), MagickError> {
+    map_colors(&mut image.pixels, |color| {
+        match intensity(color) > threshold {
+            true => [1.0; 3],
+            false => color,
+        }
+    });
+    Ok(())
+}
+
+fn intensity(color: [f64; 3]) -> f64 {
+    color
+        .iter()
+        .zip(INTENSITY)
+        .map(|(c, weight)| c * weight)
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{DynamicImage, GrayAlphaImage, LumaA, Rgb, RgbImage};
+
+    use super::*;
+
+    fn image() -> Image {
+        // dark blue, mid gray and light green
+        let colors = [[0, 0, 200], [128, 128, 128], [150, 250, 150]];
+        let pixels = RgbImage::from_fn(3, 1, |x, _| Rgb(colors[x as usize]));
+        Image::new(DynamicImage::ImageRgb8(pixels))
+    }
+
+    fn pixels(image: &Image) -> Vec<[u8; 3]> {
+        image
+            .pixels
+            .as_rgb8()
+            .unwrap()
+            .pixels()
+            .map(|p| p.0)
+            .collect()
+    }
+
+    #[test]
+    fn bilevel() {
+        let mut image = image();
+        threshold(&mut image, 0.5).unwrap();
+        assert_eq!(
+            pixels(&image),
+            [[0, 0, 0], [255, 255, 255], [255, 255, 255]]
+        );
+    }
+
+    #[test]
+    fn black_and_white() {
+        let mut image = image();
+        black_threshold(&mut image, 0.2).unwrap();
+        assert_eq!(
+            pixels(&image),
+            [[0, 0, 0], [128, 128, 128], [150, 250, 150]]
+        );
+        white_threshold(&mut image, 0.6).unwrap();
+        assert_eq!(
+            pixels(&image),
+            [[0, 0, 0], [128, 128, 128], [255, 255, 255]]
+        );
+    }
+
+    #[test]
+    fn alpha_is_kept() {
+        let pixels = GrayAlphaImage::from_pixel(1, 1, LumaA([100, 50]));
+        let mut image = Image::new(DynamicImage::ImageLumaA8(pixels));
+        threshold(&mut image, 0.3).unwrap();
+        assert_eq!(
+            image.pixels.as_luma_alpha8().unwrap().get_pixel(0, 0).0,
+            [255, 50]
+        );
+    }
+}
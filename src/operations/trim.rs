@@ -0,0 +1,145 @@
+use image::{DynamicImage, Rgba};
+
+use super::crop::{canvas, crop_region, transparent_pixel};
+use crate::{
+    arg_parsers::Fuzz, error::MagickError, image::Image, utils::color_distance::is_similar,
+};
+
+/// Implements `-trim`: removes edges that are the same color as the corners of the image.
+///
+/// Like imagemagick, the offset of the remaining part is recorded in the page geometry,
+/// so that it stays in the same place on the virtual canvas until `+repage`.
+pub fn trim(image: &mut Image, fuzz: &Fuzz) -> Result<(), MagickError> {
+    let page = canvas(image);
+    match bounding_box(&image.pixels, fuzz) {
+        Some((x, y, width, height)) => crop_region(
+            image,
+            page.x + i64::from(x),
+            page.y + i64::from(y),
+            width,
+            height,
+        ),
+        // Nothing but border
+        None => transparent_pixel(image, page),
+    }
+    Ok(())
+}
+
+/// Finds the part of the image that differs from the border, as `(x, y, width, height)`.
+/// Returns `None` if the whole image is the same color as the border.
+///
+/// Same as imagemagick, the left and top edges are compared against the top left corner,
+/// the right edge against the top right one and the bottom edge against the bottom left one.
+/// Used by `-trim` and the `%@` escape of `-format`.
+pub fn bounding_box(image: &DynamicImage, fuzz: &Fuzz) -> Option<(u32, u32, u32, u32)> {
+    let pixels = image.to_rgba32f();
+    let (width, height) = pixels.dimensions();
+    if width == 0 || height == 0 {
+        return None;
+    }
+    let top_left = *pixels.get_pixel(0, 0);
+    let top_right = *pixels.get_pixel(width - 1, 0);
+    let bottom_left = *pixels.get_pixel(0, height - 1);
+    let is_similar =
+        |a: &Rgba<f32>, b: &Rgba<f32>| is_similar(a.0.map(f64::from), b.0.map(f64::from), fuzz);
+    let (mut left, mut top) = (u32::MAX, u32::MAX);
+    let (mut right, mut bottom) = (None, None);
+    for (x, y, pixel) in pixels.enumerate_pixels() {
+        if !is_similar(pixel, &top_left) {
+            left = left.min(x);
+            top = top.min(y);
+        }
+        if !is_similar(pixel, &top_right) {
+            right = right.max(Some(x));
+        }
+        if !is_similar(pixel, &bottom_left) {
+            bottom = bottom.max(Some(y));
+        }
+    }
+    let (right, bottom) = (right?, bottom?);
+    if left > right || top > bottom {
+        return None;
+    }
+    Some((left, top, right - left + 1, bottom - top + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgb, RgbImage};
+
+    use super::*;
+    use crate::image::PageGeometry;
+    use std::str::FromStr;
+
+    fn framed_image() -> Image {
+        Image::new(DynamicImage::ImageRgb8(RgbImage::from_fn(10, 8, |x, y| {
+            if (2..7).contains(&x) && (3..5).contains(&y) {
+                Rgb([0, 0, 0])
+            } else if (x, y) == (0, 4) {
+                // a slightly off-white speck on the border
+                Rgb([250, 250, 250])
+            } else {
+                Rgb([255, 255, 255])
+            }
+        })))
+    }
+
+    #[test]
+    fn uniform_border_is_removed() {
+        let mut image = Image::new(framed_image().pixels.crop_imm(1, 0, 9, 8));
+        trim(&mut image, &Fuzz::default()).unwrap();
+        assert_eq!((image.width(), image.height()), (5, 2));
+        let page = image.page.unwrap();
+        assert_eq!((page.width, page.height, page.x, page.y), (9, 8, 1, 3));
+        assert_eq!(
+            image.pixels.as_rgb8().unwrap().get_pixel(0, 0),
+            &Rgb([0, 0, 0])
+        );
+    }
+
+    #[test]
+    fn empty_image_has_no_bounding_box() {
+        let pixels = DynamicImage::ImageRgb8(RgbImage::new(0, 0));
+        assert_eq!(bounding_box(&pixels, &Fuzz::default()), None);
+    }
+
+    #[test]
+    fn fuzz_ignores_small_differences() {
+        let mut image = framed_image();
+        trim(&mut image, &Fuzz::default()).unwrap();
+        assert_eq!((image.width(), image.height()), (7, 2));
+
+        let mut image = framed_image();
+        trim(&mut image, &Fuzz::from_str("5%").unwrap()).unwrap();
+        assert_eq!((image.width(), image.height()), (5, 2));
+    }
+
+    #[test]
+    fn offsets_add_up_on_the_canvas() {
+        let mut image = framed_image();
+        image.page = Some(PageGeometry {
+            width: 100,
+            height: 100,
+            x: 10,
+            y: 20,
+        });
+        trim(&mut image, &Fuzz::from_str("5%").unwrap()).unwrap();
+        assert_eq!(
+            image.page,
+            Some(PageGeometry {
+                width: 100,
+                height: 100,
+                x: 12,
+                y: 23,
+            })
+        );
+    }
+
+    #[test]
+    fn solid_image_becomes_a_single_pixel() {
+        let mut image = Image::new(DynamicImage::ImageRgb8(RgbImage::new(4, 4)));
+        trim(&mut image, &Fuzz::default()).unwrap();
+        assert_eq!((image.width(), image.height()), (1, 1));
+        assert_eq!(image.page.unwrap().x, -1);
+    }
+}
@@ -0,0 +1,215 @@
+//! Distortions that move pixels around the image: `-swirl`, `-implode` and `-wave`.
+//!
+//! Like imagemagick, they sample the image with `-interpolate`,
+//! and the parts that come from outside of the image are filled with `-background`.
+
+use std::f64::consts::PI;
+
+use super::{background::set_pixels, interpolate::interpolate};
+use crate::{
+    arg_parsers::{Color, InterpolateMethod, WaveGeometry},
+    error::MagickError,
+    image::Image,
+    limits::Limits,
+    utils::pool,
+};
+
+/// The center of the image, the radius of the largest circle around it that fits,
+/// and the scale that stretches the image into a square of that size
+fn circle(image: &Image) -> ((f64, f64), f64, (f64, f64)) {
+    let (width, height) = (f64::from(image.width()), f64::from(image.height()));
+    let center = (width / 2.0, height / 2.0);
+    if width > height {
+        (center, center.0, (1.0, width / height))
+    } else {
+        (center, center.1, (height / width, 1.0))
+    }
+}
+
+/// Implements `-swirl` like imagemagick's SwirlImage(): the pixels within the circle are rotated
+/// around the center by up to the given number of degrees, the most at the center and none at the edge.
+pub fn swirl(
+    image: &mut Image,
+    degrees: f64,
+    background: &Color,
+    method: InterpolateMethod,
+) -> Result<(), MagickError> {
+    let (center, radius, scale) = circle(image);
+    let radians = degrees.to_radians();
+    let source = pool::to_rgba32f(&image.pixels);
+    let fill = background.to_array();
+    let swirled = pool::rgba32f_from_fn(source.width(), source.height(), |x, y| {
+        let dx = scale.0 * (f64::from(x) + 0.5 - center.0);
+        let dy = scale.1 * (f64::from(y) + 0.5 - center.1);
+        let distance = (dx * dx + dy * dy).sqrt();
+        if distance >= radius {
+            return *source.get_pixel(x, y);
+        }
+        let factor = 1.0 - distance / radius;
+        let (sin, cos) = (radians * factor * factor).sin_cos();
+        image::Rgba(interpolate(
+            &source,
+            method,
+            (cos * dx - sin * dy) / scale.0 + center.0,
+            (sin * dx + cos * dy) / scale.1 + center.1,
+            fill,
+        ))
+    });
+    pool::recycle(source.into_raw());
+    set_pixels(image, swirled, background);
+    Ok(())
+}
+
+/// Implements `-implode` like imagemagick's ImplodeImage(): the pixels within the circle are pulled
+/// towards its center by the amount, or pushed away from it if the amount is negative.
+pub fn implode(
+    image: &mut Image,
+    amount: f64,
+    background: &Color,
+    method: InterpolateMethod,
+) -> Result<(), MagickError> {
+    let (center, radius, scale) = circle(image);
+    let source = pool::to_rgba32f(&image.pixels);
+    let fill = background.to_array();
+    let imploded = pool::rgba32f_from_fn(source.width(), source.height(), |x, y| {
+        let dx = scale.0 * (f64::from(x) + 0.5 - center.0);
+        let dy = scale.1 * (f64::from(y) + 0.5 - center.1);
+        let distance = (dx * dx + dy * dy).sqrt();
+        if distance >= radius {
+            return *source.get_pixel(x, y);
+        }
+        let factor = match distance > 0.0 {
+            true => (PI * distance / radius / 2.0).sin().powf(-amount),
+            false => 1.0,
+        };
+        image::Rgba(interpolate(
+            &source,
+            method,
+            factor * dx / scale.0 + center.0,
+            factor * dy / scale.1 + center.1,
+            fill,
+        ))
+    });
+    pool::recycle(source.into_raw());
+    set_pixels(image, imploded, background);
+    Ok(())
+}
+
+/// Implements `-wave` like imagemagick's WaveImage(): the columns are shifted vertically along
+/// a sine wave. The image grows by twice the amplitude to fit, and the new areas are the background.
+/// Huge amplitudes make it enormous, so its size is checked against the limits first.
+pub fn wave(
+    image: &mut Image,
+    geometry: &WaveGeometry,
+    background: &Color,
+    method: InterpolateMethod,
+    limits: &Limits,
+) -> Result<(), MagickError> {
+    let WaveGeometry {
+        amplitude,
+        wavelength,
+    } = *geometry;
+    let extra = (2.0 * amplitude.abs()).round();
+    // The result is built in floating point RGBA
+    let (width, height) = limits.check_new_image(
+        f64::from(image.width()),
+        f64::from(image.height()) + extra,
+        16,
+    )?;
+    let source = pool::to_rgba32f(&image.pixels);
+    let fill = background.to_array();
+    let offsets: Vec<f64> = (0..source.width())
+        .map(|x| amplitude.abs() + amplitude * (2.0 * PI * f64::from(x) / wavelength).sin())
+        .collect();
+    let waved = pool::rgba32f_from_fn(width, height, |x, y| {
+        image::Rgba(interpolate(
+            &source,
+            method,
+            f64::from(x) + 0.5,
+            f64::from(y) + 0.5 - offsets[x as usize],
+            fill,
+        ))
+    });
+    pool::recycle(source.into_raw());
+    set_pixels(image, waved, background);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{DynamicImage, GrayImage, Luma};
+
+    use super::*;
+
+    fn gradient() -> Image {
+        let pixels = GrayImage::from_fn(9, 9, |x, y| Luma([(x * 20 + y) as u8]));
+        Image::new(DynamicImage::ImageLuma8(pixels))
+    }
+
+    #[test]
+    fn swirl_leaves_the_corners_alone() {
+        let mut image = gradient();
+        swirl(&mut image, 90.0, &Color::WHITE, InterpolateMethod::Bilinear).unwrap();
+        let original = gradient().pixels.to_luma8();
+        let swirled = image.pixels.to_luma8();
+        assert_eq!(swirled.get_pixel(0, 0), original.get_pixel(0, 0));
+        assert_eq!(swirled.get_pixel(8, 8), original.get_pixel(8, 8));
+        assert_eq!(swirled.get_pixel(4, 4), original.get_pixel(4, 4));
+        assert_ne!(swirled.get_pixel(4, 2), original.get_pixel(4, 2));
+    }
+
+    #[test]
+    fn implode_pulls_towards_the_center() {
+        let mut image = gradient();
+        implode(&mut image, 1.0, &Color::WHITE, InterpolateMethod::Bilinear).unwrap();
+        let original = gradient().pixels.to_luma8();
+        let imploded = image.pixels.to_luma8();
+        // Pixels left of the center now show what was further left
+        assert!(imploded.get_pixel(3, 4)[0] < original.get_pixel(3, 4)[0]);
+        let mut zero = gradient();
+        implode(&mut zero, 0.0, &Color::WHITE, InterpolateMethod::Bilinear).unwrap();
+        assert_eq!(zero.pixels.to_luma8(), original);
+    }
+
+    #[test]
+    fn wave_grows_the_image() {
+        let mut image = gradient();
+        let geometry = WaveGeometry {
+            amplitude: 2.0,
+            wavelength: 8.0,
+        };
+        wave(
+            &mut image,
+            &geometry,
+            &Color::WHITE,
+            InterpolateMethod::Bilinear,
+            &Limits::default(),
+        )
+        .unwrap();
+        assert_eq!((image.width(), image.height()), (9, 13));
+        let pixels = image.pixels.to_luma8();
+        // The first column is shifted down by the amplitude, leaving background above it
+        assert_eq!(pixels.get_pixel(0, 0)[0], 255);
+        assert_eq!(
+            pixels.get_pixel(0, 2),
+            gradient().pixels.to_luma8().get_pixel(0, 0)
+        );
+    }
+
+    #[test]
+    fn huge_amplitude_is_rejected() {
+        let mut image = gradient();
+        let geometry = WaveGeometry {
+            amplitude: 1e9,
+            wavelength: 10.0,
+        };
+        let result = wave(
+            &mut image,
+            &geometry,
+            &Color::WHITE,
+            InterpolateMethod::Bilinear,
+            &Limits::default(),
+        );
+        assert!(result.is_err());
+    }
+}
@@ -1,21 +1,1054 @@
-use std::ffi::OsString;
+use std::collections::BTreeMap;
+use std::ffi::{OsStr, OsString};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
 
-use crate::operations::Operation;
+use image::ImageFormat;
+
+use crate::arg_parsers::{
+    parse_finite_arg, parse_numeric_arg, parse_quantum_arg, BlendPercent, BlurGeometry,
+    BrightnessContrast, ChannelFx, ChannelMask, Color, Colorspace, CropGeometry, DitherMethod,
+    EvaluateOperator, FrameSelection, Fuzz, Gravity, IdentifyFormat, InputFileArg,
+    InterpolateMethod, Modulate, MotionBlurGeometry, NoiseType, OrderedDither, RaiseGeometry,
+    ReadModifier, ResizeConstraint, ResizeGeometry, ResourceType, RotateGeometry, SepiaThreshold,
+    ShadowGeometry, ShearGeometry, StatisticType, StatisticWindow, StretchGeometry,
+    UnsharpGeometry, WaveGeometry,
+};
+use crate::args::{Arg, SignedArg};
+use crate::decode::{decode, decode_frames};
+use crate::encode::{encode_frames, split_format_prefix, writes_multiple_frames};
+use crate::file_format::FileFormat;
+use crate::fx::Expr;
+use crate::image::Image;
+use crate::journal::Journal;
+use crate::limits::Limits;
+use crate::pseudo::{PseudoImage, PseudoOutput};
+use crate::quantize::{Quantizer, MAX_TREE_DEPTH};
+use crate::utils::number_format::DEFAULT_PRECISION;
+use crate::utils::pool;
+use crate::utils::random;
+use crate::utils::timer::{format_times, Stopwatch};
+use crate::{
+    error::MagickError,
+    operations::{palette_of, Operation},
+    wm_err, wm_try,
+};
+
+/// The output file that discards the images, for runs that only print information such as `-identify`
+pub const NULL_OUTPUT: &str = "null:";
+
+/// The JPEG quality of `--wm-web-thumbnail`, a common choice for images on the web
+const WEB_THUMBNAIL_QUALITY: u8 = 82;
 
 /// Plan of operations for the whole run over multiple files
 #[derive(Debug, Default)]
 pub struct ExecutionPlan {
     pub output_file: OsString,
     pub input_files: Vec<FilePlan>,
+    pub modifiers: Modifiers,
+    /// Custom operations that can be applied by name with [ExecutionPlan::apply_plugin]
+    #[cfg(feature = "plugins")]
+    pub plugins: BTreeMap<String, crate::plugin::Plugin>,
+}
+
+/// Settings that are not operations by themselves, but change how the plan is executed
+#[derive(Debug, Default, Clone)]
+pub struct Modifiers {
+    /// `-verbose`: print a status line for every file and a summary at the end
+    pub verbose: bool,
+    /// `-regard-warnings`: abort the whole run on the first file that fails.
+    /// By default a failing file is reported and skipped, like imagemagick does.
+    pub regard_warnings: bool,
+    /// `-update`: skip input files whose output file was modified after them
+    pub update: bool,
+    /// `-preview`: print which files would be written instead of processing anything
+    pub preview: bool,
+    /// `--wm-journal`: the file that records completed inputs, to resume interrupted runs
+    pub journal: Option<PathBuf>,
+    /// `--wm-no-srgb-fallback`: write the colors of images with an ICC profile as they are
+    /// even if the output format cannot hold the profile, rather than converting them to sRGB
+    pub no_srgb_fallback: bool,
+    /// `--wm-untrusted`: only read and write common web formats, and limit resources
+    /// unless `-limit` says otherwise, for processing images from untrusted sources
+    pub untrusted: bool,
+    /// `-comment`: the comment of images read after it
+    pub comment: Option<IdentifyFormat>,
+    /// `-label`: the label of images read after it
+    pub label: Option<IdentifyFormat>,
+    /// `-format`: template for `-identify`
+    pub format: Option<IdentifyFormat>,
+    /// `-format` naming an image format, such as `-format png`: the output format type.
+    /// Used when the output filename does not determine the format by itself.
+    pub output_format: Option<ImageFormat>,
+    /// `-limit` and `MAGICK_*_LIMIT` environment variables
+    pub limits: Limits,
+    /// `-define key=value` options, with lowercased keys
+    pub defines: BTreeMap<String, String>,
+    /// `-precision`: significant digits of floats in `-identify` and `-format` output
+    pub precision: Option<usize>,
+    /// `-define registry:temporary-path=...` or the `MAGICK_TEMPORARY_PATH` environment variable
+    pub temporary_path: Option<PathBuf>,
+    /// `-background`: the color of areas that operations such as `-rotate` add to the image
+    pub background: Option<Color>,
+    /// `-fuzz`: how different colors can be while still counting as equal, e.g. for `-trim` and `-opaque`
+    pub fuzz: Fuzz,
+    /// `-channel`: the channels operations such as `-evaluate` apply to
+    pub channel: ChannelMask,
+    /// `-size`: the size of generated images such as `caption:`; either dimension may be missing
+    pub size: (Option<u32>, Option<u32>),
+    /// `-pointsize`: the size of text. Text is scaled to fit `-size` if unset.
+    pub pointsize: Option<f64>,
+    /// `-font`: path to the font to draw text with
+    pub font: Option<OsString>,
+    /// `-fill`: the color of text, and of `-colorize`, `-tint` and `-opaque`
+    pub fill: Option<Color>,
+    /// `-stroke`: the color of the outline of text, which has none by default
+    pub stroke: Option<Color>,
+    /// `-strokewidth`: the width of the outline of text, 1 by default
+    pub stroke_width: Option<f64>,
+    /// `-undercolor`: the color of the box drawn behind text, which has none by default
+    pub undercolor: Option<Color>,
+    /// `-kerning`: pixels added between characters of text, may be negative
+    pub kerning: f64,
+    /// `-interline-spacing`: pixels added between lines of text, may be negative
+    pub interline_spacing: f64,
+    /// `-interword-spacing`: pixels added to every space in text, may be negative
+    pub interword_spacing: f64,
+    /// `-bench`: run the whole plan this many times and print the throughput
+    pub bench: Option<usize>,
+    /// `-interpolate`: how `-rotate`, `-shear` and other distortions sample the image between pixel centers
+    pub interpolate: InterpolateMethod,
+    /// `-gravity`: what geometry offsets of operations such as `-crop` and `-extent` are relative to
+    pub gravity: Gravity,
+    /// `-quantize`: the colorspace colors are reduced in, e.g. when writing GIF
+    pub quantize_colorspace: Colorspace,
+    /// `-treedepth`: the depth of the octree colors are reduced with, 0 to choose automatically
+    pub tree_depth: Option<u8>,
+    /// `-adjoin` or `+adjoin`: whether images go into a single file if the format can hold several
+    pub adjoin: Option<bool>,
+    /// `-dither`, or `+dither` to turn dithering off, for color reduction with `-colors`
+    pub dither: Option<DitherMethod>,
+    /// `-attenuate`: how strong the noise added by `+noise` is, 1 by default
+    pub attenuate: Option<f64>,
+    /// `-loop`: how many times GIF animations play, 0 for forever
+    pub loop_iterations: Option<u16>,
+    /// The JPEG quality, set by `--wm-web-thumbnail`. imagemagick's default is used if unset.
+    pub quality: Option<u8>,
+    /// `-transparent-color`: the color stored for transparent pixels in palettes such as that of GIF
+    pub transparent_color: Option<Color>,
+}
+
+impl Modifiers {
+    /// Initializes the modifiers from `MAGICK_*` environment variables
+    pub fn from_env() -> Self {
+        Self {
+            limits: Limits::from_env(),
+            temporary_path: std::env::var_os("MAGICK_TEMPORARY_PATH").map(PathBuf::from),
+            ..Default::default()
+        }
+    }
+
+    /// Rejects formats other than the common web ones with `--wm-untrusted`
+    pub fn check_format_allowed(&self, format: FileFormat) -> Result<(), MagickError> {
+        if self.untrusted && !format.is_web_safe() {
+            return Err(wm_err!(
+                "attempt to perform an operation not allowed by the security policy `{}'",
+                format.name()
+            ));
+        }
+        Ok(())
+    }
+
+    /// Number of significant digits to print floats with
+    pub fn precision(&self) -> usize {
+        self.precision.unwrap_or(DEFAULT_PRECISION)
+    }
+
+    /// The background color, white unless set otherwise like in imagemagick
+    pub fn background(&self) -> Color {
+        self.background.unwrap_or(Color::WHITE)
+    }
+
+    /// The fill color, black unless set otherwise like in imagemagick
+    pub fn fill(&self) -> Color {
+        self.fill.unwrap_or(Color::BLACK)
+    }
+
+    /// The dithering method, Floyd-Steinberg unless set otherwise like in imagemagick
+    pub fn dither(&self) -> DitherMethod {
+        self.dither.unwrap_or_default()
+    }
+
+    /// How strong added noise is relative to imagemagick's defaults
+    pub fn attenuate(&self) -> f64 {
+        self.attenuate.unwrap_or(1.0)
+    }
+
+    /// The color reduction settings of `-dither`, `-quantize` and `-treedepth`
+    pub fn quantizer(&self, colors: usize) -> Quantizer {
+        let mut quantizer = Quantizer {
+            colors,
+            dither: self.dither(),
+            colorspace: self.quantize_colorspace,
+            ..Default::default()
+        };
+        if let Some(depth) = self.tree_depth {
+            quantizer.tree_depth = depth;
+        }
+        quantizer
+    }
+
+    /// Whether images are joined into a single file if the format allows, which imagemagick does by default
+    pub fn adjoin(&self) -> bool {
+        self.adjoin.unwrap_or(true)
+    }
+
+    /// The width of the outline of text, 1 pixel unless set otherwise like in imagemagick
+    pub fn stroke_width(&self) -> f64 {
+        self.stroke_width.unwrap_or(1.0)
+    }
+
+    /// Looks up the value of a `-define`, e.g. `gif:dither`
+    pub fn define(&self, key: &str) -> Option<&str> {
+        self.defines
+            .get(&key.to_ascii_lowercase())
+            .map(|value| value.as_str())
+    }
+
+    /// Parses the value of a `-define`, e.g. `webp:method`, if it is set
+    pub fn parse_define<T: FromStr>(&self, key: &str) -> Result<Option<T>, MagickError> {
+        match self.define(key) {
+            None => Ok(None),
+            Some(value) => match value.trim().parse() {
+                Ok(value) => Ok(Some(value)),
+                Err(_) => Err(wm_err!("invalid value for `-define {}': {}", key, value)),
+            },
+        }
+    }
+
+    fn add_define(&mut self, definition: &OsStr) -> Result<(), MagickError> {
+        let definition = definition
+            .to_str()
+            .ok_or_else(|| wm_err!("invalid define: {}", definition.to_string_lossy()))?;
+        // `-define key` without a value is accepted by imagemagick and sets the value to an empty string
+        let (key, value) = definition.split_once('=').unwrap_or((definition, ""));
+        let key = key.to_ascii_lowercase();
+        if key == "registry:temporary-path" {
+            self.temporary_path = Some(PathBuf::from(value));
+        }
+        self.defines.insert(key, value.to_owned());
+        Ok(())
+    }
 }
 
 impl ExecutionPlan {
+    pub fn apply_arg(&mut self, arg: SignedArg, values: &[&OsStr]) -> Result<(), MagickError> {
+        if arg.value_count() != values.len() {
+            return Err(wm_err!("argument requires a value"));
+        };
+
+        match arg.arg {
+            Arg::Resize => {
+                self.add_operation(Operation::Resize(ResizeGeometry::try_from(values[0])?))
+            }
+            Arg::Thumbnail => {
+                self.add_operation(Operation::Thumbnail(ResizeGeometry::try_from(values[0])?))
+            }
+            Arg::Scale => {
+                self.add_operation(Operation::Scale(ResizeGeometry::try_from(values[0])?))
+            }
+            Arg::Sample => {
+                self.add_operation(Operation::Sample(ResizeGeometry::try_from(values[0])?))
+            }
+            Arg::AutoOrient => self.add_operation(Operation::AutoOrient),
+            Arg::Flip => self.add_operation(Operation::Flip),
+            Arg::Flop => self.add_operation(Operation::Flop),
+            Arg::Rotate => self.add_operation(Operation::Rotate {
+                geometry: RotateGeometry::try_from(values[0])?,
+                background: self.modifiers.background(),
+                interpolate: self.modifiers.interpolate,
+            }),
+            Arg::Shear => self.add_operation(Operation::Shear {
+                geometry: ShearGeometry::try_from(values[0])?,
+                background: self.modifiers.background(),
+                interpolate: self.modifiers.interpolate,
+            }),
+            Arg::Swirl => self.add_operation(Operation::Swirl {
+                degrees: parse_finite_arg("swirl", values[0])?,
+                background: self.modifiers.background(),
+                interpolate: self.modifiers.interpolate,
+            }),
+            Arg::Implode => self.add_operation(Operation::Implode {
+                amount: parse_finite_arg("implode", values[0])?,
+                background: self.modifiers.background(),
+                interpolate: self.modifiers.interpolate,
+            }),
+            Arg::Wave => self.add_operation(Operation::Wave {
+                geometry: WaveGeometry::try_from(values[0])?,
+                background: self.modifiers.background(),
+                interpolate: self.modifiers.interpolate,
+            }),
+            Arg::Crop => self.add_operation(Operation::Crop {
+                geometry: CropGeometry::try_from(values[0])?,
+                gravity: self.modifiers.gravity,
+            }),
+            Arg::Shave => self.add_operation(Operation::Shave(CropGeometry::try_from(values[0])?)),
+            Arg::Extent => self.add_operation(Operation::Extent {
+                geometry: CropGeometry::try_from(values[0])?,
+                gravity: self.modifiers.gravity,
+                background: self.modifiers.background(),
+            }),
+            Arg::Trim => self.add_operation(Operation::Trim(self.modifiers.fuzz)),
+            Arg::Strip => self.add_operation(Operation::Strip),
+            Arg::AutoLevel => self.add_operation(Operation::AutoLevel),
+            Arg::AutoGamma => self.add_operation(Operation::AutoGamma),
+            Arg::Gamma => {
+                let gamma = parse_finite_arg("gamma", values[0])?;
+                match arg.is_plus() {
+                    true => self.add_operation(Operation::SetGamma(gamma)),
+                    false => self.add_operation(Operation::Gamma(gamma)),
+                }
+            }
+            Arg::BrightnessContrast => self.add_operation(Operation::BrightnessContrast(
+                BrightnessContrast::try_from(values[0])?,
+            )),
+            Arg::Normalize => self.add_operation(Operation::Normalize),
+            Arg::ContrastStretch => self.add_operation(Operation::ContrastStretch(
+                StretchGeometry::try_from(values[0])?,
+            )),
+            Arg::LinearStretch => self.add_operation(Operation::LinearStretch(
+                StretchGeometry::try_from(values[0])?,
+            )),
+            Arg::Modulate => self.add_operation(Operation::Modulate {
+                modulate: Modulate::try_from(values[0])?,
+                colorspace: self
+                    .modifiers
+                    .parse_define("modulate:colorspace")?
+                    .unwrap_or_default(),
+            }),
+            Arg::Posterize => self.add_operation(Operation::Posterize {
+                levels: parse_numeric_arg("posterize", values[0])?,
+                dither: self.modifiers.dither(),
+            }),
+            Arg::Unsharp => {
+                self.add_operation(Operation::Unsharp(UnsharpGeometry::try_from(values[0])?))
+            }
+            Arg::AdaptiveBlur => self.add_operation(Operation::AdaptiveBlur(
+                BlurGeometry::from_arg("adaptive-blur", values[0])?,
+            )),
+            Arg::AdaptiveSharpen => self.add_operation(Operation::AdaptiveSharpen(
+                BlurGeometry::from_arg("adaptive-sharpen", values[0])?,
+            )),
+            Arg::MotionBlur => self.add_operation(Operation::MotionBlur(
+                MotionBlurGeometry::try_from(values[0])?,
+            )),
+            Arg::RotationalBlur => self.add_operation(Operation::RotationalBlur {
+                angle: parse_finite_arg("rotational-blur", values[0])?,
+                interpolate: self.modifiers.interpolate,
+            }),
+            Arg::Statistic => self.add_operation(Operation::Statistic {
+                kind: StatisticType::try_from(values[0])?,
+                window: StatisticWindow::try_from(values[1])?,
+            }),
+            Arg::Median => self.add_operation(Operation::Statistic {
+                kind: StatisticType::Median,
+                window: StatisticWindow::try_from(values[0])?,
+            }),
+            Arg::Despeckle => self.add_operation(Operation::Despeckle),
+            Arg::Raise => self.add_operation(Operation::Raise {
+                geometry: RaiseGeometry::try_from(values[0])?,
+                raised: !arg.is_plus(),
+            }),
+            Arg::Shadow => self.add_operation(Operation::Shadow {
+                geometry: ShadowGeometry::try_from(values[0])?,
+                background: self.modifiers.background(),
+            }),
+            Arg::Edge => self.add_operation(Operation::Edge(parse_finite_arg("edge", values[0])?)),
+            Arg::Emboss => self.add_operation(Operation::Emboss(BlurGeometry::from_arg(
+                "emboss", values[0],
+            )?)),
+            Arg::Charcoal => self.add_operation(Operation::Charcoal(BlurGeometry::from_arg(
+                "charcoal", values[0],
+            )?)),
+            Arg::Sketch => self.add_operation(Operation::Sketch(MotionBlurGeometry::from_arg(
+                "sketch", values[0],
+            )?)),
+            Arg::Noise => match arg.is_plus() {
+                true => self.add_operation(Operation::AddNoise {
+                    kind: NoiseType::try_from(values[0])?,
+                    attenuate: self.modifiers.attenuate(),
+                    channels: self.modifiers.channel,
+                }),
+                // Reducing noise is a filter that replaces peaks with the values around them
+                false => self.add_operation(Operation::Statistic {
+                    kind: StatisticType::Nonpeak,
+                    window: StatisticWindow::try_from(values[0])?,
+                }),
+            },
+            Arg::OrderedDither => self.add_operation(Operation::OrderedDither {
+                dither: OrderedDither::try_from(values[0])?,
+                channels: self.modifiers.channel,
+            }),
+            Arg::Colors => {
+                let colors: usize = parse_numeric_arg("colors", values[0])?;
+                if colors == 0 {
+                    return Err(wm_err!(
+                        "invalid argument for option `-colors': {}",
+                        values[0].to_string_lossy()
+                    ));
+                }
+                self.add_operation(Operation::Colors(self.modifiers.quantizer(colors)))
+            }
+            Arg::Monochrome => {
+                self.add_operation(Operation::Monochrome(self.modifiers.quantizer(2)))
+            }
+            Arg::Remap => {
+                // imagemagick reads the palette image right away too
+                let palette_image = decode(values[0], None, &self.modifiers)?;
+                self.add_operation(Operation::Remap {
+                    palette: palette_of(&palette_image)?,
+                    quantizer: self.modifiers.quantizer(0),
+                })
+            }
+            Arg::Negate => self.add_operation(Operation::Negate {
+                gray_only: arg.is_plus(),
+                channels: self.modifiers.channel,
+            }),
+            Arg::Fx => {
+                let expr = values[0].to_str().ok_or_else(|| {
+                    wm_err!(
+                        "unable to parse expression `{}'",
+                        values[0].to_string_lossy()
+                    )
+                })?;
+                self.add_operation(Operation::Fx {
+                    expr: Expr::parse(expr)?,
+                    channels: self.modifiers.channel,
+                })
+            }
+            Arg::Evaluate => {
+                let operator = EvaluateOperator::try_from(values[0])?;
+                let constant = match operator.takes_sample_value() {
+                    true => parse_quantum_arg("evaluate", values[1])?,
+                    false => parse_finite_arg("evaluate", values[1])?,
+                };
+                self.add_operation(Operation::Evaluate {
+                    operator,
+                    constant,
+                    channels: self.modifiers.channel,
+                })
+            }
+            Arg::SepiaTone => {
+                self.add_operation(Operation::SepiaTone(SepiaThreshold::try_from(values[0])?))
+            }
+            Arg::Opaque => self.add_operation(Operation::Opaque {
+                target: Color::try_from(values[0])?,
+                fill: self.modifiers.fill(),
+                fuzz: self.modifiers.fuzz,
+                invert: arg.is_plus(),
+            }),
+            Arg::Transparent => self.add_operation(Operation::Transparent {
+                target: Color::try_from(values[0])?,
+                fuzz: self.modifiers.fuzz,
+                invert: arg.is_plus(),
+            }),
+            Arg::Separate => self.add_operation(Operation::Separate),
+            Arg::Colorspace => {
+                self.add_operation(Operation::Colorspace(Colorspace::try_from(values[0])?))
+            }
+            Arg::ChannelFx => {
+                self.add_operation(Operation::ChannelFx(ChannelFx::try_from(values[0])?))
+            }
+            Arg::Colorize => self.add_operation(Operation::Colorize {
+                blend: BlendPercent::try_from(values[0])?,
+                fill: self.modifiers.fill(),
+            }),
+            Arg::Tint => self.add_operation(Operation::Tint {
+                weight: BlendPercent::try_from(values[0])?,
+                fill: self.modifiers.fill(),
+            }),
+            Arg::Threshold => self.add_operation(Operation::Threshold(parse_quantum_arg(
+                "threshold",
+                values[0],
+            )?)),
+            Arg::BlackThreshold => self.add_operation(Operation::BlackThreshold(
+                parse_quantum_arg("black-threshold", values[0])?,
+            )),
+            Arg::WhiteThreshold => self.add_operation(Operation::WhiteThreshold(
+                parse_quantum_arg("white-threshold", values[0])?,
+            )),
+            Arg::Comment => {
+                // Applies both to the images read so far and to the ones read later, like in imagemagick
+                self.modifiers.comment = match arg.is_plus() {
+                    true => None,
+                    false => Some(IdentifyFormat::try_from(values[0])?),
+                };
+                self.add_operation(Operation::Comment(self.modifiers.comment.clone()));
+            }
+            Arg::Label => {
+                self.modifiers.label = match arg.is_plus() {
+                    true => None,
+                    false => Some(IdentifyFormat::try_from(values[0])?),
+                };
+                self.add_operation(Operation::Label(self.modifiers.label.clone()));
+            }
+            Arg::Set => {
+                let key = values[0]
+                    .to_str()
+                    .ok_or_else(|| wm_err!("invalid argument `{}'", values[0].to_string_lossy()))?
+                    .to_ascii_lowercase();
+                let value = match arg.is_plus() {
+                    true => None,
+                    false => Some(IdentifyFormat::try_from(values[1])?),
+                };
+                self.add_operation(Operation::Set { key, value });
+            }
+            Arg::Identify => self.add_operation(Operation::Identify {
+                format: self.modifiers.format.clone(),
+                verbose: self.modifiers.verbose,
+                precision: self.modifiers.precision(),
+                fuzz: self.modifiers.fuzz,
+            }),
+            Arg::Format => {
+                // `-format` is overloaded: it is both the template for `-identify`
+                // and the output format type, so a plain format name is recorded as both
+                self.modifiers.format = Some(IdentifyFormat::try_from(values[0])?);
+                self.modifiers.output_format = output_format_type(values[0]);
+            }
+            Arg::Precision => {
+                self.modifiers.precision = Some(parse_numeric_arg("precision", values[0])?)
+            }
+            Arg::Verbose => self.modifiers.verbose = true,
+            Arg::Bench => self.modifiers.bench = Some(parse_numeric_arg("bench", values[0])?),
+            Arg::RegardWarnings => self.modifiers.regard_warnings = true,
+            Arg::Update => self.modifiers.update = !arg.is_plus(),
+            Arg::Adjoin => self.modifiers.adjoin = Some(!arg.is_plus()),
+            Arg::Dither => {
+                self.modifiers.dither = Some(match arg.is_plus() {
+                    true => DitherMethod::None,
+                    false => DitherMethod::try_from(values[0])?,
+                })
+            }
+            Arg::Attenuate => {
+                self.modifiers.attenuate = Some(parse_finite_arg("attenuate", values[0])?)
+            }
+            Arg::Seed => match arg.is_plus() {
+                true => random::reset_seed(),
+                false => random::set_seed(parse_numeric_arg("seed", values[0])?),
+            },
+            Arg::TransparentColor => {
+                self.modifiers.transparent_color = Some(Color::try_from(values[0])?)
+            }
+            Arg::Loop => {
+                self.modifiers.loop_iterations = Some(parse_numeric_arg("loop", values[0])?)
+            }
+            Arg::Preview => self.modifiers.preview = !arg.is_plus(),
+            Arg::WmJournal => self.modifiers.journal = Some(PathBuf::from(values[0])),
+            Arg::WmNoSrgbFallback => self.modifiers.no_srgb_fallback = true,
+            Arg::WmWebThumbnail => self.add_web_thumbnail(values[0])?,
+            Arg::WmUntrusted => {
+                self.modifiers.untrusted = true;
+                self.modifiers.limits.set_untrusted_defaults();
+            }
+            Arg::Define => self.modifiers.add_define(values[0])?,
+            Arg::Limit => {
+                let resource = ResourceType::try_from(values[0])?;
+                self.modifiers.limits.set(resource, values[1])?
+            }
+            Arg::Background => self.modifiers.background = Some(Color::try_from(values[0])?),
+            Arg::Fuzz => self.modifiers.fuzz = Fuzz::try_from(values[0])?,
+            Arg::Channel => {
+                self.modifiers.channel = match arg.is_plus() {
+                    true => ChannelMask::default(),
+                    false => ChannelMask::try_from(values[0])?,
+                }
+            }
+            Arg::Gravity => self.modifiers.gravity = Gravity::try_from(values[0])?,
+            Arg::Quantize => self.modifiers.quantize_colorspace = Colorspace::try_from(values[0])?,
+            Arg::Treedepth => {
+                let depth: usize = parse_numeric_arg("treedepth", values[0])?;
+                self.modifiers.tree_depth = Some(depth.min(usize::from(MAX_TREE_DEPTH)) as u8);
+            }
+            Arg::Interpolate => {
+                self.modifiers.interpolate = InterpolateMethod::try_from(values[0])?
+            }
+            Arg::Size => {
+                let (width, height) = CropGeometry::try_from(values[0])?.dimensions(0, 0);
+                // A zero dimension is as good as a missing one
+                self.modifiers.size = (width.filter(|&w| w > 0), height.filter(|&h| h > 0));
+            }
+            Arg::Pointsize => {
+                let pointsize: f64 = parse_numeric_arg("pointsize", values[0])?;
+                if !(pointsize.is_finite() && pointsize > 0.0) {
+                    return Err(wm_err!(
+                        "invalid argument for option `-pointsize': {}",
+                        values[0].to_string_lossy()
+                    ));
+                }
+                self.modifiers.pointsize = Some(pointsize);
+            }
+            Arg::Font => self.modifiers.font = Some(values[0].to_owned()),
+            Arg::Fill => self.modifiers.fill = Some(Color::try_from(values[0])?),
+            Arg::Stroke => self.modifiers.stroke = Some(Color::try_from(values[0])?),
+            Arg::Strokewidth => {
+                let width = parse_finite_arg("strokewidth", values[0])?;
+                if width < 0.0 {
+                    return Err(wm_err!(
+                        "invalid argument for option `-strokewidth': {}",
+                        values[0].to_string_lossy()
+                    ));
+                }
+                self.modifiers.stroke_width = Some(width);
+            }
+            Arg::Undercolor => self.modifiers.undercolor = Some(Color::try_from(values[0])?),
+            Arg::Kerning => self.modifiers.kerning = parse_finite_arg("kerning", values[0])?,
+            Arg::InterlineSpacing => {
+                self.modifiers.interline_spacing = parse_finite_arg("interline-spacing", values[0])?
+            }
+            Arg::InterwordSpacing => {
+                self.modifiers.interword_spacing = parse_finite_arg("interword-spacing", values[0])?
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds an input file, or an image generated from the settings in effect such as `label:Hello`
+    pub fn add_input(&mut self, arg: &OsStr) -> Result<(), MagickError> {
+        let mut file_plan = match PseudoImage::parse(arg, &self.modifiers) {
+            Some(pseudo_image) => FilePlan {
+                pseudo_image: Some(pseudo_image),
+                ..FilePlan::new(arg.to_owned())
+            },
+            None => FilePlan::from_input(InputFileArg::try_from(arg)?),
+        };
+        if let Some(comment) = &self.modifiers.comment {
+            file_plan
+                .ops
+                .push(Operation::Comment(Some(comment.clone())));
+        }
+        if let Some(label) = &self.modifiers.label {
+            file_plan.ops.push(Operation::Label(Some(label.clone())));
+        }
+        self.input_files.push(file_plan);
+        Ok(())
+    }
+
+    /// Makes the plugin available to [ExecutionPlan::apply_plugin] under its name,
+    /// replacing any previously registered plugin with the same name
+    #[cfg(feature = "plugins")]
+    pub fn register_plugin(&mut self, plugin: crate::plugin::Plugin) {
+        self.plugins.insert(plugin.name().to_owned(), plugin);
+    }
+
+    /// Adds a registered plugin to the plan, same as a built-in operation would be added
+    #[cfg(feature = "plugins")]
+    pub fn apply_plugin(&mut self, name: &str) -> Result<(), MagickError> {
+        let Some(plugin) = self.plugins.get(name) else {
+            return Err(wm_err!("unrecognized plugin `{}'", name));
+        };
+        self.add_operation(Operation::Plugin(plugin.clone()));
+        Ok(())
+    }
+
+    /// Expands `--wm-web-thumbnail` into what it stands for: `-auto-orient`, `-thumbnail` that never enlarges,
+    /// conversion to sRGB, `-strip` and a JPEG quality suited to the web.
+    ///
+    /// The steps are added like the options would be, so the preset behaves exactly like them.
+    /// The colors are converted after resizing, which is cheaper, and before stripping,
+    /// which would throw away the ICC profile they are converted from.
+    fn add_web_thumbnail(&mut self, geometry: &OsStr) -> Result<(), MagickError> {
+        let mut geometry = ResizeGeometry::try_from(geometry)?;
+        if geometry.constraint == ResizeConstraint::Unconstrained {
+            geometry.constraint = ResizeConstraint::OnlyShrink;
+        }
+        self.apply_arg(Arg::AutoOrient.into(), &[])?;
+        self.add_operation(Operation::Thumbnail(geometry));
+        self.add_operation(Operation::ConvertToSrgb);
+        self.apply_arg(Arg::Strip.into(), &[])?;
+        // Thumbnails are small and viewed at a glance, so artifacts matter less than size
+        self.modifiers.quality.get_or_insert(WEB_THUMBNAIL_QUALITY);
+        Ok(())
+    }
+
     pub fn add_operation(&mut self, op: Operation) {
         // Operations such as -resize apply to all the files already listed,
         // but not subsequent ones
         for file_plan in &mut self.input_files {
-            file_plan.ops.push(op)
+            file_plan.ops.push(op.clone())
+        }
+    }
+
+    /// Checks if the images are not written to a file, as with `null:` or `info:`.
+    /// Encoding is skipped entirely then, so the output format does not have to be writable.
+    pub fn discards_output(&self) -> bool {
+        PseudoOutput::parse(&self.output_file).is_some()
+    }
+
+    /// Returns the output filename for the image with the given sequence number, if any.
+    ///
+    /// When several images are written, imagemagick writes them to separate files
+    /// with the sequence number inserted before the extension: `out-0.png`, `out-1.png` and so on.
+    pub fn output_location(&self, index: Option<usize>) -> OsString {
+        let Some(index) = index else {
+            return self.output_file.clone();
+        };
+        let path = Path::new(&self.output_file);
+        let mut name = path.with_extension("").into_os_string();
+        name.push(format!("-{index}"));
+        if let Some(ext) = path.extension() {
+            name.push(".");
+            name.push(ext);
+        }
+        name
+    }
+
+    /// Splits off the format prefix of the output file, such as `png:` in `png:-`.
+    ///
+    /// Without a prefix the extension determines the format, and if there is no recognizable extension
+    /// either, the format set by `-format png` is used.
+    fn split_output_format<'a>(
+        &self,
+        file: &'a OsStr,
+    ) -> Result<(Option<ImageFormat>, &'a OsStr), MagickError> {
+        let (format, file) = split_format_prefix(file)?;
+        if format.is_some() {
+            return Ok((format, file));
+        }
+        let known_extension = Path::new(file)
+            .extension()
+            .is_some_and(|ext| FileFormat::from_extension(&ext.to_string_lossy()).is_some());
+        match known_extension {
+            true => Ok((None, file)),
+            false => Ok((self.modifiers.output_format, file)),
+        }
+    }
+
+    /// Runs the plan over every input file, repeatedly if `-bench` is set.
+    ///
+    /// Failures on individual files are reported to stderr and do not stop the rest of the batch,
+    /// unless `-regard-warnings` is in effect. If any file failed, the returned exit code is nonzero.
+    pub fn execute(&self) -> Result<ExitCode, MagickError> {
+        // Text such as `-identify` output goes through a single buffer for the whole batch
+        let mut output = BufWriter::new(std::io::stdout().lock());
+        let exit_code = self.execute_to(&mut output);
+        wm_try!(output.flush());
+        exit_code
+    }
+
+    /// Runs the plan like [ExecutionPlan::execute], writing text such as `-identify` output to `output`
+    pub fn execute_to(&self, output: &mut dyn Write) -> Result<ExitCode, MagickError> {
+        let iterations = self.modifiers.bench.unwrap_or(1);
+        // Like imagemagick, a single iteration is a regular run without the summary
+        if iterations <= 1 {
+            return self.execute_once(output);
+        }
+        let stopwatch = Stopwatch::start();
+        let mut exit_code = ExitCode::SUCCESS;
+        for _ in 0..iterations {
+            if self.execute_once(output)? != ExitCode::SUCCESS {
+                exit_code = ExitCode::FAILURE;
+            }
+        }
+        let performance = Performance {
+            iterations,
+            elapsed: stopwatch.elapsed(),
+            cpu_time: stopwatch.cpu_time(),
+        };
+        eprintln!("{}", performance);
+        Ok(exit_code)
+    }
+
+    fn execute_once(&self, output: &mut dyn Write) -> Result<ExitCode, MagickError> {
+        let mut stats = BatchStats::default();
+        let start = Instant::now();
+        if self.joins_inputs()? {
+            self.execute_joined(&mut stats, output)?;
+            stats.elapsed = start.elapsed();
+            return Ok(match stats.failed {
+                0 => ExitCode::SUCCESS,
+                _ => ExitCode::FAILURE,
+            });
+        }
+        let mut output_index = 0;
+        let mut journal = match &self.modifiers.journal {
+            Some(path) => Some(Journal::open(path)?),
+            None => None,
+        };
+        for file_plan in &self.input_files {
+            if let Some(outputs) = journal
+                .as_ref()
+                .and_then(|j| j.completed(&file_plan.filename))
+            {
+                output_index += outputs;
+                continue;
+            }
+            let first_output = output_index;
+            match self.execute_file(file_plan, &mut output_index, &mut stats, output) {
+                Ok(()) => {
+                    // Nothing is written in a preview, so there is nothing to record
+                    if let Some(journal) = journal.as_mut().filter(|_| !self.modifiers.preview) {
+                        journal.record(&file_plan.filename, output_index - first_output)?;
+                    }
+                }
+                Err(e) => self.record_failure(e, &mut stats)?,
+            }
+        }
+        stats.elapsed = start.elapsed();
+        if self.modifiers.verbose && output_index > 1 {
+            eprintln!("{}", stats);
+        }
+        if stats.failed == 0 {
+            Ok(ExitCode::SUCCESS)
+        } else {
+            Ok(ExitCode::FAILURE)
+        }
+    }
+
+    /// Applies the operations to every frame of the file and writes the results.
+    ///
+    /// All frames go into a single file if the output format can hold them, like an animated GIF;
+    /// otherwise every frame is written to a separate numbered file.
+    fn execute_file(
+        &self,
+        file_plan: &FilePlan,
+        output_index: &mut usize,
+        stats: &mut BatchStats,
+        output: &mut dyn Write,
+    ) -> Result<(), MagickError> {
+        let file_start = Instant::now();
+        if self.skip_file(file_plan, *output_index, output)? {
+            *output_index += 1;
+            return Ok(());
+        }
+        let (mut frames, input_dimensions) = self.process_file(file_plan, output)?;
+        if PseudoOutput::parse(&self.output_file) == Some(PseudoOutput::Info) {
+            let identify = Operation::Identify {
+                format: self.modifiers.format.clone(),
+                verbose: false,
+                precision: self.modifiers.precision(),
+                fuzz: self.modifiers.fuzz,
+            };
+            identify.execute_sequence(&mut frames, output, &self.modifiers.limits)?;
+        }
+        if self.discards_output() {
+            return Ok(());
+        }
+        // The image may be written to stdout as well, so the text printed so far must come first
+        wm_try!(output.flush());
+
+        // The format prefix can only be told apart once `%[filename:...]` is expanded
+        let output_file = expand_filename_properties(&self.output_file, &frames[0]);
+        let (format, output_file) = self.split_output_format(&output_file)?;
+        let outputs: Vec<Vec<Image>> =
+            if self.modifiers.adjoin() && writes_multiple_frames(output_file, format) {
+                vec![frames]
+            } else {
+                frames.into_iter().map(|frame| vec![frame]).collect()
+            };
+        // Filenames made of image properties tell the outputs apart by themselves, as in batch renaming
+        let numbered = (self.input_files.len() > 1 || outputs.len() > 1)
+            && !uses_filename_properties(&self.output_file);
+        for (output_number, frames) in outputs.iter().enumerate() {
+            let location = self.output_location(numbered.then_some(*output_index));
+            let location = expand_filename_properties(&location, &frames[0]);
+            *output_index += 1;
+            let (format, output_file) = self.split_output_format(&location)?;
+            encode_frames(frames, output_file, format, &self.modifiers)?;
+            let report = FileReport {
+                input_dimensions,
+                output_dimensions: (frames[0].width(), frames[0].height()),
+                // The input file is only counted once, no matter how many outputs it is split into
+                input_bytes: match output_number {
+                    0 => file_size(&file_plan.filename),
+                    _ => 0,
+                },
+                output_bytes: file_size(output_file),
+            };
+            stats.record_success(&report);
+            if self.modifiers.verbose {
+                eprintln!(
+                    "{}=>{} {}x{}=>{}x{} {}B=>{}B {:.3}s",
+                    file_plan.filename.to_string_lossy(),
+                    location.to_string_lossy(),
+                    report.input_dimensions.0,
+                    report.input_dimensions.1,
+                    report.output_dimensions.0,
+                    report.output_dimensions.1,
+                    report.input_bytes,
+                    report.output_bytes,
+                    file_start.elapsed().as_secs_f64(),
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Decodes the file and applies the operations to every frame of it.
+    /// Also returns the dimensions of the first frame before the operations.
+    fn process_file(
+        &self,
+        file_plan: &FilePlan,
+        output: &mut dyn Write,
+    ) -> Result<(Vec<Image>, (u32, u32)), MagickError> {
+        let processed = self.decode_and_apply(file_plan, output);
+        // The pooled buffers are sized for this file, and the next one may be far smaller
+        pool::clear();
+        processed
+    }
+
+    fn decode_and_apply(
+        &self,
+        file_plan: &FilePlan,
+        output: &mut dyn Write,
+    ) -> Result<(Vec<Image>, (u32, u32)), MagickError> {
+        let frames = match &file_plan.pseudo_image {
+            Some(pseudo_image) => {
+                let mut image = pseudo_image.render(&self.modifiers.limits)?;
+                image.filename = file_plan.filename.clone();
+                vec![image]
+            }
+            None => decode_frames(
+                &file_plan.filename,
+                None,
+                file_plan.frames.as_ref(),
+                &self.modifiers,
+            )?,
+        };
+        let input_dimensions = (frames[0].width(), frames[0].height());
+        let images = file_plan.apply_operations(frames, output, &self.modifiers.limits)?;
+        Ok((images, input_dimensions))
+    }
+
+    /// Whether the images of all input files go into a single output file, such as the pages of a TIFF.
+    /// Otherwise every input file is converted separately.
+    fn joins_inputs(&self) -> Result<bool, MagickError> {
+        if self.input_files.len() < 2
+            || !self.modifiers.adjoin()
+            || self.discards_output()
+            || uses_filename_properties(&self.output_file)
+        {
+            return Ok(false);
+        }
+        let (format, output_file) = self.split_output_format(&self.output_file)?;
+        Ok(writes_multiple_frames(output_file, format))
+    }
+
+    /// Converts all input files and writes their images to the single output file
+    fn execute_joined(
+        &self,
+        stats: &mut BatchStats,
+        output: &mut dyn Write,
+    ) -> Result<(), MagickError> {
+        let start = Instant::now();
+        let (format, output_file) = self.split_output_format(&self.output_file)?;
+        if self.modifiers.update
+            && self
+                .input_files
+                .iter()
+                .all(|file_plan| is_up_to_date(&file_plan.filename, output_file))
+        {
+            return Ok(());
+        }
+        if self.modifiers.preview {
+            for file_plan in &self.input_files {
+                wm_try!(writeln!(
+                    output,
+                    "{}=>{}",
+                    file_plan.filename.to_string_lossy(),
+                    self.output_file.to_string_lossy()
+                ));
+            }
+            return Ok(());
+        }
+        let mut images = Vec::new();
+        let mut input_dimensions = None;
+        let mut input_bytes = 0;
+        for file_plan in &self.input_files {
+            match self.process_file(file_plan, output) {
+                Ok((frames, dimensions)) => {
+                    images.extend(frames);
+                    input_dimensions.get_or_insert(dimensions);
+                    input_bytes += file_size(&file_plan.filename);
+                }
+                Err(e) => self.record_failure(e, stats)?,
+            }
+        }
+        let (Some(first), Some(input_dimensions)) = (images.first(), input_dimensions) else {
+            return Ok(());
+        };
+        wm_try!(output.flush());
+        encode_frames(&images, output_file, format, &self.modifiers)?;
+        let report = FileReport {
+            input_dimensions,
+            output_dimensions: (first.width(), first.height()),
+            input_bytes,
+            output_bytes: file_size(output_file),
+        };
+        stats.record_success(&report);
+        if self.modifiers.verbose {
+            eprintln!(
+                "{} images=>{} {}B=>{}B {:.3}s",
+                images.len(),
+                self.output_file.to_string_lossy(),
+                report.input_bytes,
+                report.output_bytes,
+                start.elapsed().as_secs_f64(),
+            );
+        }
+        Ok(())
+    }
+
+    /// Implements `-update` and `-preview`: decides whether to process the file before it is decoded.
+    ///
+    /// Only the first output is looked at, since the number of frames is not known yet.
+    fn skip_file(
+        &self,
+        file_plan: &FilePlan,
+        output_index: usize,
+        output: &mut dyn Write,
+    ) -> Result<bool, MagickError> {
+        if !self.modifiers.update && !self.modifiers.preview {
+            return Ok(false);
+        }
+        let location = self.output_location((self.input_files.len() > 1).then_some(output_index));
+        if self.modifiers.update && !self.discards_output() {
+            let (_, output_file) = self.split_output_format(&location)?;
+            if is_up_to_date(&file_plan.filename, output_file) {
+                return Ok(true);
+            }
         }
+        if self.modifiers.preview {
+            wm_try!(writeln!(
+                output,
+                "{}=>{}",
+                file_plan.filename.to_string_lossy(),
+                location.to_string_lossy()
+            ));
+        }
+        Ok(self.modifiers.preview)
+    }
+
+    /// Reports the error and carries on, unless `-regard-warnings` is in effect
+    fn record_failure(
+        &self,
+        error: MagickError,
+        stats: &mut BatchStats,
+    ) -> Result<(), MagickError> {
+        // Running out of time ends the whole batch, not just this file
+        self.modifiers.limits.check_time()?;
+        // So does the reader of stdout going away, since the other files would fail the same way
+        if error.is_broken_pipe() {
+            return Err(error);
+        }
+        if self.modifiers.regard_warnings {
+            return Err(error);
+        }
+        eprintln!("{}", error);
+        stats.failed += 1;
+        Ok(())
     }
 }
 
@@ -24,6 +1057,10 @@ impl ExecutionPlan {
 pub struct FilePlan {
     pub filename: OsString,
     pub ops: Vec<Operation>,
+    /// Frames selected with a read modifier such as `anim.gif[0-2]`; every frame is read if unset
+    pub frames: Option<FrameSelection>,
+    /// Set if the image is generated rather than read from a file, e.g. `label:Hello`
+    pub pseudo_image: Option<PseudoImage>,
 }
 
 impl FilePlan {
@@ -31,6 +1068,439 @@ impl FilePlan {
         Self {
             filename,
             ops: Vec::new(),
+            frames: None,
+            pseudo_image: None,
+        }
+    }
+
+    /// Read modifiers that resize or crop the image become the first operations on it
+    fn from_input(arg: InputFileArg) -> Self {
+        let mut plan = Self::new(arg.path.into_os_string());
+        match arg.read_mod {
+            None => (),
+            Some(ReadModifier::Resize(geometry)) => plan.ops.push(Operation::Resize(geometry)),
+            Some(ReadModifier::Crop(geometry)) => plan.ops.push(Operation::CropOnLoad(geometry)),
+            Some(ReadModifier::FrameSelect(frames)) => plan.frames = Some(frames),
+        }
+        plan
+    }
+
+    /// Applies the operations to all frames of the file at once,
+    /// so that `%n` and `-fx` see the whole sequence like in imagemagick
+    fn apply_operations(
+        &self,
+        mut images: Vec<Image>,
+        output: &mut dyn Write,
+        limits: &Limits,
+    ) -> Result<Vec<Image>, MagickError> {
+        for operation in &self.ops {
+            limits.check_time()?;
+            operation.execute_sequence(&mut images, output, limits)?;
         }
+        // We cannot write the EXIF orientation tag to the output,
+        // so we apply it to the pixels instead to keep the image looking the same
+        Operation::AutoOrient.execute_sequence(&mut images, output, limits)?;
+        Ok(images)
+    }
+}
+
+/// What happened to a single file, used for `-verbose` reporting
+struct FileReport {
+    input_dimensions: (u32, u32),
+    output_dimensions: (u32, u32),
+    input_bytes: u64,
+    output_bytes: u64,
+}
+
+/// Summary of a run over multiple files, printed at the end under `-verbose`
+#[derive(Debug, Default)]
+struct BatchStats {
+    converted: usize,
+    failed: usize,
+    input_bytes: u64,
+    output_bytes: u64,
+    elapsed: Duration,
+}
+
+impl BatchStats {
+    fn record_success(&mut self, report: &FileReport) {
+        self.converted += 1;
+        self.input_bytes += report.input_bytes;
+        self.output_bytes += report.output_bytes;
+    }
+}
+
+impl std::fmt::Display for BatchStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // The output can easily be larger than the input, e.g. when converting JPEG to PNG
+        let saved = self.input_bytes as i64 - self.output_bytes as i64;
+        write!(
+            f,
+            "converted {} files, {} failed, in {:.3}s, {}B saved",
+            self.converted,
+            self.failed,
+            self.elapsed.as_secs_f64(),
+            saved
+        )
+    }
+}
+
+/// Interprets the value of `-format` as an output format type, such as `png`.
+///
+/// Templates such as `%wx%h` are not format names, and neither are plain words we cannot write.
+fn output_format_type(value: &OsStr) -> Option<ImageFormat> {
+    let name = value.to_str()?;
+    if name.contains('%') {
+        return None;
+    }
+    match FileFormat::from_extension(name) {
+        Some(FileFormat::Image(format)) if format.writing_enabled() => Some(format),
+        _ => None,
+    }
+}
+
+/// Summary of a `-bench` run, in the same format as imagemagick:
+/// `Performance: 10i 14.286ips 0.700u 0:00.700`
+#[derive(Debug)]
+struct Performance {
+    iterations: usize,
+    elapsed: Duration,
+    cpu_time: Duration,
+}
+
+impl std::fmt::Display for Performance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Performance: {}i {:.3}ips {}",
+            self.iterations,
+            self.iterations as f64 / self.elapsed.as_secs_f64(),
+            format_times(self.cpu_time, self.elapsed)
+        )
+    }
+}
+
+const FILENAME_PROPERTY: &str = "%[filename:";
+
+fn uses_filename_properties(location: &OsStr) -> bool {
+    location
+        .to_str()
+        .is_some_and(|location| location.contains(FILENAME_PROPERTY))
+}
+
+/// Substitutes `%[filename:key]` in the output filename with the property set by `-set filename:key`.
+///
+/// Like in imagemagick, only `filename:` properties are substituted in filenames,
+/// and the ones that are not set are left as they are.
+fn expand_filename_properties(location: &OsStr, image: &Image) -> OsString {
+    let Some(mut rest) = location.to_str() else {
+        return location.to_owned();
+    };
+    let mut expanded = String::new();
+    while let Some(start) = rest.find(FILENAME_PROPERTY) {
+        let Some(length) = rest[start..].find(']') else {
+            break;
+        };
+        let key = rest[start + 2..start + length].to_ascii_lowercase();
+        expanded.push_str(&rest[..start]);
+        match image.properties.get(&key) {
+            Some(value) => expanded.push_str(value),
+            None => expanded.push_str(&rest[start..=start + length]),
+        }
+        rest = &rest[start + length + 1..];
+    }
+    expanded.push_str(rest);
+    expanded.into()
+}
+
+/// Checks if the output was modified no earlier than the input, like `make` does.
+/// Anything that is not a regular file, such as stdin or `label:`, is never up to date.
+fn is_up_to_date(input: &OsStr, output: &OsStr) -> bool {
+    let modified = |path: &OsStr| {
+        std::fs::metadata(path)
+            .ok()
+            .filter(|m| m.is_file())
+            .and_then(|m| m.modified().ok())
+    };
+    match (modified(input), modified(output)) {
+        (Some(input), Some(output)) => output >= input,
+        _ => false,
+    }
+}
+
+/// Returns 0 if the size cannot be determined, since it's only used for reporting
+fn file_size(path: &OsStr) -> u64 {
+    std::fs::metadata(path).map(|m| m.len()).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identify_plan() {
+        let args = ["wm-identify", "-format", "%w", "a.png", "label:b"];
+        let plan = crate::args::parse_identify_args(args.map(OsString::from).to_vec()).unwrap();
+        assert!(plan.discards_output());
+        for file_plan in &plan.input_files {
+            assert!(matches!(
+                file_plan.ops.last(),
+                Some(Operation::Identify {
+                    format: Some(_),
+                    ..
+                })
+            ));
+        }
+        assert!(crate::args::parse_identify_args(vec!["wm-identify".into()]).is_err());
+    }
+
+    #[test]
+    fn untrusted_profile() {
+        let args = [
+            "wm-convert",
+            "--wm-untrusted",
+            "-limit",
+            "time",
+            "5",
+            "a.png",
+            "b.png",
+        ];
+        let plan = crate::args::parse_args(args.map(OsString::from).to_vec()).unwrap();
+        let modifiers = &plan.modifiers;
+        assert_eq!(modifiers.limits.time, Some(5));
+        assert!(modifiers.limits.area.is_some());
+        assert!(modifiers
+            .check_format_allowed(FileFormat::Image(ImageFormat::Jpeg))
+            .is_ok());
+        assert!(modifiers
+            .check_format_allowed(FileFormat::Image(ImageFormat::Tiff))
+            .is_err());
+    }
+
+    #[test]
+    fn untrusted_profile_rejects_huge_canvases() {
+        let run = |args: &[&str]| {
+            let args = [&["wm-convert", "--wm-untrusted"], args, &["null:"]].concat();
+            let plan = crate::args::parse_args(args.into_iter().map(OsString::from).collect());
+            plan.unwrap().execute_to(&mut std::io::sink()).unwrap()
+        };
+        assert_eq!(run(&["null:"]), ExitCode::SUCCESS);
+        assert_eq!(run(&["-size", "20000x20000", "null:"]), ExitCode::FAILURE);
+        assert_eq!(run(&["null:", "-extent", "20000x20000"]), ExitCode::FAILURE);
+        assert_eq!(
+            run(&["null:", "-resize", "20000x20000!"]),
+            ExitCode::FAILURE
+        );
+        assert_eq!(
+            run(&["null:", "-sample", "20000x20000!"]),
+            ExitCode::FAILURE
+        );
+        assert_eq!(run(&["null:", "-wave", "20000x10"]), ExitCode::FAILURE);
+    }
+
+    #[test]
+    fn broken_pipe_ends_the_batch() {
+        struct ClosedPipe;
+        impl Write for ClosedPipe {
+            fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+                Err(std::io::ErrorKind::BrokenPipe.into())
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+        let args = ["wm-convert", "null:", "null:", "info:"];
+        let plan = crate::args::parse_args(args.map(OsString::from).to_vec()).unwrap();
+        assert!(plan
+            .execute_to(&mut ClosedPipe)
+            .unwrap_err()
+            .is_broken_pipe());
+    }
+
+    #[test]
+    fn sequence_length_escape() {
+        let directory = tempfile::tempdir().unwrap();
+        let input = directory.path().join("in.gif");
+        let mut encoder =
+            image::codecs::gif::GifEncoder::new(std::fs::File::create(&input).unwrap());
+        for _ in 0..3 {
+            let frame = image::RgbaImage::new(2, 2);
+            encoder.encode_frame(image::Frame::new(frame)).unwrap();
+        }
+        drop(encoder);
+        let args = [
+            OsString::from("wm-identify"),
+            OsString::from("-format"),
+            OsString::from("%s/%n "),
+            input.into_os_string(),
+        ];
+        let plan = crate::args::parse_identify_args(args.to_vec()).unwrap();
+        let mut output = Vec::new();
+        assert_eq!(plan.execute_to(&mut output).unwrap(), ExitCode::SUCCESS);
+        assert_eq!(String::from_utf8(output).unwrap(), "0/3 1/3 2/3 ");
+    }
+
+    #[test]
+    fn limits_apply_to_earlier_arguments() {
+        let run = |args: &[&str]| {
+            let args = [&["wm-convert"], args, &["null:"]].concat();
+            let plan = crate::args::parse_args(args.into_iter().map(OsString::from).collect());
+            plan.unwrap().execute_to(&mut std::io::sink()).unwrap()
+        };
+        assert_eq!(
+            run(&["null:", "-resize", "30000x30000!", "--wm-untrusted"]),
+            ExitCode::FAILURE
+        );
+        assert_eq!(
+            run(&["null:", "-extent", "30000x30000", "-limit", "area", "1000"]),
+            ExitCode::FAILURE
+        );
+        assert_eq!(
+            run(&["-size", "30000x30000", "null:", "-limit", "area", "1000"]),
+            ExitCode::FAILURE
+        );
+        assert_eq!(
+            run(&["null:", "-extent", "30x30", "-limit", "area", "1000"]),
+            ExitCode::SUCCESS
+        );
+    }
+
+    #[test]
+    fn web_thumbnail_preset() {
+        let args = [
+            "wm-convert",
+            "in.jpg",
+            "--wm-web-thumbnail",
+            "400x400",
+            "out.jpg",
+        ];
+        let plan = crate::args::parse_args(args.map(OsString::from).to_vec()).unwrap();
+        let geometry = ResizeGeometry::try_from(OsStr::new("400x400>")).unwrap();
+        assert!(matches!(
+            plan.input_files[0].ops.as_slice(),
+            [
+                Operation::AutoOrient,
+                Operation::Thumbnail(thumbnail),
+                Operation::ConvertToSrgb,
+                Operation::Strip,
+            ] if *thumbnail == geometry
+        ));
+        assert_eq!(plan.modifiers.quality, Some(WEB_THUMBNAIL_QUALITY));
+    }
+
+    #[test]
+    fn update_skips_newer_outputs() {
+        let directory = tempfile::tempdir().unwrap();
+        let input = directory.path().join("in.png");
+        let output = directory.path().join("out.png");
+        std::fs::write(&input, b"").unwrap();
+        let mut plan = ExecutionPlan {
+            output_file: output.clone().into_os_string(),
+            input_files: vec![FilePlan::new(input.clone().into_os_string())],
+            ..Default::default()
+        };
+        plan.apply_arg(Arg::Update.into(), &[]).unwrap();
+        assert!(!plan
+            .skip_file(&plan.input_files[0], 0, &mut std::io::sink())
+            .unwrap());
+        std::fs::write(&output, b"").unwrap();
+        assert!(plan
+            .skip_file(&plan.input_files[0], 0, &mut std::io::sink())
+            .unwrap());
+        // an input modified after the output is processed again
+        let later = std::fs::metadata(&output).unwrap().modified().unwrap()
+            + std::time::Duration::from_secs(1);
+        std::fs::File::options()
+            .write(true)
+            .open(&input)
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+        assert!(!plan
+            .skip_file(&plan.input_files[0], 0, &mut std::io::sink())
+            .unwrap());
+        // generated images are always processed
+        assert!(!is_up_to_date(OsStr::new("label:x"), output.as_os_str()));
+    }
+
+    #[test]
+    fn filename_properties() {
+        let mut image = Image::new(image::DynamicImage::new_rgb8(1, 1));
+        image
+            .properties
+            .insert("filename:area".to_owned(), "3x2".to_owned());
+        let expand = |location: &str| expand_filename_properties(OsStr::new(location), &image);
+        assert_eq!(expand("out_%[filename:AREA].png"), "out_3x2.png");
+        assert_eq!(expand("%[filename:area]-%[filename:area]"), "3x2-3x2");
+        assert!(uses_filename_properties(OsStr::new("%[filename:x].png")));
+        assert!(!uses_filename_properties(OsStr::new("%[x].png")));
+        // unknown properties and other escapes are left alone
+        assert_eq!(
+            expand("%[filename:x]_%[w]_%[filename:"),
+            "%[filename:x]_%[w]_%[filename:"
+        );
+    }
+
+    #[test]
+    fn single_output_location() {
+        let plan = ExecutionPlan {
+            output_file: "out.png".into(),
+            input_files: vec![FilePlan::new("in.png".into())],
+            ..Default::default()
+        };
+        assert_eq!(plan.output_location(None), OsString::from("out.png"));
+    }
+
+    #[test]
+    fn numbered_output_locations() {
+        let plan = ExecutionPlan {
+            output_file: "dir/out.png".into(),
+            input_files: vec![FilePlan::new("a.png".into()), FilePlan::new("b.png".into())],
+            ..Default::default()
+        };
+        assert_eq!(
+            plan.output_location(Some(0)),
+            OsString::from("dir/out-0.png")
+        );
+        assert_eq!(
+            plan.output_location(Some(1)),
+            OsString::from("dir/out-1.png")
+        );
+    }
+
+    #[test]
+    fn performance_summary() {
+        let performance = Performance {
+            iterations: 10,
+            elapsed: Duration::from_millis(61_250),
+            cpu_time: Duration::from_millis(700),
+        };
+        assert_eq!(
+            performance.to_string(),
+            "Performance: 10i 0.163ips 0.700u 1:01.250"
+        );
+    }
+
+    #[test]
+    fn format_type() {
+        let format = |value: &str| output_format_type(OsStr::new(value));
+        assert_eq!(format("png"), Some(ImageFormat::Png));
+        assert_eq!(format("JPG"), Some(ImageFormat::Jpeg));
+        assert_eq!(format("%wx%h"), None);
+        assert_eq!(format("%m"), None);
+        assert_eq!(format("hello"), None);
+    }
+
+    #[test]
+    fn format_type_as_fallback() {
+        let mut plan = ExecutionPlan::default();
+        plan.apply_arg(Arg::Format.into(), &[OsStr::new("png")])
+            .unwrap();
+        let format = |file: &str| plan.split_output_format(OsStr::new(file)).unwrap().0;
+        assert_eq!(format("out"), Some(ImageFormat::Png));
+        assert_eq!(format("-"), Some(ImageFormat::Png));
+        // the filename takes precedence
+        assert_eq!(format("out.jpg"), None);
+        assert_eq!(format("gif:out"), Some(ImageFormat::Gif));
     }
 }
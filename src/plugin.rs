@@ -0,0 +1,89 @@
+//! Custom operations implemented in Rust by users of the library, enabled by the `plugins` feature.
+//!
+//! A plugin is registered on an [ExecutionPlan](crate::plan::ExecutionPlan) under a name
+//! and can then be applied to the files in the plan like any built-in operation:
+//!
+//! ```
+//! # use wondermagick::{plan::ExecutionPlan, plugin::Plugin};
+//! let mut plan = ExecutionPlan::default();
+//! plan.register_plugin(Plugin::new("invert", |image| {
+//!     image.pixels.invert();
+//!     Ok(())
+//! }));
+//! plan.apply_plugin("invert").unwrap();
+//! ```
+
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use crate::{error::MagickError, image::Image};
+
+type Callback = dyn Fn(&mut Image) -> Result<(), MagickError> + Send + Sync;
+
+/// A named operation backed by a closure
+#[derive(Clone)]
+pub struct Plugin {
+    name: String,
+    callback: Arc<Callback>,
+}
+
+impl Plugin {
+    pub fn new(
+        name: impl Into<String>,
+        callback: impl Fn(&mut Image) -> Result<(), MagickError> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            callback: Arc::new(callback),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn execute(&self, image: &mut Image) -> Result<(), MagickError> {
+        (self.callback)(image)
+    }
+}
+
+impl Debug for Plugin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Plugin")
+            .field("name", &self.name)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Closures cannot be compared, so plugins are only equal if they share the same closure
+impl PartialEq for Plugin {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name && Arc::ptr_eq(&self.callback, &other.callback)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{DynamicImage, GrayImage, Luma};
+
+    use super::*;
+
+    #[test]
+    fn execute() {
+        let plugin = Plugin::new("invert", |image: &mut Image| {
+            image.pixels.invert();
+            Ok(())
+        });
+        let mut image = Image::new(DynamicImage::ImageLuma8(GrayImage::from_pixel(
+            1,
+            1,
+            Luma([10]),
+        )));
+        plugin.execute(&mut image).unwrap();
+        assert_eq!(
+            image.pixels.as_luma8().unwrap().get_pixel(0, 0),
+            &Luma([245])
+        );
+        assert_eq!(plugin, plugin.clone());
+    }
+}
@@ -0,0 +1,155 @@
+//! Pseudo-files: images that are generated from the settings in effect instead of being read
+//! from a file, such as `label:Hello`, and outputs that are not written to a file, such as `null:`
+
+use std::ffi::OsStr;
+
+use image::{DynamicImage, RgbaImage};
+
+use crate::{
+    error::MagickError,
+    file_format::{FileFormat, PseudoFormat},
+    image::Image,
+    limits::Limits,
+    plan::Modifiers,
+    text::{self, TextStyle},
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PseudoImage {
+    /// `label:text`: the text on an image of the same size
+    Label { text: String, style: TextStyle },
+    /// `caption:text`: the text wrapped to the width of the image
+    Caption { text: String, style: TextStyle },
+    /// `pango:markup`: text with Pango markup
+    Pango { markup: String, style: TextStyle },
+    /// `null:`: a transparent placeholder of the `-size` in effect, 1x1 by default
+    Null { width: u32, height: u32 },
+}
+
+impl PseudoImage {
+    /// Recognizes a pseudo-image by its prefix, capturing the settings that apply to it.
+    /// Returns `None` for anything else, which is then read as a file.
+    pub fn parse(arg: &OsStr, modifiers: &Modifiers) -> Option<Self> {
+        let (prefix, text) = arg.to_str()?.split_once(':')?;
+        // imagemagick turns a literal `\n` in the text into a line break
+        let text = text.replace("\\n", "\n");
+        let style = TextStyle::from_modifiers(modifiers);
+        match PseudoFormat::from_prefix(prefix)? {
+            PseudoFormat::Label => Some(PseudoImage::Label { text, style }),
+            PseudoFormat::Caption => Some(PseudoImage::Caption { text, style }),
+            PseudoFormat::Pango => Some(PseudoImage::Pango {
+                markup: text,
+                style,
+            }),
+            PseudoFormat::Null => Some(PseudoImage::Null {
+                width: modifiers.size.0.unwrap_or(1),
+                height: modifiers.size.1.unwrap_or(1),
+            }),
+            PseudoFormat::Info => None,
+        }
+    }
+
+    pub fn format(&self) -> PseudoFormat {
+        match self {
+            PseudoImage::Label { .. } => PseudoFormat::Label,
+            PseudoImage::Caption { .. } => PseudoFormat::Caption,
+            PseudoImage::Pango { .. } => PseudoFormat::Pango,
+            PseudoImage::Null { .. } => PseudoFormat::Null,
+        }
+    }
+
+    /// Generates the image, checking its size against the limits
+    pub fn render(&self, limits: &Limits) -> Result<Image, MagickError> {
+        let mut image = match self {
+            PseudoImage::Label { text, style } => text::label(text, style, limits)?,
+            PseudoImage::Caption { text, style } => text::caption(text, style, limits)?,
+            PseudoImage::Pango { markup, style } => text::pango(markup, style, limits)?,
+            PseudoImage::Null { width, height } => {
+                let (width, height) =
+                    limits.check_new_image(f64::from(*width), f64::from(*height), 4)?;
+                Image::new(DynamicImage::ImageRgba8(RgbaImage::new(width, height)))
+            }
+        };
+        image.format = Some(FileFormat::Pseudo(self.format()));
+        Ok(image)
+    }
+}
+
+/// Outputs that are not written to a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PseudoOutput {
+    /// `null:` or `/dev/null`: the images are thrown away
+    Null,
+    /// `info:`: the images are described like `-identify` does, or with the `-format` in effect
+    Info,
+}
+
+impl PseudoOutput {
+    /// Recognizes an output that is not a file, returning `None` for anything else
+    pub fn parse(file: &OsStr) -> Option<Self> {
+        let file = file.to_str()?;
+        if file == "/dev/null" {
+            return Some(PseudoOutput::Null);
+        }
+        let (prefix, rest) = file.split_once(':')?;
+        match (PseudoFormat::from_prefix(prefix)?, rest) {
+            (PseudoFormat::Null, "") => Some(PseudoOutput::Null),
+            (PseudoFormat::Info, "" | "-") => Some(PseudoOutput::Info),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let modifiers = Modifiers {
+            pointsize: Some(20.0),
+            ..Default::default()
+        };
+        let Some(PseudoImage::Caption { text, style }) =
+            PseudoImage::parse(OsStr::new("CAPTION:one\\ntwo"), &modifiers)
+        else {
+            panic!("not a caption");
+        };
+        assert_eq!(text, "one\ntwo");
+        assert_eq!(style.pointsize, Some(20.0));
+        assert!(PseudoImage::parse(OsStr::new("label:"), &modifiers).is_some());
+        assert!(matches!(
+            PseudoImage::parse(OsStr::new("pango:<b>x</b>"), &modifiers),
+            Some(PseudoImage::Pango { .. })
+        ));
+        assert!(PseudoImage::parse(OsStr::new("photo.jpg"), &modifiers).is_none());
+        assert!(PseudoImage::parse(OsStr::new("png:out.dat"), &modifiers).is_none());
+        assert_eq!(
+            PseudoImage::parse(OsStr::new("NULL:"), &modifiers),
+            Some(PseudoImage::Null {
+                width: 1,
+                height: 1,
+            })
+        );
+    }
+
+    #[test]
+    fn huge_null_is_rejected() {
+        let modifiers = Modifiers {
+            size: (Some(300_000), Some(300_000)),
+            ..Default::default()
+        };
+        let null = PseudoImage::parse(OsStr::new("null:"), &modifiers).unwrap();
+        assert!(null.render(&modifiers.limits).is_err());
+    }
+
+    #[test]
+    fn parse_output() {
+        let parse = |file| PseudoOutput::parse(OsStr::new(file));
+        assert_eq!(parse("null:"), Some(PseudoOutput::Null));
+        assert_eq!(parse("/dev/null"), Some(PseudoOutput::Null));
+        assert_eq!(parse("INFO:"), Some(PseudoOutput::Info));
+        assert_eq!(parse("null.png"), None);
+        assert_eq!(parse("info:notes.txt"), None);
+    }
+}
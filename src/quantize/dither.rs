@@ -0,0 +1,89 @@
+//! Mapping pixels to the closest palette entries, optionally spreading the error to the neighbours
+
+use image::{Rgba, RgbaImage};
+
+use crate::{
+    arg_parsers::DitherMethod,
+    utils::dither::{floyd_steinberg, riemersma},
+};
+
+/// Replaces every opaque pixel with a color from the palette. Transparent pixels are left alone.
+pub fn remap(pixels: &mut RgbaImage, palette: &[Rgba<u8>], method: DitherMethod) {
+    match method {
+        DitherMethod::None => {
+            for pixel in pixels.pixels_mut().filter(|p| p[3] != 0) {
+                let color = [pixel[0], pixel[1], pixel[2]].map(f32::from);
+                *pixel = palette[closest(palette, color)];
+            }
+        }
+        DitherMethod::FloydSteinberg => {
+            let (width, height) = pixels.dimensions();
+            floyd_steinberg(width, height, |x, y, correction| {
+                let pixel = pixels.get_pixel_mut(x, y);
+                if pixel[3] == 0 {
+                    return None;
+                }
+                let color: [f32; 3] = std::array::from_fn(|c| {
+                    (f32::from(pixel[c]) + correction[c]).clamp(0.0, 255.0)
+                });
+                *pixel = palette[closest(palette, color)];
+                Some(std::array::from_fn(|c| color[c] - f32::from(pixel[c])))
+            });
+        }
+        DitherMethod::Riemersma => {
+            let (width, height) = pixels.dimensions();
+            riemersma(width, height, |x, y, correction| {
+                let pixel = pixels.get_pixel_mut(x, y);
+                if pixel[3] == 0 {
+                    return None;
+                }
+                let original = [pixel[0], pixel[1], pixel[2]].map(f32::from);
+                let color: [f32; 3] =
+                    std::array::from_fn(|c| (original[c] + correction[c]).clamp(0.0, 255.0));
+                *pixel = palette[closest(palette, color)];
+                Some(std::array::from_fn(|c| original[c] - f32::from(pixel[c])))
+            });
+        }
+    }
+}
+
+fn closest(palette: &[Rgba<u8>], color: [f32; 3]) -> usize {
+    let distance = |entry: &Rgba<u8>| -> f32 {
+        (0..3)
+            .map(|c| {
+                let difference = f32::from(entry[c]) - color[c];
+                difference * difference
+            })
+            .sum()
+    };
+    palette
+        .iter()
+        .enumerate()
+        .min_by(|(_, a), (_, b)| distance(a).total_cmp(&distance(b)))
+        .map(|(index, _)| index)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dithering_preserves_the_average() {
+        let palette = [Rgba([0, 0, 0, 255]), Rgba([255, 255, 255, 255])];
+        for method in [DitherMethod::FloydSteinberg, DitherMethod::Riemersma] {
+            let mut pixels = RgbaImage::from_pixel(16, 16, Rgba([64, 64, 64, 255]));
+            remap(&mut pixels, &palette, method);
+            let white = pixels.pixels().filter(|p| p[0] == 255).count();
+            // a quarter of the pixels should be white
+            assert!(
+                (56..=72).contains(&white),
+                "{white} white pixels with {method:?}"
+            );
+        }
+
+        let mut pixels = RgbaImage::from_pixel(16, 16, Rgba([64, 64, 64, 255]));
+        remap(&mut pixels, &palette, DitherMethod::None);
+        assert!(pixels.pixels().all(|p| p[0] == 0));
+    }
+}
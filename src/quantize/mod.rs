@@ -0,0 +1,341 @@
+//! Color reduction shared by operations and encoders that need a limited palette, such as GIF.
+//!
+//! The palette is chosen by an octree quantizer like imagemagick's,
+//! then every pixel is mapped to it, optionally with dithering.
+
+mod dither;
+mod octree;
+
+use std::collections::{HashMap, HashSet};
+
+use image::{Rgba, RgbaImage};
+
+use crate::{
+    arg_parsers::{Colorspace, DitherMethod},
+    colorspace::{conversions, Conversion},
+};
+
+/// The deepest octree possible, with one level per bit of an 8-bit channel
+pub const MAX_TREE_DEPTH: u8 = 8;
+
+/// How to reduce the colors of an image
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quantizer {
+    /// The maximum number of colors in the palette, including the transparent one
+    pub colors: usize,
+    /// `-treedepth`: how many levels the octree has, or 0 to choose from the number of colors.
+    /// Shallower trees are faster but less precise.
+    pub tree_depth: u8,
+    pub dither: DitherMethod,
+    /// `-quantize`: the colorspace the palette is chosen and the pixels are matched to it in.
+    /// A perceptual colorspace such as Lab spends the palette where the eye notices differences.
+    pub colorspace: Colorspace,
+}
+
+impl Default for Quantizer {
+    fn default() -> Self {
+        Self {
+            colors: 256,
+            tree_depth: MAX_TREE_DEPTH,
+            dither: DitherMethod::default(),
+            colorspace: Colorspace::Srgb,
+        }
+    }
+}
+
+impl Quantizer {
+    /// Reduces the image to at most `colors` colors in place.
+    ///
+    /// The alpha channel is reduced to fully opaque or fully transparent, the only options in GIF,
+    /// and all transparent pixels share a single palette entry.
+    /// Images that already have few enough colors are left as they are.
+    pub fn quantize(&self, pixels: &mut RgbaImage) {
+        let has_transparency = binarize_alpha(pixels);
+        if count_colors(pixels, self.colors + 1) <= self.colors {
+            return;
+        }
+        let opaque_colors = self
+            .colors
+            .saturating_sub(usize::from(has_transparency))
+            .max(1);
+        self.map_to_palette(pixels, |pixels, _| self.palette(pixels, opaque_colors));
+    }
+
+    /// Reduces all of the images to one shared palette, such as the global palette of a GIF.
+    ///
+    /// Like [Quantizer::quantize], but a palette entry is also left free for transparency
+    /// if `reserve_transparent` is set, even if none of the images have transparent pixels yet.
+    pub fn quantize_shared(&self, images: &mut [RgbaImage], reserve_transparent: bool) {
+        let mut has_transparency = reserve_transparent;
+        for pixels in images.iter_mut() {
+            has_transparency |= binarize_alpha(pixels);
+        }
+        let opaque_colors = self
+            .colors
+            .saturating_sub(usize::from(has_transparency))
+            .max(1);
+        // All the opaque pixels in a row, to choose the palette from all of them at once
+        let opaque: Vec<Rgba<u8>> = images
+            .iter()
+            .flat_map(|pixels| pixels.pixels())
+            .filter(|pixel| pixel[3] != 0)
+            .copied()
+            .collect();
+        let mut combined = RgbaImage::from_fn(opaque.len() as u32, 1, |x, _| opaque[x as usize]);
+        drop(opaque);
+        if count_colors(&combined, opaque_colors + 1) <= opaque_colors {
+            return;
+        }
+        let (to_colorspace, to_srgb) = match self.colorspace {
+            Colorspace::Srgb => (None, None),
+            colorspace => conversions(colorspace).unzip(),
+        };
+        if let Some(to_colorspace) = to_colorspace {
+            map_opaque(&mut combined, to_colorspace);
+        }
+        let palette = self.palette(&combined, opaque_colors);
+        let mut palette = RgbaImage::from_fn(palette.len() as u32, 1, |x, _| palette[x as usize]);
+        if let Some(to_srgb) = to_srgb {
+            map_opaque(&mut palette, to_srgb);
+        }
+        let palette: Vec<Rgba<u8>> = palette.pixels().copied().collect();
+        for pixels in images {
+            self.remap(pixels, &palette);
+        }
+    }
+
+    /// Replaces every opaque pixel with the closest color from the given palette,
+    /// such as the colors of another image with `-remap`. `colors` and `tree_depth` do not apply.
+    pub fn remap(&self, pixels: &mut RgbaImage, palette: &[Rgba<u8>]) {
+        self.map_to_palette(pixels, |_, to_colorspace| {
+            let mut palette =
+                RgbaImage::from_fn(palette.len() as u32, 1, |x, _| palette[x as usize]);
+            if let Some(to_colorspace) = to_colorspace {
+                map_opaque(&mut palette, to_colorspace);
+            }
+            palette.pixels().copied().collect()
+        });
+    }
+
+    /// Maps the opaque pixels to the palette chosen by `palette` in the `-quantize` colorspace.
+    /// `palette` is given the pixels in that colorspace and the conversion to it, if any.
+    fn map_to_palette(
+        &self,
+        pixels: &mut RgbaImage,
+        palette: impl FnOnce(&RgbaImage, Option<Conversion>) -> Vec<Rgba<u8>>,
+    ) {
+        // CMYK has no three channels to quantize, so it falls back to sRGB
+        let (to_colorspace, to_srgb) = match self.colorspace {
+            Colorspace::Srgb => (None, None),
+            colorspace => conversions(colorspace).unzip(),
+        };
+        if let Some(to_colorspace) = to_colorspace {
+            map_opaque(pixels, to_colorspace);
+        }
+        let palette = palette(pixels, to_colorspace);
+        dither::remap(pixels, &palette, self.dither);
+        if let Some(to_srgb) = to_srgb {
+            map_opaque(pixels, to_srgb);
+        }
+    }
+
+    /// Chooses up to `colors` representative colors for the opaque pixels of the image
+    pub fn palette(&self, pixels: &RgbaImage, colors: usize) -> Vec<Rgba<u8>> {
+        let mut tree = octree::Octree::new(self.depth());
+        for pixel in pixels.pixels().filter(|p| p[3] != 0) {
+            tree.insert(*pixel);
+        }
+        tree.reduce(colors);
+        tree.palette()
+    }
+
+    /// The depth of the octree, chosen like imagemagick's QuantizeImage() does if `tree_depth` is 0:
+    /// just deep enough to tell the colors apart, and one level less when dithering hides the difference
+    fn depth(&self) -> u8 {
+        if self.tree_depth != 0 {
+            return self.tree_depth.min(MAX_TREE_DEPTH);
+        }
+        let mut depth = 1;
+        let mut colors = self.colors;
+        while colors != 0 {
+            depth += 1;
+            colors >>= 2;
+        }
+        if self.dither != DitherMethod::None && depth > 2 {
+            depth -= 1;
+        }
+        depth.min(MAX_TREE_DEPTH)
+    }
+}
+
+/// Converts the color of every opaque pixel.
+/// Every distinct color is only converted once, which also keeps the palette as small after conversion.
+fn map_opaque(pixels: &mut RgbaImage, f: Conversion) {
+    let mut converted = HashMap::new();
+    for pixel in pixels.pixels_mut().filter(|p| p[3] != 0) {
+        let rgb = [pixel[0], pixel[1], pixel[2]];
+        let [r, g, b] = *converted.entry(rgb).or_insert_with(|| {
+            f(rgb.map(|v| f64::from(v) / 255.0))
+                .map(|v| (v * 255.0).round().clamp(0.0, 255.0) as u8)
+        });
+        *pixel = Rgba([r, g, b, pixel[3]]);
+    }
+}
+
+/// Makes every pixel either fully opaque or fully transparent black.
+/// Returns true if any pixels are transparent.
+fn binarize_alpha(pixels: &mut RgbaImage) -> bool {
+    let mut has_transparency = false;
+    for pixel in pixels.pixels_mut() {
+        if pixel[3] < 128 {
+            *pixel = Rgba([0, 0, 0, 0]);
+            has_transparency = true;
+        } else {
+            pixel[3] = u8::MAX;
+        }
+    }
+    has_transparency
+}
+
+/// Counts distinct colors, stopping at `limit`
+fn count_colors(pixels: &RgbaImage, limit: usize) -> usize {
+    let mut colors = HashSet::new();
+    for pixel in pixels.pixels() {
+        if colors.insert(pixel.0) && colors.len() >= limit {
+            break;
+        }
+    }
+    colors.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gradient() -> RgbaImage {
+        RgbaImage::from_fn(64, 64, |x, y| {
+            Rgba([(x * 4) as u8, (y * 4) as u8, ((x + y) * 2) as u8, 255])
+        })
+    }
+
+    #[test]
+    fn reduces_to_the_requested_number_of_colors() {
+        for dither in [DitherMethod::None, DitherMethod::FloydSteinberg] {
+            let mut pixels = gradient();
+            let quantizer = Quantizer {
+                colors: 16,
+                dither,
+                ..Default::default()
+            };
+            quantizer.quantize(&mut pixels);
+            assert!(count_colors(&pixels, usize::MAX) <= 16);
+        }
+    }
+
+    #[test]
+    fn remaps_to_the_given_palette() {
+        let palette = [Rgba([255, 0, 0, 255]), Rgba([0, 0, 255, 255])];
+        for dither in [DitherMethod::None, DitherMethod::FloydSteinberg] {
+            let mut pixels = gradient();
+            let quantizer = Quantizer {
+                dither,
+                ..Default::default()
+            };
+            quantizer.remap(&mut pixels, &palette);
+            assert!(pixels.pixels().all(|pixel| palette.contains(pixel)));
+        }
+    }
+
+    #[test]
+    fn tree_depth() {
+        let quantizer = |colors, tree_depth, dither| Quantizer {
+            colors,
+            tree_depth,
+            dither,
+            ..Default::default()
+        };
+        assert_eq!(quantizer(256, 4, DitherMethod::None).depth(), 4);
+        assert_eq!(
+            quantizer(256, 20, DitherMethod::None).depth(),
+            MAX_TREE_DEPTH
+        );
+        assert_eq!(quantizer(256, 0, DitherMethod::None).depth(), 6);
+        assert_eq!(quantizer(256, 0, DitherMethod::FloydSteinberg).depth(), 5);
+        assert_eq!(quantizer(16, 0, DitherMethod::None).depth(), 4);
+
+        // a shallow tree still gives a valid palette
+        let mut pixels = gradient();
+        quantizer(16, 2, DitherMethod::None).quantize(&mut pixels);
+        assert!(count_colors(&pixels, usize::MAX) <= 16);
+    }
+
+    #[test]
+    fn few_colors_are_kept_exactly() {
+        let mut pixels = RgbaImage::from_fn(8, 8, |x, _| Rgba([x as u8 * 30, 0, 0, 255]));
+        let original = pixels.clone();
+        Quantizer::default().quantize(&mut pixels);
+        assert_eq!(pixels, original);
+    }
+
+    #[test]
+    fn quantizes_in_other_colorspaces() {
+        for colorspace in [Colorspace::Lab, Colorspace::Gray, Colorspace::Cmyk] {
+            let mut pixels = gradient();
+            let quantizer = Quantizer {
+                colors: 16,
+                colorspace,
+                ..Default::default()
+            };
+            quantizer.quantize(&mut pixels);
+            assert!(count_colors(&pixels, usize::MAX) <= 16);
+            // the colors are sRGB again, close to the original ones
+            let original = gradient();
+            let (x, y) = (40, 20);
+            for c in 0..3 {
+                let difference =
+                    i32::from(pixels.get_pixel(x, y)[c]) - i32::from(original.get_pixel(x, y)[c]);
+                assert!(difference.abs() < 96, "{colorspace:?}");
+            }
+        }
+        // gray quantization gives gray colors
+        let mut pixels = gradient();
+        let quantizer = Quantizer {
+            colorspace: Colorspace::Gray,
+            ..Default::default()
+        };
+        quantizer.quantize(&mut pixels);
+        assert!(pixels.pixels().all(|p| p[0] == p[1] && p[1] == p[2]));
+    }
+
+    #[test]
+    fn transparency_takes_one_palette_entry() {
+        let mut pixels = gradient();
+        for x in 0..64 {
+            pixels.put_pixel(x, 0, Rgba([x as u8, 1, 2, 10]));
+        }
+        let quantizer = Quantizer {
+            colors: 8,
+            ..Default::default()
+        };
+        quantizer.quantize(&mut pixels);
+        assert!(count_colors(&pixels, usize::MAX) <= 8);
+        assert!(pixels.pixels().take(64).all(|p| p.0 == [0, 0, 0, 0]));
+        assert!(pixels.pixels().skip(64).all(|p| p[3] == 255));
+    }
+
+    #[test]
+    fn shared_palette() {
+        let mut images = [
+            gradient(),
+            RgbaImage::from_pixel(4, 4, Rgba([255, 0, 255, 255])),
+        ];
+        let quantizer = Quantizer {
+            colors: 16,
+            dither: DitherMethod::None,
+            ..Default::default()
+        };
+        quantizer.quantize_shared(&mut images, true);
+        let colors: HashSet<_> = images.iter().flat_map(|i| i.pixels()).collect();
+        assert!(colors.len() <= 15);
+    }
+}
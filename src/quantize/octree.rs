@@ -0,0 +1,150 @@
+//! Octree color quantization: colors are sorted into a tree by their bits from most to least significant,
+//! then the least populated branches are merged until few enough leaves remain.
+
+use image::Rgba;
+
+#[derive(Debug, Default, Clone)]
+struct Node {
+    children: [Option<u32>; 8],
+    /// Number of pixels in this node and all of its descendants
+    count: u64,
+    sums: [u64; 3],
+    depth: u8,
+}
+
+impl Node {
+    fn is_leaf(&self) -> bool {
+        self.children.iter().all(Option::is_none)
+    }
+}
+
+pub struct Octree {
+    nodes: Vec<Node>,
+    max_depth: u8,
+}
+
+impl Octree {
+    pub fn new(max_depth: u8) -> Self {
+        Self {
+            nodes: vec![Node::default()],
+            max_depth,
+        }
+    }
+
+    pub fn insert(&mut self, pixel: Rgba<u8>) {
+        let mut node = 0;
+        self.add_to(node, pixel);
+        for depth in 0..self.max_depth {
+            let shift = 7 - depth;
+            let branch = usize::from((pixel[0] >> shift) & 1) << 2
+                | usize::from((pixel[1] >> shift) & 1) << 1
+                | usize::from((pixel[2] >> shift) & 1);
+            node = match self.nodes[node].children[branch] {
+                Some(child) => child as usize,
+                None => {
+                    let child = self.nodes.len();
+                    self.nodes.push(Node {
+                        depth: depth + 1,
+                        ..Default::default()
+                    });
+                    self.nodes[node].children[branch] = Some(child as u32);
+                    child
+                }
+            };
+            self.add_to(node, pixel);
+        }
+    }
+
+    fn add_to(&mut self, node: usize, pixel: Rgba<u8>) {
+        let node = &mut self.nodes[node];
+        node.count += 1;
+        for (sum, value) in node.sums.iter_mut().zip(pixel.0) {
+            *sum += u64::from(value);
+        }
+    }
+
+    fn leaf_count(&self) -> usize {
+        self.reachable()
+            .filter(|&i| self.nodes[i].is_leaf())
+            .count()
+    }
+
+    fn reachable(&self) -> impl Iterator<Item = usize> + '_ {
+        let mut stack = vec![0];
+        std::iter::from_fn(move || {
+            let node = stack.pop()?;
+            stack.extend(
+                self.nodes[node]
+                    .children
+                    .iter()
+                    .flatten()
+                    .map(|&c| c as usize),
+            );
+            Some(node)
+        })
+    }
+
+    /// Merges the least populated branches into their parents, deepest first,
+    /// until there are at most `colors` leaves
+    pub fn reduce(&mut self, colors: usize) {
+        let mut leaves = self.leaf_count();
+        for depth in (0..self.max_depth).rev() {
+            if leaves <= colors {
+                return;
+            }
+            let mut parents: Vec<usize> = self
+                .reachable()
+                .filter(|&i| self.nodes[i].depth == depth && !self.nodes[i].is_leaf())
+                .collect();
+            parents.sort_by_key(|&i| self.nodes[i].count);
+            for parent in parents {
+                if leaves <= colors {
+                    return;
+                }
+                let children = self.nodes[parent].children.iter().flatten().count();
+                self.nodes[parent].children = [None; 8];
+                leaves -= children - 1;
+            }
+        }
+    }
+
+    /// The average color of every leaf
+    pub fn palette(&self) -> Vec<Rgba<u8>> {
+        self.reachable()
+            .map(|i| &self.nodes[i])
+            .filter(|node| node.is_leaf() && node.count > 0)
+            .map(|node| {
+                let average = |sum: u64| ((sum + node.count / 2) / node.count) as u8;
+                Rgba([
+                    average(node.sums[0]),
+                    average(node.sums[1]),
+                    average(node.sums[2]),
+                    u8::MAX,
+                ])
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn similar_colors_are_merged_first() {
+        let mut tree = Octree::new(8);
+        for _ in 0..10 {
+            tree.insert(Rgba([0, 0, 0, 255]));
+            tree.insert(Rgba([255, 255, 255, 255]));
+        }
+        tree.insert(Rgba([1, 1, 1, 255]));
+        tree.insert(Rgba([254, 254, 254, 255]));
+        tree.reduce(2);
+        let mut palette = tree.palette();
+        palette.sort_by_key(|p| p[0]);
+        assert_eq!(
+            palette,
+            vec![Rgba([0, 0, 0, 255]), Rgba([255, 255, 255, 255])]
+        );
+    }
+}
@@ -0,0 +1,435 @@
+//! Finding the fonts installed on the system by name, so that `-font` accepts names like
+//! `DejaVu-Sans-Bold` or `Arial` as well as paths to font files.
+//!
+//! The font directories are scanned once per run. Only the tables with the names and the style
+//! of each font are read, the rest of the font is left alone until it is used.
+//! The tables are parsed with `ttf-parser`.
+
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use ttf_parser::{name::Name, FaceParsingError, PlatformId, RawFace, Tag};
+
+/// Font directories can contain symlinks pointing back up the tree, so recursion is bounded
+const MAX_DIRECTORY_DEPTH: usize = 8;
+
+/// Bounds the amount of work a malformed font collection can make us do
+const MAX_FACES_PER_FILE: u32 = 256;
+
+/// The table directories are at the start of the file, and are read in growing chunks up to this size
+const MAX_DIRECTORY_SIZE: usize = 1 << 20;
+
+/// A font file, and the index of the font in it if it is a collection
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FontSource {
+    pub path: PathBuf,
+    pub index: u32,
+}
+
+impl FontSource {
+    pub fn file(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            index: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FontFace {
+    pub source: FontSource,
+    pub family: String,
+    /// e.g. `Bold Italic`
+    pub style: String,
+    pub full_name: String,
+    pub postscript_name: String,
+    pub bold: bool,
+    pub italic: bool,
+    /// Offset and length of the table mapping characters to glyphs,
+    /// which is only read when looking for fonts that have the characters missing from `-font`
+    cmap: Option<(u32, u32)>,
+}
+
+impl FontFace {
+    /// Whether the font has each of the characters, or `None` if its character map cannot be read
+    fn covered(&self, chars: &[char]) -> Option<Vec<bool>> {
+        let (offset, length) = self.cmap?;
+        let mut file = File::open(&self.source.path).ok()?;
+        let data = read_at(&mut file, offset.into(), length as usize)?;
+        let cmap = ttf_parser::cmap::Table::parse(&data)?;
+        let subtables: Vec<_> = cmap
+            .subtables
+            .into_iter()
+            .filter(|subtable| subtable.is_unicode())
+            .collect();
+        let covered = chars.iter().map(|&c| {
+            subtables
+                .iter()
+                .any(|subtable| subtable.glyph_index(c.into()).is_some_and(|id| id.0 != 0))
+        });
+        Some(covered.collect())
+    }
+
+    /// The names the font can be looked up by, other than the family name
+    fn names(&self) -> [String; 3] {
+        [
+            self.full_name.clone(),
+            self.postscript_name.clone(),
+            format!("{} {}", self.family, self.style),
+        ]
+    }
+}
+
+/// The fonts found on the system
+#[derive(Debug, Default)]
+pub struct FontCatalog {
+    faces: Vec<FontFace>,
+}
+
+impl FontCatalog {
+    /// The fonts in the usual font directories of the platform and in `MAGICK_FONT_PATH`,
+    /// scanned the first time this is called
+    pub fn system() -> &'static FontCatalog {
+        static CATALOG: OnceLock<FontCatalog> = OnceLock::new();
+        CATALOG.get_or_init(|| FontCatalog::scan(&font_directories()))
+    }
+
+    /// Finds all fonts in the directories and their subdirectories
+    pub fn scan(directories: &[PathBuf]) -> Self {
+        let mut files = Vec::new();
+        for directory in directories {
+            find_font_files(directory, MAX_DIRECTORY_DEPTH, &mut files);
+        }
+        // Directory listings come in no particular order, but the results should not change from run to run
+        files.sort();
+        files.dedup();
+        let faces = files.iter().flat_map(|path| describe(path)).collect();
+        Self { faces }
+    }
+
+    /// Looks up a font by its full name, its PostScript name, or its family and style.
+    /// Spaces, dashes and case do not matter, so `DejaVu-Sans-Bold` finds `DejaVu Sans Bold`.
+    ///
+    /// A family name on its own finds the regular style of the family.
+    pub fn find(&self, name: &str) -> Option<&FontFace> {
+        let key = normalize(name);
+        if key.is_empty() {
+            return None;
+        }
+        self.faces
+            .iter()
+            .find(|face| face.names().iter().any(|name| normalize(name) == key))
+            .or_else(|| {
+                self.faces
+                    .iter()
+                    .filter(|face| normalize(&face.family) == key)
+                    .min_by_key(|face| (face.bold, face.italic))
+            })
+    }
+
+    /// Finds the face of a family in a specific style
+    pub fn find_style(&self, family: &str, bold: bool, italic: bool) -> Option<&FontFace> {
+        let key = normalize(family);
+        self.faces.iter().find(|face| {
+            normalize(&face.family) == key && face.bold == bold && face.italic == italic
+        })
+    }
+
+    /// Finds fonts for the characters, preferring the regular style and taking each font that has
+    /// any of the characters not covered so far, up to `limit` fonts
+    pub fn fallbacks(&self, missing: &[char], limit: usize) -> Vec<&FontFace> {
+        let mut missing = missing.to_vec();
+        let mut fallbacks = Vec::new();
+        let mut faces: Vec<&FontFace> = self.faces.iter().collect();
+        faces.sort_by_key(|face| (face.bold, face.italic));
+        for face in faces {
+            if missing.is_empty() || fallbacks.len() == limit {
+                break;
+            }
+            let Some(covered) = face.covered(&missing) else {
+                continue;
+            };
+            if covered.iter().any(|&covered| covered) {
+                let mut covered = covered.into_iter();
+                missing.retain(|_| !covered.next().unwrap_or(false));
+                fallbacks.push(face);
+            }
+        }
+        fallbacks
+    }
+
+    pub fn by_source(&self, source: &FontSource) -> Option<&FontFace> {
+        self.faces.iter().find(|face| face.source == *source)
+    }
+
+    /// Implements `-list font`, in the format imagemagick uses for fonts found by fontconfig
+    pub fn list(&self) -> String {
+        let mut out = String::from("Path: System Fonts\n");
+        for face in &self.faces {
+            let style = if face.italic { "Italic" } else { "Normal" };
+            let weight = if face.bold { 700 } else { 400 };
+            out.push_str(&format!(
+                "  Font: {}\n    family: {}\n    style: {}\n    weight: {}\n    glyphs: {}\n",
+                face.full_name.replace(' ', "-"),
+                face.family,
+                style,
+                weight,
+                face.source.path.display(),
+            ));
+        }
+        out
+    }
+}
+
+/// Lowercase letters and digits only
+fn normalize(name: &str) -> String {
+    name.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+fn font_directories() -> Vec<PathBuf> {
+    let mut directories: Vec<PathBuf> = std::env::var_os("MAGICK_FONT_PATH")
+        .map(|paths| std::env::split_paths(&paths).collect())
+        .unwrap_or_default();
+    let env_path = |name: &str, subdirectory: &str| {
+        std::env::var_os(name).map(|path| PathBuf::from(path).join(subdirectory))
+    };
+    if cfg!(windows) {
+        directories.extend(env_path("WINDIR", "Fonts"));
+        directories.extend(env_path("LOCALAPPDATA", "Microsoft\\Windows\\Fonts"));
+    } else if cfg!(target_os = "macos") {
+        directories.extend(env_path("HOME", "Library/Fonts"));
+        directories.push("/Library/Fonts".into());
+        directories.push("/System/Library/Fonts".into());
+    } else {
+        match std::env::var_os("XDG_DATA_HOME") {
+            Some(data) => directories.push(PathBuf::from(data).join("fonts")),
+            None => directories.extend(env_path("HOME", ".local/share/fonts")),
+        }
+        directories.extend(env_path("HOME", ".fonts"));
+        directories.push("/usr/local/share/fonts".into());
+        directories.push("/usr/share/fonts".into());
+    }
+    directories
+}
+
+fn find_font_files(directory: &Path, depth: usize, files: &mut Vec<PathBuf>) {
+    let Ok(entries) = std::fs::read_dir(directory) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            if depth > 0 {
+                find_font_files(&path, depth - 1, files);
+            }
+            continue;
+        }
+        let extension = path.extension().and_then(|e| e.to_str());
+        let extension = extension.map(str::to_ascii_lowercase);
+        if matches!(extension.as_deref(), Some("ttf" | "ttc" | "otf")) {
+            files.push(path);
+        }
+    }
+}
+
+/// Reads the names and styles of all the fonts in a file, skipping the ones we cannot use
+fn describe(path: &Path) -> Vec<FontFace> {
+    let Ok(mut file) = File::open(path) else {
+        return Vec::new();
+    };
+    let mut start = Vec::new();
+    let mut faces = Vec::new();
+    for index in 0..MAX_FACES_PER_FILE {
+        let Some(directory) = read_directory(&mut file, &mut start, index) else {
+            break;
+        };
+        let source = FontSource {
+            path: path.to_owned(),
+            index,
+        };
+        faces.extend(describe_face(&mut file, &directory, source));
+    }
+    faces
+}
+
+/// Reads enough of the start of the file to parse the table directory of the font with the index.
+/// Returns `None` past the last font in the file.
+fn read_directory<'a>(file: &mut File, start: &'a mut Vec<u8>, index: u32) -> Option<RawFace<'a>> {
+    loop {
+        match RawFace::parse(start, index) {
+            Err(FaceParsingError::MalformedFont | FaceParsingError::UnknownMagic)
+                if start.len() < MAX_DIRECTORY_SIZE =>
+            {
+                let length = (2 * start.len()).clamp(1024, MAX_DIRECTORY_SIZE);
+                let read = read_at(file, 0, length)?;
+                if read.len() == start.len() {
+                    // The file is shorter than the directory claims
+                    return None;
+                }
+                *start = read;
+            }
+            _ => break,
+        }
+    }
+    RawFace::parse(start, index).ok()
+}
+
+fn describe_face(file: &mut File, directory: &RawFace, source: FontSource) -> Option<FontFace> {
+    let record = |tag: &[u8; 4]| {
+        let tag = Tag::from_bytes(tag);
+        directory
+            .table_records
+            .into_iter()
+            .find(|record| record.tag == tag)
+    };
+    let mut table = |tag| {
+        let record = record(tag)?;
+        read_at(file, record.offset.into(), record.length as usize)
+            .filter(|data| data.len() == record.length as usize)
+    };
+    let names = table(b"name")?;
+    let names = ttf_parser::name::Table::parse(&names)?;
+    let os2 = table(b"OS/2");
+    let os2 = os2.as_deref().and_then(ttf_parser::os2::Table::parse);
+
+    let name = |id| name(&names, id);
+    let family = name(ttf_parser::name_id::FAMILY)?;
+    let style = name(ttf_parser::name_id::SUBFAMILY).unwrap_or_else(|| "Regular".to_owned());
+    Some(FontFace {
+        source,
+        full_name: name(ttf_parser::name_id::FULL_NAME)
+            .unwrap_or_else(|| format!("{family} {style}")),
+        postscript_name: name(ttf_parser::name_id::POST_SCRIPT_NAME).unwrap_or_default(),
+        family,
+        style,
+        bold: os2.is_some_and(|os2| os2.is_bold()),
+        italic: os2.is_some_and(|os2| os2.style() != ttf_parser::Style::Normal),
+        cmap: record(b"cmap").map(|record| (record.offset, record.length)),
+    })
+}
+
+/// Reads up to `length` bytes at the offset
+fn read_at(file: &mut File, offset: u64, length: usize) -> Option<Vec<u8>> {
+    file.seek(SeekFrom::Start(offset)).ok()?;
+    let mut data = Vec::new();
+    file.take(length as u64).read_to_end(&mut data).ok()?;
+    Some(data)
+}
+
+/// Reads a string from the `name` table, preferring Unicode names in US English
+fn name(table: &ttf_parser::name::Table, id: u16) -> Option<String> {
+    let mut best: Option<(u8, String)> = None;
+    for record in table.names.into_iter().filter(|name| name.name_id == id) {
+        let (rank, name) = match record.platform_id {
+            // Mac Roman matches ASCII, which is what the names are written in almost always
+            PlatformId::Macintosh => (2, Some(mac_roman(&record))),
+            _ if record.is_unicode() => {
+                let english =
+                    record.platform_id == PlatformId::Windows && record.language_id == 0x409;
+                (if english { 0 } else { 1 }, record.to_string())
+            }
+            _ => continue,
+        };
+        let Some(name) = name.filter(|name| !name.trim().is_empty()) else {
+            continue;
+        };
+        if best.as_ref().is_none_or(|(best, _)| rank < *best) {
+            best = Some((rank, name));
+        }
+    }
+    best.map(|(_, name)| name)
+}
+
+fn mac_roman(name: &Name) -> String {
+    name.name.iter().map(|&b| char::from(b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::text::test_font::styled_test_font;
+
+    fn catalog() -> (tempfile::TempDir, FontCatalog) {
+        let directory = tempfile::tempdir().unwrap();
+        let nested = directory.path().join("test");
+        std::fs::create_dir(&nested).unwrap();
+        for (file, style, bold, italic) in [
+            ("TestSans.ttf", "Regular", false, false),
+            ("TestSans-Bold.TTF", "Bold", true, false),
+            ("TestSans-BoldOblique.ttf", "Bold Oblique", true, true),
+        ] {
+            std::fs::write(nested.join(file), styled_test_font(style, bold, italic)).unwrap();
+        }
+        std::fs::write(nested.join("notes.txt"), "not a font").unwrap();
+        std::fs::write(nested.join("broken.ttf"), "not a font either").unwrap();
+        let catalog = FontCatalog::scan(&[directory.path().to_owned()]);
+        (directory, catalog)
+    }
+
+    #[test]
+    fn scanning() {
+        let (_directory, catalog) = catalog();
+        assert_eq!(catalog.faces.len(), 3);
+        let bold = &catalog.faces[0];
+        assert!(bold.source.path.ends_with("test/TestSans-Bold.TTF"));
+        assert_eq!(bold.family, "Test Sans");
+        assert_eq!(bold.style, "Bold");
+        assert_eq!(bold.full_name, "Test Sans Bold");
+        assert_eq!(bold.postscript_name, "TestSans-Bold");
+        assert!(bold.bold && !bold.italic);
+    }
+
+    #[test]
+    fn lookup() {
+        let (_directory, catalog) = catalog();
+        let style = |name| catalog.find(name).map(|face| face.style.as_str());
+        assert_eq!(style("Test Sans"), Some("Regular"));
+        assert_eq!(style("test-sans"), Some("Regular"));
+        assert_eq!(style("Test-Sans-Bold"), Some("Bold"));
+        assert_eq!(style("TestSans-BoldOblique"), Some("Bold Oblique"));
+        assert_eq!(style("Test Sans Bold Oblique"), Some("Bold Oblique"));
+        assert_eq!(style("Test Serif"), None);
+        assert_eq!(style(""), None);
+
+        let bold_italic = catalog.find_style("Test Sans", true, true).unwrap();
+        assert_eq!(bold_italic.style, "Bold Oblique");
+        assert!(catalog.find_style("Test Sans", false, true).is_none());
+        assert_eq!(catalog.by_source(&bold_italic.source), Some(bold_italic));
+    }
+
+    #[test]
+    fn fallbacks() {
+        let (_directory, catalog) = catalog();
+        // the regular face is preferred, and only one font is needed for the characters it has
+        let fallbacks = catalog.fallbacks(&['I', 'O', 'Z'], 8);
+        assert_eq!(fallbacks.len(), 1);
+        assert_eq!(fallbacks[0].style, "Regular");
+        assert!(catalog.fallbacks(&['Z'], 8).is_empty());
+        assert!(catalog.fallbacks(&['I'], 0).is_empty());
+    }
+
+    #[test]
+    fn listing() {
+        let (directory, catalog) = catalog();
+        let list = catalog.list();
+        assert!(list.starts_with("Path: System Fonts\n  Font: Test-Sans-Bold\n"));
+        let path = directory.path().join("test/TestSans-BoldOblique.ttf");
+        let entry = format!(
+            "  Font: Test-Sans-Bold-Oblique\n    family: Test Sans\n    style: Italic\n    weight: 700\n    glyphs: {}\n",
+            path.display()
+        );
+        assert!(list.contains(&entry), "{list}");
+        // every listed name can be passed to -font
+        for line in list
+            .lines()
+            .filter_map(|line| line.strip_prefix("  Font: "))
+        {
+            assert!(catalog.find(line).is_some(), "{line}");
+        }
+    }
+}
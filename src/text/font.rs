@@ -0,0 +1,289 @@
+//! Fonts as seen by the text layout: character mapping, glyph outlines and vertical metrics.
+//!
+//! The font files are read by `ttf-parser`, which handles both TrueType and PostScript (CFF) outlines.
+//! Shaping is done by cosmic-text, which gets the same font data through [`Fonts`].
+
+use std::cell::{RefCell, RefMut};
+use std::ops::Index;
+use std::path::Path;
+use std::sync::Arc;
+
+use cosmic_text::FontSystem;
+use ttf_parser::{Face, Language, OutlineBuilder};
+
+use crate::{error::MagickError, wm_err};
+
+/// A point in font units with the y axis pointing up, or in pixels with the y axis pointing down
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Point {
+    pub x: f32,
+    pub y: f32,
+}
+
+impl Point {
+    pub fn new(x: f32, y: f32) -> Self {
+        Self { x, y }
+    }
+}
+
+/// Outlines are made of straight lines and quadratic or cubic curves
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PathCommand {
+    MoveTo(Point),
+    LineTo(Point),
+    /// Control point and end point
+    QuadTo(Point, Point),
+    /// Two control points and end point, used by PostScript outlines
+    CubicTo(Point, Point, Point),
+    Close,
+}
+
+impl PathCommand {
+    /// Applies the function to every point of the command
+    pub fn map(self, f: impl Fn(Point) -> Point) -> PathCommand {
+        match self {
+            PathCommand::MoveTo(p) => PathCommand::MoveTo(f(p)),
+            PathCommand::LineTo(p) => PathCommand::LineTo(f(p)),
+            PathCommand::QuadTo(c, p) => PathCommand::QuadTo(f(c), f(p)),
+            PathCommand::CubicTo(c1, c2, p) => PathCommand::CubicTo(f(c1), f(c2), f(p)),
+            PathCommand::Close => PathCommand::Close,
+        }
+    }
+}
+
+/// Glyph index in the font. Glyph 0 is the one drawn for missing characters.
+pub type GlyphId = u16;
+
+#[derive(Debug, Clone)]
+pub struct Font {
+    data: Arc<Vec<u8>>,
+    index: u32,
+    units_per_em: f32,
+    ascender: f32,
+    descender: f32,
+    line_gap: f32,
+}
+
+impl Font {
+    /// Reads a font from a `.ttf` or `.otf` file, or the font with the given index in a collection
+    pub fn open(path: &Path, index: u32) -> Result<Font, MagickError> {
+        let unable = || wm_err!("unable to read font `{}'", path.display());
+        let data = std::fs::read(path).map_err(|_| unable())?;
+        Font::parse_face(data, index).ok_or_else(unable)
+    }
+
+    /// Parses the font, returning `None` if it is malformed or unsupported
+    #[cfg(test)]
+    pub fn parse(data: Vec<u8>) -> Option<Font> {
+        Font::parse_face(data, 0)
+    }
+
+    /// Parses the font with the given index in a collection, or the font itself if index is 0
+    pub fn parse_face(data: Vec<u8>, index: u32) -> Option<Font> {
+        let face = Face::parse(&data, index).ok()?;
+        let (units_per_em, ascender, descender, line_gap) = (
+            face.units_per_em().into(),
+            face.ascender().into(),
+            face.descender().into(),
+            face.line_gap().into(),
+        );
+        Some(Font {
+            data: Arc::new(data),
+            index,
+            units_per_em,
+            ascender,
+            descender,
+            line_gap,
+        })
+    }
+
+    fn face(&self) -> Face<'_> {
+        Face::parse(&self.data, self.index).expect("the font was parsed when it was loaded")
+    }
+
+    pub fn units_per_em(&self) -> f32 {
+        self.units_per_em
+    }
+
+    /// Distance from the baseline to the top of the tallest glyphs, in font units
+    pub fn ascender(&self) -> f32 {
+        self.ascender
+    }
+
+    /// Distance between the baselines of two lines of text, in font units
+    pub fn line_height(&self) -> f32 {
+        self.ascender - self.descender + self.line_gap
+    }
+
+    /// Looks up the glyph for the character, or glyph 0 if the font does not have it
+    pub fn glyph_id(&self, c: char) -> GlyphId {
+        self.face().glyph_index(c).map_or(0, |glyph| glyph.0)
+    }
+
+    /// The outline of the glyph in font units, empty for glyphs like the space
+    pub fn outline(&self, glyph: GlyphId) -> Vec<PathCommand> {
+        let mut outline = Outline(Vec::new());
+        self.face()
+            .outline_glyph(ttf_parser::GlyphId(glyph), &mut outline);
+        outline.0
+    }
+}
+
+/// The fonts a piece of text is laid out with, shared with cosmic-text which shapes the text
+pub struct Fonts {
+    /// Shaping mutates the caches of the font system, but not the fonts
+    system: RefCell<FontSystem>,
+    fonts: Vec<Font>,
+    /// The IDs cosmic-text knows the fonts by, in the same order
+    ids: Vec<fontdb::ID>,
+}
+
+impl Fonts {
+    pub fn new(font: Font) -> Self {
+        let system =
+            FontSystem::new_with_locale_and_db("en-US".to_owned(), fontdb::Database::new());
+        let mut fonts = Self {
+            system: RefCell::new(system),
+            fonts: Vec::new(),
+            ids: Vec::new(),
+        };
+        fonts.add(font);
+        fonts
+    }
+
+    /// Adds the font, returning its index.
+    ///
+    /// Fonts are registered under their index as the family name and all in the regular style,
+    /// so that cosmic-text uses exactly the font a run of text asks for, and falls back to
+    /// the other fonts in the order they were added for characters the font does not have.
+    pub fn add(&mut self, font: Font) -> usize {
+        let index = self.fonts.len();
+        let face = fontdb::FaceInfo {
+            id: fontdb::ID::dummy(),
+            source: fontdb::Source::Binary(font.data.clone()),
+            index: font.index,
+            families: vec![(Self::family(index), Language::English_UnitedStates)],
+            post_script_name: String::new(),
+            style: fontdb::Style::Normal,
+            weight: fontdb::Weight::NORMAL,
+            stretch: fontdb::Stretch::Normal,
+            monospaced: false,
+        };
+        self.ids
+            .push(self.system.get_mut().db_mut().push_face_info(face));
+        self.fonts.push(font);
+        index
+    }
+
+    /// The family name cosmic-text knows the font with the index by
+    pub fn family(index: usize) -> String {
+        index.to_string()
+    }
+
+    /// The index of the font cosmic-text picked for a glyph
+    pub fn position(&self, id: fontdb::ID) -> usize {
+        self.ids
+            .iter()
+            .position(|&known| known == id)
+            .expect("cosmic-text only knows the fonts that were added")
+    }
+
+    pub fn system(&self) -> RefMut<'_, FontSystem> {
+        self.system.borrow_mut()
+    }
+}
+
+impl Index<usize> for Fonts {
+    type Output = Font;
+
+    fn index(&self, index: usize) -> &Font {
+        &self.fonts[index]
+    }
+}
+
+/// Collects the outline `ttf-parser` reads from the font
+struct Outline(Vec<PathCommand>);
+
+impl OutlineBuilder for Outline {
+    fn move_to(&mut self, x: f32, y: f32) {
+        self.0.push(PathCommand::MoveTo(Point::new(x, y)));
+    }
+
+    fn line_to(&mut self, x: f32, y: f32) {
+        self.0.push(PathCommand::LineTo(Point::new(x, y)));
+    }
+
+    fn quad_to(&mut self, x1: f32, y1: f32, x: f32, y: f32) {
+        self.0
+            .push(PathCommand::QuadTo(Point::new(x1, y1), Point::new(x, y)));
+    }
+
+    fn curve_to(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, x: f32, y: f32) {
+        self.0.push(PathCommand::CubicTo(
+            Point::new(x1, y1),
+            Point::new(x2, y2),
+            Point::new(x, y),
+        ));
+    }
+
+    fn close(&mut self) {
+        self.0.push(PathCommand::Close);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::text::test_font::{self, test_font};
+
+    #[test]
+    fn metrics() {
+        let font = Font::parse(test_font()).unwrap();
+        assert_eq!(font.units_per_em(), 1000.0);
+        assert_eq!(font.line_height(), 1000.0);
+        assert_eq!(font.glyph_id('I'), test_font::I);
+        assert_eq!(font.glyph_id('H'), test_font::H);
+        assert_eq!(font.glyph_id(' '), test_font::SPACE);
+        assert_eq!(font.glyph_id('Z'), test_font::NOTDEF);
+        assert_eq!(font.glyph_id('\u{1F600}'), test_font::NOTDEF);
+    }
+
+    #[test]
+    fn outlines() {
+        let font = Font::parse(test_font()).unwrap();
+        let rectangle = font.outline(test_font::I);
+        assert_eq!(rectangle[0], PathCommand::MoveTo(Point::new(100.0, 0.0)));
+        assert_eq!(rectangle[4], PathCommand::LineTo(Point::new(100.0, 0.0)));
+        assert_eq!(rectangle.last(), Some(&PathCommand::Close));
+        // with only off-curve points, every segment is a curve,
+        // starting halfway between the first two points
+        let curvy = font.outline(test_font::O);
+        assert_eq!(curvy[0], PathCommand::MoveTo(Point::new(375.0, 175.0)));
+        assert_eq!(
+            curvy
+                .iter()
+                .filter(|c| matches!(c, PathCommand::QuadTo(..)))
+                .count(),
+            4
+        );
+        // composite glyphs are made of shifted copies of the components
+        let composite = font.outline(test_font::H);
+        assert_eq!(composite.len(), rectangle.len() * 2);
+        assert_eq!(
+            composite[rectangle.len()],
+            PathCommand::MoveTo(Point::new(400.0, 0.0))
+        );
+        assert!(font.outline(test_font::SPACE).is_empty());
+    }
+
+    #[test]
+    fn malformed_fonts() {
+        assert!(Font::parse(Vec::new()).is_none());
+        assert!(Font::parse(b"OTTO".to_vec()).is_none());
+        let mut truncated = test_font();
+        truncated.truncate(200);
+        assert!(Font::parse(truncated).is_none());
+        // a font file that is not a collection only has the one font
+        assert!(Font::parse_face(test_font(), 1).is_none());
+    }
+}
@@ -0,0 +1,301 @@
+//! Breaking text into lines and placing the glyphs on them.
+//!
+//! Shaping, bidirectional reordering and line breaking are done by cosmic-text,
+//! the lines are measured and drawn by us.
+
+use std::ops::Range;
+
+use cosmic_text::{Align, Attrs, AttrsList, Family, Hinting, Metrics, ShapeLine, Shaping, Wrap};
+
+use super::font::{Fonts, GlyphId};
+use crate::arg_parsers::Color;
+
+/// Width of a tab in spaces
+const TAB_WIDTH: u16 = 8;
+
+/// Text laid out at a specific size, in pixels
+#[derive(Debug, Clone)]
+pub struct Layout {
+    pub lines: Vec<Line>,
+    /// Styles of the runs the text was made of, referred to by the glyphs
+    pub styles: Vec<RunStyle>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Line {
+    pub glyphs: Vec<PlacedGlyph>,
+    /// How far the pen has moved by the end of the line
+    pub width: f32,
+    /// Distance from the top of the line to its baseline
+    pub ascent: f32,
+    /// Distance from the top of the line to the top of the next one
+    pub height: f32,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PlacedGlyph {
+    pub id: GlyphId,
+    /// Index of the font the glyph is from, which is not the font of its run
+    /// if the glyph was missing from it
+    pub font: usize,
+    /// Position of the glyph origin from the left end of the line
+    pub x: f32,
+    /// Position of the glyph origin below the baseline, for marks placed above or below letters
+    pub y: f32,
+    /// Index into [`Layout::styles`]
+    pub style: usize,
+}
+
+/// A piece of text drawn in the same way throughout
+#[derive(Debug, Clone, Copy)]
+pub struct Run<'a> {
+    pub text: &'a str,
+    pub style: RunStyle,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RunStyle {
+    /// Index into the fonts the text is laid out with
+    pub font: usize,
+    pub pixel_size: f32,
+    /// Slant the glyphs, for italics in fonts that have no italic face
+    pub oblique: bool,
+    /// The color of the glyphs if it is not the `-fill` color
+    pub fill: Option<Color>,
+}
+
+impl RunStyle {
+    pub fn plain(pixel_size: f32) -> Self {
+        Self {
+            font: 0,
+            pixel_size,
+            oblique: false,
+            fill: None,
+        }
+    }
+
+    /// Pixels per font unit of the font with the index
+    pub fn scale(&self, fonts: &Fonts, font: usize) -> f32 {
+        self.pixel_size / fonts[font].units_per_em()
+    }
+}
+
+/// Extra space in pixels set by `-kerning`, `-interline-spacing` and `-interword-spacing`.
+/// Negative values bring things closer together.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Spacing {
+    /// Added between characters
+    pub kerning: f32,
+    /// Added between lines
+    pub interline: f32,
+    /// Added to every space
+    pub interword: f32,
+}
+
+impl Layout {
+    /// Lays out the text in a single font at the given size in pixels.
+    ///
+    /// Lines are broken at newlines, and also between words if the text is wrapped to a width.
+    /// Words that do not fit on a line by themselves are broken between characters.
+    pub fn new(
+        fonts: &Fonts,
+        text: &str,
+        pixel_size: f32,
+        spacing: Spacing,
+        wrap_width: Option<f32>,
+    ) -> Self {
+        let run = Run {
+            text,
+            style: RunStyle::plain(pixel_size),
+        };
+        Self::styled(fonts, &[run], spacing, wrap_width)
+    }
+
+    /// Lays out text made of runs in different fonts, sizes and colors.
+    ///
+    /// Each line is as tall as the largest text on it.
+    pub fn styled(fonts: &Fonts, runs: &[Run], spacing: Spacing, wrap_width: Option<f32>) -> Self {
+        let styles: Vec<RunStyle> = runs.iter().map(|run| run.style).collect();
+        let text: String = runs.iter().map(|run| run.text).collect();
+        // The range of the text each run covers
+        let mut ranges = Vec::with_capacity(runs.len());
+        for run in runs {
+            let start = ranges.last().map_or(0, |range: &Range<usize>| range.end);
+            ranges.push(start..start + run.text.len());
+        }
+        let shaper = Shaper {
+            fonts,
+            styles: &styles,
+            families: styles
+                .iter()
+                .map(|style| Fonts::family(style.font))
+                .collect(),
+            spacing,
+        };
+        let style_at = |offset: usize| ranges.iter().position(|range| range.contains(&offset));
+        let mut lines = Vec::new();
+        let mut start = 0;
+        for paragraph in text.split('\n') {
+            let end = start + paragraph.len();
+            // The styles of the parts of the paragraph, with ranges relative to its start
+            let styled: Vec<_> = ranges
+                .iter()
+                .enumerate()
+                .filter_map(|(style, range)| {
+                    let range = range.start.max(start)..range.end.min(end);
+                    (!range.is_empty()).then(|| (range.start - start..range.end - start, style))
+                })
+                .collect();
+            if styled.is_empty() {
+                // Empty lines are as tall as the newline that ends them
+                let fallback = style_at(end).or(text.len().checked_sub(1).and_then(style_at));
+                lines.push(shaper.measure(Line::default(), fallback));
+            } else {
+                shaper.shape(paragraph, &styled, wrap_width, &mut lines);
+            }
+            start = end + 1;
+        }
+        let last = lines.len() - 1;
+        for line in &mut lines[..last] {
+            line.height += spacing.interline;
+        }
+        Self { lines, styles }
+    }
+
+    /// Width of the longest line
+    pub fn width(&self) -> f32 {
+        self.lines.iter().map(|line| line.width).fold(0.0, f32::max)
+    }
+
+    pub fn height(&self) -> f32 {
+        self.lines.iter().map(|line| line.height).sum()
+    }
+}
+
+struct Shaper<'a> {
+    fonts: &'a Fonts,
+    styles: &'a [RunStyle],
+    /// The names cosmic-text knows the font of each style by
+    families: Vec<String>,
+    spacing: Spacing,
+}
+
+impl Shaper<'_> {
+    fn attributes(&self, style: usize, extra_spacing: f32) -> Attrs<'_> {
+        let size = self.styles[style].pixel_size;
+        Attrs::new()
+            .family(Family::Name(&self.families[style]))
+            .metadata(style)
+            .metrics(Metrics::new(size, size))
+            // cosmic-text adds the letter spacing after every glyph, in ems of the font size
+            .letter_spacing((self.spacing.kerning + extra_spacing) / size)
+    }
+
+    /// Shapes a paragraph of text made of the styled ranges, wrapping it to the width if there is one
+    fn shape(
+        &self,
+        paragraph: &str,
+        styled: &[(Range<usize>, usize)],
+        wrap_width: Option<f32>,
+        lines: &mut Vec<Line>,
+    ) {
+        let first = styled[0].1;
+        let mut attributes = AttrsList::new(&self.attributes(first, 0.0));
+        for (range, style) in styled {
+            attributes.add_span(range.clone(), &self.attributes(*style, 0.0));
+            if self.spacing.interword != 0.0 {
+                // Spaces get the extra space as letter spacing, so that wrapping accounts for it
+                let spaces = paragraph[range.clone()].match_indices(' ');
+                for (offset, _) in spaces {
+                    let space = range.start + offset;
+                    let extra = self.attributes(*style, self.spacing.interword);
+                    attributes.add_span(space..space + 1, &extra);
+                }
+            }
+        }
+        let shaped = ShapeLine::new(
+            &mut self.fonts.system(),
+            paragraph,
+            &attributes,
+            Shaping::Advanced,
+            TAB_WIDTH,
+        );
+        let laid_out = shaped.layout(
+            self.styles[first].pixel_size,
+            wrap_width,
+            Wrap::WordOrGlyph,
+            // Lines are aligned by gravity later, for which they have to start at the left edge
+            Some(Align::Left),
+            None,
+            Hinting::Disabled,
+        );
+        for laid_out in laid_out {
+            let line = Line {
+                glyphs: laid_out
+                    .glyphs
+                    .iter()
+                    .map(|glyph| PlacedGlyph {
+                        id: glyph.glyph_id,
+                        font: self.fonts.position(glyph.font_id),
+                        x: glyph.x + glyph.x_offset * glyph.font_size,
+                        y: glyph.y - glyph.y_offset * glyph.font_size,
+                        style: glyph.metadata,
+                    })
+                    .collect(),
+                width: laid_out.w,
+                ..Line::default()
+            };
+            lines.push(self.measure(line, Some(first)));
+        }
+    }
+
+    /// Sets the height of the line from the fonts of its glyphs,
+    /// or from the font of the fallback style if it has none
+    fn measure(&self, mut line: Line, fallback: Option<usize>) -> Line {
+        let fonts = line.glyphs.iter().map(|glyph| (glyph.style, glyph.font));
+        let fallback = fallback.map(|style| (style, self.styles[style].font));
+        let fonts: Vec<_> = match line.glyphs.is_empty() {
+            true => fallback.into_iter().collect(),
+            false => fonts.collect(),
+        };
+        for (style, font) in fonts {
+            let scale = self.styles[style].scale(self.fonts, font);
+            let font = &self.fonts[font];
+            line.ascent = line.ascent.max(font.ascender() * scale);
+            line.height = line.height.max(font.line_height() * scale);
+        }
+        line
+    }
+}
+
+/// Finds the largest point size at which the text fits into the given width and/or height,
+/// like imagemagick does for `label:` and `caption:` when `-pointsize` is not set.
+///
+/// `measure` returns the size of the text in pixels at the given point size.
+pub fn fit_pointsize(
+    measure: impl Fn(f64) -> (u32, u32),
+    width: Option<u32>,
+    height: Option<u32>,
+) -> f64 {
+    let too_big = |(w, h): (u32, u32)| {
+        width.is_some_and(|width| w >= width) || height.is_some_and(|height| h >= height)
+    };
+    // Double the size until the text no longer fits, then narrow it down with a binary search
+    let mut high = 12.0;
+    for _ in 0..32 {
+        if too_big(measure(high)) {
+            break;
+        }
+        high *= 2.0;
+    }
+    let mut low = 1.0;
+    while high - low > 0.5 {
+        let pointsize = (low + high) / 2.0;
+        if too_big(measure(pointsize)) {
+            high = pointsize - 0.5;
+        } else {
+            low = pointsize + 0.5;
+        }
+    }
+    ((low + high) / 2.0 - 0.5).floor().max(1.0)
+}
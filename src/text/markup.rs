@@ -0,0 +1,287 @@
+//! A subset of the Pango markup language used by `pango:`.
+//!
+//! Supported are `<b>`, `<i>`, `<big>`, `<small>` and `<span>` with the `font`, `size`, `weight`,
+//! `style` and `foreground` attributes, which is enough to mix bold, italic, sizes and colors.
+//! The markup is read with `quick-xml`. Document type definitions are rejected,
+//! so entities other than the predefined ones and character references are not supported.
+
+use std::fmt::Display;
+use std::str::FromStr;
+
+use quick_xml::escape::resolve_predefined_entity;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::{Reader, XmlVersion};
+
+use crate::{arg_parsers::Color, error::MagickError, wm_err};
+
+/// Scaling applied by `<big>`, `<small>`, `larger` and `smaller`, same as in Pango
+const SIZE_STEP: f64 = 1.2;
+
+/// A piece of text with the same attributes throughout
+#[derive(Debug, Clone, PartialEq)]
+pub struct Span {
+    pub text: String,
+    pub attributes: Attributes,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Attributes {
+    pub bold: bool,
+    pub italic: bool,
+    /// Size in points
+    pub size: f64,
+    /// `None` means the `-fill` color
+    pub foreground: Option<Color>,
+}
+
+impl Attributes {
+    pub fn new(size: f64) -> Self {
+        Self {
+            bold: false,
+            italic: false,
+            size,
+            foreground: None,
+        }
+    }
+}
+
+/// Splits the markup into spans of text, starting from the given attributes.
+/// Neighboring spans with the same attributes are merged.
+pub fn parse(markup: &str, base: Attributes) -> Result<Vec<Span>, MagickError> {
+    let invalid = |error: &dyn Display| wm_err!("invalid markup `{}': {}", markup, error);
+    let mut reader = Reader::from_str(markup);
+    let mut spans: Vec<Span> = Vec::new();
+    // The attributes in effect inside each of the tags that are open
+    let mut stack: Vec<Attributes> = Vec::new();
+    loop {
+        let attributes = stack.last().copied().unwrap_or(base);
+        let text = match reader.read_event().map_err(|error| invalid(&error))? {
+            Event::Start(tag) => {
+                stack.push(apply_tag(&tag, attributes, base.size)?);
+                continue;
+            }
+            Event::Empty(tag) => {
+                apply_tag(&tag, attributes, base.size)?;
+                continue;
+            }
+            Event::End(_) => {
+                stack.pop();
+                continue;
+            }
+            Event::Text(text) => text.xml10_content().into_owned(),
+            Event::CData(text) => text.xml10_content().into_owned(),
+            Event::GeneralRef(entity) => match entity.resolve_char_ref() {
+                Ok(Some(c)) => c.to_string(),
+                Ok(None) => resolve_predefined_entity(&entity)
+                    .ok_or_else(|| wm_err!("unknown entity `&{};' in markup", &*entity))?
+                    .to_owned(),
+                Err(error) => return Err(invalid(&error)),
+            },
+            Event::DocType(_) => {
+                return Err(invalid(&"document type definitions are not supported"))
+            }
+            Event::Comment(_) | Event::PI(_) | Event::Decl(_) => continue,
+            Event::Eof => break,
+        };
+        match spans.last_mut() {
+            Some(last) if last.attributes == attributes => last.text.push_str(&text),
+            _ => spans.push(Span { text, attributes }),
+        }
+    }
+    if !stack.is_empty() {
+        return Err(invalid(&"unclosed tag"));
+    }
+    Ok(spans)
+}
+
+/// `medium` is the size the markup started with
+fn apply_tag(
+    tag: &BytesStart,
+    mut attributes: Attributes,
+    medium: f64,
+) -> Result<Attributes, MagickError> {
+    let name = tag.name();
+    let name = name.as_ref();
+    match name {
+        "b" => attributes.bold = true,
+        "i" => attributes.italic = true,
+        "big" => attributes.size *= SIZE_STEP,
+        "small" => attributes.size /= SIZE_STEP,
+        "span" => {
+            for attribute in tag.attributes() {
+                let invalid = |error: &dyn Display| {
+                    wm_err!("invalid markup attributes in `<{}>': {}", name, error)
+                };
+                let attribute = attribute.map_err(|error| invalid(&error))?;
+                let key = attribute.key.as_ref();
+                let value = attribute
+                    .normalized_value(XmlVersion::Implicit1_0)
+                    .map_err(|error| invalid(&error))?;
+                apply_attribute(key, &value, &mut attributes, medium)?;
+            }
+            return Ok(attributes);
+        }
+        _ => return Err(wm_err!("unsupported markup tag `<{}>'", name)),
+    }
+    if tag.attributes().next().is_some() {
+        return Err(wm_err!("tag `<{}>' does not take attributes", name));
+    }
+    Ok(attributes)
+}
+
+fn apply_attribute(
+    key: &str,
+    value: &str,
+    attributes: &mut Attributes,
+    medium: f64,
+) -> Result<(), MagickError> {
+    let invalid = || wm_err!("invalid value for markup attribute `{}': {}", key, value);
+    match key {
+        "font" | "font_desc" => {
+            // Only the style and the size are used, the family comes from -font
+            for word in value.split_whitespace() {
+                match word.to_ascii_lowercase().as_str() {
+                    "bold" => attributes.bold = true,
+                    "italic" | "oblique" => attributes.italic = true,
+                    _ => {
+                        if let Ok(size) = word.strip_suffix("px").unwrap_or(word).parse::<f64>() {
+                            if !(size.is_finite() && size > 0.0) {
+                                return Err(invalid());
+                            }
+                            attributes.size = size;
+                        }
+                    }
+                }
+            }
+        }
+        "size" | "font_size" => {
+            attributes.size = parse_size(value, attributes.size, medium).ok_or_else(invalid)?
+        }
+        "weight" | "font_weight" => {
+            attributes.bold = match value {
+                "ultralight" | "light" | "normal" => false,
+                "semibold" | "bold" | "ultrabold" | "heavy" => true,
+                _ => value.parse::<u32>().map_err(|_| invalid())? >= 600,
+            }
+        }
+        "style" | "font_style" => {
+            attributes.italic = match value {
+                "normal" => false,
+                "italic" | "oblique" => true,
+                _ => return Err(invalid()),
+            }
+        }
+        "foreground" | "fgcolor" | "color" => attributes.foreground = Some(Color::from_str(value)?),
+        _ => return Err(wm_err!("unsupported markup attribute `{}'", key)),
+    }
+    Ok(())
+}
+
+/// Sizes are in 1024ths of a point, in points with a `pt` suffix, or named relative to `medium`
+fn parse_size(value: &str, current: f64, medium: f64) -> Option<f64> {
+    const NAMED: [&str; 7] = [
+        "xx-small", "x-small", "small", "medium", "large", "x-large", "xx-large",
+    ];
+    let size = match value {
+        "larger" => current * SIZE_STEP,
+        "smaller" => current / SIZE_STEP,
+        _ => match NAMED.iter().position(|name| *name == value) {
+            Some(step) => medium * SIZE_STEP.powi(step as i32 - 3),
+            None => match value.strip_suffix("pt") {
+                Some(points) => points.parse().ok()?,
+                None => value.parse::<f64>().ok()? / 1024.0,
+            },
+        },
+    };
+    (size.is_finite() && size > 0.0).then_some(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spans(markup: &str) -> Vec<(String, Attributes)> {
+        parse(markup, Attributes::new(10.0))
+            .unwrap()
+            .into_iter()
+            .map(|span| (span.text, span.attributes))
+            .collect()
+    }
+
+    #[test]
+    fn nesting() {
+        let plain = Attributes::new(10.0);
+        let bold = Attributes {
+            bold: true,
+            ..plain
+        };
+        let bold_italic = Attributes {
+            italic: true,
+            ..bold
+        };
+        assert_eq!(
+            spans("a<b>b<i>c</i></b>d"),
+            vec![
+                ("a".to_owned(), plain),
+                ("b".to_owned(), bold),
+                ("c".to_owned(), bold_italic),
+                ("d".to_owned(), plain),
+            ]
+        );
+        // spans that end up the same are merged
+        assert_eq!(spans("a<span>b</span>").len(), 1);
+    }
+
+    #[test]
+    fn span_attributes() {
+        let [(text, attributes)] = &spans(
+            "<span foreground='red' size=\"20480\" weight='700' style='italic'>&lt;x&gt;&#65;</span>",
+        )[..] else {
+            panic!("expected a single span");
+        };
+        assert_eq!(text, "<x>A");
+        assert_eq!(attributes.size, 20.0);
+        assert!(attributes.bold && attributes.italic);
+        assert_eq!(attributes.foreground, Some(Color::opaque(1.0, 0.0, 0.0)));
+
+        let size = |markup| spans(markup)[0].1.size;
+        assert_eq!(size("<span size='14pt'>x</span>"), 14.0);
+        assert_eq!(size("<span font='Sans Bold 30'>x</span>"), 30.0);
+        assert!((size("<big><small>x</small></big>") - 10.0).abs() < 1e-9);
+        assert!((size("<span size='x-large'>x</span>") - 14.4).abs() < 1e-9);
+    }
+
+    #[test]
+    fn xml() {
+        assert_eq!(
+            spans("a<!-- comment -->b<![CDATA[<c>]]>&#x64;"),
+            vec![("ab<c>d".to_owned(), Attributes::new(10.0))]
+        );
+        // deep nesting does not overflow the stack
+        let depth = 100_000;
+        let nested = format!("{}x{}", "<b>".repeat(depth), "</b>".repeat(depth));
+        assert_eq!(spans(&nested).len(), 1);
+        // a document type definition could expand entities without bounds
+        let entities = "<!DOCTYPE markup [<!ENTITY a 'aaaa'>]>&a;";
+        assert!(parse(entities, Attributes::new(10.0)).is_err());
+    }
+
+    #[test]
+    fn errors() {
+        for markup in [
+            "<b>unclosed",
+            "<b>mismatched</i>",
+            "unopened</b>",
+            "<u>unsupported</u>",
+            "<span underline='single'>x</span>",
+            "<span size='huge'>x</span>",
+            "<span font='Sans -12'>x</span>",
+            "<span font='inf'>x</span>",
+            "<span foreground=red>x</span>",
+            "&nbsp;",
+            "<b",
+        ] {
+            assert!(parse(markup, Attributes::new(10.0)).is_err(), "{markup}");
+        }
+    }
+}
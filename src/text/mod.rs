@@ -0,0 +1,729 @@
+//! Rendering text into images, for `label:`, `caption:` and `pango:`.
+//!
+//! Fonts are read with `ttf-parser`, text is shaped and broken into lines by cosmic-text,
+//! and the glyph outlines are rasterized with `ab_glyph_rasterizer`.
+
+mod discovery;
+mod font;
+mod layout;
+mod markup;
+mod raster;
+mod stroke;
+#[cfg(test)]
+mod test_font;
+
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::path::{Path, PathBuf};
+
+use image::{ColorType, DynamicImage, Rgba32FImage};
+
+use crate::{
+    arg_parsers::{Color, Gravity},
+    encoders::common::convert,
+    error::MagickError,
+    image::Image,
+    limits::Limits,
+    operations::background::over,
+    plan::Modifiers,
+    utils::pool,
+    wm_err,
+};
+
+use discovery::{FontCatalog, FontSource};
+pub use font::Font;
+use font::{Fonts, PathCommand, Point};
+use layout::{fit_pointsize, Layout, Run, RunStyle, Spacing};
+use raster::Rasterizer;
+
+/// Point size used when `-pointsize` is not set and the text is not fitted into a size
+const DEFAULT_POINTSIZE: f64 = 12.0;
+
+/// Where the default font usually is, checked before resorting to a scan of all fonts
+const DEFAULT_FONT_PATHS: &[&str] = &[
+    "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf",
+    "/usr/share/fonts/TTF/DejaVuSans.ttf",
+    "/usr/share/fonts/dejavu/DejaVuSans.ttf",
+    "/usr/share/fonts/dejavu-sans-fonts/DejaVuSans.ttf",
+];
+
+/// How many installed fonts `pango:` loads for the characters missing from `-font`
+const MAX_FALLBACK_FONTS: usize = 8;
+
+/// Font families tried in order when `-font` is not set
+const DEFAULT_FAMILIES: &[&str] = &[
+    "DejaVu Sans",
+    "Liberation Sans",
+    "Arial",
+    "Helvetica",
+    "Noto Sans",
+];
+
+/// The settings that determine how text is drawn, captured when the text image is read
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextStyle {
+    /// `-font`: path to a TrueType font file, or the name of an installed font
+    pub font: Option<OsString>,
+    /// `-pointsize`. At imagemagick's default density of 72 dpi a point is one pixel.
+    pub pointsize: Option<f64>,
+    /// `-fill`: the color of the text
+    pub fill: Color,
+    /// `-stroke`: the color of the outline of the text, if it has one
+    pub stroke: Option<Color>,
+    /// `-strokewidth`: the width of the outline in pixels
+    pub stroke_width: f32,
+    /// `-undercolor`: the color of the box behind every line of text, if there is one
+    pub undercolor: Option<Color>,
+    pub background: Color,
+    /// Where the text goes when the image is larger than the text
+    pub gravity: Gravity,
+    /// `-size`: the size of the image, if set. Missing dimensions are determined by the text.
+    pub size: (Option<u32>, Option<u32>),
+    pub spacing: Spacing,
+}
+
+impl TextStyle {
+    pub fn from_modifiers(modifiers: &Modifiers) -> Self {
+        Self {
+            font: modifiers.font.clone(),
+            pointsize: modifiers.pointsize,
+            fill: modifiers.fill(),
+            stroke: modifiers.stroke,
+            stroke_width: modifiers.stroke_width() as f32,
+            undercolor: modifiers.undercolor,
+            background: modifiers.background(),
+            gravity: modifiers.gravity,
+            size: modifiers.size,
+            spacing: Spacing {
+                kerning: modifiers.kerning as f32,
+                interline: modifiers.interline_spacing as f32,
+                interword: modifiers.interword_spacing as f32,
+            },
+        }
+    }
+}
+
+/// Implements `-list font`: the fonts `-font` can find by name
+pub fn font_list() -> String {
+    FontCatalog::system().list()
+}
+
+/// Implements `label:`: an image just large enough for the text, unless `-size` is set.
+///
+/// Without `-pointsize`, text is scaled to fill the `-size`.
+pub fn label(text: &str, style: &TextStyle, limits: &Limits) -> Result<Image, MagickError> {
+    let fonts = Fonts::new(load_font(style.font.as_deref())?);
+    let (width, height) = style.size;
+    let pointsize = match style.pointsize {
+        Some(pointsize) => pointsize,
+        None if width.is_some() || height.is_some() => fit_pointsize(
+            |size| text_size(&lay_out(&fonts, text, size, style, None), style),
+            width,
+            height,
+        ),
+        None => DEFAULT_POINTSIZE,
+    };
+    let layout = lay_out(&fonts, text, pointsize, style, None);
+    render(&fonts, &layout, style, limits)
+}
+
+/// Implements `caption:`: text wrapped to the width set by `-size`.
+///
+/// Without `-pointsize`, text is scaled to fill the `-size` if both the width and the height are set.
+pub fn caption(text: &str, style: &TextStyle, limits: &Limits) -> Result<Image, MagickError> {
+    let fonts = Fonts::new(load_font(style.font.as_deref())?);
+    let (Some(width), height) = style.size else {
+        return Err(wm_err!("must specify image size `caption:{}'", text));
+    };
+    let wrap = Some(width as f32);
+    let pointsize = match (style.pointsize, height) {
+        (Some(pointsize), _) => pointsize,
+        (None, Some(height)) => fit_pointsize(
+            |size| text_size(&lay_out(&fonts, text, size, style, wrap), style),
+            Some(width),
+            Some(height),
+        ),
+        (None, None) => DEFAULT_POINTSIZE,
+    };
+    let layout = lay_out(&fonts, text, pointsize, style, wrap);
+    render(&fonts, &layout, style, limits)
+}
+
+/// Implements `pango:`: text with Pango markup, wrapped to the width set by `-size` if there is one.
+///
+/// Bold and italic text uses the faces of the `-font` family that are installed next to it.
+/// Italics are slanted when there is no italic face; bold text without a bold face is drawn regular.
+/// Like in Pango, characters missing from `-font` are drawn with installed fonts that have them.
+pub fn pango(markup: &str, style: &TextStyle, limits: &Limits) -> Result<Image, MagickError> {
+    let pointsize = style.pointsize.unwrap_or(DEFAULT_POINTSIZE);
+    let spans = markup::parse(markup, markup::Attributes::new(pointsize))?;
+    let mut faces = Faces::new(locate_font(style.font.as_deref())?)?;
+    let mut styles = Vec::with_capacity(spans.len());
+    for span in &spans {
+        let attributes = span.attributes;
+        let (font, oblique) = faces.select(attributes.bold, attributes.italic);
+        styles.push(RunStyle {
+            font,
+            pixel_size: attributes.size as f32,
+            oblique,
+            fill: attributes.foreground,
+        });
+    }
+    let runs: Vec<Run> = spans
+        .iter()
+        .zip(styles)
+        .map(|(span, style)| Run {
+            text: &span.text,
+            style,
+        })
+        .collect();
+    let mut fonts = faces.fonts;
+    add_fallbacks(&mut fonts, &runs);
+    let wrap = style.size.0.map(|width| width as f32);
+    let layout = Layout::styled(&fonts, &runs, style.spacing, wrap);
+    render(&fonts, &layout, style, limits)
+}
+
+/// Adds installed fonts for the characters that are missing from the fonts of their runs
+fn add_fallbacks(fonts: &mut Fonts, runs: &[Run]) {
+    let mut missing: Vec<char> = runs
+        .iter()
+        .flat_map(|run| {
+            let font = &fonts[run.style.font];
+            run.text
+                .chars()
+                .filter(move |&c| !c.is_whitespace() && !c.is_control() && font.glyph_id(c) == 0)
+        })
+        .collect();
+    if missing.is_empty() {
+        return;
+    }
+    missing.sort_unstable();
+    missing.dedup();
+    for face in FontCatalog::system().fallbacks(&missing, MAX_FALLBACK_FONTS) {
+        if let Ok(font) = Font::open(&face.source.path, face.source.index) {
+            fonts.add(font);
+        }
+    }
+}
+
+fn lay_out(
+    fonts: &Fonts,
+    text: &str,
+    pointsize: f64,
+    style: &TextStyle,
+    wrap: Option<f32>,
+) -> Layout {
+    Layout::new(fonts, text, pointsize as f32, style.spacing, wrap)
+}
+
+fn load_font(font: Option<&OsStr>) -> Result<Font, MagickError> {
+    let source = locate_font(font)?;
+    Font::open(&source.path, source.index)
+}
+
+/// Finds the font file for `-font`, which is either a path or the name of an installed font
+fn locate_font(font: Option<&OsStr>) -> Result<FontSource, MagickError> {
+    let Some(font) = font else {
+        return default_font();
+    };
+    if Path::new(font).is_file() {
+        return Ok(FontSource::file(font));
+    }
+    font.to_str()
+        .and_then(|name| FontCatalog::system().find(name))
+        .map(|face| face.source.clone())
+        .ok_or_else(|| wm_err!("unable to read font `{}'", font.to_string_lossy()))
+}
+
+fn default_font() -> Result<FontSource, MagickError> {
+    if let Some(path) = DEFAULT_FONT_PATHS.iter().find(|p| Path::new(p).is_file()) {
+        return Ok(FontSource::file(path));
+    }
+    DEFAULT_FAMILIES
+        .iter()
+        .find_map(|family| FontCatalog::system().find(family))
+        .map(|face| face.source.clone())
+        .ok_or_else(|| wm_err!("unable to find a default font, use -font to specify one"))
+}
+
+/// The bold and italic faces of a font, loaded when they are first needed
+struct Faces {
+    regular: FontSource,
+    fonts: Fonts,
+    /// Index into `fonts` for (bold, italic), and whether the glyphs need to be slanted
+    selected: HashMap<(bool, bool), (usize, bool)>,
+}
+
+impl Faces {
+    fn new(regular: FontSource) -> Result<Self, MagickError> {
+        let font = Font::open(&regular.path, regular.index)?;
+        Ok(Self {
+            regular,
+            fonts: Fonts::new(font),
+            selected: HashMap::from([((false, false), (0, false))]),
+        })
+    }
+
+    fn select(&mut self, bold: bool, italic: bool) -> (usize, bool) {
+        if let Some(&selected) = self.selected.get(&(bold, italic)) {
+            return selected;
+        }
+        let font = self
+            .candidates(bold, italic)
+            .iter()
+            .find_map(|source| Font::open(&source.path, source.index).ok());
+        let selected = match font {
+            Some(font) => (self.fonts.add(font), false),
+            None if italic => (self.select(bold, false).0, true),
+            None => self.select(false, italic),
+        };
+        self.selected.insert((bold, italic), selected);
+        selected
+    }
+
+    /// Installed fonts are looked up by family, others are looked for next to the regular face
+    fn candidates(&self, bold: bool, italic: bool) -> Vec<FontSource> {
+        let catalog = FontCatalog::system();
+        match catalog.by_source(&self.regular) {
+            Some(regular) => catalog
+                .find_style(&regular.family, bold, italic)
+                .map(|face| face.source.clone())
+                .into_iter()
+                .collect(),
+            None => face_paths(&self.regular.path, bold, italic)
+                .into_iter()
+                .map(FontSource::file)
+                .collect(),
+        }
+    }
+}
+
+/// Where the other faces of a font are by the usual naming conventions,
+/// such as `DejaVuSans-BoldOblique.ttf` next to `DejaVuSans.ttf`
+fn face_paths(regular: &Path, bold: bool, italic: bool) -> Vec<PathBuf> {
+    let (Some(stem), Some(extension)) = (
+        regular.file_stem().and_then(OsStr::to_str),
+        regular.extension().and_then(OsStr::to_str),
+    ) else {
+        return Vec::new();
+    };
+    let family = ["-Regular", "-Roman", "-Book"]
+        .iter()
+        .find_map(|suffix| stem.strip_suffix(suffix))
+        .unwrap_or(stem);
+    let suffixes: &[&str] = match (bold, italic) {
+        (false, false) => &[],
+        (true, false) => &["Bold"],
+        (false, true) => &["Italic", "Oblique"],
+        (true, true) => &["BoldItalic", "BoldOblique"],
+    };
+    suffixes
+        .iter()
+        .map(|suffix| regular.with_file_name(format!("{family}-{suffix}.{extension}")))
+        .collect()
+}
+
+/// The size of the text in whole pixels, including the outline around it
+fn text_size(layout: &Layout, style: &TextStyle) -> (u32, u32) {
+    let outline = stroke_width(style);
+    let round = |length: f32| (length + outline + 0.5).floor().max(1.0) as u32;
+    (round(layout.width()), round(layout.height()))
+}
+
+fn stroke_width(style: &TextStyle) -> f32 {
+    match style.stroke {
+        Some(_) => style.stroke_width,
+        None => 0.0,
+    }
+}
+
+/// Draws the text onto a background of the size set by `-size`,
+/// with missing dimensions determined by the size of the text.
+///
+/// The canvas is checked against the limits before it is allocated, since a huge `-pointsize`
+/// or `-size` would otherwise abort the process.
+fn render(
+    fonts: &Fonts,
+    layout: &Layout,
+    style: &TextStyle,
+    limits: &Limits,
+) -> Result<Image, MagickError> {
+    /// How far oblique glyphs lean to the right, relative to their height
+    const SLANT: f32 = 0.2;
+    let (text_width, text_height) = text_size(layout, style);
+    // The canvas is drawn in floating point RGBA
+    let (width, height) = limits.check_new_image(
+        f64::from(style.size.0.unwrap_or(text_width)),
+        f64::from(style.size.1.unwrap_or(text_height)),
+        16,
+    )?;
+    let background = style.background.to_array();
+    let mut canvas = pool::rgba32f_from_pixel(width, height, image::Rgba(background));
+
+    let (block_x, block_y) =
+        style
+            .gravity
+            .position((width, height), (text_width, text_height), (0, 0));
+    // The outline sticks out of the glyphs by half its width
+    let inset = stroke_width(style) / 2.0;
+    let mut glyphs = Vec::new();
+    let mut line_y = block_y as f32 + inset;
+    for (index, line) in layout.lines.iter().enumerate() {
+        let line_x = match style.gravity {
+            Gravity::North | Gravity::Center | Gravity::South => {
+                (layout.width() - line.width) / 2.0
+            }
+            Gravity::NorthEast | Gravity::East | Gravity::SouthEast => layout.width() - line.width,
+            _ => 0.0,
+        };
+        if let Some(color) = style.undercolor {
+            // Boxes of adjacent lines touch without overlapping, and the outer ones cover the outline
+            let top = if index == 0 { line_y - inset } else { line_y };
+            let last = index + 1 == layout.lines.len();
+            let bottom = line_y + line.height + if last { inset } else { 0.0 };
+            let left = block_x as f32 + line_x;
+            let right = left + line.width + 2.0 * inset;
+            fill_path(&mut canvas, &rectangle(left, top, right, bottom), color);
+        }
+        let baseline = line_y + line.ascent;
+        for glyph in &line.glyphs {
+            let run = &layout.styles[glyph.style];
+            let scale = run.scale(fonts, glyph.font);
+            let slant = if run.oblique { SLANT } else { 0.0 };
+            let origin = Point::new(
+                block_x as f32 + inset + line_x + glyph.x,
+                baseline + glyph.y,
+            );
+            let outline: Vec<PathCommand> = fonts[glyph.font]
+                .outline(glyph.id)
+                .into_iter()
+                .map(|command| {
+                    command.map(|p| {
+                        Point::new(
+                            origin.x + (p.x + p.y * slant) * scale,
+                            origin.y - p.y * scale,
+                        )
+                    })
+                })
+                .collect();
+            glyphs.push((outline, run.fill.unwrap_or(style.fill)));
+        }
+        line_y += line.height;
+    }
+    // Outlines go on top of all the glyphs, like in imagemagick
+    for (outline, fill) in &glyphs {
+        fill_path(&mut canvas, outline, *fill);
+    }
+    if let Some(color) = style.stroke {
+        for (outline, _) in &glyphs {
+            fill_path(
+                &mut canvas,
+                &stroke::stroke(outline, style.stroke_width),
+                color,
+            );
+        }
+    }
+
+    let mut colors = glyphs.iter().map(|(_, fill)| *fill).chain(style.stroke);
+    let color = match style.background.is_opaque() && colors.all(|c| c.is_opaque()) {
+        true => ColorType::Rgb8,
+        false => ColorType::Rgba8,
+    };
+    let canvas = DynamicImage::ImageRgba32F(canvas);
+    let pixels = convert(&canvas, color);
+    pool::recycle_image(canvas);
+    Ok(Image::new(pixels))
+}
+
+/// Fills the path with the color, blending it over the canvas
+fn fill_path(canvas: &mut Rgba32FImage, path: &[PathCommand], color: Color) {
+    let Some((min, max)) = bounds(path) else {
+        return;
+    };
+    // Rasterize just the area covered by the path, clipped to the canvas
+    let left = min.x.floor().max(0.0);
+    let top = min.y.floor().max(0.0);
+    let right = max.x.ceil().min(canvas.width() as f32);
+    let bottom = max.y.ceil().min(canvas.height() as f32);
+    if left >= right || top >= bottom {
+        return;
+    }
+    let (width, height) = ((right - left) as usize, (bottom - top) as usize);
+    let mut rasterizer = Rasterizer::new(width, height);
+    let shifted: Vec<PathCommand> = path
+        .iter()
+        .map(|command| command.map(|p| Point::new(p.x - left, p.y - top)))
+        .collect();
+    rasterizer.draw_path(&shifted);
+    let color = color.to_array();
+    for (index, coverage) in rasterizer.coverage().into_iter().enumerate() {
+        if coverage <= 0.0 {
+            continue;
+        }
+        let x = left as u32 + (index % width) as u32;
+        let y = top as u32 + (index / width) as u32;
+        let pixel = canvas.get_pixel_mut(x, y);
+        let mut top = color;
+        top[3] *= coverage;
+        pixel.0 = over(top, pixel.0);
+    }
+}
+
+fn rectangle(left: f32, top: f32, right: f32, bottom: f32) -> Vec<PathCommand> {
+    vec![
+        PathCommand::MoveTo(Point::new(left, top)),
+        PathCommand::LineTo(Point::new(right, top)),
+        PathCommand::LineTo(Point::new(right, bottom)),
+        PathCommand::LineTo(Point::new(left, bottom)),
+        PathCommand::Close,
+    ]
+}
+
+fn bounds(path: &[PathCommand]) -> Option<(Point, Point)> {
+    let mut points = path.iter().flat_map(|command| match *command {
+        PathCommand::MoveTo(p) | PathCommand::LineTo(p) => vec![p],
+        PathCommand::QuadTo(c, p) => vec![c, p],
+        PathCommand::CubicTo(c1, c2, p) => vec![c1, c2, p],
+        PathCommand::Close => vec![],
+    });
+    let first = points.next()?;
+    Some(points.fold((first, first), |(min, max), p| {
+        (
+            Point::new(min.x.min(p.x), min.y.min(p.y)),
+            Point::new(max.x.max(p.x), max.y.max(p.y)),
+        )
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::GenericImageView;
+    use std::str::FromStr;
+
+    fn style() -> (Fonts, TextStyle) {
+        let fonts = Fonts::new(Font::parse(test_font::test_font()).unwrap());
+        let style = TextStyle {
+            font: None,
+            pointsize: None,
+            fill: Color::opaque(0.0, 0.0, 0.0),
+            stroke: None,
+            stroke_width: 1.0,
+            undercolor: None,
+            background: Color::WHITE,
+            gravity: Gravity::None,
+            size: (None, None),
+            spacing: Spacing::default(),
+        };
+        (fonts, style)
+    }
+
+    #[test]
+    fn draws_glyphs() {
+        let (fonts, style) = style();
+        // 100 pixels per em
+        let layout = lay_out(&fonts, "I", 100.0, &style, None);
+        let image = render(&fonts, &layout, &style, &Limits::default()).unwrap();
+        assert_eq!((image.width(), image.height()), (50, 100));
+        // the stem of the I spans x 10..40 and reaches 70 pixels above the baseline at y=80
+        assert_eq!(image.pixels.get_pixel(25, 50).0, [0, 0, 0, 255]);
+        assert_eq!(image.pixels.get_pixel(5, 50).0, [255, 255, 255, 255]);
+        assert_eq!(image.pixels.get_pixel(25, 5).0, [255, 255, 255, 255]);
+        assert_eq!(image.pixels.get_pixel(25, 85).0, [255, 255, 255, 255]);
+    }
+
+    #[test]
+    fn huge_canvas_is_limited() {
+        let (fonts, mut style) = style();
+        let layout = lay_out(&fonts, "X", 1e7, &style, None);
+        assert!(render(&fonts, &layout, &style, &Limits::default()).is_err());
+        let layout = lay_out(&fonts, "X", 10.0, &style, None);
+        style.size = (Some(u32::MAX), Some(u32::MAX));
+        assert!(render(&fonts, &layout, &style, &Limits::default()).is_err());
+    }
+
+    #[test]
+    fn shaping() {
+        let (fonts, style) = style();
+        let width = |text| lay_out(&fonts, text, 100.0, &style, None).lines[0].width;
+        // the pair "IO" is kerned by the font
+        assert_eq!(width("IO"), 90.0);
+        assert_eq!(width("OI"), 100.0);
+        // a right-to-left paragraph starts at the right, and the left-to-right text embedded in it
+        // ends up on the left. The font has no Hebrew, so alef is drawn as the missing glyph.
+        let positions = |text| {
+            let layout = lay_out(&fonts, text, 100.0, &style, None);
+            let mut glyphs: Vec<_> = layout.lines[0]
+                .glyphs
+                .iter()
+                .map(|glyph| (glyph.x, glyph.id))
+                .collect();
+            glyphs.sort_by(|a, b| a.0.total_cmp(&b.0));
+            glyphs
+        };
+        assert_eq!(
+            positions("\u{5D0}I"),
+            [(0.0, test_font::I), (50.0, test_font::NOTDEF)]
+        );
+        assert_eq!(
+            positions("\u{5D0}O I"),
+            [
+                (0.0, test_font::O),
+                (50.0, test_font::SPACE),
+                (75.0, test_font::I),
+                (125.0, test_font::NOTDEF)
+            ]
+        );
+    }
+
+    #[test]
+    fn gravity_aligns_lines() {
+        let (fonts, mut style) = style();
+        style.size = (Some(100), None);
+        style.gravity = Gravity::East;
+        let layout = lay_out(&fonts, "I", 100.0, &style, None);
+        let image = render(&fonts, &layout, &style, &Limits::default()).unwrap();
+        assert_eq!((image.width(), image.height()), (100, 100));
+        assert_eq!(image.pixels.get_pixel(25, 50).0, [255, 255, 255, 255]);
+        assert_eq!(image.pixels.get_pixel(75, 50).0, [0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn fitting_fills_the_size() {
+        let (fonts, style) = style();
+        // "II" is 1000 units wide and 1000 units tall, so it is as large as the point size
+        let measure = |size: f64| text_size(&lay_out(&fonts, "II", size, &style, None), &style);
+        let fits = |pointsize: f64, limit: u32| {
+            let (width, height) = measure(pointsize);
+            width.max(height) < limit
+        };
+        for (width, height, limit) in [
+            (Some(200), None, 200),
+            (Some(200), Some(50), 50),
+            (None, Some(30), 30),
+        ] {
+            let pointsize = fit_pointsize(measure, width, height);
+            assert!(fits(pointsize, limit), "{pointsize}");
+            assert!(!fits(pointsize + 2.0, limit), "{pointsize}");
+        }
+    }
+
+    #[test]
+    fn wrapping() {
+        let (fonts, style) = style();
+        let layout = lay_out(&fonts, "I I I", 100.0, &style, Some(130.0));
+        // "I I" is 125 pixels wide, "I I I" would not fit
+        assert_eq!(layout.lines.len(), 2);
+        assert_eq!(layout.lines[0].glyphs.len(), 3);
+        // a word wider than the line is broken up
+        let layout = lay_out(&fonts, "IIII", 100.0, &style, Some(110.0));
+        assert_eq!(layout.lines.len(), 2);
+        let layout = lay_out(&fonts, "I\nI", 100.0, &style, None);
+        assert_eq!(layout.lines.len(), 2);
+        assert_eq!(text_size(&layout, &style), (50, 200));
+    }
+
+    #[test]
+    fn styled_runs() {
+        let (fonts, style) = style();
+        let red = Color::opaque(1.0, 0.0, 0.0);
+        let small = RunStyle::plain(50.0);
+        let big = RunStyle {
+            fill: Some(red),
+            ..RunStyle::plain(100.0)
+        };
+        let runs = [
+            Run {
+                text: "I",
+                style: small,
+            },
+            Run {
+                text: "I\n",
+                style: big,
+            },
+            Run {
+                text: "I",
+                style: small,
+            },
+        ];
+        let layout = Layout::styled(&fonts, &runs, Spacing::default(), None);
+        // the first line is as tall as its largest glyph, the second one is smaller
+        assert_eq!(layout.lines[0].height, 100.0);
+        assert_eq!(layout.lines[1].height, 50.0);
+        assert_eq!(text_size(&layout, &style), (75, 150));
+        let image = render(&fonts, &layout, &style, &Limits::default()).unwrap();
+        // the baseline of the first line is at y=80
+        assert_eq!(image.pixels.get_pixel(12, 75).0, [0, 0, 0, 255]);
+        assert_eq!(image.pixels.get_pixel(12, 40).0, [255, 255, 255, 255]);
+        assert_eq!(image.pixels.get_pixel(50, 40).0, [255, 0, 0, 255]);
+        assert_eq!(image.pixels.get_pixel(12, 130).0, [0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn face_naming() {
+        let faces = |path: &str, bold, italic| face_paths(Path::new(path), bold, italic);
+        assert_eq!(
+            faces("/fonts/DejaVuSans.ttf", true, true),
+            [
+                PathBuf::from("/fonts/DejaVuSans-BoldItalic.ttf"),
+                PathBuf::from("/fonts/DejaVuSans-BoldOblique.ttf")
+            ]
+        );
+        assert_eq!(
+            faces("LiberationSerif-Regular.ttf", true, false),
+            [PathBuf::from("LiberationSerif-Bold.ttf")]
+        );
+    }
+
+    #[test]
+    fn spacing() {
+        let (fonts, mut style) = style();
+        style.spacing = Spacing {
+            kerning: 3.0,
+            interline: -10.0,
+            interword: 20.0,
+        };
+        // "I I" is 125 pixels wide, plus 20 for the space and 3 after each character,
+        // including the last one like in imagemagick
+        let layout = lay_out(&fonts, "I I\nI", 100.0, &style, None);
+        assert_eq!(layout.lines[0].width, 154.0);
+        assert_eq!(text_size(&layout, &style), (154, 190));
+    }
+
+    #[test]
+    fn outlines() {
+        let (fonts, mut style) = style();
+        style.fill = Color::WHITE;
+        style.stroke = Some(Color::opaque(1.0, 0.0, 0.0));
+        style.stroke_width = 4.0;
+        let layout = lay_out(&fonts, "I", 100.0, &style, None);
+        let image = render(&fonts, &layout, &style, &Limits::default()).unwrap();
+        // the outline makes the image larger, and the glyph moves by half of its width
+        assert_eq!((image.width(), image.height()), (54, 104));
+        // the stem of the I spans x 12..42, the outline is 2 pixels to each side of its edges
+        assert_eq!(image.pixels.get_pixel(11, 50).0, [255, 0, 0, 255]);
+        assert_eq!(image.pixels.get_pixel(12, 50).0, [255, 0, 0, 255]);
+        assert_eq!(image.pixels.get_pixel(20, 50).0, [255, 255, 255, 255]);
+        assert_eq!(image.pixels.get_pixel(8, 50).0, [255, 255, 255, 255]);
+    }
+
+    #[test]
+    fn undercolor() {
+        let (fonts, mut style) = style();
+        style.undercolor = Some(Color::from_str("#0000FF80").unwrap());
+        style.size = (Some(120), None);
+        let layout = lay_out(&fonts, "I\nII", 100.0, &style, None);
+        let image = render(&fonts, &layout, &style, &Limits::default()).unwrap();
+        let blend = [127, 127, 255, 255];
+        // the box is as wide as each line and blends over the background
+        assert_eq!(image.pixels.get_pixel(5, 50).0, blend);
+        assert_eq!(image.pixels.get_pixel(60, 50).0, [255; 4]);
+        assert_eq!(image.pixels.get_pixel(45, 150).0, blend);
+        assert_eq!(image.pixels.get_pixel(110, 150).0, [255; 4]);
+        // lines do not overlap where they meet, and the glyphs go on top
+        assert_eq!(image.pixels.get_pixel(5, 99).0, blend);
+        assert_eq!(image.pixels.get_pixel(5, 100).0, blend);
+        assert_eq!(image.pixels.get_pixel(20, 50).0, [0, 0, 0, 255]);
+    }
+}
@@ -0,0 +1,273 @@
+//! Anti-aliased rasterization of outlines into coverage masks, using `ab_glyph_rasterizer`.
+//!
+//! The rasterizer expects every line to lie within its grid, so lines are clipped to it first.
+//! Curves are flattened into lines here, so that they can be clipped too.
+
+use super::font::{PathCommand, Point};
+
+pub struct Rasterizer {
+    width: usize,
+    height: usize,
+    /// Two columns wider than the mask, so that the parts of lines right of it have somewhere to go
+    inner: ab_glyph_rasterizer::Rasterizer,
+}
+
+impl Rasterizer {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            inner: ab_glyph_rasterizer::Rasterizer::new(width + 2, height),
+        }
+    }
+
+    /// Draws a closed path in pixel coordinates
+    pub fn draw_path(&mut self, path: &[PathCommand]) {
+        let mut start = Point::new(0.0, 0.0);
+        let mut current = start;
+        for command in path {
+            match *command {
+                PathCommand::MoveTo(p) => {
+                    // An unclosed contour is closed implicitly, otherwise rows would not sum up to zero
+                    self.draw_line(current, start);
+                    start = p;
+                    current = p;
+                }
+                PathCommand::LineTo(p) => {
+                    self.draw_line(current, p);
+                    current = p;
+                }
+                PathCommand::QuadTo(control, p) => {
+                    for point in flatten_quad(current, control, p) {
+                        self.draw_line(current, point);
+                        current = point;
+                    }
+                }
+                PathCommand::CubicTo(control1, control2, p) => {
+                    for point in flatten_cubic(current, control1, control2, p) {
+                        self.draw_line(current, point);
+                        current = point;
+                    }
+                }
+                PathCommand::Close => {
+                    self.draw_line(current, start);
+                    current = start;
+                }
+            }
+        }
+        self.draw_line(current, start);
+    }
+
+    /// Parts of the line left or right of the mask are moved onto its edges.
+    /// That leaves the coverage inside the mask the same, since it only depends on what is to the left.
+    fn draw_line(&mut self, p0: Point, p1: Point) {
+        let right = self.width as f32;
+        let mut splits = vec![0.0, 1.0];
+        for edge in [0.0, right] {
+            if (p0.x - edge) * (p1.x - edge) < 0.0 {
+                splits.push((edge - p0.x) / (p1.x - p0.x));
+            }
+        }
+        splits.sort_by(f32::total_cmp);
+        let at = |t: f32| {
+            let x = p0.x + (p1.x - p0.x) * t;
+            let y = p0.y + (p1.y - p0.y) * t;
+            ab_glyph_rasterizer::point(x.clamp(0.0, right), y)
+        };
+        for pair in splits.windows(2) {
+            self.inner.draw_line(at(pair[0]), at(pair[1]));
+        }
+    }
+
+    /// Coverage of every pixel in `[0, 1]`, row by row.
+    ///
+    /// Overlapping shapes add up, so outlines of any winding direction and unions of shapes are filled.
+    pub fn coverage(&self) -> Vec<f32> {
+        /// The rasterizer keeps a running sum over the whole mask rather than a row,
+        /// so rounding errors build up. They are far too small to show in a 16-bit image.
+        const ERROR: f32 = 1e-4;
+        let stride = self.width + 2;
+        let mut coverage = Vec::with_capacity(self.width * self.height);
+        self.inner.for_each_pixel(|index, value| {
+            if index % stride < self.width {
+                coverage.push(match value {
+                    _ if value < ERROR => 0.0,
+                    _ if value > 1.0 - ERROR => 1.0,
+                    _ => value,
+                });
+            }
+        });
+        coverage
+    }
+}
+
+/// Splits a quadratic curve into enough lines that the error is well below a pixel.
+/// Returns the ends of the lines, without the start of the curve.
+pub fn flatten_quad(p0: Point, control: Point, p1: Point) -> Vec<Point> {
+    let deviation_x = p0.x - 2.0 * control.x + p1.x;
+    let deviation_y = p0.y - 2.0 * control.y + p1.y;
+    let deviation = deviation_x * deviation_x + deviation_y * deviation_y;
+    if deviation < 0.333 {
+        return vec![p1];
+    }
+    let segments = 1 + (3.0 * deviation).sqrt().sqrt().floor() as usize;
+    (1..=segments)
+        .map(|i| {
+            let t = i as f32 / segments as f32;
+            let u = 1.0 - t;
+            Point::new(
+                u * u * p0.x + 2.0 * u * t * control.x + t * t * p1.x,
+                u * u * p0.y + 2.0 * u * t * control.y + t * t * p1.y,
+            )
+        })
+        .collect()
+}
+
+/// Like [`flatten_quad`], for cubic curves
+pub fn flatten_cubic(p0: Point, control1: Point, control2: Point, p1: Point) -> Vec<Point> {
+    // The curve is no longer than the lines between its control points
+    let distance = |a: Point, b: Point| ((a.x - b.x).powi(2) + (a.y - b.y).powi(2)).sqrt();
+    let length = distance(p0, control1) + distance(control1, control2) + distance(control2, p1);
+    let segments = (length.sqrt().ceil() as usize).clamp(1, 256);
+    (1..=segments)
+        .map(|i| {
+            let t = i as f32 / segments as f32;
+            let u = 1.0 - t;
+            let (a, b, c, d) = (u * u * u, 3.0 * u * u * t, 3.0 * u * t * t, t * t * t);
+            Point::new(
+                a * p0.x + b * control1.x + c * control2.x + d * p1.x,
+                a * p0.y + b * control1.y + c * control2.y + d * p1.y,
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn square(x: f32, y: f32, size: f32) -> Vec<PathCommand> {
+        vec![
+            PathCommand::MoveTo(Point::new(x, y)),
+            PathCommand::LineTo(Point::new(x + size, y)),
+            PathCommand::LineTo(Point::new(x + size, y + size)),
+            PathCommand::LineTo(Point::new(x, y + size)),
+            PathCommand::Close,
+        ]
+    }
+
+    #[test]
+    fn pixel_aligned_square() {
+        let mut rasterizer = Rasterizer::new(4, 4);
+        rasterizer.draw_path(&square(1.0, 1.0, 2.0));
+        let coverage = rasterizer.coverage();
+        for y in 0..4 {
+            for x in 0..4 {
+                let inside = (1..3).contains(&x) && (1..3).contains(&y);
+                assert_eq!(coverage[y * 4 + x], if inside { 1.0 } else { 0.0 });
+            }
+        }
+    }
+
+    #[test]
+    fn partial_coverage() {
+        let mut rasterizer = Rasterizer::new(2, 1);
+        rasterizer.draw_path(&square(0.5, 0.0, 1.0));
+        let coverage = rasterizer.coverage();
+        assert!((coverage[0] - 0.5).abs() < 1e-6);
+        assert!((coverage[1] - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn overlaps_and_holes() {
+        let mut rasterizer = Rasterizer::new(4, 4);
+        rasterizer.draw_path(&square(0.0, 0.0, 3.0));
+        rasterizer.draw_path(&square(1.0, 1.0, 3.0));
+        let coverage = rasterizer.coverage();
+        // the union is filled, and the overlap is not counted twice
+        assert_eq!(coverage[0], 1.0);
+        assert_eq!(coverage[5], 1.0);
+        assert_eq!(coverage[15], 1.0);
+        assert_eq!(coverage[3], 0.0);
+
+        // a contour going the other way cuts a hole, like the inside of an `o`
+        let mut rasterizer = Rasterizer::new(3, 3);
+        rasterizer.draw_path(&square(0.0, 0.0, 3.0));
+        rasterizer.draw_path(&[
+            PathCommand::MoveTo(Point::new(1.0, 1.0)),
+            PathCommand::LineTo(Point::new(1.0, 2.0)),
+            PathCommand::LineTo(Point::new(2.0, 2.0)),
+            PathCommand::LineTo(Point::new(2.0, 1.0)),
+            PathCommand::Close,
+        ]);
+        let coverage = rasterizer.coverage();
+        assert_eq!(coverage[4], 0.0);
+        assert_eq!(coverage[3], 1.0);
+    }
+
+    #[test]
+    fn curves() {
+        let mut rasterizer = Rasterizer::new(10, 10);
+        // a lens shape bounded by two curves
+        rasterizer.draw_path(&[
+            PathCommand::MoveTo(Point::new(0.0, 5.0)),
+            PathCommand::QuadTo(Point::new(5.0, -5.0), Point::new(10.0, 5.0)),
+            PathCommand::QuadTo(Point::new(5.0, 15.0), Point::new(0.0, 5.0)),
+            PathCommand::Close,
+        ]);
+        let coverage = rasterizer.coverage();
+        assert_eq!(coverage[5 * 10 + 5], 1.0);
+        assert_eq!(coverage[0], 0.0);
+        let total: f32 = coverage.iter().sum();
+        // the exact area of the lens is 2/3 of its 10x20 bounding box, clipped to the canvas
+        assert!((50.0..70.0).contains(&total), "{total}");
+    }
+
+    #[test]
+    fn clipping() {
+        let mut rasterizer = Rasterizer::new(3, 3);
+        rasterizer.draw_path(&square(-5.0, -5.0, 20.0));
+        assert!(rasterizer.coverage().iter().all(|&c| c == 1.0));
+
+        // a triangle with a slanted edge crossing the left side of the mask at y=2
+        let mut rasterizer = Rasterizer::new(4, 4);
+        rasterizer.draw_path(&[
+            PathCommand::MoveTo(Point::new(-4.0, 0.0)),
+            PathCommand::LineTo(Point::new(4.0, 0.0)),
+            PathCommand::LineTo(Point::new(4.0, 4.0)),
+            PathCommand::Close,
+        ]);
+        let coverage = rasterizer.coverage();
+        assert_eq!(coverage[0], 1.0);
+        assert_eq!(coverage[3], 1.0);
+        assert!((coverage[2 * 4] - 0.25).abs() < 1e-6);
+        assert_eq!(coverage[3 * 4], 0.0);
+    }
+
+    #[test]
+    fn cubic_curves() {
+        let mut rasterizer = Rasterizer::new(10, 10);
+        // a circle made of two cubic curves, as in PostScript fonts
+        rasterizer.draw_path(&[
+            PathCommand::MoveTo(Point::new(0.0, 5.0)),
+            PathCommand::CubicTo(
+                Point::new(0.0, -1.67),
+                Point::new(10.0, -1.67),
+                Point::new(10.0, 5.0),
+            ),
+            PathCommand::CubicTo(
+                Point::new(10.0, 11.67),
+                Point::new(0.0, 11.67),
+                Point::new(0.0, 5.0),
+            ),
+            PathCommand::Close,
+        ]);
+        let coverage = rasterizer.coverage();
+        assert_eq!(coverage[5 * 10 + 5], 1.0);
+        assert_eq!(coverage[0], 0.0);
+        let total: f32 = coverage.iter().sum();
+        // a circle with a diameter of 10 pixels covers about 78.5 of them,
+        // and two curves make a slightly flattened one
+        assert!((72.0..82.0).contains(&total), "{total}");
+    }
+}
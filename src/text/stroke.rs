@@ -0,0 +1,153 @@
+//! Outlining paths with a line of a given width, for `-stroke`.
+//!
+//! The stroke is built from a rectangle along every line segment and a circle at every joint,
+//! which gives round joins. All of the pieces wind the same way, so the rasterizer fills their union.
+
+use std::f32::consts::PI;
+
+use super::font::{PathCommand, Point};
+use super::raster::{flatten_cubic, flatten_quad};
+
+/// Returns the outline of a line of the given width in pixels, centered on the path
+pub fn stroke(path: &[PathCommand], width: f32) -> Vec<PathCommand> {
+    let radius = width / 2.0;
+    let mut stroke = Vec::new();
+    if radius <= 0.0 {
+        return stroke;
+    }
+    for contour in contours(path) {
+        for pair in contour.windows(2) {
+            let (a, b) = (pair[0], pair[1]);
+            let (dx, dy) = (b.x - a.x, b.y - a.y);
+            let length = (dx * dx + dy * dy).sqrt();
+            if length > 0.0 {
+                let (nx, ny) = (-dy / length * radius, dx / length * radius);
+                add_polygon(
+                    &mut stroke,
+                    &[
+                        Point::new(a.x + nx, a.y + ny),
+                        Point::new(b.x + nx, b.y + ny),
+                        Point::new(b.x - nx, b.y - ny),
+                        Point::new(a.x - nx, a.y - ny),
+                    ],
+                );
+            }
+        }
+        for &point in &contour {
+            add_polygon(&mut stroke, &circle(point, radius));
+        }
+    }
+    stroke
+}
+
+/// Flattens the path into closed polygons, each ending with its starting point
+fn contours(path: &[PathCommand]) -> Vec<Vec<Point>> {
+    let mut contours: Vec<Vec<Point>> = Vec::new();
+    let close = |contours: &mut Vec<Vec<Point>>| {
+        if let Some(contour) = contours.last_mut() {
+            if contour.first() != contour.last() {
+                contour.push(contour[0]);
+            }
+        }
+    };
+    for command in path {
+        match *command {
+            PathCommand::MoveTo(p) => {
+                close(&mut contours);
+                contours.push(vec![p]);
+            }
+            PathCommand::LineTo(p) => contours.last_mut().into_iter().for_each(|c| c.push(p)),
+            PathCommand::QuadTo(control, p) => {
+                if let Some(contour) = contours.last_mut() {
+                    let start = *contour.last().unwrap();
+                    contour.extend(flatten_quad(start, control, p));
+                }
+            }
+            PathCommand::CubicTo(control1, control2, p) => {
+                if let Some(contour) = contours.last_mut() {
+                    let start = *contour.last().unwrap();
+                    contour.extend(flatten_cubic(start, control1, control2, p));
+                }
+            }
+            PathCommand::Close => close(&mut contours),
+        }
+    }
+    close(&mut contours);
+    contours
+}
+
+fn circle(center: Point, radius: f32) -> Vec<Point> {
+    // Segments no longer than about a pixel, so that the circle looks round
+    let segments = (2.0 * PI * radius).ceil().clamp(8.0, 128.0) as usize;
+    (0..segments)
+        .map(|i| {
+            let angle = 2.0 * PI * i as f32 / segments as f32;
+            Point::new(
+                center.x + radius * angle.cos(),
+                center.y + radius * angle.sin(),
+            )
+        })
+        .collect()
+}
+
+/// Adds the polygon turned so that it winds the same way as the others
+fn add_polygon(path: &mut Vec<PathCommand>, points: &[Point]) {
+    let area: f32 = points
+        .iter()
+        .zip(points.iter().cycle().skip(1))
+        .map(|(a, b)| a.x * b.y - b.x * a.y)
+        .sum();
+    let mut points = points.to_vec();
+    if area < 0.0 {
+        points.reverse();
+    }
+    path.push(PathCommand::MoveTo(points[0]));
+    path.extend(points[1..].iter().map(|&p| PathCommand::LineTo(p)));
+    path.push(PathCommand::Close);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::text::raster::Rasterizer;
+
+    #[test]
+    fn outlines_a_square() {
+        let square = [
+            PathCommand::MoveTo(Point::new(5.0, 5.0)),
+            PathCommand::LineTo(Point::new(15.0, 5.0)),
+            PathCommand::LineTo(Point::new(15.0, 15.0)),
+            PathCommand::LineTo(Point::new(5.0, 15.0)),
+            PathCommand::Close,
+        ];
+        let mut rasterizer = Rasterizer::new(20, 20);
+        rasterizer.draw_path(&stroke(&square, 2.0));
+        let coverage = rasterizer.coverage();
+        let at = |x: usize, y: usize| coverage[y * 20 + x];
+        // the line is a pixel to each side of the edges
+        assert_eq!(at(4, 10), 1.0);
+        assert_eq!(at(5, 10), 1.0);
+        assert_eq!(at(14, 14), 1.0);
+        assert_eq!(at(15, 10), 1.0);
+        // the inside and the outside are left alone
+        assert_eq!(at(10, 10), 0.0);
+        assert_eq!(at(2, 10), 0.0);
+        assert_eq!(at(17, 10), 0.0);
+        // corners are rounded
+        assert!(at(3, 3) < 0.5);
+        assert!(at(4, 4) > 0.0);
+    }
+
+    #[test]
+    fn curves_and_unclosed_contours() {
+        let path = [
+            PathCommand::MoveTo(Point::new(2.0, 10.0)),
+            PathCommand::QuadTo(Point::new(10.0, -6.0), Point::new(18.0, 10.0)),
+        ];
+        let contours = contours(&path);
+        assert_eq!(contours.len(), 1);
+        assert!(contours[0].len() > 3);
+        assert_eq!(contours[0].first(), contours[0].last());
+        assert!(stroke(&path, 0.0).is_empty());
+    }
+}
@@ -0,0 +1,228 @@
+//! A tiny TrueType font built in memory, so that tests do not depend on the fonts installed on the system.
+//!
+//! 1000 units per em, ascender 800, descender -200. The glyphs are:
+//! - `I`: a 300x700 rectangle starting at x=100, advance 500
+//! - `O`: a curvy diamond made only of off-curve points, 500x700, advance 500
+//! - `H`: a composite of two `I`s 300 units apart, advance 800
+//! - space: empty, advance 250
+//!
+//! The pair `IO` is kerned by -100. The family name is `Test Sans`.
+
+/// Glyph ids in the font
+pub const NOTDEF: u16 = 0;
+pub const I: u16 = 1;
+pub const O: u16 = 2;
+pub const SPACE: u16 = 3;
+pub const H: u16 = 4;
+
+pub fn test_font() -> Vec<u8> {
+    styled_test_font("Regular", false, false)
+}
+
+/// The test font with the given style name, e.g. `Bold Italic`, and style flags
+pub fn styled_test_font(style: &str, bold: bool, italic: bool) -> Vec<u8> {
+    let mut glyf = Vec::new();
+    let mut loca = vec![0u32];
+    // .notdef and space are empty
+    let glyphs = [
+        Vec::new(),
+        simple_glyph(&[
+            (100, 0, true),
+            (400, 0, true),
+            (400, 700, true),
+            (100, 700, true),
+        ]),
+        simple_glyph(&[
+            (250, 0, false),
+            (500, 350, false),
+            (250, 700, false),
+            (0, 350, false),
+        ]),
+        Vec::new(),
+        composite_glyph(&[(I, 0), (I, 300)]),
+    ];
+    for glyph in glyphs {
+        glyf.extend(glyph);
+        loca.push(glyf.len() as u32);
+    }
+    let advances = [500u16, 500, 500, 250, 800];
+
+    let mut head = vec![0u8; 54];
+    head[18..20].copy_from_slice(&1000u16.to_be_bytes());
+    // long loca offsets
+    head[50..52].copy_from_slice(&1u16.to_be_bytes());
+    let mac_style = u16::from(bold) | u16::from(italic) << 1;
+    head[44..46].copy_from_slice(&mac_style.to_be_bytes());
+
+    // version 0, with the style in fsSelection
+    let mut os2 = vec![0u8; 78];
+    let selection = match (bold, italic) {
+        (false, false) => 0x40,
+        _ => u16::from(italic) | u16::from(bold) << 5,
+    };
+    os2[62..64].copy_from_slice(&selection.to_be_bytes());
+
+    // version 0.5, which only has the number of glyphs
+    let mut maxp = vec![0u8; 6];
+    maxp[0..4].copy_from_slice(&0x5000u32.to_be_bytes());
+    maxp[4..6].copy_from_slice(&(advances.len() as u16).to_be_bytes());
+
+    let mut hhea = vec![0u8; 36];
+    hhea[4..6].copy_from_slice(&800i16.to_be_bytes());
+    hhea[6..8].copy_from_slice(&(-200i16).to_be_bytes());
+    hhea[34..36].copy_from_slice(&(advances.len() as u16).to_be_bytes());
+
+    let hmtx: Vec<u8> = advances
+        .iter()
+        .flat_map(|advance| [advance.to_be_bytes(), [0, 0]].concat())
+        .collect();
+    let loca: Vec<u8> = loca
+        .iter()
+        .flat_map(|offset| offset.to_be_bytes())
+        .collect();
+
+    // Sorted by tag, since readers look the tables up with a binary search
+    let tables: Vec<(&[u8; 4], Vec<u8>)> = vec![
+        (b"OS/2", os2),
+        (b"cmap", cmap(&[(' ', SPACE), ('H', H), ('I', I), ('O', O)])),
+        (b"glyf", glyf),
+        (b"head", head),
+        (b"hhea", hhea),
+        (b"hmtx", hmtx),
+        (b"kern", kern(&[(I, O, -100)])),
+        (b"loca", loca),
+        (b"maxp", maxp),
+        (
+            b"name",
+            name(&[
+                (1, "Test Sans"),
+                (2, style),
+                (4, &format!("Test Sans {style}")),
+                (6, &format!("TestSans-{}", style.replace(' ', ""))),
+            ]),
+        ),
+    ];
+    let mut font = vec![0, 1, 0, 0];
+    font.extend((tables.len() as u16).to_be_bytes());
+    font.extend([0; 6]);
+    let mut offset = 12 + tables.len() * 16;
+    let mut data: Vec<u8> = Vec::new();
+    for (tag, table) in &tables {
+        font.extend(*tag);
+        font.extend([0; 4]);
+        font.extend((offset as u32).to_be_bytes());
+        font.extend((table.len() as u32).to_be_bytes());
+        data.extend(table);
+        offset += table.len();
+    }
+    font.extend(data);
+    font
+}
+
+fn simple_glyph(points: &[(i16, i16, bool)]) -> Vec<u8> {
+    let mut glyph = Vec::new();
+    glyph.extend(1i16.to_be_bytes());
+    // the bounding box is not used
+    glyph.extend([0; 8]);
+    glyph.extend((points.len() as u16 - 1).to_be_bytes());
+    glyph.extend(0u16.to_be_bytes());
+    glyph.extend(points.iter().map(|&(_, _, on_curve)| u8::from(on_curve)));
+    let (mut x, mut y) = (0, 0);
+    for &(px, _, _) in points {
+        glyph.extend((px - x).to_be_bytes());
+        x = px;
+    }
+    for &(_, py, _) in points {
+        glyph.extend((py - y).to_be_bytes());
+        y = py;
+    }
+    glyph
+}
+
+fn composite_glyph(components: &[(u16, i16)]) -> Vec<u8> {
+    const ARGS_ARE_WORDS: u16 = 0x0001;
+    const ARGS_ARE_XY_VALUES: u16 = 0x0002;
+    const MORE_COMPONENTS: u16 = 0x0020;
+    let mut glyph = Vec::new();
+    glyph.extend((-1i16).to_be_bytes());
+    glyph.extend([0; 8]);
+    for (i, &(component, dx)) in components.iter().enumerate() {
+        let mut flags = ARGS_ARE_WORDS | ARGS_ARE_XY_VALUES;
+        if i + 1 < components.len() {
+            flags |= MORE_COMPONENTS;
+        }
+        glyph.extend(flags.to_be_bytes());
+        glyph.extend(component.to_be_bytes());
+        glyph.extend(dx.to_be_bytes());
+        glyph.extend(0i16.to_be_bytes());
+    }
+    glyph
+}
+
+/// A format 4 subtable with a segment for every character, sorted by character
+fn cmap(mapping: &[(char, u16)]) -> Vec<u8> {
+    let mut segments: Vec<(u16, u16)> = mapping.iter().map(|&(c, g)| (c as u16, g)).collect();
+    segments.push((0xFFFF, 0));
+    let count = segments.len() as u16;
+    let mut subtable = Vec::new();
+    subtable.extend(4u16.to_be_bytes());
+    subtable.extend((16 + count * 8).to_be_bytes());
+    subtable.extend(0u16.to_be_bytes());
+    subtable.extend((count * 2).to_be_bytes());
+    // search hints, which we do not use
+    subtable.extend([0; 6]);
+    subtable.extend(segments.iter().flat_map(|(c, _)| c.to_be_bytes()));
+    subtable.extend(0u16.to_be_bytes());
+    subtable.extend(segments.iter().flat_map(|(c, _)| c.to_be_bytes()));
+    subtable.extend(segments.iter().flat_map(|&(c, g)| match c {
+        0xFFFF => 1u16.to_be_bytes(),
+        _ => g.wrapping_sub(c).to_be_bytes(),
+    }));
+    subtable.extend(segments.iter().flat_map(|_| 0u16.to_be_bytes()));
+
+    let mut table = Vec::new();
+    table.extend(0u16.to_be_bytes());
+    table.extend(1u16.to_be_bytes());
+    // Windows Unicode BMP
+    table.extend(3u16.to_be_bytes());
+    table.extend(1u16.to_be_bytes());
+    table.extend(12u32.to_be_bytes());
+    table.extend(subtable);
+    table
+}
+
+fn kern(pairs: &[(u16, u16, i16)]) -> Vec<u8> {
+    let mut table = Vec::new();
+    table.extend(0u16.to_be_bytes());
+    table.extend(1u16.to_be_bytes());
+    table.extend(0u16.to_be_bytes());
+    table.extend((14 + pairs.len() as u16 * 6).to_be_bytes());
+    // format 0, horizontal
+    table.extend(1u16.to_be_bytes());
+    table.extend((pairs.len() as u16).to_be_bytes());
+    table.extend([0; 6]);
+    for &(left, right, value) in pairs {
+        table.extend(left.to_be_bytes());
+        table.extend(right.to_be_bytes());
+        table.extend(value.to_be_bytes());
+    }
+    table
+}
+
+/// Windows Unicode names in US English
+fn name(names: &[(u16, &str)]) -> Vec<u8> {
+    let mut table = Vec::new();
+    let mut strings: Vec<u8> = Vec::new();
+    table.extend(0u16.to_be_bytes());
+    table.extend((names.len() as u16).to_be_bytes());
+    table.extend((6 + names.len() as u16 * 12).to_be_bytes());
+    for &(id, name) in names {
+        let encoded: Vec<u8> = name.encode_utf16().flat_map(u16::to_be_bytes).collect();
+        for value in [3, 1, 0x409, id, encoded.len() as u16, strings.len() as u16] {
+            table.extend(value.to_be_bytes());
+        }
+        strings.extend(encoded);
+    }
+    table.extend(strings);
+    table
+}
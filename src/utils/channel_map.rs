@@ -0,0 +1,280 @@
+//! Helpers for applying a function to every color sample of an image, regardless of its pixel format.
+
+use image::DynamicImage;
+
+/// Applies `f` to every color sample of the image, leaving the alpha channel untouched.
+///
+/// `f` operates on values normalized to the `[0, 1]` range; out-of-range results are clamped
+/// for integer formats. For 8-bit and 16-bit images `f` is evaluated only once per possible value.
+pub fn map_color_channels(image: &mut DynamicImage, f: impl Fn(f64) -> f64) {
+    let has_alpha = image.color().has_alpha();
+    let channels = usize::from(image.color().channel_count());
+    // alpha is always the last channel
+    let color_channels = if has_alpha { channels - 1 } else { channels };
+
+    match image {
+        DynamicImage::ImageLuma8(buf) => map_u8(buf, channels, color_channels, f),
+        DynamicImage::ImageLumaA8(buf) => map_u8(buf, channels, color_channels, f),
+        DynamicImage::ImageRgb8(buf) => map_u8(buf, channels, color_channels, f),
+        DynamicImage::ImageRgba8(buf) => map_u8(buf, channels, color_channels, f),
+        DynamicImage::ImageLuma16(buf) => map_u16(buf, channels, color_channels, f),
+        DynamicImage::ImageLumaA16(buf) => map_u16(buf, channels, color_channels, f),
+        DynamicImage::ImageRgb16(buf) => map_u16(buf, channels, color_channels, f),
+        DynamicImage::ImageRgba16(buf) => map_u16(buf, channels, color_channels, f),
+        DynamicImage::ImageRgb32F(buf) => map_f32(buf, channels, color_channels, f),
+        DynamicImage::ImageRgba32F(buf) => map_f32(buf, channels, color_channels, f),
+        _ => unreachable!(),
+    }
+}
+
+fn map_u8(samples: &mut [u8], channels: usize, color_channels: usize, f: impl Fn(f64) -> f64) {
+    let max = f64::from(u8::MAX);
+    let lut: Vec<u8> = (0..=u8::MAX)
+        .map(|v| (f(f64::from(v) / max) * max).round().clamp(0.0, max) as u8)
+        .collect();
+    for pixel in samples.chunks_exact_mut(channels) {
+        for sample in &mut pixel[..color_channels] {
+            *sample = lut[usize::from(*sample)];
+        }
+    }
+}
+
+fn map_u16(samples: &mut [u16], channels: usize, color_channels: usize, f: impl Fn(f64) -> f64) {
+    let max = f64::from(u16::MAX);
+    let lut: Vec<u16> = (0..=u16::MAX)
+        .map(|v| (f(f64::from(v) / max) * max).round().clamp(0.0, max) as u16)
+        .collect();
+    for pixel in samples.chunks_exact_mut(channels) {
+        for sample in &mut pixel[..color_channels] {
+            *sample = lut[usize::from(*sample)];
+        }
+    }
+}
+
+fn map_f32(samples: &mut [f32], channels: usize, color_channels: usize, f: impl Fn(f64) -> f64) {
+    for pixel in samples.chunks_exact_mut(channels) {
+        for sample in &mut pixel[..color_channels] {
+            *sample = f(f64::from(*sample)) as f32;
+        }
+    }
+}
+
+/// Applies `f` to the color of every pixel, leaving the alpha channel untouched.
+///
+/// Unlike [`map_color_channels`], `f` sees all three channels at once, normalized to the `[0, 1]` range.
+/// Gray pixels are passed as three equal values, and the mean of the result is stored.
+pub fn map_colors(image: &mut DynamicImage, f: impl Fn([f64; 3]) -> [f64; 3]) {
+    let channels = usize::from(image.color().channel_count());
+    let color_channels = if image.color().has_alpha() {
+        channels - 1
+    } else {
+        channels
+    };
+    match image {
+        DynamicImage::ImageLuma8(buf) => map_pixels(buf, channels, color_channels, f),
+        DynamicImage::ImageLumaA8(buf) => map_pixels(buf, channels, color_channels, f),
+        DynamicImage::ImageRgb8(buf) => map_pixels(buf, channels, color_channels, f),
+        DynamicImage::ImageRgba8(buf) => map_pixels(buf, channels, color_channels, f),
+        DynamicImage::ImageLuma16(buf) => map_pixels(buf, channels, color_channels, f),
+        DynamicImage::ImageLumaA16(buf) => map_pixels(buf, channels, color_channels, f),
+        DynamicImage::ImageRgb16(buf) => map_pixels(buf, channels, color_channels, f),
+        DynamicImage::ImageRgba16(buf) => map_pixels(buf, channels, color_channels, f),
+        DynamicImage::ImageRgb32F(buf) => map_pixels(buf, channels, color_channels, f),
+        DynamicImage::ImageRgba32F(buf) => map_pixels(buf, channels, color_channels, f),
+        _ => unreachable!(),
+    }
+}
+
+fn map_pixels<T: Sample>(
+    samples: &mut [T],
+    channels: usize,
+    color_channels: usize,
+    f: impl Fn([f64; 3]) -> [f64; 3],
+) {
+    for pixel in samples.chunks_exact_mut(channels) {
+        let color = &mut pixel[..color_channels];
+        if let [gray] = color {
+            let value = gray.to_unit();
+            let [r, g, b] = f([value; 3]);
+            *gray = T::from_unit((r + g + b) / 3.0);
+        } else {
+            let [r, g, b] = f([color[0].to_unit(), color[1].to_unit(), color[2].to_unit()]);
+            color[0] = T::from_unit(r);
+            color[1] = T::from_unit(g);
+            color[2] = T::from_unit(b);
+        }
+    }
+}
+
+/// Applies `f` to every pixel including its alpha, which is 1 for images without an alpha channel.
+///
+/// Gray pixels are passed as three equal color values, and the mean of the resulting color is stored.
+/// The resulting alpha is discarded for images without an alpha channel.
+pub fn map_rgba(image: &mut DynamicImage, f: impl Fn([f64; 4]) -> [f64; 4]) {
+    let channels = usize::from(image.color().channel_count());
+    let has_alpha = image.color().has_alpha();
+    match image {
+        DynamicImage::ImageLuma8(buf) => map_rgba_pixels(buf, channels, has_alpha, f),
+        DynamicImage::ImageLumaA8(buf) => map_rgba_pixels(buf, channels, has_alpha, f),
+        DynamicImage::ImageRgb8(buf) => map_rgba_pixels(buf, channels, has_alpha, f),
+        DynamicImage::ImageRgba8(buf) => map_rgba_pixels(buf, channels, has_alpha, f),
+        DynamicImage::ImageLuma16(buf) => map_rgba_pixels(buf, channels, has_alpha, f),
+        DynamicImage::ImageLumaA16(buf) => map_rgba_pixels(buf, channels, has_alpha, f),
+        DynamicImage::ImageRgb16(buf) => map_rgba_pixels(buf, channels, has_alpha, f),
+        DynamicImage::ImageRgba16(buf) => map_rgba_pixels(buf, channels, has_alpha, f),
+        DynamicImage::ImageRgb32F(buf) => map_rgba_pixels(buf, channels, has_alpha, f),
+        DynamicImage::ImageRgba32F(buf) => map_rgba_pixels(buf, channels, has_alpha, f),
+        _ => unreachable!(),
+    }
+}
+
+fn map_rgba_pixels<T: Sample>(
+    samples: &mut [T],
+    channels: usize,
+    has_alpha: bool,
+    f: impl Fn([f64; 4]) -> [f64; 4],
+) {
+    let color_channels = if has_alpha { channels - 1 } else { channels };
+    for pixel in samples.chunks_exact_mut(channels) {
+        let alpha = match has_alpha {
+            true => pixel[channels - 1].to_unit(),
+            false => 1.0,
+        };
+        let [r, g, b, a] = match color_channels {
+            1 => {
+                let value = pixel[0].to_unit();
+                f([value, value, value, alpha])
+            }
+            _ => f([
+                pixel[0].to_unit(),
+                pixel[1].to_unit(),
+                pixel[2].to_unit(),
+                alpha,
+            ]),
+        };
+        if color_channels == 1 {
+            pixel[0] = T::from_unit((r + g + b) / 3.0);
+        } else {
+            pixel[0] = T::from_unit(r);
+            pixel[1] = T::from_unit(g);
+            pixel[2] = T::from_unit(b);
+        }
+        if has_alpha {
+            pixel[channels - 1] = T::from_unit(a);
+        }
+    }
+}
+
+/// Conversion of samples to and from the `[0, 1]` range
+pub(crate) trait Sample: Copy {
+    fn to_unit(self) -> f64;
+    /// Clamps out-of-range values for integer formats
+    fn from_unit(value: f64) -> Self;
+}
+
+impl Sample for u8 {
+    fn to_unit(self) -> f64 {
+        f64::from(self) / f64::from(u8::MAX)
+    }
+
+    fn from_unit(value: f64) -> Self {
+        (value * f64::from(u8::MAX))
+            .round()
+            .clamp(0.0, f64::from(u8::MAX)) as u8
+    }
+}
+
+impl Sample for u16 {
+    fn to_unit(self) -> f64 {
+        f64::from(self) / f64::from(u16::MAX)
+    }
+
+    fn from_unit(value: f64) -> Self {
+        (value * f64::from(u16::MAX))
+            .round()
+            .clamp(0.0, f64::from(u16::MAX)) as u16
+    }
+}
+
+impl Sample for f32 {
+    fn to_unit(self) -> f64 {
+        f64::from(self)
+    }
+
+    fn from_unit(value: f64) -> Self {
+        value as f32
+    }
+}
+
+impl Sample for f64 {
+    fn to_unit(self) -> f64 {
+        self
+    }
+
+    fn from_unit(value: f64) -> Self {
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgba, RgbaImage};
+
+    use super::*;
+
+    #[test]
+    fn alpha_is_untouched() {
+        let mut image =
+            DynamicImage::ImageRgba8(RgbaImage::from_pixel(1, 1, Rgba([0, 51, 255, 51])));
+        map_color_channels(&mut image, |v| 1.0 - v);
+        assert_eq!(
+            image.as_rgba8().unwrap().get_pixel(0, 0),
+            &Rgba([255, 204, 0, 51])
+        );
+    }
+
+    #[test]
+    fn colors_of_gray_and_rgb() {
+        let mut image =
+            DynamicImage::ImageRgba8(RgbaImage::from_pixel(1, 1, Rgba([0, 51, 255, 51])));
+        map_colors(&mut image, |[r, g, b]| [b, g, r]);
+        assert_eq!(
+            image.as_rgba8().unwrap().get_pixel(0, 0),
+            &Rgba([255, 51, 0, 51])
+        );
+
+        let mut image = DynamicImage::ImageLumaA16(image::ImageBuffer::from_pixel(
+            1,
+            1,
+            image::LumaA([65535u16, 100]),
+        ));
+        map_colors(&mut image, |[r, g, b]| [r, g * 0.5, b * 0.0]);
+        assert_eq!(
+            image.as_luma_alpha16().unwrap().get_pixel(0, 0),
+            &image::LumaA([32768, 100])
+        );
+    }
+
+    #[test]
+    fn rgba_of_every_format() {
+        let mut image = DynamicImage::ImageLumaA8(image::ImageBuffer::from_pixel(
+            1,
+            1,
+            image::LumaA([255u8, 51]),
+        ));
+        map_rgba(&mut image, |[r, g, b, a]| [r, g * 0.5, b * 0.0, a * 5.0]);
+        assert_eq!(
+            image.as_luma_alpha8().unwrap().get_pixel(0, 0),
+            &image::LumaA([128, 255])
+        );
+
+        // images without alpha see it as opaque
+        let mut image =
+            DynamicImage::ImageRgb8(image::RgbImage::from_pixel(1, 1, image::Rgb([0, 51, 255])));
+        map_rgba(&mut image, |[r, g, _, a]| [a, g, r, 0.0]);
+        assert_eq!(
+            image.as_rgb8().unwrap().get_pixel(0, 0),
+            &image::Rgb([255, 51, 0])
+        );
+    }
+}
@@ -0,0 +1,48 @@
+//! Comparing colors with the tolerance set by `-fuzz`, the same way imagemagick does.
+
+use crate::{arg_parsers::Fuzz, image::QUANTUM_RANGE};
+
+/// Checks if the two RGBA colors, normalized to `[0, 1]`, are the same within the `-fuzz` distance.
+///
+/// The distance is Euclidean, with the color channels weighted by the opacity of both colors,
+/// so that all fully transparent colors are the same.
+pub fn is_similar(a: [f64; 4], b: [f64; 4], fuzz: &Fuzz) -> bool {
+    // imagemagick never treats colors closer than this as different, even with no fuzz
+    let fuzz = fuzz
+        .distance
+        .max(std::f64::consts::FRAC_1_SQRT_2 / QUANTUM_RANGE);
+    let fuzz = fuzz * fuzz;
+    let alpha = a[3] - b[3];
+    let mut distance = alpha * alpha;
+    if distance > fuzz {
+        return false;
+    }
+    let scale = a[3] * b[3];
+    for c in 0..3 {
+        let difference = a[c] - b[c];
+        distance += scale * difference * difference;
+    }
+    distance <= fuzz
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    #[test]
+    fn fuzz_distance() {
+        let white = [1.0; 4];
+        let off_white = [0.98, 0.98, 0.98, 1.0];
+        assert!(is_similar(white, white, &Fuzz::default()));
+        assert!(!is_similar(white, off_white, &Fuzz::default()));
+        assert!(is_similar(white, off_white, &Fuzz::from_str("5%").unwrap()));
+        // transparent colors are all alike
+        assert!(is_similar(
+            [0.0, 0.0, 0.0, 0.0],
+            [1.0, 0.5, 0.0, 0.0],
+            &Fuzz::default()
+        ));
+    }
+}
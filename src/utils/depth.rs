@@ -0,0 +1,93 @@
+//! Finding the smallest bit depth that represents the pixel data without loss,
+//! as reported by `%[bit-depth]`.
+//!
+//! This is a port of `GetImageDepth` from imagemagick. We pretend to be a Q16 build,
+//! so every sample is first converted to a 16-bit quantum, same as imagemagick does when loading an image.
+
+use image::DynamicImage;
+
+/// Depth of the quantum that imagemagick stores samples in, and the upper bound of the result
+const QUANTUM_DEPTH: u8 = 16;
+const QUANTUM_RANGE: f64 = 65535.0;
+
+/// Returns the minimal number of bits per sample that all samples, including alpha,
+/// can be scaled down to and back up without changing their value
+pub fn minimal_depth(image: &DynamicImage) -> u8 {
+    // Only the distinct values matter, and there are at most 65536 of them
+    let mut seen = vec![false; 1 << QUANTUM_DEPTH];
+    match image {
+        DynamicImage::ImageLuma8(buf) => mark(buf.as_raw(), &mut seen, quantum_u8),
+        DynamicImage::ImageLumaA8(buf) => mark(buf.as_raw(), &mut seen, quantum_u8),
+        DynamicImage::ImageRgb8(buf) => mark(buf.as_raw(), &mut seen, quantum_u8),
+        DynamicImage::ImageRgba8(buf) => mark(buf.as_raw(), &mut seen, quantum_u8),
+        DynamicImage::ImageLuma16(buf) => mark(buf.as_raw(), &mut seen, |v| v),
+        DynamicImage::ImageLumaA16(buf) => mark(buf.as_raw(), &mut seen, |v| v),
+        DynamicImage::ImageRgb16(buf) => mark(buf.as_raw(), &mut seen, |v| v),
+        DynamicImage::ImageRgba16(buf) => mark(buf.as_raw(), &mut seen, |v| v),
+        DynamicImage::ImageRgb32F(buf) => mark(buf.as_raw(), &mut seen, quantum_f32),
+        DynamicImage::ImageRgba32F(buf) => mark(buf.as_raw(), &mut seen, quantum_f32),
+        _ => unreachable!(),
+    }
+    seen.iter()
+        .enumerate()
+        .filter(|(_, seen)| **seen)
+        .map(|(value, _)| sample_depth(value as u16))
+        .max()
+        .unwrap_or(1)
+}
+
+fn mark<T: Copy>(samples: &[T], seen: &mut [bool], quantum: impl Fn(T) -> u16) {
+    for sample in samples {
+        seen[usize::from(quantum(*sample))] = true;
+    }
+}
+
+fn quantum_u8(v: u8) -> u16 {
+    u16::from(v) * 257
+}
+
+fn quantum_f32(v: f32) -> u16 {
+    (f64::from(v).clamp(0.0, 1.0) * QUANTUM_RANGE).round() as u16
+}
+
+/// Minimal depth of a single 16-bit quantum
+fn sample_depth(quantum: u16) -> u8 {
+    let quantum = f64::from(quantum);
+    (1..QUANTUM_DEPTH)
+        .find(|&depth| {
+            let range = f64::from((1u32 << depth) - 1);
+            // `ScaleQuantumToAny` and `ScaleAnyToQuantum` in imagemagick
+            let scaled = (range * quantum / QUANTUM_RANGE + 0.5).floor();
+            let restored = (QUANTUM_RANGE * scaled / range + 0.5).floor();
+            restored == quantum
+        })
+        .unwrap_or(QUANTUM_DEPTH)
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{GrayImage, ImageBuffer, Luma, Rgb};
+
+    use super::*;
+
+    #[test]
+    fn bilevel() {
+        let image = GrayImage::from_fn(4, 4, |x, _| Luma([if x % 2 == 0 { 0 } else { 255 }]));
+        assert_eq!(minimal_depth(&DynamicImage::ImageLuma8(image)), 1);
+    }
+
+    #[test]
+    fn eight_bit() {
+        let image = GrayImage::from_fn(4, 4, |x, y| Luma([(x * 4 + y) as u8 * 3 + 1]));
+        assert_eq!(minimal_depth(&DynamicImage::ImageLuma8(image)), 8);
+    }
+
+    #[test]
+    fn sixteen_bit_with_eight_bit_data() {
+        let image: ImageBuffer<Rgb<u16>, _> =
+            ImageBuffer::from_fn(4, 4, |x, y| Rgb([x as u16 * 257, y as u16 * 257 * 10, 0]));
+        assert_eq!(minimal_depth(&DynamicImage::ImageRgb16(image)), 8);
+        let image: ImageBuffer<Rgb<u16>, _> = ImageBuffer::from_pixel(1, 1, Rgb([1, 0, 0]));
+        assert_eq!(minimal_depth(&DynamicImage::ImageRgb16(image)), 16);
+    }
+}
@@ -0,0 +1,169 @@
+//! Error diffusion shared by every operation that reduces colors.
+//!
+//! Floyd–Steinberg scans row by row. Riemersma dithering follows a Hilbert curve instead,
+//! which spreads the error in every direction and produces no directional artifacts.
+//! See <https://www.compuphase.com/riemer.htm>
+
+/// How many of the most recent errors are carried along the curve
+const HISTORY: usize = 16;
+/// How much more the most recent error weighs than the oldest one
+const MAX_RATIO: f32 = 16.0;
+
+/// Visits every pixel row by row, left to right, passing the correction of each color channel
+/// for the errors made at the neighbours already visited.
+///
+/// `quantize` returns the error it made, which is the corrected value of the pixel minus the chosen one,
+/// or `None` if the pixel was skipped and should not affect its neighbours.
+pub fn floyd_steinberg(
+    width: u32,
+    height: u32,
+    mut quantize: impl FnMut(u32, u32, [f32; 3]) -> Option<[f32; 3]>,
+) {
+    let columns = width as usize;
+    // Errors for the current and the next row, with a column of padding on either side
+    let mut current = vec![[0f32; 3]; columns + 2];
+    let mut next = vec![[0f32; 3]; columns + 2];
+    for y in 0..height {
+        for x in 0..columns {
+            let Some(error) = quantize(x as u32, y, current[x + 1]) else {
+                continue;
+            };
+            for c in 0..3 {
+                current[x + 2][c] += error[c] * 7.0 / 16.0;
+                next[x][c] += error[c] * 3.0 / 16.0;
+                next[x + 1][c] += error[c] * 5.0 / 16.0;
+                next[x + 2][c] += error[c] / 16.0;
+            }
+        }
+        std::mem::swap(&mut current, &mut next);
+        next.fill([0.0; 3]);
+    }
+}
+
+/// Visits every pixel along a Hilbert curve, passing the correction of each color channel
+/// for the errors made so far.
+///
+/// `quantize` returns the error it made, which is the original value of the pixel minus the chosen one,
+/// or `None` if the pixel was skipped and should not affect its neighbours.
+/// Unlike in Floyd–Steinberg, the correction is not part of the error, since the history
+/// already accounts for it.
+pub fn riemersma(
+    width: u32,
+    height: u32,
+    mut quantize: impl FnMut(u32, u32, [f32; 3]) -> Option<[f32; 3]>,
+) {
+    let weights: [f32; HISTORY] =
+        std::array::from_fn(|i| MAX_RATIO.powf(i as f32 / (HISTORY - 1) as f32) / MAX_RATIO);
+    // oldest first
+    let mut errors = [[0f32; 3]; HISTORY];
+    hilbert_curve(width, height, &mut |x, y| {
+        let correction = std::array::from_fn(|c| {
+            errors
+                .iter()
+                .zip(weights)
+                .map(|(error, weight)| error[c] * weight)
+                .sum()
+        });
+        if let Some(error) = quantize(x, y, correction) {
+            errors.rotate_left(1);
+            errors[HISTORY - 1] = error;
+        }
+    });
+}
+
+/// Visits every point of a `width` by `height` rectangle exactly once, each next to the previous one,
+/// with the generalized Hilbert curve by Jakub Červený: <https://github.com/jakubcerveny/gilbert>
+pub fn hilbert_curve(width: u32, height: u32, visit: &mut impl FnMut(u32, u32)) {
+    let (width, height) = (i64::from(width), i64::from(height));
+    if width >= height {
+        gilbert(0, 0, width, 0, 0, height, visit);
+    } else {
+        gilbert(0, 0, 0, height, width, 0, visit);
+    }
+}
+
+/// Covers the rectangle starting at `(x, y)` with the major axis `a` and the minor axis `b`
+fn gilbert(
+    mut x: i64,
+    mut y: i64,
+    ax: i64,
+    ay: i64,
+    bx: i64,
+    by: i64,
+    visit: &mut impl FnMut(u32, u32),
+) {
+    let width = (ax + ay).abs();
+    let height = (bx + by).abs();
+    let (dax, day) = (ax.signum(), ay.signum());
+    let (dbx, dby) = (bx.signum(), by.signum());
+    if height == 1 || width == 1 {
+        let (length, dx, dy) = match height == 1 {
+            true => (width, dax, day),
+            false => (height, dbx, dby),
+        };
+        for _ in 0..length {
+            visit(x as u32, y as u32);
+            x += dx;
+            y += dy;
+        }
+        return;
+    }
+    let (mut ax2, mut ay2) = (ax.div_euclid(2), ay.div_euclid(2));
+    let (mut bx2, mut by2) = (bx.div_euclid(2), by.div_euclid(2));
+    let width2 = (ax2 + ay2).abs();
+    let height2 = (bx2 + by2).abs();
+    if 2 * width > 3 * height {
+        if width2 % 2 == 1 && width > 2 {
+            ax2 += dax;
+            ay2 += day;
+        }
+        // split the long side in two
+        gilbert(x, y, ax2, ay2, bx, by, visit);
+        gilbert(x + ax2, y + ay2, ax - ax2, ay - ay2, bx, by, visit);
+    } else {
+        if height2 % 2 == 1 && height > 2 {
+            bx2 += dbx;
+            by2 += dby;
+        }
+        // go up, across and back down
+        gilbert(x, y, bx2, by2, ax2, ay2, visit);
+        gilbert(x + bx2, y + by2, ax, ay, bx - bx2, by - by2, visit);
+        gilbert(
+            x + (ax - dax) + (bx2 - dbx),
+            y + (ay - day) + (by2 - dby),
+            -bx2,
+            -by2,
+            -(ax - ax2),
+            -(ay - ay2),
+            visit,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn curve_covers_every_point_once() {
+        for (width, height) in [(1, 1), (1, 7), (8, 8), (13, 5), (4, 31), (100, 3)] {
+            let mut points = Vec::new();
+            hilbert_curve(width, height, &mut |x, y| points.push((x, y)));
+            assert_eq!(points.len() as u32, width * height);
+            points.sort();
+            points.dedup();
+            assert_eq!(points.len() as u32, width * height, "{width}x{height}");
+            assert!(points.iter().all(|&(x, y)| x < width && y < height));
+        }
+    }
+
+    #[test]
+    fn curve_is_continuous() {
+        let mut points = Vec::new();
+        hilbert_curve(16, 12, &mut |x, y| points.push((x as i64, y as i64)));
+        for pair in points.windows(2) {
+            let distance = (pair[0].0 - pair[1].0).abs() + (pair[0].1 - pair[1].1).abs();
+            assert!(distance <= 2, "{pair:?}");
+        }
+    }
+}
@@ -0,0 +1,88 @@
+//! Minimal editing of raw EXIF chunks, which are TIFF headers followed by IFDs
+
+use image::metadata::Orientation;
+
+const ORIENTATION_TAG: u16 = 0x0112;
+const SHORT: u16 = 3;
+
+/// Overwrites the orientation tag in IFD0, if present, so that the metadata
+/// stays in sync with pixels that have been rotated by e.g. `-auto-orient`.
+///
+/// Returns `false` if the chunk is malformed or has no orientation tag.
+pub fn set_orientation(chunk: &mut [u8], orientation: Orientation) -> bool {
+    let big_endian = match chunk.get(..4) {
+        Some([0x49, 0x49, 42, 0]) => false,
+        Some([0x4d, 0x4d, 0, 42]) => true,
+        _ => return false,
+    };
+    let read_u16 = |chunk: &[u8], at: usize| -> Option<u16> {
+        let bytes = chunk.get(at..at + 2)?.try_into().ok()?;
+        Some(if big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    };
+    let read_u32 = |chunk: &[u8], at: usize| -> Option<u32> {
+        let bytes = chunk.get(at..at + 4)?.try_into().ok()?;
+        Some(if big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    };
+    let Some(ifd) = read_u32(chunk, 4).map(|offset| offset as usize) else {
+        return false;
+    };
+    let Some(entries) = read_u16(chunk, ifd) else {
+        return false;
+    };
+    for index in 0..usize::from(entries) {
+        let entry = ifd + 2 + index * 12;
+        let (Some(tag), Some(format), Some(count)) = (
+            read_u16(chunk, entry),
+            read_u16(chunk, entry + 2),
+            read_u32(chunk, entry + 4),
+        ) else {
+            return false;
+        };
+        if tag == ORIENTATION_TAG && format == SHORT && count == 1 {
+            let value = u16::from(orientation.to_exif());
+            let bytes = if big_endian {
+                value.to_be_bytes()
+            } else {
+                value.to_le_bytes()
+            };
+            chunk[entry + 8..entry + 10].copy_from_slice(&bytes);
+            return true;
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A little-endian TIFF header with a single IFD entry: orientation 6
+    fn chunk() -> Vec<u8> {
+        let mut chunk = vec![0x49, 0x49, 42, 0, 8, 0, 0, 0, 1, 0];
+        chunk.extend_from_slice(&[0x12, 0x01, 3, 0, 1, 0, 0, 0, 6, 0, 0, 0]);
+        chunk.extend_from_slice(&[0, 0, 0, 0]);
+        chunk
+    }
+
+    #[test]
+    fn orientation_is_replaced() {
+        let mut chunk = chunk();
+        assert!(set_orientation(&mut chunk, Orientation::NoTransforms));
+        assert_eq!(chunk[18], 1);
+    }
+
+    #[test]
+    fn malformed_chunks_are_left_alone() {
+        let mut truncated = chunk()[..14].to_vec();
+        assert!(!set_orientation(&mut truncated, Orientation::NoTransforms));
+        assert!(!set_orientation(&mut [0; 8], Orientation::NoTransforms));
+    }
+}
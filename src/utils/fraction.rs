@@ -54,7 +54,7 @@ impl Fraction {
         )
     }
 
-    pub fn to_float(&self) -> f64 {
+    pub fn to_float(self) -> f64 {
         // We could reduce the fraction first for greater precision, but it's not clear if this will matter in practice
         self.numerator as f64 / self.denominator as f64
     }
@@ -1,4 +1,15 @@
+pub mod channel_map;
+pub mod color_distance;
+pub mod depth;
+pub mod dither;
+pub mod exif;
 pub mod fraction;
+pub mod number_format;
+pub mod pool;
+pub mod random;
+pub mod spool;
+pub mod statistics;
+pub mod timer;
 
 #[cfg(test)]
 pub mod arbitrary;
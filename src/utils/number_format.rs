@@ -0,0 +1,102 @@
+//! Formatting of floating-point numbers the way imagemagick prints them.
+
+/// imagemagick prints floats with this many significant digits unless `-precision` is specified
+pub const DEFAULT_PRECISION: usize = 6;
+
+/// Emulates the `%g` conversion of C's `printf`, which imagemagick uses for printing floats:
+/// `precision` significant digits, trailing zeroes removed,
+/// and scientific notation for very large or very small numbers.
+pub fn format_g(value: f64, precision: usize) -> String {
+    if value == 0.0 {
+        return "0".to_owned();
+    }
+    if !value.is_finite() {
+        return value.to_string();
+    }
+    let precision = precision.max(1);
+    // Round to the requested number of significant digits first, because rounding can change the exponent
+    let scientific = format!("{:.*e}", precision - 1, value);
+    let (mantissa, exponent) = scientific.split_once('e').unwrap();
+    let exponent: i32 = exponent.parse().unwrap();
+    if exponent < -4 || exponent >= precision as i32 {
+        let mantissa = strip_trailing_zeroes(mantissa);
+        let sign = if exponent < 0 { '-' } else { '+' };
+        format!("{mantissa}e{sign}{:02}", exponent.abs())
+    } else {
+        let decimals = (precision as i32 - 1 - exponent) as usize;
+        strip_trailing_zeroes(&format!("{:.*}", decimals, value)).to_owned()
+    }
+}
+
+/// Formats a file size the way imagemagick does: in bytes if that fits into `precision` digits,
+/// otherwise in binary units such as `1.5MiB` or decimal units such as `1.6MB`
+pub fn format_size(bytes: u64, binary: bool, precision: usize) -> String {
+    let mut size = bytes as f64;
+    let plain = format_g(size, precision);
+    if !plain.contains("e+") {
+        return format!("{plain}B");
+    }
+    let (base, units) = match binary {
+        true => (1024.0, ["Ki", "Mi", "Gi", "Ti", "Pi", "Ei"]),
+        false => (1000.0, ["K", "M", "G", "T", "P", "E"]),
+    };
+    let mut units = units.iter();
+    let mut unit = "";
+    while size >= base {
+        let Some(next) = units.next() else { break };
+        size /= base;
+        unit = next;
+    }
+    format!("{}{unit}B", format_g(size, precision))
+}
+
+fn strip_trailing_zeroes(number: &str) -> &str {
+    if number.contains('.') {
+        number.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        number
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fixed_notation() {
+        assert_eq!(format_g(0.5, 6), "0.5");
+        assert_eq!(format_g(145.58003, 6), "145.58");
+        assert_eq!(format_g(0.145098039, 6), "0.145098");
+        assert_eq!(format_g(255.0, 6), "255");
+        assert_eq!(format_g(-1.102623, 6), "-1.10262");
+    }
+
+    #[test]
+    fn scientific_notation() {
+        assert_eq!(format_g(1234567.0, 6), "1.23457e+06");
+        assert_eq!(format_g(0.00001234, 6), "1.234e-05");
+    }
+
+    #[test]
+    fn rounding_changes_exponent() {
+        assert_eq!(format_g(999999.5, 6), "1e+06");
+        assert_eq!(format_g(0.99999999, 6), "1");
+    }
+
+    #[test]
+    fn file_sizes() {
+        assert_eq!(format_size(0, true, 6), "0B");
+        assert_eq!(format_size(415502, true, 6), "415502B");
+        assert_eq!(format_size(6516224, true, 6), "6.21436MiB");
+        assert_eq!(format_size(6516224, true, 3), "6.21MiB");
+        assert_eq!(format_size(6516224, false, 6), "6.51622MB");
+        assert_eq!(format_size(1024, true, 3), "1KiB");
+        assert_eq!(format_size(1200, false, 2), "1.2KB");
+    }
+
+    #[test]
+    fn custom_precision() {
+        assert_eq!(format_g(1.23456789, 3), "1.23");
+        assert_eq!(format_g(1.23456789, 10), "1.23456789");
+    }
+}
@@ -0,0 +1,172 @@
+//! Recycling of the floating-point buffers that operations such as `-rotate`, `-shear` and `-extent`
+//! work in, so that a chain of them reuses memory instead of allocating and freeing
+//! a whole image for every step.
+//!
+//! Operations take their working buffers from here and hand them back once the result
+//! has been converted to the pixel format of the image, along with the pixels they replaced.
+//! The pool is emptied after every file, and the memory it holds counts against `-limit memory`.
+
+use std::cell::RefCell;
+
+use image::{DynamicImage, ImageBuffer, Pixel, Primitive, Rgba, Rgba32FImage};
+
+/// Enough for the source, the result and the previous pixels of a single operation
+const MAX_POOLED: usize = 3;
+
+thread_local! {
+    static POOL: RefCell<Vec<Vec<f32>>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Returns an empty buffer that can hold at least `length` samples without reallocating
+fn take(length: usize) -> Vec<f32> {
+    POOL.with_borrow_mut(|pool| {
+        // The smallest buffer that fits, so that the larger ones are left for larger images
+        let best = pool
+            .iter()
+            .enumerate()
+            .filter(|(_, buffer)| buffer.capacity() >= length)
+            .min_by_key(|(_, buffer)| buffer.capacity())
+            .map(|(index, _)| index);
+        match best {
+            Some(index) => {
+                let mut buffer = pool.swap_remove(index);
+                buffer.clear();
+                buffer
+            }
+            None => Vec::with_capacity(length),
+        }
+    })
+}
+
+/// Keeps the buffer for reuse, replacing a smaller one if the pool is full
+pub fn recycle(buffer: Vec<f32>) {
+    POOL.with_borrow_mut(|pool| {
+        if pool.len() < MAX_POOLED {
+            pool.push(buffer);
+            return;
+        }
+        let smallest = pool
+            .iter_mut()
+            .min_by_key(|pooled| pooled.capacity())
+            .unwrap();
+        if smallest.capacity() < buffer.capacity() {
+            *smallest = buffer;
+        }
+    });
+}
+
+/// Frees every pooled buffer, once the images they were sized for are done with
+pub fn clear() {
+    POOL.with_borrow_mut(Vec::clear);
+}
+
+/// The memory held by the pool
+pub fn pooled_bytes() -> u64 {
+    POOL.with_borrow(|pool| {
+        pool.iter()
+            .map(|buffer| (buffer.capacity() * std::mem::size_of::<f32>()) as u64)
+            .sum()
+    })
+}
+
+/// Keeps the memory of the image for reuse if it is in a pixel format the pool holds
+pub fn recycle_image(image: DynamicImage) {
+    match image {
+        DynamicImage::ImageRgba32F(buf) => recycle(buf.into_raw()),
+        DynamicImage::ImageRgb32F(buf) => recycle(buf.into_raw()),
+        _ => (),
+    }
+}
+
+/// Like [`Rgba32FImage::from_fn`], but in a recycled buffer
+pub fn rgba32f_from_fn(
+    width: u32,
+    height: u32,
+    mut f: impl FnMut(u32, u32) -> Rgba<f32>,
+) -> Rgba32FImage {
+    let mut samples = take(width as usize * height as usize * 4);
+    for y in 0..height {
+        for x in 0..width {
+            samples.extend_from_slice(&f(x, y).0);
+        }
+    }
+    ImageBuffer::from_raw(width, height, samples).unwrap()
+}
+
+/// Like [`Rgba32FImage::from_pixel`], but in a recycled buffer
+pub fn rgba32f_from_pixel(width: u32, height: u32, pixel: Rgba<f32>) -> Rgba32FImage {
+    rgba32f_from_fn(width, height, |_, _| pixel)
+}
+
+/// Like [`DynamicImage::to_rgba32f`], but in a recycled buffer
+pub fn to_rgba32f(image: &DynamicImage) -> Rgba32FImage {
+    match image {
+        DynamicImage::ImageLuma8(buf) => convert_to_rgba32f(buf),
+        DynamicImage::ImageLumaA8(buf) => convert_to_rgba32f(buf),
+        DynamicImage::ImageRgb8(buf) => convert_to_rgba32f(buf),
+        DynamicImage::ImageRgba8(buf) => convert_to_rgba32f(buf),
+        DynamicImage::ImageLuma16(buf) => convert_to_rgba32f(buf),
+        DynamicImage::ImageLumaA16(buf) => convert_to_rgba32f(buf),
+        DynamicImage::ImageRgb16(buf) => convert_to_rgba32f(buf),
+        DynamicImage::ImageRgba16(buf) => convert_to_rgba32f(buf),
+        DynamicImage::ImageRgb32F(buf) => convert_to_rgba32f(buf),
+        DynamicImage::ImageRgba32F(buf) => convert_to_rgba32f(buf),
+        _ => unreachable!(),
+    }
+}
+
+fn convert_to_rgba32f<P: Pixel>(buf: &ImageBuffer<P, Vec<P::Subpixel>>) -> Rgba32FImage
+where
+    P::Subpixel: Into<f32>,
+{
+    let max: f32 = <P::Subpixel as Primitive>::DEFAULT_MAX_VALUE.into();
+    let (width, height) = buf.dimensions();
+    let mut samples = take(width as usize * height as usize * 4);
+    for pixel in buf.pixels() {
+        samples.extend(pixel.to_rgba().0.map(|channel| channel.into() / max));
+    }
+    ImageBuffer::from_raw(width, height, samples).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{GrayAlphaImage, LumaA, Rgb, RgbImage};
+
+    use super::*;
+
+    #[test]
+    fn conversion_matches_image() {
+        let images = [
+            DynamicImage::ImageRgb8(RgbImage::from_fn(3, 2, |x, y| {
+                Rgb([x as u8 * 100, y as u8 * 50, 7])
+            })),
+            DynamicImage::ImageLumaA8(GrayAlphaImage::from_pixel(2, 2, LumaA([3, 200]))),
+            DynamicImage::ImageLuma16(ImageBuffer::from_pixel(1, 3, image::Luma([40000]))),
+        ];
+        for image in images {
+            assert_eq!(to_rgba32f(&image), image.to_rgba32f());
+        }
+    }
+
+    #[test]
+    fn buffers_are_reused() {
+        let image = rgba32f_from_pixel(10, 10, Rgba([0.5; 4]));
+        let pointer = image.as_ptr();
+        recycle_image(DynamicImage::ImageRgba32F(image));
+        // a smaller image fits into the same memory
+        let reused = rgba32f_from_fn(5, 5, |x, _| Rgba([x as f32, 0.0, 0.0, 1.0]));
+        assert_eq!(reused.as_ptr(), pointer);
+        assert_eq!(reused.get_pixel(4, 4), &Rgba([4.0, 0.0, 0.0, 1.0]));
+        // while a larger one needs a new allocation
+        let larger = rgba32f_from_pixel(20, 20, Rgba([0.0; 4]));
+        assert_ne!(larger.as_ptr(), pointer);
+        // the pool holds a limited number of buffers
+        for _ in 0..10 {
+            recycle(Vec::with_capacity(16));
+        }
+        POOL.with_borrow(|pool| assert_eq!(pool.len(), MAX_POOLED));
+        assert_eq!(pooled_bytes(), (MAX_POOLED * 16 * 4) as u64);
+        clear();
+        assert_eq!(pooled_bytes(), 0);
+    }
+}
@@ -0,0 +1,56 @@
+//! Pseudo-random numbers for operations such as `+noise`.
+//!
+//! Like in imagemagick, the sequence differs on every run unless `-seed` sets where it starts,
+//! e.g. to compare the output of two runs within a fuzz.
+
+use std::cell::Cell;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+thread_local! {
+    static STATE: Cell<u64> = Cell::new(seed_from_clock());
+}
+
+fn seed_from_clock() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_nanos() as u64)
+        .unwrap_or_default()
+}
+
+/// Implements `-seed`: the numbers that follow are the same on every run with the same seed
+pub fn set_seed(seed: u64) {
+    STATE.set(seed);
+}
+
+/// Implements `+seed`: the numbers that follow differ from run to run
+pub fn reset_seed() {
+    STATE.set(seed_from_clock());
+}
+
+/// A number in `[0, 1)`, from the SplitMix64 generator
+pub fn next_f64() -> f64 {
+    let state = STATE.get().wrapping_add(0x9E37_79B9_7F4A_7C15);
+    STATE.set(state);
+    let mut z = state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^= z >> 31;
+    // The top 53 bits are exactly representable
+    (z >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seed_repeats_the_sequence() {
+        set_seed(42);
+        let first: Vec<f64> = (0..8).map(|_| next_f64()).collect();
+        set_seed(42);
+        let second: Vec<f64> = (0..8).map(|_| next_f64()).collect();
+        assert_eq!(first, second);
+        assert!(first.iter().all(|&n| (0.0..1.0).contains(&n)));
+        assert!(first.windows(2).all(|pair| pair[0] != pair[1]));
+    }
+}
@@ -0,0 +1,32 @@
+//! Temporary files for spooling data that cannot be streamed,
+//! e.g. images read from stdin that the decoders need to seek in.
+
+use std::fs::File;
+use std::io::{Seek, SeekFrom};
+use std::path::Path;
+
+use crate::limits::Limits;
+
+/// Creates an anonymous temporary file that is deleted once closed.
+///
+/// It is created in `temporary_path` if specified, and in the system temporary directory otherwise.
+/// The system one is often a small tmpfs on servers, so `MAGICK_TEMPORARY_PATH` or
+/// `-define registry:temporary-path=...` can be used to point it somewhere roomier.
+pub fn temp_file(temporary_path: Option<&Path>) -> std::io::Result<File> {
+    match temporary_path {
+        Some(dir) => tempfile::tempfile_in(dir),
+        None => tempfile::tempfile(),
+    }
+}
+
+/// Copies all of stdin into a temporary file and rewinds it to the beginning.
+/// Fails if stdin holds more than `-limit disk` allows.
+pub fn spool_stdin(temporary_path: Option<&Path>, limits: &Limits) -> std::io::Result<File> {
+    let mut file = temp_file(temporary_path)?;
+    std::io::copy(
+        &mut std::io::stdin().lock(),
+        &mut limits.disk_bounded(&mut file),
+    )?;
+    file.seek(SeekFrom::Start(0))?;
+    Ok(file)
+}
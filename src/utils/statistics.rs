@@ -0,0 +1,308 @@
+//! Per-channel image statistics, computed in a single pass over the pixels.
+//!
+//! Shared by `identify -verbose`, the `%[mean]`-style format escapes
+//! and operations such as `-auto-level` that adjust the image based on its contents.
+
+use image::DynamicImage;
+
+/// A channel of a `DynamicImage`, in the order they are stored in memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    Gray,
+    Red,
+    Green,
+    Blue,
+    Alpha,
+}
+
+impl Channel {
+    /// Channels of the given image, in the order they are stored in memory
+    pub fn channels_of(image: &DynamicImage) -> &'static [Channel] {
+        use Channel::*;
+        let color = image.color();
+        match (color.has_color(), color.has_alpha()) {
+            (false, false) => &[Gray],
+            (false, true) => &[Gray, Alpha],
+            (true, false) => &[Red, Green, Blue],
+            (true, true) => &[Red, Green, Blue, Alpha],
+        }
+    }
+
+    /// Capitalized name as printed by `identify -verbose`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Channel::Gray => "Gray",
+            Channel::Red => "Red",
+            Channel::Green => "Green",
+            Channel::Blue => "Blue",
+            Channel::Alpha => "Alpha",
+        }
+    }
+}
+
+/// Statistics of a single channel. All values are normalized to the `[0, 1]` range.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ChannelStatistics {
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    pub standard_deviation: f64,
+    pub skewness: f64,
+    /// Excess kurtosis, i.e. 0 for a normal distribution
+    pub kurtosis: f64,
+    /// Shannon entropy normalized by the number of distinct values, same as in imagemagick
+    pub entropy: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImageStatistics {
+    pub channels: Vec<(Channel, ChannelStatistics)>,
+    /// Combined statistics of the color channels, excluding alpha
+    pub overall: ChannelStatistics,
+}
+
+impl ImageStatistics {
+    pub fn compute(image: &DynamicImage) -> Self {
+        let channels = Channel::channels_of(image);
+        let mut accumulators = vec![Accumulator::default(); channels.len()];
+        match image {
+            DynamicImage::ImageLuma8(buf) => accumulate(buf.as_raw(), &mut accumulators, unit_u8),
+            DynamicImage::ImageLumaA8(buf) => accumulate(buf.as_raw(), &mut accumulators, unit_u8),
+            DynamicImage::ImageRgb8(buf) => accumulate(buf.as_raw(), &mut accumulators, unit_u8),
+            DynamicImage::ImageRgba8(buf) => accumulate(buf.as_raw(), &mut accumulators, unit_u8),
+            DynamicImage::ImageLuma16(buf) => accumulate(buf.as_raw(), &mut accumulators, unit_u16),
+            DynamicImage::ImageLumaA16(buf) => {
+                accumulate(buf.as_raw(), &mut accumulators, unit_u16)
+            }
+            DynamicImage::ImageRgb16(buf) => accumulate(buf.as_raw(), &mut accumulators, unit_u16),
+            DynamicImage::ImageRgba16(buf) => accumulate(buf.as_raw(), &mut accumulators, unit_u16),
+            DynamicImage::ImageRgb32F(buf) => accumulate(buf.as_raw(), &mut accumulators, unit_f32),
+            DynamicImage::ImageRgba32F(buf) => {
+                accumulate(buf.as_raw(), &mut accumulators, unit_f32)
+            }
+            _ => unreachable!(),
+        }
+
+        let channels: Vec<(Channel, ChannelStatistics)> = channels
+            .iter()
+            .copied()
+            .zip(accumulators.iter().map(Accumulator::finish))
+            .collect();
+        let overall = overall_statistics(&channels);
+        Self { channels, overall }
+    }
+
+    /// Looks up the statistics of a single channel
+    pub fn channel(&self, channel: Channel) -> Option<&ChannelStatistics> {
+        self.channels
+            .iter()
+            .find(|(c, _)| *c == channel)
+            .map(|(_, stats)| stats)
+    }
+}
+
+/// Number of histogram bins used for entropy computation.
+/// Everything is quantized to 16 bits, which is lossless for 8-bit and 16-bit images.
+const HISTOGRAM_BINS: usize = 65536;
+
+#[derive(Debug, Clone)]
+struct Accumulator {
+    count: u64,
+    min: f64,
+    max: f64,
+    // Raw moments of the values relative to `shift`. Computing them in a single pass loses a bit of precision
+    // compared to a two-pass algorithm, but shifting by the first value avoids catastrophic cancellation
+    // for images with little variation, and everything is accumulated in f64.
+    shift: Option<f64>,
+    sum: f64,
+    sum2: f64,
+    sum3: f64,
+    sum4: f64,
+    histogram: Vec<u64>,
+}
+
+impl Default for Accumulator {
+    fn default() -> Self {
+        Self {
+            count: 0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+            shift: None,
+            sum: 0.0,
+            sum2: 0.0,
+            sum3: 0.0,
+            sum4: 0.0,
+            histogram: vec![0; HISTOGRAM_BINS],
+        }
+    }
+}
+
+impl Accumulator {
+    #[inline]
+    fn add(&mut self, value: f64) {
+        self.count += 1;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        let shifted = value - *self.shift.get_or_insert(value);
+        let shifted2 = shifted * shifted;
+        self.sum += shifted;
+        self.sum2 += shifted2;
+        self.sum3 += shifted2 * shifted;
+        self.sum4 += shifted2 * shifted2;
+        let bin = (value.clamp(0.0, 1.0) * (HISTOGRAM_BINS - 1) as f64).round() as usize;
+        self.histogram[bin] += 1;
+    }
+
+    fn finish(&self) -> ChannelStatistics {
+        if self.count == 0 {
+            return ChannelStatistics::default();
+        }
+        let n = self.count as f64;
+        // mean of the shifted values; the shift does not affect any of the central moments
+        let mean = self.sum / n;
+        let m2 = self.sum2 / n;
+        let m3 = self.sum3 / n;
+        let m4 = self.sum4 / n;
+        let variance = (m2 - mean * mean).max(0.0);
+        let central3 = m3 - 3.0 * mean * m2 + 2.0 * mean.powi(3);
+        let central4 = m4 - 4.0 * mean * m3 + 6.0 * mean * mean * m2 - 3.0 * mean.powi(4);
+        let (skewness, kurtosis) = if variance > 0.0 {
+            (
+                central3 / variance.powf(1.5),
+                central4 / (variance * variance) - 3.0,
+            )
+        } else {
+            (0.0, 0.0)
+        };
+        // imagemagick reports the sample standard deviation rather than the population one
+        let standard_deviation = if self.count > 1 {
+            (variance * n / (n - 1.0)).sqrt()
+        } else {
+            0.0
+        };
+
+        ChannelStatistics {
+            min: self.min,
+            max: self.max,
+            mean: mean + self.shift.unwrap_or(0.0),
+            standard_deviation,
+            skewness,
+            kurtosis,
+            entropy: self.entropy(),
+        }
+    }
+
+    fn entropy(&self) -> f64 {
+        let n = self.count as f64;
+        let used_bins = self.histogram.iter().filter(|c| **c != 0).count();
+        if used_bins <= 1 {
+            return 0.0;
+        }
+        let entropy: f64 = self
+            .histogram
+            .iter()
+            .filter(|c| **c != 0)
+            .map(|c| {
+                let p = *c as f64 / n;
+                -p * p.ln()
+            })
+            .sum();
+        entropy / (used_bins as f64).ln()
+    }
+}
+
+fn accumulate<T: Copy>(samples: &[T], accumulators: &mut [Accumulator], unit: fn(T) -> f64) {
+    for pixel in samples.chunks_exact(accumulators.len()) {
+        for (sample, acc) in pixel.iter().zip(accumulators.iter_mut()) {
+            acc.add(unit(*sample));
+        }
+    }
+}
+
+fn unit_u8(v: u8) -> f64 {
+    f64::from(v) / f64::from(u8::MAX)
+}
+
+fn unit_u16(v: u16) -> f64 {
+    f64::from(v) / f64::from(u16::MAX)
+}
+
+fn unit_f32(v: f32) -> f64 {
+    f64::from(v)
+}
+
+/// Combines the statistics of color channels the same way imagemagick does for its "Overall" section:
+/// extremes are taken across all channels, everything else is averaged.
+fn overall_statistics(channels: &[(Channel, ChannelStatistics)]) -> ChannelStatistics {
+    let color: Vec<&ChannelStatistics> = channels
+        .iter()
+        .filter(|(c, _)| *c != Channel::Alpha)
+        .map(|(_, stats)| stats)
+        .collect();
+    let count = color.len() as f64;
+    let average =
+        |f: fn(&ChannelStatistics) -> f64| color.iter().map(|s| f(s)).sum::<f64>() / count;
+    ChannelStatistics {
+        min: color.iter().map(|s| s.min).fold(f64::INFINITY, f64::min),
+        max: color
+            .iter()
+            .map(|s| s.max)
+            .fold(f64::NEG_INFINITY, f64::max),
+        mean: average(|s| s.mean),
+        standard_deviation: average(|s| s.standard_deviation),
+        skewness: average(|s| s.skewness),
+        kurtosis: average(|s| s.kurtosis),
+        entropy: average(|s| s.entropy),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{GrayImage, Luma, RgbaImage};
+
+    use super::*;
+
+    fn approx_eq(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn constant_image() {
+        let image = DynamicImage::ImageLuma8(GrayImage::from_pixel(4, 4, Luma([51])));
+        let stats = ImageStatistics::compute(&image);
+        let gray = stats.channel(Channel::Gray).unwrap();
+        assert!(approx_eq(gray.min, 0.2));
+        assert!(approx_eq(gray.max, 0.2));
+        assert!(approx_eq(gray.mean, 0.2));
+        assert!(approx_eq(gray.standard_deviation, 0.0));
+        assert!(approx_eq(gray.entropy, 0.0));
+    }
+
+    #[test]
+    fn two_values() {
+        // half black, half white
+        let image =
+            DynamicImage::ImageLuma8(GrayImage::from_fn(2, 2, |x, _| Luma([x as u8 * 255])));
+        let stats = ImageStatistics::compute(&image);
+        let gray = stats.channel(Channel::Gray).unwrap();
+        assert!(approx_eq(gray.min, 0.0));
+        assert!(approx_eq(gray.max, 1.0));
+        assert!(approx_eq(gray.mean, 0.5));
+        // sample standard deviation of [0, 0, 1, 1]
+        assert!(approx_eq(gray.standard_deviation, (1.0f64 / 3.0).sqrt()));
+        assert!(approx_eq(gray.skewness, 0.0));
+        assert!(approx_eq(gray.kurtosis, -2.0));
+        assert!(approx_eq(gray.entropy, 1.0));
+    }
+
+    #[test]
+    fn overall_excludes_alpha() {
+        let image =
+            DynamicImage::ImageRgba8(RgbaImage::from_pixel(2, 2, image::Rgba([0, 255, 255, 0])));
+        let stats = ImageStatistics::compute(&image);
+        assert_eq!(stats.channels.len(), 4);
+        assert!(approx_eq(stats.overall.mean, 2.0 / 3.0));
+        assert!(approx_eq(stats.overall.min, 0.0));
+        assert!(approx_eq(stats.overall.max, 1.0));
+    }
+}
@@ -0,0 +1,66 @@
+//! Measuring the time spent on an image, which imagemagick reports e.g. in `-identify` and `-bench`
+
+use std::time::{Duration, Instant};
+
+/// Measures the time since it was started
+#[derive(Debug, Clone, Copy)]
+pub struct Stopwatch {
+    start: Instant,
+}
+
+impl Stopwatch {
+    pub fn start() -> Self {
+        Self {
+            start: Instant::now(),
+        }
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    /// The time imagemagick reports as user time.
+    ///
+    /// The standard library cannot measure the CPU time of the process, and doing it with system calls
+    /// is not worth a dependency full of `unsafe`. Images are processed on a single thread,
+    /// so the wall-clock time is close to the CPU time and stands in for it.
+    pub fn cpu_time(&self) -> Duration {
+        self.elapsed()
+    }
+
+    /// Both times the way imagemagick prints them: `0.010u 0:00.013`
+    pub fn format(&self) -> String {
+        format_times(self.cpu_time(), self.elapsed())
+    }
+}
+
+/// Formats the CPU time in seconds and the wall-clock time in minutes, seconds and milliseconds,
+/// e.g. `0.010u 0:00.013`
+pub fn format_times(cpu_time: Duration, elapsed: Duration) -> String {
+    let millis = elapsed.as_millis();
+    format!(
+        "{:.3}u {}:{:02}.{:03}",
+        cpu_time.as_secs_f64(),
+        millis / 60_000,
+        millis / 1000 % 60,
+        millis % 1000
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formatting() {
+        let format =
+            |cpu, elapsed| format_times(Duration::from_millis(cpu), Duration::from_millis(elapsed));
+        assert_eq!(format(10, 13), "0.010u 0:00.013");
+        assert_eq!(format(700, 61_250), "0.700u 1:01.250");
+        // milliseconds are truncated, so a second never reads as 60
+        assert_eq!(
+            format_times(Duration::ZERO, Duration::from_micros(59_999_999)),
+            "0.000u 0:59.999"
+        );
+    }
+}